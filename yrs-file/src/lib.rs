@@ -0,0 +1,960 @@
+//! **yrs-file** is a persistence layer allowing to store [Yrs](https://docs.rs/yrs/latest/yrs/index.html)
+//! documents and providing convenient utility functions to work with them, using a single
+//! append-only log file for persistent backend. It has no dependencies beyond the Rust standard
+//! library, which makes it a good fit for CLI tools, tests and embedded use cases, where a full
+//! database engine is not worth its weight.
+//!
+//! # Example
+//!
+//! ```rust
+//! use yrs::{Doc, GetString, Transact};
+//! use yrs_file::FileDocStore;
+//! use yrs_kvstore::DocOps;
+//!
+//! let mut store = FileDocStore::open("my-doc-store.log").unwrap();
+//!
+//! let doc = Doc::new();
+//! let text = doc.get_or_insert_text("text");
+//! text.push(&mut doc.transact_mut(), "hello");
+//!
+//! let db = store.transaction();
+//! db.insert_doc("my-doc-name", &doc.transact()).unwrap();
+//! db.commit().unwrap();
+//!
+//! let db = store.transaction();
+//! let doc = Doc::new();
+//! let text = doc.get_or_insert_text("text");
+//! db.load_doc("my-doc-name", &mut doc.transact_mut()).unwrap();
+//! assert_eq!(text.get_string(&doc.transact()), "hello");
+//! ```
+//!
+//! # Design
+//!
+//! All committed writes are appended to a log file (see [log] for its layout), while an
+//! in-memory index maps every live key onto the position of its value within that file. The
+//! index is rebuilt by replaying the log when the store is opened, so memory usage grows with
+//! the number and size of keys, but not with the size of values, which are read from the file on
+//! demand.
+//!
+//! Store has a single writer: transactions borrow [FileDocStore] mutably, so only one of them
+//! can exist at the time. Changes made within a transaction are buffered in memory and appended
+//! to the file as a single record on [FileStore::commit]. Dropping a transaction without
+//! committing it discards its changes.
+//!
+//! # Crash recovery
+//!
+//! Every record is protected with a checksum. If the process crashes in the middle of a commit,
+//! the partially written record is detected when the file is opened again and the file is
+//! truncated back to the end of the last complete record, so that the store recovers to the
+//! state of the last successful commit.
+//!
+//! # Compaction
+//!
+//! Overwritten and removed entries stay in the log until it's compacted. Compaction rewrites
+//! live entries into a new file, which then atomically replaces the old one. It's run
+//! automatically after commits, once dead entries take more than a configured share of the file
+//! (see [FileOptions]), and it can be run explicitly with [FileDocStore::compact].
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use yrs_kvstore::capabilities::Capabilities;
+use yrs_kvstore::{DocOps, KVEntry, KVStore, ReadIsolation, WriteDurability};
+
+pub mod log;
+
+pub use yrs_kvstore as store;
+pub use yrs_kvstore::prelude;
+
+/// Options used by [FileDocStore::open_with].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileOptions {
+    /// Durability of commits. With [WriteDurability::Durable] every commit is synced to disk
+    /// before returning. Both [WriteDurability::Relaxed] and [WriteDurability::Volatile] leave
+    /// flushing committed records to the operating system. Default: [WriteDurability::Durable].
+    pub durability: WriteDurability,
+    /// Share of the file taken by dead entries, above which log is compacted automatically after
+    /// a commit. Default: `0.5`.
+    pub compaction_ratio: f64,
+    /// Minimum number of bytes taken by dead entries before log is compacted automatically, so
+    /// that small files are not rewritten all the time. Default: 1MiB.
+    pub compaction_min_bytes: u64,
+}
+
+impl Default for FileOptions {
+    fn default() -> Self {
+        FileOptions {
+            durability: WriteDurability::Durable,
+            compaction_ratio: 0.5,
+            compaction_min_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// Position of a value within the log file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ValuePos {
+    offset: u64,
+    len: u32,
+}
+
+/// Key-value store persisted in a single append-only log file.
+///
+/// Use [FileDocStore::transaction] to read and modify its contents through [DocOps] methods.
+#[derive(Debug)]
+pub struct FileDocStore {
+    path: PathBuf,
+    file: File,
+    index: BTreeMap<Vec<u8>, ValuePos>,
+    /// Length of the valid part of the log file.
+    len: u64,
+    /// Number of bytes taken by entries which have been overwritten or removed.
+    dead_bytes: u64,
+    options: FileOptions,
+}
+
+impl FileDocStore {
+    /// Maximum size of a record written by [FileDocStore::compact].
+    const COMPACTION_RECORD_LEN: usize = 1024 * 1024;
+
+    /// Opens a store persisted in a file under a given `path` using default [FileOptions],
+    /// creating that file if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open_with(path, FileOptions::default())
+    }
+
+    /// Opens a store persisted in a file under a given `path`, creating that file if it doesn't
+    /// exist.
+    ///
+    /// The index of the store is rebuilt by replaying all records of the file. Replay stops at
+    /// the first incomplete or corrupted record, which is what a crash in the middle of a commit
+    /// leaves behind, and the file is truncated to the end of the last complete record.
+    pub fn open_with<P: AsRef<Path>>(path: P, options: FileOptions) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let mut store = FileDocStore {
+            path,
+            file,
+            index: BTreeMap::new(),
+            len: 0,
+            dead_bytes: 0,
+            options,
+        };
+        store.replay()?;
+        Ok(store)
+    }
+
+    /// Rebuilds the index from the log file, truncating it after the last complete record.
+    fn replay(&mut self) -> io::Result<()> {
+        let file_len = self.file.metadata()?.len();
+        if file_len < log::HEADER_LEN {
+            // new file or a crash while it was being created
+            self.file.set_len(0)?;
+            self.file.seek(SeekFrom::Start(0))?;
+            self.file.write_all(&log::header())?;
+            self.file.sync_all()?;
+            self.len = log::HEADER_LEN;
+            return Ok(());
+        }
+        let mut reader = io::BufReader::new(self.file.try_clone()?);
+        reader.seek(SeekFrom::Start(0))?;
+        let mut header = [0; log::HEADER_LEN as usize];
+        reader.read_exact(&mut header)?;
+        log::check_header(&header)?;
+
+        let mut len = log::HEADER_LEN;
+        let mut record_header = [0; log::RECORD_HEADER_LEN];
+        let mut payload = Vec::new();
+        loop {
+            if read_full(&mut reader, &mut record_header)? < log::RECORD_HEADER_LEN {
+                break;
+            }
+            let payload_len = u32::from_be_bytes(record_header[..4].try_into().unwrap()) as u64;
+            let crc = u32::from_be_bytes(record_header[4..].try_into().unwrap());
+            let payload_start = len + log::RECORD_HEADER_LEN as u64;
+            if payload_len > file_len - payload_start {
+                break; // incomplete record
+            }
+            payload.resize(payload_len as usize, 0);
+            reader.read_exact(&mut payload)?;
+            if yrs_kvstore::config::crc32(&payload) != crc {
+                break;
+            }
+            let ops = match log::decode_ops(&payload) {
+                Some(ops) => ops,
+                None => break,
+            };
+            for op in ops {
+                match op {
+                    log::Op::Put(key, pos, value_len) => {
+                        let value = ValuePos {
+                            offset: payload_start + pos as u64,
+                            len: value_len,
+                        };
+                        self.index_put(key.to_vec(), value);
+                    }
+                    log::Op::Remove(key) => self.index_remove(key),
+                }
+            }
+            len = payload_start + payload_len;
+        }
+        drop(reader);
+        if len < file_len {
+            self.file.set_len(len)?;
+            self.file.sync_all()?;
+        }
+        self.len = len;
+        Ok(())
+    }
+
+    /// Inserts a `value` position under a given `key`, accounting for the value it replaces.
+    fn index_put(&mut self, key: Vec<u8>, value: ValuePos) {
+        let key_len = key.len();
+        if let Some(prev) = self.index.insert(key, value) {
+            self.dead_bytes += log::put_len(key_len, prev.len as usize);
+        }
+    }
+
+    /// Removes a given `key` from the index, accounting for both the removed value and the
+    /// remove operation itself.
+    fn index_remove(&mut self, key: &[u8]) {
+        self.dead_bytes += log::remove_len(key.len());
+        if let Some(prev) = self.index.remove(key) {
+            self.dead_bytes += log::put_len(key.len(), prev.len as usize);
+        }
+    }
+
+    /// Returns a path to the log file of current store.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns options current store was opened with.
+    pub fn options(&self) -> &FileOptions {
+        &self.options
+    }
+
+    /// Returns the length of the log file in bytes.
+    pub fn file_len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns the number of bytes of the log file taken by entries which have been overwritten
+    /// or removed and which will be reclaimed by the next compaction.
+    pub fn dead_bytes(&self) -> u64 {
+        self.dead_bytes
+    }
+
+    /// Returns the number of live key-value entries.
+    pub fn entry_count(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Begins a new transaction. Changes made within it are persisted only once
+    /// [FileStore::commit] is called.
+    pub fn transaction(&mut self) -> FileStore<'_> {
+        FileStore {
+            store: self,
+            writes: Default::default(),
+        }
+    }
+
+    /// Reads a value stored at a given position of the log file.
+    fn read_value(&self, pos: ValuePos) -> io::Result<Vec<u8>> {
+        let mut file = &self.file;
+        let mut value = vec![0; pos.len as usize];
+        file.seek(SeekFrom::Start(pos.offset))?;
+        file.read_exact(&mut value)?;
+        Ok(value)
+    }
+
+    /// Appends a complete `record` to the log file. If the write fails, the file is truncated
+    /// back to its previous length.
+    fn append(&mut self, record: &[u8]) -> io::Result<()> {
+        let res = self.write_record(record);
+        if res.is_err() {
+            let _ = self.file.set_len(self.len);
+        }
+        res
+    }
+
+    fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(record)?;
+        if self.options.durability == WriteDurability::Durable {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Checks if dead entries take enough of the log file to compact it.
+    fn needs_compaction(&self) -> bool {
+        self.dead_bytes >= self.options.compaction_min_bytes
+            && self.dead_bytes as f64 >= self.len as f64 * self.options.compaction_ratio
+    }
+
+    /// Rewrites all live entries into a new log file, which then replaces the current one.
+    ///
+    /// New file is written next to the current one (with `.compact` extension appended to its
+    /// name) and synced before it's renamed over the current file, so that a crash at any point
+    /// leaves either the old or the new file in place, both of which hold the same entries.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".compact");
+        let tmp_path = PathBuf::from(tmp_path);
+        match self.write_compacted(&tmp_path) {
+            Ok((file, index, len)) => {
+                self.file = file;
+                self.index = index;
+                self.len = len;
+                self.dead_bytes = 0;
+                Ok(())
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&tmp_path);
+                Err(e)
+            }
+        }
+    }
+
+    fn write_compacted(
+        &self,
+        tmp_path: &Path,
+    ) -> io::Result<(File, BTreeMap<Vec<u8>, ValuePos>, u64)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(tmp_path)?;
+        let mut out = io::BufWriter::new(&mut file);
+        out.write_all(&log::header())?;
+        let mut len = log::HEADER_LEN;
+        let mut index = BTreeMap::new();
+        let mut record = log::RecordWriter::new();
+        let mut positions = Vec::new();
+        for (key, pos) in self.index.iter() {
+            let value = self.read_value(*pos)?;
+            let value_pos = record.put(key, &value);
+            positions.push((key.clone(), value_pos, pos.len));
+            if record.len() >= Self::COMPACTION_RECORD_LEN {
+                let full = std::mem::replace(&mut record, log::RecordWriter::new());
+                len = flush_compacted(&mut out, full, len, &mut positions, &mut index)?;
+            }
+        }
+        if !record.is_empty() {
+            len = flush_compacted(&mut out, record, len, &mut positions, &mut index)?;
+        }
+        out.flush()?;
+        drop(out);
+        file.sync_all()?;
+        std::fs::rename(tmp_path, &self.path)?;
+        sync_parent_dir(&self.path)?;
+        Ok((file, index, len))
+    }
+}
+
+/// Writes a `record` produced by compaction at a given offset `len` of a new log file and
+/// registers positions of its values in the `index`. Returns the new length of the file.
+fn flush_compacted<W: Write>(
+    out: &mut W,
+    record: log::RecordWriter,
+    len: u64,
+    positions: &mut Vec<(Vec<u8>, usize, u32)>,
+    index: &mut BTreeMap<Vec<u8>, ValuePos>,
+) -> io::Result<u64> {
+    let record = record.finish();
+    out.write_all(&record)?;
+    for (key, pos, value_len) in positions.drain(..) {
+        let offset = len + pos as u64;
+        index.insert(
+            key,
+            ValuePos {
+                offset,
+                len: value_len,
+            },
+        );
+    }
+    Ok(len + record.len() as u64)
+}
+
+/// Reads from `reader` until `buf` is full or the end of the stream is reached. Returns the
+/// number of bytes read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Makes sure that renaming a file in a directory containing a given `path` is persisted.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+/// Directories cannot be opened as files on this platform, renames are persisted by the file
+/// system on its own.
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Transaction over [FileDocStore], used to read and modify its contents through [DocOps]
+/// methods. Reads observe changes made earlier within the same transaction.
+///
+/// Changes are buffered in memory until [FileStore::commit] appends them to the log file.
+/// Dropping a transaction without committing it discards all of its changes.
+pub struct FileStore<'a> {
+    store: &'a mut FileDocStore,
+    /// Changes made within current transaction: `None` marks removed keys.
+    writes: std::cell::RefCell<BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+}
+
+impl<'a> FileStore<'a> {
+    /// Appends all changes made within current transaction to the log file as a single record.
+    ///
+    /// Once dead entries take enough of the log file (see [FileOptions]), it's compacted
+    /// afterwards. Compaction errors are not returned, since committed changes have already been
+    /// persisted at that point - compaction is simply retried after the next commit. Use
+    /// [FileDocStore::compact] to compact the log file explicitly.
+    pub fn commit(self) -> io::Result<()> {
+        let store = self.store;
+        let writes = self.writes.into_inner();
+        let mut record = log::RecordWriter::new();
+        let mut puts = Vec::new();
+        for (key, value) in writes.iter() {
+            match value {
+                Some(value) => puts.push((key, record.put(key, value), value.len() as u32)),
+                None if store.index.contains_key(key) => record.remove(key),
+                None => { /* key has been inserted and removed within this transaction */ }
+            }
+        }
+        if record.is_empty() {
+            return Ok(());
+        }
+        let record = record.finish();
+        store.append(&record)?;
+        let record_start = store.len;
+        store.len += record.len() as u64;
+        for (key, pos, len) in puts {
+            let value = ValuePos {
+                offset: record_start + pos as u64,
+                len,
+            };
+            store.index_put(key.clone(), value);
+        }
+        for (key, value) in writes.iter() {
+            if value.is_none() && store.index.contains_key(key) {
+                store.index_remove(key);
+            }
+        }
+        if store.needs_compaction() {
+            let _ = store.compact();
+        }
+        Ok(())
+    }
+
+    /// Returns a value under a given `key` as seen by current transaction.
+    fn lookup(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        if let Some(value) = self.writes.borrow().get(key) {
+            return Ok(value.clone());
+        }
+        match self.store.index.get(key) {
+            Some(pos) => Ok(Some(self.store.read_value(*pos)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns all keys within `from`..=`to` range visible to current transaction.
+    fn keys_in_range(&self, from: &[u8], to: &[u8]) -> Vec<Vec<u8>> {
+        if from > to {
+            return Vec::new();
+        }
+        let range = (Bound::Included(from), Bound::Included(to));
+        let writes = self.writes.borrow();
+        let mut keys: BTreeMap<&[u8], bool> = self
+            .store
+            .index
+            .range::<[u8], _>(range)
+            .map(|(key, _)| (key.as_slice(), true))
+            .collect();
+        for (key, value) in writes.range::<[u8], _>(range) {
+            keys.insert(key.as_slice(), value.is_some());
+        }
+        keys.into_iter()
+            .filter(|(_, live)| *live)
+            .map(|(key, _)| key.to_vec())
+            .collect()
+    }
+}
+
+impl<'a> DocOps<'a> for FileStore<'a> {}
+
+impl<'a> KVStore<'a> for FileStore<'a> {
+    type Error = io::Error;
+    type Cursor = std::vec::IntoIter<FileEntry>;
+    type Entry = FileEntry;
+    type Return = Vec<u8>;
+
+    // index and pending writes are ordered maps, walked backwards from a given key
+    const CAPABILITIES: Capabilities = Capabilities::BOUNDED_PEEK_BACK;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+        self.lookup(key)
+    }
+
+    fn upsert(&self, key: &[u8], value: &[u8]) -> Result<(), Self::Error> {
+        if value.len() > u32::MAX as usize || key.len() > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "key or value is too large",
+            ));
+        }
+        self.writes
+            .borrow_mut()
+            .insert(key.to_vec(), Some(value.to_vec()));
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+        self.writes.borrow_mut().insert(key.to_vec(), None);
+        Ok(())
+    }
+
+    fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<u32, Self::Error> {
+        let keys = self.keys_in_range(from, to);
+        let removed = keys.len() as u32;
+        let mut writes = self.writes.borrow_mut();
+        for key in keys {
+            writes.insert(key, None);
+        }
+        Ok(removed)
+    }
+
+    fn iter_range(&self, from: &[u8], to: &[u8]) -> Result<Self::Cursor, Self::Error> {
+        let keys = self.keys_in_range(from, to);
+        let mut entries = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(value) = self.lookup(&key)? {
+                entries.push(FileEntry { key, value });
+            }
+        }
+        Ok(entries.into_iter())
+    }
+
+    fn peek_back(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+        let writes = self.writes.borrow();
+        let mut upper = Bound::Included(key);
+        loop {
+            let range = (Bound::Unbounded, upper);
+            let stored = self.store.index.range::<[u8], _>(range).next_back();
+            let written = writes.range::<[u8], _>(range).next_back();
+            match (stored, written) {
+                (Some((stored_key, _)), Some((written_key, _))) if stored_key > written_key => {
+                    break;
+                }
+                (_, Some((written_key, Some(value)))) => {
+                    return Ok(Some(FileEntry {
+                        key: written_key.clone(),
+                        value: value.clone(),
+                    }));
+                }
+                // removed within current transaction, keep looking before it
+                (_, Some((written_key, None))) => upper = Bound::Excluded(written_key.as_slice()),
+                (_, None) => break,
+            }
+        }
+        match self
+            .store
+            .index
+            .range::<[u8], _>((Bound::Unbounded, upper))
+            .next_back()
+        {
+            Some((key, pos)) => Ok(Some(FileEntry {
+                key: key.clone(),
+                value: self.store.read_value(*pos)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    fn seek(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+        let writes = self.writes.borrow();
+        let mut lower = Bound::Included(key);
+        loop {
+            let range = (lower, Bound::Unbounded);
+            let stored = self.store.index.range::<[u8], _>(range).next();
+            let written = writes.range::<[u8], _>(range).next();
+            match (stored, written) {
+                (Some((stored_key, _)), Some((written_key, _))) if stored_key < written_key => {
+                    break;
+                }
+                (_, Some((written_key, Some(value)))) => {
+                    return Ok(Some(FileEntry {
+                        key: written_key.clone(),
+                        value: value.clone(),
+                    }));
+                }
+                // removed within current transaction, keep looking after it
+                (_, Some((written_key, None))) => lower = Bound::Excluded(written_key.as_slice()),
+                (_, None) => break,
+            }
+        }
+        match self
+            .store
+            .index
+            .range::<[u8], _>((lower, Bound::Unbounded))
+            .next()
+        {
+            Some((key, pos)) => Ok(Some(FileEntry {
+                key: key.clone(),
+                value: self.store.read_value(*pos)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    fn read_isolation(&self) -> ReadIsolation {
+        // store is borrowed exclusively, so no other transaction can commit in the meantime
+        ReadIsolation::Snapshot
+    }
+}
+
+/// Key-value entry returned by [FileStore] cursors.
+pub struct FileEntry {
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+impl From<FileEntry> for (Vec<u8>, Vec<u8>) {
+    fn from(entry: FileEntry) -> Self {
+        (entry.key, entry.value)
+    }
+}
+
+impl KVEntry for FileEntry {
+    fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{FileDocStore, FileOptions};
+    use std::fs::OpenOptions;
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::path::Path;
+    use tempdir::TempDir;
+    use yrs::{Doc, GetString, Transact};
+    use yrs_kvstore::conformance;
+    use yrs_kvstore::{DocOps, KVEntry, KVStore};
+
+    fn get(store: &mut FileDocStore, key: &[u8]) -> Option<Vec<u8>> {
+        store.transaction().get(key).unwrap()
+    }
+
+    fn truncate(path: &Path, len: u64) {
+        let file = OpenOptions::new().write(true).open(path).unwrap();
+        file.set_len(len).unwrap();
+    }
+
+    #[test]
+    fn conformance() {
+        let dir = TempDir::new("yrs-file-conformance").unwrap();
+        let path = dir.path().join("store.log");
+        let mut store = FileDocStore::open(&path).unwrap();
+        {
+            let db = store.transaction();
+            conformance::run_all(&db);
+            db.commit().unwrap();
+        }
+        // the same scenarios run against committed state
+        {
+            let db = store.transaction();
+            conformance::run_all(&db);
+            db.commit().unwrap();
+        }
+        drop(store);
+
+        let mut store = FileDocStore::open(&path).unwrap();
+        let db = store.transaction();
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let name = format!("{}insert", conformance::DOC_PREFIX);
+        assert!(db.load_doc(&name, &mut doc.transact_mut()).unwrap());
+        assert_eq!(text.get_string(&doc.transact()), "hello world");
+    }
+
+    #[test]
+    fn reopen_persists_commits() {
+        let dir = TempDir::new("yrs-file-reopen").unwrap();
+        let path = dir.path().join("store.log");
+        let mut store = FileDocStore::open(&path).unwrap();
+        {
+            let db = store.transaction();
+            db.upsert(b"a", b"1").unwrap();
+            db.upsert(b"b", b"2").unwrap();
+            db.upsert(b"c", b"3").unwrap();
+            db.commit().unwrap();
+        }
+        {
+            let db = store.transaction();
+            db.upsert(b"a", b"11").unwrap();
+            db.remove(b"b").unwrap();
+            db.commit().unwrap();
+        }
+        let len = store.file_len();
+        drop(store);
+
+        let mut store = FileDocStore::open(&path).unwrap();
+        assert_eq!(store.file_len(), len);
+        assert_eq!(store.entry_count(), 2);
+        assert_eq!(get(&mut store, b"a"), Some(b"11".to_vec()));
+        assert_eq!(get(&mut store, b"b"), None);
+        assert_eq!(get(&mut store, b"c"), Some(b"3".to_vec()));
+        let db = store.transaction();
+        let entries: Vec<_> = db
+            .iter_range(b"a", b"z")
+            .unwrap()
+            .map(|e| (e.key().to_vec(), e.value().to_vec()))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (b"a".to_vec(), b"11".to_vec()),
+                (b"c".to_vec(), b"3".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn drop_without_commit_discards_changes() {
+        let dir = TempDir::new("yrs-file-rollback").unwrap();
+        let path = dir.path().join("store.log");
+        let mut store = FileDocStore::open(&path).unwrap();
+        {
+            let db = store.transaction();
+            db.upsert(b"a", b"1").unwrap();
+            db.commit().unwrap();
+        }
+        let len = store.file_len();
+        {
+            let db = store.transaction();
+            db.upsert(b"a", b"2").unwrap();
+            db.upsert(b"b", b"2").unwrap();
+            assert_eq!(db.get(b"a").unwrap(), Some(b"2".to_vec()));
+        }
+        assert_eq!(store.file_len(), len);
+        assert_eq!(get(&mut store, b"a"), Some(b"1".to_vec()));
+        assert_eq!(get(&mut store, b"b"), None);
+    }
+
+    #[test]
+    fn peek_back_skips_removed_entries() {
+        let dir = TempDir::new("yrs-file-peek-back").unwrap();
+        let mut store = FileDocStore::open(dir.path().join("store.log")).unwrap();
+        {
+            let db = store.transaction();
+            for key in [1u8, 3, 5].iter() {
+                db.upsert(&[*key], &[*key]).unwrap();
+            }
+            db.commit().unwrap();
+        }
+        let db = store.transaction();
+        db.upsert(&[4], &[4]).unwrap();
+        db.remove(&[5]).unwrap();
+        assert_eq!(db.peek_back(&[6]).unwrap().unwrap().key(), &[4]);
+        db.remove(&[4]).unwrap();
+        db.remove(&[3]).unwrap();
+        assert_eq!(db.peek_back(&[6]).unwrap().unwrap().key(), &[1]);
+        assert_eq!(db.remove_range(&[0], &[9]).unwrap(), 1);
+        assert!(db.peek_back(&[6]).unwrap().is_none());
+    }
+
+    #[test]
+    fn recovers_from_truncated_record() {
+        let dir = TempDir::new("yrs-file-truncated").unwrap();
+        let path = dir.path().join("store.log");
+        let mut store = FileDocStore::open(&path).unwrap();
+        {
+            let db = store.transaction();
+            db.upsert(b"a", b"1").unwrap();
+            db.commit().unwrap();
+        }
+        let committed_len = store.file_len();
+        {
+            let db = store.transaction();
+            db.upsert(b"a", b"2").unwrap();
+            db.upsert(b"b", &[7; 100]).unwrap();
+            db.commit().unwrap();
+        }
+        let full_len = store.file_len();
+        drop(store);
+        let mut contents = Vec::new();
+        std::fs::File::open(&path)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+
+        // cut the last record within its header, its payload and right before its end
+        for cut in [1, 4, 8, 9, 50, full_len - committed_len - 1].iter() {
+            std::fs::write(&path, &contents).unwrap();
+            truncate(&path, committed_len + cut);
+
+            let mut store = FileDocStore::open(&path).unwrap();
+            assert_eq!(store.file_len(), committed_len, "cut at {}", cut);
+            assert_eq!(std::fs::metadata(&path).unwrap().len(), committed_len);
+            assert_eq!(get(&mut store, b"a"), Some(b"1".to_vec()));
+            assert_eq!(get(&mut store, b"b"), None);
+
+            // store remains writable after recovery
+            {
+                let db = store.transaction();
+                db.upsert(b"c", b"3").unwrap();
+                db.commit().unwrap();
+            }
+            drop(store);
+            let mut store = FileDocStore::open(&path).unwrap();
+            assert_eq!(get(&mut store, b"a"), Some(b"1".to_vec()));
+            assert_eq!(get(&mut store, b"c"), Some(b"3".to_vec()));
+        }
+    }
+
+    #[test]
+    fn recovers_from_corrupted_record() {
+        let dir = TempDir::new("yrs-file-corrupted").unwrap();
+        let path = dir.path().join("store.log");
+        let mut store = FileDocStore::open(&path).unwrap();
+        {
+            let db = store.transaction();
+            db.upsert(b"a", b"1").unwrap();
+            db.commit().unwrap();
+        }
+        let committed_len = store.file_len();
+        {
+            let db = store.transaction();
+            db.upsert(b"b", b"2").unwrap();
+            db.commit().unwrap();
+        }
+        drop(store);
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let pos = SeekFrom::End(-1);
+        let mut last = [0u8];
+        file.seek(pos).unwrap();
+        file.read_exact(&mut last).unwrap();
+        file.seek(pos).unwrap();
+        file.write_all(&[!last[0]]).unwrap();
+        drop(file);
+
+        let mut store = FileDocStore::open(&path).unwrap();
+        assert_eq!(store.file_len(), committed_len);
+        assert_eq!(get(&mut store, b"a"), Some(b"1".to_vec()));
+        assert_eq!(get(&mut store, b"b"), None);
+    }
+
+    #[test]
+    fn rejects_foreign_files() {
+        let dir = TempDir::new("yrs-file-foreign").unwrap();
+        let path = dir.path().join("store.log");
+        std::fs::write(&path, b"definitely not a log file").unwrap();
+        let err = FileDocStore::open(&path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn compaction_preserves_contents() {
+        let dir = TempDir::new("yrs-file-compaction").unwrap();
+        let path = dir.path().join("store.log");
+        let mut store = FileDocStore::open(&path).unwrap();
+        for i in 0..10u8 {
+            let db = store.transaction();
+            db.upsert(b"a", &[i; 64]).unwrap();
+            db.upsert(&[b'k', i], &[i]).unwrap();
+            if i % 2 == 1 {
+                db.remove(&[b'k', i - 1]).unwrap();
+            }
+            db.commit().unwrap();
+        }
+        let before = store.file_len();
+        assert!(store.dead_bytes() > 0);
+        store.compact().unwrap();
+        assert_eq!(store.dead_bytes(), 0);
+        assert!(store.file_len() < before);
+        assert_eq!(get(&mut store, b"a"), Some(vec![9; 64]));
+        assert!(!dir.path().join("store.log.compact").exists());
+
+        let len = store.file_len();
+        drop(store);
+        let mut store = FileDocStore::open(&path).unwrap();
+        assert_eq!(store.file_len(), len);
+        assert_eq!(store.dead_bytes(), 0);
+        assert_eq!(get(&mut store, b"a"), Some(vec![9; 64]));
+        let db = store.transaction();
+        let keys: Vec<_> = db
+            .iter_range(b"k", b"l")
+            .unwrap()
+            .map(|e| e.key().to_vec())
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                vec![b'k', 1],
+                vec![b'k', 3],
+                vec![b'k', 5],
+                vec![b'k', 7],
+                vec![b'k', 9]
+            ]
+        );
+    }
+
+    #[test]
+    fn compaction_runs_automatically() {
+        let dir = TempDir::new("yrs-file-auto-compaction").unwrap();
+        let path = dir.path().join("store.log");
+        let options = FileOptions {
+            compaction_ratio: 0.5,
+            compaction_min_bytes: 256,
+            ..FileOptions::default()
+        };
+        let mut store = FileDocStore::open_with(&path, options).unwrap();
+        let mut max_len = 0;
+        for i in 0..100u8 {
+            let db = store.transaction();
+            db.upsert(b"a", &[i; 64]).unwrap();
+            db.commit().unwrap();
+            max_len = max_len.max(store.file_len());
+        }
+        // a single live entry never lets the file grow much above the compaction threshold
+        assert!(max_len < 1024, "file grew to {} bytes", max_len);
+        drop(store);
+        let mut store = FileDocStore::open_with(&path, options).unwrap();
+        assert_eq!(get(&mut store, b"a"), Some(vec![99; 64]));
+    }
+}
@@ -0,0 +1,162 @@
+//! Layout of the log file used by [FileDocStore](crate::FileDocStore).
+//!
+//! File starts with a header: `{MAGIC:4}{version:4}`, followed by a sequence of records, one per
+//! committed transaction:
+//!
+//! ```nocompile
+//! {payload_len:4}{crc32(payload):4}{payload}
+//! ```
+//!
+//! Payload is a sequence of operations:
+//!
+//! ```nocompile
+//! put:    00{key_len:4}{key}{value_len:4}{value}
+//! remove: 01{key_len:4}{key}
+//! ```
+//!
+//! All integers are big-endian. Records are applied atomically: a record which has not been
+//! fully written (i.e. because of a crash) doesn't pass its checksum and is discarded together
+//! with everything that follows it.
+
+use std::convert::TryInto;
+use std::io;
+
+/// Magic bytes at the beginning of every log file.
+pub(crate) const MAGIC: [u8; 4] = *b"YRSF";
+
+/// Version of the log file layout.
+pub(crate) const VERSION: u32 = 1;
+
+/// Length of the file header.
+pub(crate) const HEADER_LEN: u64 = 8;
+
+/// Length of the header preceding every record payload.
+pub(crate) const RECORD_HEADER_LEN: usize = 8;
+
+pub(crate) const OP_PUT: u8 = 0;
+pub(crate) const OP_REMOVE: u8 = 1;
+
+/// Returns the file header.
+pub(crate) fn header() -> [u8; HEADER_LEN as usize] {
+    let mut header = [0; HEADER_LEN as usize];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4..].copy_from_slice(&VERSION.to_be_bytes());
+    header
+}
+
+/// Checks the file header, returning an error if it doesn't belong to a supported log file.
+pub(crate) fn check_header(header: &[u8]) -> io::Result<()> {
+    if header[..4] != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a yrs-file log file",
+        ));
+    }
+    let version = u32::from_be_bytes(header[4..8].try_into().unwrap());
+    if version != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported log file version: {}", version),
+        ));
+    }
+    Ok(())
+}
+
+/// Payload of a single record, built from operations of a committed transaction.
+pub(crate) struct RecordWriter {
+    buf: Vec<u8>,
+}
+
+impl RecordWriter {
+    pub fn new() -> Self {
+        RecordWriter {
+            buf: vec![0; RECORD_HEADER_LEN],
+        }
+    }
+
+    /// Returns `true` if no operations have been written yet.
+    pub fn is_empty(&self) -> bool {
+        self.buf.len() == RECORD_HEADER_LEN
+    }
+
+    /// Returns the length of the record written so far, including its header.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Writes a put operation. Returns the position of the `value` within the record.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> usize {
+        self.buf.push(OP_PUT);
+        self.write_buf(key);
+        self.write_buf(value);
+        self.buf.len() - value.len()
+    }
+
+    /// Writes a remove operation.
+    pub fn remove(&mut self, key: &[u8]) {
+        self.buf.push(OP_REMOVE);
+        self.write_buf(key);
+    }
+
+    fn write_buf(&mut self, data: &[u8]) {
+        self.buf
+            .extend_from_slice(&(data.len() as u32).to_be_bytes());
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Fills the record header and returns the complete record.
+    pub fn finish(mut self) -> Vec<u8> {
+        let payload_len = (self.buf.len() - RECORD_HEADER_LEN) as u32;
+        let crc = yrs_kvstore::config::crc32(&self.buf[RECORD_HEADER_LEN..]);
+        self.buf[..4].copy_from_slice(&payload_len.to_be_bytes());
+        self.buf[4..RECORD_HEADER_LEN].copy_from_slice(&crc.to_be_bytes());
+        self.buf
+    }
+}
+
+/// Operation decoded from a record payload.
+pub(crate) enum Op<'a> {
+    /// Put of a value, together with its position within the payload.
+    Put(&'a [u8], usize, u32),
+    Remove(&'a [u8]),
+}
+
+/// Decodes all operations of a record `payload`. Returns `None` if payload is malformed.
+pub(crate) fn decode_ops(payload: &[u8]) -> Option<Vec<Op<'_>>> {
+    let mut ops = Vec::new();
+    let mut pos = 0;
+    while pos < payload.len() {
+        let op = payload[pos];
+        pos += 1;
+        let key = read_buf(payload, &mut pos)?;
+        match op {
+            OP_PUT => {
+                let value_pos = pos + 4;
+                let value = read_buf(payload, &mut pos)?;
+                ops.push(Op::Put(key, value_pos, value.len() as u32));
+            }
+            OP_REMOVE => ops.push(Op::Remove(key)),
+            _ => return None,
+        }
+    }
+    Some(ops)
+}
+
+fn read_buf<'a>(payload: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    let len_end = pos.checked_add(4)?;
+    let len = u32::from_be_bytes(payload.get(*pos..len_end)?.try_into().ok()?) as usize;
+    let end = len_end.checked_add(len)?;
+    let data = payload.get(len_end..end)?;
+    *pos = end;
+    Some(data)
+}
+
+/// Returns the length of the put operation of a given key and value within a record payload.
+pub(crate) fn put_len(key_len: usize, value_len: usize) -> u64 {
+    (9 + key_len + value_len) as u64
+}
+
+/// Returns the length of the remove operation of a given key within a record payload.
+pub(crate) fn remove_len(key_len: usize) -> u64 {
+    (5 + key_len) as u64
+}
@@ -0,0 +1,161 @@
+//! Per-document access accounting, used to find documents which haven't been read for a long
+//! time, i.e. to evict them from a bounded local cache.
+//!
+//! When [StoreConfig::access_sample_rate](crate::config::StoreConfig::access_sample_rate) is set
+//! to `Some(n)`, one in every `n` document reads made through [DocOps::load_doc] and methods built
+//! on top of it ([DocOps::with_doc], [DocOps::get_diff], [DocOps::handle_sync_step1]) and
+//! [DocOps::load_docs] records an access: it increments the access counter of the document by `n`
+//! and sets its last access time to the current time. Reads are sampled across all documents read
+//! by the process, so counters are estimates, while access times are off by at most the time
+//! between `n` reads.
+//!
+//! **Recording an access is a write.** With access tracking enabled, reads require write
+//! capabilities from the database transaction, and the transaction has to be committed for the
+//! accesses to persist. Higher sample rates trade accuracy for fewer reads turned into writes.
+//!
+//! [DocOps::iter_docs_by_staleness] returns the documents accessed least recently:
+//!
+//! ```rust,ignore
+//! let db_txn = RocksDBStore::from(db.transaction());
+//! for (name, _stats) in db_txn.iter_docs_by_staleness(100)? {
+//!     db_txn.clear_doc(&name)?;
+//! }
+//! db_txn.commit()?;
+//! ```
+//!
+//! [DocOps::load_doc]: crate::DocOps::load_doc
+//! [DocOps::with_doc]: crate::DocOps::with_doc
+//! [DocOps::get_diff]: crate::DocOps::get_diff
+//! [DocOps::handle_sync_step1]: crate::DocOps::handle_sync_step1
+//! [DocOps::load_docs]: crate::DocOps::load_docs
+//! [DocOps::iter_docs_by_staleness]: crate::DocOps::iter_docs_by_staleness
+
+use crate::clock;
+use crate::error::Error;
+use crate::format::{decode_timestamp, encode_timestamp, TIMESTAMP_LEN};
+use crate::keys::{key_meta, META_ACCESS, OID};
+use crate::{get_oid, DocOps, KVStore};
+use std::collections::BinaryHeap;
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+/// Number of document reads made by the process, used to sample the ones recording an access.
+static READS: AtomicU64 = AtomicU64::new(0);
+
+/// Access statistics of a document, returned by
+/// [DocOps::access_stats](crate::DocOps::access_stats).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessStats {
+    /// Estimated number of recorded reads of the document.
+    pub count: u64,
+    /// Time of the most recent recorded read of the document.
+    pub last_access: SystemTime,
+}
+
+/// Name of a document paired with its access statistics, returned by
+/// [DocOps::iter_docs_by_staleness](crate::DocOps::iter_docs_by_staleness).
+pub type StaleDoc = (Box<[u8]>, Option<AccessStats>);
+
+/// Records a read of a document with a given `oid`, if access tracking is enabled and the read
+/// has been sampled.
+pub(crate) fn record_access<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let rate = match db.config().access_sample_rate {
+        Some(rate) => rate.max(1) as u64,
+        None => return Ok(()),
+    };
+    if READS.fetch_add(1, Ordering::Relaxed).checked_rem(rate) != Some(0) {
+        return Ok(());
+    }
+    let key = key_meta(oid, META_ACCESS);
+    let count = match db.get(&key)? {
+        Some(value) => decode_stats(value.as_ref())?.0,
+        None => 0,
+    };
+    let mut value = [0u8; 8 + TIMESTAMP_LEN];
+    value[..8].copy_from_slice(&count.saturating_add(rate).to_be_bytes());
+    value[8..].copy_from_slice(&encode_timestamp(db.clock().now()));
+    db.upsert(&key, &value)?;
+    Ok(())
+}
+
+pub(crate) fn access_stats<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+) -> Result<Option<AccessStats>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let oid = match get_oid(db, name)? {
+        Some(oid) => oid,
+        None => return Ok(None),
+    };
+    match db.get(&key_meta(oid, META_ACCESS))? {
+        Some(value) => {
+            let (count, millis) = decode_stats(value.as_ref())?;
+            Ok(Some(AccessStats {
+                count,
+                last_access: clock::system_time(millis),
+            }))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Returns up to `limit` live documents with the oldest last access time, starting with the
+/// stalest one. Documents with no recorded access come first.
+pub(crate) fn stalest_docs<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    limit: usize,
+) -> Result<Vec<StaleDoc>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if limit == 0 {
+        return Ok(Vec::new());
+    }
+    // max-heap of the `limit` stalest documents seen so far, `None` (never accessed) being the
+    // stalest
+    let mut heap: BinaryHeap<(Option<u64>, Box<[u8]>, u64)> = BinaryHeap::new();
+    for name in db.iter_docs()? {
+        let (last_access, count) = match get_oid(db, &name)? {
+            Some(oid) => match db.get(&key_meta(oid, META_ACCESS))? {
+                Some(value) => {
+                    let (count, millis) = decode_stats(value.as_ref())?;
+                    (Some(millis), count)
+                }
+                None => (None, 0),
+            },
+            None => continue,
+        };
+        heap.push((last_access, name, count));
+        if heap.len() > limit {
+            heap.pop();
+        }
+    }
+    Ok(heap
+        .into_sorted_vec()
+        .into_iter()
+        .map(|(last_access, name, count)| {
+            let stats = last_access.map(|millis| AccessStats {
+                count,
+                last_access: clock::system_time(millis),
+            });
+            (name, stats)
+        })
+        .collect())
+}
+
+/// Decodes an access entry value into access count and last access time in milliseconds since
+/// UNIX epoch.
+fn decode_stats(value: &[u8]) -> Result<(u64, u64), Error> {
+    if value.len() != 8 + TIMESTAMP_LEN {
+        return Err(Error::CorruptedValue);
+    }
+    let count = u64::from_be_bytes(value[..8].try_into().unwrap());
+    let millis = decode_timestamp(&value[8..]).unwrap();
+    Ok((count, millis))
+}
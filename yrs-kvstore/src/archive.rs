@@ -0,0 +1,1046 @@
+//! Portable archive files containing an entire store, i.e. for backups and disaster recovery.
+//!
+//! Not to be confused with [DocOps::archive_doc](crate::DocOps::archive_doc), which moves
+//! contents of a single document into a cold storage within the same store.
+//!
+//! [export_store] streams every document of a store into a writer: its state, state vector,
+//! pending updates, metadata and snapshots. Archived documents are exported in their archived
+//! form. [import_store] restores such an archive into any store, regardless of its backend and
+//! [ValueCodec](crate::config::ValueCodec), since values are exported in their decoded form.
+//! Document references and cached sync frames are not exported.
+//!
+//! Neither side keeps more than a single record in memory. Large stores can be imported in
+//! multiple transactions using [ArchiveReader], which imports one document per call. Import which
+//! failed part way through can be resumed by importing the same archive again with
+//! [ConflictMode::Skip], which skips documents that already exist:
+//!
+//! ```rust,ignore
+//! let mut reader = ArchiveReader::new(BufReader::new(File::open("backup.yrs")?))?;
+//! loop {
+//!     let db_txn = RocksDBStore::from(db.transaction());
+//!     // import up to 100 documents per transaction
+//!     let mut done = false;
+//!     for _ in 0..100 {
+//!         if reader.import_next(&db_txn, ConflictMode::Skip)?.is_none() {
+//!             done = true;
+//!             break;
+//!         }
+//!     }
+//!     db_txn.commit()?;
+//!     if done {
+//!         break;
+//!     }
+//! }
+//! ```
+//!
+//! # Incremental backups
+//!
+//! [export_changed_since] writes only the changes made since a previous export, described by
+//! a [BackupManifest] returned by that export. Documents created since then are exported in
+//! full. Documents which have only received new updates are exported as these updates, while the
+//! contents of documents which have been flushed since are exported as a diff encoded against
+//! the state vector recorded by the manifest. Documents that no longer exist are exported as
+//! tombstones. Exporting with an empty manifest writes a full backup, which starts the chain:
+//!
+//! ```rust,ignore
+//! let manifest = export_changed_since(&db_txn, &BackupManifest::default(), full_file)?;
+//! // later on
+//! let manifest = export_changed_since(&db_txn, &manifest, incremental_file)?;
+//! std::fs::write("backup.manifest", manifest.encode())?;
+//! ```
+//!
+//! Restoring a store means importing the full backup followed by all incremental ones in the
+//! order they have been written. Updates and diffs are imported with
+//! [DocOps::push_update](crate::DocOps::push_update), so they're merged into documents already
+//! present in the store. Metadata and snapshots of changed documents are replaced as a whole.
+//!
+//! # File format
+//!
+//! Archive starts with [MAGIC] followed by [ARCHIVE_VERSION] (u32, big endian). It's followed by
+//! a sequence of records, each in the following format:
+//!
+//! ```nocompile
+//! {tag:1}{len:4}{payload:len}{crc32:4}
+//! ```
+//!
+//! where `len` is a length of the payload (u32, big endian) and `crc32` is a CRC-32 (IEEE 802.3)
+//! checksum of all preceding bytes of the record. Every document is described by a [TAG_DOC]
+//! record, followed by records of its entries and terminated by [TAG_DOC_END]. The archive ends
+//! with [TAG_END] record.
+//!
+//! Incremental archives also contain [TAG_PATCH] sections, which are terminated by
+//! [TAG_DOC_END] as well, and standalone [TAG_TOMBSTONE] records.
+
+use crate::blob;
+use crate::config::crc32;
+use crate::error::Error;
+use crate::keys::{
+    key_archive, key_channel_update, key_doc, key_doc_end, key_doc_start, key_meta, key_meta_start,
+    key_pending, key_ref_start, key_snapshot, key_state_vector, key_update, parse_key, ParsedKey,
+    META_GUID, OID, OID_FLAG_ARCHIVED,
+};
+use crate::{
+    channel, create_oid, doc_options, get_oid_entry, get_or_create_oid, guid, last_update,
+    load_doc, segment, set_oid_flags, update_entry, usage, DocOps, KVEntry, KVStore, Pending,
+    SnapshotHandle,
+};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
+use yrs::{Doc, ReadTxn, StateVector, Transact};
+
+/// Bytes every archive file starts with.
+pub const MAGIC: &[u8; 8] = b"YRSARCH\0";
+
+/// Version of the archive file format written by [export_store].
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// Record starting a document. Payload: document name.
+pub const TAG_DOC: u8 = 1;
+/// Document state. Payload: lib0 v1 encoded document state.
+pub const TAG_STATE: u8 = 2;
+/// Document state vector. Payload: state vector entry value (see [format](crate::format)).
+pub const TAG_STATE_VEC: u8 = 3;
+/// Pending update. Payload: sequence number (u32, big endian) followed by lib0 v1 encoded update.
+pub const TAG_UPDATE: u8 = 4;
+/// Metadata entry. Payload: key length (u32, big endian), key and value.
+pub const TAG_META: u8 = 5;
+/// Snapshot. Payload: label length (u32, big endian), label and lib0 v1 encoded snapshot.
+pub const TAG_SNAPSHOT: u8 = 6;
+/// State of an archived document. Payload: zstd-compressed lib0 v1 encoded document state.
+pub const TAG_ARCHIVED: u8 = 7;
+/// Record ending a document. Payload: number of records of the document (u32, big endian),
+/// excluding [TAG_DOC] and [TAG_DOC_END].
+pub const TAG_DOC_END: u8 = 8;
+/// Record ending the archive. Payload: number of documents (u32, big endian).
+pub const TAG_END: u8 = 9;
+/// Record starting changes of a document exported by [export_changed_since]. Payload: patch
+/// flags (see [PATCH_FLAG_META]) followed by document name.
+pub const TAG_PATCH: u8 = 10;
+/// Changes of a document since a previous export. Payload: lib0 v1 encoded update.
+pub const TAG_DIFF: u8 = 11;
+/// Document removed since a previous export. Payload: document name.
+pub const TAG_TOMBSTONE: u8 = 12;
+/// Update pushed to a channel other than the default one. Payload: channel number (u8) and
+/// sequence number (u32, big endian) followed by lib0 v1 encoded update.
+pub const TAG_CHANNEL_UPDATE: u8 = 13;
+
+/// [TAG_PATCH] flag set when metadata and snapshots of a document have changed. All of them
+/// are part of the patch then and replace the existing ones on import.
+pub const PATCH_FLAG_META: u8 = 0b0000_0001;
+
+/// Version of the [BackupManifest] encoding.
+const MANIFEST_VERSION: u32 = 1;
+
+/// Decides what happens to documents of an archive, which already exist in the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictMode {
+    /// Existing document is left as it is. This makes it possible to resume failed imports.
+    Skip,
+    /// Existing document is cleared and replaced with the archived one.
+    Replace,
+}
+
+/// Progress of [export_store_with] and [import_store_with], reported after every document.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveProgress {
+    /// Number of documents processed so far.
+    pub docs: u32,
+    /// Number of documents skipped by import, because they already existed.
+    pub skipped: u32,
+    /// Number of records processed so far.
+    pub records: u64,
+    /// Number of archive bytes processed so far.
+    pub bytes: u64,
+}
+
+/// Document imported by [ArchiveReader::import_next].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedDoc {
+    /// Name of the document.
+    pub name: Box<[u8]>,
+    /// True if document already existed and has been skipped (see [ConflictMode::Skip]).
+    pub skipped: bool,
+}
+
+/// Describes the store contents at the time of an export by [export_changed_since], which makes
+/// it possible to export only the changes made since then.
+///
+/// For every document it records its state vector along with checksums of its state vector
+/// entry, most recent pending update and metadata, which are cheap to compare against the store.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackupManifest {
+    docs: BTreeMap<Box<[u8]>, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ManifestEntry {
+    oid: OID,
+    archived: bool,
+    /// Sequence number of the most recent pending update or 0 if there were none.
+    last_seq: u32,
+    /// Checksum of the most recent pending update.
+    last_crc: u32,
+    /// Checksum of the state vector entry or the archive entry of archived documents.
+    state_crc: u32,
+    /// Checksum of metadata and snapshots.
+    meta_crc: u32,
+    /// lib0 v1 encoded state vector of the document. Empty for archived documents.
+    state_vector: Vec<u8>,
+}
+
+impl BackupManifest {
+    /// Returns a number of documents recorded by the manifest.
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    /// Checks if the manifest doesn't record any documents.
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty()
+    }
+
+    /// Checks if a document with a given `name` is recorded by the manifest.
+    pub fn contains<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> bool {
+        self.docs.contains_key(name.as_ref())
+    }
+
+    /// Encodes the manifest, i.e. to store it next to the backup it describes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&MANIFEST_VERSION.to_be_bytes());
+        buf.extend_from_slice(&(self.docs.len() as u32).to_be_bytes());
+        for (name, e) in self.docs.iter() {
+            buf.extend_from_slice(&(name.len() as u32).to_be_bytes());
+            buf.extend_from_slice(name);
+            buf.extend_from_slice(&e.oid.to_be_bytes());
+            buf.push(e.archived as u8);
+            buf.extend_from_slice(&e.last_seq.to_be_bytes());
+            buf.extend_from_slice(&e.last_crc.to_be_bytes());
+            buf.extend_from_slice(&e.state_crc.to_be_bytes());
+            buf.extend_from_slice(&e.meta_crc.to_be_bytes());
+            buf.extend_from_slice(&(e.state_vector.len() as u32).to_be_bytes());
+            buf.extend_from_slice(&e.state_vector);
+        }
+        buf
+    }
+
+    /// Decodes a manifest encoded with [Self::encode].
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        let (version, data) = split_u32(data)?;
+        if version != MANIFEST_VERSION {
+            return Err(Error::InvalidArchive("unsupported manifest version"));
+        }
+        let (count, mut data) = split_u32(data)?;
+        let mut docs = BTreeMap::new();
+        for _ in 0..count {
+            let (name, rest) = split_prefixed(data)?;
+            let (oid, rest) = split_u32(rest)?;
+            let (archived, rest) = rest
+                .split_first()
+                .ok_or(Error::InvalidArchive("record is too short"))?;
+            let (last_seq, rest) = split_u32(rest)?;
+            let (last_crc, rest) = split_u32(rest)?;
+            let (state_crc, rest) = split_u32(rest)?;
+            let (meta_crc, rest) = split_u32(rest)?;
+            let (state_vector, rest) = split_prefixed(rest)?;
+            docs.insert(
+                Box::from(name),
+                ManifestEntry {
+                    oid,
+                    archived: *archived != 0,
+                    last_seq,
+                    last_crc,
+                    state_crc,
+                    meta_crc,
+                    state_vector: state_vector.to_vec(),
+                },
+            );
+            data = rest;
+        }
+        Ok(BackupManifest { docs })
+    }
+}
+
+/// Exports all documents of a given store into a `writer`. See [module documentation](self).
+///
+/// This feature requires only the read capabilities from the database transaction.
+pub fn export_store<'a, DB, W>(db: &DB, writer: W) -> Result<ArchiveProgress, Error>
+where
+    DB: DocOps<'a>,
+    W: Write,
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    export_store_with(db, writer, |_| {})
+}
+
+/// Same as [export_store], but calls `progress` after every exported document.
+pub fn export_store_with<'a, DB, W, F>(
+    db: &DB,
+    writer: W,
+    mut progress: F,
+) -> Result<ArchiveProgress, Error>
+where
+    DB: DocOps<'a>,
+    W: Write,
+    F: FnMut(&ArchiveProgress),
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let mut w = RecordWriter::start(writer)?;
+    for name in db.iter_docs_with(true)? {
+        let (oid, flags) = match get_oid_entry(db, &name)? {
+            Some(entry) => entry,
+            None => continue,
+        };
+        w.write(TAG_DOC, &[&name])?;
+        let records = export_doc(db, oid, flags, &mut w)?;
+        w.write(TAG_DOC_END, &[&records.to_be_bytes()])?;
+        w.progress.docs += 1;
+        progress(&w.progress);
+    }
+    w.finish()
+}
+
+/// Writes documents with given `names` into an archive, reading all of them through a given
+/// `snapshot` (see [DocOps::read_snapshot]). Documents which don't exist are skipped. Resulting
+/// archive can be restored with [import_store].
+///
+/// If the snapshot [is consistent](SnapshotHandle::is_consistent), the archive reflects a single
+/// logical instant, so invariants spanning multiple documents hold within it even when other
+/// transactions commit changes during the export. Otherwise every document is exported
+/// consistently on its own, but documents may be exported from different points in time.
+pub fn export_docs_consistent<'a, 'x, DB, I, W>(
+    snapshot: &SnapshotHandle<DB>,
+    names: I,
+    writer: W,
+) -> Result<ArchiveProgress, Error>
+where
+    DB: DocOps<'a>,
+    I: IntoIterator<Item = &'x [u8]>,
+    W: Write,
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let db: &DB = snapshot;
+    let mut w = RecordWriter::start(writer)?;
+    for name in names {
+        let (oid, flags) = match get_oid_entry(db, name)? {
+            Some(entry) => entry,
+            None => continue,
+        };
+        w.write(TAG_DOC, &[name])?;
+        let records = export_doc(db, oid, flags, &mut w)?;
+        w.write(TAG_DOC_END, &[&records.to_be_bytes()])?;
+        w.progress.docs += 1;
+    }
+    w.finish()
+}
+
+/// Exports documents of a given store changed since the export described by a `baseline`
+/// manifest into a `writer`, returning a manifest describing the current contents of the store.
+/// Passing an empty manifest exports all documents. See [module documentation](self).
+///
+/// This feature requires only the read capabilities from the database transaction.
+pub fn export_changed_since<'a, DB, W>(
+    db: &DB,
+    baseline: &BackupManifest,
+    writer: W,
+) -> Result<BackupManifest, Error>
+where
+    DB: DocOps<'a>,
+    W: Write,
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let mut w = RecordWriter::start(writer)?;
+    let mut manifest = BackupManifest::default();
+    for name in db.iter_docs_with(true)? {
+        let (oid, flags) = match get_oid_entry(db, &name)? {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let archived = flags & OID_FLAG_ARCHIVED != 0;
+        let (last_seq, last_crc) = match last_update(db, oid)? {
+            Some(e) => {
+                let (seq, update) = segment::last_record(e.key(), e.value())?;
+                (seq, crc32(update))
+            }
+            None => (0, 0),
+        };
+        let state_crc = if archived {
+            db.get(&key_archive(oid))?
+        } else {
+            db.get(&key_state_vector(oid))?
+        }
+        .map(|value| crc32(value.as_ref()))
+        .unwrap_or(0);
+        let meta = meta_records(db, oid)?;
+        let meta_crc = crc32(&meta.iter().fold(Vec::new(), |mut buf, (tag, payload)| {
+            buf.push(*tag);
+            buf.extend_from_slice(payload);
+            buf
+        }));
+        let mut entry = ManifestEntry {
+            oid,
+            archived,
+            last_seq,
+            last_crc,
+            state_crc,
+            meta_crc,
+            state_vector: Vec::new(),
+        };
+        let prev = baseline
+            .docs
+            .get(&name)
+            .filter(|prev| prev.oid == oid && !prev.archived && !archived);
+        match (prev, baseline.docs.get(&name)) {
+            (Some(prev), _) => {
+                let content_changed = prev.last_seq != last_seq
+                    || prev.last_crc != last_crc
+                    || prev.state_crc != state_crc;
+                let meta_changed = prev.meta_crc != meta_crc;
+                entry.state_vector = prev.state_vector.clone();
+                if content_changed || meta_changed {
+                    let flags = if meta_changed { PATCH_FLAG_META } else { 0 };
+                    w.write(TAG_PATCH, &[&[flags], &name])?;
+                    let mut records = 0;
+                    if content_changed {
+                        let (state_vector, n) = export_changes(db, &name, oid, prev, &mut w)?;
+                        entry.state_vector = state_vector;
+                        records += n;
+                    }
+                    if meta_changed {
+                        for (tag, payload) in meta.iter() {
+                            w.write(*tag, &[payload])?;
+                            records += 1;
+                        }
+                    }
+                    w.write(TAG_DOC_END, &[&records.to_be_bytes()])?;
+                    w.progress.docs += 1;
+                }
+            }
+            (None, Some(prev)) if prev == &entry => {
+                // archived document which didn't change
+            }
+            (None, prev) => {
+                if prev.is_some() {
+                    // document has been replaced or (un)archived since the baseline
+                    w.write(TAG_TOMBSTONE, &[&name])?;
+                    w.progress.docs += 1;
+                }
+                w.write(TAG_DOC, &[&name])?;
+                let records = export_doc(db, oid, flags, &mut w)?;
+                w.write(TAG_DOC_END, &[&records.to_be_bytes()])?;
+                w.progress.docs += 1;
+                if !archived {
+                    entry.state_vector = current_state_vector(db, &name, oid)?;
+                }
+            }
+        }
+        manifest.docs.insert(name, entry);
+    }
+    for name in baseline.docs.keys() {
+        if !manifest.docs.contains_key(name) {
+            w.write(TAG_TOMBSTONE, &[name])?;
+            w.progress.docs += 1;
+        }
+    }
+    w.finish()?;
+    Ok(manifest)
+}
+
+/// Imports all documents of an archive read from a `reader` into a given store, handling
+/// documents that already exist according to a given `mode`. See [module documentation](self).
+///
+/// If import fails, the transaction may contain a partially imported document and should be
+/// rolled back.
+///
+/// This feature requires a write capabilities from the database transaction.
+pub fn import_store<'a, DB, R>(
+    db: &DB,
+    reader: R,
+    mode: ConflictMode,
+) -> Result<ArchiveProgress, Error>
+where
+    DB: DocOps<'a>,
+    R: Read,
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    import_store_with(db, reader, mode, |_| {})
+}
+
+/// Same as [import_store], but calls `progress` after every imported document.
+pub fn import_store_with<'a, DB, R, F>(
+    db: &DB,
+    reader: R,
+    mode: ConflictMode,
+    mut progress: F,
+) -> Result<ArchiveProgress, Error>
+where
+    DB: DocOps<'a>,
+    R: Read,
+    F: FnMut(&ArchiveProgress),
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let mut reader = ArchiveReader::new(reader)?;
+    while reader.import_next(db, mode)?.is_some() {
+        progress(reader.progress());
+    }
+    Ok(reader.progress)
+}
+
+/// Reader of archives written by [export_store] and [export_changed_since], which imports them one document at a time.
+pub struct ArchiveReader<R> {
+    reader: R,
+    progress: ArchiveProgress,
+    done: bool,
+}
+
+impl<R: Read> ArchiveReader<R> {
+    /// Creates a new archive reader, validating the archive header.
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(Error::InvalidArchive("not an archive file"));
+        }
+        let version = u32::from_be_bytes(header[8..].try_into().unwrap());
+        if version != ARCHIVE_VERSION {
+            return Err(Error::InvalidArchive("unsupported archive version"));
+        }
+        Ok(ArchiveReader {
+            reader,
+            progress: ArchiveProgress {
+                bytes: header.len() as u64,
+                ..ArchiveProgress::default()
+            },
+            done: false,
+        })
+    }
+
+    /// Returns the progress of the import so far.
+    pub fn progress(&self) -> &ArchiveProgress {
+        &self.progress
+    }
+
+    /// Imports the next document of the archive into a given store, handling a document that
+    /// already exists according to a given `mode`. Returns `None` once the end of the archive
+    /// has been reached.
+    ///
+    /// If import fails, the transaction may contain a partially imported document and should be
+    /// rolled back.
+    pub fn import_next<'a, DB>(
+        &mut self,
+        db: &DB,
+        mode: ConflictMode,
+    ) -> Result<Option<ImportedDoc>, Error>
+    where
+        DB: DocOps<'a>,
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        if self.done {
+            return Ok(None);
+        }
+        let (tag, payload) = self.read_record()?;
+        let name = match tag {
+            TAG_DOC => payload.into_boxed_slice(),
+            TAG_PATCH => return self.import_patch(db, &payload).map(Some),
+            TAG_TOMBSTONE => {
+                db.clear_doc(&payload)?;
+                self.progress.docs += 1;
+                return Ok(Some(ImportedDoc {
+                    name: payload.into_boxed_slice(),
+                    skipped: false,
+                }));
+            }
+            TAG_END => {
+                let docs = read_u32(&payload)?;
+                if docs != self.progress.docs {
+                    return Err(Error::InvalidArchive("number of documents doesn't match"));
+                }
+                self.done = true;
+                return Ok(None);
+            }
+            _ => return Err(Error::InvalidArchive("document record expected")),
+        };
+        self.import_doc(db, name, mode, true).map(Some)
+    }
+
+    /// Same as [Self::import_next], but imports the next document under a given `name` instead
+    /// of the one it has been exported with, i.e. in order to copy a document. The next record
+    /// must start a full document, as written by [export_store] or [export_docs_consistent].
+    ///
+    /// GUID identifies a single document, so it's not carried over to the imported document
+    /// unless `name` is the same as the exported one.
+    pub fn import_next_as<'a, DB>(
+        &mut self,
+        db: &DB,
+        name: &[u8],
+        mode: ConflictMode,
+    ) -> Result<Option<ImportedDoc>, Error>
+    where
+        DB: DocOps<'a>,
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        if self.done {
+            return Ok(None);
+        }
+        let (tag, payload) = self.read_record()?;
+        match tag {
+            TAG_DOC => {
+                let keep_guid = payload.as_slice() == name;
+                self.import_doc(db, name.into(), mode, keep_guid).map(Some)
+            }
+            TAG_END => {
+                let docs = read_u32(&payload)?;
+                if docs != self.progress.docs {
+                    return Err(Error::InvalidArchive("number of documents doesn't match"));
+                }
+                self.done = true;
+                Ok(None)
+            }
+            _ => Err(Error::InvalidArchive("document record expected")),
+        }
+    }
+
+    /// Imports records of a document following its [TAG_DOC] record under a given `name`.
+    /// Records of its GUID are skipped unless `keep_guid` is set.
+    fn import_doc<'a, DB>(
+        &mut self,
+        db: &DB,
+        name: Box<[u8]>,
+        mode: ConflictMode,
+        keep_guid: bool,
+    ) -> Result<ImportedDoc, Error>
+    where
+        DB: DocOps<'a>,
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        let skipped = match (get_oid_entry(db, &name)?, mode) {
+            (Some(_), ConflictMode::Skip) => true,
+            (Some(_), ConflictMode::Replace) => {
+                db.clear_doc(&name)?;
+                false
+            }
+            (None, _) => false,
+        };
+        let usage = usage::Tracker::start(db, &name)?;
+        let oid = if skipped {
+            None
+        } else {
+            Some(create_oid(db, &name)?)
+        };
+        let codec = db.config().codec;
+        let mut pending = Pending::default();
+        let mut records = 0;
+        loop {
+            let (tag, payload) = self.read_record()?;
+            let oid = match (tag, oid) {
+                (TAG_DOC_END, _) => {
+                    if read_u32(&payload)? != records {
+                        return Err(Error::InvalidArchive("number of records doesn't match"));
+                    }
+                    break;
+                }
+                (_, Some(oid)) => oid,
+                (_, None) => {
+                    records += 1;
+                    continue;
+                }
+            };
+            match tag {
+                TAG_STATE => blob::put_state(db, &key_doc(oid), &codec.encode(&payload)?)?,
+                TAG_STATE_VEC => db.upsert(&key_state_vector(oid), &codec.encode(&payload)?)?,
+                TAG_UPDATE => {
+                    let (seq, update) = split_u32(&payload)?;
+                    let update = codec.encode(update)?;
+                    db.upsert(&key_update(oid, seq), &update)?;
+                    pending.updates += 1;
+                    pending.bytes += update.len() as u64;
+                }
+                TAG_CHANNEL_UPDATE => {
+                    let (&channel, payload) = payload
+                        .split_first()
+                        .ok_or(Error::InvalidArchive("record is too short"))?;
+                    let (seq, update) = split_u32(payload)?;
+                    let key = key_channel_update(oid, channel, seq);
+                    db.upsert(&key, &codec.encode(update)?)?;
+                }
+                TAG_META => {
+                    let (key, value) = split_prefixed(&payload)?;
+                    if key == META_GUID {
+                        if keep_guid {
+                            db.upsert(&key_meta(oid, key), value)?;
+                            guid::index(db, &name, value)?;
+                        }
+                    } else {
+                        db.upsert(&key_meta(oid, key), value)?;
+                    }
+                }
+                TAG_SNAPSHOT => {
+                    let (label, snapshot) = split_prefixed(&payload)?;
+                    db.upsert(&key_snapshot(oid, label), snapshot)?;
+                }
+                TAG_ARCHIVED => {
+                    db.upsert(&key_archive(oid), &payload)?;
+                    set_oid_flags(db, &name, oid, OID_FLAG_ARCHIVED)?;
+                }
+                _ => return Err(Error::InvalidArchive("unexpected document record")),
+            }
+            records += 1;
+        }
+        if let (Some(oid), true) = (oid, pending.updates != 0) {
+            db.upsert(&key_pending(oid), &pending.encode())?;
+        }
+        self.progress.docs += 1;
+        if skipped {
+            self.progress.skipped += 1;
+        }
+        usage.finish(db, Ok(ImportedDoc { name, skipped }))
+    }
+
+    /// Imports a [TAG_PATCH] section with a given `payload`. Updates and diffs are applied via
+    /// [DocOps::push_update], creating the document if it doesn't exist.
+    fn import_patch<'a, DB>(&mut self, db: &DB, payload: &[u8]) -> Result<ImportedDoc, Error>
+    where
+        DB: DocOps<'a>,
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        let (&flags, name) = payload
+            .split_first()
+            .ok_or(Error::InvalidArchive("record is too short"))?;
+        let mut meta = Vec::new();
+        let mut records = 0;
+        loop {
+            let (tag, payload) = self.read_record()?;
+            match tag {
+                TAG_DOC_END => {
+                    if read_u32(&payload)? != records {
+                        return Err(Error::InvalidArchive("number of records doesn't match"));
+                    }
+                    break;
+                }
+                TAG_UPDATE => {
+                    let (_, update) = split_u32(&payload)?;
+                    db.push_update(name, update)?;
+                }
+                TAG_DIFF => {
+                    db.push_update(name, &payload)?;
+                }
+                TAG_META | TAG_SNAPSHOT => meta.push((tag, payload)),
+                _ => return Err(Error::InvalidArchive("unexpected document record")),
+            }
+            records += 1;
+        }
+        if flags & PATCH_FLAG_META != 0 {
+            let oid = get_or_create_oid(db, name)?;
+            let stale: Vec<Vec<u8>> = {
+                let end = key_ref_start(oid);
+                let mut stale = Vec::new();
+                for e in db.iter_range(&key_meta_start(oid), &end)? {
+                    let key = e.key();
+                    if key >= end.as_ref() {
+                        break;
+                    }
+                    if let Some(ParsedKey::Meta { .. }) | Some(ParsedKey::Snapshot { .. }) =
+                        parse_key(key)
+                    {
+                        stale.push(key.to_vec());
+                    }
+                }
+                stale
+            };
+            for key in stale {
+                db.remove(&key)?;
+            }
+            for (tag, payload) in meta {
+                let (key, value) = split_prefixed(&payload)?;
+                if tag == TAG_META {
+                    db.upsert(&key_meta(oid, key), value)?;
+                    if key == META_GUID {
+                        guid::index(db, name, value)?;
+                    }
+                } else {
+                    db.upsert(&key_snapshot(oid, key), value)?;
+                }
+            }
+        }
+        self.progress.docs += 1;
+        Ok(ImportedDoc {
+            name: Box::from(name),
+            skipped: false,
+        })
+    }
+
+    fn read_record(&mut self) -> Result<(u8, Vec<u8>), Error> {
+        let mut header = [0u8; 5];
+        self.reader.read_exact(&mut header)?;
+        let len = u32::from_be_bytes(header[1..].try_into().unwrap()) as usize;
+        let mut payload = vec![0u8; len];
+        self.reader.read_exact(&mut payload)?;
+        let mut checksum = [0u8; 4];
+        self.reader.read_exact(&mut checksum)?;
+        let mut data = Vec::with_capacity(header.len() + len);
+        data.extend_from_slice(&header);
+        data.extend_from_slice(&payload);
+        if crc32(&data) != u32::from_be_bytes(checksum) {
+            return Err(Error::InvalidArchive("record checksum mismatch"));
+        }
+        self.progress.records += 1;
+        self.progress.bytes += (header.len() + len + checksum.len()) as u64;
+        Ok((header[0], payload))
+    }
+}
+
+/// Writes records of a document with a given `oid`, returning their number.
+fn export_doc<'a, DB, W>(
+    db: &DB,
+    oid: OID,
+    flags: u8,
+    w: &mut RecordWriter<W>,
+) -> Result<u32, Error>
+where
+    DB: DocOps<'a>,
+    W: Write,
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let codec = db.config().codec;
+    let mut records = 0;
+    if flags & OID_FLAG_ARCHIVED != 0 {
+        if let Some(state) = db.get(&key_archive(oid))? {
+            w.write(TAG_ARCHIVED, &[state.as_ref()])?;
+            records += 1;
+        }
+    }
+    let end = key_doc_end(oid);
+    for e in db.iter_range(&key_doc_start(oid), &end)? {
+        let key = e.key();
+        if key >= end.as_ref() {
+            break;
+        }
+        let value = e.value();
+        match parse_key(key) {
+            Some(ParsedKey::Doc { .. }) => {
+                let value = blob::resolve(db, value)?;
+                w.write(TAG_STATE, &[&codec.decode(&value)?])?
+            }
+            Some(ParsedKey::StateVector { .. }) => {
+                w.write(TAG_STATE_VEC, &[&codec.decode(value)?])?
+            }
+            Some(ParsedKey::Update { clock, .. }) => {
+                w.write(TAG_UPDATE, &[&clock.to_be_bytes(), &codec.decode(value)?])?
+            }
+            Some(ParsedKey::UpdateSegment { .. }) => {
+                for record in segment::records(key, value) {
+                    let (seq, update) = record?;
+                    w.write(TAG_UPDATE, &[&seq.to_be_bytes(), &codec.decode(update)?])?;
+                    records += 1;
+                }
+                continue;
+            }
+            Some(ParsedKey::Meta { name, .. }) => {
+                let len = (name.len() as u32).to_be_bytes();
+                w.write(TAG_META, &[&len, name, value])?
+            }
+            Some(ParsedKey::Snapshot { label, .. }) => {
+                let len = (label.len() as u32).to_be_bytes();
+                w.write(TAG_SNAPSHOT, &[&len, label, value])?
+            }
+            Some(ParsedKey::ChannelUpdate { channel, clock, .. }) => w.write(
+                TAG_CHANNEL_UPDATE,
+                &[&[channel], &clock.to_be_bytes(), &codec.decode(value)?],
+            )?,
+            // pending counter is recomputed on import, references and sync frames are not
+            // exported
+            _ => continue,
+        }
+        records += 1;
+    }
+    Ok(records)
+}
+
+/// Writes the changes of a live document made since the export recorded by `prev`, returning
+/// the current state vector of the document and the number of written records. Pending updates
+/// appended since are written as they are, unless the document has been flushed in the meantime
+/// or has updates pushed to other channels, in which case its contents are written as a diff
+/// against the recorded state vector.
+fn export_changes<'a, DB, W>(
+    db: &DB,
+    name: &[u8],
+    oid: OID,
+    prev: &ManifestEntry,
+    w: &mut RecordWriter<W>,
+) -> Result<(Vec<u8>, u32), Error>
+where
+    DB: DocOps<'a>,
+    W: Write,
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let codec = db.config().codec;
+    let last = match prev.last_seq {
+        0 => None,
+        seq => update_entry(db, oid, seq)?,
+    };
+    let last_crc = last.as_ref().and_then(|e| {
+        segment::records(e.key(), e.value())
+            .filter_map(Result::ok)
+            .find(|(seq, _)| *seq == prev.last_seq)
+            .map(|(_, update)| crc32(update))
+    });
+    // flush rewrites the state vector entry and removes the pending updates it has merged,
+    // channel updates are exported as a part of a diff
+    let appended = !channel::has_updates(db, oid)?
+        && prev.state_crc
+            == db
+                .get(&key_state_vector(oid))?
+                .map(|value| crc32(value.as_ref()))
+                .unwrap_or(0)
+        && (prev.last_seq == 0 || last_crc == Some(prev.last_crc));
+    if appended {
+        let mut records = 0;
+        // updates appended to the segment storing the last exported one are exported as well
+        let start = match &last {
+            Some(e) => e.key().to_vec(),
+            None => key_update(oid, 1).to_vec(),
+        };
+        let end = key_update(oid, u32::MAX);
+        for e in db.iter_range(&start, &end)? {
+            let key = e.key();
+            if key >= end.as_ref() {
+                break;
+            }
+            for record in segment::records(key, e.value()) {
+                let (seq, update) = record?;
+                if seq > prev.last_seq {
+                    w.write(TAG_UPDATE, &[&seq.to_be_bytes(), &codec.decode(update)?])?;
+                    records += 1;
+                }
+            }
+        }
+        Ok((current_state_vector(db, name, oid)?, records))
+    } else {
+        let doc = Doc::with_options(doc_options(db, oid)?);
+        {
+            let mut txn = doc.transact_mut();
+            load_doc(db, oid, &mut txn)?;
+        }
+        let sv = StateVector::decode_v1(&prev.state_vector)?;
+        let txn = doc.transact();
+        w.write(TAG_DIFF, &[&txn.encode_diff_v1(&sv)])?;
+        Ok((txn.state_vector().encode_v1(), 1))
+    }
+}
+
+/// Returns the lib0 v1 encoded state vector of a live document, materializing it only if the
+/// stored state vector doesn't cover its pending updates.
+fn current_state_vector<'a, DB>(db: &DB, name: &[u8], oid: OID) -> Result<Vec<u8>, Error>
+where
+    DB: DocOps<'a>,
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if let (Some(sv), true) = db.get_state_vector(name)? {
+        return Ok(sv.encode_v1());
+    }
+    let doc = Doc::new();
+    {
+        let mut txn = doc.transact_mut();
+        load_doc(db, oid, &mut txn)?;
+    }
+    let sv = doc.transact().state_vector().encode_v1();
+    Ok(sv)
+}
+
+/// Returns [TAG_META] and [TAG_SNAPSHOT] records of a document with a given `oid`.
+fn meta_records<'a, DB>(db: &DB, oid: OID) -> Result<Vec<(u8, Vec<u8>)>, Error>
+where
+    DB: DocOps<'a>,
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let end = key_ref_start(oid);
+    let mut records = Vec::new();
+    for e in db.iter_range(&key_meta_start(oid), &end)? {
+        let key = e.key();
+        if key >= end.as_ref() {
+            break;
+        }
+        let (tag, name) = match parse_key(key) {
+            Some(ParsedKey::Meta { name, .. }) => (TAG_META, name),
+            Some(ParsedKey::Snapshot { label, .. }) => (TAG_SNAPSHOT, label),
+            _ => continue,
+        };
+        let mut payload = Vec::with_capacity(4 + name.len() + e.value().len());
+        payload.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        payload.extend_from_slice(name);
+        payload.extend_from_slice(e.value());
+        records.push((tag, payload));
+    }
+    Ok(records)
+}
+
+struct RecordWriter<W> {
+    writer: W,
+    progress: ArchiveProgress,
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Writes the archive header.
+    fn start(mut writer: W) -> Result<Self, Error> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&ARCHIVE_VERSION.to_be_bytes())?;
+        Ok(RecordWriter {
+            writer,
+            progress: ArchiveProgress {
+                bytes: (MAGIC.len() + 4) as u64,
+                ..ArchiveProgress::default()
+            },
+        })
+    }
+
+    /// Writes the record ending the archive.
+    fn finish(mut self) -> Result<ArchiveProgress, Error> {
+        let docs = self.progress.docs;
+        self.write(TAG_END, &[&docs.to_be_bytes()])?;
+        self.writer.flush()?;
+        Ok(self.progress)
+    }
+
+    /// Writes a record with a given `tag` and payload made of concatenated `parts`.
+    fn write(&mut self, tag: u8, parts: &[&[u8]]) -> Result<(), Error> {
+        let len: usize = parts.iter().map(|p| p.len()).sum();
+        let mut data = Vec::with_capacity(5 + len + 4);
+        data.push(tag);
+        data.extend_from_slice(&(len as u32).to_be_bytes());
+        for part in parts {
+            data.extend_from_slice(part);
+        }
+        let checksum = crc32(&data);
+        data.extend_from_slice(&checksum.to_be_bytes());
+        self.writer.write_all(&data)?;
+        self.progress.records += 1;
+        self.progress.bytes += data.len() as u64;
+        Ok(())
+    }
+}
+
+fn read_u32(payload: &[u8]) -> Result<u32, Error> {
+    let (value, _) = split_u32(payload)?;
+    Ok(value)
+}
+
+/// Splits a payload into a leading u32 number and remaining bytes.
+fn split_u32(payload: &[u8]) -> Result<(u32, &[u8]), Error> {
+    if payload.len() < 4 {
+        return Err(Error::InvalidArchive("record is too short"));
+    }
+    let (value, rest) = payload.split_at(4);
+    Ok((u32::from_be_bytes(value.try_into().unwrap()), rest))
+}
+
+/// Splits a payload into a length-prefixed segment and remaining bytes.
+fn split_prefixed(payload: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    let (len, rest) = split_u32(payload)?;
+    if rest.len() < len as usize {
+        return Err(Error::InvalidArchive("record is too short"));
+    }
+    Ok(rest.split_at(len as usize))
+}
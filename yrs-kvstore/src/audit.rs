@@ -0,0 +1,284 @@
+//! Audit log of plain text changes, i.e. to tell which text has been added to a document by
+//! a given user at a given time without replaying the history of the document.
+//!
+//! When [StoreConfig::audit](crate::config::StoreConfig::audit) is set,
+//! [DocOps::push_update_with_origin](crate::DocOps::push_update_with_origin) inspects every update
+//! before it's stored: the document is loaded, the update is applied to it and changes made to the
+//! text of configured root types ([AuditRoot]) are observed. For every root type which text has
+//! changed, an [AuditRecord] is appended to the audit log of the document, stamped with the time
+//! of the write and the origin given by the caller:
+//!
+//! ```rust,ignore
+//! let config = StoreConfig {
+//!     audit: Some(AuditConfig {
+//!         roots: vec![AuditRoot::Text("content".into())],
+//!         excerpt_len: 64,
+//!     }),
+//!     ..StoreConfig::DEFAULT
+//! };
+//! let db_txn = ConfiguredStore::new(RocksDBStore::from(db.transaction()), config);
+//! db_txn.push_update_with_origin("my-doc-name", &update, b"user-42")?;
+//!
+//! let day_ago = SystemTime::now() - Duration::from_secs(24 * 3600);
+//! for record in db_txn.iter_audit("my-doc-name", day_ago..SystemTime::now())? {
+//!     println!("{:?} added {} chars to {}", record.origin, record.inserted, record.root);
+//! }
+//! ```
+//!
+//! Extraction is best-effort. Lengths are measured in UTF-16 code units, just like text lengths
+//! reported by Yjs clients, and embedded values count as a single unit. Changes made by an update
+//! which cannot be integrated yet, because it depends on updates the document hasn't received,
+//! are recorded together with the update which makes it integrate. Text of XmlText nodes is
+//! recorded once they are edited: initial contents of newly inserted nodes are not. Root types
+//! which are not listed in [AuditConfig::roots] are never inspected.
+//!
+//! **Auditing loads the document on every push.** It's meant for documents which are written
+//! rarely enough that the cost of loading them is acceptable.
+//!
+//! Records are stored within the document key space, so they are removed together with the
+//! document by [DocOps::clear_doc](crate::DocOps::clear_doc), but they are not included in
+//! [archive](crate::archive) exports. Old records can be removed with
+//! [DocOps::prune_audit](crate::DocOps::prune_audit). See [format](crate::format) for the layout
+//! of audit records.
+
+use crate::clock;
+use crate::error::Error;
+use crate::keys::{key_audit, key_audit_start, parse_key, ParsedKey, OID};
+use crate::{doc_options, last_in_range, load_doc, DocOps, KVEntry, KVStore};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use yrs::encoding::read::{Cursor, Read};
+use yrs::encoding::write::Write;
+use yrs::types::{Delta, Event, Events};
+use yrs::updates::decoder::Decode;
+use yrs::{Any, DeepObservable, Doc, OffsetKind, Out, Transact, TransactionMut, Update};
+
+/// Configuration of the audit log, see [audit](self) module for details.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditConfig {
+    /// Root types, which text changes are recorded.
+    pub roots: Vec<AuditRoot>,
+    /// Maximum number of characters of inserted text kept in [AuditRecord::excerpt]. `0` disables
+    /// excerpts.
+    pub excerpt_len: usize,
+}
+
+/// Root type of a document, which text changes are recorded in the audit log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditRoot {
+    /// Root level Text with a given name.
+    Text(String),
+    /// Root level XmlFragment with a given name. Changes of its XmlText nodes at any depth are
+    /// recorded as changes of the fragment.
+    Xml(String),
+}
+
+impl AuditRoot {
+    /// Returns the name of the root type.
+    pub fn name(&self) -> &str {
+        match self {
+            AuditRoot::Text(name) => name,
+            AuditRoot::Xml(name) => name,
+        }
+    }
+}
+
+/// Text changes made to a single root type by a single update, returned by
+/// [DocOps::iter_audit](crate::DocOps::iter_audit).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// Time of the write, with millisecond precision.
+    pub time: SystemTime,
+    /// Origin of the update, as given to
+    /// [DocOps::push_update_with_origin](crate::DocOps::push_update_with_origin).
+    pub origin: Vec<u8>,
+    /// Name of the changed root type.
+    pub root: String,
+    /// Length of inserted text.
+    pub inserted: u32,
+    /// Length of deleted text.
+    pub deleted: u32,
+    /// Beginning of inserted text, up to [AuditConfig::excerpt_len] characters.
+    pub excerpt: String,
+}
+
+/// Text changes of a single root type, accumulated from the events of its observer.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TextChanges {
+    inserted: u32,
+    deleted: u32,
+    excerpt: String,
+}
+
+impl TextChanges {
+    fn is_empty(&self) -> bool {
+        self.inserted == 0 && self.deleted == 0
+    }
+
+    fn record(&mut self, delta: &[Delta], excerpt_len: usize) {
+        for d in delta {
+            match d {
+                Delta::Inserted(Out::Any(Any::String(s)), _) => {
+                    self.inserted += s.encode_utf16().count() as u32;
+                    let room = excerpt_len.saturating_sub(self.excerpt.chars().count());
+                    self.excerpt.extend(s.chars().take(room));
+                }
+                Delta::Inserted(_, _) => self.inserted += 1,
+                Delta::Deleted(len) => self.deleted += len,
+                Delta::Retain(_, _) => {}
+            }
+        }
+    }
+}
+
+/// Applies a given `update` to the current contents of a document with a given `oid` (or to an
+/// empty document if it doesn't exist yet) and returns the text changes it made to every root type
+/// listed by a given audit `config`, in the same order.
+pub(crate) fn extract<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: Option<OID>,
+    update: &[u8],
+    config: &AuditConfig,
+) -> Result<Vec<TextChanges>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let mut options = match oid {
+        Some(oid) => doc_options(db, oid)?,
+        None => yrs::Options::default(),
+    };
+    options.offset_kind = OffsetKind::Utf16;
+    let doc = Doc::with_options(options);
+    if let Some(oid) = oid {
+        load_doc(db, oid, &mut doc.transact_mut())?;
+    }
+    let changes = Arc::new(Mutex::new(vec![TextChanges::default(); config.roots.len()]));
+    let mut subscriptions = Vec::with_capacity(config.roots.len());
+    for (i, root) in config.roots.iter().enumerate() {
+        let changes = changes.clone();
+        let excerpt_len = config.excerpt_len;
+        let observer = move |txn: &TransactionMut, events: &Events| {
+            let mut changes = changes.lock().unwrap();
+            for event in events.iter() {
+                let delta = match event {
+                    Event::Text(e) => e.delta(txn),
+                    Event::XmlText(e) => e.delta(txn),
+                    _ => continue,
+                };
+                changes[i].record(delta, excerpt_len);
+            }
+        };
+        subscriptions.push(match root {
+            AuditRoot::Text(name) => doc.get_or_insert_text(name.as_str()).observe_deep(observer),
+            AuditRoot::Xml(name) => doc
+                .get_or_insert_xml_fragment(name.as_str())
+                .observe_deep(observer),
+        });
+    }
+    // observers are notified once the transaction is committed
+    doc.transact_mut().apply_update(Update::decode_v1(update)?);
+    drop(subscriptions);
+    let changes = changes.lock().unwrap().clone();
+    Ok(changes)
+}
+
+/// Appends audit records of given text `changes` made by an update of a given `origin` to the
+/// audit log of a document with a given `oid`. Roots without changes are skipped.
+pub(crate) fn append<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    origin: &[u8],
+    roots: &[AuditRoot],
+    changes: Vec<TextChanges>,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let time = db.clock().now();
+    // records written within the same millisecond are numbered in the order of their writes
+    let (first, last) = (key_audit(oid, time, 0), key_audit(oid, time, u32::MAX));
+    let mut n = match last_in_range(db, &first, &last)? {
+        Some(e) => match parse_key(e.key()) {
+            Some(ParsedKey::Audit {
+                oid: last_oid,
+                time: last_time,
+                n,
+            }) if last_oid == oid && last_time == time => n + 1,
+            _ => 0,
+        },
+        None => 0,
+    };
+    for (root, changes) in roots.iter().zip(changes) {
+        if changes.is_empty() {
+            continue;
+        }
+        let mut value = Vec::new();
+        value.write_buf(origin);
+        value.write_string(root.name());
+        value.write_var(changes.inserted);
+        value.write_var(changes.deleted);
+        value.write_string(&changes.excerpt);
+        db.upsert(&key_audit(oid, time, n), &value)?;
+        n += 1;
+    }
+    Ok(())
+}
+
+/// Returns audit records of a document with a given `oid`, written within a given `time_range`.
+pub(crate) fn records<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    time_range: Range<SystemTime>,
+) -> Result<Vec<AuditRecord>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let start = key_audit_start(oid, clock::millis(time_range.start));
+    let end = key_audit_start(oid, clock::millis(time_range.end));
+    let mut records = Vec::new();
+    for e in db.iter_range(&start, &end)? {
+        if e.key() >= end.as_ref() {
+            break;
+        }
+        let time = match parse_key(e.key()) {
+            Some(ParsedKey::Audit { time, .. }) => time,
+            _ => return Err(Error::CorruptedValue),
+        };
+        records.push(decode(time, e.value())?);
+    }
+    Ok(records)
+}
+
+/// Removes audit records of a document with a given `oid`, written before a given `cutoff` time.
+/// Returns the number of removed records.
+pub(crate) fn prune<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    cutoff: SystemTime,
+) -> Result<u32, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let end = key_audit_start(oid, clock::millis(cutoff));
+    // keys of records written at the cutoff time are greater than `end`
+    let removed = db.remove_range(&key_audit_start(oid, 0), &end)?;
+    Ok(removed)
+}
+
+fn decode(time: u64, value: &[u8]) -> Result<AuditRecord, Error> {
+    let mut cursor = Cursor::new(value);
+    let origin = cursor.read_buf()?.to_vec();
+    let root = cursor.read_string()?.to_string();
+    let inserted = cursor.read_var()?;
+    let deleted = cursor.read_var()?;
+    let excerpt = cursor.read_string()?.to_string();
+    Ok(AuditRecord {
+        time: clock::system_time(time),
+        origin,
+        root,
+        inserted,
+        deleted,
+        excerpt,
+    })
+}
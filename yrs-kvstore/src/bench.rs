@@ -0,0 +1,338 @@
+//! Benchmark scenarios and workload utilities shared by benchmarks and examples of all backends,
+//! so that their results can be compared with each other. Available with `bench` feature enabled.
+//!
+//! Backends expose their stores to the scenarios by implementing [BenchStore], which maps every
+//! operation onto a separate committed transaction:
+//!
+//! ```rust,ignore
+//! struct Store(RocksDBDocStore);
+//!
+//! impl BenchStore for Store {
+//!     const NAME: &'static str = "rocksdb";
+//!
+//!     fn push_update(&self, name: &str, update: &[u8]) -> Result<u32, Error> {
+//!         Ok(self.0.push_update(name, update)?.seq)
+//!     }
+//!     // ...
+//! }
+//!
+//! let trace = load_trace("editing-trace.bin");
+//! bench::load_doc(c, &store, &trace);
+//! ```
+//!
+//! Scenarios replay a text editing trace, which is either read from a file (see [read_trace]) or
+//! generated (see [generate_trace]) when the file is not available. The same trace can be
+//! replayed against a single transaction of any [DocOps] implementation with [replay_updates].
+
+use crate::error::Error;
+use crate::{DocOps, KVStore};
+use criterion::{BatchSize, BenchmarkId, Criterion};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use yrs::encoding::read::{Cursor, Read};
+use yrs::updates::decoder::DecoderV1;
+use yrs::{Doc, ReadTxn, StateVector, Text, TextRef, Transact, TransactionMut};
+
+/// Numbers of pending updates that [load_doc] and [flush_doc] scenarios are measured with.
+pub const PENDING_UPDATES: [usize; 4] = [1, 64, 512, 4096];
+
+/// Number of updates after which [mixed_workload] merges pending updates into document state.
+pub const FLUSH_EVERY: u32 = 128;
+
+/// Store operations used by benchmark scenarios. Every call is expected to run within its own
+/// transaction, which is committed before the call returns.
+pub trait BenchStore {
+    /// Name of the backend, used to identify its results.
+    const NAME: &'static str;
+
+    /// See [DocOps::push_update](crate::DocOps::push_update). Returns update sequence number.
+    fn push_update(&self, name: &str, update: &[u8]) -> Result<u32, Error>;
+
+    /// See [DocOps::flush_doc](crate::DocOps::flush_doc).
+    fn flush_doc(&self, name: &str) -> Result<(), Error>;
+
+    /// See [DocOps::load_doc](crate::DocOps::load_doc).
+    fn load_doc(&self, name: &str, txn: &mut TransactionMut) -> Result<bool, Error>;
+
+    /// See [DocOps::get_diff](crate::DocOps::get_diff).
+    fn get_diff(&self, name: &str, sv: &StateVector) -> Result<Option<Vec<u8>>, Error>;
+}
+
+/// Single operation of a text editing trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextOp {
+    /// Inserts a string at a given index.
+    Insert(u32, String),
+    /// Removes a given number of characters starting at a given index.
+    Delete(u32, u32),
+}
+
+impl TextOp {
+    /// Applies current operation to a given `text`.
+    pub fn apply(&self, text: &TextRef, txn: &mut TransactionMut) {
+        match self {
+            TextOp::Insert(idx, txt) => text.insert(txn, *idx, txt),
+            TextOp::Delete(idx, len) => text.remove_range(txn, *idx, *len),
+        }
+    }
+}
+
+/// Reads text editing trace from a file under given path, i.e. `editing-trace.bin`.
+pub fn read_trace(fpath: &str) -> std::io::Result<Vec<TextOp>> {
+    let buf = std::fs::read(fpath)?;
+    let mut decoder = DecoderV1::new(Cursor::new(buf.as_slice()));
+    let len: usize = decoder.read_var().unwrap();
+    let mut result = Vec::with_capacity(len);
+    for _ in 0..len {
+        let op = match decoder.read_var().unwrap() {
+            1u32 => {
+                let idx = decoder.read_var().unwrap();
+                let chunk = decoder.read_string().unwrap();
+                TextOp::Insert(idx, chunk.to_string())
+            }
+            2u32 => {
+                let idx = decoder.read_var().unwrap();
+                let len = decoder.read_var().unwrap();
+                TextOp::Delete(idx, len)
+            }
+            other => panic!("unrecognized TextOp tag type: {}", other),
+        };
+        result.push(op);
+    }
+    Ok(result)
+}
+
+/// Generates a pseudo-random text editing trace of a given length. Generated trace resembles
+/// human typing: mostly short insertions close to the previous edit, mixed with occasional
+/// deletions and jumps to another position. The same `seed` always produces the same trace.
+pub fn generate_trace(len: usize, seed: u64) -> Vec<TextOp> {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz      \n";
+    let mut rng = XorShift(seed | 1);
+    let mut text_len = 0u32;
+    let mut cursor = 0u32;
+    let mut result = Vec::with_capacity(len);
+    for _ in 0..len {
+        if rng.next() % 100 < 5 {
+            // jump to a random position
+            cursor = (rng.next() % (text_len as u64 + 1)) as u32;
+        }
+        if text_len > 0 && rng.next() % 100 < 15 {
+            let idx = cursor.min(text_len - 1);
+            let len = (1 + rng.next() % 4).min((text_len - idx) as u64) as u32;
+            result.push(TextOp::Delete(idx, len));
+            text_len -= len;
+            cursor = idx;
+        } else {
+            let len = 1 + rng.next() % 8;
+            let chunk: String = (0..len)
+                .map(|_| ALPHABET[(rng.next() % ALPHABET.len() as u64) as usize] as char)
+                .collect();
+            result.push(TextOp::Insert(cursor, chunk));
+            text_len += len as u32;
+            cursor += len as u32;
+        }
+    }
+    result
+}
+
+/// Reads text editing trace from a file under given path or - if it doesn't exist - generates
+/// one of the same size as the editing trace shipped with this repository.
+pub fn load_trace(fpath: &str) -> Vec<TextOp> {
+    match read_trace(fpath) {
+        Ok(trace) => trace,
+        Err(_) => generate_trace(260_000, 0x5eed),
+    }
+}
+
+/// Applies all operations of a given trace to a `doc`.
+pub fn apply_ops(doc: &Doc, ops: &[TextOp]) {
+    let text = doc.get_or_insert_text("text");
+    for op in ops.iter() {
+        op.apply(&text, &mut doc.transact_mut());
+    }
+}
+
+/// Applies operations of a given trace to a new document, one transaction per operation, and
+/// returns the updates produced by each of them.
+pub fn trace_updates(ops: &[TextOp]) -> Vec<Vec<u8>> {
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    ops.iter()
+        .map(|op| {
+            let mut txn = doc.transact_mut();
+            op.apply(&text, &mut txn);
+            txn.encode_update_v1()
+        })
+        .collect()
+}
+
+/// Pushes given `updates` into a document with a given `name` within a single transaction of
+/// `db`, merging pending updates into document state every `flush_every` updates (if set).
+/// Returns the number of performed flushes.
+pub fn replay_updates<'a, DB: DocOps<'a>>(
+    db: &DB,
+    name: &str,
+    updates: &[Vec<u8>],
+    flush_every: Option<u32>,
+) -> Result<u32, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let mut flushes = 0;
+    for update in updates.iter() {
+        let seq_nr = db.push_update(name, update)?.seq;
+        if let Some(flush_every) = flush_every {
+            if seq_nr % flush_every == 0 {
+                db.flush_doc(name)?;
+                flushes += 1;
+            }
+        }
+    }
+    Ok(flushes)
+}
+
+/// Measures [DocOps::load_doc](crate::DocOps::load_doc) of documents with
+/// [PENDING_UPDATES] updates pending.
+pub fn load_doc<S: BenchStore>(c: &mut Criterion, store: &S, trace: &[TextOp]) {
+    let max = PENDING_UPDATES[PENDING_UPDATES.len() - 1];
+    let updates = trace_updates(&trace[..max.min(trace.len())]);
+    let mut group = c.benchmark_group("load_doc");
+    for n in PENDING_UPDATES {
+        let name = unique_name("load_doc");
+        for update in updates.iter().take(n) {
+            store.push_update(&name, update).unwrap();
+        }
+        group.bench_with_input(BenchmarkId::new(S::NAME, n), &name, |b, name| {
+            b.iter(|| {
+                let doc = Doc::new();
+                assert!(store.load_doc(name, &mut doc.transact_mut()).unwrap());
+            });
+        });
+    }
+    group.finish();
+}
+
+/// Measures [DocOps::flush_doc](crate::DocOps::flush_doc) of documents with
+/// [PENDING_UPDATES] updates pending. Pushing updates is not included in the measurements.
+pub fn flush_doc<S: BenchStore>(c: &mut Criterion, store: &S, trace: &[TextOp]) {
+    let max = PENDING_UPDATES[PENDING_UPDATES.len() - 1];
+    let updates = trace_updates(&trace[..max.min(trace.len())]);
+    let mut group = c.benchmark_group("flush_doc");
+    for n in PENDING_UPDATES {
+        group.bench_with_input(BenchmarkId::new(S::NAME, n), &n, |b, n| {
+            b.iter_batched(
+                || {
+                    let name = unique_name("flush_doc");
+                    for update in updates.iter().take(*n) {
+                        store.push_update(&name, update).unwrap();
+                    }
+                    name
+                },
+                |name| store.flush_doc(&name).unwrap(),
+                BatchSize::PerIteration,
+            );
+        });
+    }
+    group.finish();
+}
+
+/// Measures [DocOps::get_diff](crate::DocOps::get_diff) against the state vector from the middle
+/// of document history, both with all updates pending and with updates merged into document
+/// state.
+pub fn get_diff<S: BenchStore>(c: &mut Criterion, store: &S, trace: &[TextOp]) {
+    let max = PENDING_UPDATES[PENDING_UPDATES.len() - 1];
+    let ops = &trace[..max.min(trace.len())];
+    let mid = {
+        let doc = Doc::new();
+        apply_ops(&doc, &ops[..ops.len() / 2]);
+        let sv = doc.transact().state_vector();
+        sv
+    };
+    let updates = trace_updates(ops);
+    let mut group = c.benchmark_group("get_diff");
+    for flushed in [false, true] {
+        let name = unique_name("get_diff");
+        for update in updates.iter() {
+            store.push_update(&name, update).unwrap();
+        }
+        if flushed {
+            store.flush_doc(&name).unwrap();
+        }
+        let id = if flushed { "flushed" } else { "pending" };
+        group.bench_with_input(BenchmarkId::new(S::NAME, id), &name, |b, name| {
+            b.iter(|| store.get_diff(name, &mid).unwrap().unwrap());
+        });
+    }
+    group.finish();
+}
+
+/// Replays a given trace, pushing every update in a separate transaction and merging pending
+/// updates into document state every [FLUSH_EVERY] updates.
+pub fn mixed_workload<S: BenchStore>(c: &mut Criterion, store: &S, trace: &[TextOp]) {
+    let updates = trace_updates(trace);
+    let mut group = c.benchmark_group("mixed workload");
+    group.bench_with_input(
+        BenchmarkId::new(S::NAME, updates.len()),
+        &updates,
+        |b, updates| {
+            b.iter(|| {
+                let name = unique_name("mixed");
+                for update in updates.iter() {
+                    let seq_nr = store.push_update(&name, update).unwrap();
+                    if seq_nr % FLUSH_EVERY == 0 {
+                        store.flush_doc(&name).unwrap();
+                    }
+                }
+            });
+        },
+    );
+    group.finish();
+}
+
+/// Directory removed both when it's created and when it's dropped, so that benchmarks and
+/// examples always start from an empty database and don't leave it behind.
+pub struct Cleaner(&'static str);
+
+impl Cleaner {
+    /// Removes a directory under a given path, if it exists, and returns a guard removing it
+    /// again once dropped.
+    pub fn new(dir: &'static str) -> Self {
+        Self::cleanup(dir);
+        Cleaner(dir)
+    }
+
+    /// Returns path of the guarded directory.
+    pub fn dir(&self) -> &str {
+        self.0
+    }
+
+    fn cleanup(dir: &str) {
+        if std::fs::remove_dir_all(dir).is_err() {
+            // if dir doesn't exists, ignore
+        }
+    }
+}
+
+impl Drop for Cleaner {
+    fn drop(&mut self) {
+        Self::cleanup(self.dir());
+    }
+}
+
+/// Returns a document name that has not been used by any scenario yet.
+fn unique_name(prefix: &str) -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    format!("{}-{}", prefix, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Minimal xorshift pseudo-random number generator, so that generated traces don't depend on
+/// external crates.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
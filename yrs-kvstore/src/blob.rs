@@ -0,0 +1,331 @@
+//! Storage of large document states outside of the key-value store.
+//!
+//! Key-value stores based on LSM trees rewrite stored values every time they are compacted, so
+//! multi-megabyte document states dominate their write amplification. With a [BlobStore]
+//! installed by [ConfiguredStore::with_blob_store](crate::config::ConfiguredStore::with_blob_store),
+//! document states of at least [StoreConfig::blob_threshold](crate::config::StoreConfig::blob_threshold)
+//! bytes are written into it as blobs, while the store keeps only a small pointer record (see
+//! [format](crate::format#values)) in their place. Reads resolve pointers transparently:
+//!
+//! ```rust,ignore
+//! let blobs = Arc::new(FsBlobStore::new("my-blobs-path")?);
+//! let db_txn = ConfiguredStore::new(RocksDBStore::from(db.transaction()), StoreConfig::DEFAULT)
+//!     .with_blob_store(blobs);
+//! db_txn.flush_doc("my-doc-name")?; // merged state may be written as a blob
+//!
+//! // reclaim blobs no longer referenced by any document, written at least an hour ago
+//! let removed = db_txn.gc_blobs(Duration::from_secs(3600))?;
+//! ```
+//!
+//! Blobs are written before the pointers referring to them and they are never modified, so
+//! a transaction which fails or is rolled back leaves at most an unreferenced blob behind.
+//! Likewise, blobs of document states replaced by [DocOps::flush_doc] or removed by
+//! [DocOps::clear_doc] are not removed by these writes, since the transaction making them may
+//! still be rolled back. All unreferenced blobs are reclaimed by [DocOps::gc_blobs], which skips
+//! blobs younger than a given age, so that it doesn't remove the ones written by transactions
+//! still in progress.
+//!
+//! A blob store must not be shared by different stores (i.e. namespaces of the same database), as
+//! [DocOps::gc_blobs] only sees pointers of the store it has been called on. Blobs which cannot
+//! be read are reported with [Error::BlobUnavailable], which is distinct from a document not being
+//! found.
+
+use crate::clock::{self, Clock, SystemClock};
+use crate::config::crc32;
+use crate::error::Error;
+use crate::format::{
+    decode_timestamp, encode_timestamp, BLOB_ID_LEN, BLOB_POINTER_LEN, BLOB_POINTER_TAG, OID_LEN,
+    TIMESTAMP_LEN,
+};
+use crate::keys::{KEYSPACE_DOC, SUB_DOC, V1};
+use crate::{DocOps, KVStore};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::fmt::{Display, Formatter};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, SystemTime};
+
+/// Identifier of a blob: the time it has been created at, as an u64 number of milliseconds since
+/// UNIX epoch, followed by the identifier of the process which created it and a number unique
+/// within that process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlobId(pub [u8; BLOB_ID_LEN]);
+
+impl BlobId {
+    /// Creates a new unique identifier.
+    pub fn new() -> Self {
+        Self::created(SystemClock.now())
+    }
+
+    /// Creates a new unique identifier of a blob created at a given number of milliseconds since
+    /// UNIX epoch, as read from [DocOps::clock].
+    pub(crate) fn created(millis: u64) -> Self {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        let mut id = [0; BLOB_ID_LEN];
+        id[..TIMESTAMP_LEN].copy_from_slice(&encode_timestamp(millis));
+        id[8..12].copy_from_slice(&std::process::id().to_be_bytes());
+        id[12..].copy_from_slice(&COUNTER.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+        BlobId(id)
+    }
+
+    /// Returns the time current blob has been created at.
+    pub fn created_at(&self) -> SystemTime {
+        clock::system_time(self.created_millis())
+    }
+
+    fn created_millis(&self) -> u64 {
+        decode_timestamp(&self.0[..TIMESTAMP_LEN]).unwrap()
+    }
+
+    /// Parses an identifier from its hexadecimal representation (see [Display]).
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != BLOB_ID_LEN * 2 {
+            return None;
+        }
+        let mut id = [0; BLOB_ID_LEN];
+        for (i, b) in id.iter_mut().enumerate() {
+            *b = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+        }
+        Some(BlobId(id))
+    }
+}
+
+impl Default for BlobId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display for BlobId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for b in self.0.iter() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+/// Storage of blobs written by [DocOps] in place of large document states. Blobs are written
+/// once under unique identifiers and never modified.
+pub trait BlobStore: Send + Sync {
+    /// Writes a blob with a given `id`.
+    fn put(&self, id: &BlobId, data: &[u8]) -> Result<(), Error>;
+
+    /// Reads a blob with a given `id`, or returns [None] if it doesn't exist.
+    fn get(&self, id: &BlobId) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Removes a blob with a given `id`. Removing blobs which don't exist is not an error.
+    fn remove(&self, id: &BlobId) -> Result<(), Error>;
+
+    /// Returns identifiers of all stored blobs.
+    fn list(&self) -> Result<Vec<BlobId>, Error>;
+}
+
+/// [BlobStore] keeping every blob in a separate file within a directory, named after the
+/// hexadecimal representation of its identifier.
+#[derive(Debug, Clone)]
+pub struct FsBlobStore {
+    dir: PathBuf,
+}
+
+impl FsBlobStore {
+    /// Creates a store keeping blobs within a given `dir`, creating it if it doesn't exist.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Result<Self, Error> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(FsBlobStore { dir })
+    }
+
+    fn path(&self, id: &BlobId) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+}
+
+impl BlobStore for FsBlobStore {
+    fn put(&self, id: &BlobId, data: &[u8]) -> Result<(), Error> {
+        // blobs are renamed into place, so that partially written files are never read
+        let path = self.path(id);
+        let tmp = path.with_extension("tmp");
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    fn get(&self, id: &BlobId) -> Result<Option<Vec<u8>>, Error> {
+        match std::fs::read(self.path(id)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn remove(&self, id: &BlobId) -> Result<(), Error> {
+        match std::fs::remove_file(self.path(id)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<BlobId>, Error> {
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if let Some(id) = entry.file_name().to_str().and_then(BlobId::from_hex) {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+}
+
+/// Pointer record stored in place of a value written as a blob.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pointer {
+    id: BlobId,
+    len: u64,
+    checksum: u32,
+}
+
+impl Pointer {
+    /// Parses a pointer record from a stored `value`, or returns [None] if the value is stored
+    /// inline.
+    ///
+    /// Inline values framed by codecs other than the raw one start with a byte of codec flags,
+    /// which is never [BLOB_POINTER_TAG]. Raw lib0 v1 document states starting with it encode
+    /// over a hundred clients, so they are much longer than [BLOB_POINTER_LEN].
+    fn parse(value: &[u8]) -> Option<Self> {
+        match value {
+            [BLOB_POINTER_TAG, rest @ ..] if value.len() == BLOB_POINTER_LEN => {
+                let (id, rest) = rest.split_at(BLOB_ID_LEN);
+                let (len, checksum) = rest.split_at(8);
+                Some(Pointer {
+                    id: BlobId(id.try_into().unwrap()),
+                    len: u64::from_be_bytes(len.try_into().unwrap()),
+                    checksum: u32::from_be_bytes(checksum.try_into().unwrap()),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn encode(&self) -> [u8; BLOB_POINTER_LEN] {
+        let mut buf = [0; BLOB_POINTER_LEN];
+        buf[0] = BLOB_POINTER_TAG;
+        buf[1..1 + BLOB_ID_LEN].copy_from_slice(&self.id.0);
+        buf[1 + BLOB_ID_LEN..BLOB_POINTER_LEN - 4].copy_from_slice(&self.len.to_be_bytes());
+        buf[BLOB_POINTER_LEN - 4..].copy_from_slice(&self.checksum.to_be_bytes());
+        buf
+    }
+}
+
+/// Writes an already encoded document state `value` under a given `key`, storing it as a blob if
+/// a blob store is installed and the value is not smaller than the configured threshold.
+pub(crate) fn put_state<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    key: &[u8],
+    value: &[u8],
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    match db.blob_store() {
+        Some(blobs) if value.len() >= db.config().blob_threshold => {
+            let pointer = Pointer {
+                id: BlobId::created(db.clock().now()),
+                len: value.len() as u64,
+                checksum: crc32(value),
+            };
+            blobs.put(&pointer.id, value)?;
+            db.upsert(key, &pointer.encode())?;
+        }
+        _ => db.upsert(key, value)?,
+    }
+    Ok(())
+}
+
+/// Resolves a stored document state `value`: returns the contents of the blob it points to, or
+/// the value itself if it's stored inline.
+pub(crate) fn resolve<'a, 'v, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    value: &'v [u8],
+) -> Result<Cow<'v, [u8]>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let pointer = match Pointer::parse(value) {
+        Some(pointer) => pointer,
+        None => return Ok(Cow::Borrowed(value)),
+    };
+    let blobs = db
+        .blob_store()
+        .ok_or(Error::BlobUnavailable { id: pointer.id })?;
+    match blobs.get(&pointer.id)? {
+        Some(data) if data.len() as u64 == pointer.len && crc32(&data) == pointer.checksum => {
+            Ok(Cow::Owned(data))
+        }
+        Some(_) => Err(Error::CorruptedValue),
+        None => Err(Error::BlobUnavailable { id: pointer.id }),
+    }
+}
+
+/// Returns the size of a stored document state `value`: the size of the blob it points to, or
+/// the size of the value itself if it's stored inline.
+pub(crate) fn stored_len(value: &[u8]) -> u64 {
+    match Pointer::parse(value) {
+        Some(pointer) => pointer.len,
+        None => value.len() as u64,
+    }
+}
+
+/// Removes blobs not referenced by any document state, created at least `min_age` ago. Returns
+/// the number of removed blobs.
+pub(crate) fn gc<'a, DB: DocOps<'a> + ?Sized>(db: &DB, min_age: Duration) -> Result<u32, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let blobs = match db.blob_store() {
+        Some(blobs) => blobs,
+        None => return Ok(0),
+    };
+    // list blobs first, so that blobs written after the scan of pointers has started are not
+    // mistaken for unreferenced ones
+    let listed = blobs.list()?;
+    let mut states = Vec::new();
+    let start = [V1, KEYSPACE_DOC];
+    let end = [V1, KEYSPACE_DOC + 1];
+    db.iter_keys_range(&start, &end, &mut |key, len| {
+        if key >= &end[..] {
+            return false;
+        }
+        if key.len() == 3 + OID_LEN && key[2 + OID_LEN] == SUB_DOC && len == BLOB_POINTER_LEN {
+            states.push(key.to_vec());
+        }
+        true
+    })?;
+    let mut referenced = HashSet::new();
+    for key in states {
+        if let Some(value) = db.get(&key)? {
+            if let Some(pointer) = Pointer::parse(value.as_ref()) {
+                referenced.insert(pointer.id);
+            }
+        }
+    }
+    let now = db.clock().now();
+    let mut removed = 0;
+    for id in listed {
+        let old_enough = match now.checked_sub(id.created_millis()) {
+            Some(age) => age >= min_age.as_millis() as u64,
+            None => false,
+        };
+        if old_enough && !referenced.contains(&id) {
+            blobs.remove(&id)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
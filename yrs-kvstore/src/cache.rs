@@ -0,0 +1,419 @@
+//! In-process cache of loaded documents, shared between database transactions.
+//!
+//! [DocCache] keeps up to a configured number of most recently used documents in memory. It's
+//! consulted by [CachedStore], which wraps any [DocOps] implementation: the first
+//! [CachedStore::with_doc] or [CachedStore::get_doc] call for a given document loads it from the
+//! store, while subsequent calls - also from other transactions and threads - reuse the same
+//! in-memory [Doc].
+//!
+//! ```rust,ignore
+//! let cache = DocCache::new(1024);
+//!
+//! let db_txn = CachedStore::new(RocksDBStore::from(db.transaction()), &cache);
+//! let preview = db_txn.with_doc("my-doc-name", |doc| preview(doc))?;
+//! ```
+//!
+//! # Staleness
+//!
+//! The cache is kept up to date only with writes performed through [CachedStore]:
+//!
+//! - [DocOps::push_update] applies the update to the cached document right away, even before
+//!   the database transaction is committed. If the transaction is rolled back afterwards, the
+//!   document must be removed from the cache with [DocCache::invalidate].
+//! - Other writes that change document contents (i.e. [DocOps::insert_doc],
+//!   [DocOps::import_updates], [DocOps::clear_doc], [DocOps::archive_doc] or
+//!   [DocOps::compact_client_ids]) remove the document from the cache.
+//!
+//! Writes performed directly through the underlying store, by other processes or by other
+//! [DocCache] instances are not visible until the document is evicted or invalidated.
+//!
+//! Cached documents are shared: they must be treated as read-only by the callers.
+
+use crate::blob::BlobStore;
+use crate::capabilities::Capabilities;
+use crate::client_ids::ClientCompaction;
+use crate::clock::Clock;
+use crate::config::{StoreConfig, UpdateValidator};
+use crate::error::Error;
+use crate::guard::ClearGuard;
+use crate::keys::OID;
+use crate::refs::RefPolicy;
+use crate::usage::TenantExtractor;
+use crate::{
+    ClearReport, DocOps, FlushOutcome, HealthHint, KVStore, PushReceipt, ReadIsolation, ScanMode,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Deref;
+use std::sync::Mutex;
+use std::time::Duration;
+use yrs::updates::decoder::Decode;
+use yrs::{Doc, ReadTxn, Transact, Update};
+
+/// Cache of up to a given number of most recently used documents. See
+/// [module documentation](self) for details.
+pub struct DocCache {
+    capacity: usize,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    /// Cached documents together with the tick of their last use.
+    docs: HashMap<Box<[u8]>, (Doc, u64)>,
+    /// Names of cached documents ordered by the tick of their last use.
+    lru: BTreeMap<u64, Box<[u8]>>,
+    tick: u64,
+}
+
+impl CacheState {
+    fn touch(&mut self, name: &[u8]) -> Option<Doc> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (doc, last_used) = self.docs.get_mut(name)?;
+        let name = self.lru.remove(last_used).unwrap();
+        *last_used = tick;
+        self.lru.insert(tick, name);
+        Some(doc.clone())
+    }
+
+    fn remove(&mut self, name: &[u8]) -> Option<Doc> {
+        let (doc, last_used) = self.docs.remove(name)?;
+        self.lru.remove(&last_used);
+        Some(doc)
+    }
+}
+
+impl DocCache {
+    /// Creates a new cache keeping up to `capacity` documents. Least recently used documents are
+    /// evicted once capacity is reached.
+    pub fn new(capacity: usize) -> Self {
+        DocCache {
+            capacity: capacity.max(1),
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Returns a handle to the cached document with a given `name`, marking it as recently used.
+    pub fn get<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Option<Doc> {
+        self.state.lock().unwrap().touch(name.as_ref())
+    }
+
+    /// Puts a given `doc` into the cache under provided `name`, replacing any document cached
+    /// under the same name and evicting the least recently used one if necessary.
+    pub fn insert<K: AsRef<[u8]> + ?Sized>(&self, name: &K, doc: Doc) {
+        let mut state = self.state.lock().unwrap();
+        let name: Box<[u8]> = name.as_ref().into();
+        state.remove(&name);
+        while state.docs.len() >= self.capacity {
+            let evicted = match state.lru.values().next() {
+                Some(name) => name.clone(),
+                None => break,
+            };
+            state.remove(&evicted);
+        }
+        state.tick += 1;
+        let tick = state.tick;
+        state.lru.insert(tick, name.clone());
+        state.docs.insert(name, (doc, tick));
+    }
+
+    /// Removes the document with a given `name` from the cache, so that the next read loads it
+    /// from the store again. Returns `true` if document was cached.
+    pub fn invalidate<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> bool {
+        self.state.lock().unwrap().remove(name.as_ref()).is_some()
+    }
+
+    /// Removes all documents from the cache.
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.docs.clear();
+        state.lru.clear();
+    }
+
+    /// Returns the number of cached documents.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().docs.len()
+    }
+
+    /// Checks if there are no cached documents.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Wrapper around any [DocOps] implementation, which consults and updates a [DocCache]. See
+/// [module documentation](self) for details.
+pub struct CachedStore<'c, S> {
+    store: S,
+    cache: &'c DocCache,
+}
+
+impl<'c, S> CachedStore<'c, S> {
+    /// Wraps a given `store`, making it use provided `cache`.
+    pub fn new(store: S, cache: &'c DocCache) -> Self {
+        CachedStore { store, cache }
+    }
+
+    /// Returns the cache used by current store.
+    pub fn cache(&self) -> &'c DocCache {
+        self.cache
+    }
+
+    /// Unwraps the underlying store, i.e. in order to commit it.
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+}
+
+impl<'a, 'c, S: DocOps<'a>> CachedStore<'c, S>
+where
+    Error: From<S::Error>,
+{
+    /// Returns a handle to the document stored under a given `name`, loading it from the store
+    /// only if it's not cached yet. Returns `None` if there was no document stored under that
+    /// name.
+    pub fn get_doc<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<Option<Doc>, Error> {
+        if let Some(doc) = self.cache.get(name) {
+            return Ok(Some(doc));
+        }
+        let doc = Doc::new();
+        if self.store.load_doc(name, &mut doc.transact_mut())? {
+            self.cache.insert(name, doc.clone());
+            Ok(Some(doc))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<'c, S> Deref for CachedStore<'c, S> {
+    type Target = S;
+
+    fn deref(&self) -> &Self::Target {
+        &self.store
+    }
+}
+
+impl<'a, 'c, S: KVStore<'a>> KVStore<'a> for CachedStore<'c, S> {
+    type Error = S::Error;
+    type Cursor = S::Cursor;
+    type Entry = S::Entry;
+    type Return = S::Return;
+
+    const CAPABILITIES: Capabilities = S::CAPABILITIES;
+
+    #[inline]
+    fn get(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+        self.store.get(key)
+    }
+
+    #[inline]
+    fn get_for_update(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+        self.store.get_for_update(key)
+    }
+
+    #[inline]
+    fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, Self::Error> {
+        self.store.get_many(keys)
+    }
+
+    #[inline]
+    fn upsert(&self, key: &[u8], value: &[u8]) -> Result<(), Self::Error> {
+        self.store.upsert(key, value)
+    }
+
+    #[inline]
+    fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+        self.store.remove(key)
+    }
+
+    #[inline]
+    fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<u32, Self::Error> {
+        self.store.remove_range(from, to)
+    }
+
+    #[inline]
+    fn iter_range(&self, from: &[u8], to: &[u8]) -> Result<Self::Cursor, Self::Error> {
+        self.store.iter_range(from, to)
+    }
+
+    #[inline]
+    fn iter_range_with(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        mode: ScanMode,
+    ) -> Result<Self::Cursor, Self::Error> {
+        self.store.iter_range_with(from, to, mode)
+    }
+
+    #[inline]
+    fn iter_keys_range(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        f: &mut dyn FnMut(&[u8], usize) -> bool,
+    ) -> Result<(), Self::Error> {
+        self.store.iter_keys_range(from, to, f)
+    }
+
+    #[inline]
+    fn iter_range_rev(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        f: &mut dyn FnMut(&[u8], &[u8]) -> bool,
+    ) -> Result<(), Self::Error> {
+        self.store.iter_range_rev(from, to, f)
+    }
+
+    #[inline]
+    fn peek_back(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+        self.store.peek_back(key)
+    }
+
+    #[inline]
+    fn seek(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+        self.store.seek(key)
+    }
+
+    fn read_isolation(&self) -> ReadIsolation {
+        self.store.read_isolation()
+    }
+
+    fn health_hint(&self) -> Option<HealthHint> {
+        self.store.health_hint()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.store.is_read_only()
+    }
+}
+
+impl<'a, 'c, S: DocOps<'a>> DocOps<'a> for CachedStore<'c, S>
+where
+    Error: From<S::Error>,
+{
+    fn config(&self) -> &StoreConfig {
+        self.store.config()
+    }
+
+    fn update_validator(&self) -> Option<&dyn UpdateValidator> {
+        self.store.update_validator()
+    }
+
+    fn tenant_extractor(&self) -> Option<&dyn TenantExtractor> {
+        self.store.tenant_extractor()
+    }
+
+    fn blob_store(&self) -> Option<&dyn BlobStore> {
+        self.store.blob_store()
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.store.clock()
+    }
+
+    fn insert_doc_raw_v1(
+        &self,
+        name: &[u8],
+        doc_state_v1: &[u8],
+        doc_sv_v1: &[u8],
+    ) -> Result<(), Error> {
+        self.cache.invalidate(name);
+        self.store.insert_doc_raw_v1(name, doc_state_v1, doc_sv_v1)
+    }
+
+    fn with_doc<K, F, R>(&self, name: &K, f: F) -> Result<Option<R>, Error>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        F: FnOnce(&Doc) -> R,
+    {
+        Ok(self.get_doc(name)?.map(|doc| f(&doc)))
+    }
+
+    fn push_update_with<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        update: &[u8],
+        scratch: &mut Vec<u8>,
+    ) -> Result<PushReceipt, Error> {
+        let receipt = self.store.push_update_with(name, update, scratch)?;
+        if let Some(doc) = self.cache.get(name) {
+            let update = Update::decode_v1(update)?;
+            let mut txn = doc.transact_mut();
+            txn.apply_update(update);
+            // updates depending on changes missing from the cached document are kept pending
+            // instead of being integrated, so the document is reloaded from the store instead
+            let store = txn.store();
+            let pending = store.pending_update().is_some() || store.pending_ds().is_some();
+            drop(txn);
+            if pending {
+                self.cache.invalidate(name);
+            }
+        }
+        Ok(receipt)
+    }
+
+    fn import_updates_with<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        updates: &[(u32, &[u8])],
+        flush: Option<yrs::Options>,
+    ) -> Result<Option<FlushOutcome>, Error> {
+        self.cache.invalidate(name);
+        self.store.import_updates_with(name, updates, flush)
+    }
+
+    fn clear_doc<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<ClearReport, Error> {
+        self.cache.invalidate(name);
+        self.store.clear_doc(name)
+    }
+
+    fn clear_doc_if<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        guard: ClearGuard,
+    ) -> Result<ClearReport, Error> {
+        self.cache.invalidate(name);
+        self.store.clear_doc_if(name, guard)
+    }
+
+    fn clear_doc_with<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        policy: RefPolicy,
+    ) -> Result<ClearReport, Error> {
+        if policy == RefPolicy::Cascade {
+            // documents cleared by cascade are not known upfront
+            self.cache.clear();
+        } else {
+            self.cache.invalidate(name);
+        }
+        self.store.clear_doc_with(name, policy)
+    }
+
+    fn archive_doc<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<bool, Error> {
+        self.cache.invalidate(name);
+        self.store.archive_doc(name)
+    }
+
+    fn compact_client_ids<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<ClientCompaction>, Error> {
+        self.cache.invalidate(name);
+        self.store.compact_client_ids(name)
+    }
+
+    fn adopt_orphan<K: AsRef<[u8]> + ?Sized>(&self, oid: OID, name: &K) -> Result<bool, Error> {
+        self.cache.invalidate(name);
+        self.store.adopt_orphan(oid, name)
+    }
+
+    fn purge_drafts(&self, older_than: Duration) -> Result<u32, Error> {
+        // purged drafts are not known upfront
+        self.cache.clear();
+        self.store.purge_drafts(older_than)
+    }
+}
@@ -0,0 +1,116 @@
+//! Optional features of [KVStore](crate::KVStore) implementations, declared with
+//! [KVStore::CAPABILITIES](crate::KVStore::CAPABILITIES).
+//!
+//! Apart from the required methods, a [KVStore](crate::KVStore) may provide faster variants of
+//! some operations - batched lookups, reverse scans, cursors positioned directly at a key.
+//! [DocOps](crate::DocOps) methods don't assume any of them: they check the [Capabilities]
+//! declared by a store and fall back to an implementation built on top of the required methods
+//! when a capability is missing, so stores which can't provide a feature never need to stub it
+//! out:
+//!
+//! | Capability          | Used for                                | Fallback                 |
+//! |---------------------|-----------------------------------------|--------------------------|
+//! | `BOUNDED_PEEK_BACK` | last update of a document on every push | reverse or forward scan  |
+//! | `REVERSE_ITER`      | as above, when peek_back isn't bounded  | forward scan             |
+//! | `MULTI_GET`         | resolving names of `load_docs`          | one `get` per key        |
+//! | `SNAPSHOTS`         | `read_snapshot`                         | best effort reads        |
+//! | `ATOMIC_COUNTERS`   | nothing yet                             | -                        |
+//!
+//! Capabilities are declared per type, so they're known at compile time. Applications can
+//! feature-detect them at runtime with [DocOps::capabilities](crate::DocOps::capabilities), which
+//! additionally reports [Capabilities::SNAPSHOTS] only when a given store instance actually reads
+//! from a snapshot:
+//!
+//! ```rust,ignore
+//! if db_txn.capabilities().contains(Capabilities::SNAPSHOTS) {
+//!     export_docs_consistent(&db_txn.read_snapshot(), names, &mut out)?;
+//! }
+//! ```
+//!
+//! Declared capabilities of a backend are verified by `conformance::capabilities`, available with
+//! `conformance-tests` feature enabled.
+
+use std::fmt::{Debug, Formatter};
+use std::ops::{BitOr, BitOrAssign};
+
+/// Set of optional features supported by a [KVStore](crate::KVStore). See
+/// [module documentation](self) for details.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// No optional features.
+    pub const NONE: Capabilities = Capabilities(0);
+    /// [KVStore::iter_range_rev](crate::KVStore::iter_range_rev) walks entries backwards natively,
+    /// instead of collecting the range first.
+    pub const REVERSE_ITER: Capabilities = Capabilities(1);
+    /// [KVStore::peek_back](crate::KVStore::peek_back) positions the cursor directly at a given
+    /// key, so its cost doesn't depend on the number of preceding entries.
+    pub const BOUNDED_PEEK_BACK: Capabilities = Capabilities(1 << 1);
+    /// [KVStore::get_many](crate::KVStore::get_many) looks up all keys in a single batch.
+    pub const MULTI_GET: Capabilities = Capabilities(1 << 2);
+    /// Store applies deltas to counters without reading them first, so concurrent increments
+    /// don't conflict. None of the bundled backends declares it.
+    pub const ATOMIC_COUNTERS: Capabilities = Capabilities(1 << 3);
+    /// Store can serve all reads of a transaction from a single point-in-time view of the
+    /// database, reported by [KVStore::read_isolation](crate::KVStore::read_isolation).
+    pub const SNAPSHOTS: Capabilities = Capabilities(1 << 4);
+
+    const NAMES: [(Capabilities, &'static str); 5] = [
+        (Capabilities::REVERSE_ITER, "REVERSE_ITER"),
+        (Capabilities::BOUNDED_PEEK_BACK, "BOUNDED_PEEK_BACK"),
+        (Capabilities::MULTI_GET, "MULTI_GET"),
+        (Capabilities::ATOMIC_COUNTERS, "ATOMIC_COUNTERS"),
+        (Capabilities::SNAPSHOTS, "SNAPSHOTS"),
+    ];
+
+    /// Returns a set of all capabilities present in either `self` or `other`. Unlike `|`, it can
+    /// be used to declare [KVStore::CAPABILITIES](crate::KVStore::CAPABILITIES).
+    pub const fn union(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+
+    /// Returns a set of capabilities present in `self` but not in `other`.
+    pub const fn difference(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & !other.0)
+    }
+
+    /// Checks if all of the `other` capabilities are present in current set.
+    pub const fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Checks if current set contains no capabilities.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the raw bits of current set.
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Capabilities;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        self.union(rhs)
+    }
+}
+
+impl BitOrAssign for Capabilities {
+    fn bitor_assign(&mut self, rhs: Self) {
+        *self = self.union(rhs)
+    }
+}
+
+impl Debug for Capabilities {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let names = Capabilities::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name);
+        f.debug_set().entries(names).finish()
+    }
+}
@@ -0,0 +1,173 @@
+//! Updates pushed to channels other than the default one. See
+//! [DocOps::push_update_channel](crate::DocOps::push_update_channel) and [format](crate::format)
+//! for their layout.
+
+use crate::blob;
+use crate::error::Error;
+use crate::format::OID_LEN;
+use crate::keys::{
+    key_channel_end, key_channel_start, key_channel_update, key_doc, key_state_vector, OID,
+};
+use crate::{
+    decode_update, insert_inner, last_in_range, load_doc, store_doc_options, update_clock, DocOps,
+    FlushOutcome, KVEntry, KVStore,
+};
+use std::time::Instant;
+use yrs::updates::encoder::Encode;
+use yrs::{Doc, ReadTxn, StateVector, Transact, TransactionMut};
+
+/// Reads the channel of a channel update entry from its key. Keys too short to contain a channel
+/// belong to the first one.
+fn channel_of(key: &[u8]) -> u8 {
+    // channel update key scheme: 01{oid:4}8{channel:1}{clock:4}0
+    key.get(3 + OID_LEN).copied().unwrap_or_default()
+}
+
+/// Returns the sequence number of the most recent update of a given `channel`, if there are any.
+pub(crate) fn last_seq<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    channel: u8,
+) -> Result<Option<u32>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let start = key_channel_update(oid, channel, 0);
+    let end = key_channel_update(oid, channel, u32::MAX);
+    match last_in_range(db, &start, &end)? {
+        Some(e) => Ok(Some(update_clock(e.key()))),
+        None => Ok(None),
+    }
+}
+
+/// Checks if a document with a given `oid` has any channel updates.
+pub(crate) fn has_updates<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<bool, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let start = key_channel_start(oid);
+    Ok(last_in_range(db, &start, &key_channel_end(oid))?.is_some())
+}
+
+/// Returns the most recent update entry of every channel of a document with a given `oid`. Each
+/// channel is looked up with a single [KVStore::peek_back] (if the store declares
+/// [BOUNDED_PEEK_BACK](crate::capabilities::Capabilities::BOUNDED_PEEK_BACK)), so the cost depends
+/// only on the number of channels in use.
+pub(crate) fn last_updates<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+) -> Result<Vec<DB::Entry>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let start = key_channel_start(oid);
+    let mut last = last_in_range(db, &start, &key_channel_end(oid))?;
+    let mut entries = Vec::new();
+    while let Some(e) = last.take() {
+        let channel = channel_of(e.key());
+        entries.push(e);
+        if channel > 0 {
+            let end = key_channel_update(oid, channel - 1, u32::MAX);
+            last = last_in_range(db, &start, &end)?;
+        }
+    }
+    Ok(entries)
+}
+
+/// Applies updates of all channels of a document with a given `oid` to a given `txn`, ordered
+/// by their channel and then by their sequence number. Returns the number of applied updates and
+/// the total size of keys and values read.
+pub(crate) fn apply_all<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    txn: &mut TransactionMut,
+) -> Result<(u32, u64), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let end = key_channel_end(oid);
+    let mut updates = 0;
+    let mut bytes = 0;
+    for e in db.iter_range(&key_channel_start(oid), &end)? {
+        let key = e.key();
+        if key >= end.as_ref() {
+            break;
+        }
+        let update = db.config().codec.decode(e.value())?;
+        txn.apply_update(decode_update(None, &update)?);
+        updates += 1;
+        bytes += (key.len() + e.value().len()) as u64;
+    }
+    Ok((updates, bytes))
+}
+
+/// Removes updates of all channels of a document with a given `oid`.
+pub(crate) fn delete_all<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    db.remove_range(&key_channel_start(oid), &key_channel_end(oid))?;
+    Ok(())
+}
+
+/// Merges updates of a given `channel` into the state of a document with a given `oid` and
+/// removes them, leaving pending updates of other channels intact. Returns `None` if there were
+/// no updates on that channel.
+///
+/// Written state is produced from the whole document, including updates which remain pending,
+/// so that updates of the flushed channel depending on them are not left out. Pending updates
+/// are integrated again by the following loads, which doesn't change the document.
+pub(crate) fn flush<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    channel: u8,
+    options: yrs::Options,
+) -> Result<Option<FlushOutcome>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let started = Instant::now();
+    let start = key_channel_update(oid, channel, 0);
+    let end = key_channel_update(oid, channel, u32::MAX);
+    let mut updates_folded = 0;
+    let mut bytes_before = 0;
+    for e in db.iter_range(&start, &end)? {
+        if e.key() > end.as_ref() {
+            break;
+        }
+        updates_folded += 1;
+        bytes_before += (e.key().len() + e.value().len()) as u64;
+    }
+    if updates_folded == 0 {
+        return Ok(None);
+    }
+    let doc = Doc::with_options(options);
+    load_doc(db, oid, &mut doc.transact_mut())?;
+    let txn = doc.transact();
+    let doc_state = txn.encode_state_as_update_v1(&StateVector::default());
+    let state_vec = txn.state_vector().encode_v1();
+    drop(txn);
+    let codec = &db.config().codec;
+    let doc_state = codec.encode(&doc_state)?;
+    let state_vec = codec.encode(&state_vec)?;
+
+    let key_doc = key_doc(oid);
+    if let Some(prev_state) = db.get(&key_doc)? {
+        bytes_before += key_doc.len() as u64 + blob::stored_len(prev_state.as_ref());
+    }
+    let key_sv = key_state_vector(oid);
+    if let Some(prev_sv) = db.get(&key_sv)? {
+        bytes_before += (key_sv.len() + prev_sv.as_ref().len()) as u64;
+    }
+    let bytes_after = (key_doc.len() + doc_state.len() + key_sv.len() + state_vec.len()) as u64;
+    insert_inner(db, oid, &doc_state, &state_vec)?;
+    store_doc_options(db, oid, doc.options().skip_gc)?;
+    db.remove_range(&start, &end)?;
+    Ok(Some(FlushOutcome {
+        doc,
+        updates_folded,
+        bytes_before,
+        bytes_after,
+        duration: started.elapsed(),
+    }))
+}
@@ -0,0 +1,335 @@
+//! Squashing client IDs of documents edited by many short-lived clients.
+//!
+//! Every Yrs client - usually every browser tab - edits a document under its own client ID, and
+//! the state vector of the document keeps an entry for each of them forever. After months of
+//! editing, the state vector of a popular document may list thousands of clients, which bloats
+//! its stored state vector, every sync step 1 message and every diff computation.
+//! [DocOps::compact_client_ids](crate::DocOps::compact_client_ids) rebuilds such a document in
+//! a fresh [Doc] under a single, newly generated client ID - as if all of its current contents
+//! were typed by a single client - and stores it as the new state of the document, dropping its
+//! pending updates:
+//!
+//! ```rust,ignore
+//! // all clients of "my-doc-name" have been disconnected
+//! let compaction = db_txn.compact_client_ids("my-doc-name")?.unwrap();
+//! println!(
+//!     "{} clients squashed, state vector: {} -> {} bytes",
+//!     compaction.clients_before, compaction.sv_bytes_before, compaction.sv_bytes_after
+//! );
+//! ```
+//!
+//! # Consequences for clients
+//!
+//! **Compaction replaces the identity of every piece of the document's contents.** Replicas of the
+//! document held by clients no longer share any history with the stored one:
+//!
+//! - clients which keep a copy of the document (i.e. in IndexedDB) must drop it and load the
+//!   document from scratch, otherwise syncing with the store duplicates its whole contents,
+//! - changes made by offline clients before the compaction can't be merged anymore: they refer to
+//!   content which doesn't exist in the compacted document and stay pending forever,
+//! - updates pushed concurrently with the compaction are lost together with the update log.
+//!
+//! Compaction should therefore only be run while no client has the document open, and only
+//! after all offline clients are known to have synced - or their changes can be discarded. It's
+//! never performed implicitly.
+//!
+//! # Refused documents
+//!
+//! Documents whose stored data refers to the old client IDs are refused with
+//! [Error::CompactionRefused]: documents with [snapshots](crate::DocOps::insert_snapshot), with
+//! pending updates on [channels](crate::DocOps::push_update_channel) other than the default one,
+//! and parts of [split](crate::split) documents. Texts (including their formatting), arrays, maps
+//! and their primitive values are copied. Documents containing other shared types (i.e. XML types
+//! or subdocuments) or text embeds are refused with [Error::UnsupportedContent]. Deleted content
+//! kept for past snapshots is not copied.
+//!
+//! For debugging, the new client ID and the state vector of the document before compaction are
+//! recorded under [META_CLIENT_IDS] and can be read with [decode_client_ids].
+
+use crate::error::Error;
+use crate::format::{META_CLIENT_IDS, META_SPLIT_IDS};
+use crate::keys::{key_meta, key_snapshot, OID};
+use crate::{
+    channel, delete_updates, insert_inner, load_doc, lock_live_oid, touch, usage, DocOps, KVEntry,
+    KVStore,
+};
+use std::fmt::{Display, Formatter};
+use yrs::block::ClientID;
+use yrs::encoding::read::{Cursor, Read};
+use yrs::encoding::write::Write;
+use yrs::types::text::YChange;
+use yrs::types::AsPrelim;
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
+use yrs::{
+    Any, Array, ArrayPrelim, ArrayRef, Doc, In, Map, MapPrelim, MapRef, Out, ReadTxn, StateVector,
+    Text, TextPrelim, TextRef, Transact, TransactionMut,
+};
+
+/// Outcome of [DocOps::compact_client_ids](crate::DocOps::compact_client_ids).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCompaction {
+    /// Client ID the document has been rewritten under, or `None` if it has been left intact,
+    /// because it had no more than a single client.
+    pub client: Option<ClientID>,
+    /// Number of clients in the state vector of the document before compaction.
+    pub clients_before: u32,
+    /// Number of clients in the state vector of the document after compaction.
+    pub clients_after: u32,
+    /// Size of the lib0 v1 encoded state vector of the document before compaction.
+    pub sv_bytes_before: usize,
+    /// Size of the lib0 v1 encoded state vector of the document after compaction.
+    pub sv_bytes_after: usize,
+}
+
+/// Reason of refusing to compact client IDs of a document, reported with
+/// [Error::CompactionRefused].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactionRefusal {
+    /// Document has snapshots stored, which refer to its current client IDs.
+    Snapshots,
+    /// Document has pending updates on channels other than the default one.
+    Channels,
+    /// Document is a part of a split document, which routes blocks by their client IDs.
+    Split,
+}
+
+impl Display for CompactionRefusal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompactionRefusal::Snapshots => write!(f, "document has snapshots"),
+            CompactionRefusal::Channels => write!(f, "document has pending channel updates"),
+            CompactionRefusal::Split => write!(f, "document is a part of a split document"),
+        }
+    }
+}
+
+/// Decodes the value of [META_CLIENT_IDS] entry: the client ID a document has been rewritten
+/// under and the state vector of the document before it was compacted. Returns `None` if the
+/// value is malformed.
+pub fn decode_client_ids(value: &[u8]) -> Option<(ClientID, StateVector)> {
+    let mut cursor = Cursor::new(value);
+    let client: ClientID = cursor.read_var().ok()?;
+    let sv = StateVector::decode_v1(&value[cursor.next..]).ok()?;
+    Some((client, sv))
+}
+
+/// Rewrites a document with a given `name` under a single client ID. Returns `None` if there's
+/// no such document. See [module documentation](self) for details.
+pub(crate) fn compact<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+) -> Result<Option<ClientCompaction>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let oid = match lock_live_oid(db, name)? {
+        Some(oid) => oid,
+        None => return Ok(None),
+    };
+    if let Some(reason) = refusal(db, oid)? {
+        return Err(Error::CompactionRefused(reason));
+    }
+    let doc = Doc::new();
+    if !load_doc(db, oid, &mut doc.transact_mut())?.found() {
+        return Ok(None);
+    }
+    let before = doc.transact().state_vector();
+    let clients_before = before.len() as u32;
+    let sv_bytes_before = before.encode_v1().len();
+    if clients_before <= 1 {
+        return Ok(Some(ClientCompaction {
+            client: None,
+            clients_before,
+            clients_after: clients_before,
+            sv_bytes_before,
+            sv_bytes_after: sv_bytes_before,
+        }));
+    }
+
+    // new client ID must not collide with any of the squashed ones
+    let client = loop {
+        let client = Doc::new().client_id();
+        if !before.contains_client(&client) {
+            break client;
+        }
+    };
+    let compacted = Doc::with_client_id(client);
+    copy_roots(&doc, &compacted)?;
+    let (state, sv) = {
+        let txn = compacted.transact();
+        (
+            txn.encode_state_as_update_v1(&StateVector::default()),
+            txn.state_vector(),
+        )
+    };
+    let sv_v1 = sv.encode_v1();
+    let mut mapping = Vec::new();
+    mapping.write_var(client);
+    mapping.extend_from_slice(&before.encode_v1());
+
+    let codec = &db.config().codec;
+    usage::track(db, name, || {
+        insert_inner(db, oid, &codec.encode(&state)?, &codec.encode(&sv_v1)?)?;
+        delete_updates(db, oid)?;
+        db.upsert(&key_meta(oid, META_CLIENT_IDS), &mapping)?;
+        touch(db, oid)
+    })?;
+    Ok(Some(ClientCompaction {
+        client: Some(client),
+        clients_before,
+        clients_after: sv.len() as u32,
+        sv_bytes_before,
+        sv_bytes_after: sv_v1.len(),
+    }))
+}
+
+/// Checks if stored data of a document with a given `oid` refers to its client IDs, so that it
+/// cannot be compacted.
+fn refusal<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+) -> Result<Option<CompactionRefusal>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    // snapshot with an empty label has the lowest key of all snapshots of the document
+    let first = key_snapshot(oid, b"");
+    let prefix = &first.as_ref()[..first.as_ref().len() - 1];
+    if let Some(e) = db.seek(&first)? {
+        if e.key().starts_with(prefix) {
+            return Ok(Some(CompactionRefusal::Snapshots));
+        }
+    }
+    if channel::has_updates(db, oid)? {
+        return Ok(Some(CompactionRefusal::Channels));
+    }
+    if db.get(&key_meta(oid, META_SPLIT_IDS))?.is_some() {
+        return Ok(Some(CompactionRefusal::Split));
+    }
+    Ok(None)
+}
+
+/// Root type of the compacted document, which receives the copy of a root type of the original.
+enum Root {
+    Text(TextRef, TextRef),
+    Array(ArrayRef, ArrayRef),
+    Map(MapRef, MapRef),
+}
+
+/// Copies all root types of `src` into an empty `dst` document within a single transaction.
+fn copy_roots(src: &Doc, dst: &Doc) -> Result<(), Error> {
+    let src_txn = src.transact();
+    // root types must be obtained before a write transaction is opened
+    let mut roots = Vec::new();
+    for (name, value) in src_txn.root_refs() {
+        // types of roots decoded from updates are undefined until they're accessed, so they're
+        // inferred from their contents
+        let value = match &value {
+            Out::UndefinedRef(_) => match value.as_prelim(&src_txn) {
+                In::Text(_) => src_txn.get_text(name).map(Out::YText),
+                In::Array(_) => src_txn.get_array(name).map(Out::YArray),
+                In::Map(_) => src_txn.get_map(name).map(Out::YMap),
+                _ => None,
+            }
+            .unwrap_or(value),
+            _ => value,
+        };
+        roots.push(match value {
+            Out::YText(text) => Root::Text(text, dst.get_or_insert_text(name)),
+            Out::YArray(array) => Root::Array(array, dst.get_or_insert_array(name)),
+            Out::YMap(map) => Root::Map(map, dst.get_or_insert_map(name)),
+            _ => return Err(Error::UnsupportedContent(name.to_string())),
+        });
+    }
+    let mut txn = dst.transact_mut();
+    for root in roots {
+        match root {
+            Root::Text(src, dst) => copy_text(&src_txn, &src, &mut txn, &dst, "")?,
+            Root::Array(src, dst) => copy_array(&src_txn, &src, &mut txn, &dst)?,
+            Root::Map(src, dst) => copy_map(&src_txn, &src, &mut txn, &dst)?,
+        }
+    }
+    Ok(())
+}
+
+/// Copies contents of a text together with their formatting. Embeds are refused with
+/// [Error::UnsupportedContent] naming a given `path`.
+fn copy_text<T: ReadTxn>(
+    src_txn: &T,
+    src: &TextRef,
+    txn: &mut TransactionMut,
+    dst: &TextRef,
+    path: &str,
+) -> Result<(), Error> {
+    for chunk in src.diff(src_txn, YChange::identity) {
+        let index = dst.len(txn);
+        match (chunk.insert, chunk.attributes) {
+            (Out::Any(Any::String(s)), Some(attributes)) => {
+                dst.insert_with_attributes(txn, index, &s, *attributes)
+            }
+            (Out::Any(Any::String(s)), None) => dst.insert(txn, index, &s),
+            _ => return Err(Error::UnsupportedContent(path.to_string())),
+        }
+    }
+    Ok(())
+}
+
+fn copy_array<T: ReadTxn>(
+    src_txn: &T,
+    src: &ArrayRef,
+    txn: &mut TransactionMut,
+    dst: &ArrayRef,
+) -> Result<(), Error> {
+    for (i, value) in src.iter(src_txn).enumerate() {
+        let index = dst.len(txn);
+        match value {
+            Out::Any(any) => {
+                dst.insert(txn, index, any);
+            }
+            Out::YText(text) => {
+                let copy = dst.insert(txn, index, TextPrelim::new(""));
+                copy_text(src_txn, &text, txn, &copy, &i.to_string())?;
+            }
+            Out::YArray(array) => {
+                let copy = dst.insert(txn, index, ArrayPrelim::default());
+                copy_array(src_txn, &array, txn, &copy)?;
+            }
+            Out::YMap(map) => {
+                let copy = dst.insert(txn, index, MapPrelim::default());
+                copy_map(src_txn, &map, txn, &copy)?;
+            }
+            _ => return Err(Error::UnsupportedContent(i.to_string())),
+        }
+    }
+    Ok(())
+}
+
+fn copy_map<T: ReadTxn>(
+    src_txn: &T,
+    src: &MapRef,
+    txn: &mut TransactionMut,
+    dst: &MapRef,
+) -> Result<(), Error> {
+    for (key, value) in src.iter(src_txn) {
+        match value {
+            Out::Any(any) => {
+                dst.insert(txn, key, any);
+            }
+            Out::YText(text) => {
+                let copy = dst.insert(txn, key, TextPrelim::new(""));
+                copy_text(src_txn, &text, txn, &copy, key)?;
+            }
+            Out::YArray(array) => {
+                let copy = dst.insert(txn, key, ArrayPrelim::default());
+                copy_array(src_txn, &array, txn, &copy)?;
+            }
+            Out::YMap(map) => {
+                let copy = dst.insert(txn, key, MapPrelim::default());
+                copy_map(src_txn, &map, txn, &copy)?;
+            }
+            _ => return Err(Error::UnsupportedContent(key.to_string())),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,86 @@
+//! Source of the current time for all time-dependent features of [DocOps](crate::DocOps).
+//!
+//! Every timestamp written or compared by [DocOps](crate::DocOps) - last modification times,
+//! audit records, update feed entries, access records, expiration of cached sync frames, creation
+//! times of drafts and blobs - is read from the [Clock] returned by
+//! [DocOps::clock](crate::DocOps::clock). By default it's [SystemClock]. Tests and deterministic
+//! replays can install a [ManualClock] with
+//! [ConfiguredStore::with_clock](crate::config::ConfiguredStore::with_clock), which only moves
+//! when told to:
+//!
+//! ```rust,ignore
+//! let clock = Arc::new(ManualClock::new(1_000_000));
+//! let db_txn = ConfiguredStore::new(RocksDBStore::from(db.transaction()), StoreConfig::DEFAULT)
+//!     .with_clock(clock.clone());
+//! db_txn.create_draft("my-doc-name")?;
+//! clock.advance(Duration::from_secs(3600));
+//! assert_eq!(db_txn.purge_drafts(Duration::from_secs(3600))?, 1);
+//! ```
+//!
+//! Times are measured in milliseconds since UNIX epoch and stored as described in
+//! [format](crate::format#timestamps). Durations of operations (i.e.
+//! [FlushOutcome::duration](crate::FlushOutcome::duration)) and
+//! [deadlines](crate::deadline) are measured with [Instant](std::time::Instant) instead, as they
+//! are never stored.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Source of the current time. See [module documentation](self) for details.
+pub trait Clock: Send + Sync {
+    /// Returns the current time as a number of milliseconds since UNIX epoch.
+    fn now(&self) -> u64;
+}
+
+/// [Clock] reading the time of the system with [SystemTime::now]. Times before UNIX epoch are
+/// reported as 0.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        millis(SystemTime::now())
+    }
+}
+
+/// [Clock] which stands still until it's moved with [ManualClock::set] or [ManualClock::advance],
+/// i.e. to test expiration of time-dependent entries without waiting.
+#[derive(Debug, Default)]
+pub struct ManualClock(AtomicU64);
+
+impl ManualClock {
+    /// Creates a new clock showing a given number of milliseconds since UNIX epoch.
+    pub fn new(millis: u64) -> Self {
+        ManualClock(AtomicU64::new(millis))
+    }
+
+    /// Sets the clock to a given number of milliseconds since UNIX epoch. Clock may be moved
+    /// backwards.
+    pub fn set(&self, millis: u64) {
+        self.0.store(millis, Ordering::SeqCst)
+    }
+
+    /// Moves the clock forward by a given duration.
+    pub fn advance(&self, by: Duration) {
+        self.0.fetch_add(by.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Converts a given `time` into a number of milliseconds since UNIX epoch. Times before UNIX
+/// epoch are converted to 0.
+pub fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Converts a given number of milliseconds since UNIX epoch into [SystemTime].
+pub fn system_time(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
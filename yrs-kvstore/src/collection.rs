@@ -0,0 +1,182 @@
+//! Hierarchical collections of documents.
+//!
+//! Collections don't introduce a separate document identity: a document stored in a collection
+//! is just a document whose name starts with an encoded path of that collection. Path segments are
+//! separated with [SEPARATOR], while separator and [ESCAPE] bytes occurring inside of segments are
+//! prefixed with [ESCAPE]. This way all documents of a collection (including nested ones) occupy
+//! a single contiguous range of the OID index, which lets [DocOps] list and clear collections
+//! without scanning the whole store.
+//!
+//! ```rust,ignore
+//! let project = Collection::new(["org", "project"]);
+//! db_txn.create_collection(&project)?;
+//!
+//! let name = project.doc_name("my/doc"); // stored as `org/project/my\/doc`
+//! db_txn.push_update(&name, &update)?;
+//!
+//! for name in db_txn.iter_collection(&project)? {
+//!     let (_, doc_name) = Collection::split_doc_name(&name);
+//!     assert_eq!(doc_name, b"my/doc");
+//! }
+//! ```
+//!
+//! [DocOps]: crate::DocOps
+
+/// Byte separating segments of collection path from each other and from the document name.
+pub const SEPARATOR: u8 = b'/';
+
+/// Byte used to escape [SEPARATOR] and itself within collection path segments and document names.
+pub const ESCAPE: u8 = b'\\';
+
+/// Handle to a collection of documents, identified by its path. See
+/// [module documentation](self) for details.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Collection {
+    /// Escaped path segments joined with [SEPARATOR]. Empty for the root collection.
+    path: Vec<u8>,
+}
+
+impl Collection {
+    /// Returns a root collection, which contains all documents stored in the database.
+    pub fn root() -> Self {
+        Collection { path: Vec::new() }
+    }
+
+    /// Creates a handle to the collection with a given path `segments`, starting from the root.
+    pub fn new<I, S>(segments: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<[u8]>,
+    {
+        let mut collection = Self::root();
+        for segment in segments {
+            collection = collection.child(segment.as_ref());
+        }
+        collection
+    }
+
+    /// Returns a handle to a sub-collection of the current one with a given `name`.
+    pub fn child<S: AsRef<[u8]> + ?Sized>(&self, name: &S) -> Self {
+        Collection {
+            path: self.join(name.as_ref()),
+        }
+    }
+
+    /// Returns a handle to the parent collection or `None` if current collection is a root.
+    pub fn parent(&self) -> Option<Self> {
+        if self.is_root() {
+            None
+        } else {
+            let path = match find_last_separator(&self.path) {
+                Some(i) => self.path[..i].to_vec(),
+                None => Vec::new(),
+            };
+            Some(Collection { path })
+        }
+    }
+
+    /// Checks if current handle points to the root collection.
+    pub fn is_root(&self) -> bool {
+        self.path.is_empty()
+    }
+
+    /// Returns an encoded path of the current collection.
+    pub fn path(&self) -> &[u8] {
+        &self.path
+    }
+
+    /// Returns decoded path segments of the current collection.
+    pub fn segments(&self) -> Vec<Vec<u8>> {
+        if self.is_root() {
+            return Vec::new();
+        }
+        let mut segments = Vec::new();
+        let mut rest = self.path.as_slice();
+        while let Some(i) = find_separator(rest) {
+            segments.push(unescape(&rest[..i]));
+            rest = &rest[(i + 1)..];
+        }
+        segments.push(unescape(rest));
+        segments
+    }
+
+    /// Returns a full name of the document with a given `name` stored in the current collection.
+    /// It can be passed to any [DocOps](crate::DocOps) method accepting document names.
+    pub fn doc_name<S: AsRef<[u8]> + ?Sized>(&self, name: &S) -> Vec<u8> {
+        self.join(name.as_ref())
+    }
+
+    /// Splits a full document name produced by [Collection::doc_name] back into the collection
+    /// and the decoded document name.
+    pub fn split_doc_name(full_name: &[u8]) -> (Collection, Vec<u8>) {
+        match find_last_separator(full_name) {
+            Some(i) => (
+                Collection {
+                    path: full_name[..i].to_vec(),
+                },
+                unescape(&full_name[(i + 1)..]),
+            ),
+            None => (Collection::root(), unescape(full_name)),
+        }
+    }
+
+    /// Returns a prefix shared by names of all documents stored in the current collection,
+    /// including nested ones.
+    pub(crate) fn prefix(&self) -> Vec<u8> {
+        let mut prefix = self.path.clone();
+        if !self.is_root() {
+            prefix.push(SEPARATOR);
+        }
+        prefix
+    }
+
+    fn join(&self, name: &[u8]) -> Vec<u8> {
+        let mut buf = self.prefix();
+        for b in name {
+            if *b == SEPARATOR || *b == ESCAPE {
+                buf.push(ESCAPE);
+            }
+            buf.push(*b);
+        }
+        buf
+    }
+}
+
+/// Returns a position of the first unescaped [SEPARATOR] in a given encoded `path`.
+pub(crate) fn find_separator(path: &[u8]) -> Option<usize> {
+    let mut escaped = false;
+    for (i, b) in path.iter().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if *b == ESCAPE {
+            escaped = true;
+        } else if *b == SEPARATOR {
+            return Some(i);
+        }
+    }
+    None
+}
+
+fn find_last_separator(path: &[u8]) -> Option<usize> {
+    let mut last = None;
+    let mut offset = 0;
+    while let Some(i) = find_separator(&path[offset..]) {
+        last = Some(offset + i);
+        offset += i + 1;
+    }
+    last
+}
+
+fn unescape(segment: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(segment.len());
+    let mut escaped = false;
+    for b in segment {
+        if !escaped && *b == ESCAPE {
+            escaped = true;
+        } else {
+            escaped = false;
+            buf.push(*b);
+        }
+    }
+    buf
+}
@@ -0,0 +1,334 @@
+//! Fixtures of stores written by earlier releases, used to verify that the development version
+//! still reads and writes them. Available with `conformance-tests` feature enabled.
+//!
+//! Every fixture is a text file under `yrs-kvstore/fixtures`, holding all entries of a store in
+//! the [format](crate::format) of a single [FORMAT_VERSION]. Fixtures are independent of the
+//! backend: the layout of keys and values is what yrs-kvstore is responsible for, while the files
+//! of every backend are kept compatible by the backend itself. Every backend runs all
+//! [FIXTURES] against its own empty stores:
+//!
+//! ```rust,ignore
+//! for fixture in compat::FIXTURES {
+//!     let db = RocksDBStore::from(db_env.transaction());
+//!     compat::restore(&db, fixture);
+//!     compat::verify(&db);
+//! }
+//! ```
+//!
+//! All fixtures hold the same documents, written by [populate] or, by releases which predate it,
+//! with the same calls of their own API. [verify] reads every document of a fixture, then writes
+//! into it and reads it back. It doesn't rely on OIDs or update sequence numbers, which differ
+//! between releases.
+//!
+//! `format-v1.txt` was written by yrs-kvstore 0.3.0. That release keeps neither pending updates
+//! counters nor the last allocated OID, and takes the sequence number of a new update from
+//! whichever entry precedes it, so the first updates of a document get arbitrary numbers. Its
+//! documents were written in reverse order, so the last entry of the OID index doesn't hold the
+//! highest OID. The program which wrote it is kept in `yrs-lmdb/fixtures/v0.3.0`, along with the
+//! LMDB database file it has written.
+//!
+//! Whenever [FORMAT_VERSION] is bumped, a fixture of the new version is generated with the
+//! `compat_fixture` example of yrs-lmdb (which runs [populate] and [dump]) and added to
+//! [FIXTURES], while fixtures of older versions are kept. [current_layout] fails until it's done,
+//! so layout changes can't go unnoticed.
+//!
+//! # Panics
+//!
+//! [restore], [verify] and [current_layout] panic if a store doesn't behave as expected or a
+//! store operation fails.
+
+use crate::error::Error;
+use crate::format::{FORMAT_VERSION, KEYSPACE_OID, OID_LEN, V1};
+use crate::keys::OID;
+use crate::{DocOps, KVEntry, KVStore};
+use std::convert::TryInto;
+use std::io::Write;
+use yrs::updates::decoder::Decode;
+use yrs::{Doc, GetString, StateVector, Text, Transact, Update};
+
+/// Store entries written in a given format version.
+#[derive(Debug, Clone, Copy)]
+pub struct Fixture {
+    /// Name of the fixture file.
+    pub name: &'static str,
+    /// [FORMAT_VERSION] of the release which wrote the fixture.
+    pub format_version: u32,
+    /// Contents of the fixture file: hex encoded key and value of every entry, one per line.
+    /// Empty lines and lines starting with `#` are skipped.
+    pub entries: &'static str,
+}
+
+/// All fixtures, ordered by their format version.
+pub const FIXTURES: &[Fixture] = &[
+    Fixture {
+        name: "format-v1.txt",
+        format_version: 1,
+        entries: include_str!("../fixtures/format-v1.txt"),
+    },
+    Fixture {
+        name: "format-v10.txt",
+        format_version: 10,
+        entries: include_str!("../fixtures/format-v10.txt"),
+    },
+    Fixture {
+        name: "format-v11.txt",
+        format_version: 11,
+        entries: include_str!("../fixtures/format-v11.txt"),
+    },
+    Fixture {
+        name: "format-v12.txt",
+        format_version: 12,
+        entries: include_str!("../fixtures/format-v12.txt"),
+    },
+];
+
+/// Names of documents stored in every fixture, in ascending order.
+pub const DOC_NAMES: [&str; 2] = ["compat/flushed", "compat/pending"];
+
+/// Writes the documents stored in every fixture: `compat/flushed`, which has its state flushed
+/// and a metadata entry, and `compat/pending`, which has only pending updates. Contents of the
+/// documents don't depend on the time of writing, so fixtures generated by the same release are
+/// identical.
+pub fn populate<'a, DB: DocOps<'a>>(db: &DB)
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let doc = Doc::with_client_id(1);
+    let text = doc.get_or_insert_text("text");
+    text.push(&mut doc.transact_mut(), "hello");
+    db.insert_doc(DOC_NAMES[0], &doc.transact()).unwrap();
+    db.insert_meta(DOC_NAMES[0], "title", b"Flushed").unwrap();
+
+    let doc = Doc::with_client_id(2);
+    let text = doc.get_or_insert_text("text");
+    for chunk in ["abc", "de"].iter() {
+        let mut txn = doc.transact_mut();
+        text.push(&mut txn, chunk);
+        db.push_update(DOC_NAMES[1], &txn.encode_update_v1())
+            .unwrap();
+    }
+}
+
+/// Writes all entries of a store into a fixture file.
+pub fn dump<'a, DB: DocOps<'a>, W: Write>(db: &DB, w: &mut W) -> std::io::Result<()>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    writeln!(
+        w,
+        "# Entries of a store written by yrs-kvstore, one per line: hex encoded key and value."
+    )?;
+    writeln!(
+        w,
+        "# See yrs_kvstore::compat for how fixtures are generated and verified."
+    )?;
+    writeln!(w, "# format_version: {}", FORMAT_VERSION)?;
+    for (key, value) in entries(db) {
+        writeln!(w, "{} {}", hex(&key), hex(&value))?;
+    }
+    Ok(())
+}
+
+/// Writes all entries of a given `fixture` into a store, which should be empty.
+pub fn restore<'a, DB: DocOps<'a>>(db: &DB, fixture: &Fixture)
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    for (key, value) in parse(fixture) {
+        db.upsert(&key, &value).unwrap();
+    }
+}
+
+/// Verifies that documents written by [populate] - i.e. restored from a fixture - can be read,
+/// written and read back.
+pub fn verify<'a, DB: DocOps<'a>>(db: &DB)
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let [flushed, pending] = DOC_NAMES;
+    let names: Vec<Box<[u8]>> = db.iter_docs().unwrap().collect();
+    let expected: Vec<Box<[u8]>> = DOC_NAMES
+        .iter()
+        .map(|name| name.as_bytes().into())
+        .collect();
+    assert_eq!(names, expected);
+    let meta: Vec<_> = db
+        .iter_meta(flushed)
+        .unwrap()
+        .map(|(key, value)| (key.to_vec(), value.to_vec()))
+        .collect();
+    assert_eq!(meta, vec![(b"title".to_vec(), b"Flushed".to_vec())]);
+    assert_eq!(load_text(db, flushed), "hello");
+    assert_eq!(load_text(db, pending), "abcde");
+    assert_eq!(diff_text(db, flushed), "hello");
+    assert_eq!(diff_text(db, pending), "abcde");
+    assert_eq!(db.decoded_updates(pending).unwrap().count(), 2);
+
+    // writes into existing documents
+    let doc = Doc::with_client_id(3);
+    let text = doc.get_or_insert_text("text");
+    db.load_doc(flushed, &mut doc.transact_mut()).unwrap();
+    let update = {
+        let mut txn = doc.transact_mut();
+        text.push(&mut txn, " world");
+        txn.encode_update_v1()
+    };
+    assert_eq!(db.push_update(flushed, &update).unwrap().seq, 1);
+    db.insert_meta(flushed, "title", b"Updated").unwrap();
+    let title = db.get_meta(flushed, "title").unwrap().unwrap();
+    assert_eq!(title.as_ref(), b"Updated");
+    assert_eq!(load_text(db, flushed), "hello world");
+
+    // new updates follow the stored ones, whichever sequence numbers these have
+    let last_seq = db
+        .decoded_updates(pending)
+        .unwrap()
+        .map(|res| res.unwrap().0)
+        .max()
+        .unwrap();
+    let update = {
+        let pending_doc = Doc::with_client_id(4);
+        let text = pending_doc.get_or_insert_text("text");
+        db.load_doc(pending, &mut pending_doc.transact_mut())
+            .unwrap();
+        let mut txn = pending_doc.transact_mut();
+        text.push(&mut txn, "f");
+        txn.encode_update_v1()
+    };
+    assert!(db.push_update(pending, &update).unwrap().seq > last_seq);
+    assert_eq!(load_text(db, pending), "abcdef");
+    let outcome = db.flush_doc(pending).unwrap().unwrap();
+    assert_eq!(outcome.updates_folded, 3);
+    assert_eq!(load_text(db, pending), "abcdef");
+
+    // new documents get OIDs higher than OIDs of all existing ones
+    let last_oid = oids(db).into_iter().map(|(_, oid)| oid).max().unwrap();
+    db.insert_doc("compat/new", &doc.transact()).unwrap();
+    let new_oid = oids(db)
+        .into_iter()
+        .find(|(name, _)| name.as_slice() == b"compat/new")
+        .map(|(_, oid)| oid);
+    assert_eq!(new_oid, Some(last_oid + 1));
+    db.clear_doc(flushed).unwrap();
+    assert_eq!(load_text(db, pending), "abcdef");
+    assert_eq!(load_text(db, "compat/new"), "hello world");
+    db.insert_doc(flushed, &doc.transact()).unwrap();
+
+    // everything is read back
+    let names: Vec<Box<[u8]>> = db.iter_docs().unwrap().collect();
+    let expected: Vec<Box<[u8]>> = [flushed, "compat/new", pending]
+        .iter()
+        .map(|name| name.as_bytes().into())
+        .collect();
+    assert_eq!(names, expected);
+    assert_eq!(load_text(db, flushed), "hello world");
+    assert_eq!(diff_text(db, pending), "abcdef");
+    assert_eq!(db.decoded_updates(pending).unwrap().count(), 0);
+    assert_eq!(db.iter_meta(flushed).unwrap().count(), 0);
+}
+
+/// Verifies that [populate] writes exactly the entries stored in the fixture of the current
+/// [FORMAT_VERSION] into an empty store. Fails if the layout has changed without a new fixture
+/// being added.
+pub fn current_layout<'a, DB: DocOps<'a>>(db: &DB)
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let fixture = FIXTURES
+        .iter()
+        .find(|fixture| fixture.format_version == FORMAT_VERSION)
+        .unwrap_or_else(|| panic!("missing fixture of format version {}", FORMAT_VERSION));
+    populate(db);
+    assert_eq!(entries(db), parse(fixture), "{}", fixture.name);
+}
+
+/// Parses entries of a given `fixture`.
+pub fn parse(fixture: &Fixture) -> Vec<(Vec<u8>, Vec<u8>)> {
+    fixture
+        .entries
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut parts = line.split_whitespace();
+            let mut next = || {
+                let part = parts.next().unwrap_or_default();
+                unhex(part).unwrap_or_else(|| panic!("{}: malformed line {}", fixture.name, line))
+            };
+            (next(), next())
+        })
+        .collect()
+}
+
+/// Returns all yrs-kvstore entries of a store in the order of their keys.
+fn entries<'a, DB: DocOps<'a>>(db: &DB) -> Vec<(Vec<u8>, Vec<u8>)>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let start = [V1];
+    let end = [V1 + 1];
+    db.iter_range(&start, &end)
+        .unwrap()
+        .take_while(|e| e.key() < end.as_ref())
+        .map(|e| (e.key().to_vec(), e.value().to_vec()))
+        .collect()
+}
+
+/// Returns names and OIDs of all documents stored in the OID index of a store.
+fn oids<'a, DB: DocOps<'a>>(db: &DB) -> Vec<(Vec<u8>, OID)>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let start = [V1, KEYSPACE_OID];
+    let end = [V1, KEYSPACE_OID + 1];
+    db.iter_range(&start, &end)
+        .unwrap()
+        .take_while(|e| e.key() < end.as_ref())
+        .map(|e| {
+            let name = e.key()[start.len()..e.key().len() - 1].to_vec();
+            let oid = OID::from_be_bytes(e.value()[..OID_LEN].try_into().unwrap());
+            (name, oid)
+        })
+        .collect()
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [_, _] => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Loads a document with a given `name` and returns the contents of its `text` root type.
+fn load_text<'a, DB: DocOps<'a>>(db: &DB, name: &str) -> String
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    assert!(db.load_doc(name, &mut doc.transact_mut()).unwrap());
+    let content = text.get_string(&doc.transact());
+    content
+}
+
+/// Computes the diff of a document with a given `name` against an empty state vector and returns
+/// the contents of its `text` root type.
+fn diff_text<'a, DB: DocOps<'a>>(db: &DB, name: &str) -> String
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let diff = db.get_diff(name, &StateVector::default()).unwrap().unwrap();
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    doc.transact_mut()
+        .apply_update(Update::decode_v1(&diff).unwrap());
+    let content = text.get_string(&doc.transact());
+    content
+}
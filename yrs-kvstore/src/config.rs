@@ -0,0 +1,632 @@
+//! Store-wide configuration consulted by [DocOps] default method implementations.
+//!
+//! Key-value store backends remain plain byte stores: all behaviour described by [StoreConfig] is
+//! implemented by [DocOps] itself. By default [DocOps::config] returns [StoreConfig::DEFAULT],
+//! which matches the behaviour of stores that are not configured at all. Any [KVStore] can be
+//! wrapped into [ConfiguredStore] to use a different configuration:
+//!
+//! ```rust,ignore
+//! let config = StoreConfig {
+//!     codec: ValueCodec {
+//!         compression: Compression::Zstd { level: 3, min_size: 4096 },
+//!         checksum: true,
+//!     },
+//!     flush_policy: FlushPolicy::AfterUpdates(128),
+//!     ..StoreConfig::DEFAULT
+//! };
+//! let db_txn = ConfiguredStore::new(RocksDBStore::from(db.transaction()), config);
+//! db_txn.push_update("my-doc-name", &update)?;
+//! db_txn.into_inner().commit()?;
+//! ```
+//!
+//! Updates can be checked against server policy before they are persisted by installing an
+//! [UpdateValidator] with [ConfiguredStore::with_validator].
+//!
+//! Settings which affect the format of stored values ([StoreConfig::codec]) must stay the same
+//! during the whole lifetime of a store: values written using one codec cannot be read back using
+//! another one.
+
+use crate::audit::AuditConfig;
+use crate::blob::BlobStore;
+use crate::capabilities::Capabilities;
+use crate::clock::{Clock, SystemClock};
+use crate::error::Error;
+use crate::format::{CODEC_FLAG_CRC32, CODEC_FLAG_ZSTD, CRC32_LEN};
+use crate::usage::TenantExtractor;
+use crate::{DocOps, HealthHint, KVStore, ReadIsolation, ScanMode};
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::fmt::{Display, Formatter};
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+use yrs::Update;
+
+/// Configuration of [DocOps] behaviour. See [module documentation](self) for details.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreConfig {
+    /// Codec used to encode values of document state, state vector and update entries.
+    pub codec: ValueCodec,
+    /// Maximum length (in bytes) of names of newly created documents. `None` means no limit.
+    pub max_name_len: Option<usize>,
+    /// Decides if writes to documents which don't exist yet create them.
+    pub create_policy: CreatePolicy,
+    /// When set, every write modifying document contents also stores the time of that write, which
+    /// can be read back using [DocOps::last_modified].
+    pub timestamps: bool,
+    /// Decides when pending updates are merged into document state automatically.
+    pub flush_policy: FlushPolicy,
+    /// When set, multi-key operations ([DocOps::flush_doc], [DocOps::clear_doc]) are recorded in
+    /// the intent log before they start and removed from it once they complete. Operations
+    /// interrupted in between are completed by [DocOps::recover_intents].
+    ///
+    /// Stores which apply all writes of a transaction atomically don't need it.
+    pub intent_log: bool,
+    /// Time budget of a single long running operation ([DocOps::load_doc], [DocOps::flush_doc],
+    /// [DocOps::clear_doc] and [maintenance](crate::maintenance) passes). Operations which
+    /// exceed it stop early with [Error::DeadlineExceeded]. `None` means no limit. See
+    /// [deadline](crate::deadline) module for details.
+    pub op_deadline: Option<Duration>,
+    /// When set, [DocOps::handle_sync_step1] serves replies from the
+    /// [sync frames cache](crate::sync_cache) and caches the ones it computes for this long.
+    /// `None` disables the cache.
+    pub sync_frame_ttl: Option<Duration>,
+    /// When set to `Some(n)`, one in every `n` document reads ([DocOps::load_doc] and reads built
+    /// on top of it) records an access to the document, used by
+    /// [DocOps::iter_docs_by_staleness]. `Some(1)` records every read. `None` disables access
+    /// tracking.
+    ///
+    /// Recording an access is a write: with access tracking enabled, reads require write
+    /// capabilities from the database transaction. See [access](crate::access) module for
+    /// details.
+    pub access_sample_rate: Option<u32>,
+    /// Decides how pending updates appended by [DocOps::push_update] are laid out in the store.
+    pub update_framing: UpdateFraming,
+    /// When set, [DocOps::push_update_with_origin] records the text changes made by every update
+    /// in the audit log of the document. `None` disables the audit log. See
+    /// [audit](crate::audit) module for details.
+    pub audit: Option<AuditConfig>,
+    /// When set, [DocOps::get_diff] and [DocOps::get_diff_with] fail with
+    /// [Error::IncompleteHistory] instead of serving a diff of a document with stored updates,
+    /// which cannot be integrated because changes they depend on are missing from the store.
+    /// Such diffs lack the contents of these updates. Documents whose stored state vector has
+    /// outlived their document state fail with [Error::MissingDocState] for the same reason.
+    /// See [DocOps::load_doc_checked].
+    pub strict_history: bool,
+    /// When set, [DocOps::get_state_vector] repairs documents which have a stored state, but no
+    /// stored state vector (i.e. ones migrated from older versions or written by minimal
+    /// clients): their state vector is computed from the clocks of the stored state and written
+    /// back, unless the store is read-only. With this option set, reading state vectors requires
+    /// write capabilities from the database transaction.
+    pub sv_read_repair: bool,
+    /// Document states of at least that many bytes (after being encoded with [Self::codec]) are
+    /// stored as blobs, if a [BlobStore] has been installed with
+    /// [ConfiguredStore::with_blob_store]. See [blob](crate::blob).
+    pub blob_threshold: usize,
+    /// When set, [DocOps::push_update] records every update in the global update feed, which
+    /// can be read with [DocOps::iter_feed]. See [feed](crate::feed) module for details.
+    pub update_feed: bool,
+    /// When set, every flush of a document records its encoded size in the size history of the
+    /// document, which can be read with [DocOps::size_history]. See
+    /// [size_history](crate::size_history) module for details.
+    pub size_history: bool,
+}
+
+impl StoreConfig {
+    /// Default configuration, used by stores which are not wrapped into [ConfiguredStore].
+    pub const DEFAULT: StoreConfig = StoreConfig {
+        codec: ValueCodec::RAW,
+        max_name_len: None,
+        create_policy: CreatePolicy::CreateIfMissing,
+        timestamps: false,
+        flush_policy: FlushPolicy::Manual,
+        intent_log: false,
+        op_deadline: None,
+        sync_frame_ttl: None,
+        access_sample_rate: None,
+        update_framing: UpdateFraming::Individual,
+        audit: None,
+        strict_history: false,
+        sv_read_repair: false,
+        blob_threshold: 1024 * 1024,
+        update_feed: false,
+        size_history: false,
+    };
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        StoreConfig::DEFAULT
+    }
+}
+
+/// Decides if [DocOps::push_update], [DocOps::insert_meta] and [DocOps::insert_snapshot] may
+/// create documents which don't exist yet. Writes refused by the policy fail with
+/// [Error::DocNotFound] without allocating a new document.
+///
+/// Restrictive policies protect against typos in document names, which would otherwise silently
+/// create new, empty documents. Documents can still be created explicitly with
+/// [DocOps::insert_doc].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CreatePolicy {
+    /// Missing documents are created. This is the default.
+    #[default]
+    CreateIfMissing,
+    /// Writes are accepted only for documents which have their state or updates stored.
+    RequireExisting,
+    /// Same as [CreatePolicy::RequireExisting], but documents which only have metadata stored
+    /// are accepted as well. This way documents can be registered upfront by writing their
+    /// metadata using a store configured with [CreatePolicy::CreateIfMissing].
+    RequireExistingOrMeta,
+}
+
+/// Decides when [DocOps::push_update] merges pending updates into document state on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    /// Updates are merged only when [DocOps::flush_doc] is called explicitly. This is the default.
+    #[default]
+    Manual,
+    /// Updates are merged once the number of pending updates reaches a given threshold.
+    AfterUpdates(u32),
+    /// Updates are merged once the total size of pending updates (in bytes) reaches a given
+    /// threshold.
+    AfterBytes(u64),
+}
+
+/// Layout of pending updates appended by [DocOps::push_update].
+///
+/// Storing every update under its own key is simple, but for documents receiving thousands of
+/// small updates the per-entry overhead of the key, value header and index entries may take more
+/// space than the updates themselves. Segmented framing co-locates consecutive updates within
+/// a small number of segment entries instead (see [format](crate::format) for their layout).
+///
+/// Both framings can be read by any store configuration, so it can be changed at any time: updates
+/// already stored keep their layout until they are merged by [DocOps::flush_doc], which removes
+/// whole segments. [DocOps::import_updates] always stores updates individually.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UpdateFraming {
+    /// Every update is stored under its own key. This is the default.
+    #[default]
+    Individual,
+    /// Updates are appended as length-prefixed records to the most recent segment, until its size
+    /// would exceed `segment_size` bytes, in which case a new segment is started. Updates larger
+    /// than `segment_size` are stored in segments of their own.
+    ///
+    /// Appending to a segment rewrites it, so `segment_size` should be kept small (i.e. 32KB).
+    Segmented { segment_size: usize },
+}
+
+/// Compression applied to stored values by [ValueCodec].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// Values are stored uncompressed.
+    #[default]
+    None,
+    /// Values of at least `min_size` bytes are compressed using zstd with a given `level`.
+    /// Requires the `compression` feature, otherwise writing such values fails with
+    /// [Error::CompressionUnavailable].
+    Zstd { level: i32, min_size: usize },
+}
+
+/// Codec used to encode values of document entries.
+///
+/// [ValueCodec::RAW] stores values as they are. Any other codec prefixes each value with a single
+/// byte of flags, describing how the value has been encoded, and - if [ValueCodec::checksum] is
+/// set - suffixes it with CRC-32 checksum of the stored payload:
+///
+/// ```nocompile
+/// {flags:1}{payload:N}{crc32:4}
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueCodec {
+    /// Compression applied to values.
+    pub compression: Compression,
+    /// When set, checksums of values are stored and verified on every read. Values which fail
+    /// verification are reported with [Error::CorruptedValue].
+    pub checksum: bool,
+}
+
+impl ValueCodec {
+    /// Codec storing values as they are.
+    pub const RAW: ValueCodec = ValueCodec {
+        compression: Compression::None,
+        checksum: false,
+    };
+
+    /// Checks if current codec stores values as they are.
+    pub fn is_raw(&self) -> bool {
+        *self == Self::RAW
+    }
+
+    /// Encodes a given `value` before storing it.
+    pub fn encode<'v>(&self, value: &'v [u8]) -> Result<Cow<'v, [u8]>, Error> {
+        if self.is_raw() {
+            return Ok(Cow::Borrowed(value));
+        }
+        let mut buf = Vec::new();
+        self.encode_into(value, &mut buf)?;
+        Ok(Cow::Owned(buf))
+    }
+
+    /// Same as [Self::encode], but writes encoded value into a given `buf` (clearing it first),
+    /// so that it can be reused between calls. Values of [ValueCodec::RAW] are returned as they
+    /// are, without touching the buffer.
+    pub fn encode_into<'v>(
+        &self,
+        value: &'v [u8],
+        buf: &'v mut Vec<u8>,
+    ) -> Result<&'v [u8], Error> {
+        if self.is_raw() {
+            return Ok(value);
+        }
+        let mut flags = 0;
+        buf.clear();
+        buf.push(0);
+        match self.compression {
+            Compression::Zstd { level, min_size } if value.len() >= min_size => {
+                flags |= CODEC_FLAG_ZSTD;
+                compress(value, level, buf)?;
+            }
+            _ => buf.extend_from_slice(value),
+        }
+        if self.checksum {
+            flags |= CODEC_FLAG_CRC32;
+            let checksum = crc32(&buf[1..]);
+            buf.extend_from_slice(&checksum.to_be_bytes());
+        }
+        buf[0] = flags;
+        Ok(buf.as_slice())
+    }
+
+    /// Decodes a given `value` previously encoded with [ValueCodec::encode].
+    pub fn decode<'v>(&self, value: &'v [u8]) -> Result<Cow<'v, [u8]>, Error> {
+        let (compressed, payload) = self.payload(value)?;
+        if compressed {
+            let mut buf = Vec::new();
+            decompress(payload, &mut buf)?;
+            Ok(Cow::Owned(buf))
+        } else {
+            Ok(Cow::Borrowed(payload))
+        }
+    }
+
+    /// Same as [Self::decode], but decompresses values into a given `buf` (clearing it first), so
+    /// that it can be reused between calls. Uncompressed values are returned without touching the
+    /// buffer.
+    pub fn decode_into<'v>(
+        &self,
+        value: &'v [u8],
+        buf: &'v mut Vec<u8>,
+    ) -> Result<&'v [u8], Error> {
+        let (compressed, payload) = self.payload(value)?;
+        if compressed {
+            buf.clear();
+            decompress(payload, buf)?;
+            Ok(buf.as_slice())
+        } else {
+            Ok(payload)
+        }
+    }
+
+    /// Verifies flags and checksum of an encoded `value`. Returns its payload and whether it's
+    /// compressed.
+    fn payload<'v>(&self, value: &'v [u8]) -> Result<(bool, &'v [u8]), Error> {
+        if self.is_raw() {
+            return Ok((false, value));
+        }
+        let (flags, mut payload) = match value.split_first() {
+            Some((flags, payload)) => (*flags, payload),
+            None => return Err(Error::CorruptedValue),
+        };
+        if flags & !(CODEC_FLAG_ZSTD | CODEC_FLAG_CRC32) != 0 {
+            return Err(Error::CorruptedValue);
+        }
+        if flags & CODEC_FLAG_CRC32 != 0 {
+            if payload.len() < CRC32_LEN {
+                return Err(Error::CorruptedValue);
+            }
+            let (data, checksum) = payload.split_at(payload.len() - CRC32_LEN);
+            let checksum = u32::from_be_bytes(checksum.try_into().unwrap());
+            if crc32(data) != checksum {
+                return Err(Error::CorruptedValue);
+            }
+            payload = data;
+        }
+        Ok((flags & CODEC_FLAG_ZSTD != 0, payload))
+    }
+}
+
+/// Appends a zstd-compressed `data` to `out`, using a given compression `level`.
+#[cfg(feature = "compression")]
+pub(crate) fn compress(data: &[u8], level: i32, out: &mut Vec<u8>) -> Result<(), Error> {
+    zstd::stream::copy_encode(data, out, level)?;
+    Ok(())
+}
+
+/// Fails with [Error::CompressionUnavailable], as compression support is disabled.
+#[cfg(not(feature = "compression"))]
+pub(crate) fn compress(_data: &[u8], _level: i32, _out: &mut Vec<u8>) -> Result<(), Error> {
+    Err(Error::CompressionUnavailable)
+}
+
+/// Appends decompressed zstd `data` to `out`.
+#[cfg(feature = "compression")]
+pub(crate) fn decompress(data: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+    zstd::stream::copy_decode(data, out)?;
+    Ok(())
+}
+
+/// Fails with [Error::CompressionUnavailable], as compression support is disabled.
+#[cfg(not(feature = "compression"))]
+pub(crate) fn decompress(_data: &[u8], _out: &mut Vec<u8>) -> Result<(), Error> {
+    Err(Error::CompressionUnavailable)
+}
+
+impl Default for ValueCodec {
+    fn default() -> Self {
+        ValueCodec::RAW
+    }
+}
+
+/// Lookup table of CRC-32 (IEEE 802.3) checksum.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Computes CRC-32 (IEEE 802.3) checksum of given `data`, the same one which is used by codecs
+/// with [CODEC_FLAG_CRC32](crate::format::CODEC_FLAG_CRC32) set and by archive files.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in data {
+        crc = CRC32_TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Hook deciding if updates are allowed to be stored, i.e. in order to enforce server policy. It's
+/// consulted by [DocOps::push_update] before anything is written: rejected updates are neither
+/// persisted nor - by extension - broadcast to other peers.
+///
+/// Any `Fn(&[u8], &Update) -> Result<(), RejectReason>` closure can be used as a validator.
+pub trait UpdateValidator: Send + Sync {
+    /// Checks if a decoded `update` can be stored in a document with a given `doc_name`. Returned
+    /// reason is reported to the caller with [Error::UpdateRejected].
+    fn validate(&self, doc_name: &[u8], update: &Update) -> Result<(), RejectReason>;
+}
+
+impl<F> UpdateValidator for F
+where
+    F: Fn(&[u8], &Update) -> Result<(), RejectReason> + Send + Sync,
+{
+    fn validate(&self, doc_name: &[u8], update: &Update) -> Result<(), RejectReason> {
+        self(doc_name, update)
+    }
+}
+
+/// Reason of rejecting an update by [UpdateValidator].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectReason(String);
+
+impl RejectReason {
+    /// Creates a new reason described by a given human-readable `message`.
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        RejectReason(message.into())
+    }
+
+    /// Returns human-readable description of the reason.
+    pub fn message(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for RejectReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Wrapper around any [KVStore], which implements [DocOps] using provided [StoreConfig].
+pub struct ConfiguredStore<S> {
+    store: S,
+    config: StoreConfig,
+    validator: Option<Arc<dyn UpdateValidator>>,
+    tenants: Option<Arc<dyn TenantExtractor>>,
+    blobs: Option<Arc<dyn BlobStore>>,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl<S> ConfiguredStore<S> {
+    /// Wraps a given `store`, making [DocOps] methods use provided `config`.
+    pub fn new(store: S, config: StoreConfig) -> Self {
+        ConfiguredStore {
+            store,
+            config,
+            validator: None,
+            tenants: None,
+            blobs: None,
+            clock: None,
+        }
+    }
+
+    /// Makes [DocOps::push_update] check all updates with a given `validator` before storing
+    /// them. Validator is shared, so the same instance can be used by stores wrapping different
+    /// transactions.
+    pub fn with_validator(mut self, validator: Arc<dyn UpdateValidator>) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+
+    /// Makes writes account storage usage of documents to tenants returned by a given `extractor`
+    /// (see [usage](crate::usage)). Like validators, extractors are shared between stores.
+    pub fn with_tenant_extractor(mut self, extractor: Arc<dyn TenantExtractor>) -> Self {
+        self.tenants = Some(extractor);
+        self
+    }
+
+    /// Makes writes store document states of at least [StoreConfig::blob_threshold] bytes as
+    /// blobs in a given `store`, and reads resolve them from it (see [blob](crate::blob)).
+    pub fn with_blob_store(mut self, store: Arc<dyn BlobStore>) -> Self {
+        self.blobs = Some(store);
+        self
+    }
+
+    /// Makes all time-dependent features read the current time from a given `clock` instead of
+    /// the system clock (see [clock](crate::clock)), i.e. a [ManualClock](crate::clock::ManualClock)
+    /// in tests.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Unwraps the underlying store, i.e. in order to commit it.
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+}
+
+impl<S> Deref for ConfiguredStore<S> {
+    type Target = S;
+
+    fn deref(&self) -> &Self::Target {
+        &self.store
+    }
+}
+
+impl<'a, S: KVStore<'a>> KVStore<'a> for ConfiguredStore<S> {
+    type Error = S::Error;
+    type Cursor = S::Cursor;
+    type Entry = S::Entry;
+    type Return = S::Return;
+
+    const CAPABILITIES: Capabilities = S::CAPABILITIES;
+
+    #[inline]
+    fn get(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+        self.store.get(key)
+    }
+
+    #[inline]
+    fn get_for_update(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+        self.store.get_for_update(key)
+    }
+
+    #[inline]
+    fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, Self::Error> {
+        self.store.get_many(keys)
+    }
+
+    #[inline]
+    fn upsert(&self, key: &[u8], value: &[u8]) -> Result<(), Self::Error> {
+        self.store.upsert(key, value)
+    }
+
+    #[inline]
+    fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+        self.store.remove(key)
+    }
+
+    #[inline]
+    fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<u32, Self::Error> {
+        self.store.remove_range(from, to)
+    }
+
+    #[inline]
+    fn iter_range(&self, from: &[u8], to: &[u8]) -> Result<Self::Cursor, Self::Error> {
+        self.store.iter_range(from, to)
+    }
+
+    #[inline]
+    fn iter_range_with(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        mode: ScanMode,
+    ) -> Result<Self::Cursor, Self::Error> {
+        self.store.iter_range_with(from, to, mode)
+    }
+
+    fn iter_keys_range(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        f: &mut dyn FnMut(&[u8], usize) -> bool,
+    ) -> Result<(), Self::Error> {
+        self.store.iter_keys_range(from, to, f)
+    }
+
+    #[inline]
+    fn iter_range_rev(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        f: &mut dyn FnMut(&[u8], &[u8]) -> bool,
+    ) -> Result<(), Self::Error> {
+        self.store.iter_range_rev(from, to, f)
+    }
+
+    #[inline]
+    fn peek_back(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+        self.store.peek_back(key)
+    }
+
+    #[inline]
+    fn seek(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+        self.store.seek(key)
+    }
+
+    fn read_isolation(&self) -> ReadIsolation {
+        self.store.read_isolation()
+    }
+
+    fn health_hint(&self) -> Option<HealthHint> {
+        self.store.health_hint()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.store.is_read_only()
+    }
+}
+
+impl<'a, S: KVStore<'a>> DocOps<'a> for ConfiguredStore<S>
+where
+    Error: From<S::Error>,
+{
+    fn config(&self) -> &StoreConfig {
+        &self.config
+    }
+
+    fn update_validator(&self) -> Option<&dyn UpdateValidator> {
+        self.validator.as_deref()
+    }
+
+    fn tenant_extractor(&self) -> Option<&dyn TenantExtractor> {
+        self.tenants.as_deref()
+    }
+
+    fn blob_store(&self) -> Option<&dyn BlobStore> {
+        self.blobs.as_deref()
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        match &self.clock {
+            Some(clock) => clock.as_ref(),
+            None => &SystemClock,
+        }
+    }
+}
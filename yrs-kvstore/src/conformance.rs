@@ -0,0 +1,6203 @@
+//! Test suite verifying that a [KVStore] implementation behaves the way [DocOps] expects it to.
+//! Available with `conformance-tests` feature enabled.
+//!
+//! Scenarios work on a single store handle, within a single transaction, and write documents
+//! named with the [DOC_PREFIX], so they can share a store with other data. Scenarios counting
+//! pending updates clear their documents first, so they can be run again against the same store.
+//! They are meant to be run by the test suite of every backend:
+//!
+//! ```rust,ignore
+//! let db = RocksDBStore::from(db_env.transaction());
+//! conformance::run_all(&db);
+//! ```
+//!
+//! Remaining scenarios need a database of their own, i.e. to observe changes committed by earlier
+//! transactions or to run transactions from many threads. They open it through the [Backend]
+//! implemented by the test suite of a backend, which generates a test for each one of them with
+//! [conformance_tests](crate::conformance_tests):
+//!
+//! ```rust,ignore
+//! impl conformance::Backend for LmdbBackend { /* ... */ }
+//!
+//! yrs_kvstore::conformance_tests!(LmdbBackend);
+//! ```
+//!
+//! Scenarios checking heap allocations count them with [CountingAlloc], which must be installed
+//! as the global allocator of the test binary.
+//!
+//! # Panics
+//!
+//! Every scenario panics if a store doesn't behave as expected or a store operation fails.
+
+use crate::access::StaleDoc;
+use crate::archive::{
+    export_changed_since, export_store_with, import_store, ArchiveReader, BackupManifest,
+    ConflictMode,
+};
+use crate::cache::{CachedStore, DocCache};
+use crate::capabilities::Capabilities;
+use crate::collection::Collection;
+use crate::config::{
+    Compression, ConfiguredStore, CreatePolicy, FlushPolicy, RejectReason, StoreConfig,
+    UpdateFraming, UpdateValidator, ValueCodec,
+};
+use crate::deadline::Progress;
+use crate::doc_id::{DocId, DocName, Uuid};
+use crate::doc_txn::DocTransaction;
+use crate::error::Error;
+use crate::format::V1;
+use crate::keys::{
+    build_key, key_doc, key_oid, key_state_vector, key_update, parse_key, DocKey, ParsedDocKey,
+    ParsedKey, DEFAULT_CHANNEL, KEYSPACE_DOC, KEYSPACE_SYNC,
+};
+use crate::maintenance::MaintenanceOptions;
+use crate::split::SplitPolicy;
+use crate::sync_cache::sv_hash;
+use crate::verify;
+use crate::verify::{compare_stores_with, CompareMode, MetaMismatch};
+use crate::worker::{CompactionWorker, WorkerConfig};
+use crate::{ClearReport, DocOps, KVEntry, KVStore, ReadIsolation};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::{Encode, Encoder, EncoderV1};
+use yrs::{
+    Any, Array, Doc, GetString, Map, Out, ReadTxn, StateVector, Text, TextPrelim, Transact, Update,
+};
+
+/// Prefix of the names of all documents written by conformance scenarios.
+pub const DOC_PREFIX: &str = "conformance/";
+
+/// Runs all conformance scenarios against a given store.
+pub fn run_all<'a, DB: DocOps<'a>>(db: &DB)
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    key_value_semantics(db);
+    insert_and_load(db);
+    push_and_flush(db);
+    state_vectors(db);
+    metadata(db);
+    clear(db);
+    list_docs(db);
+    capabilities(db);
+}
+
+/// Verifies raw [KVStore] operations: reads observe writes made earlier through the same handle,
+/// ranges are ordered by keys and bounded inclusively by their start, [KVStore::peek_back] finds
+/// the last key at or before a given one - or nothing, if a given key precedes all keys of
+/// yrs-kvstore key spaces - [KVStore::seek] finds the first key at or after a given one - or
+/// nothing, if a given key follows all of them - and removed keys are no longer visible.
+pub fn key_value_semantics<'a, DB: DocOps<'a>>(db: &DB)
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    // keys outside of yrs-kvstore key spaces, so that they don't interfere with documents
+    let key = |i: u8| [0xf0, i];
+    for i in [5u8, 1, 3, 7].iter() {
+        db.upsert(&key(*i), &[*i; 3]).unwrap();
+    }
+    assert_eq!(db.get(&key(3)).unwrap().unwrap().as_ref(), &[3; 3]);
+    assert!(db.get(&key(4)).unwrap().is_none());
+    db.upsert(&key(3), &[33]).unwrap();
+    assert_eq!(db.get(&key(3)).unwrap().unwrap().as_ref(), &[33]);
+
+    let keys: Vec<Vec<u8>> = db
+        .iter_range(&key(1), &key(6))
+        .unwrap()
+        .map(|e| e.key().to_vec())
+        .collect();
+    assert_eq!(
+        keys,
+        vec![key(1).to_vec(), key(3).to_vec(), key(5).to_vec()]
+    );
+
+    let last = db.peek_back(&key(4)).unwrap().unwrap();
+    assert_eq!(last.key(), &key(3));
+    assert_eq!(last.value(), &[33]);
+    let last = db.peek_back(&key(5)).unwrap().unwrap();
+    assert_eq!(last.key(), &key(5));
+    // positioned past the last key or before the first one
+    assert_eq!(db.peek_back(&[0xf0, 0xff]).unwrap().unwrap().key(), &key(7));
+    assert!(db.peek_back(&[V1]).unwrap().is_none());
+
+    let first = db.seek(&key(4)).unwrap().unwrap();
+    assert_eq!(first.key(), &key(5));
+    assert_eq!(first.value(), &[5; 3]);
+    let first = db.seek(&key(3)).unwrap().unwrap();
+    assert_eq!(first.key(), &key(3));
+    assert_eq!(first.value(), &[33]);
+    // positioned before the first key or past the last one
+    assert_eq!(db.seek(&[0xf0]).unwrap().unwrap().key(), &key(1));
+    assert!(db.seek(&[0xf0, 0xff]).unwrap().is_none());
+    assert!(db.seek(&[0xff]).unwrap().is_none());
+
+    db.remove(&key(5)).unwrap();
+    assert!(db.get(&key(5)).unwrap().is_none());
+    assert_eq!(db.peek_back(&key(6)).unwrap().unwrap().key(), &key(3));
+    assert_eq!(db.seek(&key(4)).unwrap().unwrap().key(), &key(7));
+    // backends differ in whether the upper bound of a removed range is inclusive
+    db.remove_range(&key(0), &key(4)).unwrap();
+    assert!(db.get(&key(1)).unwrap().is_none());
+    assert!(db.get(&key(3)).unwrap().is_none());
+    assert_eq!(db.get(&key(7)).unwrap().unwrap().as_ref(), &[7; 3]);
+    db.remove(&key(7)).unwrap();
+    assert_eq!(db.iter_range(&key(0), &key(255)).unwrap().count(), 0);
+}
+
+/// Verifies that a document inserted with [DocOps::insert_doc] is loaded back unchanged.
+pub fn insert_and_load<'a, DB: DocOps<'a>>(db: &DB)
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let name = format!("{}insert", DOC_PREFIX);
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    text.push(&mut doc.transact_mut(), "hello");
+    db.insert_doc(&name, &doc.transact()).unwrap();
+    text.push(&mut doc.transact_mut(), " world");
+    db.insert_doc(&name, &doc.transact()).unwrap();
+
+    assert_eq!(load_text(db, &name), Some("hello world".to_string()));
+    assert_eq!(load_text(db, &format!("{}missing", DOC_PREFIX)), None);
+}
+
+/// Verifies that updates appended with [DocOps::push_update] are given subsequent sequence
+/// numbers, are applied when a document is loaded and are merged by [DocOps::flush_doc].
+pub fn push_and_flush<'a, DB: DocOps<'a>>(db: &DB)
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let name = format!("{}push", DOC_PREFIX);
+    // pending updates left by an earlier run would carry over
+    db.clear_doc(&name).unwrap();
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    for (i, chunk) in ["a", "b", "c"].iter().enumerate() {
+        let update = {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            txn.encode_update_v1()
+        };
+        let receipt = db.push_update(&name, &update).unwrap();
+        assert_eq!(receipt.seq, i as u32 + 1);
+        assert_eq!(receipt.pending_updates, i as u32 + 1);
+        assert_eq!(receipt.doc_created, i == 0);
+    }
+    assert_eq!(db.decoded_updates(&name).unwrap().count(), 3);
+    assert_eq!(load_text(db, &name), Some("abc".to_string()));
+
+    let outcome = db.flush_doc(&name).unwrap().unwrap();
+    assert_eq!(outcome.updates_folded, 3);
+    assert_eq!(db.decoded_updates(&name).unwrap().count(), 0);
+    assert!(db.flush_doc(&name).unwrap().is_none());
+    assert_eq!(load_text(db, &name), Some("abc".to_string()));
+
+    // sequence numbers start over once pending updates are flushed
+    let update = {
+        let mut txn = doc.transact_mut();
+        text.push(&mut txn, "d");
+        txn.encode_update_v1()
+    };
+    assert_eq!(db.push_update(&name, &update).unwrap().seq, 1);
+    assert_eq!(load_text(db, &name), Some("abcd".to_string()));
+}
+
+/// Verifies that stored state vectors are reported as up to date only when they cover all
+/// pending updates, and that diffs computed against them contain the missing updates.
+pub fn state_vectors<'a, DB: DocOps<'a>>(db: &DB)
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let name = format!("{}sv", DOC_PREFIX);
+    db.clear_doc(&name).unwrap();
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    text.push(&mut doc.transact_mut(), "hello");
+    db.insert_doc(&name, &doc.transact()).unwrap();
+    let (sv, up_to_date) = db.get_state_vector(&name).unwrap();
+    assert_eq!(sv, Some(doc.transact().state_vector()));
+    assert!(up_to_date);
+
+    let before = doc.transact().state_vector();
+    let base = doc
+        .transact()
+        .encode_state_as_update_v1(&StateVector::default());
+    let update = {
+        let mut txn = doc.transact_mut();
+        text.push(&mut txn, " world");
+        txn.encode_update_v1()
+    };
+    db.push_update(&name, &update).unwrap();
+    assert!(!db.get_state_vector(&name).unwrap().1);
+    assert_eq!(
+        db.state_vector_force(&name).unwrap(),
+        Some(doc.transact().state_vector())
+    );
+
+    let diff = db.get_diff(&name, &before).unwrap().unwrap();
+    let remote = Doc::new();
+    let remote_text = remote.get_or_insert_text("text");
+    {
+        let mut txn = remote.transact_mut();
+        txn.apply_update(Update::decode_v1(&base).unwrap());
+        txn.apply_update(Update::decode_v1(&diff).unwrap());
+    }
+    assert_eq!(remote_text.get_string(&remote.transact()), "hello world");
+}
+
+/// Verifies that metadata entries can be written, listed in the order of their keys, read and
+/// removed.
+pub fn metadata<'a, DB: DocOps<'a>>(db: &DB)
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let name = format!("{}meta", DOC_PREFIX);
+    db.insert_meta(&name, "b", &[2]).unwrap();
+    db.insert_meta(&name, "a", &[1]).unwrap();
+    assert_eq!(db.get_meta(&name, "a").unwrap().unwrap().as_ref(), &[1]);
+    let entries: Vec<_> = db
+        .iter_meta(&name)
+        .unwrap()
+        .map(|(key, value)| (key.to_vec(), value.to_vec()))
+        .collect();
+    assert_eq!(
+        entries,
+        vec![(b"a".to_vec(), vec![1]), (b"b".to_vec(), vec![2])]
+    );
+    db.remove_meta(&name, "a").unwrap();
+    assert!(db.get_meta(&name, "a").unwrap().is_none());
+    assert_eq!(db.iter_meta(&name).unwrap().count(), 1);
+}
+
+/// Verifies that [DocOps::clear_doc] removes all entries of a document and only them.
+pub fn clear<'a, DB: DocOps<'a>>(db: &DB)
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let name = format!("{}clear", DOC_PREFIX);
+    let other = format!("{}clear-other", DOC_PREFIX);
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    text.push(&mut doc.transact_mut(), "hello");
+    db.insert_doc(&name, &doc.transact()).unwrap();
+    db.insert_doc(&other, &doc.transact()).unwrap();
+    let update = {
+        let mut txn = doc.transact_mut();
+        text.push(&mut txn, " world");
+        txn.encode_update_v1()
+    };
+    db.push_update(&name, &update).unwrap();
+    db.insert_meta(&name, "key", &[1]).unwrap();
+
+    let report = db.clear_doc(&name).unwrap();
+    assert!(report.doc_state_removed);
+    assert_eq!(report.updates_removed, 1);
+    assert!(report.meta_removed >= 1);
+    assert_eq!(load_text(db, &name), None);
+    assert!(db.get_meta(&name, "key").unwrap().is_none());
+    assert_eq!(load_text(db, &other), Some("hello".to_string()));
+}
+
+/// Verifies that documents are listed in the order of their names.
+pub fn list_docs<'a, DB: DocOps<'a>>(db: &DB)
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let prefix = format!("{}list/", DOC_PREFIX);
+    let doc = Doc::new();
+    doc.get_or_insert_text("text")
+        .push(&mut doc.transact_mut(), "hello");
+    for name in ["c", "a", "b"].iter() {
+        db.insert_doc(&format!("{}{}", prefix, name), &doc.transact())
+            .unwrap();
+    }
+    let names: Vec<Box<[u8]>> = db.iter_docs_prefix(&prefix).unwrap().collect();
+    let expected: Vec<Box<[u8]>> = ["a", "b", "c"]
+        .iter()
+        .map(|name| format!("{}{}", prefix, name).into_bytes().into())
+        .collect();
+    assert_eq!(names, expected);
+}
+
+/// Verifies every capability declared by a store with [KVStore::CAPABILITIES]:
+///
+/// - [Capabilities::REVERSE_ITER]: [KVStore::iter_range_rev] visits entries of a range -
+///   including ones written and skipping ones removed through the same handle - in descending
+///   order, and stops once its callback returns `false`,
+/// - [Capabilities::BOUNDED_PEEK_BACK]: [KVStore::peek_back] finds the last key at or before
+///   a given one, skipping keys removed through the same handle,
+/// - [Capabilities::MULTI_GET]: [KVStore::get_many] returns the same values as [KVStore::get],
+/// - [Capabilities::SNAPSHOTS]: stores reporting it with [DocOps::capabilities] read from
+///   a snapshot,
+/// - [Capabilities::ATOMIC_COUNTERS] isn't declared, since no operation is backed by it yet.
+pub fn capabilities<'a, DB: DocOps<'a>>(db: &DB)
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let declared = DB::CAPABILITIES;
+    // keys outside of yrs-kvstore key spaces, so that they don't interfere with documents
+    let key = |i: u8| [0xf1, i];
+    for i in [5u8, 1, 3, 7, 9].iter() {
+        db.upsert(&key(*i), &[*i; 2]).unwrap();
+    }
+    db.remove(&key(9)).unwrap();
+
+    if declared.contains(Capabilities::REVERSE_ITER) {
+        let mut visited = Vec::new();
+        db.iter_range_rev(&key(2), &key(8), &mut |key, value| {
+            visited.push((key.to_vec(), value.to_vec()));
+            true
+        })
+        .unwrap();
+        let expected: Vec<_> = [7u8, 5, 3]
+            .iter()
+            .map(|i| (key(*i).to_vec(), vec![*i; 2]))
+            .collect();
+        assert_eq!(visited, expected);
+
+        let mut visited = Vec::new();
+        db.iter_range_rev(&key(0), &key(10), &mut |key, _| {
+            visited.push(key.to_vec());
+            visited.len() < 2
+        })
+        .unwrap();
+        assert_eq!(visited, vec![key(7).to_vec(), key(5).to_vec()]);
+
+        let mut visited = 0;
+        db.iter_range_rev(&key(8), &key(10), &mut |_, _| {
+            visited += 1;
+            true
+        })
+        .unwrap();
+        assert_eq!(visited, 0);
+    }
+
+    if declared.contains(Capabilities::BOUNDED_PEEK_BACK) {
+        assert_eq!(db.peek_back(&key(10)).unwrap().unwrap().key(), &key(7));
+        assert_eq!(db.peek_back(&key(4)).unwrap().unwrap().key(), &key(3));
+        assert_eq!(db.peek_back(&key(1)).unwrap().unwrap().value(), &[1; 2]);
+    }
+
+    if declared.contains(Capabilities::MULTI_GET) {
+        let keys = [key(3), key(4), key(9), key(3), key(1)];
+        let keys: Vec<&[u8]> = keys.iter().map(|key| key.as_ref()).collect();
+        let values = db.get_many(&keys).unwrap();
+        let expected: Vec<Option<Vec<u8>>> = keys
+            .iter()
+            .map(|key| db.get(key).unwrap().map(|value| value.as_ref().to_vec()))
+            .collect();
+        assert_eq!(values, expected);
+        assert_eq!(values[0], Some(vec![3; 2]));
+        assert_eq!(values[2], None);
+    }
+
+    if db.capabilities().contains(Capabilities::SNAPSHOTS) {
+        assert!(declared.contains(Capabilities::SNAPSHOTS));
+        assert_eq!(db.read_isolation(), ReadIsolation::Snapshot);
+        assert!(db.read_snapshot().is_consistent());
+    }
+
+    assert!(
+        !declared.contains(Capabilities::ATOMIC_COUNTERS),
+        "atomic counters are not used by any operation yet"
+    );
+
+    db.remove_range(&key(0), &key(10)).unwrap();
+    assert_eq!(db.iter_range(&key(0), &key(255)).unwrap().count(), 0);
+}
+
+/// Database of a backend under test, opened by scenarios which need one of their own. See
+/// [module documentation](self).
+///
+/// Clones share the same database, so that scenarios can hand them over to other threads.
+pub trait Backend: Clone + Send + Sync + 'static {
+    /// Store over a single transaction of the backend.
+    type Store<'t>: DocOps<'t> + KVStore<'t, Error = Error>;
+
+    /// Opens an empty database in a new temporary directory, which name starts with `name`.
+    fn open(name: &str) -> Self;
+
+    /// Calls `f` with a store over a new read-write transaction and commits it with the store
+    /// returned by `f`, together with the result.
+    fn transaction<R>(&self, f: impl for<'t> TransactionFn<'t, Self::Store<'t>, R>) -> R;
+
+    /// Calls `f` with a store over a new read-only transaction, or a read-write one which is
+    /// rolled back once `f` returns, if the backend doesn't have read-only transactions.
+    fn read<R>(&self, f: impl for<'t> FnOnce(Self::Store<'t>) -> R) -> R;
+
+    /// Calls `f` with a store over a new read-write transaction, which is rolled back once `f`
+    /// returns.
+    fn abort<R>(&self, f: impl for<'t> FnOnce(&Self::Store<'t>) -> R) -> R;
+
+    /// Calls `f` with a store over a new read-write transaction, which is committed if `f`
+    /// succeeds and rolled back otherwise.
+    fn try_write<R, E>(
+        &self,
+        f: impl for<'t> FnOnce(&Self::Store<'t>) -> Result<R, E>,
+    ) -> Result<R, E>;
+
+    /// Calls `f` with a function opening a new store every time it's called. Changes made through
+    /// these stores are rolled back once `f` returns.
+    fn open_stores<R>(&self, f: impl for<'t> FnOnce(&dyn Fn() -> Self::Store<'t>) -> R) -> R;
+
+    /// Calls `f` with a store over a new read-write transaction, which is committed once `f`
+    /// returns.
+    fn write<R>(&self, f: impl for<'t> FnOnce(&Self::Store<'t>) -> R) -> R {
+        self.transaction(|db| {
+            let result = f(&db);
+            (db, result)
+        })
+    }
+
+    /// Calls `f` with a store over a new read-write transaction configured with a given `config`,
+    /// which is committed once `f` returns.
+    fn write_configured<R>(
+        &self,
+        config: &StoreConfig,
+        f: impl for<'t> FnOnce(&ConfiguredStore<Self::Store<'t>>) -> R,
+    ) -> R {
+        self.transaction(|db| {
+            let db = ConfiguredStore::new(db, config.clone());
+            let result = f(&db);
+            (db.into_inner(), result)
+        })
+    }
+}
+
+/// Closure passed to [Backend::transaction]. The `'t` parameter only names the lifetime of the
+/// store `S`, which a closure returning `S` couldn't be generic over otherwise.
+pub trait TransactionFn<'t, S, R>: FnOnce(S) -> (S, R) {}
+
+impl<'t, S, R, F: FnOnce(S) -> (S, R)> TransactionFn<'t, S, R> for F {}
+
+/// Generates a test for every scenario, which needs a database of its own, running it against
+/// a database opened by a given [Backend](conformance::Backend), as well as for scenarios which
+/// need no database, but the [CountingAlloc](conformance::CountingAlloc) of the test binary.
+/// Tests are named after the scenarios.
+#[macro_export]
+macro_rules! conformance_tests {
+    ($backend:ty) => {
+        $crate::conformance_tests!(@tests $backend; access_tracking, clear_doc_if, collections, compact_client_ids, decode_error_context, doc_guids, doc_keys, doc_meta_iter, doc_meta_iter_pending_updates, doc_quota, doc_version, flush_options, flush_outcome, load_doc_allocations, load_doc_at_seq, load_doc_budgeted, load_doc_twice, malformed_entries, merge_docs, meta_refs, orphan_adoption, push_update_long_log, replace_doc, seek_db_edges, segmented_updates, size_history, snapshots, split_docs, state_vector_force, update_channels, update_feed, verify_state_vectors, with_doc, write_fencing, init_meta_once, audit_log, blob_storage, drafts, incomplete_history, list_docs_with_timestamps, manual_clock, missing_doc_state, sync_frame_cache, tenant_usage, create_get_remove, multi_insert, incremental_updates, state_vector_updates_only, state_diff_from_updates, state_diff_from_doc, doc_meta, doc_iter, doc_archive, doc_uuid_names, store_config, intent_log, format_golden, maintenance, sv_consistency, op_deadline, op_deadline_codec, remove_range_count, flush_doc_with_hook, compaction_worker, sync_step1, state_vector_fast_path, doc_transaction_retry, import_updates, create_policy, update_validator, read_your_writes, doc_cache, archive_round_trip, compare_stores, incremental_backup, export_docs_consistent, io_stats, memo, prelude, flush_policy_workload, dual_store);
+        $crate::conformance_tests!(@plain key_allocations);
+    };
+    (@tests $backend:ty; $($name:ident),*) => {
+        $(
+            #[test]
+            fn $name() {
+                use $crate::conformance::Backend;
+                let backend = <$backend>::open(stringify!($name));
+                $crate::conformance::$name(&backend);
+            }
+        )*
+    };
+    (@plain $($name:ident),*) => {
+        $(
+            #[test]
+            fn $name() {
+                $crate::conformance::$name();
+            }
+        )*
+    };
+}
+
+/// Global allocator counting heap allocations separately for every thread, so that tests running
+/// concurrently don't affect each other's counts. See [allocations].
+pub struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| {
+            let (allocations, bytes) = count.get();
+            count.set((allocations + 1, bytes + layout.size()))
+        });
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Returns the number of heap allocations made by the current thread so far, counted by
+/// [CountingAlloc].
+pub fn allocations() -> usize {
+    ALLOCATIONS.with(|count| count.get().0)
+}
+
+/// Returns the total size in bytes of heap allocations made by the current thread so far,
+/// counted by [CountingAlloc].
+pub fn allocated_bytes() -> usize {
+    ALLOCATIONS.with(|count| count.get().1)
+}
+
+/// Verifies that sampled reads record document accesses, which make documents not accessed since a
+/// given time stale and evictable, while writes don't count as accesses.
+pub fn access_tracking<B: Backend>(backend: &B) {
+    fn names(stale: Vec<StaleDoc>) -> Vec<String> {
+        stale
+            .into_iter()
+            .map(|(name, _)| String::from_utf8(name.into()).unwrap())
+            .collect()
+    }
+
+    let config = StoreConfig {
+        access_sample_rate: Some(1),
+        ..StoreConfig::DEFAULT
+    };
+    let doc = Doc::with_client_id(1);
+    let text = doc.get_or_insert_text("text");
+    let update = {
+        let mut txn = doc.transact_mut();
+        text.push(&mut txn, "hello");
+        txn.encode_update_v1()
+    };
+
+    backend.write_configured(&config, |db| {
+        for name in ["a", "b", "c"] {
+            db.push_update(name, &update).unwrap();
+        }
+        // writes don't count as accesses
+        assert_eq!(db.access_stats("a").unwrap(), None);
+        let stale: Vec<_> = db.iter_docs_by_staleness(10).unwrap().collect();
+        assert_eq!(names(stale), vec!["a", "b", "c"]);
+
+        assert!(db.load_doc("b", &mut Doc::new().transact_mut()).unwrap());
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(db.load_doc("a", &mut Doc::new().transact_mut()).unwrap());
+        assert!(db.load_doc("a", &mut Doc::new().transact_mut()).unwrap());
+        let a = db.access_stats("a").unwrap().unwrap();
+        let b = db.access_stats("b").unwrap().unwrap();
+        assert_eq!(a.count, 2);
+        assert_eq!(b.count, 1);
+        assert!(a.last_access > b.last_access);
+        let stale: Vec<_> = db.iter_docs_by_staleness(3).unwrap().collect();
+        assert_eq!(stale[0].1, None);
+        assert_eq!(stale[1].1, Some(b));
+        assert_eq!(names(stale), vec!["c", "b", "a"]);
+
+        // diffs are reads as well
+        std::thread::sleep(Duration::from_millis(10));
+        db.get_diff("c", &StateVector::default()).unwrap().unwrap();
+        assert_eq!(db.access_stats("c").unwrap().unwrap().count, 1);
+        let stale: Vec<_> = db.iter_docs_by_staleness(2).unwrap().collect();
+        assert_eq!(names(stale), vec!["b", "a"]);
+
+        // reads made without access tracking don't record accesses
+        let db: &B::Store<'_> = db;
+        assert!(db.load_doc("b", &mut Doc::new().transact_mut()).unwrap());
+        assert_eq!(db.access_stats("b").unwrap(), Some(b));
+
+        // evicted documents are gone
+        db.clear_doc("b").unwrap();
+        assert_eq!(db.access_stats("b").unwrap(), None);
+        let stale: Vec<_> = db.iter_docs_by_staleness(10).unwrap().collect();
+        assert_eq!(names(stale), vec!["a", "c"]);
+    });
+}
+
+/// Verifies that documents are cleared only once a guard holds for them: emptiness, size threshold,
+/// metadata flag or unchanged version.
+pub fn clear_doc_if<B: Backend>(backend: &B) {
+    use crate::guard::{ClearGuard, ClearRefusal};
+
+    backend.write(|db| {
+        let refusal = |res: Result<ClearReport, Error>| match res {
+            Err(Error::ClearRefused(refusal)) => refusal,
+            other => panic!("expected refusal, got {:?}", other),
+        };
+        let exists = |name: &str| db.get_meta(name, "kind").unwrap().is_some();
+        let doc = Doc::with_client_id(1);
+        let text = doc.get_or_insert_text("text");
+        let push = |name: &str, content: &str| {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, content);
+            db.push_update(name, &txn.encode_update_v1()).unwrap();
+        };
+
+        // empty placeholders can be cleared, documents with contents cannot
+        db.insert_meta("placeholder", "kind", b"placeholder")
+            .unwrap();
+        db.insert_meta("doc", "kind", b"notes").unwrap();
+        push("doc", "hello");
+        let res = db.clear_doc_if("doc", ClearGuard::Empty);
+        assert_eq!(refusal(res), ClearRefusal::NotEmpty);
+        db.flush_doc("doc").unwrap();
+        let res = db.clear_doc_if("doc", ClearGuard::Empty);
+        assert_eq!(refusal(res), ClearRefusal::NotEmpty);
+        assert!(exists("doc"));
+        let report = db.clear_doc_if("placeholder", ClearGuard::Empty).unwrap();
+        assert_eq!(report.meta_removed, 1);
+        assert!(!exists("placeholder"));
+
+        // size threshold covers document state and pending updates
+        push("doc", " world");
+        let bytes = match refusal(db.clear_doc_if("doc", ClearGuard::MaxBytes(16))) {
+            ClearRefusal::TooLarge { bytes, limit } => {
+                assert_eq!(limit, 16);
+                bytes
+            }
+            other => panic!("unexpected refusal {:?}", other),
+        };
+        assert!(bytes > 16);
+
+        // metadata flag
+        let flag = ClearGuard::MetaFlag(b"disposable".to_vec());
+        let res = db.clear_doc_if("doc", flag.clone());
+        assert_eq!(refusal(res), ClearRefusal::MissingFlag);
+        assert!(exists("doc"));
+
+        // version token read before the document has changed
+        let version = db.doc_version("doc").unwrap().unwrap();
+        push("doc", "!");
+        let current = db.doc_version("doc").unwrap().unwrap();
+        let res = db.clear_doc_if("doc", ClearGuard::Version(version));
+        assert_eq!(refusal(res), ClearRefusal::VersionMismatch { current });
+        assert!(exists("doc"));
+
+        // document is cleared once the guard holds
+        db.insert_meta("doc", "disposable", b"").unwrap();
+        let report = db.clear_doc_if("doc", flag).unwrap();
+        assert!(report.doc_state_removed);
+        assert_eq!(report.updates_removed, 2);
+        assert!(!exists("doc"));
+        assert!(!db.load_doc("doc", &mut Doc::new().transact_mut()).unwrap());
+
+        push("small", "abc");
+        let version = db.doc_version("small").unwrap().unwrap();
+        let report = db
+            .clear_doc_if("small", ClearGuard::Version(version))
+            .unwrap();
+        assert_eq!(report.updates_removed, 1);
+        push("small", "abc");
+        db.clear_doc_if("small", ClearGuard::MaxBytes(bytes))
+            .unwrap();
+        assert!(db.doc_version("small").unwrap().is_none());
+
+        // clearing missing documents is a no-op for any guard
+        let report = db
+            .clear_doc_if("missing", ClearGuard::Version(current))
+            .unwrap();
+        assert_eq!(report, ClearReport::default());
+    });
+}
+
+/// Verifies that documents are grouped into nested collections by their names, and that clearing a
+/// collection clears the nested ones as well.
+pub fn collections<B: Backend>(backend: &B) {
+    backend.write(|db| {
+        let org = Collection::new(["org"]);
+        let project = org.child("project");
+        let sub = project.child("sub");
+        assert!(db.create_collection(&sub).unwrap());
+        assert!(!db.create_collection(&sub).unwrap());
+        db.create_collection(&project).unwrap();
+        db.create_collection(&org).unwrap();
+        assert!(!db.collection_exists(&org.child("other")).unwrap());
+
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        text.push(&mut doc.transact_mut(), "hello");
+        let update = doc
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        let names = [
+            org.doc_name("readme"),
+            project.doc_name("a"),
+            project.doc_name("b/c"), // contains separator
+            sub.doc_name("d"),
+            b"org-doc".to_vec(), // shares prefix with collection, but not stored in it
+        ];
+        for name in names.iter() {
+            db.push_update(name, &update).unwrap();
+        }
+        assert_eq!(project.doc_name("b/c"), b"org/project/b\\/c".to_vec());
+        let (collection, name) = Collection::split_doc_name(&project.doc_name("b/c"));
+        assert_eq!(collection, project);
+        assert_eq!(name, b"b/c".to_vec());
+
+        let list = |c: &Collection| -> Vec<Vec<u8>> {
+            db.iter_collection(c)
+                .unwrap()
+                .into_iter()
+                .map(|name| name.to_vec())
+                .collect()
+        };
+        assert_eq!(list(&org), vec![org.doc_name("readme")]);
+        assert_eq!(
+            list(&project),
+            vec![project.doc_name("a"), project.doc_name("b/c")]
+        );
+        assert_eq!(list(&sub), vec![sub.doc_name("d")]);
+        assert_eq!(list(&Collection::root()), vec![b"org-doc".to_vec()]);
+
+        // clearing middle-level collection removes nested ones as well
+        assert_eq!(db.clear_collection(&project).unwrap(), 3);
+        assert!(list(&project).is_empty());
+        assert!(list(&sub).is_empty());
+        assert!(!db.collection_exists(&project).unwrap());
+        assert!(!db.collection_exists(&sub).unwrap());
+        assert!(db.collection_exists(&org).unwrap());
+        assert_eq!(list(&org), vec![org.doc_name("readme")]);
+        let loaded = Doc::new();
+        assert!(db
+            .load_doc(&org.doc_name("readme"), &mut loaded.transact_mut())
+            .unwrap());
+        assert!(!db
+            .load_doc(&sub.doc_name("d"), &mut loaded.transact_mut())
+            .unwrap());
+    });
+}
+
+/// Verifies that a document edited by many short-lived clients is rewritten under a single client
+/// ID, keeping its contents intact.
+pub fn compact_client_ids<B: Backend>(backend: &B) {
+    use crate::client_ids::{decode_client_ids, CompactionRefusal};
+    use crate::error::Error;
+    use crate::format::META_CLIENT_IDS;
+    use std::sync::Arc;
+    use yrs::types::text::YChange;
+    use yrs::types::Attrs;
+
+    backend.write(|db| {
+        // every edit comes from a different, short-lived client
+        let expected = Doc::with_client_id(1);
+        for client in 1..=200u64 {
+            let doc = Doc::with_client_id(client);
+            let text = doc.get_or_insert_text("text");
+            let meta = doc.get_or_insert_map("meta");
+            let mut txn = doc.transact_mut();
+            txn.apply_update(
+                Update::decode_v1(
+                    &expected
+                        .transact()
+                        .encode_state_as_update_v1(&StateVector::default()),
+                )
+                .unwrap(),
+            );
+            let before = txn.state_vector();
+            let index = text.len(&txn);
+            if client % 50 == 0 {
+                let bold: Attrs = HashMap::from([(Arc::from("bold"), Any::Bool(true))]);
+                text.insert_with_attributes(&mut txn, index, "!", bold);
+            } else {
+                text.insert(&mut txn, index, "x");
+            }
+            meta.insert(&mut txn, "edits", client as f64);
+            let update = txn.encode_diff_v1(&before);
+            drop(txn);
+            expected
+                .transact_mut()
+                .apply_update(Update::decode_v1(&update).unwrap());
+            db.push_update("doc", &update).unwrap();
+            if client == 100 {
+                db.flush_doc("doc").unwrap().unwrap();
+            }
+        }
+        let content = |doc: &Doc| {
+            let txn = doc.transact();
+            let text = txn.get_text("text").unwrap();
+            let chunks: Vec<_> = text
+                .diff(&txn, YChange::identity)
+                .into_iter()
+                .map(|d| (d.insert, d.attributes))
+                .collect();
+            let edits = txn.get_map("meta").unwrap().get(&txn, "edits");
+            (text.get_string(&txn), chunks, edits)
+        };
+
+        let compaction = db.compact_client_ids("doc").unwrap().unwrap();
+        let client = compaction.client.unwrap();
+        assert_eq!(compaction.clients_before, 200);
+        assert_eq!(compaction.clients_after, 1);
+        assert!(compaction.sv_bytes_after * 50 < compaction.sv_bytes_before);
+        let (sv, _) = db.get_state_vector("doc").unwrap();
+        assert_eq!(sv.unwrap().len(), 1);
+        assert_eq!(db.last_update_seq("doc").unwrap(), None);
+        let loaded = Doc::new();
+        assert!(db.load_doc("doc", &mut loaded.transact_mut()).unwrap());
+        assert_eq!(content(&loaded), content(&expected));
+        assert!(loaded.transact().state_vector().contains_client(&client));
+        let mapping = db.get_meta("doc", META_CLIENT_IDS).unwrap().unwrap();
+        let (recorded, before) = decode_client_ids(mapping.as_ref()).unwrap();
+        assert_eq!(recorded, client);
+        assert_eq!(before, expected.transact().state_vector());
+
+        // compacted document has a single client and is left intact
+        let again = db.compact_client_ids("doc").unwrap().unwrap();
+        assert_eq!(again.client, None);
+        assert_eq!(again.clients_before, 1);
+        assert!(db.compact_client_ids("missing").unwrap().is_none());
+
+        // snapshots refer to the old client IDs
+        db.push_update(
+            "snap",
+            &expected
+                .transact()
+                .encode_state_as_update_v1(&StateVector::default()),
+        )
+        .unwrap();
+        db.insert_snapshot("snap", "v1", &expected.transact().snapshot())
+            .unwrap();
+        assert!(matches!(
+            db.compact_client_ids("snap"),
+            Err(Error::CompactionRefused(CompactionRefusal::Snapshots))
+        ));
+    });
+}
+
+/// Verifies that decoding errors point at the document and the entry which has failed to decode,
+/// leaving other entries readable.
+pub fn decode_error_context<B: Backend>(backend: &B) {
+    backend.write(|db| {
+        let doc = Doc::with_client_id(1);
+        let text = doc.get_or_insert_text("text");
+        text.push(&mut doc.transact_mut(), "hello");
+        db.insert_doc("state", &doc.transact()).unwrap();
+        for chunk in ["a", "b", "c"].iter() {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            db.push_update("updates", &txn.encode_update_v1()).unwrap();
+        }
+        // inject corrupted document state and a single corrupted update
+        let garbage = [1, 2];
+        db.upsert(&key_doc(1), &garbage).unwrap();
+        db.upsert(&key_update(2, 2), &garbage).unwrap();
+
+        let load = |name: &str| db.load_doc(name, &mut Doc::new().transact_mut());
+        let assert_state_error = |e: Option<Error>| match e {
+            Some(Error::DocStateDecode { doc, .. }) => assert_eq!(doc, b"state"),
+            Some(e) => panic!("unexpected error {}", e),
+            None => panic!("corrupted state has been decoded"),
+        };
+        let assert_update_error = |e: Option<Error>| match e {
+            Some(Error::UpdateDecode { doc, seq, .. }) => {
+                assert_eq!(doc, b"updates");
+                assert_eq!(seq, Some(2));
+            }
+            Some(e) => panic!("unexpected error {}", e),
+            None => panic!("corrupted update has been decoded"),
+        };
+        assert_state_error(load("state").err());
+        assert_state_error(db.get_diff("state", &StateVector::default()).err());
+        assert_update_error(load("updates").err());
+        assert_update_error(db.get_diff("updates", &StateVector::default()).err());
+        assert_update_error(db.flush_doc("updates").err());
+
+        // only the corrupted update fails to decode
+        let updates: Vec<_> = db.decoded_updates("updates").unwrap().collect();
+        assert_eq!(updates.len(), 3);
+        assert!(matches!(updates[0], Ok((1, _))));
+        assert!(matches!(updates[2], Ok((3, _))));
+        let mut updates = updates;
+        assert_update_error(updates.remove(1).err());
+
+        let e = load("updates").unwrap_err().to_string();
+        assert!(
+            e.starts_with("cannot decode update 2 of document 'updates'"),
+            "{}",
+            e
+        );
+    });
+}
+
+/// Verifies that a document GUID recorded when the document is persisted first is kept, cannot be
+/// claimed by another document and is released once the document is cleared.
+pub fn doc_guids<B: Backend>(backend: &B) {
+    let doc_with_guid = |guid: &str, content: &str| {
+        let doc = Doc::with_options(yrs::Options::with_guid_and_client_id(guid.into(), 1));
+        let text = doc.get_or_insert_text("text");
+        text.push(&mut doc.transact_mut(), content);
+        doc
+    };
+
+    backend.write(|db| {
+        db.insert_doc_with_guid("a", &doc_with_guid("guid-a", "a"))
+            .unwrap();
+        assert_eq!(db.doc_by_guid("guid-a").unwrap(), Some(b"a".to_vec()));
+        assert_eq!(db.guid_of("a").unwrap(), Some("guid-a".to_string()));
+
+        // GUID recorded when document has been persisted first is kept
+        db.insert_doc_with_guid("a", &doc_with_guid("guid-other", "a2"))
+            .unwrap();
+        assert_eq!(db.guid_of("a").unwrap(), Some("guid-a".to_string()));
+        assert_eq!(db.doc_by_guid("guid-other").unwrap(), None);
+
+        // documents persisted without GUID don't have one
+        db.insert_doc("c", &doc_with_guid("guid-c", "c").transact())
+            .unwrap();
+        assert_eq!(db.guid_of("c").unwrap(), None);
+        assert_eq!(db.guid_of("missing").unwrap(), None);
+        assert_eq!(db.doc_by_guid("guid-c").unwrap(), None);
+
+        // another document cannot claim the same GUID
+        let err = db
+            .insert_doc_with_guid("b", &doc_with_guid("guid-a", "b"))
+            .unwrap_err();
+        match err {
+            Error::GuidConflict { owner } => assert_eq!(owner, b"a".to_vec()),
+            other => panic!("unexpected error: {}", other),
+        }
+        let doc = Doc::new();
+        assert!(!db.load_doc("b", &mut doc.transact_mut()).unwrap());
+        assert_eq!(db.doc_by_guid("guid-a").unwrap(), Some(b"a".to_vec()));
+
+        // clearing a document releases its GUID
+        db.clear_doc("a").unwrap();
+        assert_eq!(db.doc_by_guid("guid-a").unwrap(), None);
+        assert_eq!(db.guid_of("a").unwrap(), None);
+        db.insert_doc_with_guid("b", &doc_with_guid("guid-a", "b"))
+            .unwrap();
+        assert_eq!(db.doc_by_guid("guid-a").unwrap(), Some(b"b".to_vec()));
+        assert_eq!(db.guid_of("b").unwrap(), Some("guid-a".to_string()));
+    });
+}
+
+/// Verifies that typed document keys are ordered by their values and can be used to read documents
+/// back.
+pub fn doc_keys<B: Backend>(backend: &B) {
+    backend.write(|db| {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        text.push(&mut doc.transact_mut(), "hello");
+        let update = doc
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+
+        let keys = [
+            DocKey::from(10u64),
+            DocKey::from(9u64),
+            DocKey::from((7u64, 10u64)),
+            DocKey::from((7u64, 9u64)),
+            DocKey::from((8u64, 1u64)),
+            DocKey::from((6u64, u64::MAX)),
+            DocKey::from("doc"),
+            DocKey::from(b"bin".as_ref()),
+        ];
+        for key in keys.iter() {
+            db.push_update(key, &update).unwrap();
+        }
+
+        // numeric keys are ordered by their values, tags keep encodings apart
+        let names: Vec<Box<[u8]>> = db.iter_docs().unwrap().collect();
+        let parsed: Vec<ParsedDocKey> = names.iter().map(|name| DocKey::parse(name)).collect();
+        assert_eq!(
+            parsed,
+            vec![
+                ParsedDocKey::Bytes(b"bin"),
+                ParsedDocKey::Bytes(b"doc"),
+                ParsedDocKey::U64Pair(6, u64::MAX),
+                ParsedDocKey::U64Pair(7, 9),
+                ParsedDocKey::U64Pair(7, 10),
+                ParsedDocKey::U64Pair(8, 1),
+                ParsedDocKey::U64(9),
+                ParsedDocKey::U64(10),
+            ]
+        );
+
+        let workspace: Vec<Box<[u8]>> = db
+            .iter_docs_prefix(&DocKey::workspace(7))
+            .unwrap()
+            .collect();
+        assert_eq!(
+            workspace,
+            vec![
+                DocKey::from((7u64, 9u64)).as_ref().into(),
+                DocKey::from((7u64, 10u64)).as_ref().into(),
+            ]
+        );
+        assert_eq!(
+            db.iter_docs_prefix(&DocKey::workspace(5)).unwrap().count(),
+            0
+        );
+        assert_eq!(db.iter_docs_prefix(&[0xffu8][..]).unwrap().count(), 0);
+
+        // documents can be read back using the same keys
+        let doc = Doc::new();
+        db.load_doc(&DocKey::from((7u64, 9u64)), &mut doc.transact_mut())
+            .unwrap();
+        let text = doc.get_or_insert_text("text");
+        assert_eq!(text.get_string(&doc.transact()), "hello");
+    });
+}
+
+/// Verifies that iterating over metadata of a document returns its entries only, in the order of
+/// their keys.
+pub fn doc_meta_iter<B: Backend>(backend: &B) {
+    backend.write(|db| {
+        db.insert_meta("A", "key1", [1].as_ref()).unwrap();
+        db.insert_meta("B", "key2", [2].as_ref()).unwrap();
+        db.insert_meta("B", "key3", [3].as_ref()).unwrap();
+        db.insert_meta("C", "key4", [4].as_ref()).unwrap();
+
+        let mut i = db.iter_meta("B").unwrap();
+        assert_eq!(i.next(), Some(("key2".as_bytes().into(), [2].into())));
+        assert_eq!(i.next(), Some(("key3".as_bytes().into(), [3].into())));
+        assert!(i.next().is_none());
+    });
+}
+
+/// Verifies that the pending updates counter, stored right past metadata entries of a document, is
+/// not returned as metadata.
+pub fn doc_meta_iter_pending_updates<B: Backend>(backend: &B) {
+    backend.write(|db| {
+        // pending updates counter is stored right past the metadata entries of a document
+        db.insert_meta("doc", "key1", [1].as_ref()).unwrap();
+        db.push_update("doc", &[0, 0]).unwrap();
+        db.push_update("doc", &[0, 0]).unwrap();
+        db.insert_meta("doc", "key2", [2].as_ref()).unwrap();
+        db.insert_meta("other", "key3", [3].as_ref()).unwrap();
+
+        let meta: Vec<_> = db.iter_meta("doc").unwrap().collect();
+        assert_eq!(
+            meta,
+            vec![
+                ("key1".as_bytes().into(), [1].into()),
+                ("key2".as_bytes().into(), [2].into())
+            ]
+        );
+    });
+}
+
+/// Verifies that pushes are refused once a document exceeds its quota, which a flush frees up
+/// again, and that per-document quotas take precedence over the default one.
+pub fn doc_quota<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    backend.write(|db| {
+        db.set_default_max_doc_bytes(Some(256)).unwrap();
+
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        db.insert_doc(DOC_NAME, &doc.transact()).unwrap();
+
+        // push small updates until document quota is exhausted
+        let err = loop {
+            let sv = doc.transact().state_vector();
+            text.push(&mut doc.transact_mut(), "a");
+            let update = doc.transact().encode_diff_v1(&sv);
+            if let Err(e) = db.push_update(DOC_NAME, &update) {
+                break e;
+            }
+        };
+        match err {
+            Error::QuotaExceeded {
+                used,
+                limit,
+                flushable,
+            } => {
+                assert_eq!(limit, 256);
+                assert!(used <= limit);
+                assert!(flushable > 0);
+            }
+            other => panic!("expected quota error, got: {}", other),
+        }
+
+        // flush merges pending updates into compact document state
+        db.flush_doc(DOC_NAME).unwrap();
+        let sv = doc.transact().state_vector();
+        text.push(&mut doc.transact_mut(), "b");
+        let update = doc.transact().encode_diff_v1(&sv);
+        db.push_update(DOC_NAME, &update).unwrap();
+
+        // per-document quota takes precedence over default one
+        db.set_max_doc_bytes(DOC_NAME, Some(1)).unwrap();
+        let res = db.push_update(DOC_NAME, &update);
+        assert!(matches!(res, Err(Error::QuotaExceeded { limit: 1, .. })));
+        db.set_max_doc_bytes(DOC_NAME, None).unwrap();
+        db.push_update(DOC_NAME, &update).unwrap();
+    });
+}
+
+/// Verifies that the version of a document moves forward with every write, but not with reads or
+/// metadata changes, and survives flushes.
+pub fn doc_version<B: Backend>(backend: &B) {
+    use crate::version::DocVersion;
+
+    let doc = Doc::with_client_id(1);
+    let text = doc.get_or_insert_text("text");
+    let edit = |chunk: &str| {
+        let mut txn = doc.transact_mut();
+        text.push(&mut txn, chunk);
+        txn.encode_update_v1()
+    };
+
+    backend.write(|db| {
+        assert_eq!(db.doc_version("doc").unwrap(), None);
+        db.push_update("doc", &edit("a")).unwrap();
+        let v1 = db.doc_version("doc").unwrap().unwrap();
+
+        // reads and metadata don't change the version
+        let loaded = Doc::new();
+        db.load_doc("doc", &mut loaded.transact_mut()).unwrap();
+        assert_eq!(db.doc_version("doc").unwrap(), Some(v1));
+        db.insert_meta("doc", "owner", b"alice").unwrap();
+        assert_eq!(db.doc_version("doc").unwrap(), Some(v1));
+
+        db.push_update("doc", &edit("b")).unwrap();
+        let v2 = db.doc_version("doc").unwrap().unwrap();
+        assert_ne!(v2, v1);
+        assert!(v2.is_newer_than(&v1));
+        assert!(!v1.is_newer_than(&v2));
+        assert!(!v2.is_newer_than(&v2));
+
+        db.flush_doc("doc").unwrap().unwrap();
+        let v3 = db.doc_version("doc").unwrap().unwrap();
+        assert_ne!(v3, v2);
+        assert!(v3.is_newer_than(&v2));
+
+        // sequence numbers start over after a flush, but the version still moves forward
+        db.push_update("doc", &edit("c")).unwrap();
+        let v4 = db.doc_version("doc").unwrap().unwrap();
+        assert_ne!(v4, v3);
+        assert!(v4.is_newer_than(&v3));
+        assert!(v4.is_newer_than(&v1));
+
+        // tokens can be embedded in headers and parsed back
+        let token = v4.to_string();
+        assert_eq!(token.parse::<DocVersion>(), Ok(v4));
+        assert!("".parse::<DocVersion>().is_err());
+        assert!("x.0.1.0".parse::<DocVersion>().is_err());
+        assert!(format!("{}.0", token).parse::<DocVersion>().is_err());
+
+        db.clear_doc("doc").unwrap();
+        assert_eq!(db.doc_version("doc").unwrap(), None);
+        db.push_update("doc", &edit("d")).unwrap();
+        let v5 = db.doc_version("doc").unwrap().unwrap();
+        assert_ne!(v5, v1);
+        assert!(v5.is_newer_than(&v4));
+    });
+}
+
+/// Verifies that flush options are persisted with a document and reused by later flushes, unless
+/// overridden.
+pub fn flush_options<B: Backend>(backend: &B) {
+    backend.write(|db| {
+        let no_gc = || yrs::Options {
+            skip_gc: true,
+            ..yrs::Options::default()
+        };
+        let doc = Doc::with_options(no_gc());
+        let text = doc.get_or_insert_text("text");
+        text.push(&mut doc.transact_mut(), "hello world");
+        let snapshot = doc.transact().snapshot();
+        let sv = doc.transact().state_vector();
+        text.remove_range(&mut doc.transact_mut(), 5, 6);
+        let updates = [
+            doc.transact()
+                .encode_state_as_update_v1(&StateVector::default()),
+            doc.transact().encode_diff_v1(&sv),
+        ];
+        for name in ["kept", "collected"].iter() {
+            for update in updates.iter() {
+                db.push_update(name, update).unwrap();
+            }
+        }
+        db.flush_doc_with("kept", no_gc()).unwrap();
+        db.flush_doc("collected").unwrap();
+
+        // flush without explicit options reuses persisted skip_gc
+        let sv = doc.transact().state_vector();
+        text.push(&mut doc.transact_mut(), "!");
+        let update = doc.transact().encode_diff_v1(&sv);
+        for name in ["kept", "collected"].iter() {
+            db.push_update(name, &update).unwrap();
+            assert!(db.flush_doc(name).unwrap().is_some());
+        }
+        assert!(matches!(
+            db.encode_state_from_snapshot("collected", &snapshot),
+            Err(Error::HistoryUnavailable)
+        ));
+        let state = db
+            .encode_state_from_snapshot("kept", &snapshot)
+            .unwrap()
+            .unwrap();
+        let past = Doc::new();
+        let past_text = past.get_or_insert_text("text");
+        past.transact_mut()
+            .apply_update(Update::decode_v1(&state).unwrap());
+        assert_eq!(past_text.get_string(&past.transact()), "hello world");
+
+        // reconstructed diff keeps tombstones only for documents flushed with skip_gc
+        let kept = db
+            .get_diff("kept", &StateVector::default())
+            .unwrap()
+            .unwrap();
+        let collected = db
+            .get_diff("collected", &StateVector::default())
+            .unwrap()
+            .unwrap();
+        assert!(collected.len() < kept.len());
+        let restored = Doc::with_options(no_gc());
+        restored
+            .transact_mut()
+            .apply_update(Update::decode_v1(&kept).unwrap());
+        let mut encoder = EncoderV1::new();
+        restored
+            .transact()
+            .encode_state_from_snapshot(&snapshot, &mut encoder)
+            .unwrap();
+        let past = Doc::new();
+        let past_text = past.get_or_insert_text("text");
+        past.transact_mut()
+            .apply_update(Update::decode_v1(&encoder.to_vec()).unwrap());
+        assert_eq!(past_text.get_string(&past.transact()), "hello world");
+
+        // explicit options override persisted ones
+        let options = yrs::Options {
+            client_id: 1,
+            ..yrs::Options::default()
+        };
+        let diff = db
+            .get_diff_with("kept", &StateVector::default(), options)
+            .unwrap()
+            .unwrap();
+        assert!(diff.len() < kept.len());
+    });
+}
+
+/// Verifies that a flush reports the number of updates folded into document state and the sequence
+/// number of the last one.
+pub fn flush_outcome<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    backend.write(|db| {
+        // nothing to flush
+        assert!(db.flush_doc(DOC_NAME).unwrap().is_none());
+
+        // store 3 updates without document state
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let mut expected_before = 0;
+        for chunk in ["a", "b", "c"] {
+            let sv = doc.transact().state_vector();
+            text.push(&mut doc.transact_mut(), chunk);
+            let update = doc.transact().encode_diff_v1(&sv);
+            let seq_nr = db.push_update(DOC_NAME, &update).unwrap().seq;
+            expected_before += (key_update(0, seq_nr).len() + update.len()) as u64;
+        }
+
+        let outcome = db.flush_doc(DOC_NAME).unwrap().unwrap();
+        let doc_state = outcome
+            .doc
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        let sv = outcome.doc.transact().state_vector().encode_v1();
+        let expected_after =
+            (key_doc(0).len() + doc_state.len() + key_state_vector(0).len() + sv.len()) as u64;
+        assert_eq!(outcome.updates_folded, 3);
+        assert_eq!(outcome.bytes_before, expected_before);
+        assert_eq!(outcome.bytes_after, expected_after);
+
+        // all updates were folded
+        assert!(db.flush_doc(DOC_NAME).unwrap().is_none());
+
+        // next flush accounts for previously flushed document state
+        let prev_sv = doc.transact().state_vector();
+        text.push(&mut doc.transact_mut(), "d");
+        let update = doc.transact().encode_diff_v1(&prev_sv);
+        let seq_nr = db.push_update(DOC_NAME, &update).unwrap().seq;
+        let outcome = db.flush_doc(DOC_NAME).unwrap().unwrap();
+        assert_eq!(outcome.updates_folded, 1);
+        assert_eq!(
+            outcome.bytes_before,
+            expected_after + (key_update(0, seq_nr).len() + update.len()) as u64
+        );
+    });
+}
+
+/// Verifies that loading a document allocates only a bounded number of times on top of decoding and
+/// applying its updates.
+pub fn load_doc_allocations<B: Backend>(backend: &B) {
+    use yrs::Update;
+
+    // allocations made by the store on top of decoding and applying updates themselves, i.e.
+    // for iterator entries and keys too long to be built on the stack
+    const BASE_BUDGET: usize = 64;
+    const UPDATE_BUDGET: usize = 4;
+
+    backend.write(|db| {
+        let source = Doc::with_client_id(1);
+        let text = source.get_or_insert_text("text");
+        let updates: Vec<Vec<u8>> = (0..100)
+            .map(|i| {
+                let mut txn = source.transact_mut();
+                text.push(&mut txn, &i.to_string());
+                txn.encode_update_v1()
+            })
+            .collect();
+        let mut scratch = Vec::new();
+        for update in updates.iter() {
+            db.push_update_with("doc", update, &mut scratch).unwrap();
+        }
+
+        // baseline: the same updates decoded and applied straight from memory
+        let before = allocations();
+        {
+            let doc = Doc::new();
+            let mut txn = doc.transact_mut();
+            for update in updates.iter() {
+                txn.apply_update(Update::decode_v1(update).unwrap());
+            }
+        }
+        let baseline = allocations() - before;
+
+        let doc = Doc::new();
+        let before = allocations();
+        {
+            let mut txn = doc.transact_mut();
+            assert!(db.load_doc_with("doc", &mut txn, &mut scratch).unwrap());
+        }
+        let loaded = allocations() - before;
+        let budget = baseline + BASE_BUDGET + UPDATE_BUDGET * updates.len();
+        assert!(
+            loaded <= budget,
+            "loading 100 updates took {} allocations, budget is {}",
+            loaded,
+            budget
+        );
+        let expected = text.get_string(&source.transact());
+        let text = doc.get_or_insert_text("text");
+        assert_eq!(text.get_string(&doc.transact()), expected);
+    });
+}
+
+/// Verifies that a document can be loaded as of a given sequence number of its pending updates,
+/// until they are merged by a flush.
+pub fn load_doc_at_seq<B: Backend>(backend: &B) {
+    use crate::SeqLoad;
+
+    fn text_at<'a, DB: DocOps<'a>>(db: &DB, seq: u32) -> (String, Option<SeqLoad>)
+    where
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let outcome = db
+            .load_doc_at_seq("doc", seq, &mut doc.transact_mut())
+            .unwrap();
+        let content = text.get_string(&doc.transact());
+        (content, outcome)
+    }
+
+    let doc = Doc::with_client_id(1);
+    let text = doc.get_or_insert_text("text");
+    let updates: Vec<Vec<u8>> = ["a", "b", "c", "d", "e"]
+        .iter()
+        .map(|chunk| {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            txn.encode_update_v1()
+        })
+        .collect();
+    let exact = |content: &str| (content.to_string(), Some(SeqLoad::Exact));
+
+    backend.write(|db| {
+        for update in &updates[..3] {
+            db.push_update("doc", update).unwrap();
+        }
+        assert_eq!(db.last_update_seq("doc").unwrap(), Some(3));
+        assert_eq!(text_at(db, 0), exact(""));
+        assert_eq!(text_at(db, 1), exact("a"));
+        assert_eq!(text_at(db, 2), exact("ab"));
+        assert_eq!(text_at(db, 3), exact("abc"));
+        assert_eq!(text_at(db, 10), exact("abc"));
+
+        // history merged by a flush is no longer reachable
+        db.flush_doc("doc").unwrap().unwrap();
+        assert_eq!(db.last_update_seq("doc").unwrap(), None);
+        db.import_updates("doc", &[(5, &updates[3]), (6, &updates[4])])
+            .unwrap();
+        assert_eq!(db.last_update_seq("doc").unwrap(), Some(6));
+        assert_eq!(text_at(db, 6), exact("abcde"));
+        assert_eq!(text_at(db, 5), exact("abcd"));
+        assert_eq!(text_at(db, 4), exact("abc"));
+        assert_eq!(
+            text_at(db, 2),
+            ("abc".to_string(), Some(SeqLoad::ClampedToFlushBase))
+        );
+
+        let doc = Doc::new();
+        let missing = db
+            .load_doc_at_seq("missing", 1, &mut doc.transact_mut())
+            .unwrap();
+        assert_eq!(missing, None);
+        assert_eq!(db.last_update_seq("missing").unwrap(), None);
+    });
+}
+
+/// Verifies that a document is loaded in steps applying a bounded amount of updates each, and ends
+/// up the same as one loaded at once.
+pub fn load_doc_budgeted<B: Backend>(backend: &B) {
+    use crate::bench::{load_trace, trace_updates};
+
+    fn load<'a, DB: DocOps<'a>>(db: &DB, budget: Option<usize>) -> (String, Option<u32>)
+    where
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let batches = match budget {
+            Some(budget) => db
+                .load_doc_budgeted("doc", &mut doc.transact_mut(), budget)
+                .unwrap(),
+            None => {
+                assert!(db.load_doc("doc", &mut doc.transact_mut()).unwrap());
+                None
+            }
+        };
+        let str = text.get_string(&doc.transact());
+        (str, batches)
+    }
+
+    backend.write(|db| {
+        let ops = load_trace("editing-trace.bin");
+        let updates = trace_updates(&ops[..ops.len().min(5000)]);
+        for update in updates.iter() {
+            db.push_update("doc", update).unwrap();
+        }
+        let (expected, _) = load(db, None);
+        assert!(!expected.is_empty());
+
+        // tiny budget applies updates almost one by one
+        let (content, batches) = load(db, Some(64));
+        assert_eq!(content, expected);
+        let batches = batches.unwrap();
+        assert!(batches > 1 && batches <= updates.len() as u32);
+        let (content, fewer) = load(db, Some(4096));
+        assert_eq!(content, expected);
+        assert!(fewer.unwrap() < batches);
+        let (content, single) = load(db, Some(usize::MAX));
+        assert_eq!(content, expected);
+        assert_eq!(single, Some(1));
+
+        db.flush_doc("doc").unwrap();
+        assert_eq!(load(db, Some(64)), (expected, Some(0)));
+        let doc = Doc::new();
+        assert_eq!(
+            db.load_doc_budgeted("missing", &mut doc.transact_mut(), 64)
+                .unwrap(),
+            None
+        );
+    });
+}
+
+/// Verifies that loading a document into a replica which is partially up to date applies only the
+/// missing updates.
+pub fn load_doc_twice<B: Backend>(backend: &B) {
+    backend.write(|db| {
+        let writer = Doc::with_client_id(1);
+        let writer_text = writer.get_or_insert_text("text");
+        let edit = |f: &dyn Fn(&mut yrs::TransactionMut)| {
+            let mut txn = writer.transact_mut();
+            f(&mut txn);
+            db.push_update("doc", &txn.encode_update_v1()).unwrap();
+        };
+        for chunk in ["a", "b", "c"].iter() {
+            edit(&|txn| writer_text.push(txn, chunk));
+        }
+        db.flush_doc("doc").unwrap();
+        edit(&|txn| writer_text.push(txn, "d"));
+        edit(&|txn| writer_text.push(txn, "e"));
+
+        let doc = Doc::with_client_id(2);
+        let text = doc.get_or_insert_text("text");
+        let load = || db.load_doc_checked("doc", &mut doc.transact_mut()).unwrap();
+        let result = load();
+        assert!(result.found);
+        assert!(!result.state_skipped);
+        assert_eq!((result.updates_applied, result.updates_skipped), (2, 0));
+        assert_eq!(text.get_string(&doc.transact()), "abcde");
+
+        // loading the same document again applies nothing
+        let result = load();
+        assert!(result.found);
+        assert!(result.state_skipped);
+        assert_eq!((result.updates_applied, result.updates_skipped), (0, 2));
+        assert_eq!(text.get_string(&doc.transact()), "abcde");
+        assert!(db.load_doc("doc", &mut doc.transact_mut()).unwrap());
+
+        // catch-up loads apply only the missing suffix
+        edit(&|txn| writer_text.push(txn, "f"));
+        let result = load();
+        assert_eq!((result.updates_applied, result.updates_skipped), (1, 2));
+        assert_eq!(text.get_string(&doc.transact()), "abcdef");
+
+        // deletions are applied even though they don't advance the state vector
+        edit(&|txn| writer_text.remove_range(txn, 0, 1));
+        let result = load();
+        assert_eq!((result.updates_applied, result.updates_skipped), (1, 3));
+        assert_eq!(text.get_string(&doc.transact()), "bcdef");
+        let stale = Doc::with_client_id(3);
+        let stale_text = stale.get_or_insert_text("text");
+        stale.transact_mut().apply_update(
+            Update::decode_v1(
+                &writer
+                    .transact()
+                    .encode_state_as_update_v1(&StateVector::default()),
+            )
+            .unwrap(),
+        );
+        edit(&|txn| writer_text.remove_range(txn, 0, 1));
+        db.flush_doc("doc").unwrap();
+        let result = db
+            .load_doc_checked("doc", &mut stale.transact_mut())
+            .unwrap();
+        assert!(result.state_skipped);
+        assert_eq!(stale_text.get_string(&stale.transact()), "cdef");
+    });
+}
+
+/// Verifies that malformed keys and values are reported as corrupted instead of causing panics.
+pub fn malformed_entries<B: Backend>(backend: &B) {
+    // regressions found by fuzzing key and value parsers
+    use crate::keys::{
+        key_channel_start, key_meta_start, key_oid, key_pending, KEYSPACE_DOC, KEYSPACE_INTENT,
+        KEYSPACE_OID, V1,
+    };
+
+    backend.write(|db| {
+        db.push_update("doc", &[0, 0]).unwrap();
+
+        // keys too short to contain a name
+        db.upsert(&[V1, KEYSPACE_OID], &1u32.to_be_bytes()).unwrap();
+        db.upsert(&key_meta_start(1), b"value").unwrap();
+        let names: Vec<Box<[u8]>> = db.iter_docs().unwrap().collect();
+        let names: Vec<&[u8]> = names.iter().map(|name| name.as_ref()).collect();
+        assert_eq!(names, vec![&b""[..], &b"doc"[..]]);
+        let meta: Vec<_> = db.iter_meta("doc").unwrap().collect();
+        assert_eq!(meta, vec![(Box::from(&b""[..]), Box::from(&b"value"[..]))]);
+        db.remove(&[V1, KEYSPACE_OID]).unwrap();
+        db.remove(&key_meta_start(1)).unwrap();
+
+        // keys too short to contain an OID or a channel
+        db.upsert(&[V1, KEYSPACE_INTENT, 0], &[]).unwrap();
+        assert_eq!(db.recover_intents().unwrap(), 0);
+        db.remove(&[V1, KEYSPACE_INTENT, 0]).unwrap();
+        db.upsert(&[V1, KEYSPACE_DOC, 0xFF], &[]).unwrap();
+        let options = MaintenanceOptions {
+            vacuum: true,
+            ..MaintenanceOptions::default()
+        };
+        assert_eq!(db.maintain(options).unwrap().orphans_removed, 0);
+        db.remove(&[V1, KEYSPACE_DOC, 0xFF]).unwrap();
+        db.upsert(&key_channel_start(1), &[0, 0]).unwrap();
+        db.flush_doc("doc").unwrap();
+        db.get_state_vector("doc").unwrap();
+        db.remove(&key_channel_start(1)).unwrap();
+
+        // values too short to be decoded are reported as corrupted
+        db.upsert(&key_pending(1), &[1]).unwrap();
+        let result = db.push_update("doc", &[0, 0]);
+        assert!(matches!(result, Err(Error::CorruptedValue)));
+        db.upsert(&key_oid(b"doc"), &[1]).unwrap();
+        let result = db.load_doc("doc", &mut Doc::new().transact_mut());
+        assert!(matches!(result, Err(Error::CorruptedValue)));
+        assert!(matches!(
+            db.get_meta("doc", "key"),
+            Err(Error::CorruptedValue)
+        ));
+    });
+}
+
+/// Verifies that merging a document into another one carries over its contents and metadata and
+/// removes the source document.
+pub fn merge_docs<B: Backend>(backend: &B) {
+    use crate::merge::{MergeStrategy, MetaMerge};
+
+    backend.write(|db| {
+        let src = Doc::with_client_id(1);
+        src.get_or_insert_text("text")
+            .push(&mut src.transact_mut(), "from src");
+        src.get_or_insert_map("src-only")
+            .insert(&mut src.transact_mut(), "key", "value");
+        db.insert_doc("src", &src.transact()).unwrap();
+        db.insert_meta("src", "title", b"src title").unwrap();
+        db.insert_meta("src", "owner", b"alice").unwrap();
+
+        let dst = Doc::with_client_id(2);
+        dst.get_or_insert_text("text")
+            .push(&mut dst.transact_mut(), "from dst");
+        let update = dst
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        db.push_update("dst", &update).unwrap();
+        db.insert_meta("dst", "title", b"dst title").unwrap();
+
+        // replica of the destination document, connected before the merge
+        let replica = Doc::with_client_id(3);
+        db.load_doc("dst", &mut replica.transact_mut()).unwrap();
+        let load = |name: &str| {
+            let doc = Doc::new();
+            assert!(db.load_doc(name, &mut doc.transact_mut()).unwrap());
+            doc
+        };
+
+        // overlapping roots are merged, the source document is removed
+        let strategy = MergeStrategy {
+            meta: MetaMerge::Combine,
+            remove_src: true,
+        };
+        let update = db.merge_docs("src", "dst", strategy).unwrap();
+        replica
+            .transact_mut()
+            .apply_update(Update::decode_v1(&update).unwrap());
+        let merged = load("dst");
+        assert_eq!(
+            merged.transact().state_vector(),
+            replica.transact().state_vector()
+        );
+        let content = merged
+            .get_or_insert_text("text")
+            .get_string(&merged.transact());
+        assert!(content.contains("from src") && content.contains("from dst"));
+        assert_eq!(content.len(), 16);
+        assert_eq!(
+            replica
+                .get_or_insert_text("text")
+                .get_string(&replica.transact()),
+            content
+        );
+        for doc in [&merged, &replica] {
+            let map = doc.get_or_insert_map("src-only");
+            assert_eq!(
+                map.get(&doc.transact(), "key"),
+                Some(Out::Any(Any::from("value")))
+            );
+        }
+        let meta =
+            |name: &str, key: &str| db.get_meta(name, key).unwrap().map(|v| v.as_ref().to_vec());
+        assert_eq!(meta("dst", "title"), Some(b"dst title".to_vec()));
+        assert_eq!(meta("dst", "owner"), Some(b"alice".to_vec()));
+        assert!(!db.load_doc("src", &mut Doc::new().transact_mut()).unwrap());
+        assert_eq!(meta("src", "owner"), None);
+        let res = db.merge_docs("src", "dst", MergeStrategy::default());
+        assert!(matches!(res, Err(Error::DocNotFound)));
+
+        // disjoint roots are carried over, source metadata replaces destination metadata
+        let other = Doc::with_client_id(4);
+        other
+            .get_or_insert_text("notes")
+            .push(&mut other.transact_mut(), "other notes");
+        db.insert_doc("other", &other.transact()).unwrap();
+        db.insert_meta("other", "title", b"other title").unwrap();
+        let strategy = MergeStrategy {
+            meta: MetaMerge::KeepSrc,
+            remove_src: false,
+        };
+        let update = db.merge_docs("other", "dst", strategy).unwrap();
+        replica
+            .transact_mut()
+            .apply_update(Update::decode_v1(&update).unwrap());
+        let merged = load("dst");
+        assert_eq!(
+            merged.transact().state_vector(),
+            replica.transact().state_vector()
+        );
+        for doc in [&merged, &replica] {
+            let notes = doc.get_or_insert_text("notes");
+            let text = doc.get_or_insert_text("text");
+            let txn = doc.transact();
+            assert_eq!(notes.get_string(&txn), "other notes");
+            assert_eq!(text.get_string(&txn), content);
+        }
+        assert_eq!(meta("dst", "title"), Some(b"other title".to_vec()));
+        assert_eq!(meta("dst", "owner"), None);
+        assert_eq!(meta("other", "title"), Some(b"other title".to_vec()));
+        load("other");
+
+        // merging a document with itself changes nothing
+        let sv = load("dst").transact().state_vector();
+        let update = db.merge_docs("dst", "dst", strategy).unwrap();
+        let update = Update::decode_v1(&update).unwrap();
+        assert_eq!(update.state_vector(), StateVector::default());
+        assert_eq!(load("dst").transact().state_vector(), sv);
+    });
+}
+
+/// Verifies that references between documents stored in metadata are indexed, prevent referenced
+/// documents from being cleared and are removed together with them.
+pub fn meta_refs<B: Backend>(backend: &B) {
+    use crate::keys::KEYSPACE_REF;
+    use crate::refs::{InboundRef, RefPolicy};
+
+    let inbound = |source: &str, key: &str| InboundRef {
+        source: source.as_bytes().to_vec(),
+        key: key.as_bytes().to_vec(),
+    };
+
+    backend.write(|db| {
+        for name in ["root", "parent", "child", "template", "copy", "other"] {
+            db.insert_meta(name, "title", name.as_bytes()).unwrap();
+        }
+        assert!(matches!(
+            db.insert_meta_ref("child", "parent", "missing"),
+            Err(Error::DocNotFound)
+        ));
+        db.insert_meta_ref("parent", "parent", "root").unwrap();
+        db.insert_meta_ref("child", "parent", "parent").unwrap();
+        db.insert_meta_ref("copy", "template_of", "template")
+            .unwrap();
+        db.insert_meta_ref("other", "template_of", "template")
+            .unwrap();
+        let target = db.get_meta_ref("child", "parent").unwrap().unwrap();
+        assert_eq!(target.as_ref(), b"parent");
+        assert!(db.get_meta_ref("child", "missing").unwrap().is_none());
+        assert_eq!(
+            db.get_inbound_refs("template").unwrap(),
+            vec![
+                inbound("copy", "template_of"),
+                inbound("other", "template_of")
+            ]
+        );
+
+        // overwritten reference is moved between inbound indexes
+        db.insert_meta_ref("other", "template_of", "root").unwrap();
+        assert_eq!(
+            db.get_inbound_refs("template").unwrap(),
+            vec![inbound("copy", "template_of")]
+        );
+        assert_eq!(db.get_inbound_refs("root").unwrap().len(), 2);
+
+        // referenced document cannot be cleared
+        assert!(matches!(
+            db.clear_doc_with("template", RefPolicy::Refuse),
+            Err(Error::DocReferenced)
+        ));
+        assert!(db.get_meta("template", "title").unwrap().is_some());
+        db.remove_meta_ref("copy", "template_of").unwrap();
+        assert!(db.get_inbound_refs("template").unwrap().is_empty());
+        let report = db.clear_doc_with("template", RefPolicy::Refuse).unwrap();
+        assert_eq!(report.meta_removed, 1);
+        assert!(!report.doc_state_removed);
+        assert!(db.get_meta("template", "title").unwrap().is_none());
+
+        // referring documents are cleared recursively
+        let report = db.clear_doc_with("root", RefPolicy::Cascade).unwrap();
+        // root, parent, child and other
+        assert_eq!(report.meta_removed, 4);
+        for name in ["root", "parent", "child", "other"] {
+            assert!(db.get_meta(name, "title").unwrap().is_none());
+        }
+        assert!(db.get_meta("copy", "title").unwrap().is_some());
+
+        // references to a cleared document are removed
+        db.insert_meta("root", "title", b"root").unwrap();
+        db.insert_meta_ref("copy", "parent", "root").unwrap();
+        db.clear_doc("root").unwrap();
+        assert!(db.get_meta_ref("copy", "parent").unwrap().is_none());
+        assert!(db.get_meta("copy", "title").unwrap().is_some());
+
+        // no inbound references index entries are left behind
+        let start = [V1, KEYSPACE_REF];
+        let end = [V1, KEYSPACE_REF + 1];
+        let left = db.iter_range(&start, &end).unwrap();
+        assert_eq!(left.filter(|e| e.key() < &end[..]).count(), 0);
+    });
+}
+
+/// Verifies that document contents left without an OID index entry can be found and adopted under a
+/// new name.
+pub fn orphan_adoption<B: Backend>(backend: &B) {
+    use crate::keys::{key_setting, SETTING_LAST_OID};
+
+    backend.write(|db| {
+        let update = |content: &str| {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, content);
+            txn.encode_update_v1()
+        };
+        // edits of a single replica, so that their order doesn't depend on client IDs
+        let a = Doc::new();
+        let a_text = a.get_or_insert_text("text");
+        let append = |chunk: &str| {
+            let mut txn = a.transact_mut();
+            a_text.push(&mut txn, chunk);
+            txn.encode_update_v1()
+        };
+        let load = |name: &str| -> Option<String> {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            if db.load_doc(name, &mut doc.transact_mut()).unwrap() {
+                Some(text.get_string(&doc.transact()))
+            } else {
+                None
+            }
+        };
+
+        db.push_update("a", &append("hello")).unwrap(); // OID 1
+        db.flush_doc("a").unwrap();
+        db.push_update("a", &append(" world")).unwrap();
+        db.insert_meta("a", "title", b"A").unwrap();
+        db.push_update("b", &update("bee")).unwrap(); // OID 2
+        assert!(db.orphaned_oids().unwrap().is_empty());
+
+        // losing an OID index entry makes document contents unreachable
+        KVStore::remove(db, &key_oid(b"a")).unwrap();
+        assert_eq!(db.orphaned_oids().unwrap(), vec![1]);
+        assert_eq!(load("a"), None);
+
+        // adoption is refused for taken names, claimed OIDs and OIDs without contents
+        assert!(!db.adopt_orphan(1, "b").unwrap());
+        match db.adopt_orphan(2, "recovered-b") {
+            Err(Error::OidClaimed { owner }) => assert_eq!(owner, b"b".to_vec()),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(!db.adopt_orphan(3, "recovered").unwrap());
+        assert_eq!(db.orphaned_oids().unwrap(), vec![1]);
+
+        // adopted document has all its contents back
+        assert!(db.adopt_orphan(1, "recovered-a").unwrap());
+        assert!(db.orphaned_oids().unwrap().is_empty());
+        assert_eq!(load("recovered-a"), Some("hello world".to_string()));
+        let title = db.get_meta("recovered-a", "title").unwrap().unwrap();
+        assert_eq!(title.as_ref(), b"A");
+        db.push_update("recovered-a", &append("!")).unwrap();
+        assert_eq!(load("recovered-a").unwrap().len(), 12);
+        match db.adopt_orphan(1, "again") {
+            Err(Error::OidClaimed { owner }) => assert_eq!(owner, b"recovered-a".to_vec()),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // OIDs of adopted documents are not reused, even if the OID counter has been lost too
+        KVStore::remove(db, &key_oid(b"b")).unwrap();
+        KVStore::remove(db, &key_setting(SETTING_LAST_OID)).unwrap();
+        assert_eq!(db.orphaned_oids().unwrap(), vec![2]);
+        assert!(db.adopt_orphan(2, "recovered-b").unwrap());
+        db.push_update("c", &update("sea")).unwrap();
+        assert_eq!(load("recovered-b"), Some("bee".to_string()));
+        assert_eq!(load("c"), Some("sea".to_string()));
+
+        // vacuum has nothing left to remove
+        let report = db
+            .maintain(MaintenanceOptions {
+                vacuum: true,
+                ..MaintenanceOptions::default()
+            })
+            .unwrap();
+        assert_eq!(report.orphans_removed, 0);
+        assert_eq!(load("recovered-a").unwrap().len(), 12);
+    });
+}
+
+/// Verifies that pushing an update looks the last one up with a single seek, no matter how many
+/// updates of a document are pending.
+pub fn push_update_long_log<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    backend.write(|db| {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        text.push(&mut doc.transact_mut(), "a");
+        let update = doc
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        let push_many = |n: u32| {
+            let start = Instant::now();
+            for _ in 0..n {
+                db.push_update(DOC_NAME, &update).unwrap();
+            }
+            start.elapsed()
+        };
+
+        let short_log = push_many(100);
+        push_many(10_000);
+        let long_log = push_many(100);
+        // last clock lookup is a single seek, no matter how many updates are pending
+        assert!(
+            long_log < short_log * 10 + Duration::from_millis(50),
+            "push_update slowed down from {:?} to {:?}",
+            short_log,
+            long_log
+        );
+        assert_eq!(db.push_update(DOC_NAME, &update).unwrap().seq, 10_201);
+
+        // entries of other documents are never mistaken for updates
+        assert_eq!(db.push_update("other", &update).unwrap().seq, 1);
+        db.flush_doc(DOC_NAME).unwrap();
+        assert_eq!(db.push_update(DOC_NAME, &update).unwrap().seq, 1);
+    });
+}
+
+/// Verifies that replacing contents of a document with a regenerated one is merged into its state,
+/// so that connected replicas converge with it.
+pub fn replace_doc<B: Backend>(backend: &B) {
+    fn content(doc: &Doc) -> (String, Option<Out>, Option<Out>, u32) {
+        let txn = doc.transact();
+        let text = txn.get_text("text").unwrap().get_string(&txn);
+        let meta = txn.get_map("meta").unwrap();
+        let items = txn.get_array("items").map(|a| a.len(&txn)).unwrap_or(0);
+        (
+            text,
+            meta.get(&txn, "title"),
+            meta.get(&txn, "author"),
+            items,
+        )
+    }
+
+    backend.write(|db| {
+        // replica connected to the store, which has written a flushed and a pending update
+        let replica = Doc::with_client_id(1);
+        let text = replica.get_or_insert_text("text");
+        let meta = replica.get_or_insert_map("meta");
+        replica.get_or_insert_array("items");
+        {
+            let mut txn = replica.transact_mut();
+            text.push(&mut txn, "hello");
+            meta.insert(&mut txn, "title", "old");
+            meta.insert(&mut txn, "author", "someone");
+            db.push_update("doc", &txn.encode_update_v1()).unwrap();
+        }
+        db.flush_doc("doc").unwrap();
+        {
+            let mut txn = replica.transact_mut();
+            text.push(&mut txn, " world");
+            db.push_update("doc", &txn.encode_update_v1()).unwrap();
+        }
+
+        // content regenerated by an external system, from scratch
+        let external = Doc::with_client_id(2);
+        {
+            let text = external.get_or_insert_text("text");
+            let meta = external.get_or_insert_map("meta");
+            let items = external.get_or_insert_array("items");
+            let mut txn = external.transact_mut();
+            text.push(&mut txn, "brand new");
+            meta.insert(&mut txn, "title", "new");
+            items.push_back(&mut txn, 1.0);
+            items.push_back(&mut txn, TextPrelim::new("two"));
+        }
+        let expected = (
+            "brand new".to_string(),
+            Some(Out::Any(Any::from("new"))),
+            None,
+            2,
+        );
+
+        let update = db.replace_doc("doc", &external.transact()).unwrap();
+        let stored = Doc::new();
+        assert!(db.load_doc("doc", &mut stored.transact_mut()).unwrap());
+        assert_eq!(content(&stored), expected);
+        // replacement has been merged into document state
+        assert_eq!(db.decoded_updates("doc").unwrap().count(), 0);
+
+        // replica receiving the replacement converges with the store
+        replica
+            .transact_mut()
+            .apply_update(Update::decode_v1(&update).unwrap());
+        assert_eq!(content(&replica), expected);
+        assert_eq!(
+            replica.transact().state_vector(),
+            stored.transact().state_vector()
+        );
+        {
+            // concurrent edit of the replica is kept on top of the replacement
+            let mut txn = replica.transact_mut();
+            text.push(&mut txn, "!");
+            db.push_update("doc", &txn.encode_update_v1()).unwrap();
+        }
+        let stored = Doc::new();
+        assert!(db.load_doc("doc", &mut stored.transact_mut()).unwrap());
+        assert_eq!(content(&stored).0, "brand new!");
+        assert_eq!(content(&replica).0, "brand new!");
+
+        // replacing a missing document creates it
+        let update = db.replace_doc("missing", &external.transact()).unwrap();
+        let created = Doc::new();
+        assert!(db.load_doc("missing", &mut created.transact_mut()).unwrap());
+        assert_eq!(content(&created), expected);
+        let replica = Doc::new();
+        replica
+            .transact_mut()
+            .apply_update(Update::decode_v1(&update).unwrap());
+        assert_eq!(content(&replica), expected);
+    });
+}
+
+/// Verifies that seeks at the edges of the database and of document key ranges stay within their
+/// bounds.
+pub fn seek_db_edges<B: Backend>(backend: &B) {
+    backend.write(|db| {
+        // empty database
+        assert!(db.seek(&[5]).unwrap().is_none());
+
+        db.upsert(&[1], &[1]).unwrap();
+        db.upsert(&[3], &[3]).unwrap();
+        let first = db.seek(&[0]).unwrap().unwrap();
+        assert_eq!((first.key(), first.value()), (&[1][..], &[1][..]));
+        assert_eq!(db.seek(&[1]).unwrap().unwrap().key(), &[1]);
+        assert_eq!(db.seek(&[2]).unwrap().unwrap().key(), &[3]);
+        assert_eq!(db.seek(&[3]).unwrap().unwrap().key(), &[3]);
+        // cursor positioned past the last entry
+        assert!(db.seek(&[3, 0]).unwrap().is_none());
+        assert!(db.seek(&[9]).unwrap().is_none());
+        db.remove(&[1]).unwrap();
+        assert_eq!(db.seek(&[0]).unwrap().unwrap().key(), &[3]);
+        db.remove(&[3]).unwrap();
+
+        // seek is bounded to the update key range of a document
+        assert_eq!(db.first_update_seq("a").unwrap(), None);
+        db.push_update("a", &[0, 0]).unwrap();
+        for _ in 0..3 {
+            db.push_update("b", &[0, 0]).unwrap();
+        }
+        assert_eq!(db.first_update_seq("a").unwrap(), Some(1));
+        assert_eq!(db.first_update_seq("b").unwrap(), Some(1));
+        db.flush_doc("b").unwrap().unwrap();
+        assert_eq!(db.first_update_seq("b").unwrap(), None);
+        db.import_updates("b", &[(5, &[0, 0]), (6, &[0, 0])])
+            .unwrap();
+        assert_eq!(db.first_update_seq("b").unwrap(), Some(5));
+        assert_eq!(db.last_update_seq("b").unwrap(), Some(6));
+        assert_eq!(db.first_update_seq("a").unwrap(), Some(1));
+    });
+}
+
+/// Verifies that pushed updates are appended to segments, which are flushed as a whole, while
+/// imported updates are stored individually.
+pub fn segmented_updates<B: Backend>(backend: &B) {
+    use crate::bench::{apply_ops, load_trace, trace_updates};
+
+    let ops = load_trace("editing-trace.bin");
+    let ops = &ops[..ops.len().min(2000)];
+    let updates = trace_updates(ops);
+    let expected = {
+        let doc = Doc::new();
+        apply_ops(&doc, ops);
+        let text = doc.get_or_insert_text("text");
+        let str = text.get_string(&doc.transact());
+        str
+    };
+    fn text_of<'a, DB: DocOps<'a>>(db: &DB) -> String
+    where
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        assert!(db.load_doc("doc", &mut doc.transact_mut()).unwrap());
+        let str = text.get_string(&doc.transact());
+        str
+    }
+
+    // returns total size of update entries, individual and segmented ones
+    let store = |backend: &B, framing: UpdateFraming| -> (usize, usize) {
+        let config = StoreConfig {
+            update_framing: framing,
+            ..StoreConfig::DEFAULT
+        };
+        backend.write_configured(&config, |db| {
+            // imported updates are stored individually, pushed ones are appended after them
+            let imported: Vec<_> = updates[..10]
+                .iter()
+                .enumerate()
+                .map(|(i, update)| (i as u32 + 1, update.as_slice()))
+                .collect();
+            db.import_updates("doc", &imported).unwrap();
+            for (i, update) in updates.iter().enumerate().skip(10) {
+                assert_eq!(db.push_update("doc", update).unwrap().seq, i as u32 + 1);
+            }
+            let update_entries = || {
+                let end = [V1, KEYSPACE_DOC + 1];
+                KVStore::iter_range(db, &[V1, KEYSPACE_DOC], &end)
+                    .unwrap()
+                    .take_while(|e| e.key() < &end[..])
+                    .filter_map(|e| match parse_key(e.key()) {
+                        Some(ParsedKey::Update { .. }) | Some(ParsedKey::UpdateSegment { .. }) => {
+                            Some(e.key().len() + e.value().len())
+                        }
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let entries = update_entries();
+
+            let seqs: Vec<_> = db
+                .decoded_updates("doc")
+                .unwrap()
+                .map(|update| update.unwrap().0)
+                .collect();
+            assert_eq!(seqs, (1..=updates.len() as u32).collect::<Vec<_>>());
+            assert_eq!(text_of(db), expected);
+
+            // flush removes whole segments
+            let outcome = db.flush_doc("doc").unwrap().unwrap();
+            assert_eq!(outcome.updates_folded, updates.len() as u32);
+            assert!(update_entries().is_empty());
+            let (sv, up_to_date) = db.get_state_vector("doc").unwrap();
+            assert!(sv.is_some() && up_to_date);
+            assert_eq!(text_of(db), expected);
+            (entries.len(), entries.into_iter().sum())
+        })
+    };
+
+    let (individual_entries, individual_bytes) = store(backend, UpdateFraming::Individual);
+    let (segmented_entries, segmented_bytes) = store(
+        &B::open("segmented_updates_framed"),
+        UpdateFraming::Segmented {
+            segment_size: 32 * 1024,
+        },
+    );
+    assert_eq!(individual_entries, updates.len());
+    assert!(segmented_entries > 10);
+    assert!(segmented_entries < individual_entries / 10);
+    assert!(
+        segmented_bytes < individual_bytes,
+        "segmented: {} bytes, individual: {} bytes",
+        segmented_bytes,
+        individual_bytes
+    );
+}
+
+/// Verifies that flushes record size samples of a document in a bounded history, which is removed
+/// together with the document.
+pub fn size_history<B: Backend>(backend: &B) {
+    use crate::clock::{self, ManualClock};
+    use crate::config::{ConfiguredStore, StoreConfig};
+    use crate::format::SIZE_HISTORY_LEN;
+    use crate::merge::MergeStrategy;
+
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let config = StoreConfig {
+        size_history: true,
+        ..StoreConfig::DEFAULT
+    };
+
+    backend.transaction(|db| {
+        let db = ConfiguredStore::new(db, config).with_clock(clock.clone());
+        let doc = Doc::with_client_id(1);
+        let text = doc.get_or_insert_text("text");
+        let flushes = SIZE_HISTORY_LEN as u64 + 6;
+        for i in 0..flushes {
+            let sv = doc.transact().state_vector();
+            text.push(&mut doc.transact_mut(), "abcd");
+            let update = doc.transact().encode_state_as_update_v1(&sv);
+            db.push_update("doc", &update).unwrap();
+            clock.set(1_000_000 + i * 1000);
+            db.flush_doc("doc").unwrap().unwrap();
+            if i == 2 {
+                let history = db.size_history("doc").unwrap();
+                assert_eq!(history.len(), 3);
+                assert_eq!(history[0].time, clock::system_time(1_000_000));
+                assert_eq!(history[2].time, clock::system_time(1_002_000));
+            }
+        }
+
+        // the oldest samples have been overwritten, the rest is ordered from the oldest one
+        let history = db.size_history("doc").unwrap();
+        assert_eq!(history.len(), SIZE_HISTORY_LEN as usize);
+        assert_eq!(history[0].time, clock::system_time(1_006_000));
+        assert_eq!(
+            history.last().unwrap().time,
+            clock::system_time(1_000_000 + (flushes - 1) * 1000)
+        );
+        for pair in history.windows(2) {
+            assert!(pair[0].time < pair[1].time);
+            assert!(pair[0].state_bytes < pair[1].state_bytes);
+        }
+        assert!(history.iter().all(|sample| sample.pending_bytes > 0));
+        let stored = db.get(&key_doc(1)).unwrap().unwrap();
+        assert_eq!(
+            history.last().unwrap().state_bytes,
+            stored.as_ref().len() as u64
+        );
+
+        // copies don't inherit the history of their source
+        db.merge_docs("doc", "copy", MergeStrategy::default())
+            .unwrap();
+        assert_eq!(db.size_history("copy").unwrap().len(), 1);
+
+        // history is removed together with its document
+        db.clear_doc("doc").unwrap();
+        assert!(db.size_history("doc").unwrap().is_empty());
+        assert!(db.size_history("missing").unwrap().is_empty());
+
+        // flushes don't record samples unless enabled
+        let db = db.into_inner();
+        db.push_update(
+            "plain",
+            &doc.transact()
+                .encode_state_as_update_v1(&StateVector::default()),
+        )
+        .unwrap();
+        db.flush_doc("plain").unwrap().unwrap();
+        assert!(db.size_history("plain").unwrap().is_empty());
+        assert_eq!(db.size_history("copy").unwrap().len(), 1);
+        (db, ())
+    });
+}
+
+/// Verifies that snapshots of a document can be restored, as long as the flush keeps deleted
+/// content around.
+pub fn snapshots<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    backend.write(|db| {
+        let no_gc = || yrs::Options {
+            skip_gc: true,
+            ..yrs::Options::default()
+        };
+        let doc = Doc::with_options(no_gc());
+        let text = doc.get_or_insert_text("text");
+        text.push(&mut doc.transact_mut(), "hello world");
+        let update = doc
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        db.push_update(DOC_NAME, &update).unwrap();
+        let yesterday = doc.transact().snapshot();
+        db.insert_snapshot(DOC_NAME, "yesterday", &yesterday)
+            .unwrap();
+
+        let sv = doc.transact().state_vector();
+        text.remove_range(&mut doc.transact_mut(), 5, 6);
+        db.push_update(DOC_NAME, &doc.transact().encode_diff_v1(&sv))
+            .unwrap();
+        // flush without GC keeps deleted content around
+        db.flush_doc_with(DOC_NAME, no_gc()).unwrap();
+
+        let snapshot = db.get_snapshot(DOC_NAME, "yesterday").unwrap().unwrap();
+        let state = db
+            .encode_state_from_snapshot(DOC_NAME, &snapshot)
+            .unwrap()
+            .unwrap();
+        let past = Doc::new();
+        let past_text = past.get_or_insert_text("text");
+        past.transact_mut()
+            .apply_update(Update::decode_v1(&state).unwrap());
+        assert_eq!(past_text.get_string(&past.transact()), "hello world");
+        assert!(db
+            .encode_state_from_snapshot("unknown", &snapshot)
+            .unwrap()
+            .is_none());
+
+        // flush with GC drops deleted content for good
+        let sv = doc.transact().state_vector();
+        text.push(&mut doc.transact_mut(), "!");
+        db.push_update(DOC_NAME, &doc.transact().encode_diff_v1(&sv))
+            .unwrap();
+        db.flush_doc_with(DOC_NAME, yrs::Options::default())
+            .unwrap();
+        assert!(matches!(
+            db.encode_state_from_snapshot(DOC_NAME, &snapshot),
+            Err(Error::HistoryUnavailable)
+        ));
+
+        db.remove_snapshot(DOC_NAME, "yesterday").unwrap();
+        assert!(db.get_snapshot(DOC_NAME, "yesterday").unwrap().is_none());
+    });
+}
+
+/// Verifies that a document split into parts, which are equivalent to the original document
+/// together, can be loaded part by part.
+pub fn split_docs<B: Backend>(backend: &B) {
+    backend.write(|db| {
+        let policy = SplitPolicy::new("content").route("history", "history");
+        let push = |update: &[u8]| db.push_split_update("doc", update, &policy);
+
+        let d1 = Doc::with_client_id(1);
+        let d2 = Doc::with_client_id(2);
+        let (content, history, meta) = (
+            d1.get_or_insert_text("content"),
+            d1.get_or_insert_array("history"),
+            d1.get_or_insert_map("meta"),
+        );
+        {
+            let mut txn = d1.transact_mut();
+            content.push(&mut txn, "hello");
+            history.push_back(&mut txn, "created");
+            meta.insert(&mut txn, "notes", TextPrelim::new(""));
+        }
+        let u1 = d1
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        push(&u1).unwrap();
+        d2.transact_mut()
+            .apply_update(Update::decode_v1(&u1).unwrap());
+
+        // concurrent edits of both clients, including a nested type created by the other one
+        let sv1 = d1.transact().state_vector();
+        {
+            let mut txn = d1.transact_mut();
+            content.push(&mut txn, " world");
+            history.push_back(&mut txn, "edited");
+        }
+        let u2 = d1.transact().encode_diff_v1(&sv1);
+        let sv2 = d2.transact().state_vector();
+        {
+            let content = d2.get_or_insert_text("content");
+            let meta = d2.get_or_insert_map("meta");
+            let mut txn = d2.transact_mut();
+            content.insert(&mut txn, 0, ">> ");
+            match meta.get(&txn, "notes") {
+                Some(Out::YText(notes)) => notes.push(&mut txn, "note"),
+                other => panic!("expected nested text, found {:?}", other),
+            }
+        }
+        let u3 = d2.transact().encode_diff_v1(&sv2);
+        push(&u2).unwrap();
+        push(&u3).unwrap();
+        d1.transact_mut()
+            .apply_update(Update::decode_v1(&u3).unwrap());
+
+        let sv = d1.transact().state_vector();
+        {
+            let mut txn = d1.transact_mut();
+            content.remove_range(&mut txn, 0, 3);
+            history.push_back(&mut txn, "trimmed");
+        }
+        push(&d1.transact().encode_diff_v1(&sv)).unwrap();
+
+        // updates depending on content which has not been pushed yet are rejected
+        let sv = d1.transact().state_vector();
+        content.push(&mut d1.transact_mut(), "!");
+        let u5 = d1.transact().encode_diff_v1(&sv);
+        let sv = d1.transact().state_vector();
+        content.push(&mut d1.transact_mut(), "?");
+        let u6 = d1.transact().encode_diff_v1(&sv);
+        assert!(matches!(push(&u6), Err(Error::UpdateRejected(_))));
+        push(&u5).unwrap();
+        push(&u6).unwrap();
+
+        let names: Vec<Box<[u8]>> = db.iter_docs().unwrap().collect();
+        assert_eq!(
+            names,
+            vec![
+                SplitPolicy::doc_name(b"doc", "content").into(),
+                SplitPolicy::doc_name(b"doc", "history").into(),
+            ]
+        );
+        db.flush_doc(&SplitPolicy::doc_name(b"doc", "history"))
+            .unwrap();
+
+        // all parts together are equivalent to the original document
+        let full = Doc::new();
+        assert!(db
+            .load_split_doc(
+                "doc",
+                &policy,
+                &["content", "history", "meta"],
+                &mut full.transact_mut()
+            )
+            .unwrap());
+        {
+            let expected = d1.transact();
+            let txn = full.transact();
+            assert_eq!(txn.state_vector(), expected.state_vector());
+            let text = txn.get_text("content").unwrap();
+            assert_eq!(text.get_string(&txn), "hello world!?");
+            assert_eq!(text.get_string(&txn), content.get_string(&expected));
+            let array = txn.get_array("history").unwrap();
+            assert_eq!(array.len(&txn), 3);
+            for i in 0..3 {
+                assert_eq!(array.get(&txn, i), history.get(&expected, i));
+            }
+            match txn.get_map("meta").unwrap().get(&txn, "notes") {
+                Some(Out::YText(notes)) => assert_eq!(notes.get_string(&txn), "note"),
+                other => panic!("expected nested text, found {:?}", other),
+            }
+        }
+        // applying each other's state changes nothing
+        let update = full
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        d1.transact_mut()
+            .apply_update(Update::decode_v1(&update).unwrap());
+        assert_eq!(content.get_string(&d1.transact()), "hello world!?");
+        assert_eq!(history.len(&d1.transact()), 3);
+
+        // loading only the content part skips the history
+        let partial = Doc::new();
+        assert!(db
+            .load_split_doc("doc", &policy, &["content"], &mut partial.transact_mut())
+            .unwrap());
+        {
+            let txn = partial.transact();
+            let text = txn.get_text("content").unwrap();
+            assert_eq!(text.get_string(&txn), "hello world!?");
+            assert_eq!(txn.get_array("history").map_or(0, |a| a.len(&txn)), 0);
+        }
+        assert!(!db
+            .load_split_doc(
+                "unknown",
+                &policy,
+                &["content"],
+                &mut Doc::new().transact_mut()
+            )
+            .unwrap());
+    });
+}
+
+/// Verifies that forcing a state vector read reconstructs and writes back a missing or stale state
+/// vector.
+pub fn state_vector_force<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    backend.write(|db| {
+        assert!(db.state_vector_force(DOC_NAME).unwrap().is_none());
+
+        let doc = Doc::with_client_id(1);
+        let text = doc.get_or_insert_text("text");
+        let push = |chunk: &str| {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            let update = txn.encode_update_v1();
+            db.push_update(DOC_NAME, &update).unwrap();
+            update
+        };
+        let u1 = push("a");
+        push("b");
+        assert_eq!(db.get_state_vector(DOC_NAME).unwrap(), (None, false));
+
+        // missing state vector is reconstructed and written back
+        let sv = db.state_vector_force(DOC_NAME).unwrap().unwrap();
+        assert_eq!(sv, doc.transact().state_vector());
+        assert_eq!(
+            db.get_state_vector(DOC_NAME).unwrap(),
+            (Some(sv.clone()), true)
+        );
+
+        // stored state vector is returned without reconstructing the document
+        db.remove(&key_update(1, 1)).unwrap();
+        assert_eq!(db.state_vector_force(DOC_NAME).unwrap(), Some(sv.clone()));
+        db.upsert(&key_update(1, 1), &u1).unwrap();
+
+        // another push makes stored state vector stale
+        push("c");
+        let (stale, completed) = db.get_state_vector(DOC_NAME).unwrap();
+        assert_eq!(stale, Some(sv.clone()));
+        assert!(!completed);
+        let sv = db.state_vector_force(DOC_NAME).unwrap().unwrap();
+        assert_eq!(sv, doc.transact().state_vector());
+
+        // flush rewrites state vector without recorded sequence number, so that sequence numbers
+        // reused by later pushes are not mistaken for the covered ones
+        db.flush_doc(DOC_NAME).unwrap();
+        assert_eq!(db.get_state_vector(DOC_NAME).unwrap(), (Some(sv), true));
+        push("d");
+        push("e");
+        push("f");
+        assert!(!db.get_state_vector(DOC_NAME).unwrap().1);
+        assert_eq!(
+            db.state_vector_force(DOC_NAME).unwrap(),
+            Some(doc.transact().state_vector())
+        );
+    });
+}
+
+/// Verifies that updates of separate channels are stored and flushed independently, but are applied
+/// in the order they were made.
+pub fn update_channels<B: Backend>(backend: &B) {
+    const CURSORS: u8 = 1;
+
+    fn content<'a, DB: DocOps<'a>>(db: &DB) -> (String, Option<Out>)
+    where
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let cursors = doc.get_or_insert_map("cursors");
+        assert!(db.load_doc("doc", &mut doc.transact_mut()).unwrap());
+        let txn = doc.transact();
+        (text.get_string(&txn), cursors.get(&txn, "alice"))
+    }
+
+    // edits and cursor annotations made by the same client interleave, so that updates of
+    // each channel depend on the updates of the other one
+    let source = Doc::with_client_id(1);
+    let text = source.get_or_insert_text("text");
+    let cursors = source.get_or_insert_map("cursors");
+    let mut edits = Vec::new();
+    let mut annotations = Vec::new();
+    for (chunk, cursor) in [("hello", "5"), (" world", "11")].iter() {
+        let mut txn = source.transact_mut();
+        text.push(&mut txn, chunk);
+        edits.push(txn.encode_update_v1());
+        drop(txn);
+        let mut txn = source.transact_mut();
+        cursors.insert(&mut txn, "alice", *cursor);
+        annotations.push(txn.encode_update_v1());
+    }
+    let expected = ("hello world".to_string(), Some(Out::Any(Any::from("11"))));
+
+    backend.write(|db| {
+        for (i, (edit, annotation)) in edits.iter().zip(annotations.iter()).enumerate() {
+            let seq = i as u32 + 1;
+            assert_eq!(db.push_update("doc", edit).unwrap().seq, seq);
+            let receipt = db.push_update_channel("doc", CURSORS, annotation).unwrap();
+            assert_eq!(receipt.seq, seq);
+            // channel updates are not tracked by pending updates counter
+            assert_eq!(receipt.pending_updates, seq);
+        }
+
+        // default channel is applied first, its second edit waits for the annotation preceding
+        // it, so the result is the same as if updates were applied in the order they were made
+        assert_eq!(content(db), expected);
+        let reversed = Doc::new();
+        {
+            let mut txn = reversed.transact_mut();
+            for update in annotations.iter().chain(edits.iter()).rev() {
+                txn.apply_update(Update::decode_v1(update).unwrap());
+            }
+        }
+        let txn = reversed.transact();
+        let reversed = (
+            txn.get_text("text").unwrap().get_string(&txn),
+            txn.get_map("cursors").unwrap().get(&txn, "alice"),
+        );
+        assert_eq!(reversed, expected);
+        assert!(!db.get_state_vector("doc").unwrap().1);
+
+        // flushing a channel leaves the other one intact
+        let outcome = db.flush_channel("doc", CURSORS).unwrap().unwrap();
+        assert_eq!(outcome.updates_folded, 2);
+        assert!(db.flush_channel("doc", CURSORS).unwrap().is_none());
+        assert_eq!(db.decoded_updates("doc").unwrap().count(), 2);
+        assert_eq!(content(db), expected);
+
+        // sequence numbers of a flushed channel start over
+        let annotation = {
+            let mut txn = source.transact_mut();
+            cursors.insert(&mut txn, "alice", "12");
+            txn.encode_update_v1()
+        };
+        let receipt = db.push_update_channel("doc", CURSORS, &annotation).unwrap();
+        assert_eq!(receipt.seq, 1);
+        let expected = ("hello world".to_string(), Some(Out::Any(Any::from("12"))));
+
+        let outcome = db.flush_channel("doc", DEFAULT_CHANNEL).unwrap().unwrap();
+        assert_eq!(outcome.updates_folded, 2);
+        assert_eq!(db.decoded_updates("doc").unwrap().count(), 0);
+        assert_eq!(content(db), expected);
+        assert!(db.get_state_vector("doc").unwrap().1);
+        let outcome = db.flush_channel("doc", CURSORS).unwrap().unwrap();
+        assert_eq!(outcome.updates_folded, 1);
+        assert_eq!(content(db), expected);
+        assert!(db.flush_doc("doc").unwrap().is_none());
+    });
+}
+
+/// Verifies that writes are recorded in the update feed in the order of their times, which can be
+/// pruned, if the feed is enabled.
+pub fn update_feed<B: Backend>(backend: &B) {
+    use crate::config::StoreConfig;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let config = StoreConfig {
+        update_feed: true,
+        ..StoreConfig::DEFAULT
+    };
+
+    backend.write_configured(&config, |db| {
+        let a = Doc::with_client_id(1);
+        let b = Doc::with_client_id(2);
+        let mut pushed = Vec::new();
+        for (i, (name, doc)) in [("a", &a), ("b", &b), ("a", &a), ("b", &b)]
+            .iter()
+            .enumerate()
+        {
+            let text = doc.get_or_insert_text("text");
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, &"x".repeat(i + 1));
+            let update = txn.encode_update_v1();
+            let receipt = db.push_update(*name, &update).unwrap();
+            pushed.push((name.as_bytes().to_vec(), receipt.seq, update.len() as u32));
+            // keep entries of interleaved documents in different milliseconds
+            std::thread::sleep(Duration::from_millis(2));
+        }
+
+        // entries of all documents are ordered by the time of their writes
+        let all = UNIX_EPOCH..SystemTime::now() + Duration::from_secs(60);
+        let entries: Vec<_> = db.iter_feed(all.clone(), 100).unwrap().collect();
+        let feed: Vec<_> = entries
+            .iter()
+            .map(|e| (e.doc_name.clone(), e.seq, e.len))
+            .collect();
+        assert_eq!(feed, pushed);
+        assert_eq!(feed[2].1, 2);
+        assert!(entries.windows(2).all(|w| w[0].time < w[1].time));
+        assert_eq!(db.iter_feed(all.clone(), 3).unwrap().count(), 3);
+        let recent = entries[2].time..all.end;
+        let recent: Vec<_> = db.iter_feed(recent, 100).unwrap().collect();
+        assert_eq!(recent, entries[2..].to_vec());
+
+        // pruning removes entries written before the cutoff
+        assert_eq!(db.prune_feed(entries[2].time).unwrap(), 2);
+        let remaining: Vec<_> = db.iter_feed(all.clone(), 100).unwrap().collect();
+        assert_eq!(remaining, entries[2..].to_vec());
+
+        // stores without the feed enabled don't write to it
+        let db: &B::Store<'_> = db;
+        let text = a.get_or_insert_text("text");
+        let mut txn = a.transact_mut();
+        text.push(&mut txn, "y");
+        db.push_update("a", &txn.encode_update_v1()).unwrap();
+        db.push_update("c", &txn.encode_update_v1()).unwrap();
+        drop(txn);
+        let remaining: Vec<_> = db.iter_feed(all, 100).unwrap().collect();
+        assert_eq!(remaining, entries[2..].to_vec());
+    });
+}
+
+/// Verifies that state vectors which don't match documents they belong to are detected and
+/// repaired.
+pub fn verify_state_vectors<B: Backend>(backend: &B) {
+    backend.write(|db| {
+        let doc = Doc::with_client_id(1);
+        let text = doc.get_or_insert_text("text");
+        text.push(&mut doc.transact_mut(), "hello");
+        // documents get subsequent OIDs: a - 1, b - 2, c - 3
+        for name in ["a", "b", "c"].iter() {
+            db.insert_doc(name, &doc.transact()).unwrap();
+        }
+        let update = {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, " world");
+            txn.encode_update_v1()
+        };
+        db.push_update("b", &update).unwrap();
+        let bogus = {
+            let other = Doc::with_client_id(99);
+            let text = other.get_or_insert_text("text");
+            text.push(&mut other.transact_mut(), "bogus");
+            let sv = other.transact().state_vector();
+            sv
+        };
+        db.upsert(&key_state_vector(3), &bogus.encode_v1()).unwrap();
+
+        let drifts = db.verify_state_vectors(None).unwrap();
+        let names: Vec<_> = drifts.iter().map(|d| d.name.as_ref()).collect();
+        assert_eq!(names, vec![b"b".as_ref(), b"c".as_ref()]);
+        // pending update pushed after the state vector has been stored
+        assert!(drifts[0].explained_by_pending);
+        assert_eq!(drifts[0].computed, doc.transact().state_vector());
+        // partial write
+        assert!(!drifts[1].explained_by_pending);
+        assert_eq!(drifts[1].stored, bogus);
+        assert_ne!(drifts[1].computed, bogus);
+        assert_eq!(db.verify_state_vectors(Some(1.0)).unwrap(), drifts);
+        assert!(db.verify_state_vectors(Some(0.0)).unwrap().is_empty());
+
+        let repaired = db.repair_state_vector("c").unwrap().unwrap();
+        assert_eq!(repaired, drifts[1].computed);
+        assert!(db.verify_sv_consistency("c").unwrap());
+        let repaired = db.repair_state_vector("b").unwrap().unwrap();
+        assert_eq!(repaired, doc.transact().state_vector());
+        assert_eq!(db.get_state_vector("b").unwrap(), (Some(repaired), true));
+        assert!(db.verify_state_vectors(None).unwrap().is_empty());
+        assert!(db.repair_state_vector("missing").unwrap().is_none());
+    });
+}
+
+/// Verifies that a document can be read through a closure without loading it into a caller's
+/// document, and that names of its root types can be listed.
+pub fn with_doc<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    backend.write(|db| {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let map = doc.get_or_insert_map("meta");
+        text.push(&mut doc.transact_mut(), &"lorem ipsum ".repeat(50));
+        map.insert(&mut doc.transact_mut(), "title", "lorem");
+        db.insert_doc(DOC_NAME, &doc.transact()).unwrap();
+        let sv = doc.transact().state_vector();
+        text.insert(&mut doc.transact_mut(), 0, "> ");
+        db.push_update(DOC_NAME, &doc.transact().encode_diff_v1(&sv))
+            .unwrap();
+
+        let preview = db
+            .with_doc(DOC_NAME, |doc| {
+                let text = doc.get_or_insert_text("text");
+                let content = text.get_string(&doc.transact());
+                content.chars().take(14).collect::<String>()
+            })
+            .unwrap();
+        assert_eq!(preview, Some("> lorem ipsum ".to_string()));
+        assert_eq!(
+            db.get_root_type_names(DOC_NAME).unwrap(),
+            vec!["meta".to_string(), "text".to_string()]
+        );
+
+        assert_eq!(db.with_doc("unknown", |_| ()).unwrap(), None);
+        assert!(db.get_root_type_names("unknown").unwrap().is_empty());
+    });
+}
+
+/// Verifies that writes carrying fencing tokens older than the one recorded for a document are
+/// refused.
+pub fn write_fencing<B: Backend>(backend: &B) {
+    use crate::keys::key_fence;
+
+    backend.write(|db| {
+        let write = |doc: &Doc, content: &str| {
+            let text = doc.get_or_insert_text("text");
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, content);
+            txn.encode_update_v1()
+        };
+        let old_owner = Doc::with_client_id(1);
+        let new_owner = Doc::with_client_id(2);
+
+        db.set_fence("doc", 1).unwrap();
+        db.push_update_fenced("doc", &write(&old_owner, "a"), 1)
+            .unwrap();
+        new_owner.transact_mut().apply_update(
+            Update::decode_v1(&old_owner.transact().encode_diff_v1(&StateVector::default()))
+                .unwrap(),
+        );
+
+        // document is handed over, but the old owner keeps writing
+        db.set_fence("doc", 2).unwrap();
+        assert_eq!(db.get_fence("doc").unwrap(), Some(2));
+        let stale = write(&old_owner, "b");
+        assert!(matches!(
+            db.push_update_fenced("doc", &stale, 1),
+            Err(Error::Fenced { current: 2 })
+        ));
+        assert!(matches!(
+            db.flush_doc_fenced("doc", 1),
+            Err(Error::Fenced { current: 2 })
+        ));
+        assert!(matches!(
+            db.check_fence("doc", 1),
+            Err(Error::Fenced { current: 2 })
+        ));
+        db.push_update_fenced("doc", &write(&new_owner, "c"), 2)
+            .unwrap();
+        assert!(db.flush_doc_fenced("doc", 3).unwrap().is_some());
+
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        db.load_doc("doc", &mut doc.transact_mut()).unwrap();
+        assert_eq!(text.get_string(&doc.transact()), "ac");
+
+        // tokens only move forward
+        assert!(matches!(
+            db.set_fence("doc", 1),
+            Err(Error::Fenced { current: 2 })
+        ));
+        db.set_fence("doc", 2).unwrap();
+        assert_eq!(db.get_fence("doc").unwrap(), Some(2));
+
+        // documents without a fence accept all fenced writes
+        assert_eq!(db.get_fence("other").unwrap(), None);
+        db.push_update_fenced("other", &stale, 0).unwrap();
+
+        // fence outlives the cleared document
+        db.clear_doc("doc").unwrap();
+        assert_eq!(db.get_fence("doc").unwrap(), Some(2));
+        assert!(matches!(
+            db.push_update_fenced("doc", &stale, 1),
+            Err(Error::Fenced { current: 2 })
+        ));
+        assert!(!db.load_doc("doc", &mut Doc::new().transact_mut()).unwrap());
+
+        // document created again under the same name takes the fence over
+        db.push_update_fenced("doc", &write(&new_owner, "d"), 2)
+            .unwrap();
+        assert_eq!(db.get_fence("doc").unwrap(), Some(2));
+        assert!(KVStore::get(db, &key_fence(b"doc")).unwrap().is_none());
+        assert!(matches!(
+            db.push_update_fenced("doc", &stale, 1),
+            Err(Error::Fenced { current: 2 })
+        ));
+    });
+}
+
+/// Verifies that document metadata is initialized only by the first caller, until the document is
+/// cleared.
+pub fn init_meta_once<B: Backend>(backend: &B) {
+    backend.write(|db| {
+        let owner = |owner: &'static str| [(b"owner".as_ref(), owner.as_bytes())];
+        assert!(db.init_meta_once("doc", &owner("alice")).unwrap());
+        assert!(!db.init_meta_once("doc", &owner("bob")).unwrap());
+        let stored = db.get_meta("doc", "owner").unwrap().unwrap();
+        assert_eq!(stored.as_ref(), b"alice");
+
+        // documents created by a push can be initialized by whoever has created them
+        let receipt = db.push_update("other", &[0, 0]).unwrap();
+        assert!(receipt.doc_created);
+        assert!(db.init_meta_once("other", &owner("bob")).unwrap());
+
+        // clearing a document removes its marker, so a recreated one can be initialized again
+        db.clear_doc("doc").unwrap();
+        assert!(db.init_meta_once("doc", &owner("carol")).unwrap());
+        let stored = db.get_meta("doc", "owner").unwrap().unwrap();
+        assert_eq!(stored.as_ref(), b"carol");
+    });
+}
+
+/// Verifies that edits of audited root types are recorded in the audit log, from which they're
+/// selected and pruned by time.
+pub fn audit_log<B: Backend>(backend: &B) {
+    use crate::audit::{AuditConfig, AuditRoot};
+    use crate::config::StoreConfig;
+    use std::time::{Duration, SystemTime};
+    use yrs::{XmlFragment, XmlTextPrelim};
+
+    let config = StoreConfig {
+        audit: Some(AuditConfig {
+            roots: vec![AuditRoot::Text("text".into()), AuditRoot::Xml("xml".into())],
+            excerpt_len: 5,
+        }),
+        ..StoreConfig::DEFAULT
+    };
+
+    backend.write_configured(&config, |db| {
+        let doc = Doc::with_client_id(1);
+        let text = doc.get_or_insert_text("text");
+        let xml = doc.get_or_insert_xml_fragment("xml");
+        let map = doc.get_or_insert_map("map");
+        let start = SystemTime::now() - Duration::from_secs(1);
+
+        let update = {
+            let mut txn = doc.transact_mut();
+            text.insert(&mut txn, 0, "hello world");
+            txn.encode_update_v1()
+        };
+        db.push_update_with_origin("doc", &update, b"alice")
+            .unwrap();
+        let update = {
+            let mut txn = doc.transact_mut();
+            text.remove_range(&mut txn, 0, 6);
+            text.insert(&mut txn, 0, "hi \u{1f600} ");
+            txn.encode_update_v1()
+        };
+        let receipt = db.push_update_with_origin("doc", &update, b"bob").unwrap();
+        assert_eq!(receipt.seq, 2);
+        assert_eq!(receipt.pending_updates, 2);
+
+        // changes of roots which are not audited aren't recorded
+        let update = {
+            let mut txn = doc.transact_mut();
+            map.insert(&mut txn, "key", "value");
+            txn.encode_update_v1()
+        };
+        db.push_update_with_origin("doc", &update, b"carol")
+            .unwrap();
+
+        // text of xml nodes is recorded once they are edited
+        let (update, xml_text) = {
+            let mut txn = doc.transact_mut();
+            let xml_text = xml.insert(&mut txn, 0, XmlTextPrelim::new(""));
+            (txn.encode_update_v1(), xml_text)
+        };
+        db.push_update_with_origin("doc", &update, b"dave").unwrap();
+        let update = {
+            let mut txn = doc.transact_mut();
+            xml_text.insert(&mut txn, 0, "abc");
+            txn.encode_update_v1()
+        };
+        db.push_update_with_origin("doc", &update, b"dave").unwrap();
+
+        let records: Vec<_> = db
+            .iter_audit("doc", start..SystemTime::now() + Duration::from_secs(1))
+            .unwrap()
+            .collect();
+        let summary: Vec<_> = records
+            .iter()
+            .map(|r| {
+                let origin = String::from_utf8(r.origin.clone()).unwrap();
+                (
+                    origin,
+                    r.root.as_str(),
+                    r.inserted,
+                    r.deleted,
+                    r.excerpt.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("alice".to_string(), "text", 11, 0, "hello"),
+                ("bob".to_string(), "text", 6, 6, "hi \u{1f600} "),
+                ("dave".to_string(), "xml", 3, 0, "abc"),
+            ]
+        );
+        assert!(records.windows(2).all(|w| w[0].time <= w[1].time));
+
+        // audit records coexist with document entries
+        let loaded = Doc::new();
+        let loaded_text = loaded.get_or_insert_text("text");
+        db.load_doc("doc", &mut loaded.transact_mut()).unwrap();
+        assert_eq!(
+            loaded_text.get_string(&loaded.transact()),
+            "hi \u{1f600} world"
+        );
+        db.flush_doc("doc").unwrap().unwrap();
+        assert_eq!(
+            db.iter_audit("doc", start..SystemTime::now() + Duration::from_secs(1))
+                .unwrap()
+                .count(),
+            3
+        );
+        for e in db.iter_range(&[V1], &[V1 + 1]).unwrap() {
+            let parsed = parse_key(e.key()).unwrap();
+            assert_eq!(build_key(&parsed), e.key());
+        }
+
+        // plain pushes and stores without audit log don't record anything
+        db.push_update(
+            "doc",
+            &doc.transact()
+                .encode_state_as_update_v1(&StateVector::default()),
+        )
+        .unwrap();
+        db.insert_meta("other", "owner", b"alice").unwrap();
+        db.push_update("other", &update).unwrap();
+        (*db)
+            .push_update_with_origin("other", &update, b"erin")
+            .unwrap();
+        assert_eq!(
+            db.iter_audit("other", start..SystemTime::now())
+                .unwrap()
+                .count(),
+            0
+        );
+        assert_eq!(
+            db.iter_audit("missing", start..SystemTime::now())
+                .unwrap()
+                .count(),
+            0
+        );
+
+        // records are selected and pruned by the time of their writes
+        let later = SystemTime::now() + Duration::from_secs(3600);
+        assert_eq!(db.iter_audit("doc", later..later).unwrap().count(), 0);
+        assert_eq!(db.iter_audit("doc", start..start).unwrap().count(), 0);
+        assert_eq!(db.prune_audit("doc", start).unwrap(), 0);
+        assert_eq!(db.prune_audit("doc", later).unwrap(), 3);
+        assert_eq!(db.iter_audit("doc", start..later).unwrap().count(), 0);
+        assert_eq!(db.prune_audit("missing", later).unwrap(), 0);
+
+        // cleared documents take their audit records with them
+        db.push_update_with_origin("doc", &update, b"frank")
+            .unwrap();
+        db.push_update_with_origin("doc", &update, b"frank")
+            .unwrap();
+        assert_eq!(db.iter_audit("doc", start..later).unwrap().count(), 0);
+        let update = {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, "!");
+            txn.encode_update_v1()
+        };
+        db.push_update_with_origin("doc", &update, b"frank")
+            .unwrap();
+        assert_eq!(db.iter_audit("doc", start..later).unwrap().count(), 1);
+        db.clear_doc("doc").unwrap();
+        db.push_update("doc", &update).unwrap();
+        assert_eq!(db.iter_audit("doc", start..later).unwrap().count(), 0);
+    });
+}
+
+/// Verifies that document states above a threshold are written to a blob store, whose blobs are
+/// collected once replaced or orphaned.
+pub fn blob_storage<B: Backend>(backend: &B) {
+    use crate::bench::Cleaner;
+    use crate::blob::{BlobId, BlobStore, FsBlobStore};
+    use crate::format::{BLOB_POINTER_LEN, BLOB_POINTER_TAG};
+
+    let dir = Cleaner::new("conformance-blob-storage");
+    let blobs = Arc::new(FsBlobStore::new(dir.dir()).unwrap());
+    let config = StoreConfig {
+        blob_threshold: 256,
+        ..StoreConfig::DEFAULT
+    };
+
+    backend.transaction(|db| {
+        let db = ConfiguredStore::new(db, config.clone()).with_blob_store(blobs.clone());
+        let doc = Doc::with_client_id(1);
+        let text = doc.get_or_insert_text("text");
+        let push = |content: &str| {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, content);
+            txn.encode_update_v1()
+        };
+        let read = |db: &ConfiguredStore<B::Store<'_>>| {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            let loaded = db.load_doc("doc", &mut doc.transact_mut());
+            loaded.map(|_| text.get_string(&doc.transact()))
+        };
+        let is_pointer = |db: &ConfiguredStore<B::Store<'_>>| {
+            let state = db.get(&key_doc(1)).unwrap().unwrap();
+            state.as_ref().len() == BLOB_POINTER_LEN && state.as_ref()[0] == BLOB_POINTER_TAG
+        };
+
+        // states below the threshold are stored inline
+        db.push_update("doc", &push("small")).unwrap(); // OID 1
+        db.flush_doc("doc").unwrap().unwrap();
+        assert!(!is_pointer(&db));
+        assert!(blobs.list().unwrap().is_empty());
+
+        // once they cross it, they are written as blobs
+        let large = "x".repeat(300);
+        db.push_update("doc", &push(&large)).unwrap();
+        db.flush_doc("doc").unwrap().unwrap();
+        assert!(is_pointer(&db));
+        assert_eq!(blobs.list().unwrap().len(), 1);
+        assert_eq!(read(&db).unwrap(), format!("small{}", large));
+
+        // replaced blobs are kept until they are collected
+        db.push_update("doc", &push("more")).unwrap();
+        db.flush_doc("doc").unwrap().unwrap();
+        assert_eq!(blobs.list().unwrap().len(), 2);
+        assert_eq!(db.gc_blobs(Duration::ZERO).unwrap(), 1);
+        assert_eq!(blobs.list().unwrap().len(), 1);
+        let expected = format!("small{}more", large);
+        assert_eq!(read(&db).unwrap(), expected);
+
+        // blobs orphaned by a crash are collected once they are old enough
+        let orphan = BlobId::new();
+        blobs.put(&orphan, b"orphan").unwrap();
+        let old_orphan = BlobId([0; 16]);
+        blobs.put(&old_orphan, b"orphan").unwrap();
+        assert_eq!(db.gc_blobs(Duration::from_secs(3600)).unwrap(), 1);
+        assert!(blobs.get(&old_orphan).unwrap().is_none());
+        assert_eq!(blobs.list().unwrap().len(), 2);
+        assert_eq!(db.gc_blobs(Duration::ZERO).unwrap(), 1);
+        assert!(blobs.get(&orphan).unwrap().is_none());
+        assert_eq!(read(&db).unwrap(), expected);
+
+        // unresolvable blobs are reported, unlike missing documents
+        let id = blobs.list().unwrap()[0];
+        let data = blobs.get(&id).unwrap().unwrap();
+        blobs.remove(&id).unwrap();
+        assert!(matches!(read(&db), Err(Error::BlobUnavailable { id: missing }) if missing == id));
+        blobs.put(&id, b"garbage").unwrap();
+        assert!(matches!(read(&db), Err(Error::CorruptedValue)));
+        blobs.put(&id, &data).unwrap();
+        let missing = db.load_doc("missing", &mut Doc::new().transact_mut());
+        assert!(!missing.unwrap());
+
+        // stores without blob store cannot resolve pointers
+        let plain = db.into_inner();
+        let res = plain.load_doc("doc", &mut Doc::new().transact_mut());
+        assert!(matches!(res, Err(Error::BlobUnavailable { .. })));
+        let db = ConfiguredStore::new(plain, config).with_blob_store(blobs.clone());
+
+        db.clear_doc("doc").unwrap();
+        assert_eq!(db.gc_blobs(Duration::ZERO).unwrap(), 1);
+        assert!(blobs.list().unwrap().is_empty());
+        (db.into_inner(), ())
+    });
+}
+
+/// Verifies that drafts are hidden from listings and tenant usage until they're promoted, and
+/// purged once old enough.
+pub fn drafts<B: Backend>(backend: &B) {
+    use crate::keys::{key_meta, META_DRAFT};
+    use crate::listing::ListOptions;
+    use crate::usage;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    backend.transaction(|db| {
+        let db = ConfiguredStore::new(db, StoreConfig::DEFAULT)
+            .with_tenant_extractor(Arc::new(usage::first_segment));
+        let update = |content: &str| {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, content);
+            txn.encode_update_v1()
+        };
+        let docs = || -> Vec<Box<[u8]>> { db.iter_docs().unwrap().collect() };
+        let listed = |include_drafts: bool| -> Vec<(Box<[u8]>, bool)> {
+            let options = ListOptions {
+                include_drafts,
+                ..ListOptions::default()
+            };
+            db.list_docs(options)
+                .unwrap()
+                .map(|doc| {
+                    let doc = doc.unwrap();
+                    (doc.name, doc.draft)
+                })
+                .collect()
+        };
+        let name = |name: &str| -> Box<[u8]> { name.as_bytes().into() };
+
+        db.push_update("acme/doc", &update("hello")).unwrap(); // OID 1
+        let usage = db.usage_of("acme").unwrap();
+        assert!(usage > 0);
+
+        // drafts are hidden from listings, but can be read and written
+        assert!(db.create_draft("acme/draft").unwrap()); // OID 2
+        assert!(!db.create_draft("acme/draft").unwrap());
+        assert!(!db.create_draft("acme/doc").unwrap());
+        assert!(db.is_draft("acme/draft").unwrap());
+        assert!(!db.is_draft("acme/doc").unwrap());
+        db.push_update("acme/draft", &update("draft")).unwrap();
+        let loaded = Doc::new();
+        let text = loaded.get_or_insert_text("text");
+        assert!(db
+            .load_doc("acme/draft", &mut loaded.transact_mut())
+            .unwrap());
+        assert_eq!(text.get_string(&loaded.transact()), "draft");
+        assert_eq!(docs(), vec![name("acme/doc")]);
+        assert_eq!(db.iter_docs_prefix("acme/").unwrap().count(), 1);
+        assert_eq!(db.iter_docs_with(true).unwrap().count(), 2);
+        assert_eq!(listed(false), vec![(name("acme/doc"), false)]);
+        assert_eq!(
+            listed(true),
+            vec![(name("acme/doc"), false), (name("acme/draft"), true)]
+        );
+
+        // drafts don't count towards usage of their tenants until they're promoted
+        assert_eq!(db.usage_of("acme").unwrap(), usage);
+        assert!(db.promote_draft("acme/draft").unwrap());
+        assert!(!db.promote_draft("acme/draft").unwrap());
+        assert!(!db.promote_draft("acme/doc").unwrap());
+        assert!(!db.is_draft("acme/draft").unwrap());
+        assert!(db.get(&key_meta(2, META_DRAFT)).unwrap().is_none());
+        assert!(db.usage_of("acme").unwrap() > usage);
+        assert_eq!(docs(), vec![name("acme/doc"), name("acme/draft")]);
+
+        // only drafts created at least a given time ago are purged
+        assert!(db.create_draft("acme/old").unwrap()); // OID 3
+        assert!(db.create_draft("acme/fresh").unwrap()); // OID 4
+        db.push_update("acme/old", &update("old")).unwrap();
+        let created = SystemTime::now() - Duration::from_secs(2 * 3600);
+        let millis = created.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        db.upsert(&key_meta(3, META_DRAFT), &millis.to_be_bytes())
+            .unwrap();
+        let usage = db.usage_of("acme").unwrap();
+        assert_eq!(db.purge_drafts(Duration::from_secs(3600)).unwrap(), 1);
+        assert!(!db.is_draft("acme/old").unwrap());
+        assert!(db.get(&key_meta(3, META_DRAFT)).unwrap().is_none());
+        assert!(!db
+            .load_doc("acme/old", &mut Doc::new().transact_mut())
+            .unwrap());
+        assert!(db.is_draft("acme/fresh").unwrap());
+        assert_eq!(db.usage_of("acme").unwrap(), usage);
+        assert_eq!(db.purge_drafts(Duration::ZERO).unwrap(), 1);
+        assert_eq!(db.iter_docs_with(true).unwrap().count(), 2);
+        assert_eq!(docs(), vec![name("acme/doc"), name("acme/draft")]);
+        (db.into_inner(), ())
+    });
+}
+
+/// Verifies that documents with updates which cannot be integrated are reported as incomplete,
+/// until the missing updates are pushed.
+pub fn incomplete_history<B: Backend>(backend: &B) {
+    use crate::config::{ConfiguredStore, StoreConfig};
+
+    backend.transaction(|db| {
+        let doc = Doc::with_client_id(1);
+        let text = doc.get_or_insert_text("text");
+        for chunk in ["a", "b", "c"].iter() {
+            let update = {
+                let mut txn = doc.transact_mut();
+                text.push(&mut txn, chunk);
+                txn.encode_update_v1()
+            };
+            db.push_update("doc", &update).unwrap();
+        }
+
+        // complete history
+        let loaded = Doc::new();
+        let result = db
+            .load_doc_checked("doc", &mut loaded.transact_mut())
+            .unwrap();
+        assert!(result.found);
+        assert_eq!(result.missing, None);
+        assert!(db.missing_ranges("doc").unwrap().is_empty());
+        let result = db
+            .load_doc_checked("missing", &mut Doc::new().transact_mut())
+            .unwrap();
+        assert!(!result.found);
+        assert_eq!(result.missing, None);
+        assert!(db.missing_ranges("missing").unwrap().is_empty());
+
+        // drop the update inserting "b", so that the one inserting "c" cannot be integrated
+        db.remove(&key_update(1, 2)).unwrap();
+        let loaded = Doc::new();
+        let loaded_text = loaded.get_or_insert_text("text");
+        let result = db
+            .load_doc_checked("doc", &mut loaded.transact_mut())
+            .unwrap();
+        assert!(result.found);
+        let missing = result.missing.unwrap();
+        assert_eq!(missing.get(&1), 1);
+        assert_eq!(loaded_text.get_string(&loaded.transact()), "a");
+        assert_eq!(loaded.transact().state_vector().get(&1), 1);
+        assert!(db.load_doc("doc", &mut Doc::new().transact_mut()).unwrap());
+        assert_eq!(db.missing_ranges("doc").unwrap(), vec![(1, 1..2)]);
+
+        // diffs lack pending data unless strict mode refuses to serve them
+        let diff = db
+            .get_diff("doc", &StateVector::default())
+            .unwrap()
+            .unwrap();
+        let remote = Doc::new();
+        let remote_text = remote.get_or_insert_text("text");
+        remote
+            .transact_mut()
+            .apply_update(Update::decode_v1(&diff).unwrap());
+        assert_eq!(remote_text.get_string(&remote.transact()), "a");
+        let strict = ConfiguredStore::new(
+            db,
+            StoreConfig {
+                strict_history: true,
+                ..StoreConfig::DEFAULT
+            },
+        );
+        match strict.get_diff("doc", &StateVector::default()) {
+            Err(Error::IncompleteHistory { missing }) => assert_eq!(missing.get(&1), 1),
+            other => panic!("expected incomplete history, got {:?}", other),
+        }
+        assert!(matches!(
+            strict.get_diff_with("doc", &StateVector::default(), yrs::Options::default()),
+            Err(Error::IncompleteHistory { .. })
+        ));
+        assert_eq!(
+            strict.get_diff("missing", &StateVector::default()).unwrap(),
+            None
+        );
+
+        // once the lost update is pushed again, history is complete
+        let update = {
+            let txn = doc.transact();
+            let mut sv = StateVector::default();
+            sv.set_max(1, 1);
+            txn.encode_diff_v1(&sv)
+        };
+        strict.push_update("doc", &update).unwrap();
+        assert!(strict.missing_ranges("doc").unwrap().is_empty());
+        let diff = strict
+            .get_diff("doc", &StateVector::default())
+            .unwrap()
+            .unwrap();
+        let remote = Doc::new();
+        let remote_text = remote.get_or_insert_text("text");
+        remote
+            .transact_mut()
+            .apply_update(Update::decode_v1(&diff).unwrap());
+        assert_eq!(remote_text.get_string(&remote.transact()), "abc");
+        (strict.into_inner(), ())
+    });
+}
+
+/// Verifies that documents are listed together with their archived flag, pending updates and last
+/// modification time.
+pub fn list_docs_with_timestamps<B: Backend>(backend: &B) {
+    use crate::config::StoreConfig;
+    use crate::listing::ListOptions;
+
+    let config = StoreConfig {
+        timestamps: true,
+        ..StoreConfig::DEFAULT
+    };
+
+    backend.write_configured(&config, |db| {
+        let doc = Doc::with_client_id(1);
+        let text = doc.get_or_insert_text("text");
+        let push = |name: &str| {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, "x");
+            db.push_update(name, &txn.encode_update_v1()).unwrap();
+        };
+        push("a/flushed");
+        db.flush_doc("a/flushed").unwrap();
+        push("a/pending");
+        push("a/archived");
+        assert!(db.archive_doc("a/archived").unwrap());
+        db.insert_meta("b/meta-only", "kind", b"placeholder")
+            .unwrap();
+        push("b/pending");
+        push("b/pending");
+
+        // (name, archived, has pending updates, has last modified time)
+        let states = [
+            ("a/archived", true, false, true),
+            ("a/flushed", false, false, true),
+            ("a/pending", false, true, true),
+            ("b/meta-only", false, false, false),
+            ("b/pending", false, true, true),
+        ];
+        for prefix in ["", "a/", "b/", "b/p", "c/"].iter() {
+            for &include_archived in [false, true].iter() {
+                for &hints in [false, true].iter() {
+                    let options = ListOptions {
+                        prefix: prefix.as_bytes().to_vec(),
+                        include_archived,
+                        include_drafts: false,
+                        hints,
+                    };
+                    let listed: Vec<_> = db
+                        .list_docs(options.clone())
+                        .unwrap()
+                        .map(|doc| doc.unwrap())
+                        .collect();
+                    let expected: Vec<_> = states
+                        .iter()
+                        .filter(|(name, archived, _, _)| {
+                            name.starts_with(prefix) && (include_archived || !archived)
+                        })
+                        .collect();
+                    assert_eq!(listed.len(), expected.len(), "{:?}", options);
+                    for (doc, (name, archived, pending, modified)) in listed.iter().zip(expected) {
+                        assert_eq!(doc.name.as_ref(), name.as_bytes());
+                        assert_eq!(doc.archived, *archived);
+                        match &doc.hints {
+                            Some(h) => {
+                                assert!(hints);
+                                assert_eq!(h.has_pending_updates, *pending, "{}", name);
+                                assert_eq!(h.last_modified.is_some(), *modified, "{}", name);
+                                assert_eq!(h.last_modified, db.last_modified(*name).unwrap());
+                            }
+                            None => assert!(!hints),
+                        }
+                    }
+                    // listing matches the plain iterator over document names
+                    if !hints && prefix.is_empty() {
+                        let names: Vec<_> = db.iter_docs_with(include_archived).unwrap().collect();
+                        let listed: Vec<_> = listed.into_iter().map(|doc| doc.name).collect();
+                        assert_eq!(listed, names);
+                    }
+                }
+            }
+        }
+
+        // listing is lazy, so documents can be read while it's in progress
+        let mut listing = db.list_docs(ListOptions::default()).unwrap();
+        let first = listing.next().unwrap().unwrap();
+        assert_eq!(first.name.as_ref(), b"a/flushed");
+        assert_eq!(
+            db.get_meta("b/meta-only", "kind")
+                .unwrap()
+                .unwrap()
+                .as_ref(),
+            b"placeholder"
+        );
+        assert_eq!(listing.count(), 3);
+    });
+}
+
+/// Verifies that modification times, cache expiration and draft purging follow the clock of a
+/// store.
+pub fn manual_clock<B: Backend>(backend: &B) {
+    use crate::clock::{self, Clock, ManualClock};
+
+    const TTL: Duration = Duration::from_secs(60);
+    const HOUR: Duration = Duration::from_secs(3600);
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let config = StoreConfig {
+        timestamps: true,
+        sync_frame_ttl: Some(TTL),
+        ..StoreConfig::DEFAULT
+    };
+
+    backend.transaction(|db| {
+        let db = ConfiguredStore::new(db, config).with_clock(clock.clone());
+        let update = |content: &str| {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, content);
+            txn.encode_update_v1()
+        };
+
+        // modification times are read from the clock
+        db.push_update("doc", &update("a")).unwrap();
+        assert_eq!(
+            db.last_modified("doc").unwrap(),
+            Some(clock::system_time(1_000_000))
+        );
+        clock.advance(Duration::from_millis(1500));
+        db.push_update("doc", &update("b")).unwrap();
+        assert_eq!(
+            db.last_modified("doc").unwrap(),
+            Some(clock::system_time(1_001_500))
+        );
+
+        // cached sync frames are served until the clock reaches their expiration time
+        db.cache_sync_frame("doc", 1, b"frame", TTL).unwrap();
+        clock.advance(TTL - Duration::from_millis(1));
+        assert_eq!(
+            db.get_cached_sync_frame("doc", 1).unwrap(),
+            Some(b"frame".to_vec())
+        );
+        clock.advance(Duration::from_millis(1));
+        assert!(db.get_cached_sync_frame("doc", 1).unwrap().is_none());
+
+        // drafts are purged once they're old enough according to the clock
+        assert!(db.create_draft("old-draft").unwrap());
+        clock.advance(HOUR);
+        assert!(db.create_draft("new-draft").unwrap());
+        assert_eq!(db.purge_drafts(HOUR).unwrap(), 1);
+        assert!(!db.is_draft("old-draft").unwrap());
+        assert!(db.is_draft("new-draft").unwrap());
+        clock.advance(HOUR - Duration::from_millis(1));
+        assert_eq!(db.purge_drafts(HOUR).unwrap(), 0);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(db.purge_drafts(HOUR).unwrap(), 1);
+        assert!(!db.is_draft("new-draft").unwrap());
+
+        // clock may be moved backwards, which doesn't make anything expire
+        clock.set(0);
+        assert_eq!(clock.now(), 0);
+        db.cache_sync_frame("doc", 2, b"frame", TTL).unwrap();
+        assert!(db.get_cached_sync_frame("doc", 2).unwrap().is_some());
+        assert!(db.create_draft("draft").unwrap());
+        assert_eq!(db.purge_drafts(HOUR).unwrap(), 0);
+        (db.into_inner(), ())
+    });
+}
+
+/// Verifies that documents whose state has been removed by hand are served from what's left, and
+/// reported by verification.
+pub fn missing_doc_state<B: Backend>(backend: &B) {
+    use crate::config::{ConfiguredStore, StoreConfig};
+    use yrs::block::ClientID;
+
+    backend.transaction(|db| {
+        let update = |client: ClientID, chunk: &str| {
+            let doc = Doc::with_client_id(client);
+            let text = doc.get_or_insert_text("text");
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            txn.encode_update_v1()
+        };
+        // OID 1: flushed state of client 1, followed by an independent pending update of client 2
+        db.push_update("doc", &update(1, "hello")).unwrap();
+        db.flush_doc("doc").unwrap().unwrap();
+        db.push_update("doc", &update(2, "world")).unwrap();
+        let loaded = Doc::new();
+        let result = db
+            .load_doc_checked("doc", &mut loaded.transact_mut())
+            .unwrap();
+        assert_eq!(result.orphaned_sv, None);
+        let stored_sv = loaded.transact().state_vector();
+        db.put_state_vector("doc", &stored_sv).unwrap();
+
+        // botched manual cleanup removes the document state, but leaves its state vector
+        db.remove(&key_doc(1)).unwrap();
+        let loaded = Doc::new();
+        let result = db
+            .load_doc_checked("doc", &mut loaded.transact_mut())
+            .unwrap();
+        assert!(result.found);
+        assert_eq!(result.missing, None);
+        assert_eq!(result.orphaned_sv, Some(stored_sv.clone()));
+        let drifts = db.verify_state_vectors(None).unwrap();
+        assert_eq!(drifts.len(), 1);
+        assert!(drifts[0].doc_state_missing);
+        assert!(!drifts[0].explained_by_pending);
+
+        // lenient reads serve whatever is left
+        assert!(db
+            .get_diff("doc", &StateVector::default())
+            .unwrap()
+            .is_some());
+        let strict = ConfiguredStore::new(
+            db,
+            StoreConfig {
+                strict_history: true,
+                ..StoreConfig::DEFAULT
+            },
+        );
+        match strict.get_diff("doc", &StateVector::default()) {
+            Err(Error::MissingDocState { claimed }) => assert_eq!(claimed, stored_sv),
+            other => panic!("expected missing doc state, got {:?}", other),
+        }
+
+        // once the orphaned state vector is dropped, document degrades to its pending updates
+        assert!(strict.repair_drop_state_vector("doc").unwrap());
+        assert!(!strict.repair_drop_state_vector("doc").unwrap());
+        assert!(!strict.repair_drop_state_vector("missing").unwrap());
+        let diff = strict
+            .get_diff("doc", &StateVector::default())
+            .unwrap()
+            .unwrap();
+        let remote = Doc::new();
+        let remote_text = remote.get_or_insert_text("text");
+        remote
+            .transact_mut()
+            .apply_update(Update::decode_v1(&diff).unwrap());
+        // pending updates alone have no stored state vector
+        let (sv, up_to_date) = strict.get_state_vector("doc").unwrap();
+        assert_eq!((sv, up_to_date), (None, false));
+        assert!(strict.verify_state_vectors(None).unwrap().is_empty());
+        assert_eq!(remote_text.get_string(&remote.transact()), "world");
+
+        // state vectors of documents with a stored state are never dropped
+        strict.insert_doc("other", &remote.transact()).unwrap();
+        assert!(!strict.repair_drop_state_vector("other").unwrap());
+        (strict.into_inner(), ())
+    });
+}
+
+/// Verifies that cached sync frames are served until their document changes or they expire, and are
+/// removed together with their document.
+pub fn sync_frame_cache<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+    const TTL: Duration = Duration::from_secs(60);
+    let config = StoreConfig {
+        sync_frame_ttl: Some(TTL),
+        ..StoreConfig::DEFAULT
+    };
+
+    backend.write_configured(&config, |db| {
+        let doc = Doc::with_client_id(1);
+        let text = doc.get_or_insert_text("text");
+        let push = |chunk: &str| {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            db.push_update(DOC_NAME, &txn.encode_update_v1()).unwrap();
+        };
+        let frames = || {
+            let end = [V1, KEYSPACE_SYNC + 1];
+            KVStore::iter_range(db, &[V1, KEYSPACE_SYNC], &end)
+                .unwrap()
+                .take_while(|e| e.key() < &end[..])
+                .count()
+        };
+
+        // frames of missing documents are not stored
+        db.cache_sync_frame(DOC_NAME, 1, b"frame", TTL).unwrap();
+        assert!(db.get_cached_sync_frame(DOC_NAME, 1).unwrap().is_none());
+
+        push("a");
+        db.cache_sync_frame(DOC_NAME, 1, b"frame-1", TTL).unwrap();
+        assert_eq!(
+            db.get_cached_sync_frame(DOC_NAME, 1).unwrap(),
+            Some(b"frame-1".to_vec())
+        );
+        assert!(db.get_cached_sync_frame(DOC_NAME, 2).unwrap().is_none());
+
+        // writes invalidate cached frames
+        push("b");
+        assert!(db.get_cached_sync_frame(DOC_NAME, 1).unwrap().is_none());
+        db.cache_sync_frame(DOC_NAME, 1, b"frame-2", TTL).unwrap();
+        db.flush_doc(DOC_NAME).unwrap();
+        assert!(db.get_cached_sync_frame(DOC_NAME, 1).unwrap().is_none());
+
+        // expired frames are not served, frames of past generations are removed
+        db.cache_sync_frame(DOC_NAME, 1, b"frame-3", Duration::ZERO)
+            .unwrap();
+        assert!(db.get_cached_sync_frame(DOC_NAME, 1).unwrap().is_none());
+        assert_eq!(frames(), 1);
+
+        // sync step 1 replies are served from cache until the document changes
+        let remote_sv = StateVector::default().encode_v1();
+        let hash = sv_hash(&remote_sv);
+        let reply = db.handle_sync_step1(DOC_NAME, &remote_sv).unwrap();
+        assert!(db.get_cached_sync_frame(DOC_NAME, hash).unwrap().is_some());
+        assert_eq!(db.handle_sync_step1(DOC_NAME, &remote_sv).unwrap(), reply);
+        push("c");
+        assert!(db.get_cached_sync_frame(DOC_NAME, hash).unwrap().is_none());
+        let reply = db.handle_sync_step1(DOC_NAME, &remote_sv).unwrap();
+        assert_eq!(
+            reply.state_vector,
+            doc.transact().state_vector().encode_v1()
+        );
+
+        // clearing a document removes its frames
+        db.clear_doc(DOC_NAME).unwrap();
+        assert_eq!(frames(), 0);
+    });
+}
+
+/// Verifies that storage used by the documents of a tenant is tracked across pushes, flushes and
+/// clears, and can be reconciled.
+pub fn tenant_usage<B: Backend>(backend: &B) {
+    use crate::keys::{key_pending, key_usage};
+    use crate::usage;
+    use std::convert::TryInto;
+
+    backend.transaction(|db| {
+        let db = ConfiguredStore::new(db, StoreConfig::DEFAULT)
+            .with_tenant_extractor(Arc::new(usage::first_segment));
+        let update = |content: &str| {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, content);
+            txn.encode_update_v1()
+        };
+        // size of a document: its stored state and pending updates
+        let doc_size = |oid: u32| {
+            let state = db
+                .get(&key_doc(oid))
+                .unwrap()
+                .map_or(0, |v| v.as_ref().len());
+            let pending = db.get(&key_pending(oid)).unwrap().map_or(0, |v| {
+                u64::from_be_bytes(v.as_ref()[4..12].try_into().unwrap())
+            });
+            state as u64 + pending
+        };
+
+        db.push_update("acme/a", &update("hello")).unwrap(); // OID 1
+        db.push_update("acme/a", &update(" world")).unwrap();
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        text.push(&mut doc.transact_mut(), "inserted");
+        db.insert_doc("acme/b", &doc.transact()).unwrap(); // OID 2
+        let imported = update("imported");
+        db.import_updates("other/x", &[(1, imported.as_slice())])
+            .unwrap(); // OID 3
+        db.push_update("no-tenant", &update("ignored")).unwrap(); // OID 4
+        db.push_update("acme2/c", &update("prefix")).unwrap(); // OID 5
+
+        assert_eq!(db.usage_of("acme").unwrap(), doc_size(1) + doc_size(2));
+        assert_eq!(db.usage_of("other").unwrap(), doc_size(3));
+        assert_eq!(db.usage_of("no-tenant").unwrap(), 0);
+
+        // flushes replace pending updates with merged state
+        db.flush_doc("acme/a").unwrap().unwrap();
+        db.flush_doc("other/x").unwrap().unwrap();
+        let acme = doc_size(1) + doc_size(2);
+        assert_eq!(db.usage_of("acme").unwrap(), acme);
+        assert_eq!(
+            db.usage_by_tenant().unwrap(),
+            vec![
+                (b"acme".to_vec(), acme),
+                (b"acme2".to_vec(), doc_size(5)),
+                (b"other".to_vec(), doc_size(3)),
+            ]
+        );
+
+        // cleared documents don't use any storage
+        db.clear_doc("other/x").unwrap();
+        assert_eq!(db.usage_of("other").unwrap(), 0);
+        let tenants: Vec<Vec<u8>> = db
+            .usage_by_tenant()
+            .unwrap()
+            .into_iter()
+            .map(|(tenant, _)| tenant)
+            .collect();
+        assert_eq!(tenants, vec![b"acme".to_vec(), b"acme2".to_vec()]);
+
+        // drifted counters are reconciled by scanning documents of a tenant
+        db.upsert(&key_usage(b"acme"), &1u64.to_be_bytes()).unwrap();
+        assert_eq!(db.usage_of("acme").unwrap(), 1);
+        assert_eq!(db.recompute_usage("acme").unwrap(), acme);
+        assert_eq!(db.usage_of("acme").unwrap(), acme);
+        assert_eq!(db.recompute_usage("other").unwrap(), 0);
+        (db.into_inner(), ())
+    });
+}
+
+/// Verifies that a document can be inserted, loaded back and removed.
+pub fn create_get_remove<B: Backend>(backend: &B) {
+    // insert document
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let mut txn = doc.transact_mut();
+        text.insert(&mut txn, 0, "hello");
+
+        backend.write(|db| {
+            db.insert_doc("doc", &txn).unwrap();
+        });
+    }
+
+    // retrieve document
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let mut txn = doc.transact_mut();
+        backend.read(|db| {
+            db.load_doc("doc", &mut txn).unwrap();
+
+            assert_eq!(text.get_string(&txn), "hello");
+
+            let (sv, completed) = db.get_state_vector("doc").unwrap();
+            assert_eq!(sv, Some(txn.state_vector()));
+            assert!(completed);
+        });
+    }
+
+    // remove document
+    backend.abort(|db| {
+        let report = db.clear_doc("doc").unwrap();
+        assert!(report.doc_state_removed);
+        assert_eq!(report.updates_removed, 0);
+        assert_eq!(report.meta_removed, 0);
+        assert!(report.bytes_removed > 0);
+        assert_eq!(db.clear_doc("doc").unwrap(), ClearReport::default());
+
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let mut txn = doc.transact_mut();
+        db.load_doc("doc", &mut txn).unwrap();
+
+        assert_eq!(text.get_string(&txn), "");
+
+        let (sv, completed) = db.get_state_vector("doc").unwrap();
+        assert!(sv.is_none());
+        assert!(completed);
+    });
+}
+
+/// Verifies that inserting a document again overwrites its previously inserted state.
+pub fn multi_insert<B: Backend>(backend: &B) {
+    // insert document twice
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let mut txn = doc.transact_mut();
+        text.push(&mut txn, "hello");
+
+        backend.write(|db| {
+            db.insert_doc("doc", &txn).unwrap();
+
+            text.push(&mut txn, " world");
+
+            db.insert_doc("doc", &txn).unwrap();
+        });
+    }
+
+    // retrieve document
+    backend.read(|db| {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let mut txn = doc.transact_mut();
+        db.load_doc("doc", &mut txn).unwrap();
+
+        assert_eq!(text.get_string(&txn), "hello world");
+    });
+}
+
+/// Verifies that pushed updates are applied when a document is loaded, both before and after a
+/// flush.
+pub fn incremental_updates<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    // store document updates
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+
+        let push = |chunk: &str| {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            let update = txn.encode_update_v1();
+            backend.write(|db| {
+                db.push_update(DOC_NAME, &update).unwrap();
+            });
+        };
+
+        // generate 3 updates
+        push("a");
+        push("b");
+        push("c");
+    }
+
+    // load document
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let mut txn = doc.transact_mut();
+
+        backend.read(|db| {
+            db.load_doc(DOC_NAME, &mut txn).unwrap();
+
+            assert_eq!(text.get_string(&txn), "abc");
+        });
+    }
+
+    // flush document
+    {
+        let doc = backend.write(|db| db.flush_doc(DOC_NAME).unwrap().unwrap().doc);
+
+        let text = doc.get_or_insert_text("text");
+
+        assert_eq!(text.get_string(&doc.transact()), "abc");
+    }
+}
+
+/// Verifies that the state vector of a document made of pending updates only is computed from them.
+pub fn state_vector_updates_only<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    // store document updates
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let push = |chunk: &str| {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            let update = txn.encode_update_v1();
+            backend.write(|db| {
+                db.push_update(DOC_NAME, &update).unwrap();
+            });
+        };
+
+        // generate 3 updates
+        push("a");
+        push("b");
+        push("c");
+
+        let sv = doc.transact().state_vector();
+        sv
+    };
+
+    backend.read(|db| {
+        let (sv, completed) = db.get_state_vector(DOC_NAME).unwrap();
+        assert!(sv.is_none());
+        assert!(!completed); // since it's not completed, we should recalculate state vector from doc state
+    });
+}
+
+/// Verifies that a diff of a document made of pending updates only contains all of them.
+pub fn state_diff_from_updates<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    let (sv, expected) = {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+
+        let push = |chunk: &str| {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            let update = txn.encode_update_v1();
+            backend.write(|db| {
+                db.push_update(DOC_NAME, &update).unwrap();
+            });
+        };
+
+        // generate 3 updates
+        push("a");
+        push("b");
+        let sv = doc.transact().state_vector();
+        push("c");
+        let update = doc.transact().encode_diff_v1(&sv);
+        (sv, update)
+    };
+
+    backend.read(|db| {
+        let actual = db.get_diff(DOC_NAME, &sv).unwrap();
+        assert_eq!(actual, Some(expected));
+    });
+}
+
+/// Verifies that a diff of a flushed document contains its whole state.
+pub fn state_diff_from_doc<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    let (sv, expected) = {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        // generate 3 updates
+        text.push(&mut doc.transact_mut(), "a");
+        text.push(&mut doc.transact_mut(), "b");
+        let sv = doc.transact().state_vector();
+        text.push(&mut doc.transact_mut(), "c");
+        let update = doc.transact().encode_diff_v1(&sv);
+
+        backend.write(|db| {
+            db.insert_doc(DOC_NAME, &doc.transact()).unwrap();
+        });
+
+        (sv, update)
+    };
+
+    backend.read(|db| {
+        let actual = db.get_diff(DOC_NAME, &sv).unwrap();
+        assert_eq!(actual, Some(expected));
+    });
+}
+
+/// Verifies that metadata entries can be inserted, overwritten and removed across transactions.
+pub fn doc_meta<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    backend.write(|db| {
+        let value = db.get_meta(DOC_NAME, "key").unwrap();
+        assert!(value.is_none());
+        db.insert_meta(DOC_NAME, "key", "value1".as_bytes())
+            .unwrap();
+    });
+
+    let prev = backend.write(|db| {
+        let prev = db
+            .get_meta(DOC_NAME, "key")
+            .unwrap()
+            .map(|v| v.as_ref().to_vec());
+        db.insert_meta(DOC_NAME, "key", "value2".as_bytes())
+            .unwrap();
+        prev
+    });
+    assert_eq!(prev.as_deref(), Some("value1".as_bytes()));
+
+    backend.abort(|db| {
+        let prev = db
+            .get_meta(DOC_NAME, "key")
+            .unwrap()
+            .map(|v| v.as_ref().to_vec());
+        db.remove_meta(DOC_NAME, "key").unwrap();
+        assert_eq!(prev.as_deref(), Some("value2".as_bytes()));
+        let value = db.get_meta(DOC_NAME, "key").unwrap();
+        assert!(value.is_none());
+    });
+}
+
+/// Verifies that documents are listed regardless of whether they have metadata, state or pending
+/// updates only, until they are cleared.
+pub fn doc_iter<B: Backend>(backend: &B) {
+    // insert metadata
+    backend.write(|db| {
+        db.insert_meta("A", "key1", [1].as_ref()).unwrap();
+    });
+
+    // insert full doc state
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let mut txn = doc.transact_mut();
+        text.push(&mut txn, "hello world");
+        backend.write(|db| {
+            db.insert_doc("B", &txn).unwrap();
+        });
+    }
+
+    // insert update
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let mut txn = doc.transact_mut();
+        text.push(&mut txn, "hello world");
+        backend.write(|db| {
+            db.push_update("C", &txn.encode_update_v1()).unwrap();
+        });
+    }
+
+    backend.read(|db| {
+        let mut i = db.iter_docs().unwrap();
+        assert_eq!(i.next(), Some("A".as_bytes().into()));
+        assert_eq!(i.next(), Some("B".as_bytes().into()));
+        assert_eq!(i.next(), Some("C".as_bytes().into()));
+        assert!(i.next().is_none());
+    });
+
+    // clear doc
+    backend.write(|db| {
+        db.clear_doc("B").unwrap();
+    });
+
+    backend.read(|db| {
+        let mut i = db.iter_docs().unwrap();
+        assert_eq!(i.next(), Some("A".as_bytes().into()));
+        assert_eq!(i.next(), Some("C".as_bytes().into()));
+        assert!(i.next().is_none());
+    });
+}
+
+/// Verifies that archived documents are hidden from listings and can be restored with their
+/// contents and metadata.
+pub fn doc_archive<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    // insert document state, pending update and metadata, then archive it
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        backend.write(|db| {
+            {
+                let mut txn = doc.transact_mut();
+                text.push(&mut txn, "hello");
+                db.insert_doc(DOC_NAME, &txn).unwrap();
+            }
+            let sv = doc.transact().state_vector();
+            text.push(&mut doc.transact_mut(), " world");
+            let update = doc.transact().encode_diff_v1(&sv);
+            db.push_update(DOC_NAME, &update).unwrap();
+            db.insert_meta(DOC_NAME, "key", "value".as_bytes()).unwrap();
+
+            assert!(db.archive_doc(DOC_NAME).unwrap());
+            assert!(!db.archive_doc(DOC_NAME).unwrap()); // already archived
+        });
+    }
+
+    // reopen the database
+    backend.read(|db| {
+        assert!(db.is_archived(DOC_NAME).unwrap());
+
+        let doc = Doc::new();
+        let res = db.load_doc(DOC_NAME, &mut doc.transact_mut());
+        assert!(matches!(res, Err(Error::DocArchived)));
+
+        assert!(db.iter_docs().unwrap().next().is_none());
+        let mut i = db.iter_docs_with(true).unwrap();
+        assert_eq!(i.next(), Some(DOC_NAME.as_bytes().into()));
+        assert!(i.next().is_none());
+    });
+
+    // restore archived document
+    backend.write(|db| {
+        assert!(db.unarchive_doc(DOC_NAME).unwrap());
+    });
+
+    backend.read(|db| {
+        assert!(!db.is_archived(DOC_NAME).unwrap());
+
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let mut txn = doc.transact_mut();
+        db.load_doc(DOC_NAME, &mut txn).unwrap();
+        assert_eq!(text.get_string(&txn), "hello world");
+
+        let (sv, completed) = db.get_state_vector(DOC_NAME).unwrap();
+        assert_eq!(sv, Some(txn.state_vector()));
+        assert!(completed);
+
+        let meta = db.get_meta(DOC_NAME, "key").unwrap();
+        assert_eq!(meta.unwrap().as_ref(), b"value");
+    });
+}
+
+/// Verifies that documents named by UUIDs are stored and listed after documents with string names.
+pub fn doc_uuid_names<B: Backend>(backend: &B) {
+    let a = Uuid::from_u128(0x1);
+    let b = Uuid::from_u128(0x2);
+    // UUID names are always ordered after string names in OID index
+    let names: [&dyn AsRef<[u8]>; 4] = [&DocId::from(b), &"doc", &DocId::from(a), &"abc"];
+    backend.write(|db| {
+        for (i, name) in names.iter().enumerate() {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            text.push(&mut doc.transact_mut(), &i.to_string());
+            db.insert_doc(name.as_ref(), &doc.transact()).unwrap();
+        }
+    });
+
+    backend.read(|db| {
+        for (i, name) in names.iter().enumerate() {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            db.load_doc(name.as_ref(), &mut doc.transact_mut()).unwrap();
+            assert_eq!(text.get_string(&doc.transact()), i.to_string());
+        }
+
+        let docs: Vec<_> = db.iter_docs().unwrap().decoded().collect();
+        assert_eq!(
+            docs,
+            vec![
+                DocName::Bytes("abc".as_bytes().into()),
+                DocName::Bytes("doc".as_bytes().into()),
+                DocName::Uuid(a),
+                DocName::Uuid(b),
+            ]
+        );
+    });
+}
+
+/// Verifies that a store configuration limits name length, encodes stored values and flushes
+/// documents after a number of updates.
+pub fn store_config<B: Backend>(backend: &B) {
+    let config = StoreConfig {
+        codec: ValueCodec {
+            compression: Compression::Zstd {
+                level: 3,
+                min_size: 0,
+            },
+            checksum: true,
+        },
+        max_name_len: Some(8),
+        create_policy: CreatePolicy::CreateIfMissing,
+        timestamps: true,
+        flush_policy: FlushPolicy::AfterUpdates(2),
+        intent_log: false,
+        op_deadline: None,
+        sync_frame_ttl: None,
+        access_sample_rate: None,
+        update_framing: UpdateFraming::Individual,
+        audit: None,
+        strict_history: false,
+        sv_read_repair: false,
+        blob_threshold: 1024 * 1024,
+        update_feed: false,
+        size_history: false,
+    };
+
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    text.push(&mut doc.transact_mut(), "hello");
+    let u1 = doc
+        .transact()
+        .encode_state_as_update_v1(&StateVector::default());
+    let sv1 = doc.transact().state_vector();
+    text.push(&mut doc.transact_mut(), " world");
+    let u2 = doc.transact().encode_diff_v1(&sv1);
+
+    backend.write_configured(&config, |db| {
+        // names longer than configured limit are refused
+        assert!(matches!(
+            db.push_update("long-doc-name", &u1),
+            Err(Error::InvalidDocName { len: 13, limit: 8 })
+        ));
+        db.push_update("config", &u1).unwrap();
+        // stored values are encoded using configured codec
+        let raw = KVStore::get(db, &key_update(1, 1)).unwrap().unwrap();
+        assert_ne!(raw.as_ref(), u1.as_slice());
+        // second update reaches flush threshold
+        db.push_update("config", &u2).unwrap();
+        let (sv, completed) = db.get_state_vector("config").unwrap();
+        assert_eq!(sv, Some(doc.transact().state_vector()));
+        assert!(completed);
+        assert!(db.last_modified("config").unwrap().is_some());
+
+        let loaded = Doc::new();
+        let loaded_text = loaded.get_or_insert_text("text");
+        db.load_doc("config", &mut loaded.transact_mut()).unwrap();
+        assert_eq!(loaded_text.get_string(&loaded.transact()), "hello world");
+    });
+
+    // default configuration leaves all of the above to the caller
+    backend.abort(|db| {
+        db.push_update("long-doc-name", &u1).unwrap();
+        let raw = KVStore::get(db, &key_update(2, 1)).unwrap().unwrap();
+        assert_eq!(raw.as_ref(), u1.as_slice());
+        db.push_update("long-doc-name", &u2).unwrap();
+        let (sv, completed) = db.get_state_vector("long-doc-name").unwrap();
+        assert_eq!(sv, None);
+        assert!(!completed);
+        assert_eq!(db.last_modified("long-doc-name").unwrap(), None);
+    });
+}
+
+/// Verifies that operations interrupted by a crash after writing their intent are completed by the
+/// next write.
+pub fn intent_log<B: Backend>(backend: &B) {
+    /// Store which fails all writes after a given number of them succeeded, simulating a crash
+    /// in the middle of multi-key operation.
+    struct FailAfter<'s, S> {
+        store: &'s S,
+        writes_left: Cell<u32>,
+        config: StoreConfig,
+    }
+
+    impl<'s, S> FailAfter<'s, S> {
+        fn new(store: &'s S, writes_left: u32) -> Self {
+            FailAfter {
+                store,
+                writes_left: Cell::new(writes_left),
+                config: StoreConfig {
+                    intent_log: true,
+                    ..StoreConfig::DEFAULT
+                },
+            }
+        }
+
+        fn write(&self) -> io::Result<()> {
+            match self.writes_left.get() {
+                0 => Err(io::Error::other("simulated crash")),
+                n => {
+                    self.writes_left.set(n - 1);
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    fn other<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+        io::Error::other(e)
+    }
+
+    impl<'a, 's, S: KVStore<'a>> KVStore<'a> for FailAfter<'s, S>
+    where
+        S::Error: Send + Sync + 'static,
+    {
+        type Error = io::Error;
+        type Cursor = S::Cursor;
+        type Entry = S::Entry;
+        type Return = S::Return;
+
+        fn get(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+            self.store.get(key).map_err(other)
+        }
+
+        fn upsert(&self, key: &[u8], value: &[u8]) -> Result<(), Self::Error> {
+            self.write()?;
+            self.store.upsert(key, value).map_err(other)
+        }
+
+        fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+            self.write()?;
+            self.store.remove(key).map_err(other)
+        }
+
+        fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<u32, Self::Error> {
+            self.write()?;
+            self.store.remove_range(from, to).map_err(other)
+        }
+
+        fn iter_range(&self, from: &[u8], to: &[u8]) -> Result<Self::Cursor, Self::Error> {
+            self.store.iter_range(from, to).map_err(other)
+        }
+
+        fn peek_back(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+            self.store.peek_back(key).map_err(other)
+        }
+
+        fn seek(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+            self.store.seek(key).map_err(other)
+        }
+    }
+
+    impl<'a, 's, S: KVStore<'a>> DocOps<'a> for FailAfter<'s, S>
+    where
+        S::Error: Send + Sync + 'static,
+    {
+        fn config(&self) -> &StoreConfig {
+            &self.config
+        }
+    }
+
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    backend.write(|db| {
+        for i in 0..3 {
+            text.push(&mut doc.transact_mut(), &i.to_string());
+            let update = doc
+                .transact()
+                .encode_state_as_update_v1(&StateVector::default());
+            db.push_update("doc", &update).unwrap();
+        }
+    });
+
+    // crash after intent, document state and state vector have been written
+    backend.write(|db| {
+        assert!(FailAfter::new(db, 4).flush_doc("doc").is_err());
+    });
+    backend.write(|db| {
+        assert_eq!(db.recover_intents().unwrap(), 1);
+        assert_eq!(db.recover_intents().unwrap(), 0);
+        let loaded = Doc::new();
+        let loaded_text = loaded.get_or_insert_text("text");
+        db.load_doc("doc", &mut loaded.transact_mut()).unwrap();
+        assert_eq!(loaded_text.get_string(&loaded.transact()), "012");
+        let (_, completed) = db.get_state_vector("doc").unwrap();
+        assert!(completed);
+        assert!(KVStore::get(db, &key_update(1, 1)).unwrap().is_none());
+    });
+
+    // crash after intent and OID index entry removal have been written
+    backend.write(|db| {
+        assert!(FailAfter::new(db, 2).clear_doc("doc").is_err());
+        assert!(KVStore::get(db, &key_doc(1)).unwrap().is_some());
+    });
+    backend.abort(|db| {
+        assert_eq!(db.recover_intents().unwrap(), 1);
+        assert!(KVStore::get(db, &key_doc(1)).unwrap().is_none());
+        assert!(KVStore::get(db, &key_state_vector(1)).unwrap().is_none());
+        assert!(!db.load_doc("doc", &mut Doc::new().transact_mut()).unwrap());
+    });
+}
+
+/// Verifies that stored entries of a document match their golden encoding, byte for byte.
+pub fn format_golden<B: Backend>(backend: &B) {
+    use crate::compat;
+
+    // lib0 v1 encoded updates of client 1 inserting "a" and "b" into root text "text"
+    const STATE: &[u8] = &[1, 1, 1, 0, 4, 1, 4, b't', b'e', b'x', b't', 1, b'a', 0];
+    const STATE_VECTOR: &[u8] = &[1, 1, 1];
+    const UPDATE: &[u8] = &[1, 1, 1, 1, 0x84, 1, 0, 1, b'b', 0];
+    const GOLDEN: &str = include_str!("../../format-v1.golden");
+
+    let collection = Collection::new(["col"]);
+    backend.write(|db| {
+        db.insert_doc_raw_v1(b"doc", STATE, STATE_VECTOR).unwrap();
+        assert_eq!(db.push_update("doc", UPDATE).unwrap().seq, 1);
+        db.insert_meta("doc", "author", b"me").unwrap();
+        db.set_max_doc_bytes("doc", Some(4096)).unwrap();
+        db.set_default_max_doc_bytes(Some(1 << 20)).unwrap();
+        assert!(db.create_collection(&collection).unwrap());
+    });
+    {
+        let config = StoreConfig {
+            codec: ValueCodec {
+                compression: Compression::None,
+                checksum: true,
+            },
+            ..StoreConfig::DEFAULT
+        };
+        backend.write_configured(&config, |db| {
+            db.push_update(&collection.doc_name("x"), UPDATE).unwrap();
+        });
+    }
+
+    backend.abort(|db| {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        db.load_doc("doc", &mut doc.transact_mut()).unwrap();
+        assert_eq!(text.get_string(&doc.transact()), "ab");
+
+        let expected: Vec<_> = GOLDEN
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let mut parts = line.split(' ');
+                let key = parts.next().unwrap().to_string();
+                let value = parts.next().unwrap_or("").to_string();
+                (key, value)
+            })
+            .collect();
+        let actual: Vec<_> = db
+            .iter_range(&[V1], &[V1 + 1])
+            .unwrap()
+            .map(|e| {
+                let key = e.key();
+                let parsed = parse_key(key).unwrap();
+                assert_eq!(build_key(&parsed), key);
+                (compat::hex(key), compat::hex(e.value()))
+            })
+            .collect();
+        assert_eq!(actual, expected);
+    });
+}
+
+/// Verifies that maintenance flushes documents with many pending updates and removes entries of
+/// documents which are no longer referenced, within its time budget.
+pub fn maintenance<B: Backend>(backend: &B) {
+    let updates: Vec<Vec<u8>> = {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        (0..4)
+            .map(|i| {
+                let mut txn = doc.transact_mut();
+                text.push(&mut txn, &i.to_string());
+                txn.encode_update_v1()
+            })
+            .collect()
+    };
+
+    // doc i has (i % 4) + 1 pending updates, every 100th doc has corrupted state
+    backend.write(|db| {
+        for i in 0..1000 {
+            let name = format!("doc-{:04}", i);
+            for update in &updates[..=(i % 4)] {
+                db.push_update(&name, update).unwrap();
+            }
+            if i % 100 == 0 {
+                db.insert_doc_raw_v1(name.as_bytes(), &[1, 2, 3], &[0])
+                    .unwrap();
+            }
+        }
+        // entries of documents no longer referenced by any name
+        db.upsert(&key_doc(100_000), &[0, 0]).unwrap();
+        db.upsert(&key_update(100_001, 1), &[0, 0]).unwrap();
+    });
+
+    // time budget is checked only after the first document
+    backend.write(|db| {
+        let report = db
+            .maintain(MaintenanceOptions {
+                verify_percent: 100,
+                max_duration: Some(Duration::ZERO),
+                ..MaintenanceOptions::default()
+            })
+            .unwrap();
+        assert_eq!(report.docs_visited, 1);
+        assert_eq!(report.docs_verified, 1);
+        assert!(report.cursor.is_some());
+    });
+
+    let mut options = MaintenanceOptions {
+        compact_threshold: Some(3),
+        verify_percent: 100,
+        vacuum: true,
+        max_docs: 100,
+        ..MaintenanceOptions::default()
+    };
+    let (mut calls, mut compacted, mut folded, mut verified, mut orphans) = (0, 0, 0, 0, 0);
+    let mut corrupted = Vec::new();
+    loop {
+        let report = backend.write(|db| db.maintain(options.clone()).unwrap());
+        assert!(report.docs_visited <= 100);
+        calls += 1;
+        compacted += report.docs_compacted;
+        folded += report.updates_folded;
+        verified += report.docs_verified;
+        orphans += report.orphans_removed;
+        corrupted.extend(report.corrupted.into_iter().map(|(name, _)| name));
+        match report.cursor {
+            Some(cursor) => options.cursor = Some(cursor),
+            None => break,
+        }
+    }
+    // 1000 documents followed by 1002 document OIDs
+    assert_eq!(calls, 21);
+    assert_eq!(compacted, 500);
+    assert_eq!(folded, 250 * 3 + 250 * 4);
+    assert_eq!(verified, 1000);
+    assert_eq!(orphans, 2);
+    let expected: Vec<Box<[u8]>> = (0..1000)
+        .step_by(100)
+        .map(|i| format!("doc-{:04}", i).as_bytes().into())
+        .collect();
+    assert_eq!(corrupted, expected);
+
+    backend.write(|db| {
+        assert!(KVStore::get(db, &key_doc(100_000)).unwrap().is_none());
+        assert!(KVStore::get(db, &key_update(100_001, 1)).unwrap().is_none());
+        assert!(db.flush_doc("doc-0003").unwrap().is_none());
+        assert_eq!(db.flush_doc("doc-0001").unwrap().unwrap().updates_folded, 2);
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let mut txn = doc.transact_mut();
+        db.load_doc("doc-0003", &mut txn).unwrap();
+        assert_eq!(text.get_string(&txn), "0123");
+    });
+}
+
+/// Verifies that state vectors left behind by a previous state of a document are detected.
+pub fn sv_consistency<B: Backend>(backend: &B) {
+    /// Store which verifies state vector consistency of "doc" after every write, as seen by
+    /// a reader not isolated from writes in progress.
+    struct CheckAfterWrite<'s, S> {
+        store: &'s S,
+        checks: Cell<u32>,
+        inconsistent: Cell<u32>,
+    }
+
+    impl<'a, 's, S: DocOps<'a>> CheckAfterWrite<'s, S>
+    where
+        Error: From<S::Error>,
+    {
+        fn check(&self) {
+            self.checks.set(self.checks.get() + 1);
+            if !self.store.verify_sv_consistency("doc").unwrap() {
+                self.inconsistent.set(self.inconsistent.get() + 1);
+            }
+        }
+    }
+
+    impl<'a, 's, S: DocOps<'a>> KVStore<'a> for CheckAfterWrite<'s, S>
+    where
+        Error: From<S::Error>,
+    {
+        type Error = S::Error;
+        type Cursor = S::Cursor;
+        type Entry = S::Entry;
+        type Return = S::Return;
+
+        fn get(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+            self.store.get(key)
+        }
+
+        fn upsert(&self, key: &[u8], value: &[u8]) -> Result<(), Self::Error> {
+            self.store.upsert(key, value)?;
+            self.check();
+            Ok(())
+        }
+
+        fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+            self.store.remove(key)?;
+            self.check();
+            Ok(())
+        }
+
+        fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<u32, Self::Error> {
+            let removed = self.store.remove_range(from, to)?;
+            self.check();
+            Ok(removed)
+        }
+
+        fn iter_range(&self, from: &[u8], to: &[u8]) -> Result<Self::Cursor, Self::Error> {
+            self.store.iter_range(from, to)
+        }
+
+        fn peek_back(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+            self.store.peek_back(key)
+        }
+
+        fn seek(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+            self.store.seek(key)
+        }
+    }
+
+    impl<'a, 's, S: DocOps<'a>> DocOps<'a> for CheckAfterWrite<'s, S> where Error: From<S::Error> {}
+
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    text.push(&mut doc.transact_mut(), "hello");
+    let old_sv = doc.transact().state_vector().encode_v1();
+    backend.write(|db| {
+        db.insert_doc("doc", &doc.transact()).unwrap();
+    });
+
+    backend.abort(|db| {
+        let checked = CheckAfterWrite {
+            store: db,
+            checks: Cell::new(0),
+            inconsistent: Cell::new(0),
+        };
+        text.push(&mut doc.transact_mut(), " world");
+        checked.insert_doc("doc", &doc.transact()).unwrap();
+        for chunk in ["!", "?"] {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            db.push_update("doc", &txn.encode_update_v1()).unwrap();
+        }
+        checked.flush_doc("doc").unwrap();
+        assert!(checked.checks.get() > 0);
+        assert_eq!(checked.inconsistent.get(), 0);
+        assert!(db.verify_sv_consistency("doc").unwrap());
+
+        // state vector of the previous document state is detected
+        db.upsert(&key_state_vector(1), &old_sv).unwrap();
+        assert!(!db.verify_sv_consistency("doc").unwrap());
+        assert!(db.verify_sv_consistency("missing").unwrap());
+    });
+}
+
+/// Verifies that operations interrupted by a deadline can be continued by the next call, reporting
+/// their progress.
+pub fn op_deadline<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+    // every deadline-aware loop stops after processing a single entry
+    let config = StoreConfig {
+        op_deadline: Some(Duration::ZERO),
+        ..StoreConfig::DEFAULT
+    };
+
+    let doc = Doc::with_client_id(1);
+    let text = doc.get_or_insert_text("text");
+    backend.write(|db| {
+        for chunk in ["a", "b", "c", "d", "e"] {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            db.push_update(DOC_NAME, &txn.encode_update_v1()).unwrap();
+        }
+    });
+
+    backend.write_configured(&config, |db| {
+        let loaded = Doc::new();
+        let res = db.load_doc(DOC_NAME, &mut loaded.transact_mut());
+        assert!(matches!(
+            res,
+            Err(Error::DeadlineExceeded {
+                progress: Progress::Load { seq: 1 }
+            })
+        ));
+    });
+
+    // interrupted flushes keep merged updates, so that the next call continues from them
+    let mut progress = Vec::new();
+    loop {
+        let res = backend.write_configured(&config, |db| db.flush_doc(DOC_NAME));
+        match res {
+            Err(Error::DeadlineExceeded {
+                progress: Progress::Flush { seq, remaining },
+            }) => progress.push((seq, remaining)),
+            other => {
+                assert!(other.unwrap().is_some());
+                break;
+            }
+        }
+    }
+    assert_eq!(progress, vec![(1, 4), (2, 3), (3, 2), (4, 1)]);
+
+    backend.write(|db| {
+        let loaded = Doc::new();
+        let loaded_text = loaded.get_or_insert_text("text");
+        db.load_doc(DOC_NAME, &mut loaded.transact_mut()).unwrap();
+        assert_eq!(loaded_text.get_string(&loaded.transact()), "abcde");
+        assert_eq!(db.decoded_updates(DOC_NAME).unwrap().count(), 0);
+        db.insert_meta(DOC_NAME, "owner", b"me").unwrap();
+    });
+
+    // interrupted clear is recorded and completed by the next call
+    let mut interrupted = 0;
+    let report = loop {
+        let res = backend.write_configured(&config, |db| db.clear_doc(DOC_NAME));
+        match res {
+            Err(Error::DeadlineExceeded {
+                progress: Progress::Clear { .. },
+            }) => interrupted += 1,
+            other => break other.unwrap(),
+        }
+    };
+    assert!(interrupted > 0);
+    // only the entries removed by the completing call are reported
+    assert!(report.bytes_removed > 0);
+
+    backend.write(|db| {
+        assert!(KVStore::get(db, &key_doc(1)).unwrap().is_none());
+        assert!(KVStore::get(db, &key_state_vector(1)).unwrap().is_none());
+        assert!(db.get_meta(DOC_NAME, "owner").unwrap().is_none());
+        assert_eq!(db.recover_intents().unwrap(), 0);
+    });
+}
+
+/// Verifies that operations interrupted by a deadline keep the pending updates counter consistent
+/// when stored values are encoded.
+pub fn op_deadline_codec<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+    // stored values are larger than updates, since they carry flags and checksums
+    let codec = ValueCodec {
+        compression: Compression::None,
+        checksum: true,
+    };
+    let config = StoreConfig {
+        codec,
+        ..StoreConfig::DEFAULT
+    };
+    let interrupting = StoreConfig {
+        op_deadline: Some(Duration::ZERO),
+        ..config.clone()
+    };
+
+    let doc = Doc::with_client_id(1);
+    let text = doc.get_or_insert_text("text");
+    let mut stored_sizes = Vec::new();
+    backend.write_configured(&config, |db| {
+        for chunk in ["a", "b", "c"] {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            let update = txn.encode_update_v1();
+            stored_sizes.push(codec.encode(&update).unwrap().len() as u64);
+            db.push_update(DOC_NAME, &update).unwrap();
+        }
+    });
+
+    backend.write_configured(&interrupting, |db| {
+        let res = db.flush_doc(DOC_NAME);
+        assert!(matches!(
+            res,
+            Err(Error::DeadlineExceeded {
+                progress: Progress::Flush {
+                    seq: 1,
+                    remaining: 2
+                }
+            })
+        ));
+    });
+
+    // pending updates counter still matches stored sizes of updates left after interruption
+    backend.write_configured(&config, |db| {
+        let update = {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, "d");
+            txn.encode_update_v1()
+        };
+        let receipt = db.push_update(DOC_NAME, &update).unwrap();
+        assert_eq!(receipt.pending_updates, 3);
+        assert_eq!(
+            receipt.pending_bytes,
+            stored_sizes[1] + stored_sizes[2] + codec.encode(&update).unwrap().len() as u64
+        );
+
+        let outcome = db.flush_doc(DOC_NAME).unwrap().unwrap();
+        assert_eq!(outcome.updates_folded, 3);
+        let text = outcome.doc.get_or_insert_text("text");
+        assert_eq!(text.get_string(&outcome.doc.transact()), "abcd");
+    });
+}
+
+/// Verifies that removing a key range reports the number of removed entries.
+pub fn remove_range_count<B: Backend>(backend: &B) {
+    backend.write(|db| {
+        for key in [[1u8], [2], [3], [5]] {
+            db.upsert(&key, b"value").unwrap();
+        }
+        assert_eq!(db.remove_range(&[2], &[4]).unwrap(), 2);
+        assert_eq!(db.remove_range(&[2], &[4]).unwrap(), 0);
+        assert!(KVStore::get(db, &[1]).unwrap().is_some());
+        assert!(KVStore::get(db, &[5]).unwrap().is_some());
+    });
+}
+
+/// Verifies that writes made by a flush hook are persisted or rolled back together with the flush.
+pub fn flush_doc_with_hook<B: Backend>(backend: &B) {
+    fn index_len<'a, DB: DocOps<'a>>(doc: &Doc, db: &DB) -> Result<(), Error>
+    where
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        let text = doc.get_or_insert_text("text");
+        let len = text.get_string(&doc.transact()).len() as u32;
+        db.insert_meta("doc", "len", &len.to_be_bytes())
+    }
+
+    fn push_text<'a, DB: DocOps<'a>>(db: &DB, doc: &Doc, chunk: &str)
+    where
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        let text = doc.get_or_insert_text("text");
+        let mut txn = doc.transact_mut();
+        let len = text.len(&txn);
+        text.insert(&mut txn, len, chunk);
+        let update = txn.encode_update_v1();
+        db.push_update("doc", &update).unwrap();
+    }
+
+    let doc = Doc::new();
+    backend.write(|db| {
+        push_text(db, &doc, "hello");
+        let outcome = db.flush_doc_with_hook("doc", index_len).unwrap();
+        assert_eq!(outcome.unwrap().updates_folded, 1);
+    });
+    backend.write(|db| {
+        let len = db.get_meta("doc", "len").unwrap().unwrap();
+        assert_eq!(len.as_ref(), &5u32.to_be_bytes());
+        // nothing to flush, hook is not called
+        let outcome = db
+            .flush_doc_with_hook("doc", |_, _| panic!("unexpected hook call"))
+            .unwrap();
+        assert!(outcome.is_none());
+        push_text(db, &doc, " world");
+    });
+    backend.abort(|db| {
+        let result = db.flush_doc_with_hook("doc", |doc, db| {
+            index_len(doc, db)?;
+            Err(Error::Other("injected failure".into()))
+        });
+        assert!(result.is_err());
+        // transaction is dropped without commit
+    });
+    backend.write(|db| {
+        // neither flushed state nor hook writes were persisted
+        let len = db.get_meta("doc", "len").unwrap().unwrap();
+        assert_eq!(len.as_ref(), &5u32.to_be_bytes());
+        let outcome = db.flush_doc_with_hook("doc", index_len).unwrap();
+        assert_eq!(outcome.unwrap().updates_folded, 1);
+        let len = db.get_meta("doc", "len").unwrap().unwrap();
+        assert_eq!(len.as_ref(), &11u32.to_be_bytes());
+    });
+}
+
+/// Verifies that a compaction worker flushes a repeatedly enqueued document once per debounce
+/// window, and flushes remaining documents on shutdown.
+pub fn compaction_worker<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    let push = |chunk: &str| {
+        let sv = doc.transact().state_vector();
+        text.push(&mut doc.transact_mut(), chunk);
+        let update = doc.transact().encode_diff_v1(&sv);
+        backend.write(|db| db.push_update(DOC_NAME, &update).unwrap().seq)
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    let config = WorkerConfig {
+        debounce: Duration::from_millis(200),
+        threshold: 3,
+        ..WorkerConfig::default()
+    };
+    let worker = {
+        let backend = backend.clone();
+        CompactionWorker::spawn(
+            config,
+            move |name| backend.write(|db| db.flush_doc(name)),
+            move |name, result| {
+                let folded = result.unwrap().map(|outcome| outcome.updates_folded);
+                tx.lock().unwrap().send((name.to_vec(), folded)).unwrap();
+            },
+        )
+    };
+    let handle = worker.handle();
+
+    // enqueue the same document repeatedly within debounce window
+    for chunk in ["a", "b", "c"] {
+        let seq_nr = push(chunk);
+        handle.notify_pending(DOC_NAME, seq_nr); // below threshold for first 2 updates
+        worker.enqueue(DOC_NAME);
+    }
+    let (name, folded) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(name, DOC_NAME.as_bytes());
+    assert_eq!(folded, Some(3));
+    // document was flushed only once
+    assert!(rx.recv_timeout(Duration::from_millis(400)).is_err());
+
+    // shutdown doesn't wait for debounce window to flush remaining documents
+    push("d");
+    worker.enqueue(DOC_NAME);
+    worker.shutdown();
+    let (_, folded) = rx.try_recv().unwrap();
+    assert_eq!(folded, Some(1));
+    assert!(rx.try_recv().is_err());
+
+    backend.read(|db| {
+        let (_, completed) = db.get_state_vector(DOC_NAME).unwrap();
+        assert!(completed);
+    });
+}
+
+/// Verifies that sync step 1 messages are answered with the changes missing on the remote side and
+/// a local state vector.
+pub fn sync_step1<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    let doc = Doc::with_client_id(1);
+    {
+        let text = doc.get_or_insert_text("text");
+        text.push(&mut doc.transact_mut(), "hello");
+        backend.write(|db| {
+            db.insert_doc(DOC_NAME, &doc.transact()).unwrap();
+        });
+    }
+
+    backend.read(|db| {
+        // remote peer has its own, concurrent changes
+        let remote = Doc::with_client_id(2);
+        let remote_text = remote.get_or_insert_text("text");
+        remote_text.push(&mut remote.transact_mut(), " world");
+        let remote_sv = remote.transact().state_vector().encode_v1();
+
+        let reply = db.handle_sync_step1(DOC_NAME, &remote_sv).unwrap();
+        remote
+            .transact_mut()
+            .apply_update(Update::decode_v1(&reply.update).unwrap());
+        // local state vector allows remote peer to reply with changes missing locally
+        let local_sv = StateVector::decode_v1(&reply.state_vector).unwrap();
+        assert_eq!(local_sv, doc.transact().state_vector());
+        let missing = remote.transact().encode_diff_v1(&local_sv);
+        doc.transact_mut()
+            .apply_update(Update::decode_v1(&missing).unwrap());
+        let text = doc.get_or_insert_text("text");
+        assert_eq!(
+            text.get_string(&doc.transact()),
+            remote_text.get_string(&remote.transact())
+        );
+
+        // unknown documents are answered with an empty update and state vector
+        let reply = db.handle_sync_step1("unknown", &remote_sv).unwrap();
+        let empty = Doc::new();
+        assert_eq!(
+            reply.update,
+            empty.transact().encode_diff_v1(&StateVector::default())
+        );
+        assert_eq!(reply.state_vector, StateVector::default().encode_v1());
+
+        // malformed state vector
+        let err = db.handle_sync_step1(DOC_NAME, &[0xff]).unwrap_err();
+        assert!(matches!(err, Error::InvalidStateVector(_)));
+    });
+}
+
+/// Verifies that stored state vectors are used only while they cover the latest update of a
+/// document.
+pub fn state_vector_fast_path<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    let push = |chunk: &str, put_sv: bool| {
+        let sv = doc.transact().state_vector();
+        text.push(&mut doc.transact_mut(), chunk);
+        let update = doc.transact().encode_diff_v1(&sv);
+        backend.write(|db| {
+            db.push_update(DOC_NAME, &update).unwrap();
+            if put_sv {
+                db.put_state_vector(DOC_NAME, &doc.transact().state_vector())
+                    .unwrap();
+            }
+        });
+    };
+    let get_sv = || backend.read(|db| db.get_state_vector(DOC_NAME).unwrap());
+
+    // updates without stored state vector
+    push("a", false);
+    assert_eq!(get_sv(), (None, false));
+
+    // state vector stored alongside the update
+    push("b", true);
+    assert_eq!(get_sv(), (Some(doc.transact().state_vector()), true));
+
+    // stale state vector doesn't cover the latest update
+    let stale = doc.transact().state_vector();
+    push("c", false);
+    assert_eq!(get_sv(), (Some(stale), false));
+
+    // catching up makes it trustworthy again
+    push("d", true);
+    assert_eq!(get_sv(), (Some(doc.transact().state_vector()), true));
+
+    // state vector can be stored for a document that doesn't exist yet
+    backend.write(|db| {
+        let sv = doc.transact().state_vector();
+        db.put_state_vector("new-doc", &sv).unwrap();
+        assert_eq!(db.get_state_vector("new-doc").unwrap(), (Some(sv), true));
+    });
+}
+
+/// Verifies that changes collected by a document transaction are rolled back when commit fails, and
+/// can be retried.
+pub fn doc_transaction_retry<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    let doc_txn = DocTransaction::begin(&doc);
+    text.push(&mut doc.transact_mut(), "hello");
+    text.push(&mut doc.transact_mut(), " world");
+
+    // all writes are rolled back when commit fails
+    let uncommitted = doc_txn
+        .commit(|update| {
+            backend.abort(|db| {
+                db.push_update(DOC_NAME, update)?;
+                db.insert_meta(DOC_NAME, "key", "value".as_bytes())?;
+                Err::<(), _>(Error::other("simulated commit failure"))
+            })
+        })
+        .unwrap_err();
+    assert!(matches!(uncommitted.error, Error::Other(_)));
+    backend.read(|db| {
+        assert!(db.iter_docs().unwrap().next().is_none());
+    });
+
+    // collected changes can be retried against a fresh transaction
+    let seq_nr = uncommitted
+        .retry(|update| {
+            backend.write(|db| {
+                let seq_nr = db.push_update(DOC_NAME, update)?.seq;
+                db.insert_meta(DOC_NAME, "key", "value".as_bytes())?;
+                Ok(seq_nr)
+            })
+        })
+        .unwrap();
+    assert_eq!(seq_nr, 1);
+
+    backend.read(|db| {
+        let loaded = Doc::new();
+        let loaded_text = loaded.get_or_insert_text("text");
+        db.load_doc(DOC_NAME, &mut loaded.transact_mut()).unwrap();
+        assert_eq!(loaded_text.get_string(&loaded.transact()), "hello world");
+        let meta = db.get_meta(DOC_NAME, "key").unwrap();
+        assert_eq!(meta.unwrap().as_ref(), b"value");
+    });
+}
+
+/// Verifies that updates imported with their sequence numbers keep them, unless they would collide
+/// with stored ones.
+pub fn import_updates<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+
+    backend.abort(|db| {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let mut updates = Vec::new();
+        for i in 0..10_000u32 {
+            let sv = doc.transact().state_vector();
+            text.push(&mut doc.transact_mut(), "a");
+            // source numbering has gaps, which are preserved
+            updates.push((i * 2 + 1, doc.transact().encode_diff_v1(&sv)));
+        }
+        let updates: Vec<(u32, &[u8])> = updates
+            .iter()
+            .map(|(clock, update)| (*clock, update.as_slice()))
+            .collect();
+
+        // non-monotonic clocks are refused before anything is written
+        let invalid = [updates[1], updates[0]];
+        assert!(matches!(
+            db.import_updates(DOC_NAME, &invalid),
+            Err(Error::ClockConflict {
+                clock: 1,
+                last_clock: 3
+            })
+        ));
+        assert!(db.iter_docs().unwrap().next().is_none());
+
+        db.import_updates(DOC_NAME, &updates).unwrap();
+        let loaded = Doc::new();
+        let loaded_text = loaded.get_or_insert_text("text");
+        db.load_doc(DOC_NAME, &mut loaded.transact_mut()).unwrap();
+        assert_eq!(
+            loaded_text.get_string(&loaded.transact()),
+            text.get_string(&doc.transact())
+        );
+
+        // imported clocks cannot collide with stored ones
+        assert!(matches!(
+            db.import_updates(DOC_NAME, &updates[..1]),
+            Err(Error::ClockConflict {
+                clock: 1,
+                last_clock: 19_999
+            })
+        ));
+        assert_eq!(db.push_update(DOC_NAME, updates[0].1).unwrap().seq, 20_000);
+
+        // import can be followed by a flush right away
+        let outcome = db
+            .import_updates_with("other", &updates, Some(yrs::Options::default()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(outcome.updates_folded, 10_000);
+        let (_, completed) = db.get_state_vector("other").unwrap();
+        assert!(completed);
+    });
+}
+
+/// Verifies that the create policy of a store decides which writes may create missing documents.
+pub fn create_policy<B: Backend>(backend: &B) {
+    let config = |create_policy| StoreConfig {
+        create_policy,
+        ..StoreConfig::DEFAULT
+    };
+
+    // default policy creates missing documents
+    backend.write_configured(&config(CreatePolicy::CreateIfMissing), |db| {
+        assert!(db.push_update("content", &[0, 0]).unwrap().doc_created);
+        db.insert_meta("meta-only", "owner", b"me").unwrap();
+    });
+
+    backend.write_configured(&config(CreatePolicy::RequireExisting), |db| {
+        assert!(matches!(
+            db.push_update("typo", &[0, 0]),
+            Err(Error::DocNotFound)
+        ));
+        assert!(matches!(
+            db.insert_meta("typo", "owner", b"me"),
+            Err(Error::DocNotFound)
+        ));
+        assert!(matches!(
+            db.push_update("meta-only", &[0, 0]),
+            Err(Error::DocNotFound)
+        ));
+        assert_eq!(db.push_update("content", &[0, 0]).unwrap().seq, 2);
+        db.insert_meta("content", "owner", b"me").unwrap();
+    });
+
+    backend.write_configured(&config(CreatePolicy::RequireExistingOrMeta), |db| {
+        assert!(matches!(
+            db.push_update("typo", &[0, 0]),
+            Err(Error::DocNotFound)
+        ));
+        let receipt = db.push_update("meta-only", &[0, 0]).unwrap();
+        assert!(!receipt.doc_created);
+        db.insert_meta("meta-only", "editor", b"you").unwrap();
+        assert_eq!(db.push_update("content", &[0, 0]).unwrap().seq, 3);
+    });
+
+    // refused writes didn't allocate any document
+    backend.abort(|db| {
+        assert!(KVStore::get(db, &key_oid(b"typo")).unwrap().is_none());
+        assert!(KVStore::get(db, &key_oid(b"meta-only")).unwrap().is_some());
+    });
+}
+
+/// Verifies that updates refused by a validator are not written and don't create documents.
+pub fn update_validator<B: Backend>(backend: &B) {
+    const MAX_UPDATE_LEN: usize = 64;
+    let validator: Arc<dyn UpdateValidator> = Arc::new(|_: &[u8], update: &Update| {
+        let encoded = update.encode_v1();
+        if encoded.len() > MAX_UPDATE_LEN {
+            return Err(RejectReason::new("update too large"));
+        }
+        let doc = Doc::new();
+        let mut txn = doc.transact_mut();
+        txn.apply_update(Update::decode_v1(&encoded).unwrap());
+        if txn.root_refs().any(|(name, _)| name == "readonly") {
+            return Err(RejectReason::new("readonly root cannot be modified"));
+        }
+        Ok(())
+    });
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    let small = {
+        let mut txn = doc.transact_mut();
+        text.push(&mut txn, "hello");
+        txn.encode_update_v1()
+    };
+    let large = {
+        let mut txn = doc.transact_mut();
+        text.push(&mut txn, &" world".repeat(20));
+        txn.encode_update_v1()
+    };
+    // made by another client, so that the validator can apply it on its own
+    let protected = {
+        let doc = Doc::new();
+        let readonly = doc.get_or_insert_map("readonly");
+        let mut txn = doc.transact_mut();
+        readonly.insert(&mut txn, "key", "value");
+        txn.encode_update_v1()
+    };
+
+    backend.transaction(|db| {
+        let db = ConfiguredStore::new(db, StoreConfig::DEFAULT).with_validator(validator.clone());
+        assert_eq!(db.push_update("doc", &small).unwrap().seq, 1);
+        match db.push_update("doc", &large) {
+            Err(Error::UpdateRejected(reason)) => {
+                assert_eq!(reason.message(), "update too large")
+            }
+            other => panic!("expected rejected update, got {:?}", other.map(|r| r.seq)),
+        }
+        assert!(matches!(
+            db.push_update("doc", &protected),
+            Err(Error::UpdateRejected(_))
+        ));
+        // rejected updates don't create documents
+        assert!(matches!(
+            db.push_update("other", &large),
+            Err(Error::UpdateRejected(_))
+        ));
+        assert!(!db
+            .load_doc("other", &mut Doc::new().transact_mut())
+            .unwrap());
+
+        let loaded = Doc::new();
+        let loaded_text = loaded.get_or_insert_text("text");
+        db.load_doc("doc", &mut loaded.transact_mut()).unwrap();
+        assert_eq!(loaded_text.get_string(&loaded.transact()), "hello");
+        (db.into_inner(), ())
+    });
+
+    // stores without validator accept all updates
+    backend.abort(|db| {
+        assert_eq!(db.push_update("doc", &large).unwrap().seq, 2);
+    });
+}
+
+/// Verifies that writes made within a transaction are visible to its own reads.
+pub fn read_your_writes<B: Backend>(backend: &B) {
+    /// Checks that every write performed through `db` is visible to its subsequent reads,
+    /// before it's committed.
+    fn check<'a, S: DocOps<'a>>(db: &S)
+    where
+        Error: From<S::Error>,
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let update = {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, "hello");
+            txn.encode_update_v1()
+        };
+        assert_eq!(db.push_update("doc", &update).unwrap().seq, 1);
+
+        // pushed update is visible to loads, diffs and iterators
+        let loaded = Doc::new();
+        let loaded_text = loaded.get_or_insert_text("text");
+        assert!(db.load_doc("doc", &mut loaded.transact_mut()).unwrap());
+        assert_eq!(loaded_text.get_string(&loaded.transact()), "hello");
+        let diff = db
+            .get_diff("doc", &StateVector::default())
+            .unwrap()
+            .unwrap();
+        let remote = Doc::new();
+        let remote_text = remote.get_or_insert_text("text");
+        remote
+            .transact_mut()
+            .apply_update(Update::decode_v1(&diff).unwrap());
+        assert_eq!(remote_text.get_string(&remote.transact()), "hello");
+        assert_eq!(db.decoded_updates("doc").unwrap().count(), 1);
+        let names: Vec<_> = db.iter_docs().unwrap().collect();
+        assert_eq!(names, vec![Box::<[u8]>::from(&b"doc"[..])]);
+
+        // next update gets the next sequence number
+        let update = {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, " world");
+            txn.encode_update_v1()
+        };
+        assert_eq!(db.push_update("doc", &update).unwrap().seq, 2);
+
+        // flushed state replaces pending updates
+        db.flush_doc("doc").unwrap().unwrap();
+        assert_eq!(db.decoded_updates("doc").unwrap().count(), 0);
+        let (sv, up_to_date) = db.get_state_vector("doc").unwrap();
+        assert_eq!(sv, Some(doc.transact().state_vector()));
+        assert!(up_to_date);
+        let loaded = Doc::new();
+        let loaded_text = loaded.get_or_insert_text("text");
+        db.load_doc("doc", &mut loaded.transact_mut()).unwrap();
+        assert_eq!(loaded_text.get_string(&loaded.transact()), "hello world");
+
+        // flush has written reserved metadata entries of its own
+        db.insert_meta("doc", "owner", b"me").unwrap();
+        assert!(db
+            .iter_meta("doc")
+            .unwrap()
+            .any(|(key, value)| key.as_ref() == b"owner" && value.as_ref() == b"me"));
+
+        // removals are visible as well
+        db.clear_doc("doc").unwrap();
+        assert!(!db.load_doc("doc", &mut Doc::new().transact_mut()).unwrap());
+        assert!(db.iter_docs().unwrap().next().is_none());
+    }
+
+    backend.abort(|db| check(db));
+    let config = StoreConfig {
+        codec: ValueCodec {
+            compression: Compression::Zstd {
+                level: 3,
+                min_size: 0,
+            },
+            checksum: true,
+        },
+        ..StoreConfig::DEFAULT
+    };
+    backend.write_configured(&config, |db| check(db));
+}
+
+/// Verifies that cached documents are served without reaching the store, kept fresh by writes made
+/// through the cache and evicted in least recently used order.
+pub fn doc_cache<B: Backend>(backend: &B) {
+    const DOC_NAME: &str = "doc";
+    let cache = DocCache::new(2);
+    let read_text = |doc: &Doc| {
+        let text = doc.get_or_insert_text("text");
+        text.get_string(&doc.transact())
+    };
+
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    text.push(&mut doc.transact_mut(), "hello");
+    backend.write(|db| {
+        db.insert_doc(DOC_NAME, &doc.transact()).unwrap();
+    });
+    backend.read(|db| {
+        let db = CachedStore::new(db, &cache);
+        let loaded = db.with_doc(DOC_NAME, read_text).unwrap();
+        assert_eq!(loaded, Some("hello".to_string()));
+        assert_eq!(cache.len(), 1);
+    });
+    // remove the document bypassing the cache
+    backend.write(|db| {
+        db.clear_doc(DOC_NAME).unwrap();
+    });
+    backend.read(|db| {
+        // second load doesn't reach the store, so it doesn't see the removal
+        let db = CachedStore::new(db, &cache);
+        let loaded = db.with_doc(DOC_NAME, read_text).unwrap();
+        assert_eq!(loaded, Some("hello".to_string()));
+
+        assert!(cache.invalidate(DOC_NAME));
+        assert_eq!(db.with_doc(DOC_NAME, read_text).unwrap(), None);
+    });
+    backend.transaction(|db| {
+        // writes through the cached store keep cached document fresh
+        let db = CachedStore::new(db, &cache);
+        db.insert_doc(DOC_NAME, &doc.transact()).unwrap();
+        assert!(db.get_doc(DOC_NAME).unwrap().is_some());
+        let sv = doc.transact().state_vector();
+        text.push(&mut doc.transact_mut(), " world");
+        db.push_update(DOC_NAME, &doc.transact().encode_diff_v1(&sv))
+            .unwrap();
+        let cached = cache.get(DOC_NAME).unwrap();
+        assert_eq!(read_text(&cached), "hello world");
+
+        let report = db.clear_doc(DOC_NAME).unwrap();
+        assert!(report.doc_state_removed);
+        assert_eq!(report.updates_removed, 1);
+        assert!(cache.is_empty());
+        (db.into_inner(), ())
+    });
+
+    // least recently used documents are evicted first
+    cache.insert("a", Doc::new());
+    cache.insert("b", Doc::new());
+    assert!(cache.get("a").is_some());
+    cache.insert("c", Doc::new());
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get("b").is_none());
+}
+
+/// Verifies that an exported archive is imported into another database unchanged, and that
+/// interrupted imports can be resumed.
+pub fn archive_round_trip<B: Backend>(backend: &B) {
+    const DOCS: u32 = 200;
+    const LOG_LEN: usize = 2000;
+    fn content<'a, DB: DocOps<'a>>(db: &DB, name: &str) -> String
+    where
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        db.load_doc(name, &mut doc.transact_mut()).unwrap();
+        let content = text.get_string(&doc.transact());
+        content
+    }
+
+    backend.write(|db| {
+        for i in 0..DOCS {
+            let name = format!("doc-{}", i);
+            let doc = Doc::with_client_id(i as u64 + 1);
+            let text = doc.get_or_insert_text("text");
+            text.push(&mut doc.transact_mut(), &name);
+            db.insert_doc(&name, &doc.transact()).unwrap();
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, "!");
+            db.push_update(&name, &txn.encode_update_v1()).unwrap();
+            db.insert_meta(&name, "index", &i.to_be_bytes()).unwrap();
+        }
+        // document with a long log of pending updates
+        let doc = Doc::with_client_id(1000);
+        let text = doc.get_or_insert_text("text");
+        for _ in 0..LOG_LEN {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, "x");
+            db.push_update("log", &txn.encode_update_v1()).unwrap();
+        }
+        db.insert_snapshot("log", "v1", &doc.transact().snapshot())
+            .unwrap();
+        assert!(db.archive_doc("doc-0").unwrap());
+    });
+
+    let mut data = Vec::new();
+    let exported = backend.abort(|db| {
+        let mut reported = 0;
+        let progress = export_store_with(db, &mut data, |p| reported = p.docs).unwrap();
+        assert_eq!(progress.docs, DOCS + 1);
+        assert_eq!(reported, progress.docs);
+        progress
+    });
+
+    let target = B::open("archive_round_trip_target");
+
+    // import of a truncated archive fails part way through, committing a document at a time
+    let mut reader = ArchiveReader::new(&data[..data.len() / 2]).unwrap();
+    let res = loop {
+        match target.try_write(|db| reader.import_next(db, ConflictMode::Skip)) {
+            Ok(Some(_)) => {}
+            other => break other,
+        }
+    };
+    assert!(res.is_err());
+    let committed = reader.progress().docs;
+    assert!(committed > 0);
+
+    // import of the whole archive resumes, skipping documents imported so far
+    target.write(|db| {
+        let progress = import_store(db, &data[..], ConflictMode::Skip).unwrap();
+        assert_eq!(progress.docs, exported.docs);
+        assert_eq!(progress.skipped, committed);
+        assert_eq!(progress.bytes, exported.bytes);
+    });
+
+    backend.read(|src| {
+        target.write(|dst| {
+            for i in 1..DOCS {
+                let name = format!("doc-{}", i);
+                assert_eq!(content(dst, &name), format!("{}!", name));
+                let index = DocOps::get_meta(dst, &name, "index").unwrap();
+                let index = index.map(|v| v.as_ref().to_vec());
+                assert_eq!(index, Some(i.to_be_bytes().to_vec()));
+            }
+            assert_eq!(content(dst, "log"), content(&src, "log"));
+            assert_eq!(dst.decoded_updates("log").unwrap().count(), LOG_LEN);
+            assert_eq!(
+                dst.get_snapshot("log", "v1")
+                    .unwrap()
+                    .map(|s| s.encode_v1()),
+                src.get_snapshot("log", "v1")
+                    .unwrap()
+                    .map(|s| s.encode_v1())
+            );
+            // archived documents stay archived
+            assert!(matches!(
+                dst.load_doc("doc-0", &mut Doc::new().transact_mut()),
+                Err(Error::DocArchived)
+            ));
+            assert!(dst.unarchive_doc("doc-0").unwrap());
+            assert_eq!(content(dst, "doc-0"), "doc-0!");
+        });
+    });
+}
+
+/// Verifies that comparing two stores ignores their OIDs and reports drifting updates, metadata and
+/// missing documents.
+pub fn compare_stores<B: Backend>(backend: &B) {
+    /// Writes the same documents, flushing one of them on one side and the other one on the
+    /// other side, so that their entries differ.
+    fn fill<'a, DB: DocOps<'a>>(db: &DB, updates: &[Vec<u8>], flushed: &str)
+    where
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        for name in ["x", "y"] {
+            for update in updates {
+                db.push_update(name, update).unwrap();
+            }
+        }
+        db.flush_doc(flushed).unwrap().unwrap();
+        db.insert_meta("x", "k", b"1").unwrap();
+    }
+
+    let doc = Doc::with_client_id(1);
+    let text = doc.get_or_insert_text("text");
+    let updates: Vec<Vec<u8>> = ["a", "b", "c"]
+        .iter()
+        .map(|chunk| {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            txn.encode_update_v1()
+        })
+        .collect();
+    let drift = {
+        let mut txn = doc.transact_mut();
+        text.push(&mut txn, "d");
+        txn.encode_update_v1()
+    };
+
+    let other = B::open("compare_stores_b");
+    backend.abort(|a| {
+        other.abort(|b| {
+            // shift OIDs of the second store
+            b.push_update("pad", &updates[0]).unwrap();
+            b.clear_doc("pad").unwrap();
+            fill(a, &updates, "x");
+            fill(b, &updates, "y");
+
+            // equal stores, even though OIDs and entries differ
+            let report = compare_stores_with(a, b, CompareMode::Fast).unwrap();
+            assert!(report.is_equal(), "{:?}", report);
+            let report = verify::compare_stores(a, b).unwrap();
+            assert!(report.is_equal(), "{:?}", report);
+            assert_eq!(report.docs_compared, 2);
+
+            // one-update drift
+            b.push_update("y", &drift).unwrap();
+            for mode in [CompareMode::Fast, CompareMode::Thorough] {
+                let report = compare_stores_with(a, b, mode).unwrap();
+                assert_eq!(report.content_mismatches.len(), 1);
+                let mismatch = &report.content_mismatches[0];
+                assert_eq!(mismatch.name.as_ref(), b"y");
+                assert_ne!(mismatch.state_vector_a, mismatch.state_vector_b);
+                assert!(report.meta_mismatches.is_empty());
+            }
+            a.push_update("y", &drift).unwrap();
+
+            // metadata-only difference and a document present on one side only
+            a.insert_meta("x", "k", b"2").unwrap();
+            a.insert_meta("x", "only-a", b"3").unwrap();
+            b.push_update("z", &drift).unwrap();
+            let report = verify::compare_stores(a, b).unwrap();
+            assert!(report.content_mismatches.is_empty());
+            assert!(report.only_in_a.is_empty());
+            assert_eq!(report.only_in_b, vec![Box::<[u8]>::from(&b"z"[..])]);
+            assert_eq!(
+                report.meta_mismatches,
+                vec![
+                    MetaMismatch {
+                        name: "x".as_bytes().into(),
+                        key: "k".as_bytes().into(),
+                        value_a: Some("2".as_bytes().into()),
+                        value_b: Some("1".as_bytes().into()),
+                    },
+                    MetaMismatch {
+                        name: "x".as_bytes().into(),
+                        key: "only-a".as_bytes().into(),
+                        value_a: Some("3".as_bytes().into()),
+                        value_b: None,
+                    },
+                ]
+            );
+        });
+    });
+}
+
+/// Verifies that a chain of incremental backups restores another database to the same state as a
+/// full backup.
+pub fn incremental_backup<B: Backend>(backend: &B) {
+    fn content<'a, DB: DocOps<'a>>(db: &DB, name: &str) -> String
+    where
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        db.load_doc(name, &mut doc.transact_mut()).unwrap();
+        let content = text.get_string(&doc.transact());
+        content
+    }
+    fn append<'a, DB: DocOps<'a>>(db: &DB, doc: &Doc, name: &str, chunk: &str)
+    where
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        let text = doc.get_or_insert_text("text");
+        let mut txn = doc.transact_mut();
+        text.push(&mut txn, chunk);
+        db.push_update(name, &txn.encode_update_v1()).unwrap();
+    }
+    /// Makes changes of a given `round`, covering every kind of incremental export.
+    fn change<'a, DB: DocOps<'a>>(db: &DB, docs: &mut HashMap<&'static str, Doc>, round: u64)
+    where
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        // new pending updates only
+        append(db, &docs["a"], "a", &format!("a{}", round));
+        // deletion followed by a flush, which doesn't advance the state vector
+        let b = &docs["b"];
+        let text = b.get_or_insert_text("text");
+        let mut txn = b.transact_mut();
+        text.remove_range(&mut txn, 0, 1);
+        db.push_update("b", &txn.encode_update_v1()).unwrap();
+        drop(txn);
+        append(db, b, "b", &format!("b{}", round));
+        db.flush_doc("b").unwrap();
+        db.insert_meta("b", "k", &round.to_be_bytes()).unwrap();
+        match round {
+            1 => {
+                db.clear_doc("c").unwrap();
+                let e = Doc::with_client_id(5);
+                append(db, &e, "e", "e");
+                docs.insert("e", e);
+            }
+            2 => {
+                // document re-created under the name of a cleared one
+                let c = Doc::with_client_id(33);
+                append(db, &c, "c", "new c");
+                docs.insert("c", c);
+                db.remove_meta("a", "k").unwrap();
+            }
+            _ => {
+                assert!(db.archive_doc("d").unwrap());
+            }
+        }
+    }
+    fn assert_same<'a, 'b, DB1: DocOps<'a>, DB2: DocOps<'b>>(src: &DB1, dst: &DB2)
+    where
+        Error: From<<DB1 as KVStore<'a>>::Error> + From<<DB2 as KVStore<'b>>::Error>,
+    {
+        let names: Vec<_> = src.iter_docs_with(true).unwrap().collect();
+        assert_eq!(names, dst.iter_docs_with(true).unwrap().collect::<Vec<_>>());
+        for name in names {
+            let name = std::str::from_utf8(&name).unwrap();
+            if name == "d" {
+                assert!(matches!(
+                    dst.load_doc("d", &mut Doc::new().transact_mut()),
+                    Err(Error::DocArchived)
+                ));
+                continue;
+            }
+            assert_eq!(content(src, name), content(dst, name), "{}", name);
+            let src_meta = src
+                .get_meta(name, "k")
+                .unwrap()
+                .map(|v| v.as_ref().to_vec());
+            let dst_meta = dst
+                .get_meta(name, "k")
+                .unwrap()
+                .map(|v| v.as_ref().to_vec());
+            assert_eq!(src_meta, dst_meta, "{}", name);
+        }
+    }
+
+    let mut docs: HashMap<&'static str, Doc> = HashMap::new();
+    backend.write(|db| {
+        for (i, name) in ["a", "b", "c", "d"].iter().enumerate() {
+            let doc = Doc::with_client_id(i as u64 + 1);
+            append(db, &doc, name, name);
+            db.insert_meta(name, "k", b"v").unwrap();
+            docs.insert(name, doc);
+        }
+    });
+
+    // full backup followed by a chain of three incremental ones
+    let mut manifest = BackupManifest::default();
+    let mut backups = Vec::new();
+    for round in 0..4 {
+        if round > 0 {
+            backend.write(|db| change(db, &mut docs, round));
+        }
+        let next = backend.abort(|db| {
+            let mut backup = Vec::new();
+            let next = export_changed_since(db, &manifest, &mut backup).unwrap();
+            backups.push(backup);
+            next
+        });
+        assert_eq!(BackupManifest::decode(&next.encode()).unwrap(), next);
+        manifest = next;
+    }
+    assert_eq!(manifest.len(), 5);
+    assert!(backups[1].len() < backups[0].len());
+
+    let target = B::open("incremental_backup_target");
+    for backup in backups.iter() {
+        target.write(|db| {
+            import_store(db, &backup[..], ConflictMode::Skip).unwrap();
+        });
+    }
+    backend.read(|src| {
+        target.write(|dst| {
+            assert_same(&src, dst);
+            assert!(dst.unarchive_doc("d").unwrap());
+            assert_eq!(content(dst, "d"), "d");
+        });
+    });
+}
+
+/// Verifies that documents exported from a snapshot all come from the same point in time, despite
+/// commits made in between.
+pub fn export_docs_consistent<B: Backend>(backend: &B) {
+    use crate::archive::export_docs_consistent;
+    use crate::ReadIsolation;
+    use std::io::Write;
+
+    const DOCS: u32 = 5;
+
+    fn counter<'a, DB: DocOps<'a>>(db: &DB, name: &str) -> u32
+    where
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        match db.get_meta(name, "counter").unwrap() {
+            Some(value) => {
+                let mut buf = [0; 4];
+                buf.copy_from_slice(value.as_ref());
+                u32::from_be_bytes(buf)
+            }
+            None => 0,
+        }
+    }
+
+    /// Increments counters of all documents, which are expected to be equal at all times.
+    fn bump<'a, DB: DocOps<'a>>(db: &DB)
+    where
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        for i in 0..DOCS {
+            let name = format!("doc-{}", i);
+            let next = counter(db, &name) + 1;
+            db.insert_meta(&name, "counter", &next.to_be_bytes())
+                .unwrap();
+        }
+    }
+
+    fn counters<'a, DB: DocOps<'a>>(db: &DB) -> Vec<u32>
+    where
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        (0..DOCS)
+            .map(|i| counter(db, &format!("doc-{}", i)))
+            .collect()
+    }
+
+    /// Archive writer, which lets a concurrent writer commit before every record.
+    struct Interleaved {
+        archive: Vec<u8>,
+        commit: mpsc::Sender<()>,
+        committed: mpsc::Receiver<()>,
+    }
+
+    impl Write for Interleaved {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.commit.send(()).unwrap();
+            self.committed.recv().unwrap();
+            self.archive.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let names: Vec<String> = (0..DOCS).map(|i| format!("doc-{}", i)).collect();
+    let (commit, requests) = mpsc::channel();
+    let (done, committed) = mpsc::channel();
+    let mut out = Interleaved {
+        archive: Vec::new(),
+        commit,
+        committed,
+    };
+    backend.write(|db| bump(db));
+
+    let writer = {
+        let backend = backend.clone();
+        std::thread::spawn(move || {
+            for _ in requests {
+                backend.write(|db| bump(db));
+                done.send(()).unwrap();
+            }
+        })
+    };
+    backend.read(|db| {
+        let snapshot = db.read_snapshot();
+        assert_eq!(snapshot.isolation(), ReadIsolation::Snapshot);
+        let names = names.iter().map(|name| name.as_bytes());
+        let progress = export_docs_consistent(&snapshot, names, &mut out).unwrap();
+        assert_eq!(progress.docs, DOCS);
+    });
+    let archive = std::mem::take(&mut out.archive);
+    drop(out);
+    writer.join().unwrap();
+
+    let imported = B::open("export_docs_consistent_imported");
+    let exported = imported.write(|db| {
+        import_store(db, archive.as_slice(), ConflictMode::Skip).unwrap();
+        counters(db)
+    });
+    // all documents come from the same point in time, despite commits made in between
+    assert!(exported.iter().all(|&counter| counter == exported[0]));
+    let live = backend.read(|db| counters(&db));
+    assert!(live[0] > exported[0]);
+}
+
+/// Verifies that reads and writes are counted by the operation that has made them.
+pub fn io_stats<B: Backend>(backend: &B) {
+    use crate::io_stats::{IoStatsStore, OpStats, Operation};
+
+    fn check<'a, S: DocOps<'a>>(store: S) -> S
+    where
+        Error: From<S::Error>,
+    {
+        let db = IoStatsStore::new(store);
+        db.upsert(b"key", b"value").unwrap();
+        assert_eq!(db.get(b"key").unwrap().unwrap().as_ref(), b"value");
+        assert!(db.get(b"missing").unwrap().is_none());
+        assert_eq!(
+            db.report().get(Operation::Other),
+            OpStats {
+                calls: 0,
+                keys_read: 2,
+                bytes_read: 5,
+                keys_written: 1,
+                bytes_written: 8,
+            }
+        );
+        db.remove(b"key").unwrap();
+        assert_eq!(db.report().get(Operation::Other).bytes_written, 11);
+        db.reset();
+        assert_eq!(db.report().total(), OpStats::default());
+
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let updates: Vec<Vec<u8>> = ["hello", " world"]
+            .iter()
+            .map(|chunk| {
+                let mut txn = doc.transact_mut();
+                text.push(&mut txn, chunk);
+                txn.encode_update_v1()
+            })
+            .collect();
+        let update_bytes = (updates[0].len() + updates[1].len()) as u64;
+        for update in updates.iter() {
+            db.push_update("doc", update).unwrap();
+        }
+        let push = db.report().get(Operation::PushUpdate);
+        assert_eq!(push.calls, 2);
+        assert!(push.bytes_written >= update_bytes);
+
+        db.flush_doc("doc").unwrap().unwrap();
+        let flush = db.report().get(Operation::FlushDoc);
+        assert_eq!(flush.calls, 1);
+        // both pending updates are read back in order to be merged
+        assert!(flush.keys_read >= 2);
+        assert!(flush.bytes_read >= update_bytes);
+        assert!(flush.keys_written > 0);
+
+        let loaded = Doc::new();
+        assert!(db.load_doc("doc", &mut loaded.transact_mut()).unwrap());
+        let load = db.report().get(Operation::LoadDoc);
+        assert_eq!(load.calls, 1);
+        assert!(load.bytes_read > 0);
+
+        // calls made through DocOps trait are not attributed to any particular operation
+        DocOps::get_state_vector(&db, "doc").unwrap();
+        assert_eq!(
+            db.report().get(Operation::GetStateVector),
+            OpStats::default()
+        );
+        assert!(db.report().get(Operation::Other).keys_read > 0);
+
+        let mut aggregated = db.report();
+        aggregated.merge(&db.report());
+        assert_eq!(aggregated.total().calls, 8);
+        assert_eq!(
+            aggregated.get(Operation::PushUpdate).bytes_written,
+            2 * push.bytes_written
+        );
+        db.into_inner()
+    }
+
+    backend.transaction(|db| (check(db), ()));
+}
+
+/// Verifies that memoized lookups reduce the number of reads of a sync handshake, while observing
+/// writes made through the memo.
+pub fn memo<B: Backend>(backend: &B) {
+    use crate::io_stats::IoStatsStore;
+    use crate::memo::{MemoStats, MemoStore};
+
+    fn check<'a, S: DocOps<'a>>(store: S) -> S
+    where
+        Error: From<S::Error>,
+    {
+        // sync handshake: state vector followed by the diff of a remote replica
+        fn exchange<'a, S: DocOps<'a>>(db: &S, name: &str) -> Option<Vec<u8>>
+        where
+            Error: From<S::Error>,
+        {
+            let (sv, _) = db.get_state_vector(name).unwrap();
+            assert!(sv.is_some());
+            db.get_diff(name, &StateVector::default()).unwrap()
+        }
+
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let update = |chunk: &str| {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            txn.encode_update_v1()
+        };
+        let db = IoStatsStore::new(store);
+        db.push_update("doc", &update("hello")).unwrap();
+        db.flush_doc("doc").unwrap().unwrap();
+        db.push_update("doc", &update(" world")).unwrap();
+
+        db.reset();
+        let expected = exchange(&db, "doc").unwrap();
+        let plain_reads = db.report().total().keys_read;
+        db.reset();
+        let db = MemoStore::new(db);
+        assert_eq!(exchange(&db, "doc").unwrap(), expected);
+        let memo_reads = db.report().total().keys_read;
+        assert!(
+            memo_reads < plain_reads,
+            "{} reads with memo, {} without",
+            memo_reads,
+            plain_reads
+        );
+        let stats = db.stats();
+        assert!(stats.hits > 0);
+        assert!(stats.misses > 0);
+        let misses = stats.misses;
+        exchange(&db, "doc").unwrap();
+        assert_eq!(db.stats().misses, misses);
+
+        // writes through the memo are observed by subsequent reads
+        assert!(db
+            .get_diff("new", &StateVector::default())
+            .unwrap()
+            .is_none());
+        db.push_update("new", &update("!")).unwrap();
+        assert!(db
+            .get_diff("new", &StateVector::default())
+            .unwrap()
+            .is_some());
+        db.flush_doc("doc").unwrap().unwrap();
+        let loaded = Doc::new();
+        assert!(db.load_doc("doc", &mut loaded.transact_mut()).unwrap());
+        let (sv, _) = db.get_state_vector("doc").unwrap();
+        assert_eq!(sv.unwrap(), loaded.transact().state_vector());
+        db.clear_doc("doc").unwrap();
+        assert_eq!(db.get_state_vector("doc").unwrap().0, None);
+        assert!(db
+            .get_diff("doc", &StateVector::default())
+            .unwrap()
+            .is_none());
+
+        // capacity bounds the number of memoized entries
+        db.flush_doc("new").unwrap().unwrap();
+        let db = MemoStore::with_capacity(db.into_inner(), 1);
+        exchange(&db, "new").unwrap();
+        exchange(&db, "new").unwrap();
+        assert!(db.stats().misses > 1);
+        db.clear();
+        assert_ne!(db.stats(), MemoStats::default());
+        db.into_inner().into_inner()
+    }
+
+    backend.transaction(|db| (check(db), ()));
+}
+
+/// Verifies that code using nothing but the prelude compiles against a store.
+pub fn prelude<B: Backend>(backend: &B) {
+    // the same snippet is compiled against every backend, using nothing but the prelude
+    mod snippet {
+        use crate::prelude::*;
+
+        pub fn configured<'a, S: KVStore<'a>>(store: S) -> ConfiguredStore<S> {
+            let config = StoreConfig {
+                flush_policy: FlushPolicy::AfterUpdates(2),
+                ..StoreConfig::DEFAULT
+            };
+            ConfiguredStore::new(store, config)
+        }
+
+        pub fn save<'a, S: DocOps<'a>>(db: &S, name: &str, update: &[u8]) -> Result<u32, Error>
+        where
+            Error: From<<S as KVStore<'a>>::Error>,
+        {
+            let receipt: PushReceipt = db.push_update(name, update)?;
+            Ok(receipt.seq)
+        }
+
+        pub fn doc_names<'a, S: KVStore<'a>>(db: &S) -> Result<Vec<String>, Error>
+        where
+            Error: From<<S as KVStore<'a>>::Error>,
+        {
+            let mut names = Vec::new();
+            for e in db.iter_range(&[0], &[0xff])? {
+                if let Some(ParsedKey::Oid { doc_name }) = parse_key(e.key()) {
+                    names.push(String::from_utf8_lossy(doc_name).into_owned());
+                }
+            }
+            Ok(names)
+        }
+    }
+
+    backend.transaction(|db| {
+        let db = snippet::configured(db);
+        assert_eq!(snippet::save(&db, "a", &[0, 0]).unwrap(), 1);
+        assert_eq!(snippet::save(&db, "a", &[0, 0]).unwrap(), 2);
+        assert_eq!(snippet::save(&db, "b", &[0, 0]).unwrap(), 1);
+        assert_eq!(snippet::doc_names(&db).unwrap(), vec!["a", "b"]);
+        (db.into_inner(), ())
+    });
+}
+
+/// Verifies that replaying a workload under different flush policies reports their write
+/// amplification.
+pub fn flush_policy_workload<B: Backend>(backend: &B) {
+    use crate::bench::{generate_trace, trace_updates};
+    use crate::io_stats::Operation;
+    use crate::workload::{compare_flush_policies, comparison_table, PolicyRun};
+
+    fn check(runs: &[PolicyRun]) {
+        assert_eq!(runs[0].peak_pending_updates, 63);
+        assert_eq!(runs[1].peak_pending_updates, 300);
+        for run in runs.iter() {
+            assert_eq!(run.updates, 300);
+            assert!(run.store_size > 0);
+            let push = run.io.get(Operation::PushUpdate);
+            assert_eq!(push.calls, 300);
+            assert!(push.bytes_written >= run.update_bytes);
+        }
+        // merged document states are written on top of the updates themselves
+        assert!(runs[0].io.total().bytes_written > runs[1].io.total().bytes_written);
+        assert!(runs[0].write_amplification() > runs[1].write_amplification());
+
+        let table = comparison_table(runs);
+        assert_eq!(table.lines().count(), 3);
+        assert!(table.contains("every 64 updates"));
+        assert!(table.contains("never"));
+    }
+
+    let updates = trace_updates(&generate_trace(300, 7));
+    let policies = [FlushPolicy::AfterUpdates(64), FlushPolicy::Manual];
+    let runs =
+        backend.open_stores(|open| compare_flush_policies(open, &updates, &policies).unwrap());
+    check(&runs);
+}
+
+/// Verifies that writes missed by an unavailable secondary store are marked as diverged and
+/// repaired once it's back.
+pub fn dual_store<B: Backend>(backend: &B) {
+    use crate::dual::DualStore;
+    use yrs::TextRef;
+
+    /// Store which fails all operations while it's down, simulating an unavailable backend.
+    struct Flaky<S> {
+        store: S,
+        down: Cell<bool>,
+    }
+
+    impl<S> Flaky<S> {
+        fn check(&self) -> io::Result<()> {
+            if self.down.get() {
+                Err(io::Error::other("store is down"))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn other<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+        io::Error::other(e)
+    }
+
+    impl<'a, S: KVStore<'a>> KVStore<'a> for Flaky<S>
+    where
+        S::Error: Send + Sync + 'static,
+    {
+        type Error = io::Error;
+        type Cursor = S::Cursor;
+        type Entry = S::Entry;
+        type Return = S::Return;
+
+        fn get(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+            self.check()?;
+            self.store.get(key).map_err(other)
+        }
+
+        fn upsert(&self, key: &[u8], value: &[u8]) -> Result<(), Self::Error> {
+            self.check()?;
+            self.store.upsert(key, value).map_err(other)
+        }
+
+        fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+            self.check()?;
+            self.store.remove(key).map_err(other)
+        }
+
+        fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<u32, Self::Error> {
+            self.check()?;
+            self.store.remove_range(from, to).map_err(other)
+        }
+
+        fn iter_range(&self, from: &[u8], to: &[u8]) -> Result<Self::Cursor, Self::Error> {
+            self.check()?;
+            self.store.iter_range(from, to).map_err(other)
+        }
+
+        fn peek_back(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+            self.check()?;
+            self.store.peek_back(key).map_err(other)
+        }
+
+        fn seek(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+            self.check()?;
+            self.store.seek(key).map_err(other)
+        }
+    }
+
+    impl<'a, S: KVStore<'a>> DocOps<'a> for Flaky<S> where S::Error: Send + Sync + 'static {}
+
+    /// Runs a workload through the dual store, taking the secondary store down every few
+    /// rounds. Returns contents of all documents written by the workload.
+    fn workload<'a, 'b, P: DocOps<'a>, S: KVStore<'b>>(
+        dual: &DualStore<P, Flaky<S>>,
+    ) -> Vec<(String, String)>
+    where
+        Error: From<<P as KVStore<'a>>::Error>,
+        S::Error: Send + Sync + 'static,
+    {
+        let docs: Vec<(String, Doc, TextRef)> = (0..4)
+            .map(|i| {
+                let doc = Doc::with_client_id(i + 1);
+                let text = doc.get_or_insert_text("text");
+                (format!("doc-{}", i), doc, text)
+            })
+            .collect();
+        for round in 0..6u8 {
+            dual.secondary().down.set(round % 3 == 1);
+            for (i, (name, doc, text)) in docs.iter().enumerate() {
+                if round == 4 && i == 0 {
+                    // secondary store comes back in the middle of a round
+                    dual.secondary().down.set(false);
+                }
+                let update = {
+                    let mut txn = doc.transact_mut();
+                    text.push(&mut txn, &format!("{}", round));
+                    txn.encode_update_v1()
+                };
+                dual.push_update(name, &update).unwrap();
+                if (round as usize + i).is_multiple_of(2) {
+                    dual.insert_meta(name, "round", &[round]).unwrap();
+                }
+            }
+            if round % 2 == 1 {
+                dual.flush_doc("doc-1").unwrap().unwrap();
+            }
+            dual.remove_meta("doc-2", "round").unwrap();
+        }
+        dual.insert_meta("removed", "key", &[1]).unwrap();
+        dual.secondary().down.set(true);
+        dual.clear_doc("removed").unwrap();
+        dual.secondary().down.set(false);
+        docs.iter()
+            .map(|(name, doc, text)| (name.clone(), text.get_string(&doc.transact())))
+            .collect()
+    }
+
+    fn read<'a, P: DocOps<'a>, S>(dual: &DualStore<P, S>, name: &str) -> Option<String>
+    where
+        Error: From<<P as KVStore<'a>>::Error>,
+    {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        if dual
+            .primary()
+            .load_doc(name, &mut doc.transact_mut())
+            .unwrap()
+        {
+            Some(text.get_string(&doc.transact()))
+        } else {
+            None
+        }
+    }
+
+    let other = B::open("dual_store_secondary");
+    backend.transaction(|primary| {
+        let primary = other.transaction(|secondary| {
+            let dual = DualStore::new(
+                primary,
+                Flaky {
+                    store: secondary,
+                    down: Cell::new(false),
+                },
+            );
+            let expected = workload(&dual);
+            for (name, content) in expected.iter() {
+                assert_eq!(read(&dual, name), Some(content.clone()));
+            }
+            let diverged: Vec<Box<[u8]>> = ["doc-0", "doc-1", "doc-2", "doc-3", "removed"]
+                .iter()
+                .map(|name| name.as_bytes().into())
+                .collect();
+            assert_eq!(dual.diverged_docs().unwrap(), diverged);
+            assert_eq!(dual.written_docs(), diverged);
+            let report =
+                compare_stores_with(dual.primary(), dual.secondary(), CompareMode::Fast).unwrap();
+            assert!(!report.is_equal());
+
+            // repair fails while secondary store is down, keeping the markers
+            dual.secondary().down.set(true);
+            assert!(dual.repair().is_err());
+            assert_eq!(dual.diverged_docs().unwrap(), diverged);
+            dual.secondary().down.set(false);
+            assert_eq!(dual.repair().unwrap(), 5);
+            assert!(dual.diverged_docs().unwrap().is_empty());
+            let report =
+                compare_stores_with(dual.primary(), dual.secondary(), CompareMode::Thorough)
+                    .unwrap();
+            assert!(report.is_equal(), "{:?}", report);
+            assert_eq!(report.docs_compared, 4);
+
+            // once repaired, secondary store can serve reads
+            let dual = dual.promote();
+            for (name, content) in expected.iter() {
+                assert_eq!(read(&dual, name), Some(content.clone()));
+            }
+            assert_eq!(read(&dual, "removed"), None);
+            let (secondary, primary) = dual.into_inner();
+            (secondary.store, primary)
+        });
+        (primary, ())
+    });
+}
+
+/// Verifies that keys of document names fitting into inline capacity are built without heap
+/// allocations.
+pub fn key_allocations() {
+    use crate::keys::{key_doc, key_meta, key_oid, key_pending, key_update};
+
+    let name = b"0b7e4d2c-8f3a-4c59-9a6e-2d5f1c3b7a90";
+    let before = allocations();
+    let lens = [
+        key_oid(name).len(),
+        key_meta(1, b"$last_modified").len(),
+        key_doc(1).len(),
+        key_update(1, 7).len(),
+        key_pending(1).len(),
+    ];
+    assert_eq!(allocations(), before);
+    assert_eq!(lens, [39, 22, 7, 12, 7]);
+
+    // names longer than inline capacity spill to the heap
+    let before = allocations();
+    assert_eq!(key_oid(&[b'a'; 64]).len(), 67);
+    assert!(allocations() > before);
+}
+
+/// Loads a document with a given `name` and returns the contents of its `text` root type, or
+/// `None` if document doesn't exist.
+fn load_text<'a, DB: DocOps<'a>>(db: &DB, name: &str) -> Option<String>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    if db.load_doc(name, &mut doc.transact_mut()).unwrap() {
+        Some(text.get_string(&doc.transact()))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,94 @@
+//! Cooperative deadlines of long running [DocOps](crate::DocOps) calls.
+//!
+//! When [StoreConfig::op_deadline] is set, loops iterating over pending updates and document
+//! entries check the time elapsed since the operation started after every processed entry. Once
+//! the deadline passes, the operation stops and fails with [Error::DeadlineExceeded], which
+//! describes how far it got. At least one entry is always processed, so every call makes
+//! progress.
+//!
+//! Interrupted operations leave the store in a consistent state, and the writes they have made
+//! so far are not lost once the transaction is committed:
+//!
+//! - [DocOps::flush_doc](crate::DocOps::flush_doc) merges the pending updates it has already
+//!   loaded into document state and removes them, so that the next call continues with the
+//!   remaining ones.
+//! - [DocOps::clear_doc](crate::DocOps::clear_doc) records itself in the intent log, so it's
+//!   completed by the next [DocOps::clear_doc](crate::DocOps::clear_doc) call for the same
+//!   document or by [DocOps::recover_intents](crate::DocOps::recover_intents).
+//! - [DocOps::load_doc](crate::DocOps::load_doc) and other reads have applied document state and
+//!   pending updates up to the reported sequence number to the provided transaction. Applying
+//!   them again is harmless, but flushing the document is what makes subsequent loads faster.
+//!
+//! ```rust,ignore
+//! let config = StoreConfig {
+//!     op_deadline: Some(Duration::from_millis(500)),
+//!     ..StoreConfig::DEFAULT
+//! };
+//! loop {
+//!     let db_txn = ConfiguredStore::new(RocksDBStore::from(db.transaction()), config.clone());
+//!     let result = db_txn.flush_doc("my-doc-name");
+//!     // partial progress is committed, so that the next call can continue from it
+//!     db_txn.into_inner().commit()?;
+//!     match result {
+//!         Err(Error::DeadlineExceeded { .. }) => continue,
+//!         other => break other?,
+//!     }
+//! }
+//! ```
+//!
+//! [StoreConfig::op_deadline]: crate::config::StoreConfig::op_deadline
+//! [Error::DeadlineExceeded]: crate::error::Error::DeadlineExceeded
+
+use crate::config::StoreConfig;
+use std::fmt::{Display, Formatter};
+use std::time::Instant;
+
+/// Describes how far an operation interrupted by
+/// [Error::DeadlineExceeded](crate::error::Error::DeadlineExceeded) got.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Progress {
+    /// Document state and pending updates with sequence numbers up to `seq` have been applied.
+    Load { seq: u32 },
+    /// Pending updates with sequence numbers up to `seq` have been merged into document state
+    /// and removed. `remaining` pending updates are left.
+    Flush { seq: u32, remaining: u32 },
+    /// Entries of a cleared document preceding `key` have been removed.
+    Clear { key: Vec<u8> },
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Progress::Load { seq } => write!(f, "loaded updates up to {}", seq),
+            Progress::Flush { seq, remaining } => write!(
+                f,
+                "flushed updates up to {} ({} updates remaining)",
+                seq, remaining
+            ),
+            Progress::Clear { key } => write!(f, "cleared entries preceding {:?}", key),
+        }
+    }
+}
+
+/// Point in time after which an operation should stop, see [StoreConfig::op_deadline].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline(Option<Instant>);
+
+impl Deadline {
+    /// Starts measuring time of an operation executed by a store with a given `config`.
+    pub(crate) fn start(config: &StoreConfig) -> Self {
+        Deadline(
+            config
+                .op_deadline
+                .and_then(|deadline| Instant::now().checked_add(deadline)),
+        )
+    }
+
+    /// Checks if the deadline has passed.
+    pub(crate) fn exceeded(&self) -> bool {
+        match self.0 {
+            Some(at) => Instant::now() >= at,
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Support for documents identified by [Uuid] instead of arbitrary byte names. Requires `uuid`
+//! feature.
+//!
+//! UUID identifiers are stored using their 16 raw bytes, prefixed with [UUID_TAG] byte. Since
+//! this byte never occurs in valid UTF-8 strings, UUID-named documents can live alongside string
+//! named ones in the same store. Binary document names starting with [UUID_TAG] are reserved.
+//!
+//! ```rust
+//! use yrs_kvstore::doc_id::{DocId, DocName, Uuid};
+//!
+//! let uuid = Uuid::from_u128(0x67e5504410b1426f9247bb680e5fe0c8);
+//! let id = DocId::from(uuid);
+//! // DocId can be used as a document name in any DocOps method
+//! let name: &[u8] = id.as_ref();
+//! assert_eq!(name.len(), 17);
+//!
+//! // names returned from DocOps::iter_docs can be decoded back
+//! assert_eq!(DocName::decode(name.into()), DocName::Uuid(uuid));
+//! assert_eq!(
+//!     DocName::decode("my-doc".as_bytes().into()),
+//!     DocName::Bytes("my-doc".as_bytes().into())
+//! );
+//! ```
+
+pub use uuid::Uuid;
+
+/// Tag byte prefixing UUID document names.
+pub const UUID_TAG: u8 = 0xff;
+
+/// Binary document name of a document identified by [Uuid]. It can be used as a document name
+/// in all [DocOps](crate::DocOps) methods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DocId([u8; 17]);
+
+impl DocId {
+    /// Returns [Uuid] of current document.
+    pub fn uuid(&self) -> Uuid {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&self.0[1..]);
+        Uuid::from_bytes(bytes)
+    }
+
+    /// Tries to decode a [Uuid] from a given binary document `name`. Returns `None` if `name` was
+    /// not produced by [DocId].
+    pub fn decode(name: &[u8]) -> Option<Uuid> {
+        if name.len() == 17 && name[0] == UUID_TAG {
+            let mut bytes = [0u8; 16];
+            bytes.copy_from_slice(&name[1..]);
+            Some(Uuid::from_bytes(bytes))
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Uuid> for DocId {
+    fn from(uuid: Uuid) -> Self {
+        let mut buf = [0u8; 17];
+        buf[0] = UUID_TAG;
+        buf[1..].copy_from_slice(uuid.as_bytes());
+        DocId(buf)
+    }
+}
+
+impl AsRef<[u8]> for DocId {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Document name returned by [DocsNameIter::decoded](crate::DocsNameIter::decoded).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DocName {
+    /// Document identified by [DocId].
+    Uuid(Uuid),
+    /// Document identified by any other binary name.
+    Bytes(Box<[u8]>),
+}
+
+impl DocName {
+    /// Decodes binary document `name`, as returned by [DocOps::iter_docs](crate::DocOps::iter_docs).
+    pub fn decode(name: Box<[u8]>) -> Self {
+        match DocId::decode(&name) {
+            Some(uuid) => DocName::Uuid(uuid),
+            None => DocName::Bytes(name),
+        }
+    }
+}
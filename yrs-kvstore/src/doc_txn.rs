@@ -0,0 +1,130 @@
+//! Tracking of in-memory document changes, which have to be persisted together with other writes
+//! performed within the same database transaction.
+//!
+//! [DocTransaction] captures the state vector of a document when it begins. All changes made to
+//! that document afterwards are collected into a single update, which is passed to a user-provided
+//! function responsible for writing it (i.e. with [DocOps::push_update](crate::DocOps::push_update))
+//! alongside any other writes and committing the database transaction. If that function fails,
+//! collected update is returned back to the caller as part of [Uncommitted] error, so that it can
+//! be retried against a fresh database transaction instead of being lost, while in-memory document
+//! already contains these changes.
+//!
+//! ```rust,ignore
+//! let doc = Doc::new();
+//! RocksDBStore::from(db.transaction()).load_doc("my-doc-name", &mut doc.transact_mut())?;
+//!
+//! let doc_txn = DocTransaction::begin(&doc);
+//! text.push(&mut doc.transact_mut(), "hello");
+//!
+//! let persist = |update: &[u8]| {
+//!     let db_txn = RocksDBStore::from(db.transaction());
+//!     db_txn.push_update("my-doc-name", update)?;
+//!     db_txn.insert_meta("my-doc-name", "last-editor", b"me")?;
+//!     db_txn.commit()?;
+//!     Ok(())
+//! };
+//! if let Err(uncommitted) = doc_txn.commit(persist) {
+//!     // try again with a fresh database transaction
+//!     uncommitted.retry(persist)?;
+//! }
+//! ```
+
+use crate::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use yrs::{Doc, ReadTxn, StateVector, Transact};
+
+/// Collects changes made to a document since the transaction began. See
+/// [module documentation](self) for details.
+pub struct DocTransaction<'doc> {
+    doc: &'doc Doc,
+    start: StateVector,
+}
+
+impl<'doc> DocTransaction<'doc> {
+    /// Begins tracking changes made to a given `doc` from now on.
+    pub fn begin(doc: &'doc Doc) -> Self {
+        let start = doc.transact().state_vector();
+        DocTransaction { doc, start }
+    }
+
+    /// Returns a document tracked by current transaction.
+    pub fn doc(&self) -> &'doc Doc {
+        self.doc
+    }
+
+    /// Returns a state vector of the document captured when current transaction began.
+    pub fn start(&self) -> &StateVector {
+        &self.start
+    }
+
+    /// Returns a single update (encoded using lib0 v1 encoding) containing all changes made to
+    /// the document since current transaction began. Keep in mind that these also include changes
+    /// made concurrently from other threads.
+    pub fn update(&self) -> Vec<u8> {
+        self.doc.transact().encode_diff_v1(&self.start)
+    }
+
+    /// Passes an update containing all changes made to the document since current transaction
+    /// began to a given function `f`, which is expected to write it into the database and commit
+    /// database transaction. If `f` fails, returns [Uncommitted] error containing that update, so
+    /// that it can be retried.
+    pub fn commit<F, T>(self, f: F) -> Result<T, Uncommitted>
+    where
+        F: FnOnce(&[u8]) -> Result<T, Error>,
+    {
+        Uncommitted::run(self.update(), f)
+    }
+}
+
+/// Error returned when changes collected by [DocTransaction] could not be persisted. Since the
+/// in-memory document already contains them, they must not be lost: [Uncommitted::update] can be
+/// used to write them again using a fresh database transaction.
+pub struct Uncommitted {
+    /// Update (encoded using lib0 v1 encoding) containing all changes, which failed to be
+    /// persisted.
+    pub update: Vec<u8>,
+    /// Error returned while persisting the update.
+    pub error: Error,
+}
+
+impl Uncommitted {
+    fn run<F, T>(update: Vec<u8>, f: F) -> Result<T, Uncommitted>
+    where
+        F: FnOnce(&[u8]) -> Result<T, Error>,
+    {
+        match f(&update) {
+            Ok(result) => Ok(result),
+            Err(error) => Err(Uncommitted { update, error }),
+        }
+    }
+
+    /// Tries to persist the update again using a given function `f`. See [DocTransaction::commit].
+    pub fn retry<F, T>(self, f: F) -> Result<T, Uncommitted>
+    where
+        F: FnOnce(&[u8]) -> Result<T, Error>,
+    {
+        Self::run(self.update, f)
+    }
+}
+
+impl Debug for Uncommitted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Uncommitted")
+            .field("update_len", &self.update.len())
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+impl Display for Uncommitted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "failed to persist document update ({} bytes): {}",
+            self.update.len(),
+            self.error
+        )
+    }
+}
+
+impl std::error::Error for Uncommitted {}
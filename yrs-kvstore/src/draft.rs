@@ -0,0 +1,126 @@
+//! Draft documents, which are not visible until they're promoted to regular documents, i.e.
+//! documents created by an editor which haven't been saved by the user yet.
+//!
+//! [DocOps::create_draft](crate::DocOps::create_draft) creates an empty document marked with
+//! [OID_FLAG_DRAFT] in its OID index entry. Drafts can be written and loaded like any other
+//! document, but:
+//!
+//! - [DocOps::iter_docs](crate::DocOps::iter_docs), [DocOps::list_docs](crate::DocOps::list_docs)
+//!   (unless [ListOptions::include_drafts](crate::listing::ListOptions::include_drafts) is set)
+//!   and the listings built on top of them skip drafts,
+//! - drafts don't count towards storage [usage](crate::usage) of their tenants.
+//!
+//! [DocOps::promote_draft](crate::DocOps::promote_draft) turns a draft into a regular document,
+//! while [DocOps::purge_drafts](crate::DocOps::purge_drafts) removes drafts abandoned for a given
+//! time:
+//!
+//! ```rust,ignore
+//! db_txn.create_draft("my-doc-name")?;
+//! db_txn.push_update("my-doc-name", &update)?;
+//! // ... user saves the document ...
+//! db_txn.promote_draft("my-doc-name")?;
+//!
+//! // periodic cleanup job
+//! db_txn.purge_drafts(Duration::from_secs(24 * 3600))?;
+//! ```
+//!
+//! Promotion and purge both read the OID index entry of a draft using [KVStore::get_for_update]
+//! before changing it, so concurrent transactions promoting and purging the same draft conflict
+//! with each other: a draft is either promoted or purged, never both.
+
+use crate::error::Error;
+use crate::format::{decode_timestamp, encode_timestamp, META_DRAFT, OID_FLAG_DRAFT};
+use crate::keys::{doc_oid_name, key_meta, key_oid, Key, KEYSPACE_DOC, KEYSPACE_OID, V1};
+use crate::refs::{self, RefPolicy};
+use crate::{create_oid, oid_value, set_oid_flags, usage, DocOps, KVEntry, KVStore};
+use std::time::Duration;
+
+/// Creates an empty draft document with a given `name`. Returns `false` if a document with that
+/// name already exists.
+pub(crate) fn create<'a, DB: DocOps<'a> + ?Sized>(db: &DB, name: &[u8]) -> Result<bool, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if db.get_for_update(&key_oid(name))?.is_some() {
+        return Ok(false);
+    }
+    let oid = create_oid(db, name)?;
+    set_oid_flags(db, name, oid, OID_FLAG_DRAFT)?;
+    db.upsert(
+        &key_meta(oid, META_DRAFT),
+        &encode_timestamp(db.clock().now()),
+    )?;
+    Ok(true)
+}
+
+/// Turns a draft document with a given `name` into a regular one. Returns `false` if there's no
+/// draft with that name.
+pub(crate) fn promote<'a, DB: DocOps<'a> + ?Sized>(db: &DB, name: &[u8]) -> Result<bool, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let (oid, flags) = match db.get_for_update(&key_oid(name))? {
+        Some(value) => oid_value(value.as_ref())?,
+        None => return Ok(false),
+    };
+    if flags & OID_FLAG_DRAFT == 0 {
+        return Ok(false);
+    }
+    // promoted document starts to count towards the usage of its tenant
+    usage::track(db, name, || {
+        set_oid_flags(db, name, oid, flags & !OID_FLAG_DRAFT)?;
+        db.remove(&key_meta(oid, META_DRAFT))?;
+        Ok(true)
+    })
+}
+
+/// Removes draft documents created at least `older_than` ago. Returns the number of removed
+/// drafts.
+pub(crate) fn purge<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    older_than: Duration,
+) -> Result<u32, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let cutoff = match db.clock().now().checked_sub(older_than.as_millis() as u64) {
+        Some(cutoff) => cutoff,
+        None => return Ok(0),
+    };
+    let start = Key::from_const([V1, KEYSPACE_OID]);
+    let end = Key::from_const([V1, KEYSPACE_DOC]);
+    let mut drafts = Vec::new();
+    for e in db.iter_range(&start, &end)? {
+        if e.key() >= end.as_ref() {
+            break;
+        }
+        if let Ok((oid, flags)) = oid_value(e.value()) {
+            if flags & OID_FLAG_DRAFT != 0 {
+                drafts.push((doc_oid_name(e.key()).to_vec(), oid));
+            }
+        }
+    }
+    let mut purged = 0;
+    for (name, oid) in drafts {
+        let created = match db.get(&key_meta(oid, META_DRAFT))? {
+            Some(value) => decode_timestamp(value.as_ref()).ok_or(Error::CorruptedValue)?,
+            None => 0,
+        };
+        if created > cutoff {
+            continue;
+        }
+        // draft may have been promoted by a concurrent transaction in the meantime
+        let still_draft = match db.get_for_update(&key_oid(&name))? {
+            Some(value) => {
+                let (current, flags) = oid_value(value.as_ref())?;
+                current == oid && flags & OID_FLAG_DRAFT != 0
+            }
+            None => false,
+        };
+        if still_draft {
+            refs::clear_doc(db, &name, RefPolicy::Nullify)?;
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
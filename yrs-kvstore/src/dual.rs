@@ -0,0 +1,284 @@
+//! Writing documents into two stores at once, i.e. to migrate them to another backend without
+//! downtime.
+//!
+//! [DualStore] wraps a primary and a secondary store. Every write is applied to both of them,
+//! while reads are served by the primary store only. Writes are driven by document names rather
+//! than raw keys: each store allocates OIDs and sequence numbers on its own, so these may differ
+//! between stores without affecting document contents, which is what
+//! [compare_stores](crate::verify::compare_stores) looks at.
+//!
+//! A failed write into the primary store is returned to the caller as usual. A failed write into
+//! the secondary store is not: instead a divergence marker (see
+//! [KEYSPACE_DIVERGED](crate::format::KEYSPACE_DIVERGED)) is written into the primary store for
+//! the affected document, so that [DualStore::repair] can later copy it over again:
+//!
+//! ```rust,ignore
+//! let dual = DualStore::new(
+//!     LmdbStore::from(lmdb_txn.bind(&handle)),
+//!     RocksDBStore::from(rocksdb.transaction()),
+//! );
+//! dual.push_update("my-doc-name", &update)?;
+//! let primary = dual.commit_secondary(|secondary| secondary.commit())?;
+//! drop(primary);
+//! lmdb_txn.commit()?;
+//! ```
+//!
+//! Both stores are committed separately: secondary first, so that a failed commit can still be
+//! recorded in the primary store. Once the secondary store has caught up, [DualStore::promote]
+//! swaps the roles, so that reads are served by the new backend while the old one keeps receiving
+//! writes until it's decommissioned.
+//!
+//! Document references (see [DocOps::insert_meta_ref]) and archives are not mirrored.
+
+use crate::error::Error;
+use crate::format::{KEYSPACE_DIVERGED, V1};
+use crate::keys::{key_diverged, parse_key, Key, ParsedKey};
+use crate::{ClearReport, DocOps, FlushOutcome, KVEntry, KVStore, PushReceipt};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use yrs::{Doc, ReadTxn, StateVector, Transact, TransactionMut};
+
+/// Wrapper applying writes to two stores at once. See [dual](crate::dual) module for details.
+pub struct DualStore<P, S> {
+    primary: P,
+    secondary: S,
+    /// Names of documents written through current store.
+    written: RefCell<BTreeSet<Box<[u8]>>>,
+}
+
+impl<P, S> DualStore<P, S> {
+    /// Creates a new dual store, serving reads from a `primary` store.
+    pub fn new(primary: P, secondary: S) -> Self {
+        DualStore {
+            primary,
+            secondary,
+            written: RefCell::new(BTreeSet::new()),
+        }
+    }
+
+    /// Returns the store which serves reads.
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    /// Returns the store which receives copies of all writes.
+    pub fn secondary(&self) -> &S {
+        &self.secondary
+    }
+
+    /// Returns names of all documents written through current store so far.
+    pub fn written_docs(&self) -> Vec<Box<[u8]>> {
+        self.written.borrow().iter().cloned().collect()
+    }
+
+    /// Swaps primary and secondary store. Divergence markers are kept in the old primary store,
+    /// so documents marked there should be repaired using [Self::repair] first.
+    pub fn promote(self) -> DualStore<S, P> {
+        DualStore {
+            primary: self.secondary,
+            secondary: self.primary,
+            written: self.written,
+        }
+    }
+
+    /// Unwraps primary and secondary store, i.e. in order to commit them.
+    pub fn into_inner(self) -> (P, S) {
+        (self.primary, self.secondary)
+    }
+}
+
+impl<'a, 'b, P, S> DualStore<P, S>
+where
+    P: DocOps<'a>,
+    S: DocOps<'b>,
+    Error: From<<P as KVStore<'a>>::Error> + From<<S as KVStore<'b>>::Error>,
+{
+    /// Inserts document state into both stores. See [DocOps::insert_doc].
+    pub fn insert_doc<K: AsRef<[u8]> + ?Sized, T: ReadTxn>(
+        &self,
+        name: &K,
+        txn: &T,
+    ) -> Result<(), Error> {
+        self.primary.insert_doc(name, txn)?;
+        self.mirror(name.as_ref(), self.secondary.insert_doc(name, txn))
+    }
+
+    /// Appends an update to both stores. Returns receipt of the primary store. See
+    /// [DocOps::push_update].
+    pub fn push_update<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        update: &[u8],
+    ) -> Result<PushReceipt, Error> {
+        let receipt = self.primary.push_update(name, update)?;
+        self.mirror(name.as_ref(), self.secondary.push_update(name, update))?;
+        Ok(receipt)
+    }
+
+    /// Merges pending updates of a document in both stores. Returns outcome of the primary
+    /// store. See [DocOps::flush_doc].
+    pub fn flush_doc<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<FlushOutcome>, Error> {
+        let outcome = self.primary.flush_doc(name)?;
+        self.mirror(name.as_ref(), self.secondary.flush_doc(name))?;
+        Ok(outcome)
+    }
+
+    /// Removes document from both stores. Returns report of the primary store. See
+    /// [DocOps::clear_doc].
+    pub fn clear_doc<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<ClearReport, Error> {
+        let report = self.primary.clear_doc(name)?;
+        self.mirror(name.as_ref(), self.secondary.clear_doc(name))?;
+        Ok(report)
+    }
+
+    /// Inserts metadata entry into both stores. See [DocOps::insert_meta].
+    pub fn insert_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        meta_key: &K2,
+        meta: &[u8],
+    ) -> Result<(), Error> {
+        self.primary.insert_meta(name, meta_key, meta)?;
+        let result = self.secondary.insert_meta(name, meta_key, meta);
+        self.mirror(name.as_ref(), result)
+    }
+
+    /// Removes metadata entry from both stores. See [DocOps::remove_meta].
+    pub fn remove_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        meta_key: &K2,
+    ) -> Result<(), Error> {
+        self.primary.remove_meta(name, meta_key)?;
+        let result = self.secondary.remove_meta(name, meta_key);
+        self.mirror(name.as_ref(), result)
+    }
+
+    /// Loads document from the primary store. See [DocOps::load_doc].
+    pub fn load_doc<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        txn: &mut TransactionMut,
+    ) -> Result<bool, Error> {
+        self.primary.load_doc(name, txn)
+    }
+
+    /// Returns state vector of a document from the primary store. See
+    /// [DocOps::get_state_vector].
+    pub fn get_state_vector<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<(Option<StateVector>, bool), Error> {
+        self.primary.get_state_vector(name)
+    }
+
+    /// Computes diff of a document from the primary store. See [DocOps::get_diff].
+    pub fn get_diff<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        sv: &StateVector,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.primary.get_diff(name, sv)
+    }
+
+    /// Returns metadata entry from the primary store. See [DocOps::get_meta].
+    pub fn get_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        meta_key: &K2,
+    ) -> Result<Option<<P as KVStore<'a>>::Return>, Error> {
+        self.primary.get_meta(name, meta_key)
+    }
+
+    /// Returns names of documents marked as diverged in the primary store, in ascending order.
+    pub fn diverged_docs(&self) -> Result<Vec<Box<[u8]>>, Error> {
+        diverged_docs(&self.primary)
+    }
+
+    /// Copies every document marked as diverged from the primary into the secondary store,
+    /// replacing whatever secondary store has under its name, and removes its marker. Documents,
+    /// which don't exist in the primary store anymore, are removed from the secondary store.
+    /// Returns the number of repaired documents.
+    ///
+    /// Unlike regular writes, failures of the secondary store are returned, leaving markers of
+    /// all documents which haven't been repaired yet in place.
+    pub fn repair(&self) -> Result<u32, Error> {
+        let mut repaired = 0;
+        for name in self.diverged_docs()? {
+            self.copy_doc(&name)?;
+            self.primary.remove(&key_diverged(&name))?;
+            repaired += 1;
+        }
+        Ok(repaired)
+    }
+
+    /// Commits the secondary store using a given `commit` function and returns the primary store,
+    /// which should be committed afterwards. If `commit` fails, all documents written through
+    /// current store are marked as diverged in the primary store, so that committing it records
+    /// them for [Self::repair].
+    pub fn commit_secondary<F, E>(self, commit: F) -> Result<P, Error>
+    where
+        F: FnOnce(S) -> Result<(), E>,
+    {
+        if commit(self.secondary).is_err() {
+            for name in self.written.into_inner() {
+                mark_diverged(&self.primary, &name)?;
+            }
+        }
+        Ok(self.primary)
+    }
+
+    fn mirror<T>(&self, name: &[u8], result: Result<T, Error>) -> Result<(), Error> {
+        self.written.borrow_mut().insert(name.into());
+        if result.is_err() {
+            mark_diverged(&self.primary, name)?;
+        }
+        Ok(())
+    }
+
+    fn copy_doc(&self, name: &[u8]) -> Result<(), Error> {
+        let doc = Doc::new();
+        let exists = self.primary.load_doc(name, &mut doc.transact_mut())?;
+        self.secondary.clear_doc(name)?;
+        if exists {
+            self.secondary.insert_doc(name, &doc.transact())?;
+        }
+        for (key, value) in self.primary.iter_meta(name)? {
+            // reserved entries are maintained by the secondary store itself
+            if !key.starts_with(b"$") {
+                self.secondary.insert_meta(name, &key, value.as_ref())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Marks document with a given `name` as diverged in a primary store `db`, i.e. when its
+/// secondary store has been committed manually and the commit has failed.
+pub fn mark_diverged<'a, DB: DocOps<'a>>(db: &DB, name: &[u8]) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    db.upsert(&key_diverged(name), &[])?;
+    Ok(())
+}
+
+/// Returns names of all documents marked as diverged in a given store, in ascending order.
+pub fn diverged_docs<'a, DB: DocOps<'a>>(db: &DB) -> Result<Vec<Box<[u8]>>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let start = Key::from_const([V1, KEYSPACE_DIVERGED]);
+    let end = Key::from_const([V1, KEYSPACE_DIVERGED + 1]);
+    let mut names = Vec::new();
+    for e in db.iter_range(&start, &end)? {
+        match parse_key(e.key()) {
+            Some(ParsedKey::Diverged { doc_name }) => names.push(doc_name.into()),
+            _ => break,
+        }
+    }
+    Ok(names)
+}
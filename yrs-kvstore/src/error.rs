@@ -1 +1,204 @@
-pub type Error = Box<dyn std::error::Error>;
+use crate::blob::BlobId;
+use crate::client_ids::CompactionRefusal;
+use crate::config::RejectReason;
+use crate::deadline::Progress;
+use crate::guard::ClearRefusal;
+use yrs::StateVector;
+
+/// Error type returned by [DocOps](crate::DocOps) methods.
+///
+/// Errors returned by the underlying key-value store or by Yrs encoding layer are wrapped into
+/// [Error::Other] variant (see [Error::other]), except for stored document states and updates
+/// which could not be decoded: these are reported with [Error::DocStateDecode] and
+/// [Error::UpdateDecode], together with the document they belong to. Remaining variants describe
+/// conditions specific to yrs-kvstore itself. New variants may be added in the future, so matches
+/// over this type need a wildcard arm.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Requested document has been moved into an archive using
+    /// [DocOps::archive_doc](crate::DocOps::archive_doc). It must be restored with
+    /// [DocOps::unarchive_doc](crate::DocOps::unarchive_doc) before it can be used again.
+    #[error("document is archived")]
+    DocArchived,
+    /// Write was refused, because it would make the document exceed its storage quota. `used` is
+    /// the number of bytes currently occupied by document state and its pending updates, while
+    /// `flushable` is the part of it taken by pending updates: these are going to be merged into
+    /// document state by [DocOps::flush_doc](crate::DocOps::flush_doc), which usually recovers
+    /// most of that space.
+    #[error(
+        "document quota exceeded: {used} of {limit} bytes used ({flushable} bytes can be recovered by flush)"
+    )]
+    QuotaExceeded {
+        used: u64,
+        limit: u64,
+        flushable: u64,
+    },
+    /// Write was refused, because the document doesn't exist and
+    /// [StoreConfig::create_policy](crate::config::StoreConfig::create_policy) doesn't allow to
+    /// create it.
+    #[error("document not found")]
+    DocNotFound,
+    /// Name of a new document is longer than
+    /// [StoreConfig::max_name_len](crate::config::StoreConfig::max_name_len).
+    #[error("document name is {len} bytes long, while at most {limit} bytes are allowed")]
+    InvalidDocName { len: usize, limit: usize },
+    /// Value read from the store failed checksum verification or its framing is malformed. See
+    /// [ValueCodec](crate::config::ValueCodec).
+    #[error("stored value is corrupted")]
+    CorruptedValue,
+    /// Update was refused, because its `clock` is not greater than the clock of the update
+    /// preceding it. See [DocOps::import_updates](crate::DocOps::import_updates).
+    #[error("update clock {clock} must be greater than preceding update clock {last_clock}")]
+    ClockConflict { clock: u32, last_clock: u32 },
+    /// Update was refused by [UpdateValidator](crate::config::UpdateValidator) installed in the
+    /// store.
+    #[error("update rejected: {0}")]
+    UpdateRejected(RejectReason),
+    /// Past state of the document cannot be reconstructed, because its stored state has been
+    /// written with garbage collection enabled. See
+    /// [DocOps::encode_state_from_snapshot](crate::DocOps::encode_state_from_snapshot).
+    #[error("document history has been garbage collected")]
+    HistoryUnavailable,
+    /// Document cannot be cleared, because other documents refer to it and
+    /// [RefPolicy::Refuse](crate::refs::RefPolicy::Refuse) has been requested. See
+    /// [DocOps::clear_doc_with](crate::DocOps::clear_doc_with).
+    #[error("document is referenced by other documents")]
+    DocReferenced,
+    /// Operation has been interrupted, because it took longer than
+    /// [StoreConfig::op_deadline](crate::config::StoreConfig::op_deadline). `progress` describes
+    /// how far it got. Writes made before the interruption leave the store in a consistent state
+    /// and should be committed, so that the next call can resume from them. See
+    /// [deadline](crate::deadline) module.
+    #[error("operation deadline exceeded: {progress}")]
+    DeadlineExceeded { progress: Progress },
+    /// Archive file read by [import_store](crate::archive::import_store) is malformed, failed
+    /// checksum verification or has been written by an unsupported version of the format.
+    #[error("invalid archive: {0}")]
+    InvalidArchive(&'static str),
+    /// Content passed to [DocOps::replace_doc](crate::DocOps::replace_doc) or a document passed to
+    /// [DocOps::compact_client_ids](crate::DocOps::compact_client_ids) contains a shared type,
+    /// which cannot be copied (i.e. an XML type, a subdocument or a root type which has never
+    /// been accessed with its type). Contains the name of the root type, map key or array index
+    /// under which it's stored.
+    #[error("content of '{0}' cannot be copied")]
+    UnsupportedContent(String),
+    /// Binary state vector provided by the caller could not be decoded.
+    #[error("invalid state vector: {0}")]
+    InvalidStateVector(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// GUID of a document passed to
+    /// [DocOps::insert_doc_with_guid](crate::DocOps::insert_doc_with_guid) is already assigned to
+    /// another document, named `owner`.
+    #[error("GUID is already assigned to document '{}'", String::from_utf8_lossy(.owner))]
+    GuidConflict { owner: Vec<u8> },
+    /// OID passed to [DocOps::adopt_orphan](crate::DocOps::adopt_orphan) is not orphaned, because
+    /// it's already referenced by another document, named `owner`.
+    #[error("OID is already referenced by document '{}'", String::from_utf8_lossy(.owner))]
+    OidClaimed { owner: Vec<u8> },
+    /// Document has stored updates, which cannot be integrated because changes they depend on
+    /// (described by `missing` state vector) are missing from the store. Returned only when
+    /// [StoreConfig::strict_history](crate::config::StoreConfig::strict_history) is set. See
+    /// [DocOps::load_doc_checked](crate::DocOps::load_doc_checked).
+    #[error("document history is incomplete: stored updates depend on missing changes")]
+    IncompleteHistory { missing: StateVector },
+    /// Document has a stored state vector (`claimed`) describing changes missing from its stored
+    /// contents, because its document state is missing from the store. Returned only when
+    /// [StoreConfig::strict_history](crate::config::StoreConfig::strict_history) is set. See
+    /// [DocOps::repair_drop_state_vector](crate::DocOps::repair_drop_state_vector).
+    #[error("document state is missing: stored state vector claims changes which are not stored")]
+    MissingDocState { claimed: StateVector },
+    /// Write was refused, because the fencing token supplied by the writer is older than the
+    /// `current` one stored for the document. See [DocOps::set_fence](crate::DocOps::set_fence).
+    #[error("write fenced off: document fencing token has been advanced to {current}")]
+    Fenced { current: u64 },
+    /// Document state is stored as a blob with a given `id`, which doesn't exist or cannot be
+    /// read, because no blob store has been installed. See [blob](crate::blob).
+    #[error("blob {id} is unavailable")]
+    BlobUnavailable { id: BlobId },
+    /// Document has not been cleared by [DocOps::clear_doc_if](crate::DocOps::clear_doc_if),
+    /// because it doesn't meet the given guard for a given reason.
+    #[error("clear refused: {0}")]
+    ClearRefused(ClearRefusal),
+    /// Stored value is compressed, but yrs-kvstore has been built without the `compression`
+    /// feature.
+    #[error("value is compressed, but compression support is disabled")]
+    CompressionUnavailable,
+    /// Stored update of a document named `doc` could not be decoded. `seq` is the sequence number
+    /// of the update, or `None` for updates which don't have one assigned (i.e. updates of
+    /// channels other than the default one).
+    #[error(
+        "cannot decode update{} of document '{}': {source}",
+        seq_suffix(.seq),
+        String::from_utf8_lossy(.doc)
+    )]
+    UpdateDecode {
+        doc: Vec<u8>,
+        seq: Option<u32>,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// Stored state of a document named `doc` could not be decoded.
+    #[error("cannot decode state of document '{}': {source}", String::from_utf8_lossy(.doc))]
+    DocStateDecode {
+        doc: Vec<u8>,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// Client IDs of a document have not been compacted by
+    /// [DocOps::compact_client_ids](crate::DocOps::compact_client_ids), because its stored data
+    /// refers to them for a given reason.
+    #[error("compaction refused: {0}")]
+    CompactionRefused(CompactionRefusal),
+    /// Error returned by the key-value store implementation or by Yrs encoding/decoding layer.
+    #[error(transparent)]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl Error {
+    /// Fills in the name of the document, which stored value could not be decoded, unless it's
+    /// already known. Internal helpers working on OIDs leave it empty.
+    pub(crate) fn in_doc(mut self, name: &[u8]) -> Self {
+        match &mut self {
+            Error::UpdateDecode { doc, .. } | Error::DocStateDecode { doc, .. }
+                if doc.is_empty() =>
+            {
+                doc.extend_from_slice(name)
+            }
+            _ => {}
+        }
+        self
+    }
+
+    /// Wraps an error returned by the key-value store implementation or by a library used
+    /// together with it into [Error::Other].
+    pub fn other<E>(e: E) -> Self
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        Error::Other(e.into())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::other(e)
+    }
+}
+
+impl From<std::array::TryFromSliceError> for Error {
+    fn from(e: std::array::TryFromSliceError) -> Self {
+        Error::other(e)
+    }
+}
+
+impl From<yrs::encoding::read::Error> for Error {
+    fn from(e: yrs::encoding::read::Error) -> Self {
+        Error::other(e)
+    }
+}
+
+/// Formats the sequence number of an update reported by [Error::UpdateDecode].
+fn seq_suffix(seq: &Option<u32>) -> String {
+    match seq {
+        Some(seq) => format!(" {}", seq),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,115 @@
+//! Global feed of updates pushed to all documents of a store, ordered by the time of their
+//! writes, i.e. to tell which documents have been written to and how much, without scanning the
+//! update logs of every document.
+//!
+//! When [StoreConfig::update_feed](crate::config::StoreConfig::update_feed) is set,
+//! [DocOps::push_update](crate::DocOps::push_update) writes a small feed entry next to every
+//! update it stores: the time of the write, the name of the document, the sequence number and the
+//! length of the update. The update itself is not copied.
+//!
+//! ```rust,ignore
+//! let config = StoreConfig {
+//!     update_feed: true,
+//!     ..StoreConfig::DEFAULT
+//! };
+//! let db_txn = ConfiguredStore::new(RocksDBStore::from(db.transaction()), config);
+//! db_txn.push_update("my-doc-name", &update)?;
+//!
+//! let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+//! for entry in db_txn.iter_feed(hour_ago..SystemTime::now(), 1000)? {
+//!     println!("{:?}: {} bytes", entry.time, entry.len);
+//! }
+//! ```
+//!
+//! Entries are ordered by the time of their writes with millisecond precision. Entries written
+//! within the same millisecond are ordered by the OID of their document and the sequence number of
+//! their update. Feed entries are not removed together with their documents: old entries have to
+//! be removed with [DocOps::prune_feed](crate::DocOps::prune_feed). See [format](crate::format)
+//! for the layout of feed entries.
+
+use crate::clock;
+use crate::error::Error;
+use crate::keys::{key_feed, key_feed_start, parse_key, ParsedKey, OID};
+use crate::{DocOps, KVEntry, KVStore};
+use std::convert::TryInto;
+use std::ops::Range;
+use std::time::SystemTime;
+
+/// Update pushed to a document, returned by [DocOps::iter_feed](crate::DocOps::iter_feed).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedEntry {
+    /// Time of the write, with millisecond precision.
+    pub time: SystemTime,
+    /// Name of the document the update has been pushed to.
+    pub doc_name: Vec<u8>,
+    /// Sequence number of the update within the document, as returned by
+    /// [DocOps::push_update](crate::DocOps::push_update).
+    pub seq: u32,
+    /// Length of the update in bytes, as it has been pushed.
+    pub len: u32,
+}
+
+/// Records an update of a given `len`, stored under a given `seq` number by a document with
+/// a given `name` and `oid`.
+pub(crate) fn append<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    oid: OID,
+    seq: u32,
+    len: usize,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let time = db.clock().now();
+    let mut value = Vec::with_capacity(4 + name.len());
+    value.extend_from_slice(&(len as u32).to_be_bytes());
+    value.extend_from_slice(name);
+    db.upsert(&key_feed(time, oid, seq), &value)?;
+    Ok(())
+}
+
+/// Returns up to `limit` feed entries written within a given `time_range`.
+pub(crate) fn entries<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    time_range: Range<SystemTime>,
+    limit: usize,
+) -> Result<Vec<FeedEntry>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let start = key_feed_start(clock::millis(time_range.start));
+    let end = key_feed_start(clock::millis(time_range.end));
+    let mut entries = Vec::new();
+    for e in db.iter_range(&start, &end)? {
+        if entries.len() == limit || e.key() >= end.as_ref() {
+            break;
+        }
+        let (time, seq) = match parse_key(e.key()) {
+            Some(ParsedKey::Feed { time, seq, .. }) => (time, seq),
+            _ => return Err(Error::CorruptedValue),
+        };
+        let value = e.value();
+        if value.len() < 4 {
+            return Err(Error::CorruptedValue);
+        }
+        let (len, doc_name) = value.split_at(4);
+        entries.push(FeedEntry {
+            time: clock::system_time(time),
+            doc_name: doc_name.to_vec(),
+            seq,
+            len: u32::from_be_bytes(len.try_into().unwrap()),
+        });
+    }
+    Ok(entries)
+}
+
+/// Removes feed entries written before a given `cutoff` time. Returns the number of removed
+/// entries.
+pub(crate) fn prune<'a, DB: DocOps<'a> + ?Sized>(db: &DB, cutoff: SystemTime) -> Result<u32, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let removed = db.remove_range(&key_feed_start(0), &key_feed_start(clock::millis(cutoff)))?;
+    Ok(removed)
+}
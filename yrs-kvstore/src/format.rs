@@ -0,0 +1,446 @@
+//! Specification of the on-disk format used by [DocOps](crate::DocOps).
+//!
+//! The layout of keys and values described here is a stable contract: it can be read by tools
+//! written in other languages and it doesn't change between releases, unless [FORMAT_VERSION] is
+//! bumped. Every constant used to build keys and values is defined in this module, together with
+//! the encoding of [timestamps](self#timestamps). The [keys](crate::keys) module provides
+//! [parse_key](crate::keys::parse_key) and [build_key](crate::keys::build_key) helpers working on
+//! top of it.
+//!
+//! # Keys
+//!
+//! All numbers are stored in big endian format, so that their byte order matches their numeric
+//! order. Every key starts with [V1] byte followed by a key space tag:
+//!
+//! ```nocompile
+//! 00{doc_name:N}0      - OID index entry                   (KEYSPACE_OID)
+//! 01{oid:4}0           - document state                    (KEYSPACE_DOC, SUB_DOC)
+//! 01{oid:4}1           - document state vector             (KEYSPACE_DOC, SUB_STATE_VEC)
+//! 01{oid:4}2{clock:4}0 - document update                   (KEYSPACE_DOC, SUB_UPDATE)
+//! 01{oid:4}2{base:4}1  - document update segment           (KEYSPACE_DOC, SUB_UPDATE)
+//! 01{oid:4}3{name:M}0  - document metadata entry           (KEYSPACE_DOC, SUB_META)
+//! 01{oid:4}4           - document pending updates counter  (KEYSPACE_DOC, SUB_PENDING)
+//! 01{oid:4}5{label:M}0 - document snapshot                 (KEYSPACE_DOC, SUB_SNAPSHOT)
+//! 01{oid:4}6{name:M}0  - document reference entry          (KEYSPACE_DOC, SUB_REF)
+//! 01{oid:4}7           - document sync frames generation   (KEYSPACE_DOC, SUB_SYNC_GEN)
+//! 01{oid:4}8{ch:1}{clock:4}0 - document channel update     (KEYSPACE_DOC, SUB_CHANNEL)
+//! 01{oid:4}9{time:8}{n:4}0 - document audit record         (KEYSPACE_DOC, SUB_AUDIT)
+//! 01{oid:4}a           - document size history head        (KEYSPACE_DOC, SUB_SIZE_HISTORY)
+//! 01{oid:4}a{slot:1}   - document size sample              (KEYSPACE_DOC, SUB_SIZE_HISTORY)
+//! 02{oid:4}0           - archived document state           (KEYSPACE_ARCHIVE)
+//! 03{name:M}0          - store setting                     (KEYSPACE_SETTINGS)
+//! 04{path:M}0          - collection marker                 (KEYSPACE_COLLECTION)
+//! 05{oid:4}0           - intent log entry                  (KEYSPACE_INTENT)
+//! 06{oid:4}0           - referenced document name          (KEYSPACE_REF, REF_TARGET)
+//! 06{oid:4}1{src:4}{name:M}0 - inbound reference           (KEYSPACE_REF, REF_INBOUND)
+//! 07{oid:4}{gen:4}{hash:8}0 - cached sync frame            (KEYSPACE_SYNC)
+//! 08{ns:2}{key:N}      - entry of a namespaced store       (KEYSPACE_NAMESPACE)
+//! 09{doc_name:N}0      - divergence marker                 (KEYSPACE_DIVERGED)
+//! 0a{guid:N}0          - GUID index entry                  (KEYSPACE_GUID)
+//! 0b{tenant:N}0        - tenant usage counter              (KEYSPACE_USAGE)
+//! 0c{doc_name:N}0      - fence of a cleared document       (KEYSPACE_FENCE)
+//! 0d{time:8}{oid:4}{seq:4}0 - update feed entry             (KEYSPACE_FEED)
+//! ```
+//!
+//! Variable-length segments (document names, metadata keys, labels, setting names and collection
+//! paths) are stored as they are and followed by [TERMINATOR]. OIDs are [OID_LEN] bytes long and
+//! update clocks are [CLOCK_LEN] bytes long. All entries of a single document lie within
+//! `01{oid:4}0..=01{oid:4}{TERMINATOR_HI_WATERMARK}` range.
+//!
+//! Entries of logical stores sharing the same database (see [namespace](crate::namespace)) are
+//! stored within [KEYSPACE_NAMESPACE], under the namespace identifier (`ns`, [NAMESPACE_LEN] bytes)
+//! followed by a complete `key` of any of the entries above. Namespaced stores have their own OID
+//! index, settings and all other key spaces, so they never see each other's entries.
+//!
+//! Document names built with [DocKey](crate::keys::DocKey) from numeric identifiers start with
+//! a tag byte ([DOC_KEY_U64], [DOC_KEY_U64_PAIR]) followed by the numbers in big endian format.
+//! Tag bytes never occur in valid UTF-8 strings, so numeric names don't collide with string names
+//! and numeric names of the same kind are ordered by their numeric values.
+//!
+//! Document state and its state vector are always written in the same order: the state vector
+//! entry is removed first, then the document state is written, then the state vector is written
+//! back. Readers not isolated from writes in progress, which read the document state before its
+//! state vector, observe either a consistent pair or a missing state vector, never a state vector
+//! of a different document state. Readers which read the state vector first are not covered.
+//!
+//! # Timestamps
+//!
+//! All times stored in keys and values (`time` segments of keys, last modification and access
+//! times, expiration times etc.) are u64 numbers of milliseconds since UNIX epoch in big endian
+//! format, [TIMESTAMP_LEN] bytes long, so that keys prefixed with them are ordered by time. They
+//! are written with [encode_timestamp] and read with [decode_timestamp], and the current time is
+//! read from the [Clock](crate::clock::Clock) of the store.
+//!
+//! # Values
+//!
+//! - OID index entry: document OID ([OID_LEN] bytes), optionally followed by a single byte of
+//!   flags (see [OID_FLAG_ARCHIVED], [OID_FLAG_DRAFT]). Missing flags byte means no flags are set.
+//! - Document state, state vector and updates: lib0 v1 encoded Yrs document state, state vector
+//!   and updates respectively, framed using the [ValueCodec](crate::config::ValueCodec) the store
+//!   has been configured with. Raw codec (the default) stores them as they are. Any other codec
+//!   prepends a byte of flags ([CODEC_FLAG_ZSTD], [CODEC_FLAG_CRC32]) to the payload and, if
+//!   [CODEC_FLAG_CRC32] is set, appends [CRC32_LEN] bytes of CRC-32 (IEEE 802.3) checksum of the
+//!   payload.
+//! - Document update segment: a sequence of records, each one being the length of an update
+//!   framed using the [ValueCodec](crate::config::ValueCodec), stored as unsigned LEB128 varint,
+//!   followed by that framed update. Record `i` holds the update with sequence number `base + i`.
+//!   Segments are keyed like update entries, but with [UPDATE_SEGMENT] in place of the trailing
+//!   [TERMINATOR], so both kinds of entries are ordered by their sequence numbers and may coexist
+//!   within a single document. Segments are written only by stores configured with
+//!   [UpdateFraming::Segmented](crate::config::UpdateFraming::Segmented).
+//! - Document channel update: lib0 v1 encoded Yrs update framed like the document updates above.
+//!   Updates pushed to [DEFAULT_CHANNEL] are stored as document updates, so channel update entries
+//!   exist only for other channels (`ch`). Every channel numbers its updates separately. Channel
+//!   updates are never stored in segments and are not accounted by pending updates counter.
+//! - Document audit record: lib0 v1 encoded origin of the update (buffer), name of the root type
+//!   (string), lengths of inserted and deleted text (var u32 each) and an excerpt of inserted
+//!   text (string). Records are keyed by the `time` of the write, as an u64 number of
+//!   milliseconds since UNIX epoch, followed by the ordinal number `n` of the record among the
+//!   ones written within the same millisecond. See [audit](crate::audit).
+//! - Document size history head: number `n` of size samples written for the document as an u32
+//!   number in big endian format. Samples are stored in [SIZE_HISTORY_LEN] slots used as a ring
+//!   buffer: the next sample is stored in slot `n % SIZE_HISTORY_LEN`, overwriting the oldest one
+//!   once `n` reaches [SIZE_HISTORY_LEN]. From then on `n` is kept below `2 * SIZE_HISTORY_LEN`
+//!   by subtracting [SIZE_HISTORY_LEN] from it.
+//! - Document size sample: time of the flush, size in bytes of the document state value written
+//!   by the flush and total size in bytes of the pending updates stored right before the
+//!   flush, each one an u64 number in big endian format, [SIZE_SAMPLE_LEN] bytes in total. See
+//!   [size_history](crate::size_history).
+//! - State vector may be followed by [STATE_VEC_SEQ_MARKER] byte and [CLOCK_LEN] bytes of the
+//!   sequence number of the last pending update it covers. Readers decoding only the lib0 v1 state
+//!   vector ignore these trailing bytes. State vectors written together with document state never
+//!   have the marker.
+//! - Metadata entry: user-provided bytes. Metadata keys starting with `$` are reserved (see
+//!   [META_MAX_DOC_BYTES], [META_LAST_MODIFIED], [META_GC], [META_DOC_OPTIONS], [META_SPLIT_IDS],
+//!   [META_ACCESS], [META_GUID], [META_INITIALIZED], [META_FENCE], [META_DRAFT],
+//!   [META_CLIENT_IDS]).
+//! - Pending updates counter: number of updates (u32) followed by their total size in bytes (u64),
+//!   [PENDING_LEN] bytes in total.
+//! - Snapshot: lib0 v1 encoded Yrs snapshot.
+//! - Document reference entry: OID of the referenced document ([OID_LEN] bytes).
+//! - Referenced document name: name of the document, that has inbound references.
+//! - Inbound reference: name of the referring document (`src`), which stores the reference under
+//!   a reference entry `name`.
+//! - Sync frames generation: u32 number in big endian format, incremented by every write
+//!   modifying document contents. Present only for documents which had sync frames cached.
+//! - Cached sync frame: expiration time as an u64 number of milliseconds since UNIX epoch in big
+//!   endian format, followed by the frame bytes. Frames are stored under the generation (`gen`)
+//!   current at the time they were cached and the hash of the remote state vector (`hash`) they
+//!   were computed for. See [sync_cache](crate::sync_cache).
+//! - Archived document state: zstd-compressed lib0 v1 encoded document state.
+//! - Store settings: see [SETTING_LAST_OID] and [SETTING_MAX_DOC_BYTES].
+//! - Collection marker: empty.
+//! - Intent log entry: operation tag ([INTENT_FLUSH], [INTENT_CLEAR]) followed by its arguments.
+//! - Divergence marker: empty. Written by [DualStore](crate::dual::DualStore) into its primary
+//!   store for documents, which writes into the secondary store have failed.
+//! - GUID index entry: name of the document, which GUID is `guid` (see [META_GUID]).
+//! - Update feed entry: length in bytes of the pushed update as an u32 number in big endian
+//!   format, followed by the name of the document. Entries are keyed by the `time` of the push,
+//!   as an u64 number of milliseconds since UNIX epoch, followed by the OID and the sequence
+//!   number (`seq`) of the update. See [feed](crate::feed).
+//! - Tenant usage counter: total size in bytes of documents of a tenant as an u64 number in big
+//!   endian format. See [usage](crate::usage).
+//! - Fence of a cleared document: fencing token of the document (see [META_FENCE]) kept after it
+//!   has been cleared.
+//!
+//! Document state may be stored outside of the store as a blob (see [blob](crate::blob)), in which
+//! case its entry holds a pointer record, [BLOB_POINTER_LEN] bytes in total: [BLOB_POINTER_TAG]
+//! byte, [BLOB_ID_LEN] bytes of the blob identifier, length of the blob as an u64 number and
+//! CRC-32 checksum of the blob as an u32 number. The blob holds the value, which would have been
+//! stored in the entry otherwise.
+
+use std::convert::TryInto;
+
+/// Version of the format described by this module. Keys of all versions are prefixed with [V1]
+/// byte.
+///
+/// Version 2 added channel update entries ([SUB_CHANNEL]). Stores which have never received
+/// updates on channels other than [DEFAULT_CHANNEL] are laid out exactly as in version 1, but
+/// readers of version 1 don't see the contents of channel updates.
+///
+/// Version 3 added [KEYSPACE_NAMESPACE]. Entries of stores which don't use namespaces are laid out
+/// exactly as in version 2.
+///
+/// Version 4 added [KEYSPACE_DIVERGED], which is written only by stores used as a primary of
+/// [DualStore](crate::dual::DualStore).
+///
+/// Version 5 added [KEYSPACE_GUID] and [META_GUID].
+///
+/// Version 6 added audit records ([SUB_AUDIT]), which are written only by stores with
+/// [StoreConfig::audit](crate::config::StoreConfig::audit) enabled.
+///
+/// Version 7 added [KEYSPACE_USAGE], which is written only by stores with a
+/// [TenantExtractor](crate::usage::TenantExtractor) installed.
+///
+/// Version 8 added [KEYSPACE_FENCE], which is written only when a document fenced with
+/// [DocOps::set_fence](crate::DocOps::set_fence) is cleared.
+///
+/// Version 9 added blob pointer records in place of document states, which are written only by
+/// stores with a [BlobStore](crate::blob::BlobStore) installed.
+///
+/// Version 10 added [KEYSPACE_FEED], which is written only by stores with
+/// [StoreConfig::update_feed](crate::config::StoreConfig::update_feed) enabled.
+///
+/// Version 11 added [OID_FLAG_DRAFT] and [META_DRAFT], which are written only for documents
+/// created with [DocOps::create_draft](crate::DocOps::create_draft).
+///
+/// Version 12 added size history entries ([SUB_SIZE_HISTORY]), which are written only by stores
+/// with [StoreConfig::size_history](crate::config::StoreConfig::size_history) enabled.
+pub const FORMAT_VERSION: u32 = 12;
+
+/// Prefix byte used for all of the yrs-kvstore entries.
+pub const V1: u8 = 0;
+
+/// Prefix byte used for document name -> OID mapping index key space.
+pub const KEYSPACE_OID: u8 = 0;
+
+/// Prefix byte used for document key space.
+pub const KEYSPACE_DOC: u8 = 1;
+
+/// Prefix byte used for archived documents key space.
+pub const KEYSPACE_ARCHIVE: u8 = 2;
+
+/// Prefix byte used for store-wide settings key space.
+pub const KEYSPACE_SETTINGS: u8 = 3;
+
+/// Prefix byte used for collections key space.
+pub const KEYSPACE_COLLECTION: u8 = 4;
+
+/// Prefix byte used for intent log key space.
+pub const KEYSPACE_INTENT: u8 = 5;
+
+/// Prefix byte used for inbound references index key space.
+pub const KEYSPACE_REF: u8 = 6;
+
+/// Prefix byte used for cached sync frames key space.
+pub const KEYSPACE_SYNC: u8 = 7;
+
+/// Prefix byte used for key space of namespaced stores.
+pub const KEYSPACE_NAMESPACE: u8 = 8;
+
+/// Prefix byte used for divergence markers key space.
+pub const KEYSPACE_DIVERGED: u8 = 9;
+
+/// Prefix byte used for GUID index key space.
+pub const KEYSPACE_GUID: u8 = 10;
+
+/// Prefix byte used for tenant usage counters key space.
+pub const KEYSPACE_USAGE: u8 = 11;
+
+/// Prefix byte used for fencing tokens of cleared documents key space.
+pub const KEYSPACE_FENCE: u8 = 12;
+
+/// Prefix byte used for global update feed key space.
+pub const KEYSPACE_FEED: u8 = 13;
+
+/// Tag byte within [KEYSPACE_DOC] used to identify document's state entry.
+pub const SUB_DOC: u8 = 0;
+
+/// Tag byte within [KEYSPACE_DOC] used to identify document's state vector entry.
+pub const SUB_STATE_VEC: u8 = 1;
+
+/// Tag byte within [KEYSPACE_DOC] used to identify document's update entries.
+pub const SUB_UPDATE: u8 = 2;
+
+/// Trailing byte of [SUB_UPDATE] keys, which identifies entries storing a segment of update
+/// records instead of a single update.
+pub const UPDATE_SEGMENT: u8 = 1;
+
+/// Tag byte within [KEYSPACE_DOC] used to identify document's metadata entries.
+pub const SUB_META: u8 = 3;
+
+/// Tag byte within [KEYSPACE_DOC] used to identify document's counter of pending updates, that
+/// have not been merged into document state yet.
+pub const SUB_PENDING: u8 = 4;
+
+/// Tag byte within [KEYSPACE_DOC] used to identify document's snapshot entries.
+pub const SUB_SNAPSHOT: u8 = 5;
+
+/// Tag byte within [KEYSPACE_DOC] used to identify document's reference entries, pointing at
+/// other documents (see [DocOps::insert_meta_ref](crate::DocOps::insert_meta_ref)).
+pub const SUB_REF: u8 = 6;
+
+/// Tag byte within [KEYSPACE_DOC] used to identify document's generation counter of cached sync
+/// frames (see [sync_cache](crate::sync_cache)).
+pub const SUB_SYNC_GEN: u8 = 7;
+
+/// Tag byte within [KEYSPACE_DOC] used to identify document's update entries pushed to channels
+/// other than [DEFAULT_CHANNEL] (see
+/// [DocOps::push_update_channel](crate::DocOps::push_update_channel)).
+pub const SUB_CHANNEL: u8 = 8;
+
+/// Tag byte within [KEYSPACE_DOC] used to identify document's audit records (see
+/// [audit](crate::audit)).
+pub const SUB_AUDIT: u8 = 9;
+
+/// Tag byte within [KEYSPACE_DOC] used to identify document's size history entries (see
+/// [size_history](crate::size_history)).
+pub const SUB_SIZE_HISTORY: u8 = 10;
+
+/// Number of slots of a document size history. Once all of them are taken, every new sample
+/// overwrites the oldest one.
+pub const SIZE_HISTORY_LEN: u8 = 64;
+
+/// Length in bytes of a document size sample value.
+pub const SIZE_SAMPLE_LEN: usize = 3 * 8;
+
+/// Update channel, which updates are stored as regular document updates ([SUB_UPDATE]).
+pub const DEFAULT_CHANNEL: u8 = 0;
+
+/// Tag byte within [KEYSPACE_REF] used to identify the entry storing a name of referenced
+/// document.
+pub const REF_TARGET: u8 = 0;
+
+/// Tag byte within [KEYSPACE_REF] used to identify inbound reference entries of a document.
+pub const REF_INBOUND: u8 = 1;
+
+/// Byte terminating keys and variable-length key segments.
+pub const TERMINATOR: u8 = 0;
+
+/// Byte greater than any tag within [KEYSPACE_DOC], used as an upper bound of document entries.
+pub const TERMINATOR_HI_WATERMARK: u8 = 255;
+
+/// Tag byte prefixing document names built from a single u64 identifier, followed by 8 bytes of
+/// the identifier in big endian format.
+pub const DOC_KEY_U64: u8 = 0xfe;
+
+/// Tag byte prefixing document names built from a pair of u64 identifiers (e.g. workspace and
+/// document id), followed by 16 bytes of both identifiers in big endian format.
+pub const DOC_KEY_U64_PAIR: u8 = 0xfd;
+
+/// Length (in bytes) of document OIDs.
+pub const OID_LEN: usize = 4;
+
+/// Length (in bytes) of update clocks (sequence numbers).
+pub const CLOCK_LEN: usize = 4;
+
+/// Length (in bytes) of namespace identifiers within [KEYSPACE_NAMESPACE].
+pub const NAMESPACE_LEN: usize = 2;
+
+/// Reserved metadata key used to store per-document storage quota, overriding the store-wide
+/// [SETTING_MAX_DOC_BYTES]. Value is an u64 number of bytes in big endian format.
+///
+/// Metadata keys starting with `$` are reserved for yrs-kvstore internal use.
+pub const META_MAX_DOC_BYTES: &[u8] = b"$max_doc_bytes";
+
+/// Reserved document meta key used to store the time of the last write modifying document contents,
+/// when [StoreConfig::timestamps](crate::config::StoreConfig::timestamps) are enabled. Value is an
+/// u64 number of milliseconds since UNIX epoch in big endian format.
+pub const META_LAST_MODIFIED: &[u8] = b"$last_modified";
+
+/// Reserved document meta key marking that stored document state has been written with garbage
+/// collection enabled, so it no longer contains deleted content needed to reconstruct past states
+/// of the document.
+pub const META_GC: &[u8] = b"$gc";
+
+/// Reserved document meta key used to store options of the most recent flush of the document,
+/// which are reused by flushes and reads that don't specify options explicitly. Value is a single
+/// byte of flags (see [DOC_OPTION_SKIP_GC]). Missing entry means default options.
+pub const META_DOC_OPTIONS: &[u8] = b"$options";
+
+/// Flag stored in [META_DOC_OPTIONS] entry value, marking that document has been flushed with
+/// `skip_gc` option set.
+pub const DOC_OPTION_SKIP_GC: u8 = 0b0000_0001;
+
+/// Reserved document meta key used by parts of split documents (see [split](crate::split)) to
+/// store ranges of block IDs routed into them. Value uses the same encoding as lib0 v1 delete
+/// sets: number of clients followed by client ids, each with the number of ranges followed by
+/// their start clocks and lengths, all written as variable length integers.
+pub const META_SPLIT_IDS: &[u8] = b"$split_ids";
+
+/// Reserved document meta key used to store access statistics of the document, recorded when
+/// [StoreConfig::access_sample_rate](crate::config::StoreConfig::access_sample_rate) is set. Value
+/// is an u64 access count followed by an u64 number of milliseconds since UNIX epoch of the last
+/// access, both in big endian format.
+pub const META_ACCESS: &[u8] = b"$access";
+
+/// Reserved document meta key used to store GUID of the Yrs document, recorded the first time it
+/// has been persisted with [DocOps::insert_doc_with_guid](crate::DocOps::insert_doc_with_guid).
+/// Value is the GUID string. Every GUID is also indexed under [KEYSPACE_GUID].
+pub const META_GUID: &[u8] = b"$guid";
+
+/// Reserved document meta key marking that document metadata has been initialized by
+/// [DocOps::init_meta_once](crate::DocOps::init_meta_once). Value is empty.
+pub const META_INITIALIZED: &[u8] = b"$initialized";
+
+/// Reserved document meta key used to store the fencing token of the document, set by
+/// [DocOps::set_fence](crate::DocOps::set_fence). Value is an u64 number in big endian format.
+pub const META_FENCE: &[u8] = b"$fence";
+
+/// Reserved document meta key used to store the creation time of a draft document (see
+/// [OID_FLAG_DRAFT]). Value is an u64 number of milliseconds since UNIX epoch in big endian
+/// format. Removed once the draft is promoted.
+pub const META_DRAFT: &[u8] = b"$draft";
+
+/// Reserved document meta key used to record the most recent compaction of client IDs of the
+/// document (see [client_ids](crate::client_ids)). Value is the new client ID written as
+/// a variable length integer, followed by the lib0 v1 encoded state vector of the document before
+/// compaction.
+pub const META_CLIENT_IDS: &[u8] = b"$client_ids";
+
+/// Settings entry used to store store-wide per-document storage quota. Value is an u64 number of
+/// bytes in big endian format.
+pub const SETTING_MAX_DOC_BYTES: &[u8] = b"max_doc_bytes";
+
+/// Settings entry used to store the most recently allocated document OID. Value is an OID in big
+/// endian format.
+pub const SETTING_LAST_OID: &[u8] = b"last_oid";
+
+/// Flag stored next to OID in [KEYSPACE_OID] entry value, marking that document contents have
+/// been moved into [KEYSPACE_ARCHIVE].
+pub const OID_FLAG_ARCHIVED: u8 = 0b0000_0001;
+
+/// Flag stored next to OID in [KEYSPACE_OID] entry value, marking a draft document created with
+/// [DocOps::create_draft](crate::DocOps::create_draft), which hasn't been promoted yet.
+pub const OID_FLAG_DRAFT: u8 = 0b0000_0010;
+
+/// Byte following lib0 v1 encoded state vector in a state vector entry, when the entry records the
+/// sequence number of the last pending update covered by the state vector (see
+/// [DocOps::state_vector_force](crate::DocOps::state_vector_force)). It's followed by
+/// [CLOCK_LEN] bytes of the sequence number.
+pub const STATE_VEC_SEQ_MARKER: u8 = 1;
+
+/// Length (in bytes) of pending updates counter value.
+pub const PENDING_LEN: usize = 12;
+
+/// Codec flag set on values compressed with zstd.
+pub const CODEC_FLAG_ZSTD: u8 = 0b0000_0001;
+
+/// Codec flag set on values suffixed with CRC-32 checksum.
+pub const CODEC_FLAG_CRC32: u8 = 0b0000_0010;
+
+/// First byte of pointer records stored in place of document states written as blobs. It's not
+/// a valid byte of [ValueCodec](crate::config::ValueCodec) flags.
+pub const BLOB_POINTER_TAG: u8 = 0xff;
+
+/// Length (in bytes) of blob identifiers.
+pub const BLOB_ID_LEN: usize = 16;
+
+/// Length (in bytes) of pointer records: tag byte, blob identifier, blob length and checksum.
+pub const BLOB_POINTER_LEN: usize = 1 + BLOB_ID_LEN + 8 + CRC32_LEN;
+
+/// Length (in bytes) of CRC-32 checksum appended to values by codecs with [CODEC_FLAG_CRC32] set.
+pub const CRC32_LEN: usize = 4;
+
+/// Intent log operation tag of interrupted [DocOps::flush_doc](crate::DocOps::flush_doc). It's
+/// followed by a single byte, which is non-zero if garbage collection was disabled.
+pub const INTENT_FLUSH: u8 = 0;
+
+/// Intent log operation tag of interrupted [DocOps::clear_doc](crate::DocOps::clear_doc). It's
+/// followed by the name of the cleared document.
+pub const INTENT_CLEAR: u8 = 1;
+
+/// Length (in bytes) of timestamps stored in keys and values.
+pub const TIMESTAMP_LEN: usize = 8;
+
+/// Encodes a timestamp - a number of milliseconds since UNIX epoch - the way it's stored in keys
+/// and values. See [Timestamps](self#timestamps).
+pub fn encode_timestamp(millis: u64) -> [u8; TIMESTAMP_LEN] {
+    millis.to_be_bytes()
+}
+
+/// Decodes a timestamp written with [encode_timestamp]. Returns `None` if `bytes` are not
+/// [TIMESTAMP_LEN] bytes long.
+pub fn decode_timestamp(bytes: &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
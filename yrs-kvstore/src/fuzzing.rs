@@ -0,0 +1,134 @@
+//! Entry points of fuzz targets, which feed arbitrary bytes into the parsers of keys and values
+//! read from the store. Stores shared with other processes or restored from untrusted backups may
+//! contain any bytes, so none of these parsers may panic on their input.
+//!
+//! Targets are defined in the `fuzz` directory of this crate and run with
+//! [cargo-fuzz](https://github.com/rust-fuzz/cargo-fuzz):
+//!
+//! ```sh
+//! cd yrs-kvstore
+//! cargo +nightly fuzz run keys
+//! ```
+//!
+//! Every function panics when a given input breaks the invariants of the parser it exercises, so
+//! that the fuzzer reports it. This module is available only with `fuzzing` feature enabled.
+
+use crate::config::{Compression, ValueCodec};
+use crate::format::STATE_VEC_SEQ_MARKER;
+use crate::keys::{
+    build_key, doc_meta_name, doc_oid_name, key_oid_part, key_update, key_update_segment,
+    parse_key, split_namespace,
+};
+use crate::{decode_state_vector, segment, update_clock};
+use yrs::updates::encoder::Encode;
+
+/// Codecs used by [value_codec]: every combination of compression and checksums.
+const CODECS: [ValueCodec; 4] = [
+    ValueCodec::RAW,
+    ValueCodec {
+        compression: Compression::None,
+        checksum: true,
+    },
+    ValueCodec {
+        compression: Compression::Zstd {
+            level: 1,
+            min_size: 0,
+        },
+        checksum: false,
+    },
+    ValueCodec {
+        compression: Compression::Zstd {
+            level: 1,
+            min_size: 0,
+        },
+        checksum: true,
+    },
+];
+
+/// Parses a given `key` with [parse_key] and [split_namespace], and slices it with the helpers
+/// reading names and clocks of known entries. Keys recognized by [parse_key] must be rebuilt
+/// unchanged by [build_key].
+pub fn keys(key: &[u8]) {
+    if let Some(parsed) = parse_key(key) {
+        assert_eq!(
+            build_key(&parsed),
+            key,
+            "{:?} is not rebuilt unchanged",
+            parsed
+        );
+    }
+    if let Some((_, key)) = split_namespace(key) {
+        if let Some(parsed) = parse_key(key) {
+            assert_eq!(
+                build_key(&parsed),
+                key,
+                "{:?} is not rebuilt unchanged",
+                parsed
+            );
+        }
+    }
+    assert!(doc_oid_name(key).len() <= key.len());
+    assert!(doc_meta_name(key).len() <= key.len());
+    key_oid_part(key);
+    update_clock(key);
+}
+
+/// Decodes a given state vector entry `value`. Decoded entries must be decoded the same after
+/// being encoded again.
+pub fn state_vector(value: &[u8]) {
+    if let Ok((sv, seq)) = decode_state_vector(value) {
+        let mut encoded = sv.encode_v1();
+        if let Some(seq) = seq {
+            encoded.push(STATE_VEC_SEQ_MARKER);
+            encoded.extend_from_slice(&seq.to_be_bytes());
+        }
+        let (decoded, decoded_seq) =
+            decode_state_vector(&encoded).expect("re-encoded state vector entry is malformed");
+        assert_eq!(decoded, sv);
+        assert_eq!(decoded_seq, seq);
+    }
+}
+
+/// Decodes a given stored `value` with every [ValueCodec] configuration. Decoded values must be
+/// decoded the same after being encoded again.
+pub fn value_codec(value: &[u8]) {
+    for codec in CODECS.iter() {
+        if let Ok(decoded) = codec.decode(value) {
+            let encoded = codec
+                .encode(&decoded)
+                .expect("decoded value cannot be encoded");
+            let decoded_again = codec
+                .decode(&encoded)
+                .expect("re-encoded value is malformed");
+            assert_eq!(decoded_again, decoded);
+        }
+    }
+}
+
+/// Reads records of a given update entry `value`, both as an individual update entry and as an
+/// update segment. Records must be numbered consecutively and contained within the value.
+pub fn update_entry(value: &[u8]) {
+    let base = 7;
+    let keys = [key_update(1, base), key_update_segment(1, base)];
+    for key in keys.iter() {
+        let mut seq = base;
+        let mut read = 0;
+        for record in segment::records(key, value) {
+            let (record_seq, update) = match record {
+                Ok(record) => record,
+                Err(_) => break,
+            };
+            assert_eq!(record_seq, seq);
+            seq += 1;
+            read += if segment::is_segment(key) {
+                segment::record_len(update.len())
+            } else {
+                update.len()
+            };
+        }
+        assert!(read <= value.len());
+        if let Ok((last_seq, _)) = segment::last_record(key, value) {
+            assert_eq!(last_seq, seq - 1);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Guards protecting documents from being cleared by mistake.
+//!
+//! [DocOps::clear_doc](crate::DocOps::clear_doc) removes a document no matter what it contains.
+//! Code paths meant to remove i.e. empty placeholder documents can use
+//! [DocOps::clear_doc_if](crate::DocOps::clear_doc_if) instead, which clears a document only if
+//! a given [ClearGuard] holds, and fails with [Error::ClearRefused] otherwise:
+//!
+//! ```rust,ignore
+//! let version = db_txn.doc_version("my-doc-name")?.unwrap();
+//! // ... user confirms removal of the document they have seen ...
+//! db_txn.clear_doc_if("my-doc-name", ClearGuard::Version(version))?;
+//! ```
+//!
+//! The guard is checked within the same transaction that clears the document. Entries it depends
+//! on are read using [KVStore::get_for_update], so that writes of concurrent transactions to the
+//! same document conflict with the guarded clear, instead of slipping in between the check and
+//! the removal.
+
+use crate::blob;
+use crate::error::Error;
+use crate::keys::{key_archive, key_doc, key_meta, OID};
+use crate::version::{self, DocVersion};
+use crate::{has_contents, lock_pending, DocOps, KVStore};
+use std::fmt::{Display, Formatter};
+
+/// Condition, which a document must meet in order to be cleared by
+/// [DocOps::clear_doc_if](crate::DocOps::clear_doc_if).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClearGuard {
+    /// Document must have neither state nor updates stored. Metadata entries are not taken into
+    /// account, so documents which have only metadata stored are considered empty.
+    Empty,
+    /// Document state and pending updates must take at most a given number of bytes, as they are
+    /// accounted by storage quotas. Archived documents are measured by the size of their archived
+    /// state.
+    MaxBytes(u64),
+    /// Document must have a metadata entry stored under a given key.
+    MetaFlag(Vec<u8>),
+    /// Document must be at a given version, i.e. the one read by
+    /// [DocOps::doc_version](crate::DocOps::doc_version) before deciding to clear it.
+    Version(DocVersion),
+}
+
+/// Reason of refusing to clear a document, reported with [Error::ClearRefused].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClearRefusal {
+    /// Document has state or updates stored, see [ClearGuard::Empty].
+    NotEmpty,
+    /// Document takes `bytes`, more than the `limit` given by [ClearGuard::MaxBytes].
+    TooLarge { bytes: u64, limit: u64 },
+    /// Document doesn't have the metadata entry required by [ClearGuard::MetaFlag].
+    MissingFlag,
+    /// Document has been changed since the version given by [ClearGuard::Version] and is now at
+    /// `current` version.
+    VersionMismatch { current: DocVersion },
+}
+
+impl Display for ClearRefusal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClearRefusal::NotEmpty => write!(f, "document is not empty"),
+            ClearRefusal::TooLarge { bytes, limit } => write!(
+                f,
+                "document takes {} bytes, while at most {} bytes are allowed",
+                bytes, limit
+            ),
+            ClearRefusal::MissingFlag => write!(f, "document doesn't have required metadata"),
+            ClearRefusal::VersionMismatch { current } => {
+                write!(f, "document has changed, current version is {}", current)
+            }
+        }
+    }
+}
+
+/// Checks if a document with a given `oid` meets a given `guard`, failing with
+/// [Error::ClearRefused] otherwise.
+pub(crate) fn check<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    guard: &ClearGuard,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    // every write appending updates rewrites the pending counter
+    let pending = lock_pending(db, oid)?;
+    let refusal = match guard {
+        ClearGuard::Empty => {
+            let archived = db.get_for_update(&key_archive(oid))?.is_some();
+            if archived || has_contents(db, oid)? {
+                Some(ClearRefusal::NotEmpty)
+            } else {
+                None
+            }
+        }
+        ClearGuard::MaxBytes(limit) => {
+            let bytes = match db.get_for_update(&key_doc(oid))? {
+                Some(state) => blob::stored_len(state.as_ref()) + pending.bytes,
+                None => match db.get_for_update(&key_archive(oid))? {
+                    Some(archived) => archived.as_ref().len() as u64,
+                    None => pending.bytes,
+                },
+            };
+            if bytes > *limit {
+                Some(ClearRefusal::TooLarge {
+                    bytes,
+                    limit: *limit,
+                })
+            } else {
+                None
+            }
+        }
+        ClearGuard::MetaFlag(key) => match db.get_for_update(&key_meta(oid, key))? {
+            Some(_) => None,
+            None => Some(ClearRefusal::MissingFlag),
+        },
+        ClearGuard::Version(expected) => {
+            let current = version::doc_version(db, oid)?;
+            if current != *expected {
+                Some(ClearRefusal::VersionMismatch { current })
+            } else {
+                None
+            }
+        }
+    };
+    match refusal {
+        Some(refusal) => Err(Error::ClearRefused(refusal)),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,106 @@
+//! Index of Yrs document GUIDs, used by subdocuments and some providers to address documents.
+//! See [DocOps::insert_doc_with_guid](crate::DocOps::insert_doc_with_guid) and
+//! [format](crate::format) for its layout.
+
+use crate::error::Error;
+use crate::keys::{key_guid, key_meta, META_GUID, OID};
+use crate::{get_oid, DocOps, KVStore};
+
+/// Returns GUID recorded for a document with a given `oid`.
+pub(crate) fn get<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<Option<String>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    match db.get(&key_meta(oid, META_GUID))? {
+        Some(value) => match std::str::from_utf8(value.as_ref()) {
+            Ok(guid) => Ok(Some(guid.to_string())),
+            Err(_) => Err(Error::CorruptedValue),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Returns the name of a document with a given `guid`.
+pub(crate) fn lookup<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    guid: &str,
+) -> Result<Option<Vec<u8>>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let name = db.get(&key_guid(guid.as_bytes()))?;
+    Ok(name.map(|name| name.as_ref().to_vec()))
+}
+
+/// Checks if `guid` can be assigned to a document with a given `name`: either the document has
+/// a GUID already, which is going to be kept, or `guid` doesn't belong to any other document.
+pub(crate) fn check<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    guid: &str,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if let Some(oid) = get_oid(db, name)? {
+        if get(db, oid)?.is_some() {
+            return Ok(());
+        }
+    }
+    match lookup(db, guid)? {
+        Some(owner) if owner != name => Err(Error::GuidConflict { owner }),
+        _ => Ok(()),
+    }
+}
+
+/// Assigns `guid` to a document with a given `oid` and `name`, unless it has a GUID already.
+/// Assignment must be verified with [check] first.
+pub(crate) fn assign<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    oid: OID,
+    guid: &str,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if get(db, oid)?.is_none() {
+        db.upsert(&key_meta(oid, META_GUID), guid.as_bytes())?;
+        index(db, name, guid.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Writes GUID index entry pointing at a document with a given `name`.
+pub(crate) fn index<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    guid: &[u8],
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    db.upsert(&key_guid(guid), name)?;
+    Ok(())
+}
+
+/// Removes GUID index entry of a document with a given `oid` and `name`, if there's one.
+pub(crate) fn unindex<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    oid: OID,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if let Some(guid) = db.get(&key_meta(oid, META_GUID))? {
+        let key = key_guid(guid.as_ref());
+        // index entry may have been taken over by another document in the meantime
+        if let Some(owner) = db.get(&key)? {
+            if owner.as_ref() == name {
+                db.remove(&key)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,43 @@
+//! Records of multi-key operations in progress, used by stores which cannot perform them
+//! atomically. See [StoreConfig::intent_log](crate::config::StoreConfig::intent_log).
+
+use crate::error::Error;
+use crate::format::{INTENT_CLEAR, INTENT_FLUSH};
+
+/// Multi-key operation on a single document, which has been started but may not have been
+/// completed. Every intent can be safely executed again from the beginning, no matter how many
+/// of its writes have already been applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Intent {
+    /// Merging pending updates into document state. Document state is always written before
+    /// updates are removed, so flushing again yields the same result.
+    Flush { skip_gc: bool },
+    /// Removing all entries of a document with a given name.
+    Clear { name: Vec<u8> },
+}
+
+impl Intent {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Intent::Flush { skip_gc } => vec![INTENT_FLUSH, *skip_gc as u8],
+            Intent::Clear { name } => {
+                let mut buf = Vec::with_capacity(name.len() + 1);
+                buf.push(INTENT_CLEAR);
+                buf.extend_from_slice(name);
+                buf
+            }
+        }
+    }
+
+    pub fn decode(value: &[u8]) -> Result<Self, Error> {
+        match value {
+            [INTENT_FLUSH, skip_gc] => Ok(Intent::Flush {
+                skip_gc: *skip_gc != 0,
+            }),
+            [INTENT_CLEAR, name @ ..] => Ok(Intent::Clear {
+                name: name.to_vec(),
+            }),
+            _ => Err(Error::CorruptedValue),
+        }
+    }
+}
@@ -0,0 +1,464 @@
+//! Instrumentation of key-value store traffic generated by [DocOps] methods, i.e. in order to
+//! measure write amplification of different [FlushPolicy](crate::config::FlushPolicy) settings.
+//!
+//! [IoStatsStore] wraps any [KVStore] and counts keys and bytes passing through it. Counters are
+//! attributed to the [Operation] which is currently executed: main [DocOps] methods are shadowed
+//! by inherent methods of [IoStatsStore], which mark the operation they perform. All other calls -
+//! including the ones made through a generic [DocOps] bound - are attributed to
+//! [Operation::Other]:
+//!
+//! ```rust,ignore
+//! let db = IoStatsStore::new(RocksDBStore::from(db.transaction()));
+//! db.push_update("my-doc-name", &update)?;
+//! db.flush_doc("my-doc-name")?;
+//! let report = db.report();
+//! println!("flush wrote {} bytes", report.get(Operation::FlushDoc).bytes_written);
+//! db.into_inner().commit()?;
+//! ```
+//!
+//! Counted bytes are the logical sizes of keys and values exchanged with the wrapped store, after
+//! they have been encoded by [ValueCodec](crate::config::ValueCodec). Storage overhead of the
+//! backend itself (i.e. write-ahead log or compaction) is not included.
+
+use crate::blob::BlobStore;
+use crate::capabilities::Capabilities;
+use crate::clock::Clock;
+use crate::config::{StoreConfig, UpdateValidator};
+use crate::error::Error;
+use crate::guard::ClearGuard;
+use crate::usage::TenantExtractor;
+use crate::{
+    ClearReport, DocOps, FlushOutcome, HealthHint, KVEntry, KVStore, PushReceipt, ReadIsolation,
+    ScanMode,
+};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use std::rc::Rc;
+use yrs::{ReadTxn, StateVector, TransactionMut};
+
+/// Kind of [DocOps] operation, which key-value store traffic is attributed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Operation {
+    /// [DocOps::insert_doc].
+    InsertDoc,
+    /// [DocOps::push_update], including automatic flushes triggered by it.
+    PushUpdate,
+    /// [DocOps::flush_doc].
+    FlushDoc,
+    /// [DocOps::load_doc].
+    LoadDoc,
+    /// [DocOps::get_state_vector].
+    GetStateVector,
+    /// [DocOps::get_diff].
+    GetDiff,
+    /// [DocOps::clear_doc].
+    ClearDoc,
+    /// Any other call made through the store.
+    Other,
+}
+
+/// Key-value store traffic counters of a single [Operation].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpStats {
+    /// Number of calls of the operation. It's not counted for [Operation::Other].
+    pub calls: u64,
+    /// Number of keys looked up or returned by cursors.
+    pub keys_read: u64,
+    /// Total size of values read.
+    pub bytes_read: u64,
+    /// Number of keys inserted, updated or removed.
+    pub keys_written: u64,
+    /// Total size of keys and values written. Removed keys count with their key size, while
+    /// entries removed by range deletes count as keys only.
+    pub bytes_written: u64,
+}
+
+impl OpStats {
+    /// Adds counters of `other` to current ones.
+    pub fn merge(&mut self, other: &OpStats) {
+        self.calls += other.calls;
+        self.keys_read += other.keys_read;
+        self.bytes_read += other.bytes_read;
+        self.keys_written += other.keys_written;
+        self.bytes_written += other.bytes_written;
+    }
+}
+
+/// Key-value store traffic counted by [IoStatsStore], grouped by [Operation]. Reports of multiple
+/// stores (i.e. of subsequent transactions) can be aggregated with [IoReport::merge].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IoReport {
+    ops: BTreeMap<Operation, OpStats>,
+}
+
+impl IoReport {
+    /// Returns counters of a given operation.
+    pub fn get(&self, op: Operation) -> OpStats {
+        self.ops.get(&op).copied().unwrap_or_default()
+    }
+
+    /// Returns counters of all operations combined.
+    pub fn total(&self) -> OpStats {
+        let mut total = OpStats::default();
+        for stats in self.ops.values() {
+            total.merge(stats);
+        }
+        total
+    }
+
+    /// Returns an iterator over counters of all operations, which have generated any traffic.
+    pub fn iter(&self) -> impl Iterator<Item = (Operation, &OpStats)> {
+        self.ops.iter().map(|(op, stats)| (*op, stats))
+    }
+
+    /// Adds counters of `other` report to current ones.
+    pub fn merge(&mut self, other: &IoReport) {
+        for (op, stats) in other.ops.iter() {
+            self.ops.entry(*op).or_default().merge(stats);
+        }
+    }
+
+    fn record(&mut self, op: Operation) -> &mut OpStats {
+        self.ops.entry(op).or_default()
+    }
+}
+
+/// Wrapper around any [KVStore], which counts keys and bytes read and written through it. See
+/// [io_stats](crate::io_stats) module for details.
+pub struct IoStatsStore<S> {
+    store: S,
+    current: Cell<Operation>,
+    report: Rc<RefCell<IoReport>>,
+}
+
+impl<S> IoStatsStore<S> {
+    /// Wraps a given `store` with all counters set to zero.
+    pub fn new(store: S) -> Self {
+        IoStatsStore {
+            store,
+            current: Cell::new(Operation::Other),
+            report: Rc::new(RefCell::new(IoReport::default())),
+        }
+    }
+
+    /// Returns counters collected so far.
+    pub fn report(&self) -> IoReport {
+        self.report.borrow().clone()
+    }
+
+    /// Sets all counters back to zero.
+    pub fn reset(&self) {
+        *self.report.borrow_mut() = IoReport::default();
+    }
+
+    /// Runs `f`, attributing all traffic it generates - unless it's attributed by a nested call
+    /// already - to a given operation, which call count is incremented.
+    pub fn measure<F, R>(&self, op: Operation, f: F) -> R
+    where
+        F: FnOnce(&Self) -> R,
+    {
+        if op != Operation::Other {
+            self.report.borrow_mut().record(op).calls += 1;
+        }
+        let outer = self.current.replace(op);
+        let result = f(self);
+        self.current.set(outer);
+        result
+    }
+
+    /// Unwraps the underlying store, i.e. in order to commit it.
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+
+    fn count_read(&self, len: Option<usize>) {
+        let mut report = self.report.borrow_mut();
+        let stats = report.record(self.current.get());
+        stats.keys_read += 1;
+        stats.bytes_read += len.unwrap_or(0) as u64;
+    }
+
+    fn count_write(&self, keys: u64, bytes: usize) {
+        let mut report = self.report.borrow_mut();
+        let stats = report.record(self.current.get());
+        stats.keys_written += keys;
+        stats.bytes_written += bytes as u64;
+    }
+}
+
+impl<'a, S: DocOps<'a>> IoStatsStore<S>
+where
+    Error: From<S::Error>,
+{
+    /// Inserts document state, counted as [Operation::InsertDoc]. See [DocOps::insert_doc].
+    pub fn insert_doc<K: AsRef<[u8]> + ?Sized, T: ReadTxn>(
+        &self,
+        name: &K,
+        txn: &T,
+    ) -> Result<(), Error> {
+        self.measure(Operation::InsertDoc, |db| DocOps::insert_doc(db, name, txn))
+    }
+
+    /// Appends an update, counted as [Operation::PushUpdate]. See [DocOps::push_update].
+    pub fn push_update<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        update: &[u8],
+    ) -> Result<PushReceipt, Error> {
+        self.measure(Operation::PushUpdate, |db| {
+            DocOps::push_update(db, name, update)
+        })
+    }
+
+    /// Appends an update using a `scratch` buffer, counted as [Operation::PushUpdate]. See
+    /// [DocOps::push_update_with].
+    pub fn push_update_with<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        update: &[u8],
+        scratch: &mut Vec<u8>,
+    ) -> Result<PushReceipt, Error> {
+        self.measure(Operation::PushUpdate, |db| {
+            DocOps::push_update_with(db, name, update, scratch)
+        })
+    }
+
+    /// Merges pending updates, counted as [Operation::FlushDoc]. See [DocOps::flush_doc].
+    pub fn flush_doc<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<FlushOutcome>, Error> {
+        self.measure(Operation::FlushDoc, |db| DocOps::flush_doc(db, name))
+    }
+
+    /// Loads a document, counted as [Operation::LoadDoc]. See [DocOps::load_doc].
+    pub fn load_doc<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        txn: &mut TransactionMut,
+    ) -> Result<bool, Error> {
+        self.measure(Operation::LoadDoc, |db| DocOps::load_doc(db, name, txn))
+    }
+
+    /// Loads a document using a `scratch` buffer, counted as [Operation::LoadDoc]. See
+    /// [DocOps::load_doc_with].
+    pub fn load_doc_with<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        txn: &mut TransactionMut,
+        scratch: &mut Vec<u8>,
+    ) -> Result<bool, Error> {
+        self.measure(Operation::LoadDoc, |db| {
+            DocOps::load_doc_with(db, name, txn, scratch)
+        })
+    }
+
+    /// Returns state vector of a document, counted as [Operation::GetStateVector]. See
+    /// [DocOps::get_state_vector].
+    pub fn get_state_vector<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<(Option<StateVector>, bool), Error> {
+        self.measure(Operation::GetStateVector, |db| {
+            DocOps::get_state_vector(db, name)
+        })
+    }
+
+    /// Computes diff of a document, counted as [Operation::GetDiff]. See [DocOps::get_diff].
+    pub fn get_diff<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        sv: &StateVector,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.measure(Operation::GetDiff, |db| DocOps::get_diff(db, name, sv))
+    }
+
+    /// Removes a document, counted as [Operation::ClearDoc]. See [DocOps::clear_doc].
+    pub fn clear_doc<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<ClearReport, Error> {
+        self.measure(Operation::ClearDoc, |db| DocOps::clear_doc(db, name))
+    }
+
+    /// Removes a document meeting a given `guard`, counted as [Operation::ClearDoc]. See
+    /// [DocOps::clear_doc_if].
+    pub fn clear_doc_if<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        guard: ClearGuard,
+    ) -> Result<ClearReport, Error> {
+        self.measure(Operation::ClearDoc, |db| {
+            DocOps::clear_doc_if(db, name, guard)
+        })
+    }
+}
+
+impl<S> Deref for IoStatsStore<S> {
+    type Target = S;
+
+    fn deref(&self) -> &Self::Target {
+        &self.store
+    }
+}
+
+impl<'a, S: KVStore<'a>> KVStore<'a> for IoStatsStore<S> {
+    type Error = S::Error;
+    type Cursor = IoStatsCursor<S::Cursor>;
+    type Entry = S::Entry;
+    type Return = S::Return;
+
+    const CAPABILITIES: Capabilities = S::CAPABILITIES;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+        let value = self.store.get(key)?;
+        self.count_read(value.as_ref().map(|v| v.as_ref().len()));
+        Ok(value)
+    }
+
+    fn get_for_update(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+        let value = self.store.get_for_update(key)?;
+        self.count_read(value.as_ref().map(|v| v.as_ref().len()));
+        Ok(value)
+    }
+
+    fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, Self::Error> {
+        let values = self.store.get_many(keys)?;
+        for value in values.iter() {
+            self.count_read(value.as_ref().map(Vec::len));
+        }
+        Ok(values)
+    }
+
+    fn upsert(&self, key: &[u8], value: &[u8]) -> Result<(), Self::Error> {
+        self.store.upsert(key, value)?;
+        self.count_write(1, key.len() + value.len());
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+        self.store.remove(key)?;
+        self.count_write(1, key.len());
+        Ok(())
+    }
+
+    fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<u32, Self::Error> {
+        let removed = self.store.remove_range(from, to)?;
+        self.count_write(removed as u64, 0);
+        Ok(removed)
+    }
+
+    fn iter_range(&self, from: &[u8], to: &[u8]) -> Result<Self::Cursor, Self::Error> {
+        self.iter_range_with(from, to, ScanMode::Interactive)
+    }
+
+    fn iter_range_with(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        mode: ScanMode,
+    ) -> Result<Self::Cursor, Self::Error> {
+        let inner = self.store.iter_range_with(from, to, mode)?;
+        Ok(IoStatsCursor {
+            inner,
+            op: self.current.get(),
+            report: self.report.clone(),
+        })
+    }
+
+    fn iter_keys_range(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        f: &mut dyn FnMut(&[u8], usize) -> bool,
+    ) -> Result<(), Self::Error> {
+        // values aren't read, only visited keys are counted
+        self.store.iter_keys_range(from, to, &mut |key, len| {
+            self.count_read(None);
+            f(key, len)
+        })
+    }
+
+    fn iter_range_rev(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        f: &mut dyn FnMut(&[u8], &[u8]) -> bool,
+    ) -> Result<(), Self::Error> {
+        self.store.iter_range_rev(from, to, &mut |key, value| {
+            self.count_read(Some(value.len()));
+            f(key, value)
+        })
+    }
+
+    fn peek_back(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+        let entry = self.store.peek_back(key)?;
+        self.count_read(entry.as_ref().map(|e| e.value().len()));
+        Ok(entry)
+    }
+
+    fn seek(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+        let entry = self.store.seek(key)?;
+        self.count_read(entry.as_ref().map(|e| e.value().len()));
+        Ok(entry)
+    }
+
+    fn read_isolation(&self) -> ReadIsolation {
+        self.store.read_isolation()
+    }
+
+    fn health_hint(&self) -> Option<HealthHint> {
+        self.store.health_hint()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.store.is_read_only()
+    }
+}
+
+impl<'a, S: DocOps<'a>> DocOps<'a> for IoStatsStore<S>
+where
+    Error: From<S::Error>,
+{
+    fn config(&self) -> &StoreConfig {
+        self.store.config()
+    }
+
+    fn update_validator(&self) -> Option<&dyn UpdateValidator> {
+        self.store.update_validator()
+    }
+
+    fn tenant_extractor(&self) -> Option<&dyn TenantExtractor> {
+        self.store.tenant_extractor()
+    }
+
+    fn blob_store(&self) -> Option<&dyn BlobStore> {
+        self.store.blob_store()
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.store.clock()
+    }
+}
+
+/// Cursor returned by [IoStatsStore], which counts entries it returns as reads of the operation
+/// that has created it.
+pub struct IoStatsCursor<C> {
+    inner: C,
+    op: Operation,
+    report: Rc<RefCell<IoReport>>,
+}
+
+impl<C: Iterator> Iterator for IoStatsCursor<C>
+where
+    C::Item: KVEntry,
+{
+    type Item = C::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.inner.next()?;
+        let mut report = self.report.borrow_mut();
+        let stats = report.record(self.op);
+        stats.keys_read += 1;
+        stats.bytes_read += entry.value().len() as u64;
+        Some(entry)
+    }
+}
@@ -1,46 +1,32 @@
+use crate::format::{
+    decode_timestamp, encode_timestamp, CLOCK_LEN, NAMESPACE_LEN, OID_LEN, TIMESTAMP_LEN,
+};
 use smallvec::{smallvec, SmallVec};
+use std::convert::TryInto;
 use std::io::Write;
 use std::ops::Deref;
 
-/// Prefix byte used for all of the yrs-kvstore entries.
-pub const V1: u8 = 0;
-
-/*
-   00{doc_name:n}0      - OID key pattern
-   01{oid:4}0           - document key pattern
-   01{oid:4}1           - state vector key pattern
-   01{oid:4}2{clock:4}0 - document update key pattern
-   01{oid:4}3{name:m}0  - document meta key pattern
-
-  First 0 byte is marker for current version of records stored.
-  Second 0|1 byte is used to differentiate oid index and document key spaces.
-*/
-
-/// Prefix byte used for document name -> OID mapping index key space.
-pub const KEYSPACE_OID: u8 = 0;
-
-/// Prefix byte used for document key space.
-pub const KEYSPACE_DOC: u8 = 1;
-
-/// Tag byte within [KEYSPACE_DOC] used to identify document's state entry.
-pub const SUB_DOC: u8 = 0;
-
-/// Tag byte within [KEYSPACE_DOC] used to identify document's state vector entry.
-pub const SUB_STATE_VEC: u8 = 1;
-
-/// Tag byte within [KEYSPACE_DOC] used to identify document's update entries.
-pub const SUB_UPDATE: u8 = 2;
-
-/// Tag byte within [KEYSPACE_DOC] used to identify document's metadata entries.
-pub const SUB_META: u8 = 3;
-
-pub const TERMINATOR: u8 = 0;
-pub const TERMINATOR_HI_WATERMARK: u8 = 255;
+pub use crate::format::{
+    DEFAULT_CHANNEL, DOC_KEY_U64, DOC_KEY_U64_PAIR, DOC_OPTION_SKIP_GC, KEYSPACE_ARCHIVE,
+    KEYSPACE_COLLECTION, KEYSPACE_DIVERGED, KEYSPACE_DOC, KEYSPACE_FEED, KEYSPACE_FENCE,
+    KEYSPACE_GUID, KEYSPACE_INTENT, KEYSPACE_NAMESPACE, KEYSPACE_OID, KEYSPACE_REF,
+    KEYSPACE_SETTINGS, KEYSPACE_SYNC, KEYSPACE_USAGE, META_ACCESS, META_DOC_OPTIONS, META_DRAFT,
+    META_FENCE, META_GC, META_GUID, META_INITIALIZED, META_LAST_MODIFIED, META_MAX_DOC_BYTES,
+    META_SPLIT_IDS, OID_FLAG_ARCHIVED, OID_FLAG_DRAFT, REF_INBOUND, REF_TARGET, SETTING_LAST_OID,
+    SETTING_MAX_DOC_BYTES, SUB_AUDIT, SUB_CHANNEL, SUB_DOC, SUB_META, SUB_PENDING, SUB_REF,
+    SUB_SIZE_HISTORY, SUB_SNAPSHOT, SUB_STATE_VEC, SUB_SYNC_GEN, SUB_UPDATE, TERMINATOR,
+    TERMINATOR_HI_WATERMARK, UPDATE_SEGMENT, V1,
+};
 
 pub type OID = u32;
 
-pub fn key_oid(doc_name: &[u8]) -> Key<20> {
-    let mut v: SmallVec<[u8; 20]> = smallvec![V1, KEYSPACE_OID];
+/// Inline capacity of keys containing variable-length parts, i.e. document and metadata names.
+/// It fits UUID-based document names, so that building keys for them doesn't allocate. Keys with
+/// longer names spill to the heap.
+pub const NAME_KEY_LEN: usize = 48;
+
+pub fn key_oid(doc_name: &[u8]) -> Key<NAME_KEY_LEN> {
+    let mut v: SmallVec<[u8; NAME_KEY_LEN]> = smallvec![V1, KEYSPACE_OID];
     v.write_all(doc_name).unwrap();
     v.push(TERMINATOR);
     Key(v)
@@ -80,16 +66,98 @@ pub fn key_update(oid: OID, clock: u32) -> Key<12> {
     Key(v)
 }
 
+pub fn key_update_segment(oid: OID, base: u32) -> Key<12> {
+    let mut v: SmallVec<[u8; 12]> = smallvec![V1, KEYSPACE_DOC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(SUB_UPDATE);
+    v.write_all(&base.to_be_bytes()).unwrap();
+    v.push(UPDATE_SEGMENT);
+    Key(v)
+}
+
+pub fn key_channel_update(oid: OID, channel: u8, clock: u32) -> Key<13> {
+    let mut v: SmallVec<[u8; 13]> = smallvec![V1, KEYSPACE_DOC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(SUB_CHANNEL);
+    v.push(channel);
+    v.write_all(&clock.to_be_bytes()).unwrap();
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_channel_start(oid: OID) -> Key<8> {
+    let mut v: SmallVec<[u8; 8]> = smallvec![V1, KEYSPACE_DOC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(SUB_CHANNEL);
+    Key(v)
+}
+
+pub fn key_channel_end(oid: OID) -> Key<8> {
+    let mut v: SmallVec<[u8; 8]> = smallvec![V1, KEYSPACE_DOC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(SUB_CHANNEL + 1);
+    Key(v)
+}
+
+pub fn key_audit(oid: OID, time: u64, n: u32) -> Key<20> {
+    let mut v: SmallVec<[u8; 20]> = smallvec![V1, KEYSPACE_DOC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(SUB_AUDIT);
+    v.write_all(&encode_timestamp(time)).unwrap();
+    v.write_all(&n.to_be_bytes()).unwrap();
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_audit_start(oid: OID, time: u64) -> Key<16> {
+    let mut v: SmallVec<[u8; 16]> = smallvec![V1, KEYSPACE_DOC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(SUB_AUDIT);
+    v.write_all(&encode_timestamp(time)).unwrap();
+    Key(v)
+}
+
+pub fn key_size_history_head(oid: OID) -> Key<8> {
+    let mut v: SmallVec<[u8; 8]> = smallvec![V1, KEYSPACE_DOC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(SUB_SIZE_HISTORY);
+    Key(v)
+}
+
+pub fn key_size_history(oid: OID, slot: u8) -> Key<8> {
+    let mut v: SmallVec<[u8; 8]> = smallvec![V1, KEYSPACE_DOC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(SUB_SIZE_HISTORY);
+    v.push(slot);
+    Key(v)
+}
+
+/// Returns the metadata entry name from a given metadata `key`, built with [key_meta]. Keys too
+/// short to contain a name have an empty one.
 pub fn doc_meta_name(key: &[u8]) -> &[u8] {
-    &key[7..(key.len() - 1)]
+    // meta key scheme: 01{oid:4}3{name:N}0
+    let end = key.len().saturating_sub(1);
+    key.get((3 + OID_LEN)..end).unwrap_or_default()
 }
 
+/// Returns the OID from a given `key` of an entry stored under it, i.e. built with [key_doc] or
+/// [key_intent]. Returns `None` for keys too short to contain one.
+pub fn key_oid_part(key: &[u8]) -> Option<OID> {
+    // key scheme: 0{keyspace:1}{oid:4}..
+    let oid = key.get(2..(2 + OID_LEN))?;
+    Some(OID::from_be_bytes(oid.try_into().unwrap()))
+}
+
+/// Returns the document name from a given OID index `key`, built with [key_oid]. Keys too short
+/// to contain a name have an empty one.
 pub fn doc_oid_name(key: &[u8]) -> &[u8] {
-    &key[2..(key.len() - 1)]
+    // oid key scheme: 00{doc_name:N}0
+    let end = key.len().saturating_sub(1);
+    key.get(2..end).unwrap_or_default()
 }
 
-pub fn key_meta(oid: OID, name: &[u8]) -> Key<20> {
-    let mut v: SmallVec<[u8; 20]> = smallvec![V1, KEYSPACE_DOC];
+pub fn key_meta(oid: OID, name: &[u8]) -> Key<NAME_KEY_LEN> {
+    let mut v: SmallVec<[u8; NAME_KEY_LEN]> = smallvec![V1, KEYSPACE_DOC];
     v.write_all(&oid.to_be_bytes()).unwrap();
     v.push(SUB_META);
     v.write_all(&name).unwrap();
@@ -112,6 +180,589 @@ pub fn key_meta_end(oid: OID) -> Key<8> {
     Key(v)
 }
 
+pub fn key_pending(oid: OID) -> Key<8> {
+    let mut v: SmallVec<[u8; 8]> = smallvec![V1, KEYSPACE_DOC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(SUB_PENDING);
+    Key(v)
+}
+
+pub fn key_snapshot(oid: OID, label: &[u8]) -> Key<NAME_KEY_LEN> {
+    let mut v: SmallVec<[u8; NAME_KEY_LEN]> = smallvec![V1, KEYSPACE_DOC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(SUB_SNAPSHOT);
+    v.write_all(label).unwrap();
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_ref(oid: OID, name: &[u8]) -> Key<NAME_KEY_LEN> {
+    let mut v: SmallVec<[u8; NAME_KEY_LEN]> = smallvec![V1, KEYSPACE_DOC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(SUB_REF);
+    v.write_all(name).unwrap();
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_ref_start(oid: OID) -> Key<8> {
+    let mut v: SmallVec<[u8; 8]> = smallvec![V1, KEYSPACE_DOC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(SUB_REF);
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_ref_end(oid: OID) -> Key<8> {
+    let mut v: SmallVec<[u8; 8]> = smallvec![V1, KEYSPACE_DOC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(SUB_REF + 1);
+    Key(v)
+}
+
+pub fn key_ref_target(oid: OID) -> Key<8> {
+    let mut v: SmallVec<[u8; 8]> = smallvec![V1, KEYSPACE_REF];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(REF_TARGET);
+    Key(v)
+}
+
+pub fn key_ref_inbound(target: OID, source: OID, name: &[u8]) -> Key<NAME_KEY_LEN> {
+    let mut v: SmallVec<[u8; NAME_KEY_LEN]> = smallvec![V1, KEYSPACE_REF];
+    v.write_all(&target.to_be_bytes()).unwrap();
+    v.push(REF_INBOUND);
+    v.write_all(&source.to_be_bytes()).unwrap();
+    v.write_all(name).unwrap();
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_ref_inbound_start(target: OID) -> Key<8> {
+    let mut v: SmallVec<[u8; 8]> = smallvec![V1, KEYSPACE_REF];
+    v.write_all(&target.to_be_bytes()).unwrap();
+    v.push(REF_INBOUND);
+    Key(v)
+}
+
+pub fn key_ref_inbound_end(target: OID) -> Key<8> {
+    let mut v: SmallVec<[u8; 8]> = smallvec![V1, KEYSPACE_REF];
+    v.write_all(&target.to_be_bytes()).unwrap();
+    v.push(REF_INBOUND + 1);
+    Key(v)
+}
+
+pub fn key_sync_gen(oid: OID) -> Key<8> {
+    let mut v: SmallVec<[u8; 8]> = smallvec![V1, KEYSPACE_DOC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(SUB_SYNC_GEN);
+    Key(v)
+}
+
+pub fn key_sync_frame(oid: OID, generation: u32, hash: u64) -> Key<20> {
+    let mut v: SmallVec<[u8; 20]> = smallvec![V1, KEYSPACE_SYNC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.write_all(&generation.to_be_bytes()).unwrap();
+    v.write_all(&hash.to_be_bytes()).unwrap();
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_sync_frame_start(oid: OID, generation: u32) -> Key<12> {
+    let mut v: SmallVec<[u8; 12]> = smallvec![V1, KEYSPACE_SYNC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.write_all(&generation.to_be_bytes()).unwrap();
+    Key(v)
+}
+
+pub fn key_sync_frame_end(oid: OID) -> Key<20> {
+    // greater than any frame key of a given document, since these end with TERMINATOR
+    let mut v: SmallVec<[u8; 20]> = smallvec![V1, KEYSPACE_SYNC];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.write_all(&[TERMINATOR_HI_WATERMARK; 13]).unwrap();
+    Key(v)
+}
+
+pub fn key_setting(name: &[u8]) -> Key<NAME_KEY_LEN> {
+    let mut v: SmallVec<[u8; NAME_KEY_LEN]> = smallvec![V1, KEYSPACE_SETTINGS];
+    v.write_all(name).unwrap();
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_collection(path: &[u8]) -> Key<NAME_KEY_LEN> {
+    let mut v: SmallVec<[u8; NAME_KEY_LEN]> = smallvec![V1, KEYSPACE_COLLECTION];
+    v.write_all(path).unwrap();
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_intent(oid: OID) -> Key<8> {
+    let mut v: SmallVec<[u8; 8]> = smallvec![V1, KEYSPACE_INTENT];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_archive(oid: OID) -> Key<8> {
+    let mut v: SmallVec<[u8; 8]> = smallvec![V1, KEYSPACE_ARCHIVE];
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_diverged(doc_name: &[u8]) -> Key<NAME_KEY_LEN> {
+    let mut v: SmallVec<[u8; NAME_KEY_LEN]> = smallvec![V1, KEYSPACE_DIVERGED];
+    v.write_all(doc_name).unwrap();
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_guid(guid: &[u8]) -> Key<NAME_KEY_LEN> {
+    let mut v: SmallVec<[u8; NAME_KEY_LEN]> = smallvec![V1, KEYSPACE_GUID];
+    v.write_all(guid).unwrap();
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_usage(tenant: &[u8]) -> Key<NAME_KEY_LEN> {
+    let mut v: SmallVec<[u8; NAME_KEY_LEN]> = smallvec![V1, KEYSPACE_USAGE];
+    v.write_all(tenant).unwrap();
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_fence(doc_name: &[u8]) -> Key<NAME_KEY_LEN> {
+    let mut v: SmallVec<[u8; NAME_KEY_LEN]> = smallvec![V1, KEYSPACE_FENCE];
+    v.write_all(doc_name).unwrap();
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_feed(time: u64, oid: OID, seq: u32) -> Key<20> {
+    let mut v: SmallVec<[u8; 20]> = smallvec![V1, KEYSPACE_FEED];
+    v.write_all(&encode_timestamp(time)).unwrap();
+    v.write_all(&oid.to_be_bytes()).unwrap();
+    v.write_all(&seq.to_be_bytes()).unwrap();
+    v.push(TERMINATOR);
+    Key(v)
+}
+
+pub fn key_feed_start(time: u64) -> Key<12> {
+    let mut v: SmallVec<[u8; 12]> = smallvec![V1, KEYSPACE_FEED];
+    v.write_all(&encode_timestamp(time)).unwrap();
+    Key(v)
+}
+
+/// Returns the prefix of all entries of a namespace with a given identifier.
+pub fn key_namespace(namespace: u16) -> Key<4> {
+    let [hi, lo] = namespace.to_be_bytes();
+    Key::from_const([V1, KEYSPACE_NAMESPACE, hi, lo])
+}
+
+/// Returns the key following all entries of a namespace with a given identifier.
+pub fn key_namespace_end(namespace: u16) -> Key<4> {
+    match namespace.checked_add(1) {
+        Some(next) => key_namespace(next),
+        None => Key::from_const([V1, KEYSPACE_NAMESPACE + 1, 0, 0]),
+    }
+}
+
+/// Splits a key of an entry stored within [KEYSPACE_NAMESPACE] into the namespace identifier and
+/// the key of that entry within the namespace, which can be decoded with [parse_key]. Returns
+/// `None` if `key` doesn't belong to any namespace.
+pub fn split_namespace(key: &[u8]) -> Option<(u16, &[u8])> {
+    match key {
+        [V1, KEYSPACE_NAMESPACE, rest @ ..] if rest.len() >= NAMESPACE_LEN => {
+            let (namespace, key) = rest.split_at(NAMESPACE_LEN);
+            Some((u16::from_be_bytes(namespace.try_into().unwrap()), key))
+        }
+        _ => None,
+    }
+}
+
+/// Decoded key of one of the entries described by [format](crate::format) specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParsedKey<'a> {
+    /// OID index entry of a document with a given name.
+    Oid { doc_name: &'a [u8] },
+    /// Document state entry.
+    Doc { oid: OID },
+    /// Document state vector entry.
+    StateVector { oid: OID },
+    /// Document update entry stored under a given sequence number.
+    Update { oid: OID, clock: u32 },
+    /// Document update segment, starting at a given sequence number.
+    UpdateSegment { oid: OID, base: u32 },
+    /// Document metadata entry.
+    Meta { oid: OID, name: &'a [u8] },
+    /// Document pending updates counter.
+    Pending { oid: OID },
+    /// Document snapshot entry.
+    Snapshot { oid: OID, label: &'a [u8] },
+    /// Document reference entry.
+    Ref { oid: OID, name: &'a [u8] },
+    /// Document generation counter of cached sync frames.
+    SyncGen { oid: OID },
+    /// Document update pushed to a given channel under a given sequence number.
+    ChannelUpdate { oid: OID, channel: u8, clock: u32 },
+    /// Document audit record written at a given `time`, `n`-th within the same millisecond.
+    Audit { oid: OID, time: u64, n: u32 },
+    /// Document size history head.
+    SizeHistoryHead { oid: OID },
+    /// Document size sample stored in a given slot of the size history.
+    SizeSample { oid: OID, slot: u8 },
+    /// Archived document state entry.
+    Archive { oid: OID },
+    /// Store-wide setting entry.
+    Setting { name: &'a [u8] },
+    /// Collection marker.
+    Collection { path: &'a [u8] },
+    /// Intent log entry.
+    Intent { oid: OID },
+    /// Name of a document referenced by other documents.
+    RefTarget { oid: OID },
+    /// Inbound reference of `target` document, stored by `source` document under `name`.
+    RefInbound {
+        target: OID,
+        source: OID,
+        name: &'a [u8],
+    },
+    /// Sync frame cached for a remote state vector with a given `hash`.
+    SyncFrame {
+        oid: OID,
+        generation: u32,
+        hash: u64,
+    },
+    /// Divergence marker of a document with a given name.
+    Diverged { doc_name: &'a [u8] },
+    /// GUID index entry.
+    Guid { guid: &'a [u8] },
+    /// Usage counter of a given tenant.
+    Usage { tenant: &'a [u8] },
+    /// Update feed entry of an update with a given sequence number `seq`, pushed at a given
+    /// `time`.
+    Feed { time: u64, oid: OID, seq: u32 },
+    /// Fencing token left by a cleared document with a given name.
+    Fence { doc_name: &'a [u8] },
+}
+
+/// Parses a given `key` of an entry stored by [DocOps](crate::DocOps). Returns `None` if key
+/// doesn't match any of the patterns described by [format](crate::format) specification.
+/// Keys of namespaced entries must be split with [split_namespace] first.
+pub fn parse_key(key: &[u8]) -> Option<ParsedKey<'_>> {
+    let (&version, key) = key.split_first()?;
+    if version != V1 {
+        return None;
+    }
+    let (&keyspace, rest) = key.split_first()?;
+    match keyspace {
+        KEYSPACE_OID => Some(ParsedKey::Oid {
+            doc_name: terminated(rest)?,
+        }),
+        KEYSPACE_DOC => {
+            let (oid, rest) = split_oid(rest)?;
+            let (&sub, rest) = rest.split_first()?;
+            match (sub, rest) {
+                (SUB_DOC, []) => Some(ParsedKey::Doc { oid }),
+                (SUB_STATE_VEC, []) => Some(ParsedKey::StateVector { oid }),
+                (SUB_UPDATE, rest)
+                    if rest.len() == CLOCK_LEN + 1 && rest[CLOCK_LEN] == UPDATE_SEGMENT =>
+                {
+                    let base = u32::from_be_bytes(rest[..CLOCK_LEN].try_into().unwrap());
+                    Some(ParsedKey::UpdateSegment { oid, base })
+                }
+                (SUB_UPDATE, rest) if rest.len() == CLOCK_LEN + 1 => {
+                    let clock = terminated(rest)?;
+                    let clock = u32::from_be_bytes(clock.try_into().unwrap());
+                    Some(ParsedKey::Update { oid, clock })
+                }
+                (SUB_META, rest) => Some(ParsedKey::Meta {
+                    oid,
+                    name: terminated(rest)?,
+                }),
+                (SUB_PENDING, []) => Some(ParsedKey::Pending { oid }),
+                (SUB_SNAPSHOT, rest) => Some(ParsedKey::Snapshot {
+                    oid,
+                    label: terminated(rest)?,
+                }),
+                (SUB_REF, rest) => Some(ParsedKey::Ref {
+                    oid,
+                    name: terminated(rest)?,
+                }),
+                (SUB_SYNC_GEN, []) => Some(ParsedKey::SyncGen { oid }),
+                (SUB_CHANNEL, [channel, rest @ ..])
+                    if *channel != DEFAULT_CHANNEL && rest.len() == CLOCK_LEN + 1 =>
+                {
+                    let clock = terminated(rest)?;
+                    let clock = u32::from_be_bytes(clock.try_into().unwrap());
+                    Some(ParsedKey::ChannelUpdate {
+                        oid,
+                        channel: *channel,
+                        clock,
+                    })
+                }
+                (SUB_AUDIT, rest) if rest.len() == TIMESTAMP_LEN + 4 + 1 => {
+                    let rest = terminated(rest)?;
+                    let (time, n) = rest.split_at(TIMESTAMP_LEN);
+                    Some(ParsedKey::Audit {
+                        oid,
+                        time: decode_timestamp(time)?,
+                        n: u32::from_be_bytes(n.try_into().unwrap()),
+                    })
+                }
+                (SUB_SIZE_HISTORY, []) => Some(ParsedKey::SizeHistoryHead { oid }),
+                (SUB_SIZE_HISTORY, [slot]) => Some(ParsedKey::SizeSample { oid, slot: *slot }),
+                _ => None,
+            }
+        }
+        KEYSPACE_ARCHIVE => Some(ParsedKey::Archive {
+            oid: terminated_oid(rest)?,
+        }),
+        KEYSPACE_SETTINGS => Some(ParsedKey::Setting {
+            name: terminated(rest)?,
+        }),
+        KEYSPACE_COLLECTION => Some(ParsedKey::Collection {
+            path: terminated(rest)?,
+        }),
+        KEYSPACE_INTENT => Some(ParsedKey::Intent {
+            oid: terminated_oid(rest)?,
+        }),
+        KEYSPACE_REF => {
+            let (target, rest) = split_oid(rest)?;
+            let (&tag, rest) = rest.split_first()?;
+            match (tag, rest) {
+                (REF_TARGET, []) => Some(ParsedKey::RefTarget { oid: target }),
+                (REF_INBOUND, rest) => {
+                    let (source, rest) = split_oid(rest)?;
+                    Some(ParsedKey::RefInbound {
+                        target,
+                        source,
+                        name: terminated(rest)?,
+                    })
+                }
+                _ => None,
+            }
+        }
+        KEYSPACE_SYNC => {
+            let (oid, rest) = split_oid(rest)?;
+            let rest = terminated(rest)?;
+            if rest.len() != 12 {
+                return None;
+            }
+            let (generation, hash) = rest.split_at(4);
+            Some(ParsedKey::SyncFrame {
+                oid,
+                generation: u32::from_be_bytes(generation.try_into().unwrap()),
+                hash: u64::from_be_bytes(hash.try_into().unwrap()),
+            })
+        }
+        KEYSPACE_DIVERGED => Some(ParsedKey::Diverged {
+            doc_name: terminated(rest)?,
+        }),
+        KEYSPACE_GUID => Some(ParsedKey::Guid {
+            guid: terminated(rest)?,
+        }),
+        KEYSPACE_USAGE => Some(ParsedKey::Usage {
+            tenant: terminated(rest)?,
+        }),
+        KEYSPACE_FENCE => Some(ParsedKey::Fence {
+            doc_name: terminated(rest)?,
+        }),
+        KEYSPACE_FEED => {
+            let rest = terminated(rest)?;
+            if rest.len() != TIMESTAMP_LEN + OID_LEN + CLOCK_LEN {
+                return None;
+            }
+            let (time, rest) = rest.split_at(TIMESTAMP_LEN);
+            let (oid, seq) = rest.split_at(OID_LEN);
+            Some(ParsedKey::Feed {
+                time: decode_timestamp(time)?,
+                oid: OID::from_be_bytes(oid.try_into().unwrap()),
+                seq: u32::from_be_bytes(seq.try_into().unwrap()),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Builds a key of an entry described by a given `key`. This is an inverse of [parse_key].
+pub fn build_key(key: &ParsedKey) -> Vec<u8> {
+    match *key {
+        ParsedKey::Oid { doc_name } => key_oid(doc_name).into(),
+        ParsedKey::Doc { oid } => key_doc(oid).into(),
+        ParsedKey::StateVector { oid } => key_state_vector(oid).into(),
+        ParsedKey::Update { oid, clock } => key_update(oid, clock).into(),
+        ParsedKey::UpdateSegment { oid, base } => key_update_segment(oid, base).into(),
+        ParsedKey::Meta { oid, name } => key_meta(oid, name).into(),
+        ParsedKey::Pending { oid } => key_pending(oid).into(),
+        ParsedKey::Snapshot { oid, label } => key_snapshot(oid, label).into(),
+        ParsedKey::Archive { oid } => key_archive(oid).into(),
+        ParsedKey::Setting { name } => key_setting(name).into(),
+        ParsedKey::Collection { path } => key_collection(path).into(),
+        ParsedKey::Intent { oid } => key_intent(oid).into(),
+        ParsedKey::Ref { oid, name } => key_ref(oid, name).into(),
+        ParsedKey::RefTarget { oid } => key_ref_target(oid).into(),
+        ParsedKey::RefInbound {
+            target,
+            source,
+            name,
+        } => key_ref_inbound(target, source, name).into(),
+        ParsedKey::SyncGen { oid } => key_sync_gen(oid).into(),
+        ParsedKey::ChannelUpdate {
+            oid,
+            channel,
+            clock,
+        } => key_channel_update(oid, channel, clock).into(),
+        ParsedKey::Audit { oid, time, n } => key_audit(oid, time, n).into(),
+        ParsedKey::SizeHistoryHead { oid } => key_size_history_head(oid).into(),
+        ParsedKey::SizeSample { oid, slot } => key_size_history(oid, slot).into(),
+        ParsedKey::SyncFrame {
+            oid,
+            generation,
+            hash,
+        } => key_sync_frame(oid, generation, hash).into(),
+        ParsedKey::Diverged { doc_name } => key_diverged(doc_name).into(),
+        ParsedKey::Guid { guid } => key_guid(guid).into(),
+        ParsedKey::Usage { tenant } => key_usage(tenant).into(),
+        ParsedKey::Feed { time, oid, seq } => key_feed(time, oid, seq).into(),
+        ParsedKey::Fence { doc_name } => key_fence(doc_name).into(),
+    }
+}
+
+/// Strips [TERMINATOR] from the end of a given key `segment`.
+fn terminated(segment: &[u8]) -> Option<&[u8]> {
+    match segment.split_last() {
+        Some((&TERMINATOR, segment)) => Some(segment),
+        _ => None,
+    }
+}
+
+fn split_oid(key: &[u8]) -> Option<(OID, &[u8])> {
+    if key.len() < OID_LEN {
+        return None;
+    }
+    let (oid, rest) = key.split_at(OID_LEN);
+    Some((OID::from_be_bytes(oid.try_into().unwrap()), rest))
+}
+
+fn terminated_oid(key: &[u8]) -> Option<OID> {
+    match split_oid(key)? {
+        (oid, [TERMINATOR]) => Some(oid),
+        _ => None,
+    }
+}
+
+/// Document name built from a string, binary or numeric identifier. It can be used as a document
+/// name in all [DocOps](crate::DocOps) methods.
+///
+/// Numeric identifiers are stored as a tag byte ([DOC_KEY_U64], [DOC_KEY_U64_PAIR]) followed by
+/// their big endian bytes, so documents named by numbers of the same kind are ordered by their
+/// numeric values and documents sharing the first number of a pair occupy a contiguous range,
+/// which can be listed with [DocOps::iter_docs_prefix](crate::DocOps::iter_docs_prefix) using
+/// [DocKey::workspace] as a prefix. String and binary names are stored as they are. Binary names
+/// starting with a tag byte are reserved.
+///
+/// Numeric names may contain any byte, including [SEPARATOR](crate::collection::SEPARATOR), so
+/// they should not be used in [Collection](crate::collection::Collection) paths.
+///
+/// ```rust
+/// use yrs_kvstore::keys::{DocKey, ParsedDocKey};
+///
+/// let (a, b) = (DocKey::from(9u64), DocKey::from(10u64));
+/// assert!(a.as_ref() < b.as_ref());
+///
+/// let key = DocKey::from((7u64, 42u64));
+/// assert!(key.starts_with(&DocKey::workspace(7)));
+/// assert_eq!(DocKey::parse(&key), ParsedDocKey::U64Pair(7, 42));
+/// assert_eq!(DocKey::parse(b"my-doc"), ParsedDocKey::Bytes(b"my-doc"));
+/// ```
+#[repr(transparent)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DocKey(SmallVec<[u8; 20]>);
+
+impl DocKey {
+    /// Returns a prefix shared by names of all documents identified by a pair of numbers, which
+    /// first number is equal to `workspace`.
+    pub fn workspace(workspace: u64) -> Self {
+        let mut v: SmallVec<[u8; 20]> = smallvec![DOC_KEY_U64_PAIR];
+        v.write_all(&workspace.to_be_bytes()).unwrap();
+        DocKey(v)
+    }
+
+    /// Decodes binary document `name`, as returned by
+    /// [DocOps::iter_docs](crate::DocOps::iter_docs), using its tag byte to recognize numeric
+    /// identifiers. Any other name is returned as [ParsedDocKey::Bytes].
+    pub fn parse(name: &[u8]) -> ParsedDocKey<'_> {
+        match name.split_first() {
+            Some((&DOC_KEY_U64, id)) if id.len() == 8 => {
+                ParsedDocKey::U64(u64::from_be_bytes(id.try_into().unwrap()))
+            }
+            Some((&DOC_KEY_U64_PAIR, ids)) if ids.len() == 16 => {
+                let (workspace, id) = ids.split_at(8);
+                ParsedDocKey::U64Pair(
+                    u64::from_be_bytes(workspace.try_into().unwrap()),
+                    u64::from_be_bytes(id.try_into().unwrap()),
+                )
+            }
+            _ => ParsedDocKey::Bytes(name),
+        }
+    }
+}
+
+impl From<u64> for DocKey {
+    fn from(id: u64) -> Self {
+        let mut v: SmallVec<[u8; 20]> = smallvec![DOC_KEY_U64];
+        v.write_all(&id.to_be_bytes()).unwrap();
+        DocKey(v)
+    }
+}
+
+impl From<(u64, u64)> for DocKey {
+    fn from((workspace, id): (u64, u64)) -> Self {
+        let mut key = DocKey::workspace(workspace);
+        key.0.write_all(&id.to_be_bytes()).unwrap();
+        key
+    }
+}
+
+impl From<&str> for DocKey {
+    fn from(name: &str) -> Self {
+        DocKey(SmallVec::from_slice(name.as_bytes()))
+    }
+}
+
+impl From<&[u8]> for DocKey {
+    fn from(name: &[u8]) -> Self {
+        DocKey(SmallVec::from_slice(name))
+    }
+}
+
+impl Deref for DocKey {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl AsRef<[u8]> for DocKey {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+/// Document name decoded by [DocKey::parse].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParsedDocKey<'a> {
+    /// Document identified by a single number.
+    U64(u64),
+    /// Document identified by a pair of numbers, i.e. workspace and document id.
+    U64Pair(u64, u64),
+    /// Document identified by any other string or binary name.
+    Bytes(&'a [u8]),
+}
+
 #[repr(transparent)]
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Key<const N: usize>(SmallVec<[u8; N]>);
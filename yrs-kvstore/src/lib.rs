@@ -15,7 +15,8 @@
 //! - [KEYSPACE_OID] used for object ID (OID) index mapping. Whenever the new document is being
 //!   inserted, a new OID number is generated for it. While document names can be any kind of strings
 //!   OIDs are guaranteed to have constant size. Internally all of the document contents are referred
-//!   to via their OID identifiers.
+//!   to via their OID identifiers. OID entries of [drafts](draft) are marked with [OID_FLAG_DRAFT]
+//!   flag.
 //! - [KEYSPACE_DOC] used to store [document state](crate::keys::SUB_DOC), its
 //!   [state vector](crate::keys::SUB_STATE_VEC), corresponding series of
 //!   [updates](crate::keys::SUB_UPDATE) and [metadata](crate::keys::SUB_META). Document state and
@@ -24,32 +25,207 @@
 //!   binary read/parse/merge/store cycles of every update. It's a good idea to insert updates as they
 //!   come and every once in a while call [DocOps::flush_doc] or [DocOps::flush_doc_with] to merge
 //!   them into document state itself.
+//! - [KEYSPACE_ARCHIVE] used to store compressed state of documents moved out of [KEYSPACE_DOC]
+//!   using [DocOps::archive_doc]. Archived documents keep their OID and metadata, while OID entry
+//!   is marked with [OID_FLAG_ARCHIVED] flag.
+//! - [KEYSPACE_SETTINGS] used to store store-wide settings, like default document quota.
+//! - [KEYSPACE_COLLECTION] used to register [collections](collection::Collection) created with
+//!   [DocOps::create_collection]. Documents themselves are stored in [KEYSPACE_OID] under names
+//!   prefixed with their collection path.
+//! - [KEYSPACE_INTENT] used to record multi-key operations in progress, when
+//!   [StoreConfig::intent_log](config::StoreConfig::intent_log) is enabled.
 //!
-//! The variants and schemas of byte keys in use could be summarized as:
+//! Exact layout of keys and values is specified in [format] module.
 //!
-//! ```nocompile
-//! 00{doc_name:N}0      - OID key pattern
-//! 01{oid:4}0           - document key pattern
-//! 01{oid:4}1           - state vector key pattern
-//! 01{oid:4}2{seqNr:4}0 - document update key pattern
-//! 01{oid:4}3{name:M}0  - document meta key pattern
-//! ```
+//! ## Storage quotas
+//!
+//! Size of individual documents can be capped using [DocOps::set_default_max_doc_bytes] (applied
+//! to all documents) or [DocOps::set_max_doc_bytes] (applied to a single document). Document size
+//! is computed as a size of its stored state and all of its pending updates. Once set, both
+//! [DocOps::insert_doc] and [DocOps::push_update] refuse to write data that would exceed that
+//! quota, returning [Error::QuotaExceeded] instead.
+//!
+//! Sizes of documents can also be summed up per tenant, i.e. for billing, once tenants are
+//! assigned to documents by [DocOps::tenant_extractor]. See [usage] module for details.
+//!
+//! Large document states can be kept outside of the store, in a [BlobStore](blob::BlobStore)
+//! returned by [DocOps::blob_store]. See [blob] module for details.
+//!
+//! ## Configuration
+//!
+//! Behaviour of [DocOps] methods - like compression and checksums of stored values, limits on
+//! document names or automatic flushing of pending updates - is described by
+//! [StoreConfig](config::StoreConfig), returned from [DocOps::config]. Stores use the default
+//! configuration, unless they are wrapped into [ConfiguredStore](config::ConfiguredStore).
+//!
+//! ## Small footprint builds
+//!
+//! Compression (zstd) is enabled by the default `compression` feature. Builds which care about
+//! binary size - i.e. libraries embedded into mobile applications - can opt out of it with
+//! `default-features = false`. Such builds can still read and write values stored without
+//! compression, while compressed ones (including [archived](DocOps::archive_doc) documents) are
+//! reported with [Error::CompressionUnavailable].
+//!
+//! Hot paths have variants reusing a caller-provided scratch buffer instead of allocating a new
+//! one on every call: [DocOps::load_doc_with] and [DocOps::push_update_with]. The buffer is only
+//! written when values need to be encoded or decoded by [ValueCodec](config::ValueCodec), so with
+//! the default raw codec updates are written straight from - and read straight into - the slices
+//! given by the key-value store.
+//!
+//! ## Prelude
+//!
+//! Items used by most applications can be imported at once from [prelude]. Every backend crate
+//! re-exports it, so that code importing them from there doesn't depend on the backend.
 
+pub mod access;
+pub mod archive;
+pub mod audit;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod blob;
+#[cfg(feature = "cache")]
+pub mod cache;
+pub mod capabilities;
+mod channel;
+pub mod client_ids;
+pub mod clock;
+pub mod collection;
+#[cfg(feature = "conformance-tests")]
+pub mod compat;
+pub mod config;
+#[cfg(feature = "conformance-tests")]
+pub mod conformance;
+pub mod deadline;
+#[cfg(feature = "uuid")]
+pub mod doc_id;
+pub mod doc_txn;
+pub mod draft;
+pub mod dual;
 pub mod error;
+pub mod feed;
+pub mod format;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+pub mod guard;
+mod guid;
+mod intent;
+pub mod io_stats;
 pub mod keys;
+pub mod listing;
+pub mod maintenance;
+pub mod memo;
+pub mod merge;
+pub mod namespace;
+pub mod persist;
+pub mod prelude;
+pub mod refs;
+mod replace;
+mod segment;
+pub mod shard;
+pub mod size_history;
+pub mod split;
+#[cfg(feature = "stress-tests")]
+pub mod stress;
+pub mod sync_cache;
+pub mod usage;
+pub mod verify;
+pub mod version;
+pub mod worker;
+#[cfg(feature = "bench")]
+pub mod workload;
 
+use crate::access::{AccessStats, StaleDoc};
+use crate::audit::AuditRecord;
+use crate::blob::BlobStore;
+use crate::capabilities::Capabilities;
+use crate::client_ids::ClientCompaction;
+use crate::clock::{Clock, SystemClock};
+use crate::collection::{find_separator, Collection, SEPARATOR};
+use crate::config::{
+    crc32, CreatePolicy, FlushPolicy, StoreConfig, UpdateFraming, UpdateValidator, ValueCodec,
+};
+use crate::deadline::{Deadline, Progress};
 use crate::error::Error;
+use crate::feed::FeedEntry;
+use crate::format::{
+    decode_timestamp, encode_timestamp, CLOCK_LEN, OID_LEN, PENDING_LEN, STATE_VEC_SEQ_MARKER,
+};
+use crate::guard::ClearGuard;
+use crate::intent::Intent;
 use crate::keys::{
-    doc_oid_name, key_doc, key_doc_end, key_doc_start, key_meta, key_meta_end, key_meta_start,
-    key_oid, key_state_vector, key_update, Key, KEYSPACE_DOC, KEYSPACE_OID, OID, V1,
+    doc_meta_name, doc_oid_name, key_archive, key_channel_update, key_collection, key_doc,
+    key_doc_end, key_doc_start, key_fence, key_intent, key_meta, key_meta_end, key_meta_start,
+    key_oid, key_oid_part, key_pending, key_setting, key_snapshot, key_state_vector, key_update,
+    key_update_segment, Key, DEFAULT_CHANNEL, DOC_OPTION_SKIP_GC, KEYSPACE_COLLECTION,
+    KEYSPACE_DOC, KEYSPACE_INTENT, KEYSPACE_OID, META_DOC_OPTIONS, META_FENCE, META_GC,
+    META_INITIALIZED, META_LAST_MODIFIED, META_MAX_DOC_BYTES, OID, OID_FLAG_ARCHIVED,
+    OID_FLAG_DRAFT, SETTING_LAST_OID, SETTING_MAX_DOC_BYTES, SUB_CHANNEL, SUB_DOC, SUB_META,
+    SUB_UPDATE, V1,
 };
+use crate::listing::{DocListing, ListOptions};
+use crate::maintenance::{MaintenanceOptions, MaintenanceReport};
+use crate::merge::MergeStrategy;
+use crate::refs::{InboundRef, RefPolicy};
+use crate::size_history::SizeSample;
+use crate::split::SplitPolicy;
+use crate::usage::TenantExtractor;
+use crate::verify::SvDrift;
+use crate::version::DocVersion;
+use crate::worker::FlushCandidate;
+use std::cmp::Reverse;
+use std::collections::VecDeque;
 use std::convert::TryInto;
+use std::ops::{Deref, Range};
+use std::time::{Duration, Instant, SystemTime};
+use yrs::block::ClientID;
+use yrs::encoding::read::{Cursor, Read};
+use yrs::encoding::write::Write;
 use yrs::updates::decoder::Decode;
-use yrs::updates::encoder::Encode;
-use yrs::{Doc, ReadTxn, StateVector, Transact, TransactionMut, Update};
+use yrs::updates::encoder::{Encode, Encoder, EncoderV1};
+use yrs::{Doc, ReadTxn, Snapshot, StateVector, Transact, TransactionMut, Update};
 
 /// A trait to be implemented by the specific key-value store transaction equivalent in order to
 /// auto-implement features provided by [DocOps] trait.
+///
+/// # Read-your-own-writes
+///
+/// Implementations must make all writes performed through a given instance ([Self::upsert],
+/// [Self::remove] and [Self::remove_range]) visible to all subsequent reads performed through the
+/// same instance ([Self::get], [Self::get_many], [Self::iter_range], [Self::iter_range_rev] and
+/// [Self::peek_back]), even
+/// before they are committed. [DocOps] methods rely on it: i.e. [DocOps::push_update] reads the
+/// most recent update written earlier within the same transaction to assign a sequence number,
+/// and [DocOps::load_doc] called right after it is expected to include that update.
+///
+/// Implementations reading from a snapshot must still merge their own pending writes into the
+/// results. Write-only batches, which cannot be read back before they are applied, cannot be used
+/// as a [KVStore].
+///
+/// # Isolation
+///
+/// [DocOps::push_update] assigns sequence numbers by reading the last stored update and the
+/// pending updates counter of a document, which is read with [Self::get_for_update]. When write
+/// transactions run concurrently, two of them pushing into the same document must not both
+/// commit: otherwise both would write an update under the same key and one of the updates would
+/// be silently lost. Implementations must guarantee it in one of the following ways:
+///
+/// - by executing write transactions one at a time,
+/// - by locking keys read with [Self::get_for_update] until the end of the transaction,
+/// - by failing the commit of a transaction, which read a key with [Self::get_for_update] that
+///   has been written by another transaction committed in the meantime.
+///
+/// Reading from a snapshot alone is not enough, unless conflicting writes are detected at commit.
+/// Bundled backends provide these guarantees as follows:
+///
+/// | Backend                       | Concurrent writers | Guarantee                                |
+/// |-------------------------------|--------------------|------------------------------------------|
+/// | yrs-lmdb                      | no                 | one write transaction per environment    |
+/// | yrs-rocksdb (`TransactionDB`) | yes                | `get_for_update` locks a key till commit |
+/// | yrs-rocksdb (`with_snapshot`) | yes                | as above, fails if key changed since     |
+///
+/// A transaction waiting for a lock may fail with a timeout, in which case it can be retried.
+/// Implementations can be verified with the concurrency test suite available with
+/// `stress-tests` feature enabled.
 pub trait KVStore<'a> {
     /// Error type returned from the implementation.
     type Error: std::error::Error;
@@ -61,9 +237,35 @@ pub trait KVStore<'a> {
     /// abstractions over the binary data they use.
     type Return: AsRef<[u8]>;
 
+    /// Optional features supported by the implementation, which [DocOps] methods take advantage
+    /// of instead of falling back to the required methods (see [capabilities]). Defaults to
+    /// [Capabilities::NONE]. Implementations must not declare capabilities they don't provide:
+    /// declared ones are verified by the conformance test suite.
+    const CAPABILITIES: Capabilities = Capabilities::NONE;
+
     /// Return a value stored under given `key` or `None` if key was not found.
     fn get(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error>;
 
+    /// Return a value stored under given `key` or `None` if key was not found, locking that key
+    /// against writes from concurrent transactions until current transaction ends. This is used
+    /// to make sure that document is created only once and that concurrent pushes never assign
+    /// the same sequence number (see [isolation](KVStore#isolation) requirements).
+    /// Implementations which don't execute write transactions concurrently don't need to override
+    /// it. By default it calls [Self::get].
+    fn get_for_update(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+        self.get(key)
+    }
+
+    /// Return values stored under given `keys`, in the same order. Implementations supporting
+    /// batched lookups should override this method. By default it calls [Self::get] for every key.
+    fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, Self::Error> {
+        let mut result = Vec::with_capacity(keys.len());
+        for key in keys {
+            result.push(self.get(key)?.map(|value| value.as_ref().to_vec()));
+        }
+        Ok(result)
+    }
+
     /// Insert a new `value` under given `key` or replace an existing value with new one if
     /// entry with that `key` already existed.
     fn upsert(&self, key: &[u8], value: &[u8]) -> Result<(), Self::Error>;
@@ -71,18 +273,160 @@ pub trait KVStore<'a> {
     /// Return a value stored under the given `key` if it exists.
     fn remove(&self, key: &[u8]) -> Result<(), Self::Error>;
 
-    /// Remove all keys between `from`..=`to` range of keys.
-    fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<(), Self::Error>;
+    /// Remove all keys between `from`..=`to` range of keys. Returns the number of removed keys.
+    fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<u32, Self::Error>;
 
     /// Return an iterator over all entries between `from`..=`to` range of keys.
     fn iter_range(&self, from: &[u8], to: &[u8]) -> Result<Self::Cursor, Self::Error>;
 
-    /// Looks into the last entry value prior to a given key. The provided key parameter may not
-    /// exist and it's used only to establish cursor position in ordered key collection.
+    /// Return an iterator over all entries between `from`..=`to` range of keys. `mode` informs
+    /// the implementation about the purpose of the scan, so that i.e. large maintenance scans
+    /// don't evict caches serving interactive traffic. By default it's the same as
+    /// [Self::iter_range].
+    fn iter_range_with(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        mode: ScanMode,
+    ) -> Result<Self::Cursor, Self::Error> {
+        let _ = mode;
+        self.iter_range(from, to)
+    }
+
+    /// Calls `f` with the key and the value length of every entry between `from`..=`to` range of
+    /// keys, in ascending order, until `f` returns `false`. Used by scans which only need keys,
+    /// i.e. when looking for documents or counting their entries, so that values of visited
+    /// entries - possibly large document states - don't have to be read. Implementations whose
+    /// cursors copy values should override it. By default it iterates over [Self::iter_range].
+    fn iter_keys_range(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        f: &mut dyn FnMut(&[u8], usize) -> bool,
+    ) -> Result<(), Self::Error> {
+        for e in self.iter_range(from, to)? {
+            if !f(e.key(), e.value().len()) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls `f` with the key and the value of every entry between `from`..=`to` range of keys,
+    /// in descending order, until `f` returns `false`. Implementations able to walk their cursors
+    /// backwards should override it and declare [Capabilities::REVERSE_ITER]. By default it
+    /// collects the whole range with [Self::iter_range] and walks it from its end.
+    fn iter_range_rev(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        f: &mut dyn FnMut(&[u8], &[u8]) -> bool,
+    ) -> Result<(), Self::Error> {
+        let entries: Vec<_> = self
+            .iter_range(from, to)?
+            .take_while(|e| e.key() <= to)
+            .collect();
+        for e in entries.iter().rev() {
+            if !f(e.key(), e.value()) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks into the last entry value at or prior to a given key. The provided key parameter may
+    /// not exist and it's used only to establish cursor position in ordered key collection.
     ///
     /// In example: in a key collection of `{1,2,5,7}`, this method with the key parameter of `4`
-    /// should return value of `2`.
+    /// should return value of `2`, while with the key parameter of `5` it should return `5`.
+    ///
+    /// Implementations positioning the cursor directly (i.e. with a seek) should declare
+    /// [Capabilities::BOUNDED_PEEK_BACK]. Otherwise [DocOps] - which looks up the last update of
+    /// a document on every [DocOps::push_update] - scans update ranges instead of calling it.
     fn peek_back(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error>;
+
+    /// Looks into the first entry at or after a given key. Just like with [KVStore::peek_back],
+    /// the provided key may not exist.
+    ///
+    /// In example: in a key collection of `{1,2,5,7}`, this method with the key parameter of `4`
+    /// should return value of `5`, while with the key parameter of `8` it should return [None].
+    ///
+    /// Implementations are expected to position the cursor directly (i.e. with a seek), since
+    /// [DocOps] uses this method to look up single entries without scanning key ranges.
+    fn seek(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error>;
+
+    /// Returns isolation of reads performed through current store, reported by
+    /// [DocOps::read_snapshot]. Defaults to [ReadIsolation::BestEffort]. Implementations, which
+    /// serve all reads from a single point-in-time view of the database, should override it.
+    fn read_isolation(&self) -> ReadIsolation {
+        ReadIsolation::BestEffort
+    }
+
+    /// Returns current health of the underlying database, reported to callers of
+    /// [DocOps::push_update] through [PushReceipt::health], so that they can slow down producers
+    /// of updates before writes start to stall. Defaults to [None], meaning that the health is
+    /// unknown. Since it's called on every push, implementations should keep it cheap.
+    fn health_hint(&self) -> Option<HealthHint> {
+        None
+    }
+
+    /// Returns `true` if current store rejects writes, i.e. because it's bound to a read-only
+    /// transaction. [DocOps] methods which write only opportunistically (see
+    /// [StoreConfig::sv_read_repair]) skip their writes on such stores. Defaults to `false`.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+/// Hint about the purpose of a range scan, passed to [KVStore::iter_range_with].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScanMode {
+    /// Short scans serving regular document operations. Read data is expected to be reused soon.
+    #[default]
+    Interactive,
+    /// Long scans performed by maintenance operations, touching data which is unlikely to be
+    /// needed again soon. Implementations should avoid populating their caches with it.
+    Bulk,
+}
+
+/// Isolation of reads performed through a [KVStore], see [DocOps::read_snapshot].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadIsolation {
+    /// All reads observe the database as it was at a single point in time, plus the writes made
+    /// through the same store. Changes committed concurrently by other transactions are not
+    /// visible.
+    Snapshot,
+    /// Every read observes changes committed before it's performed, so reads of multiple
+    /// documents may observe the database at different points in time.
+    BestEffort,
+}
+
+/// Handle reading documents at a single logical instant, returned by [DocOps::read_snapshot].
+/// It dereferences to the store it has been created from.
+pub struct SnapshotHandle<'s, S> {
+    store: &'s S,
+    isolation: ReadIsolation,
+}
+
+impl<'s, S> SnapshotHandle<'s, S> {
+    /// Returns isolation of reads performed through current handle.
+    pub fn isolation(&self) -> ReadIsolation {
+        self.isolation
+    }
+
+    /// Returns `true` if all reads performed through current handle observe the same point in
+    /// time, so that invariants spanning multiple documents hold for what has been read.
+    pub fn is_consistent(&self) -> bool {
+        self.isolation == ReadIsolation::Snapshot
+    }
+}
+
+impl<'s, S> Deref for SnapshotHandle<'s, S> {
+    type Target = S;
+
+    fn deref(&self) -> &Self::Target {
+        self.store
+    }
 }
 
 /// Trait used by [KVStore] to define key-value entry tuples returned by cursor iterators.
@@ -93,14 +437,87 @@ pub trait KVEntry {
     fn value(&self) -> &[u8];
 }
 
+/// Durability guarantees requested from the key-value store when committing write transactions.
+/// It's not used by [DocOps] itself, but it's shared by store implementations, which map it onto
+/// their own sync settings. Relaxing durability can significantly reduce write latency, at the
+/// cost of losing the most recent commits in case of a crash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WriteDurability {
+    /// Every commit is flushed to persistent storage before returning. This is the default.
+    #[default]
+    Durable,
+    /// Commits are written, but not necessarily flushed to persistent storage before returning.
+    /// Store remains consistent, but the most recent commits may be lost on system crash.
+    Relaxed,
+    /// Commits are not flushed or written to write-ahead log at all. Data is persisted only after
+    /// clean close of the store or when the store decides to flush it on its own.
+    Volatile,
+}
+
+/// Documents returned by [DocOps::load_docs]: names paired with loaded documents, or `None` for
+/// names with no document stored.
+pub type LoadedDocs = Vec<(Vec<u8>, Option<Doc>)>;
+
 /// Trait used to automatically implement core operations over the Yrs document.
 pub trait DocOps<'a>: KVStore<'a> + Sized
 where
     Error: From<<Self as KVStore<'a>>::Error>,
 {
+    /// Returns configuration consulted by all other [DocOps] methods. By default it's
+    /// [StoreConfig::DEFAULT]. Use [ConfiguredStore](config::ConfiguredStore) to change it.
+    fn config(&self) -> &StoreConfig {
+        &StoreConfig::DEFAULT
+    }
+
+    /// Returns a validator consulted by [Self::push_update] before storing updates. By default
+    /// there's none, so updates are stored without being decoded. Use
+    /// [ConfiguredStore::with_validator](config::ConfiguredStore::with_validator) to install one.
+    fn update_validator(&self) -> Option<&dyn UpdateValidator> {
+        None
+    }
+
+    /// Returns an extractor deciding which tenants documents belong to, used to account their
+    /// storage usage (see [usage]). By default there's none, so usage is not accounted. Use
+    /// [ConfiguredStore::with_tenant_extractor](config::ConfiguredStore::with_tenant_extractor) to
+    /// install one.
+    fn tenant_extractor(&self) -> Option<&dyn TenantExtractor> {
+        None
+    }
+
+    /// Returns a store of blobs, which large document states are written into (see [blob]). By
+    /// default there's none, so all document states are stored inline. Use
+    /// [ConfiguredStore::with_blob_store](config::ConfiguredStore::with_blob_store) to install
+    /// one.
+    fn blob_store(&self) -> Option<&dyn BlobStore> {
+        None
+    }
+
+    /// Returns a source of the current time, read by all time-dependent features (see [clock]).
+    /// By default it's [SystemClock](clock::SystemClock). Use
+    /// [ConfiguredStore::with_clock](config::ConfiguredStore::with_clock) to install another one.
+    fn clock(&self) -> &dyn Clock {
+        &SystemClock
+    }
+
+    /// Returns optional features supported by current store, as declared by
+    /// [KVStore::CAPABILITIES] (see [capabilities]). [Capabilities::SNAPSHOTS] is reported only
+    /// if reads of this particular store are served from a snapshot (see
+    /// [KVStore::read_isolation]), i.e. RocksDB stores created without `with_snapshot` don't
+    /// report it.
+    fn capabilities(&self) -> Capabilities {
+        match self.read_isolation() {
+            ReadIsolation::Snapshot => Self::CAPABILITIES,
+            ReadIsolation::BestEffort => Self::CAPABILITIES.difference(Capabilities::SNAPSHOTS),
+        }
+    }
+
     /// Inserts or updates a document given it's read transaction and name. lib0 v1 encoding is
     /// used for storing the document.
     ///
+    /// The state vector of the document is removed before its state is written and stored again
+    /// afterwards, so that readers not isolated from this write never observe the new document
+    /// state together with a stale state vector (see [Self::verify_sv_consistency]).
+    ///
     /// This feature requires a write capabilities from the database transaction.
     fn insert_doc<K: AsRef<[u8]> + ?Sized, T: ReadTxn>(
         &self,
@@ -112,12 +529,90 @@ where
         self.insert_doc_raw_v1(name.as_ref(), &doc_state, &state_vector)
     }
 
+    /// Replaces the contents of a document with a given `name` by the contents of another document,
+    /// given its read transaction, i.e. one regenerated from an external source. Unlike
+    /// [Self::insert_doc], the replacement is stored as a regular update, so that replicas of the
+    /// document converge to the new contents once they receive it.
+    ///
+    /// The current document (including its pending updates) is loaded, all of its contents are
+    /// deleted and root types of `new_content_txn` are copied into it within a single
+    /// transaction. The update produced this way is appended using [Self::push_update] and merged
+    /// into document state with [Self::flush_doc]. Replacing a document which doesn't exist
+    /// creates it. Returns the lib0 v1 encoded update, which should be broadcast to the replicas.
+    ///
+    /// Texts, arrays, maps and their primitive values are copied. Formatting attributes and
+    /// embeds of texts are not. Content containing other shared types (i.e. XML types or
+    /// subdocuments) is refused with [Error::UnsupportedContent].
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn replace_doc<K: AsRef<[u8]> + ?Sized, T: ReadTxn>(
+        &self,
+        name: &K,
+        new_content_txn: &T,
+    ) -> Result<Vec<u8>, Error> {
+        let doc = Doc::new();
+        self.load_doc(name, &mut doc.transact_mut())?;
+        let update = replace::replace_content(&doc, new_content_txn)?;
+        self.push_update(name, &update)?;
+        self.flush_doc(name)?;
+        Ok(update)
+    }
+
+    /// Rewrites a document with a given `name` under a single, newly generated client ID, so that
+    /// its state vector lists just one client instead of every client which has ever edited it.
+    /// The compacted document replaces the stored state, and pending updates are removed. Returns
+    /// the sizes of state vectors before and after, or `None` if the document doesn't exist.
+    /// Documents with a single client are left intact. See [client_ids] module for details.
+    ///
+    /// **Compaction breaks every existing replica of the document.** Clients holding a copy of
+    /// it must discard that copy and reload the document, and changes of clients which were
+    /// offline during the compaction can no longer be merged. Only compact documents which no
+    /// client has open, once all offline clients have synced or their changes can be discarded.
+    ///
+    /// Documents with snapshots, pending channel updates or split into parts are refused with
+    /// [Error::CompactionRefused]. Content which cannot be copied (i.e. XML types, subdocuments
+    /// or text embeds) is refused with [Error::UnsupportedContent].
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn compact_client_ids<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<ClientCompaction>, Error> {
+        client_ids::compact(self, name.as_ref())
+    }
+
+    /// Merges contents of a document named `src` into a document named `dst`, as if they were
+    /// concurrent edits of the same document. Changes of `src` missing from `dst` are pushed to
+    /// `dst` as a single update, which is then flushed. Returns the lib0 v1 encoded update, which
+    /// should be broadcast to the replicas of `dst`. Metadata is merged and `src` optionally
+    /// removed according to a given `strategy`. See [merge] module for details.
+    ///
+    /// Returns [Error::DocNotFound] if `src` doesn't exist. Destination document is created if it
+    /// doesn't exist. Merging a document with itself leaves it intact and returns an empty update.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn merge_docs<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        src: &K1,
+        dst: &K2,
+        strategy: MergeStrategy,
+    ) -> Result<Vec<u8>, Error> {
+        merge::merge_docs(self, src.as_ref(), dst.as_ref(), strategy)
+    }
+
     /// Inserts or updates a document given it's binary update and state vector. lib0 v1 encoding is
     /// assumed as a format for storing the document.
     ///
     /// This is useful when you i.e. want to pre-serialize big document prior to acquiring
     /// a database transaction.
     ///
+    /// Store cannot tell if provided document state has been garbage collected. If it's going to be
+    /// used with [Self::encode_state_from_snapshot], it must come from a document created with
+    /// `skip_gc` option.
+    ///
+    /// Returns [Error::QuotaExceeded] if new document state together with its pending updates
+    /// would exceed document storage quota.
+    ///
     /// This feature requires a write capabilities from the database transaction.
     fn insert_doc_raw_v1(
         &self,
@@ -125,310 +620,3046 @@ where
         doc_state_v1: &[u8],
         doc_sv_v1: &[u8],
     ) -> Result<(), Error> {
-        let oid = get_or_create_oid(self, name)?;
-        insert_inner_v1(self, oid, doc_state_v1, doc_sv_v1)?;
-        Ok(())
+        let oid = lock_live_oid(self, name)?;
+        let usage = usage::Tracker::start(self, name)?;
+        let pending = match oid {
+            Some(oid) => get_pending(self, oid)?,
+            None => Pending::default(),
+        };
+        let codec = &self.config().codec;
+        let doc_state = codec.encode(doc_state_v1)?;
+        check_quota(self, oid, &pending, doc_state.len() as u64, true)?;
+        let oid = match oid {
+            Some(oid) => oid,
+            None => create_oid(self, name)?,
+        };
+        insert_inner(self, oid, &doc_state, &codec.encode(doc_sv_v1)?)?;
+        self.remove(&key_meta(oid, META_GC))?;
+        touch(self, oid)?;
+        usage.finish(self, Ok(()))
+    }
+
+    /// Inserts or updates a document, just like [Self::insert_doc]. The first time a document is
+    /// persisted this way, GUID of the `doc` ([Doc::guid]) is recorded, so that the document can
+    /// be found with [Self::doc_by_guid]. Recorded GUID is kept by subsequent calls, even if they
+    /// pass a [Doc] with a different one, and it's removed together with the document by
+    /// [Self::clear_doc].
+    ///
+    /// Returns [Error::GuidConflict] without writing anything if GUID of the `doc` has been
+    /// recorded for another document.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn insert_doc_with_guid<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        doc: &Doc,
+    ) -> Result<(), Error> {
+        let name = name.as_ref();
+        let doc_guid = doc.guid();
+        guid::check(self, name, doc_guid)?;
+        self.insert_doc(name, &doc.transact())?;
+        match get_oid(self, name)? {
+            Some(oid) => guid::assign(self, name, oid, doc_guid),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the name of a document, which GUID has been recorded by
+    /// [Self::insert_doc_with_guid], or `None` if there's no such document.
+    ///
+    /// This feature requires only a read capabilities from the database transaction.
+    fn doc_by_guid(&self, guid: &str) -> Result<Option<Vec<u8>>, Error> {
+        guid::lookup(self, guid)
+    }
+
+    /// Returns GUID recorded by [Self::insert_doc_with_guid] for a document with a given `name`,
+    /// or `None` if document doesn't exist or has been persisted without it.
+    ///
+    /// This feature requires only a read capabilities from the database transaction.
+    fn guid_of<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<Option<String>, Error> {
+        match get_oid(self, name.as_ref())? {
+            Some(oid) => guid::get(self, oid),
+            None => Ok(None),
+        }
     }
 
     /// Loads the document state stored in current database under given document `name` into
     /// in-memory Yrs document using provided [TransactionMut]. This includes potential update
     /// entries that may not have been merged with the main document state yet.
     ///
+    /// Stored document state and update entries, which blocks are all covered by the state vector
+    /// `txn` had before the call (i.e. because the document has been loaded into it already), are
+    /// not integrated again: only their deletions are applied. This way loading a document into
+    /// a transaction which already holds most of it applies just the missing suffix. See
+    /// [LoadResult] for how many entries have been skipped.
+    ///
+    /// Returns [Error::DocArchived] if document has been archived using [Self::archive_doc].
+    ///
     /// This feature requires only a read capabilities from the database transaction.
     fn load_doc<K: AsRef<[u8]> + ?Sized>(
         &self,
         name: &K,
         txn: &mut TransactionMut,
     ) -> Result<bool, Error> {
-        if let Some(oid) = get_oid(self, name.as_ref())? {
-            let loaded = load_doc(self, oid, txn)?;
-            Ok(loaded != 0)
-        } else {
-            Ok(false)
+        Ok(self.load_doc_checked(name, txn)?.found)
+    }
+
+    /// Same as [Self::load_doc], but decodes stored values into a given `scratch` buffer, which
+    /// can be reused across calls to avoid allocating a new one every time a value needs to be
+    /// decompressed. Contents of the buffer on return are unspecified.
+    ///
+    /// This feature requires only a read capabilities from the database transaction.
+    fn load_doc_with<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        txn: &mut TransactionMut,
+        scratch: &mut Vec<u8>,
+    ) -> Result<bool, Error> {
+        match load_live_doc(self, name.as_ref(), txn, scratch)? {
+            Some((_, loaded)) => Ok(loaded.found()),
+            None => Ok(false),
         }
     }
 
-    /// Merges all updates stored via [Self::push_update] that were detached from the main document
-    /// state, updates the document and its state vector and finally prunes the updates that have
-    /// been integrated this way. Returns the [Doc] with the most recent state produced this way.
+    /// Same as [Self::load_doc], but also checks if all loaded updates could be integrated.
     ///
-    /// This feature requires a write capabilities from the database transaction.
-    fn flush_doc<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<Option<Doc>, Error> {
-        self.flush_doc_with(name, yrs::Options::default())
+    /// Updates which depend on changes the document has never received (i.e. because a client
+    /// pushed them out of order and the update preceding them has been lost) cannot be
+    /// integrated. Yrs keeps them aside, so they don't show up in the document contents, its
+    /// state vector or diffs computed from it. [LoadResult::missing] describes the changes they
+    /// wait for. See also [Self::missing_ranges].
+    ///
+    /// Documents, which have a stored state vector but no stored document state (i.e. because
+    /// the state has been removed by a manual cleanup), are loaded from their pending updates
+    /// only. If the stored state vector claims changes missing from the loaded document, it's
+    /// reported as [LoadResult::orphaned_sv]. See [Self::repair_drop_state_vector].
+    ///
+    /// This feature requires only a read capabilities from the database transaction.
+    fn load_doc_checked<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        txn: &mut TransactionMut,
+    ) -> Result<LoadResult, Error> {
+        match load_live_doc(self, name.as_ref(), txn, &mut Vec::new())? {
+            Some((oid, loaded)) => {
+                let orphaned_sv = if loaded.doc_state {
+                    None
+                } else {
+                    orphaned_state_vector(self, oid, txn)?
+                };
+                Ok(LoadResult {
+                    found: loaded.found(),
+                    missing: missing_updates(txn),
+                    orphaned_sv,
+                    state_skipped: loaded.state_skipped,
+                    updates_applied: loaded.updates - loaded.updates_skipped,
+                    updates_skipped: loaded.updates_skipped,
+                })
+            }
+            None => Ok(LoadResult {
+                found: false,
+                missing: None,
+                orphaned_sv: None,
+                state_skipped: false,
+                updates_applied: 0,
+                updates_skipped: 0,
+            }),
+        }
     }
 
-    /// Merges all updates stored via [Self::push_update] that were detached from the main document
-    /// state, updates the document and its state vector and finally prunes the updates that have
-    /// been integrated this way. `options` are used to drive the details of integration process.
-    /// Returns the [Doc] with the most recent state produced this way, initialized using
-    /// `options` parameter.
+    /// Same as [Self::load_doc], but bounds the memory taken by pending updates of documents with
+    /// long update logs. Pending updates are read in batches of up to `budget_bytes` (measured by
+    /// the size of their lib0 v1 encoding). Every batch is merged into a single update and applied
+    /// before the next one is read, so that only a single batch of decoded updates is kept in
+    /// memory at a time, while the number of applied updates is still reduced. Updates larger
+    /// than the budget are applied on their own.
     ///
-    /// This feature requires a write capabilities from the database transaction.
-    fn flush_doc_with<K: AsRef<[u8]> + ?Sized>(
+    /// Returns the number of applied batches, which can be used to tune the budget, or `None` if
+    /// there was no document stored under a given `name`. Documents without pending updates are
+    /// loaded using `0` batches. Updates pushed to channels other than the default one (see
+    /// [Self::push_update_channel]) are applied one by one after all batches.
+    ///
+    /// Returns [Error::DocArchived] if document has been archived using [Self::archive_doc].
+    ///
+    /// This feature requires only a read capabilities from the database transaction.
+    fn load_doc_budgeted<K: AsRef<[u8]> + ?Sized>(
         &self,
         name: &K,
-        options: yrs::Options,
-    ) -> Result<Option<Doc>, Error> {
-        if let Some(oid) = get_oid(self, name.as_ref())? {
-            let doc = flush_doc(self, oid, options)?;
-            Ok(doc)
+        txn: &mut TransactionMut,
+        budget_bytes: usize,
+    ) -> Result<Option<u32>, Error> {
+        if let Some(oid) = get_live_oid(self, name.as_ref())? {
+            let batches = load_doc_batched(self, oid, txn, budget_bytes)
+                .map_err(|e| e.in_doc(name.as_ref()))?;
+            match batches {
+                Some(batches) => {
+                    access::record_access(self, oid)?;
+                    Ok(Some(batches))
+                }
+                None => Ok(None),
+            }
         } else {
             Ok(None)
         }
     }
 
-    /// Returns the [StateVector] stored directly for the document with a given `name`.
-    /// Returns `None` if the state vector was not stored.
+    /// Loads the document stored under a given `name` the way it was right after its pending
+    /// update with a given sequence number `seq` has been stored: document state is applied,
+    /// followed only by the pending updates with sequence numbers not greater than `seq`. Together
+    /// with [Self::last_update_seq] it can be used to browse recent history of a document without
+    /// taking snapshots. Updates pushed to channels other than the default one (see
+    /// [Self::push_update_channel]) are not applied.
     ///
-    /// Keep in mind that this method only returns a state vector that's stored directly. A second
-    /// tuple parameter boolean informs if returned value is up to date. If that's not the case, it
-    /// means that state vector exists but must be recalculated from the collection of persisted
-    /// updates using either [Self::load_doc] (read-only) or [Self::flush_doc] (read-write).
+    /// Only the history since the last flush can be reached, since [Self::flush_doc] merges
+    /// pending updates into document state. If `seq` precedes the updates merged by the last
+    /// flush, document is loaded as of that flush and [SeqLoad::ClampedToFlushBase] is returned.
+    /// Returns `None` if there was no document stored under a given `name`.
     ///
-    /// This feature requires only the read capabilities from the database transaction.
-    fn get_state_vector<K: AsRef<[u8]> + ?Sized>(
+    /// Returns [Error::DocArchived] if document has been archived using [Self::archive_doc].
+    ///
+    /// This feature requires only a read capabilities from the database transaction.
+    fn load_doc_at_seq<K: AsRef<[u8]> + ?Sized>(
         &self,
         name: &K,
-    ) -> Result<(Option<StateVector>, bool), Error> {
-        if let Some(oid) = get_oid(self, name.as_ref())? {
-            let key = key_state_vector(oid);
-            let data = self.get(&key)?;
-            let sv = if let Some(data) = data {
-                let state_vector = StateVector::decode_v1(data.as_ref())?;
-                Some(state_vector)
-            } else {
-                None
-            };
-            let update_range_start = key_update(oid, 0);
-            let update_range_end = key_update(oid, u32::MAX);
-            let mut iter = self.iter_range(&update_range_start, &update_range_end)?;
-            let up_to_date = iter.next().is_none();
-            Ok((sv, up_to_date))
-        } else {
-            Ok((None, true))
+        seq: u32,
+        txn: &mut TransactionMut,
+    ) -> Result<Option<SeqLoad>, Error> {
+        match get_live_oid(self, name.as_ref())? {
+            Some(oid) => load_doc_at_seq(self, oid, seq, txn).map_err(|e| e.in_doc(name.as_ref())),
+            None => Ok(None),
         }
     }
 
-    /// Appends new update without integrating it directly into document store (which is faster
-    /// than persisting full document state on every update). Updates are assumed to be serialized
-    /// using lib0 v1 encoding.
+    /// Returns the sequence number of the most recent pending update of a document stored under
+    /// a given `name`, or `None` if it has no pending updates. Sequence numbers start over once
+    /// pending updates are merged by [Self::flush_doc].
     ///
-    /// Returns a sequence number of a stored update. Once updates are integrated into document and
-    /// pruned (using [Self::flush_doc] method), sequence number is reset.
+    /// This feature requires only a read capabilities from the database transaction.
+    fn last_update_seq<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<Option<u32>, Error> {
+        match get_oid(self, name.as_ref())? {
+            Some(oid) => last_seq(self, oid),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the sequence number of the oldest pending update of a document stored under a given
+    /// `name`, or `None` if it has no pending updates. Together with [Self::last_update_seq] it
+    /// gives the range of sequence numbers which can be passed to [Self::load_doc_at_seq] without
+    /// being clamped to the last flush.
     ///
-    /// This feature requires a write capabilities from the database transaction.
-    fn push_update<K: AsRef<[u8]> + ?Sized>(&self, name: &K, update: &[u8]) -> Result<u32, Error> {
-        let oid = get_or_create_oid(self, name.as_ref())?;
-        let last_clock = {
-            let end = key_update(oid, u32::MAX);
-            if let Some(e) = self.peek_back(&end)? {
-                let last_key = e.key();
-                let len = last_key.len();
-                let last_clock = &last_key[(len - 5)..(len - 1)]; // update key scheme: 01{name:n}1{clock:4}0
-                u32::from_be_bytes(last_clock.try_into().unwrap())
-            } else {
-                0
+    /// This feature requires only a read capabilities from the database transaction.
+    fn first_update_seq<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<Option<u32>, Error> {
+        match get_oid(self, name.as_ref())? {
+            Some(oid) => Ok(first_update(self, oid)?.map(|e| segment::base_seq(e.key()))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns a version token of a document stored under a given `name`, or `None` if document
+    /// doesn't exist. Version changes with every write changing the contents of the document,
+    /// including [Self::push_update], [Self::flush_doc] and [Self::clear_doc], which makes it
+    /// suitable for HTTP entity tags. It's derived from the stored state vector and the sequence
+    /// number of the last pending update, without reconstructing the document. See [version]
+    /// module for details.
+    ///
+    /// Returns [Error::DocArchived] if document has been archived using [Self::archive_doc].
+    ///
+    /// This feature requires only a read capabilities from the database transaction.
+    fn doc_version<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<Option<DocVersion>, Error> {
+        match get_live_oid(self, name.as_ref())? {
+            Some(oid) => version::doc_version(self, oid).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns a handle reading multiple documents at a single logical instant, i.e. in order to
+    /// export them with [export_docs_consistent](archive::export_docs_consistent).
+    ///
+    /// Snapshot is established by the underlying transaction, so this method only reports whether
+    /// its reads are isolated from concurrent commits (see [SnapshotHandle::isolation]):
+    ///
+    /// - LMDB transactions always read from a snapshot.
+    /// - RocksDB transactions read from a snapshot when created with `RocksDBStore::with_snapshot`,
+    ///   which binds it into read options of all reads.
+    /// - Other stores fall back to [ReadIsolation::BestEffort] unless they override
+    ///   [KVStore::read_isolation]. Every document is then read consistently on its own, but
+    ///   documents may come from different points in time.
+    fn read_snapshot(&self) -> SnapshotHandle<'_, Self> {
+        SnapshotHandle {
+            store: self,
+            isolation: self.read_isolation(),
+        }
+    }
+
+    /// Loads multiple documents with given `names` at once, using a single database transaction.
+    /// Returns a list of document names paired with [Doc]s restored from their persisted state
+    /// (or `None` if there was no document stored under that name), in the same order as
+    /// provided `names`.
+    ///
+    /// OIDs of all documents are resolved up front - using [KVStore::get_many] if the store
+    /// declares [Capabilities::MULTI_GET] - which makes this method more efficient than loading
+    /// documents one by one, i.e. when warming up a server.
+    ///
+    /// Returns [Error::DocArchived] if any of the documents has been archived using
+    /// [Self::archive_doc].
+    ///
+    /// This feature requires only a read capabilities from the database transaction.
+    fn load_docs<'x, I>(&self, names: I) -> Result<LoadedDocs, Error>
+    where
+        I: IntoIterator<Item = &'x [u8]>,
+    {
+        let names: Vec<&[u8]> = names.into_iter().collect();
+        let keys: Vec<_> = names.iter().map(|name| key_oid(name)).collect();
+        let keys: Vec<&[u8]> = keys.iter().map(|key| key.as_ref()).collect();
+        let entries = if Self::CAPABILITIES.contains(Capabilities::MULTI_GET) {
+            self.get_many(&keys)?
+        } else {
+            let mut entries = Vec::with_capacity(keys.len());
+            for key in keys.iter() {
+                entries.push(self.get(key)?.map(|value| value.as_ref().to_vec()));
             }
+            entries
         };
-        let clock = last_clock + 1;
-        let update_key = key_update(oid, clock);
-        self.upsert(&update_key, &update)?;
-        Ok(clock)
+        let mut result = Vec::with_capacity(names.len());
+        for (name, entry) in names.into_iter().zip(entries) {
+            let doc = match entry {
+                Some(value) => {
+                    let (oid, flags) = oid_value(&value)?;
+                    if flags & OID_FLAG_ARCHIVED != 0 {
+                        return Err(Error::DocArchived);
+                    }
+                    let doc = Doc::new();
+                    let loaded = load_doc(self, oid, &mut doc.transact_mut())?;
+                    if loaded.doc_state || loaded.updates != 0 {
+                        access::record_access(self, oid)?;
+                        Some(doc)
+                    } else {
+                        None
+                    }
+                }
+                None => None,
+            };
+            result.push((name.to_vec(), doc));
+        }
+        Ok(result)
     }
 
-    /// Returns an update (encoded using lib0 v1 encoding) which contains all new changes that
-    /// happened since provided state vector for a given document.
+    /// Loads the document stored under a given `name` (including its pending updates) into
+    /// a scratch [Doc] and passes it to a given function `f`, returning its result. Returns `None`
+    /// if there was no document stored under that name.
     ///
-    /// This feature requires only the read capabilities from the database transaction.
-    fn get_diff<K: AsRef<[u8]> + ?Sized>(
-        &self,
-        name: &K,
-        sv: &StateVector,
-    ) -> Result<Option<Vec<u8>>, Error> {
+    /// Returns [Error::DocArchived] if document has been archived using [Self::archive_doc].
+    ///
+    /// This feature requires only a read capabilities from the database transaction.
+    fn with_doc<K, F, R>(&self, name: &K, f: F) -> Result<Option<R>, Error>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        F: FnOnce(&Doc) -> R,
+    {
         let doc = Doc::new();
-        let found = {
-            let mut txn = doc.transact_mut();
-            self.load_doc(name, &mut txn)?
-        };
-        if found {
-            Ok(Some(doc.transact().encode_diff_v1(sv)))
+        if self.load_doc(name, &mut doc.transact_mut())? {
+            Ok(Some(f(&doc)))
         } else {
             Ok(None)
         }
     }
 
-    /// Removes all data associated with the current document (including its updates and metadata).
+    /// Returns sorted names of root types (texts, maps, arrays etc.) existing in the document
+    /// stored under a given `name`. Returns an empty list if there was no document stored under
+    /// that name.
+    ///
+    /// Returns [Error::DocArchived] if document has been archived using [Self::archive_doc].
+    ///
+    /// This feature requires only a read capabilities from the database transaction.
+    fn get_root_type_names<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<Vec<String>, Error> {
+        let names = self.with_doc(name, |doc| {
+            let txn = doc.transact();
+            let mut names: Vec<String> =
+                txn.root_refs().map(|(name, _)| name.to_string()).collect();
+            names.sort();
+            names
+        })?;
+        Ok(names.unwrap_or_default())
+    }
+
+    /// Merges all updates stored via [Self::push_update] that were detached from the main document
+    /// state, updates the document and its state vector and finally prunes the updates that have
+    /// been integrated this way. Returns a [FlushOutcome] with the [Doc] containing the most recent
+    /// state produced this way, or `None` if there were no pending updates to merge.
+    ///
+    /// Document is flushed using the options of its most recent [Self::flush_doc_with] call, so
+    /// documents once flushed with `skip_gc` option keep their deleted content. Documents which
+    /// have never been flushed with explicit options use [yrs::Options::default].
     ///
     /// This feature requires a write capabilities from the database transaction.
-    fn clear_doc<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<(), Error> {
-        let oid_key = key_oid(name.as_ref());
-        if let Some(oid) = self.get(&oid_key)? {
-            // all document related elements are stored within bounds [0,1,..oid,0]..[0,1,..oid,255]
-            let oid: [u8; 4] = oid.as_ref().try_into().unwrap();
-            let oid = OID::from_be_bytes(oid);
-            self.remove(&oid_key)?;
-            let start = key_doc_start(oid);
-            let end = key_doc_end(oid);
-            for v in self.iter_range(&start, &end)? {
-                let key: &[u8] = v.key();
-                if key > &end {
-                    break; //TODO: for some reason key range doesn't always work
-                }
-                self.remove(&key)?;
-            }
+    fn flush_doc<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<Option<FlushOutcome>, Error> {
+        if let Some(oid) = get_live_oid(self, name.as_ref())? {
+            let options = doc_options(self, oid)?;
+            usage::track(self, name.as_ref(), || flush_doc(self, oid, options))
+                .map_err(|e| e.in_doc(name.as_ref()))
+        } else {
+            Ok(None)
         }
-        Ok(())
     }
 
-    /// Returns a metadata value stored under its metadata `key` for a document with given `name`.
+    /// Merges all updates stored via [Self::push_update] that were detached from the main document
+    /// state, updates the document and its state vector and finally prunes the updates that have
+    /// been integrated this way. `options` are used to drive the details of integration process.
+    /// Returns a [FlushOutcome] with the [Doc] containing the most recent state produced this way,
+    /// initialized using `options` parameter, or `None` if there were no pending updates to merge.
     ///
-    /// This feature requires only the read capabilities from the database transaction.
-    fn get_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+    /// Unless `options` have `skip_gc` set, merged document state no longer contains deleted
+    /// content and cannot be used with [Self::encode_state_from_snapshot] afterwards. `skip_gc`
+    /// option is persisted together with the document and used by subsequent flushes and reads
+    /// which don't specify options explicitly (see [Self::flush_doc] and [Self::get_diff]).
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn flush_doc_with<K: AsRef<[u8]> + ?Sized>(
         &self,
-        name: &K1,
-        meta_key: &K2,
-    ) -> Result<Option<Self::Return>, Error> {
-        if let Some(oid) = get_oid(self, name.as_ref())? {
-            let key = key_meta(oid, meta_key.as_ref());
-            Ok(self.get(&key)?)
+        name: &K,
+        options: yrs::Options,
+    ) -> Result<Option<FlushOutcome>, Error> {
+        if let Some(oid) = get_live_oid(self, name.as_ref())? {
+            usage::track(self, name.as_ref(), || flush_doc(self, oid, options))
+                .map_err(|e| e.in_doc(name.as_ref()))
         } else {
             Ok(None)
         }
     }
 
-    /// Inserts or updates new `meta` value stored under its metadata `key` for a document with
-    /// given `name`.
+    /// Works like [Self::flush_doc], but once the compacted document state has been written, calls
+    /// a `hook` with the reconstructed [Doc] and this store handle. Writes made by the `hook` go
+    /// through the same database transaction as the flush itself, which makes it a good place to
+    /// maintain data derived from the document contents (i.e. search indexes) next to it.
     ///
-    /// This feature requires write capabilities from the database transaction.
-    fn insert_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+    /// An error returned by the `hook` is returned from this method as well. In that case caller
+    /// should not commit the database transaction, so that neither the flush nor the writes made by
+    /// the `hook` are persisted. The `hook` is not called when there were no pending updates to
+    /// merge, as document contents didn't change since the last flush.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn flush_doc_with_hook<K, F>(&self, name: &K, hook: F) -> Result<Option<FlushOutcome>, Error>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        F: FnOnce(&Doc, &Self) -> Result<(), Error>,
+    {
+        let outcome = self.flush_doc(name)?;
+        if let Some(outcome) = &outcome {
+            hook(&outcome.doc, self)?;
+        }
+        Ok(outcome)
+    }
+
+    /// Same as [Self::flush_doc], but refuses to flush the document with [Error::Fenced] if a given
+    /// fencing `token` is older than the one stored for the document (see [Self::set_fence]).
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn flush_doc_fenced<K: AsRef<[u8]> + ?Sized>(
         &self,
-        name: &K1,
-        meta_key: &K2,
-        meta: &[u8],
+        name: &K,
+        token: u64,
+    ) -> Result<Option<FlushOutcome>, Error> {
+        self.check_fence(name, token)?;
+        self.flush_doc(name)
+    }
+
+    /// Performs the CPU-heavy part of [Self::flush_doc] without writing anything: reconstructs
+    /// the document with a given `name` from its stored state and pending updates. Returned
+    /// [PreparedFlush] can be written with [Self::apply_flush] by another transaction, so that
+    /// documents can be reconstructed on many threads using read-only transactions, while only
+    /// the writes are serialized, i.e. by stores which allow a single write transaction at a time.
+    ///
+    /// Returns `None` if document doesn't exist or it has no pending updates to merge.
+    ///
+    /// This feature requires only a read capabilities from the database transaction.
+    fn prepare_flush<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<PreparedFlush>, Error> {
+        let name = name.as_ref();
+        let oid = match get_live_oid(self, name)? {
+            Some(oid) => oid,
+            None => return Ok(None),
+        };
+        // version is read first, so that updates pushed in the meantime change it
+        let version = version::doc_version(self, oid)?;
+        let reconstructed =
+            reconstruct(self, oid, doc_options(self, oid)?).map_err(|e| e.in_doc(name))?;
+        Ok(reconstructed.map(|reconstructed| PreparedFlush {
+            version,
+            reconstructed,
+        }))
+    }
+
+    /// Writes a document with a given `name` reconstructed by [Self::prepare_flush], with the same
+    /// outcome as [Self::flush_doc] called instead. If the document has been changed since it's
+    /// been reconstructed, [PreparedFlush] is discarded and the document is flushed from scratch.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn apply_flush<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        prepared: PreparedFlush,
+    ) -> Result<Option<FlushOutcome>, Error> {
+        if let Some(oid) = get_live_oid(self, name.as_ref())? {
+            usage::track(self, name.as_ref(), || apply_prepared(self, oid, prepared))
+                .map_err(|e| e.in_doc(name.as_ref()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Merges updates pushed to a given `channel` (see [Self::push_update_channel]) into the
+    /// document state and prunes them, leaving the updates of all other channels pending. Flushing
+    /// [DEFAULT_CHANNEL] is the same as calling [Self::flush_doc]. Returns a [FlushOutcome] with
+    /// the [Doc] containing the document state together with the merged updates, or `None` if
+    /// there were no updates pushed to that channel.
+    ///
+    /// Document is flushed using the options of its most recent [Self::flush_doc_with] call.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn flush_channel<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        channel: u8,
+    ) -> Result<Option<FlushOutcome>, Error> {
+        if channel == DEFAULT_CHANNEL {
+            return self.flush_doc(name);
+        }
+        if let Some(oid) = get_live_oid(self, name.as_ref())? {
+            let options = doc_options(self, oid)?;
+            usage::track(self, name.as_ref(), || {
+                channel::flush(self, oid, channel, options)
+            })
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns the [StateVector] stored directly for the document with a given `name`.
+    /// Returns `None` if the state vector was not stored.
+    ///
+    /// Keep in mind that this method only returns a state vector that's stored directly. A second
+    /// tuple parameter boolean informs if returned value is up to date. It's the case when there
+    /// are no pending updates or when stored state vector covers all client clocks of the most
+    /// recent update stored via [Self::push_update] (i.e. because it was stored afterwards using
+    /// [Self::put_state_vector]). If that's not the case, it means that state vector must be
+    /// recalculated from the collection of persisted updates using either [Self::load_doc]
+    /// (read-only) or [Self::flush_doc] (read-write). State vectors stored by
+    /// [Self::state_vector_force] are up to date only as long as no update has been pushed since.
+    /// Updates pushed to other channels (see [Self::push_update_channel]) are checked the same
+    /// way, using the most recent update of each channel.
+    ///
+    /// If [StoreConfig::sv_read_repair] is set, state vectors missing for stored document states
+    /// are computed from the clocks of their blocks and, unless the store is read-only (see
+    /// [KVStore::is_read_only]), written back.
+    ///
+    /// This feature requires only the read capabilities from the database transaction, unless
+    /// [StoreConfig::sv_read_repair] is set.
+    fn get_state_vector<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<(Option<StateVector>, bool), Error> {
+        if let Some(oid) = get_live_oid(self, name.as_ref())? {
+            let key = key_state_vector(oid);
+            let data = self.get(&key)?;
+            let (sv, covered_seq) = if let Some(data) = data {
+                let data = self.config().codec.decode(data.as_ref())?;
+                let (state_vector, covered_seq) = decode_state_vector(&data)?;
+                (Some(state_vector), covered_seq)
+            } else if self.config().sv_read_repair {
+                let state_vector = doc_state_sv(self, oid).map_err(|e| e.in_doc(name.as_ref()))?;
+                if let (Some(sv), false) = (&state_vector, self.is_read_only()) {
+                    // state vector without a marker covers only the document state
+                    let data = self.config().codec.encode(&sv.encode_v1())?.into_owned();
+                    self.upsert(&key, &data)?;
+                }
+                (state_vector, None)
+            } else {
+                (None, None)
+            };
+            let last_update = last_update(self, oid)?;
+            let up_to_date = match (&sv, last_update) {
+                (_, None) => true,
+                (Some(_), Some(e)) if covered_seq.is_some() => {
+                    covered_seq == Some(segment::last_seq(e.key(), e.value())?)
+                }
+                (Some(sv), Some(e)) => {
+                    let (seq, update) = segment::last_record(e.key(), e.value())?;
+                    let update = self.config().codec.decode(update)?;
+                    let update = decode_update(Some(seq), &update)
+                        .map_err(|err| err.in_doc(name.as_ref()))?;
+                    sv_covered_by(&update.state_vector(), sv)
+                }
+                (None, Some(_)) => false,
+            };
+            let up_to_date = up_to_date
+                && match &sv {
+                    Some(sv) => {
+                        let mut covered = true;
+                        for e in channel::last_updates(self, oid)? {
+                            let update = self.config().codec.decode(e.value())?;
+                            let update = decode_update(None, &update)
+                                .map_err(|err| err.in_doc(name.as_ref()))?;
+                            covered &= sv_covered_by(&update.state_vector(), sv);
+                        }
+                        covered
+                    }
+                    None => !channel::has_updates(self, oid)?,
+                };
+            Ok((sv, up_to_date))
+        } else {
+            Ok((None, true))
+        }
+    }
+
+    /// Returns an up to date state vector of a document with a given `name` or `None` if document
+    /// doesn't exist. Unlike [Self::get_state_vector], if the stored state vector is missing or
+    /// doesn't cover all pending updates, the document is reconstructed from its state and pending
+    /// updates and its state vector is written back, together with the sequence number of the
+    /// last update it covers. Subsequent calls return the stored state vector without loading the
+    /// document, until another update is pushed. Documents with updates pushed to channels other
+    /// than the default one (see [Self::push_update_channel]) are reconstructed on every call.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn state_vector_force<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<StateVector>, Error> {
+        let oid = match get_live_oid(self, name.as_ref())? {
+            Some(oid) => oid,
+            None => return Ok(None),
+        };
+        let key = key_state_vector(oid);
+        let last_seq = last_seq(self, oid)?;
+        if let Some(data) = self.get(&key)? {
+            let data = self.config().codec.decode(data.as_ref())?;
+            let (sv, covered_seq) = decode_state_vector(&data)?;
+            // state vector without a marker is written only together with document state, the
+            // marker doesn't cover channel updates
+            if covered_seq == last_seq && !channel::has_updates(self, oid)? {
+                return Ok(Some(sv));
+            }
+        }
+        Ok(Some(recompute_state_vector(self, oid, last_seq)?))
+    }
+
+    /// Reconstructs documents with a stored state vector and compares it with the state vector of
+    /// their actual contents, reporting the documents for which they differ. Stored state vectors
+    /// may legitimately lag behind pending updates pushed since they were written, in which case
+    /// [SvDrift::explained_by_pending] is set. Other differences are left by partial writes and
+    /// can be fixed with [Self::repair_state_vector]. State vectors left behind by removed
+    /// document states have [SvDrift::doc_state_missing] set and can be removed with
+    /// [Self::repair_drop_state_vector].
+    ///
+    /// If `sample` is given, only that fraction (between `0.0` and `1.0`) of documents is
+    /// checked. Documents are sampled by the hash of their names, so that subsequent calls check
+    /// the same documents. Archived documents are not checked.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn verify_state_vectors(&self, sample: Option<f64>) -> Result<Vec<SvDrift>, Error> {
+        let mut drifts = Vec::new();
+        for name in self.iter_docs()? {
+            if let Some(sample) = sample {
+                if crc32(&name) as f64 >= sample * (u32::MAX as f64 + 1.0) {
+                    continue;
+                }
+            }
+            let oid = match get_live_oid(self, &name)? {
+                Some(oid) => oid,
+                None => continue,
+            };
+            if let Some(drift) = verify::sv_drift(self, &name, oid)? {
+                drifts.push(drift);
+            }
+        }
+        Ok(drifts)
+    }
+
+    /// Reconstructs a document with a given `name` and rewrites its stored state vector with the
+    /// one of its actual contents, no matter if the stored one is considered up to date (see
+    /// [Self::verify_state_vectors]). Returns the written state vector or `None` if document
+    /// doesn't exist.
+    ///
+    /// Returns [Error::DocArchived] if document has been archived using [Self::archive_doc].
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn repair_state_vector<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<StateVector>, Error> {
+        let oid = match get_live_oid(self, name.as_ref())? {
+            Some(oid) => oid,
+            None => return Ok(None),
+        };
+        let last_seq = last_seq(self, oid)?;
+        Ok(Some(recompute_state_vector(self, oid, last_seq)?))
+    }
+
+    /// Removes the state vector stored for a document with a given `name`, if the document has no
+    /// stored document state - i.e. because it has been removed by a manual cleanup, leaving the
+    /// state vector behind (see [LoadResult::orphaned_sv] and [SvDrift::doc_state_missing]). Such
+    /// document degrades to the contents of its pending updates: its state vector is computed
+    /// from them from now on. Returns `true` if the state vector has been removed, or `false` if
+    /// document doesn't exist, has a stored document state or has no stored state vector.
+    ///
+    /// Returns [Error::DocArchived] if document has been archived using [Self::archive_doc].
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn repair_drop_state_vector<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<bool, Error> {
+        let oid = match get_live_oid(self, name.as_ref())? {
+            Some(oid) => oid,
+            None => return Ok(false),
+        };
+        let key = key_state_vector(oid);
+        if self.get(&key_doc(oid))?.is_some() || self.get(&key)?.is_none() {
+            return Ok(false);
+        }
+        self.remove(&key)?;
+        sync_cache::invalidate(self, oid)?;
+        Ok(true)
+    }
+
+    /// Checks if the state vector stored for a document with a given `name` is consistent with its
+    /// contents: it must cover all client clocks of the stored document state and must not exceed
+    /// the state of the document with all of its pending updates applied. Missing documents and
+    /// documents without stored state vector are always consistent.
+    ///
+    /// Document state and its state vector are written in an order, which guarantees that readers
+    /// never observe a state vector inconsistent with the document state, even if they are not
+    /// isolated from writes in progress (see [Self::insert_doc]).
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn verify_sv_consistency<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<bool, Error> {
+        let oid = match get_live_oid(self, name.as_ref())? {
+            Some(oid) => oid,
+            None => return Ok(true),
+        };
+        let sv = match self.get(&key_state_vector(oid))? {
+            Some(data) => StateVector::decode_v1(&self.config().codec.decode(data.as_ref())?)?,
+            None => return Ok(true),
+        };
+        let doc = Doc::new();
+        if let Some(state_sv) = doc_state_sv(self, oid)? {
+            if !sv_covered_by(&state_sv, &sv) {
+                return Ok(false);
+            }
+        }
+        load_doc(self, oid, &mut doc.transact_mut())?;
+        let full_sv = doc.transact().state_vector();
+        Ok(sv_covered_by(&sv, &full_sv))
+    }
+
+    /// Stores a state vector `sv` of the document with a given `name`, without modifying the
+    /// document state itself. This is a lightweight way to keep [Self::get_state_vector] up to
+    /// date for documents persisted via [Self::push_update]: a document update observer has
+    /// access to the document transaction, so it can cheaply store its state vector right after
+    /// pushing an update.
+    ///
+    /// Returns [Error::DocArchived] if document has been archived using [Self::archive_doc].
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn put_state_vector<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        sv: &StateVector,
     ) -> Result<(), Error> {
-        let oid = get_or_create_oid(self, name.as_ref())?;
-        let key = key_meta(oid, meta_key.as_ref());
-        self.upsert(&key, meta)?;
+        let name = name.as_ref();
+        let oid = match lock_live_oid(self, name)? {
+            Some(oid) => oid,
+            None => create_oid(self, name)?,
+        };
+        let sv = self.config().codec.encode(&sv.encode_v1())?.into_owned();
+        self.upsert(&key_state_vector(oid), &sv)?;
         Ok(())
     }
 
-    /// Removes an metadata entry stored under given metadata `key` for a document with provided `name`.
+    /// Appends new update without integrating it directly into document store (which is faster
+    /// than persisting full document state on every update). Updates are assumed to be serialized
+    /// using lib0 v1 encoding.
     ///
-    /// This feature requires write capabilities from the database transaction.
-    fn remove_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+    /// Returns a [PushReceipt] containing a sequence number of a stored update. Once updates are
+    /// integrated into document and pruned (using [Self::flush_doc] method), sequence number is
+    /// reset. [PushReceipt::doc_created] is set only for the call which created the document, even
+    /// when multiple transactions push updates to a new document concurrently.
+    ///
+    /// Returns [Error::QuotaExceeded] if appending the update would exceed document storage quota
+    /// and [Error::DocNotFound] if document doesn't exist and [StoreConfig::create_policy] doesn't
+    /// allow to create it.
+    ///
+    /// If [StoreConfig::flush_policy] threshold has been reached, pending updates are merged into
+    /// document state right away, just like with [Self::flush_doc].
+    ///
+    /// If [Self::update_validator] is set, the update is decoded and checked by it first. Rejected
+    /// updates are reported with [Error::UpdateRejected] and nothing is written.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn push_update<K: AsRef<[u8]> + ?Sized>(
         &self,
-        name: &K1,
-        meta_key: &K2,
-    ) -> Result<(), Error> {
-        if let Some(oid) = get_oid(self, name.as_ref())? {
-            let key = key_meta(oid, meta_key.as_ref());
-            self.remove(&key)?;
+        name: &K,
+        update: &[u8],
+    ) -> Result<PushReceipt, Error> {
+        self.push_update_with(name, update, &mut Vec::new())
+    }
+
+    /// Same as [Self::push_update], but encodes the `update` into a given `scratch` buffer, which
+    /// can be reused across calls to avoid allocating a new one every time an update needs to be
+    /// encoded. With [ValueCodec::RAW](config::ValueCodec::RAW) codec updates are written
+    /// straight from a given slice. Contents of the buffer on return are unspecified.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn push_update_with<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        update: &[u8],
+        scratch: &mut Vec<u8>,
+    ) -> Result<PushReceipt, Error> {
+        let name = name.as_ref();
+        let oid = lock_live_oid(self, name)?;
+        check_create_policy(self, oid)?;
+        if let Some(validator) = self.update_validator() {
+            let decoded = Update::decode_v1(update)?;
+            validator
+                .validate(name, &decoded)
+                .map_err(Error::UpdateRejected)?;
+        }
+        let usage = usage::Tracker::start(self, name)?;
+        let doc_created = oid.is_none();
+        let pending = match oid {
+            Some(oid) => lock_pending(self, oid)?,
+            None => Pending::default(),
+        };
+        let update_len = update.len();
+        let update = self.config().codec.encode_into(update, scratch)?;
+        check_quota(self, oid, &pending, update.len() as u64, false)?;
+        let oid = match oid {
+            Some(oid) => oid,
+            None => create_oid(self, name)?,
+        };
+        let clock = match self.config().update_framing {
+            UpdateFraming::Individual => {
+                let clock = last_seq(self, oid)?.unwrap_or(0) + 1;
+                self.upsert(&key_update(oid, clock), update)?;
+                clock
+            }
+            UpdateFraming::Segmented { segment_size } => {
+                append_segment(self, oid, update, segment_size)?
+            }
+        };
+        let pending = Pending {
+            updates: pending.updates + 1,
+            bytes: pending.bytes + update.len() as u64,
+        };
+        self.upsert(&key_pending(oid), &pending.encode())?;
+        if self.config().update_feed {
+            feed::append(self, name, oid, clock, update_len)?;
+        }
+        touch(self, oid)?;
+        let mut receipt = PushReceipt {
+            seq: clock,
+            pending_updates: pending.updates,
+            pending_bytes: pending.bytes,
+            doc_created,
+            health: self.health_hint(),
+        };
+        if should_flush(self.config(), &pending) {
+            match flush_doc(self, oid, doc_options(self, oid)?) {
+                Ok(_) => {
+                    receipt.pending_updates = 0;
+                    receipt.pending_bytes = 0;
+                }
+                // remaining updates are merged by the following flushes
+                Err(Error::DeadlineExceeded { .. }) => {
+                    let pending = get_pending(self, oid)?;
+                    receipt.pending_updates = pending.updates;
+                    receipt.pending_bytes = pending.bytes;
+                }
+                Err(e) => return Err(e.in_doc(name)),
+            }
+        }
+        usage.finish(self, Ok(receipt))
+    }
+
+    /// Same as [Self::push_update], but returns only the sequence number of a stored update.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn push_update_seq<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        update: &[u8],
+    ) -> Result<u32, Error> {
+        Ok(self.push_update(name, update)?.seq)
+    }
+
+    /// Same as [Self::push_update], but appends the update to a separate log of updates identified
+    /// by a `channel` number, i.e. to keep updates of different origins apart. Updates pushed to
+    /// [DEFAULT_CHANNEL] are the ones written by [Self::push_update]. Every channel numbers its
+    /// updates separately, starting over once it's flushed with [Self::flush_channel].
+    ///
+    /// All channels are applied whenever a document is loaded: the default one first, followed by
+    /// the others in the ascending order of their numbers. Since updates commute, the order
+    /// doesn't affect the resulting document contents.
+    ///
+    /// Updates of channels other than the default one are always stored individually, no matter
+    /// the [StoreConfig::update_framing]. They are not tracked by the pending updates counter, so
+    /// they don't count towards [StoreConfig::flush_policy] thresholds nor the document storage
+    /// quota. They are not pruned by [Self::flush_doc], even though the document state it writes
+    /// includes them.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn push_update_channel<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        channel: u8,
+        update: &[u8],
+    ) -> Result<PushReceipt, Error> {
+        if channel == DEFAULT_CHANNEL {
+            return self.push_update(name, update);
+        }
+        let name = name.as_ref();
+        let oid = lock_live_oid(self, name)?;
+        check_create_policy(self, oid)?;
+        if let Some(validator) = self.update_validator() {
+            let decoded = Update::decode_v1(update)?;
+            validator
+                .validate(name, &decoded)
+                .map_err(Error::UpdateRejected)?;
+        }
+        let doc_created = oid.is_none();
+        let (oid, pending) = match oid {
+            Some(oid) => (oid, lock_pending(self, oid)?),
+            None => (create_oid(self, name)?, Pending::default()),
+        };
+        let update = self.config().codec.encode(update)?;
+        let seq = channel::last_seq(self, oid, channel)?.unwrap_or(0) + 1;
+        self.upsert(&key_channel_update(oid, channel, seq), &update)?;
+        touch(self, oid)?;
+        Ok(PushReceipt {
+            seq,
+            pending_updates: pending.updates,
+            pending_bytes: pending.bytes,
+            doc_created,
+            health: self.health_hint(),
+        })
+    }
+
+    /// Same as [Self::push_update], but refuses to store the update with [Error::Fenced] if a given
+    /// fencing `token` is older than the one stored for the document (see [Self::set_fence]).
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn push_update_fenced<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        update: &[u8],
+        token: u64,
+    ) -> Result<PushReceipt, Error> {
+        self.check_fence(name, token)?;
+        self.push_update(name, update)
+    }
+
+    /// Same as [Self::push_update], but if [StoreConfig::audit] is set, it also records the text
+    /// changes made by the update in the audit log of the document, attributed to a given
+    /// `origin`, i.e. an identifier of the user who made them. Updates which fail to be stored are
+    /// not recorded. Without [StoreConfig::audit] the `origin` is ignored.
+    ///
+    /// Auditing loads the document on every call. See [audit] module for details.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn push_update_with_origin<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        update: &[u8],
+        origin: &[u8],
+    ) -> Result<PushReceipt, Error> {
+        let config = match &self.config().audit {
+            Some(config) => config,
+            None => return self.push_update(name, update),
+        };
+        let name = name.as_ref();
+        let changes = audit::extract(self, get_live_oid(self, name)?, update, config)?;
+        let receipt = self.push_update(name, update)?;
+        if let Some(oid) = get_oid(self, name)? {
+            audit::append(self, oid, origin, &config.roots, changes)?;
+        }
+        Ok(receipt)
+    }
+
+    /// Returns audit records of a given document written within a given `time_range`, in the
+    /// order of their writes. Returns no records if the document doesn't exist. See [audit]
+    /// module for details.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn iter_audit<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        time_range: Range<SystemTime>,
+    ) -> Result<std::vec::IntoIter<AuditRecord>, Error> {
+        let records = match get_oid(self, name.as_ref())? {
+            Some(oid) => audit::records(self, oid, time_range)?,
+            None => Vec::new(),
+        };
+        Ok(records.into_iter())
+    }
+
+    /// Removes audit records of a given document written before a given `cutoff` time. Returns
+    /// the number of removed records.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn prune_audit<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        cutoff: SystemTime,
+    ) -> Result<u32, Error> {
+        match get_oid(self, name.as_ref())? {
+            Some(oid) => audit::prune(self, oid, cutoff),
+            None => Ok(0),
         }
+    }
+
+    /// Returns up to `limit` entries of the global update feed written within a given
+    /// `time_range`, in the order of their writes. Returns no entries if
+    /// [StoreConfig::update_feed] has never been enabled. See [feed] module for details.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn iter_feed(
+        &self,
+        time_range: Range<SystemTime>,
+        limit: usize,
+    ) -> Result<std::vec::IntoIter<FeedEntry>, Error> {
+        Ok(feed::entries(self, time_range, limit)?.into_iter())
+    }
+
+    /// Removes entries of the global update feed written before a given `cutoff` time. Returns
+    /// the number of removed entries.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn prune_feed(&self, cutoff: SystemTime) -> Result<u32, Error> {
+        feed::prune(self, cutoff)
+    }
+
+    /// Appends a series of updates, i.e. migrated from another persistence layer, under their
+    /// original sequence numbers (`clock`s). Updates are assumed to be serialized using lib0 v1
+    /// encoding. All of them are written in a single pass, without looking up the last stored
+    /// update for every entry.
+    ///
+    /// Clocks must be strictly increasing and greater than the clock of the last update already
+    /// stored for the document, otherwise [Error::ClockConflict] is returned and nothing is
+    /// written. Returns [Error::QuotaExceeded] if appending all updates would exceed document
+    /// storage quota.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn import_updates<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        updates: &[(u32, &[u8])],
+    ) -> Result<(), Error> {
+        self.import_updates_with(name, updates, None)?;
         Ok(())
     }
 
-    /// Returns an iterator over all document names stored in current database.
-    fn iter_docs(&self) -> Result<DocsNameIter<Self::Cursor, Self::Entry>, Error> {
-        let start = Key::from_const([V1, KEYSPACE_OID]);
-        let end = Key::from_const([V1, KEYSPACE_DOC]);
-        let cursor = self.iter_range(&start, &end)?;
-        Ok(DocsNameIter { cursor })
+    /// Appends a series of updates under their original sequence numbers, just like
+    /// [Self::import_updates]. If `flush` options are provided, imported updates are merged into
+    /// document state right away, just like with [Self::flush_doc_with]. Otherwise, they are
+    /// merged only if [StoreConfig::flush_policy] threshold has been reached.
+    ///
+    /// Returns a [FlushOutcome] if imported updates have been merged into document state.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn import_updates_with<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        updates: &[(u32, &[u8])],
+        flush: Option<yrs::Options>,
+    ) -> Result<Option<FlushOutcome>, Error> {
+        let name = name.as_ref();
+        let oid = lock_live_oid(self, name)?;
+        let (pending, mut last_clock) = match oid {
+            Some(oid) => {
+                let last_clock = last_seq(self, oid)?.unwrap_or(0);
+                (lock_pending(self, oid)?, last_clock)
+            }
+            None => (Pending::default(), 0),
+        };
+        let codec = &self.config().codec;
+        let mut encoded = Vec::with_capacity(updates.len());
+        let mut bytes = 0;
+        for (clock, update) in updates {
+            if *clock <= last_clock {
+                return Err(Error::ClockConflict {
+                    clock: *clock,
+                    last_clock,
+                });
+            }
+            last_clock = *clock;
+            let update = codec.encode(update)?;
+            bytes += update.len() as u64;
+            encoded.push((*clock, update));
+        }
+        if encoded.is_empty() {
+            return Ok(None);
+        }
+        check_quota(self, oid, &pending, bytes, false)?;
+        let usage = usage::Tracker::start(self, name)?;
+        let oid = match oid {
+            Some(oid) => oid,
+            None => create_oid(self, name)?,
+        };
+        for (clock, update) in encoded.iter() {
+            self.upsert(&key_update(oid, *clock), update)?;
+        }
+        let pending = Pending {
+            updates: pending.updates + encoded.len() as u32,
+            bytes: pending.bytes + bytes,
+        };
+        self.upsert(&key_pending(oid), &pending.encode())?;
+        touch(self, oid)?;
+        let outcome = match flush {
+            Some(options) => flush_doc(self, oid, options),
+            None if should_flush(self.config(), &pending) => {
+                match flush_doc(self, oid, doc_options(self, oid)?) {
+                    // remaining updates are merged by the following flushes
+                    Err(Error::DeadlineExceeded { .. }) => Ok(None),
+                    other => other,
+                }
+            }
+            None => Ok(None),
+        };
+        usage.finish(self, outcome.map_err(|e| e.in_doc(name)))
     }
 
-    /// Returns an iterator over all metadata entries stored for a given document.
-    fn iter_meta<K: AsRef<[u8]> + ?Sized>(
+    /// Returns an update (encoded using lib0 v1 encoding) which contains all new changes that
+    /// happened since provided state vector for a given document.
+    ///
+    /// Document is materialized using the options persisted by its most recent
+    /// [Self::flush_doc_with] call or [yrs::Options::default] if there are none.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn get_diff<K: AsRef<[u8]> + ?Sized>(
         &self,
-        doc_name: &K,
-    ) -> Result<MetadataIter<Self::Cursor, Self::Entry>, Error> {
-        if let Some(oid) = get_oid(self, doc_name.as_ref())? {
-            let start = key_meta_start(oid).to_vec();
-            let end = key_meta_end(oid).to_vec();
-            let cursor = self.iter_range(&start, &end)?;
-            Ok(MetadataIter(Some((cursor, start, end))))
+        name: &K,
+        sv: &StateVector,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        match get_live_oid(self, name.as_ref())? {
+            Some(oid) => {
+                let options = doc_options(self, oid)?;
+                self.get_diff_with(name, sv, options)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Same as [Self::get_diff], but materializes the document using provided `options`, i.e. to
+    /// use a fixed client id or keep deleted content regardless of persisted options.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn get_diff_with<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        sv: &StateVector,
+        options: yrs::Options,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let doc = Doc::with_options(options);
+        let loaded = {
+            let mut txn = doc.transact_mut();
+            self.load_doc_checked(name, &mut txn)?
+        };
+        if self.config().strict_history {
+            if let Some(missing) = loaded.missing {
+                return Err(Error::IncompleteHistory { missing });
+            }
+            if let Some(claimed) = loaded.orphaned_sv {
+                return Err(Error::MissingDocState { claimed });
+            }
+        }
+        if loaded.found {
+            Ok(Some(doc.transact().encode_diff_v1(sv)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns the ranges of clocks, per client, of the changes which updates stored for
+    /// a document with a given `name` depend on, but which the store has never received (see
+    /// [Self::load_doc_checked]). Every range starts at the first clock of a given client missing
+    /// from the document and ends after the last clock known to be missing: the actual gap may
+    /// be longer. Returns an empty list if all stored updates can be integrated or there's no
+    /// document with a given `name`.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn missing_ranges<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Vec<(ClientID, Range<u32>)>, Error> {
+        let oid = match get_live_oid(self, name.as_ref())? {
+            Some(oid) => oid,
+            None => return Ok(Vec::new()),
+        };
+        let doc = Doc::with_options(doc_options(self, oid)?);
+        let mut txn = doc.transact_mut();
+        load_doc(self, oid, &mut txn)?;
+        let missing = match missing_updates(&txn) {
+            Some(missing) => missing,
+            None => return Ok(Vec::new()),
+        };
+        let sv = txn.state_vector();
+        let mut ranges: Vec<_> = missing
+            .iter()
+            .map(|(&client, &clock)| {
+                let start = sv.get(&client);
+                (client, start..clock.max(start) + 1)
+            })
+            .collect();
+        ranges.sort_by_key(|(client, _)| *client);
+        Ok(ranges)
+    }
+
+    /// Handles the first step of y-sync protocol: given the binary state vector `remote_sv` sent by
+    /// a remote peer (encoded using lib0 v1 encoding), returns a [SyncStep2] containing an update
+    /// with all changes missing on the remote side together with the local state vector, which
+    /// the remote peer can use to send back changes missing locally.
+    ///
+    /// If there's no document with a given `name`, returned update and state vector describe an
+    /// empty document. Returns [Error::InvalidStateVector] if `remote_sv` could not be decoded.
+    ///
+    /// When [StoreConfig::sync_frame_ttl] is set, replies are served from the
+    /// [sync frames cache](crate::sync_cache) and the ones computed from scratch are stored there.
+    ///
+    /// This feature requires only the read capabilities from the database transaction, unless
+    /// [StoreConfig::sync_frame_ttl] is set.
+    fn handle_sync_step1<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        remote_sv: &[u8],
+    ) -> Result<SyncStep2, Error> {
+        let ttl = self.config().sync_frame_ttl;
+        let hash = sync_cache::sv_hash(remote_sv);
+        if ttl.is_some() {
+            if let Some(frame) = self.get_cached_sync_frame(name, hash)? {
+                if let Some(step2) = sync_cache::decode_step2(remote_sv, &frame)? {
+                    return Ok(step2);
+                }
+            }
+        }
+        let decoded = StateVector::decode_v1(remote_sv)
+            .map_err(|e| Error::InvalidStateVector(Box::new(e)))?;
+        let doc = Doc::new();
+        self.load_doc(name, &mut doc.transact_mut())?;
+        let txn = doc.transact();
+        let step2 = SyncStep2 {
+            update: txn.encode_diff_v1(&decoded),
+            state_vector: txn.state_vector().encode_v1(),
+        };
+        if let Some(ttl) = ttl {
+            let frame = sync_cache::encode_step2(remote_sv, &step2);
+            self.cache_sync_frame(name, hash, &frame, ttl)?;
+        }
+        Ok(step2)
+    }
+
+    /// Stores a pre-encoded sync protocol `frame` of a document with a given `name`, computed for
+    /// a remote state vector with a given `remote_sv_hash`, so that it can be served by
+    /// [Self::get_cached_sync_frame] to other peers sending the same state vector. Frame expires
+    /// after `ttl` and is invalidated by any write modifying document contents, including
+    /// [Self::push_update], [Self::flush_doc] and [Self::clear_doc]. Does nothing if there's no
+    /// document with a given `name`.
+    ///
+    /// Frames are stored as they are, without using the [ValueCodec] of the store. See
+    /// [sync_cache] module for details.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn cache_sync_frame<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        remote_sv_hash: u64,
+        frame: &[u8],
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        match get_live_oid(self, name.as_ref())? {
+            Some(oid) => sync_cache::cache_frame(self, oid, remote_sv_hash, frame, ttl),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns a sync frame of a document with a given `name` stored by [Self::cache_sync_frame]
+    /// for a given `remote_sv_hash`, unless it has expired or the document has been modified
+    /// since.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn get_cached_sync_frame<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        remote_sv_hash: u64,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        match get_live_oid(self, name.as_ref())? {
+            Some(oid) => sync_cache::get_frame(self, oid, remote_sv_hash),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores a `snapshot` of the document with a given `name` under provided `label`, replacing
+    /// any snapshot previously stored under the same label. Snapshots can be obtained with
+    /// [ReadTxn::snapshot] and used later on with [Self::encode_state_from_snapshot].
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn insert_snapshot<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        label: &K2,
+        snapshot: &Snapshot,
+    ) -> Result<(), Error> {
+        let oid = get_or_create_oid(self, name.as_ref())?;
+        let key = key_snapshot(oid, label.as_ref());
+        self.upsert(&key, &snapshot.encode_v1())?;
+        Ok(())
+    }
+
+    /// Returns a snapshot stored under a given `label` for a document with provided `name`.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn get_snapshot<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        label: &K2,
+    ) -> Result<Option<Snapshot>, Error> {
+        if let Some(oid) = get_oid(self, name.as_ref())? {
+            if let Some(value) = self.get(&key_snapshot(oid, label.as_ref()))? {
+                return Ok(Some(Snapshot::decode_v1(value.as_ref())?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Removes a snapshot stored under a given `label` for a document with provided `name`.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn remove_snapshot<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        label: &K2,
+    ) -> Result<(), Error> {
+        if let Some(oid) = get_oid(self, name.as_ref())? {
+            self.remove(&key_snapshot(oid, label.as_ref()))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the state of the document with a given `name` as it was at the moment when
+    /// provided `snapshot` has been taken, encoded as an update using lib0 v1 encoding. Returns
+    /// `None` if document doesn't exist.
+    ///
+    /// Past document states can be reconstructed only as long as deleted content is still stored.
+    /// This is true for documents which have never been flushed, or which have been flushed using
+    /// [Self::flush_doc_with] with `skip_gc` option set (subsequent [Self::flush_doc] calls and
+    /// [StoreConfig::flush_policy] reuse that option). Once document state has been written with
+    /// garbage collection enabled (i.e. by [Self::flush_doc_with] with default options or
+    /// [Self::archive_doc]), this method returns [Error::HistoryUnavailable].
+    ///
+    /// Returns [Error::DocArchived] if document has been archived using [Self::archive_doc].
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn encode_state_from_snapshot<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        snapshot: &Snapshot,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let oid = match get_live_oid(self, name.as_ref())? {
+            Some(oid) => oid,
+            None => return Ok(None),
+        };
+        if self.get(&key_meta(oid, META_GC))?.is_some() {
+            return Err(Error::HistoryUnavailable);
+        }
+        let doc = Doc::with_options(yrs::Options {
+            skip_gc: true,
+            ..yrs::Options::default()
+        });
+        load_doc(self, oid, &mut doc.transact_mut())?;
+        let mut encoder = EncoderV1::new();
+        doc.transact()
+            .encode_state_from_snapshot(snapshot, &mut encoder)
+            .map_err(Error::other)?;
+        Ok(Some(encoder.to_vec()))
+    }
+
+    /// Removes all data associated with the current document (including its updates, metadata and
+    /// archived state). References of other documents to it are removed (see
+    /// [RefPolicy::Nullify]). Returns a [ClearReport] describing the removed data, which is empty
+    /// if the document didn't exist.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn clear_doc<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<ClearReport, Error> {
+        refs::clear_doc(self, name.as_ref(), RefPolicy::Nullify)
+    }
+
+    /// Removes all data associated with the current document, just like [Self::clear_doc], handling
+    /// documents referring to it (see [Self::insert_meta_ref]) according to a given `policy`.
+    ///
+    /// Returns [Error::DocReferenced] if `policy` is [RefPolicy::Refuse] and other documents
+    /// refer to the document. With [RefPolicy::Cascade], returned [ClearReport] covers all
+    /// cleared documents.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn clear_doc_with<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        policy: RefPolicy,
+    ) -> Result<ClearReport, Error> {
+        refs::clear_doc(self, name.as_ref(), policy)
+    }
+
+    /// Removes all data associated with the current document, just like [Self::clear_doc], but
+    /// only if it meets a given `guard`. Otherwise nothing is removed and [Error::ClearRefused]
+    /// is returned. The guard is checked within the same transaction which clears the document.
+    /// See [guard] module for details.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn clear_doc_if<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        guard: ClearGuard,
+    ) -> Result<ClearReport, Error> {
+        let name = name.as_ref();
+        if let Some(value) = self.get_for_update(&key_oid(name))? {
+            let (oid, _) = oid_value(value.as_ref())?;
+            guard::check(self, oid, &guard)?;
+        }
+        refs::clear_doc(self, name, RefPolicy::Nullify)
+    }
+
+    /// Completes multi-key operations recorded in the intent log, which have been interrupted
+    /// i.e. by a crash of a store that cannot apply them atomically (see
+    /// [StoreConfig::intent_log]). It should be called once, when the store is opened, before
+    /// any other operation. Returns the number of recovered operations.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn recover_intents(&self) -> Result<u32, Error> {
+        let intents = get_intents(self)?;
+        let recovered = intents.len() as u32;
+        for (oid, intent) in intents {
+            match intent {
+                Intent::Flush { skip_gc } => {
+                    let options = yrs::Options {
+                        skip_gc,
+                        ..yrs::Options::default()
+                    };
+                    if flush_doc(self, oid, options)?.is_none() {
+                        // updates have been removed already, but pending counter may be left
+                        delete_updates(self, oid)?;
+                    }
+                }
+                Intent::Clear { name } => {
+                    clear_doc(self, &name, oid)?;
+                }
+            }
+            self.remove(&key_intent(oid))?;
+        }
+        Ok(recovered)
+    }
+
+    /// Runs a bounded portion of store maintenance: compaction of documents with many pending
+    /// updates, verification of a sample of stored documents and removal of orphaned document
+    /// entries, depending on provided `options`. Returned report carries a cursor, which should be
+    /// passed to the next call to continue where this one stopped. See [maintenance] module
+    /// documentation for details.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn maintain(&self, options: MaintenanceOptions) -> Result<MaintenanceReport, Error> {
+        maintenance::maintain(self, options)
+    }
+
+    /// Returns OIDs of orphaned documents in ascending order: documents which have their
+    /// contents stored, but are not referenced by any document name, i.e. because entries of the
+    /// OID index have been lost. Orphaned documents are removed by [Self::maintain] with
+    /// [MaintenanceOptions::vacuum] set, unless they're adopted with [Self::adopt_orphan] first.
+    fn orphaned_oids(&self) -> Result<Vec<OID>, Error> {
+        maintenance::orphaned_oids(self)
+    }
+
+    /// Makes contents of an orphaned document with a given `oid` (see [Self::orphaned_oids])
+    /// reachable again as a document with a given `name`.
+    ///
+    /// Returns `false` if a document with that `name` already exists or nothing is stored under
+    /// that `oid`. Fails with [Error::OidClaimed] if the `oid` is not orphaned, but belongs to
+    /// another document.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn adopt_orphan<K: AsRef<[u8]> + ?Sized>(&self, oid: OID, name: &K) -> Result<bool, Error> {
+        maintenance::adopt_orphan(self, oid, name.as_ref())
+    }
+
+    /// Decodes an `update` of a document with a given `name` and stores its blocks in separate
+    /// documents, one for each part of the document defined by `policy`, depending on the root
+    /// types they belong to. See [split] module documentation for details.
+    ///
+    /// Returns [Error::UpdateRejected] if update depends on content, which has not been pushed
+    /// yet.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn push_split_update<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        update: &[u8],
+        policy: &SplitPolicy,
+    ) -> Result<(), Error> {
+        split::push_split_update(self, name.as_ref(), update, policy)
+    }
+
+    /// Loads the parts of a document with a given `name`, which hold provided `roots` according
+    /// to `policy`, into in-memory Yrs document using provided [TransactionMut]. Content of the
+    /// remaining parts is not loaded. Returns `false` if none of the loaded parts exist. See
+    /// [split] module documentation for details.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn load_split_doc<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        policy: &SplitPolicy,
+        roots: &[&str],
+        txn: &mut TransactionMut,
+    ) -> Result<bool, Error> {
+        split::load_split_doc(self, name.as_ref(), policy, roots, txn)
+    }
+
+    /// Returns a metadata value stored under its metadata `key` for a document with given `name`.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn get_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        meta_key: &K2,
+    ) -> Result<Option<Self::Return>, Error> {
+        if let Some(oid) = get_oid(self, name.as_ref())? {
+            let key = key_meta(oid, meta_key.as_ref());
+            Ok(self.get(&key)?)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Inserts or updates new `meta` value stored under its metadata `key` for a document with
+    /// given `name`.
+    ///
+    /// Returns [Error::DocNotFound] if document doesn't exist and [StoreConfig::create_policy]
+    /// doesn't allow to create it.
+    ///
+    /// This feature requires write capabilities from the database transaction.
+    fn insert_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        meta_key: &K2,
+        meta: &[u8],
+    ) -> Result<(), Error> {
+        let oid = get_or_create_oid(self, name.as_ref())?;
+        let key = key_meta(oid, meta_key.as_ref());
+        self.upsert(&key, meta)?;
+        Ok(())
+    }
+
+    /// Removes an metadata entry stored under given metadata `key` for a document with provided `name`.
+    ///
+    /// This feature requires write capabilities from the database transaction.
+    fn remove_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        meta_key: &K2,
+    ) -> Result<(), Error> {
+        if let Some(oid) = get_oid(self, name.as_ref())? {
+            let key = key_meta(oid, meta_key.as_ref());
+            self.remove(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Writes given metadata `entries` of a document with given `name`, unless they have been
+    /// initialized by this method already. Returns `true` if current call has performed the
+    /// initialization. Document is created if it doesn't exist yet.
+    ///
+    /// Initialization is recorded under reserved [META_INITIALIZED] key, which is read using
+    /// [KVStore::get_for_update], so that concurrent transactions initializing the same document
+    /// conflict with each other and only one of them succeeds. Combined with
+    /// [PushReceipt::doc_created] this can be used to attach data - like the owner of a document
+    /// or its creation time - to newly created documents. Marker is removed together with the
+    /// document by [Self::clear_doc], so that a recreated document can be initialized again.
+    ///
+    /// Returns [Error::DocNotFound] if document doesn't exist and [StoreConfig::create_policy]
+    /// doesn't allow to create it.
+    ///
+    /// This feature requires write capabilities from the database transaction.
+    fn init_meta_once<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        entries: &[(&[u8], &[u8])],
+    ) -> Result<bool, Error> {
+        let oid = get_or_create_oid(self, name.as_ref())?;
+        let marker = key_meta(oid, META_INITIALIZED);
+        if self.get_for_update(&marker)?.is_some() {
+            return Ok(false);
+        }
+        for (meta_key, meta) in entries.iter() {
+            self.upsert(&key_meta(oid, meta_key), meta)?;
+        }
+        self.upsert(&marker, &[])?;
+        Ok(true)
+    }
+
+    /// Stores a reference to a document with `target` name under its reference `key` for a
+    /// document with given `name`. References are stored separately from metadata entries and
+    /// point at the OID of the `target`, which is recorded in the inbound references index of the
+    /// `target`. This way they never dangle: once the `target` is cleared, references to it are
+    /// handled according to [RefPolicy] passed to [Self::clear_doc_with].
+    ///
+    /// Returns [Error::DocNotFound] if `target` doesn't exist or if document doesn't exist and
+    /// [StoreConfig::create_policy] doesn't allow to create it.
+    ///
+    /// This feature requires write capabilities from the database transaction.
+    fn insert_meta_ref<K1, K2, K3>(&self, name: &K1, key: &K2, target: &K3) -> Result<(), Error>
+    where
+        K1: AsRef<[u8]> + ?Sized,
+        K2: AsRef<[u8]> + ?Sized,
+        K3: AsRef<[u8]> + ?Sized,
+    {
+        refs::insert_ref(self, name.as_ref(), key.as_ref(), target.as_ref())
+    }
+
+    /// Returns a name of the document referenced under its reference `key` by a document with
+    /// given `name` (see [Self::insert_meta_ref]).
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn get_meta_ref<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        key: &K2,
+    ) -> Result<Option<Self::Return>, Error> {
+        refs::get_ref(self, name.as_ref(), key.as_ref())
+    }
+
+    /// Removes a reference stored under its reference `key` by a document with given `name`.
+    ///
+    /// This feature requires write capabilities from the database transaction.
+    fn remove_meta_ref<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        key: &K2,
+    ) -> Result<(), Error> {
+        refs::remove_ref(self, name.as_ref(), key.as_ref())
+    }
+
+    /// Returns references of other documents to a document with given `name` (see
+    /// [Self::insert_meta_ref]).
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn get_inbound_refs<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Vec<InboundRef>, Error> {
+        match get_oid(self, name.as_ref())? {
+            Some(oid) => refs::inbound_refs(self, oid),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns an iterator over all document names stored in current database. Archived documents
+    /// and drafts (see [draft]) are skipped - use [Self::iter_docs_with] to include them, or
+    /// [Self::list_docs] to tell them apart.
+    fn iter_docs(&self) -> Result<DocsNameIter<Self::Cursor, Self::Entry>, Error> {
+        self.iter_docs_with(false)
+    }
+
+    /// Returns an iterator over all document names stored in current database. If
+    /// `include_archived` is set, names of documents archived with [Self::archive_doc] and of
+    /// drafts created with [Self::create_draft] will be returned as well.
+    fn iter_docs_with(
+        &self,
+        include_archived: bool,
+    ) -> Result<DocsNameIter<Self::Cursor, Self::Entry>, Error> {
+        let start = Key::from_const([V1, KEYSPACE_OID]);
+        let end = Key::from_const([V1, KEYSPACE_DOC]);
+        let cursor = self.iter_range(&start, &end)?;
+        Ok(DocsNameIter {
+            cursor,
+            end: end.into(),
+            include_archived,
+        })
+    }
+
+    /// Returns an iterator over names of all documents, which names start with a given `prefix`,
+    /// in the order of their names. Archived documents and drafts are skipped.
+    ///
+    /// Together with [keys::DocKey::workspace] it can be used to list all documents of a single
+    /// workspace, when documents are identified by a pair of numbers.
+    fn iter_docs_prefix<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        prefix: &K,
+    ) -> Result<DocsNameIter<Self::Cursor, Self::Entry>, Error> {
+        let start = key_oid_prefix(prefix.as_ref());
+        let end = key_oid_prefix_end(prefix.as_ref());
+        let cursor = self.iter_range(&start, &end)?;
+        Ok(DocsNameIter {
+            cursor,
+            end,
+            include_archived: false,
+        })
+    }
+
+    /// Returns a lazy iterator over documents selected by given `options`, in ascending order of
+    /// their names. Unlike [Self::iter_docs], every listed document reports whether it's archived
+    /// and can carry hints about its state, read without loading it. See [listing] module for
+    /// details.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn list_docs(&self, options: ListOptions) -> Result<DocListing<'_, 'a, Self>, Error> {
+        let start = key_oid_prefix(&options.prefix);
+        let end = key_oid_prefix_end(&options.prefix);
+        let cursor = self.iter_range(&start, &end)?;
+        Ok(DocListing::new(self, cursor, end, options))
+    }
+
+    /// Returns an iterator over all metadata entries stored for a given document.
+    fn iter_meta<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        doc_name: &K,
+    ) -> Result<MetadataIter<Self::Cursor, Self::Entry>, Error> {
+        if let Some(oid) = get_oid(self, doc_name.as_ref())? {
+            let start = key_meta_start(oid).to_vec();
+            let end = key_meta_end(oid).to_vec();
+            let cursor = self.iter_range(&start, &end)?;
+            Ok(MetadataIter(Some((cursor, start, end))))
+        } else {
+            Ok(MetadataIter(None))
+        }
+    }
+
+    /// Returns a lazy iterator over pending updates of a given document, which have not been
+    /// merged into its state yet (see [Self::flush_doc]). Updates are decoded one by one and
+    /// returned together with their sequence numbers, in the order they were pushed, without
+    /// being applied to any document. Only updates of [DEFAULT_CHANNEL] are returned.
+    ///
+    /// Updates which cannot be decoded are returned as errors, without stopping the iteration.
+    fn decoded_updates<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<UpdatesIter<Self::Cursor, Self::Entry>, Error> {
+        if let Some(oid) = get_oid(self, name.as_ref())? {
+            let start = key_update(oid, 0);
+            let end = key_update(oid, u32::MAX);
+            let cursor = self.iter_range(&start, &end)?;
+            Ok(UpdatesIter(
+                Some((
+                    cursor,
+                    end.to_vec(),
+                    self.config().codec,
+                    name.as_ref().into(),
+                )),
+                VecDeque::new(),
+            ))
+        } else {
+            Ok(UpdatesIter(None, VecDeque::new()))
+        }
+    }
+
+    /// Registers a given `collection`, so that it's reported by [Self::collection_exists] even
+    /// if it doesn't contain any documents. Documents are stored in a collection by using names
+    /// produced by [Collection::doc_name]. Returns `false` if collection already existed.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn create_collection(&self, collection: &Collection) -> Result<bool, Error> {
+        if self.collection_exists(collection)? {
+            return Ok(false);
+        }
+        self.upsert(&key_collection(collection.path()), &[])?;
+        Ok(true)
+    }
+
+    /// Checks if a given `collection` has been created with [Self::create_collection]. Root
+    /// collection always exists.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn collection_exists(&self, collection: &Collection) -> Result<bool, Error> {
+        if collection.is_root() {
+            return Ok(true);
+        }
+        Ok(self.get(&key_collection(collection.path()))?.is_some())
+    }
+
+    /// Returns full names of documents stored directly in a given `collection`. Documents of
+    /// nested collections are skipped without being read, as are archived documents.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn iter_collection(&self, collection: &Collection) -> Result<Vec<Box<[u8]>>, Error> {
+        let prefix = collection.prefix();
+        let mut start = key_oid_prefix(&prefix);
+        let end = collection_end(collection);
+        let mut names = Vec::new();
+        'scan: loop {
+            for e in self.iter_range(&start, &end)? {
+                let key = e.key();
+                if key >= end.as_slice() {
+                    break;
+                }
+                let name = doc_oid_name(key);
+                if let Some(i) = find_separator(&name[prefix.len()..]) {
+                    // document of a nested collection: seek past all of its entries
+                    let mut next = key_oid_prefix(&name[..(prefix.len() + i)]);
+                    next.push(SEPARATOR + 1);
+                    start = next;
+                    continue 'scan;
+                }
+                let (_, flags) = oid_value(e.value())?;
+                if flags & (OID_FLAG_ARCHIVED | OID_FLAG_DRAFT) == 0 {
+                    names.push(name.into());
+                }
+            }
+            return Ok(names);
+        }
+    }
+
+    /// Removes a given `collection` together with all documents stored in it and in its nested
+    /// collections (see [Self::clear_doc]). Documents are removed in batches, so that the cursor
+    /// is never used while its entries are being deleted. Returns the number of removed documents.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn clear_collection(&self, collection: &Collection) -> Result<u32, Error> {
+        const BATCH_SIZE: usize = 256;
+        let start = key_oid_prefix(&collection.prefix());
+        let end = collection_end(collection);
+        let mut removed = 0;
+        loop {
+            let mut batch: Vec<Vec<u8>> = Vec::new();
+            self.iter_keys_range(&start, &end, &mut |key, _| {
+                if key >= end.as_slice() {
+                    return false;
+                }
+                batch.push(doc_oid_name(key).to_vec());
+                batch.len() < BATCH_SIZE
+            })?;
+            if batch.is_empty() {
+                break;
+            }
+            for name in batch {
+                self.clear_doc(&name)?;
+                removed += 1;
+            }
+        }
+        if collection.is_root() {
+            let start = Key::from_const([V1, KEYSPACE_COLLECTION]);
+            let end = Key::from_const([V1, KEYSPACE_COLLECTION + 1]);
+            self.remove_range(&start, &end)?;
+        } else {
+            self.remove(&key_collection(collection.path()))?;
+            let mut start = vec![V1, KEYSPACE_COLLECTION];
+            start.extend_from_slice(&collection.prefix());
+            let mut end = vec![V1, KEYSPACE_COLLECTION];
+            end.extend_from_slice(collection.path());
+            end.push(SEPARATOR + 1);
+            self.remove_range(&start, &end)?;
+        }
+        Ok(removed)
+    }
+
+    /// Moves the document with a given `name` into an archive key space. Before that, all pending
+    /// updates are merged into document state, which is then compressed using zstd. Once archived,
+    /// document state, its state vector and update entries are removed. Document metadata is left
+    /// intact.
+    ///
+    /// Archived documents cannot be loaded or updated: these operations return
+    /// [Error::DocArchived] until the document is restored with [Self::unarchive_doc].
+    ///
+    /// Returns `false` if document didn't exist or it was already archived.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn archive_doc<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<bool, Error> {
+        let name = name.as_ref();
+        match get_oid_entry(self, name)? {
+            Some((oid, flags)) if flags & OID_FLAG_ARCHIVED == 0 => {
+                usage::track(self, name, || archive_doc(self, name, oid, flags))?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Restores the document with a given `name` previously archived with [Self::archive_doc],
+    /// decompressing its state back into the document key space.
+    ///
+    /// Returns `false` if document didn't exist or it was not archived.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn unarchive_doc<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<bool, Error> {
+        let name = name.as_ref();
+        match get_oid_entry(self, name)? {
+            Some((oid, flags)) if flags & OID_FLAG_ARCHIVED != 0 => {
+                usage::track(self, name, || unarchive_doc(self, name, oid, flags))?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Creates an empty [draft](draft) document with a given `name`. Drafts can be read and
+    /// written like any other document, but they are not listed by [Self::iter_docs] or
+    /// [Self::list_docs] and don't count towards storage [usage] until they are promoted with
+    /// [Self::promote_draft].
+    ///
+    /// Returns `false` if a document with that name already exists.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn create_draft<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<bool, Error> {
+        draft::create(self, name.as_ref())
+    }
+
+    /// Turns a draft created with [Self::create_draft] into a regular document.
+    ///
+    /// Returns `false` if there's no draft with a given `name`, i.e. because it has been promoted
+    /// or purged already.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn promote_draft<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<bool, Error> {
+        draft::promote(self, name.as_ref())
+    }
+
+    /// Removes all drafts created at least `older_than` ago, together with their contents.
+    /// Returns the number of removed drafts.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn purge_drafts(&self, older_than: Duration) -> Result<u32, Error> {
+        draft::purge(self, older_than)
+    }
+
+    /// Sets a store-wide storage quota for every document, that doesn't have its own quota set via
+    /// [Self::set_max_doc_bytes]. Passing `None` removes the quota.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn set_default_max_doc_bytes(&self, limit: Option<u64>) -> Result<(), Error> {
+        let key = key_setting(SETTING_MAX_DOC_BYTES);
+        match limit {
+            Some(limit) => self.upsert(&key, &limit.to_be_bytes())?,
+            None => self.remove(&key)?,
+        }
+        Ok(())
+    }
+
+    /// Sets a storage quota for a document with a given `name`, overriding the store-wide
+    /// quota set via [Self::set_default_max_doc_bytes]. Passing `None` removes the quota override.
+    ///
+    /// Quota is stored as document metadata entry under reserved [META_MAX_DOC_BYTES] key.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn set_max_doc_bytes<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        limit: Option<u64>,
+    ) -> Result<(), Error> {
+        match limit {
+            Some(limit) => self.insert_meta(name, META_MAX_DOC_BYTES, &limit.to_be_bytes()),
+            None => self.remove_meta(name, META_MAX_DOC_BYTES),
+        }
+    }
+
+    /// Advances the fencing token of a document with a given `name` to `token`, i.e. when the
+    /// document is handed over to a new owner. From then on, fenced writes
+    /// ([Self::push_update_fenced], [Self::flush_doc_fenced] or any write preceded by
+    /// [Self::check_fence] within the same transaction) made with older tokens are refused with
+    /// [Error::Fenced], so that a previous owner which still holds the document open cannot
+    /// interleave its writes with the new one.
+    ///
+    /// Unfenced writes are not checked. These include writes which replace or remove the whole
+    /// document ([Self::insert_doc], [Self::replace_doc], [Self::merge_docs], [Self::clear_doc]):
+    /// they take no token, since they're meant for administrative tools rather than document
+    /// owners. An owner making such a write calls [Self::check_fence] first.
+    ///
+    /// Tokens can only move forward: setting a token older than the current one fails with
+    /// [Error::Fenced], while setting the current one again does nothing. Token is stored as
+    /// document metadata entry under reserved [META_FENCE] key. When the document is cleared by
+    /// [Self::clear_doc], the token is kept under the name of the document in
+    /// [KEYSPACE_FENCE](crate::format::KEYSPACE_FENCE), so that fenced writes of a previous
+    /// owner can't create the document again, and a document created under the same name takes
+    /// the token over.
+    ///
+    /// Returns [Error::DocNotFound] if document doesn't exist and [StoreConfig::create_policy]
+    /// doesn't allow to create it.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn set_fence<K: AsRef<[u8]> + ?Sized>(&self, name: &K, token: u64) -> Result<(), Error> {
+        let oid = get_or_create_oid(self, name.as_ref())?;
+        let key = key_meta(oid, META_FENCE);
+        if let Some(current) = lock_fence(self, oid)? {
+            if token < current {
+                return Err(Error::Fenced { current });
+            } else if token == current {
+                return Ok(());
+            }
+        }
+        self.upsert(&key, &token.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Returns the fencing token of a document with a given `name` set by [Self::set_fence], or
+    /// `None` if document has never been fenced. Tokens of cleared documents are returned as
+    /// well.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn get_fence<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<Option<u64>, Error> {
+        let value = match get_oid(self, name.as_ref())? {
+            Some(oid) => self.get(&key_meta(oid, META_FENCE))?,
+            None => self.get(&key_fence(name.as_ref()))?,
+        };
+        match value {
+            Some(value) => Ok(Some(decode_u64(value.as_ref())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns [Error::Fenced] if a given fencing `token` is older than the one stored for
+    /// a document with a given `name` (see [Self::set_fence]). Call it before any other write
+    /// made within the same transaction to fence it: the stored token is read for update, so that
+    /// transactions racing with [Self::set_fence] conflict with it.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn check_fence<K: AsRef<[u8]> + ?Sized>(&self, name: &K, token: u64) -> Result<(), Error> {
+        let current = match get_oid(self, name.as_ref())? {
+            Some(oid) => lock_fence(self, oid)?,
+            None => match self.get_for_update(&key_fence(name.as_ref()))? {
+                Some(value) => Some(decode_u64(value.as_ref())?),
+                None => None,
+            },
+        };
+        match current {
+            Some(current) if token < current => Err(Error::Fenced { current }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the time of the last write modifying contents of the document with a given `name`.
+    /// Returns `None` if document doesn't exist or it was last modified while
+    /// [StoreConfig::timestamps] was disabled.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn last_modified<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<SystemTime>, Error> {
+        if let Some(oid) = get_oid(self, name.as_ref())? {
+            if let Some(value) = self.get(&key_meta(oid, META_LAST_MODIFIED))? {
+                let millis = decode_timestamp(value.as_ref()).ok_or(Error::CorruptedValue)?;
+                return Ok(Some(clock::system_time(millis)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns storage usage (in bytes) of every tenant, in the order of their identifiers.
+    /// Tenants are assigned to documents by [Self::tenant_extractor] (see [usage]). Tenants without
+    /// any usage are not listed.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn usage_by_tenant(&self) -> Result<Vec<(Vec<u8>, u64)>, Error> {
+        usage::all(self)
+    }
+
+    /// Returns storage usage (in bytes) of a given `tenant`, or 0 if it doesn't use any.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn usage_of<K: AsRef<[u8]> + ?Sized>(&self, tenant: &K) -> Result<u64, Error> {
+        usage::get(self, tenant.as_ref())
+    }
+
+    /// Recomputes storage usage of a given `tenant` by scanning its documents, which are found by
+    /// their names starting with the tenant identifier, and corrects its usage counter. Returns the
+    /// recomputed usage. Use it to reconcile counters which drifted, i.e. after a crash of a store
+    /// which doesn't apply writes atomically.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn recompute_usage<K: AsRef<[u8]> + ?Sized>(&self, tenant: &K) -> Result<u64, Error> {
+        usage::recompute(self, tenant.as_ref())
+    }
+
+    /// Removes blobs of [Self::blob_store] which are not referenced by any document state, i.e.
+    /// ones left by transactions which failed or have been rolled back, and ones replaced by
+    /// [Self::flush_doc] or removed by [Self::clear_doc]. Blobs created less than `min_age` ago
+    /// are kept, as they may belong to transactions still in progress. Returns the number of
+    /// removed blobs. See [blob] module for details.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn gc_blobs(&self, min_age: Duration) -> Result<u32, Error> {
+        blob::gc(self, min_age)
+    }
+
+    /// Returns access statistics of the document with a given `name`, recorded while
+    /// [StoreConfig::access_sample_rate] was set. Returns `None` if document doesn't exist or
+    /// no access to it has been recorded. See [access] module for details.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn access_stats<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<AccessStats>, Error> {
+        access::access_stats(self, name.as_ref())
+    }
+
+    /// Returns encoded sizes of the document with a given `name` recorded by its flushes while
+    /// [StoreConfig::size_history] was set, starting with the oldest one. Only the last
+    /// [SIZE_HISTORY_LEN](format::SIZE_HISTORY_LEN) samples are kept. Returns no samples if
+    /// document doesn't exist. See [size_history] module for details.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn size_history<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<Vec<SizeSample>, Error> {
+        size_history::samples(self, name.as_ref())
+    }
+
+    /// Returns up to `limit` documents accessed least recently, starting with the stalest one,
+    /// together with their access statistics (see [Self::access_stats]). Documents with no
+    /// recorded access come first. Archived documents are not returned.
+    ///
+    /// Access statistics of every document are read, so this method is meant for periodic
+    /// eviction jobs rather than request paths. See [access] module for details.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn iter_docs_by_staleness(&self, limit: usize) -> Result<std::vec::IntoIter<StaleDoc>, Error> {
+        Ok(access::stalest_docs(self, limit)?.into_iter())
+    }
+
+    /// Returns all documents with pending updates (see [Self::push_update]), starting with the
+    /// ones with the most pending bytes, i.e. in order to flush them with
+    /// [shutdown_flush](worker::shutdown_flush). Archived documents are not returned.
+    ///
+    /// Pending update counters of every document are read, so this method is meant for shutdown
+    /// and maintenance jobs rather than request paths.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn flush_candidates(&self) -> Result<std::vec::IntoIter<FlushCandidate>, Error> {
+        let mut candidates = Vec::new();
+        for name in self.iter_docs()? {
+            if let Some(oid) = get_oid(self, &name)? {
+                let pending = get_pending(self, oid)?;
+                if pending.updates > 0 {
+                    candidates.push(FlushCandidate {
+                        name,
+                        pending_updates: pending.updates,
+                        pending_bytes: pending.bytes,
+                    });
+                }
+            }
+        }
+        candidates.sort_by_key(|c| Reverse(c.pending_bytes));
+        Ok(candidates.into_iter())
+    }
+
+    /// Checks if the document with a given `name` has been archived using [Self::archive_doc].
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn is_archived<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<bool, Error> {
+        match get_oid_entry(self, name.as_ref())? {
+            Some((_, flags)) => Ok(flags & OID_FLAG_ARCHIVED != 0),
+            None => Ok(false),
+        }
+    }
+
+    /// Checks if the document with a given `name` is a draft created with [Self::create_draft],
+    /// which hasn't been promoted yet.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn is_draft<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<bool, Error> {
+        match get_oid_entry(self, name.as_ref())? {
+            Some((_, flags)) => Ok(flags & OID_FLAG_DRAFT != 0),
+            None => Ok(false),
+        }
+    }
+
+    /// Returns identifiers of all namespaces (see [namespace]) with any entries, in ascending
+    /// order. Namespaces nested in a [NamespacedStore](namespace::NamespacedStore) are listed
+    /// when it's called on that store.
+    ///
+    /// This feature requires only the read capabilities from the database transaction.
+    fn list_namespaces(&self) -> Result<Vec<u16>, Error> {
+        namespace::list(self)
+    }
+
+    /// Removes all entries of a namespace with a given `id` (see [namespace]) using a single
+    /// range delete. Returns the number of removed entries.
+    ///
+    /// This feature requires a write capabilities from the database transaction.
+    fn drop_namespace(&self, id: u16) -> Result<u32, Error> {
+        namespace::delete_all(self, id)
+    }
+}
+
+/// Counter of updates appended via [DocOps::push_update] since the last flush.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Pending {
+    updates: u32,
+    bytes: u64,
+}
+
+impl Pending {
+    fn decode(value: &[u8]) -> Result<Self, Error> {
+        if value.len() < PENDING_LEN {
+            return Err(Error::CorruptedValue);
+        }
+        Ok(Pending {
+            updates: u32::from_be_bytes(value[..4].try_into().unwrap()),
+            bytes: u64::from_be_bytes(value[4..12].try_into().unwrap()),
+        })
+    }
+
+    fn encode(&self) -> [u8; PENDING_LEN] {
+        let mut buf = [0u8; PENDING_LEN];
+        buf[..4].copy_from_slice(&self.updates.to_be_bytes());
+        buf[4..].copy_from_slice(&self.bytes.to_be_bytes());
+        buf
+    }
+}
+
+/// Checks if pending updates should be merged into document state according to
+/// [StoreConfig::flush_policy].
+fn should_flush(config: &StoreConfig, pending: &Pending) -> bool {
+    match config.flush_policy {
+        FlushPolicy::Manual => false,
+        FlushPolicy::AfterUpdates(threshold) => pending.updates >= threshold,
+        FlushPolicy::AfterBytes(threshold) => pending.bytes >= threshold,
+    }
+}
+
+fn get_pending<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<Pending, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    match db.get(&key_pending(oid))? {
+        Some(value) => Pending::decode(value.as_ref()),
+        None => Ok(Pending::default()),
+    }
+}
+
+/// Same as [get_pending], but reads the counter using [KVStore::get_for_update]. Every write
+/// appending updates to a document rewrites its counter, so concurrent transactions allocating
+/// sequence numbers of the same document conflict on it.
+fn lock_pending<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<Pending, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    match db.get_for_update(&key_pending(oid))? {
+        Some(value) => Pending::decode(value.as_ref()),
+        None => Ok(Pending::default()),
+    }
+}
+
+pub(crate) fn decode_oid(value: &[u8]) -> Result<OID, Error> {
+    let bytes: [u8; 4] = value.try_into()?;
+    Ok(OID::from_be_bytes(bytes))
+}
+
+fn decode_u64(value: &[u8]) -> Result<u64, Error> {
+    let bytes: [u8; 8] = value.try_into()?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// Returns storage quota of a given document, falling back to store-wide quota.
+fn get_max_doc_bytes<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: Option<OID>,
+) -> Result<Option<u64>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if let Some(oid) = oid {
+        if let Some(value) = db.get(&key_meta(oid, META_MAX_DOC_BYTES))? {
+            return Ok(Some(decode_u64(value.as_ref())?));
+        }
+    }
+    match db.get(&key_setting(SETTING_MAX_DOC_BYTES))? {
+        Some(value) => Ok(Some(decode_u64(value.as_ref())?)),
+        None => Ok(None),
+    }
+}
+
+/// Returns the fencing token of a given document, reading it for update.
+fn lock_fence<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<Option<u64>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    match db.get_for_update(&key_meta(oid, META_FENCE))? {
+        Some(value) => Ok(Some(decode_u64(value.as_ref())?)),
+        None => Ok(None),
+    }
+}
+
+/// Checks if writing `incoming` number of bytes will not exceed storage quota of a document.
+/// If `replace_state` is set, incoming bytes are going to replace current document state,
+/// otherwise they will be appended as a new pending update.
+fn check_quota<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: Option<OID>,
+    pending: &Pending,
+    incoming: u64,
+    replace_state: bool,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if let Some(limit) = get_max_doc_bytes(db, oid)? {
+        let state_bytes = match oid {
+            Some(oid) => match db.get(&key_doc(oid))? {
+                Some(state) => blob::stored_len(state.as_ref()),
+                None => 0,
+            },
+            None => 0,
+        };
+        let used = state_bytes + pending.bytes;
+        let requested = if replace_state {
+            pending.bytes + incoming
         } else {
-            Ok(MetadataIter(None))
+            used + incoming
+        };
+        if requested > limit {
+            return Err(Error::QuotaExceeded {
+                used,
+                limit,
+                flushable: pending.bytes,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// zstd compression level used for archived document states.
+const ARCHIVE_COMPRESSION_LEVEL: i32 = 3;
+
+/// Returns a [KEYSPACE_OID] key prefix shared by all documents, which names start with `prefix`.
+fn key_oid_prefix(prefix: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(prefix.len() + 2);
+    key.push(V1);
+    key.push(KEYSPACE_OID);
+    key.extend_from_slice(prefix);
+    key
+}
+
+/// Returns an upper bound of [KEYSPACE_OID] key range occupied by documents, which names start
+/// with `prefix`.
+fn key_oid_prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = key_oid_prefix(prefix);
+    // drop trailing 0xff bytes, which cannot be incremented
+    while end.len() > 2 && end[end.len() - 1] == u8::MAX {
+        end.pop();
+    }
+    if end.len() == 2 {
+        vec![V1, KEYSPACE_DOC]
+    } else {
+        *end.last_mut().unwrap() += 1;
+        end
+    }
+}
+
+/// Returns an upper bound of [KEYSPACE_OID] key range occupied by documents of a given
+/// `collection`, including its nested collections.
+fn collection_end(collection: &Collection) -> Vec<u8> {
+    if collection.is_root() {
+        vec![V1, KEYSPACE_DOC]
+    } else {
+        let mut end = key_oid_prefix(collection.path());
+        end.push(SEPARATOR + 1);
+        end
+    }
+}
+
+/// Parses a value of [KEYSPACE_OID] entry into document OID and its flags. Documents which were
+/// never flagged use 4-byte values.
+fn oid_value(value: &[u8]) -> Result<(OID, u8), Error> {
+    let oid = match value.get(..OID_LEN) {
+        Some(oid) => OID::from_be_bytes(oid.try_into().unwrap()),
+        None => return Err(Error::CorruptedValue),
+    };
+    let flags = value.get(OID_LEN).copied().unwrap_or(0);
+    Ok((oid, flags))
+}
+
+fn set_oid_flags<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    oid: OID,
+    flags: u8,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let key = key_oid(name);
+    let oid = oid.to_be_bytes();
+    if flags == 0 {
+        db.upsert(&key, &oid)?;
+    } else {
+        db.upsert(&key, &[oid[0], oid[1], oid[2], oid[3], flags])?;
+    }
+    Ok(())
+}
+
+fn get_oid_entry<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+) -> Result<Option<(OID, u8)>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let key = key_oid(name);
+    let value = db.get(&key)?;
+    if let Some(value) = value {
+        Ok(Some(oid_value(value.as_ref())?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn get_oid<'a, DB: DocOps<'a> + ?Sized>(db: &DB, name: &[u8]) -> Result<Option<OID>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    Ok(get_oid_entry(db, name)?.map(|(oid, _)| oid))
+}
+
+/// Same as [get_oid], but fails with [Error::DocArchived] if document contents are not
+/// available in the document key space.
+fn get_live_oid<'a, DB: DocOps<'a> + ?Sized>(db: &DB, name: &[u8]) -> Result<Option<OID>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    match get_oid_entry(db, name)? {
+        Some((_, flags)) if flags & OID_FLAG_ARCHIVED != 0 => Err(Error::DocArchived),
+        entry => Ok(entry.map(|(oid, _)| oid)),
+    }
+}
+
+/// Same as [get_live_oid], but reads the OID index entry using [KVStore::get_for_update], so that
+/// a document with a given `name` cannot be created by concurrent transactions in the meantime.
+fn lock_live_oid<'a, DB: DocOps<'a> + ?Sized>(db: &DB, name: &[u8]) -> Result<Option<OID>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    match db.get_for_update(&key_oid(name))? {
+        Some(value) => match oid_value(value.as_ref())? {
+            (_, flags) if flags & OID_FLAG_ARCHIVED != 0 => Err(Error::DocArchived),
+            (oid, _) => Ok(Some(oid)),
+        },
+        None => Ok(None),
+    }
+}
+
+fn get_or_create_oid<'a, DB: DocOps<'a> + ?Sized>(db: &DB, name: &[u8]) -> Result<OID, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let oid = db
+        .get_for_update(&key_oid(name))?
+        .map(|value| oid_value(value.as_ref()).map(|(oid, _)| oid))
+        .transpose()?;
+    check_create_policy(db, oid)?;
+    match oid {
+        Some(oid) => Ok(oid),
+        None => create_oid(db, name),
+    }
+}
+
+/// Checks if [StoreConfig::create_policy] allows to write into a document with a given `oid`,
+/// which is `None` if document doesn't exist yet.
+fn check_create_policy<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: Option<OID>) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let allowed = match (db.config().create_policy, oid) {
+        (CreatePolicy::CreateIfMissing, _) => true,
+        (_, None) => false,
+        (CreatePolicy::RequireExisting, Some(oid)) => has_contents(db, oid)?,
+        (CreatePolicy::RequireExistingOrMeta, Some(oid)) => {
+            has_contents(db, oid)? || has_meta(db, oid)?
+        }
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(Error::DocNotFound)
+    }
+}
+
+/// Checks if a document with a given `oid` has its state or any pending updates stored.
+fn has_contents<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<bool, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    Ok(db.get(&key_doc(oid))?.is_some()
+        || last_update(db, oid)?.is_some()
+        || channel::has_updates(db, oid)?)
+}
+
+/// Checks if a document with a given `oid` has any metadata entries stored.
+fn has_meta<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<bool, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let start = key_meta_start(oid);
+    let end = key_meta_end(oid);
+    let mut found = false;
+    db.iter_keys_range(&start, &end, &mut |key, _| {
+        found = key < end.as_ref();
+        false
+    })?;
+    Ok(found)
+}
+
+fn create_oid<'a, DB: DocOps<'a> + ?Sized>(db: &DB, name: &[u8]) -> Result<OID, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if let Some(limit) = db.config().max_name_len {
+        if name.len() > limit {
+            return Err(Error::InvalidDocName {
+                len: name.len(),
+                limit,
+            });
+        }
+    }
+    /*
+       OID index entries are ordered by document name, not by OID, so the last entry doesn't
+       necessarily hold the highest OID. Last allocated OID is kept in settings keyspace instead.
+       Stores created before that counter was introduced don't have it, so in that case we need
+       to scan the entire OID index once.
+    */
+    let counter_key = key_setting(SETTING_LAST_OID);
+    let last_oid = match db.get_for_update(&counter_key)? {
+        Some(value) => decode_oid(value.as_ref())?,
+        None => {
+            let start = Key::from_const([V1, KEYSPACE_OID]);
+            let end = Key::from_const([V1, KEYSPACE_DOC]);
+            let mut last_oid = 0;
+            for e in db.iter_range(&start, &end)? {
+                let (oid, _) = oid_value(e.value())?;
+                last_oid = last_oid.max(oid);
+            }
+            last_oid
+        }
+    };
+    let new_oid = last_oid + 1;
+    let key = key_oid(name);
+    db.upsert(&key, new_oid.to_be_bytes().as_ref())?;
+    db.upsert(&counter_key, new_oid.to_be_bytes().as_ref())?;
+    // document created under the name of a cleared one takes over its fence
+    let fence_key = key_fence(name);
+    if let Some(token) = db.get(&fence_key)? {
+        db.upsert(&key_meta(new_oid, META_FENCE), token.as_ref())?;
+        db.remove(&fence_key)?;
+    }
+    Ok(new_oid)
+}
+
+/// Summary of document entries read by [load_doc].
+struct Loaded {
+    /// True if document core state was used.
+    doc_state: bool,
+    /// Number of pending updates applied on top of document core state.
+    updates: u32,
+    /// Total size of keys and values of all entries read.
+    bytes: u64,
+    /// Total size of stored (encoded) values of applied pending updates, which is how they are
+    /// accounted by pending updates counter.
+    update_bytes: u64,
+    /// Sequence number of the last applied pending update.
+    last_seq: Option<u32>,
+    /// Number of applied updates pushed to channels other than [DEFAULT_CHANNEL]. These are
+    /// applied only by [load_doc].
+    channel_updates: u32,
+    /// Key of the last update entry read. Empty if no update has been applied.
+    last_key: Vec<u8>,
+    /// False if loading stopped at the deadline, before all pending updates were applied.
+    complete: bool,
+    /// True if document core state was already covered by the loaded transaction, so only its
+    /// deletions have been applied.
+    state_skipped: bool,
+    /// Number of pending updates (included in [Loaded::updates]) already covered by the loaded
+    /// transaction, which had only their deletions applied.
+    updates_skipped: u32,
+}
+
+impl Loaded {
+    /// Checks if any entries of the document have been found.
+    fn found(&self) -> bool {
+        self.doc_state || self.updates != 0 || self.channel_updates != 0
+    }
+}
+
+/// Applies stored state and pending updates of a document with a given `oid` to a given `txn`,
+/// followed by the updates of all of its channels. Fails with [Error::DeadlineExceeded] if
+/// [StoreConfig::op_deadline] passes before all pending updates are applied.
+fn load_doc<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    txn: &mut TransactionMut,
+) -> Result<Loaded, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    load_doc_scratch(db, oid, txn, &mut Vec::new())
+}
+
+/// Same as [load_doc], but decodes stored values into a given `scratch` buffer.
+fn load_doc_scratch<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    txn: &mut TransactionMut,
+    scratch: &mut Vec<u8>,
+) -> Result<Loaded, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let deadline = Deadline::start(db.config());
+    let mut loaded = load_doc_until(db, oid, txn, deadline, scratch)?;
+    match loaded.last_seq {
+        Some(seq) if !loaded.complete => Err(Error::DeadlineExceeded {
+            progress: Progress::Load { seq },
+        }),
+        _ => {
+            let (updates, bytes) = channel::apply_all(db, oid, txn)?;
+            loaded.channel_updates = updates;
+            loaded.bytes += bytes;
+            Ok(loaded)
+        }
+    }
+}
+
+/// Loads a live document with a given `name` into a given `txn`, recording the access if it has
+/// been found. Returns the OID of the document, or `None` if there's no live document under that
+/// name.
+fn load_live_doc<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    txn: &mut TransactionMut,
+    scratch: &mut Vec<u8>,
+) -> Result<Option<(OID, Loaded)>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let oid = match get_live_oid(db, name)? {
+        Some(oid) => oid,
+        None => return Ok(None),
+    };
+    let loaded = load_doc_scratch(db, oid, txn, scratch).map_err(|e| e.in_doc(name))?;
+    if loaded.found() {
+        access::record_access(db, oid)?;
+    }
+    Ok(Some((oid, loaded)))
+}
+
+/// Returns the state vector stored for a document with a given `oid`, which has no stored document
+/// state, if it claims changes missing from a given `txn` the document has been loaded into.
+fn orphaned_state_vector<'a, DB: DocOps<'a> + ?Sized, T: ReadTxn>(
+    db: &DB,
+    oid: OID,
+    txn: &T,
+) -> Result<Option<StateVector>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let stored = match db.get(&key_state_vector(oid))? {
+        Some(data) => decode_state_vector(&db.config().codec.decode(data.as_ref())?)?.0,
+        None => return Ok(None),
+    };
+    if sv_covered_by(&stored, &txn.state_vector()) {
+        Ok(None)
+    } else {
+        Ok(Some(stored))
+    }
+}
+
+/// Returns the state vector of changes, which updates applied within a given `txn` depend on, but
+/// which have not been received yet. Returns `None` if all applied updates have been integrated.
+fn missing_updates<T: ReadTxn>(txn: &T) -> Option<StateVector> {
+    let store = txn.store();
+    match (store.pending_update(), store.pending_ds()) {
+        (Some(pending), _) => Some(pending.missing.clone()),
+        // pending delete sets don't tell which changes they are waiting for
+        (None, Some(_)) => Some(StateVector::default()),
+        (None, None) => None,
+    }
+}
+
+/// Same as [load_doc], but returns the part of the document loaded before a given `deadline`
+/// passed. At least one pending update is always applied. Channel updates are not applied. Stored
+/// values are decoded into a given `scratch` buffer. Entries covered by the state vector of `txn`
+/// only have their deletions applied (see [covered_by]).
+fn load_doc_until<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    txn: &mut TransactionMut,
+    deadline: Deadline,
+    scratch: &mut Vec<u8>,
+) -> Result<Loaded, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let mut loaded = Loaded {
+        doc_state: false,
+        updates: 0,
+        bytes: 0,
+        update_bytes: 0,
+        last_seq: None,
+        channel_updates: 0,
+        last_key: Vec::new(),
+        complete: true,
+        state_skipped: false,
+        updates_skipped: 0,
+    };
+    // state of the document already present in the transaction, i.e. loaded by a previous call
+    let local = txn.state_vector();
+    {
+        let doc_key = key_doc(oid);
+        if let Some(doc_state) = db.get(&doc_key)? {
+            let doc_state = blob::resolve(db, doc_state.as_ref())?;
+            let state = decode_doc_state(db.config().codec.decode_into(&doc_state, scratch)?)?;
+            let state = if covered_by(&local, &state) {
+                loaded.state_skipped = true;
+                deletions_of(state)?
+            } else {
+                Some(state)
+            };
+            if let Some(state) = state {
+                txn.apply_update(state);
+            }
+            loaded.doc_state = true;
+            loaded.bytes += (doc_key.len() + doc_state.len()) as u64;
+        }
+    }
+    {
+        let update_key_start = key_update(oid, 0);
+        let update_key_end = key_update(oid, u32::MAX);
+        let mut iter = db.iter_range(&update_key_start, &update_key_end)?;
+        while let Some(e) = iter.next() {
+            if loaded.updates != 0 && deadline.exceeded() {
+                loaded.complete = false;
+                break;
+            }
+            let key = e.key();
+            let value = e.value();
+            // update segments are always applied as a whole
+            for record in segment::records(key, value) {
+                let (seq, update) = record?;
+                loaded.update_bytes += update.len() as u64;
+                let update = db.config().codec.decode_into(update, scratch)?;
+                let update = decode_update(Some(seq), update)?;
+                let update = if covered_by(&local, &update) {
+                    loaded.updates_skipped += 1;
+                    deletions_of(update)?
+                } else {
+                    Some(update)
+                };
+                if let Some(update) = update {
+                    txn.apply_update(update);
+                }
+                loaded.updates += 1;
+                loaded.last_seq = Some(seq);
+            }
+            loaded.bytes += (key.len() + value.len()) as u64;
+            loaded.last_key.clear();
+            loaded.last_key.extend_from_slice(key);
+        }
+    }
+    Ok(loaded)
+}
+
+/// Applies stored state and pending updates of a document with a given `oid` to a given `txn`,
+/// merging pending updates into batches of up to `budget` bytes. Returns the number of applied
+/// batches or `None` if document has neither state nor pending updates stored. Fails with
+/// [Error::DeadlineExceeded] if [StoreConfig::op_deadline] passes before all batches are
+/// applied.
+fn load_doc_batched<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    txn: &mut TransactionMut,
+    budget: usize,
+) -> Result<Option<u32>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let deadline = Deadline::start(db.config());
+    let codec = &db.config().codec;
+    let mut found = false;
+    if let Some(doc_state) = db.get(&key_doc(oid))? {
+        let doc_state = blob::resolve(db, doc_state.as_ref())?;
+        apply_doc_state(txn, &codec.decode(&doc_state)?)?;
+        found = true;
+    }
+    let mut batches = 0;
+    let mut batch = Vec::new();
+    let mut batch_bytes = 0;
+    // sequence numbers of the last update of the batch being read and of the last applied one
+    let mut batch_seq = 0;
+    let mut applied_seq = None;
+    let end = key_update(oid, u32::MAX);
+    for e in db.iter_range(&key_update(oid, 0), &end)? {
+        found = true;
+        for record in segment::records(e.key(), e.value()) {
+            let (seq, update) = record?;
+            let update = codec.decode(update)?;
+            if !batch.is_empty() && batch_bytes + update.len() > budget {
+                if let Some(seq) = applied_seq {
+                    if deadline.exceeded() {
+                        return Err(Error::DeadlineExceeded {
+                            progress: Progress::Load { seq },
+                        });
+                    }
+                }
+                txn.apply_update(Update::merge_updates(batch.drain(..)));
+                batches += 1;
+                batch_bytes = 0;
+                applied_seq = Some(batch_seq);
+            }
+            batch_bytes += update.len();
+            batch.push(decode_update(Some(seq), &update)?);
+            batch_seq = seq;
+        }
+    }
+    if !batch.is_empty() {
+        txn.apply_update(Update::merge_updates(batch));
+        batches += 1;
+    }
+    let (channel_updates, _) = channel::apply_all(db, oid, txn)?;
+    found |= channel_updates != 0;
+    Ok(if found { Some(batches) } else { None })
+}
+
+/// Applies stored state of a document with a given `oid` and its pending updates with sequence
+/// numbers not greater than `seq` to a given `txn`. Returns `None` if document has neither state
+/// nor pending updates stored. Channel updates are not applied.
+fn load_doc_at_seq<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    seq: u32,
+    txn: &mut TransactionMut,
+) -> Result<Option<SeqLoad>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let codec = &db.config().codec;
+    let mut doc_state = false;
+    if let Some(state) = db.get(&key_doc(oid))? {
+        let state = blob::resolve(db, state.as_ref())?;
+        apply_doc_state(txn, &codec.decode(&state)?)?;
+        doc_state = true;
+    }
+    let first_seq = first_update(db, oid)?.map(|e| segment::base_seq(e.key()));
+    match first_seq {
+        None if !doc_state => return Ok(None),
+        None => return Ok(Some(SeqLoad::Exact)),
+        // updates preceding the first pending one have been merged into document state
+        Some(first) if doc_state && seq.saturating_add(1) < first => {
+            return Ok(Some(SeqLoad::ClampedToFlushBase))
+        }
+        // none of the pending updates is old enough to be applied
+        Some(first) if first > seq => return Ok(Some(SeqLoad::Exact)),
+        Some(_) => {}
+    }
+    let end = key_update(oid, u32::MAX);
+    'entries: for e in db.iter_range(&key_update(oid, 0), &end)? {
+        for record in segment::records(e.key(), e.value()) {
+            let (update_seq, update) = record?;
+            if update_seq > seq {
+                break 'entries;
+            }
+            let update = decode_update(Some(update_seq), &codec.decode(update)?)?;
+            txn.apply_update(update);
+        }
+    }
+    Ok(Some(SeqLoad::Exact))
+}
+
+/// Returns the oldest pending update entry of a given document, if there are any. Just like in
+/// [last_update], update entries are looked up with a single [KVStore::seek] bounded to the
+/// document's update key range.
+fn first_update<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<Option<DB::Entry>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let end = key_update(oid, u32::MAX);
+    let first = db.seek(&key_update(oid, 0))?;
+    Ok(first.filter(|e| e.key() <= end.as_ref()))
+}
+
+/// Returns the most recent update entry of a given document, if there are any. See
+/// [last_in_range] for the cost of the lookup.
+fn last_update<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<Option<DB::Entry>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    last_in_range(db, &key_update(oid, 0), &key_update(oid, u32::MAX))
+}
+
+/// Returns the last entry between `from`..=`to` range of keys, if there are any. With
+/// [Capabilities::BOUNDED_PEEK_BACK] it's a single [KVStore::peek_back], so the cost doesn't
+/// depend on the number of entries in the range. Otherwise the range is walked backwards with
+/// [KVStore::iter_range_rev] if the store declares [Capabilities::REVERSE_ITER], or scanned
+/// forward if it doesn't.
+pub(crate) fn last_in_range<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    from: &[u8],
+    to: &[u8],
+) -> Result<Option<DB::Entry>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if DB::CAPABILITIES.contains(Capabilities::BOUNDED_PEEK_BACK) {
+        let last = db.peek_back(to)?;
+        Ok(last.filter(|e| e.key() >= from))
+    } else if DB::CAPABILITIES.contains(Capabilities::REVERSE_ITER) {
+        let mut last = None;
+        db.iter_range_rev(from, to, &mut |key, _| {
+            last = Some(key.to_vec());
+            false
+        })?;
+        match last {
+            Some(key) => Ok(db.seek(&key)?),
+            None => Ok(None),
         }
+    } else {
+        let last = db
+            .iter_range(from, to)?
+            .take_while(|e| e.key() <= to)
+            .last();
+        Ok(last)
     }
 }
 
-fn get_oid<'a, DB: DocOps<'a> + ?Sized>(db: &DB, name: &[u8]) -> Result<Option<OID>, Error>
+/// Reconstructs a document with a given `oid` and writes its state vector, together with the
+/// sequence number `last_seq` of the last pending update it covers.
+fn recompute_state_vector<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    last_seq: Option<u32>,
+) -> Result<StateVector, Error>
 where
     Error: From<<DB as KVStore<'a>>::Error>,
 {
-    let key = key_oid(name);
-    let value = db.get(&key)?;
-    if let Some(value) = value {
-        let bytes: [u8; 4] = value.as_ref().try_into().unwrap();
-        let oid = OID::from_be_bytes(bytes);
-        Ok(Some(oid))
-    } else {
-        Ok(None)
+    let doc = Doc::new();
+    load_doc(db, oid, &mut doc.transact_mut())?;
+    let sv = doc.transact().state_vector();
+    let mut data = sv.encode_v1();
+    if let Some(seq) = last_seq {
+        data.push(STATE_VEC_SEQ_MARKER);
+        data.extend_from_slice(&seq.to_be_bytes());
     }
+    let data = db.config().codec.encode(&data)?;
+    db.upsert(&key_state_vector(oid), &data)?;
+    Ok(sv)
 }
 
-fn get_or_create_oid<'a, DB: DocOps<'a> + ?Sized>(db: &DB, name: &[u8]) -> Result<OID, Error>
+/// Returns the state vector of the stored state of a given document, or `None` if it has no
+/// stored state. Clocks are read from the decoded blocks of the state, without integrating them
+/// into a document, which makes it much cheaper than loading the document.
+pub(crate) fn doc_state_sv<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+) -> Result<Option<StateVector>, Error>
 where
     Error: From<<DB as KVStore<'a>>::Error>,
 {
-    if let Some(oid) = get_oid(db, name)? {
-        Ok(oid)
-    } else {
-        /*
-           Since pattern is:
+    match db.get(&key_doc(oid))? {
+        Some(state) => {
+            let state = blob::resolve(db, state.as_ref())?;
+            let state = db.config().codec.decode(&state)?;
+            Ok(Some(decode_doc_state(&state)?.state_vector()))
+        }
+        None => Ok(None),
+    }
+}
 
-           00{doc_name:n}0      - OID key pattern
-           01{oid:4}0           - document key pattern
+/// Returns the sequence number of the most recent pending update of a given document, if there
+/// are any.
+fn last_seq<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<Option<u32>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    match last_update(db, oid)? {
+        Some(e) => Ok(Some(segment::last_seq(e.key(), e.value())?)),
+        None => Ok(None),
+    }
+}
 
-           Use 00{0000}0 to try to move cursor to GTE first document, then move cursor 1 position
-           back to get the latest OID or not found.
-        */
-        let last_oid = if let Some(e) = db.peek_back([V1, KEYSPACE_DOC].as_ref())? {
-            let value = e.value();
-            let last_value = OID::from_be_bytes(value.try_into().unwrap());
-            last_value
-        } else {
-            0
-        };
-        let new_oid = last_oid + 1;
-        let key = key_oid(name);
-        db.upsert(&key, new_oid.to_be_bytes().as_ref())?;
-        Ok(new_oid)
+/// Returns the update entry of a given document, which stores the update with a given sequence
+/// number `seq`, if it's still pending.
+fn update_entry<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    seq: u32,
+) -> Result<Option<DB::Entry>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    // segment starting at `seq` is the greatest key an entry storing it can have
+    let start = key_update(oid, 0);
+    match last_in_range(db, &start, &key_update_segment(oid, seq))? {
+        Some(e) if segment::last_seq(e.key(), e.value())? >= seq => Ok(Some(e)),
+        _ => Ok(None),
     }
 }
 
-fn load_doc<'a, DB: DocOps<'a> + ?Sized>(
+/// Appends a codec framed `update` to the most recent update segment of a document with a given
+/// `oid`, or starts a new segment if there's none or the update doesn't fit into it within
+/// `segment_size`. Returns the sequence number of the appended update.
+fn append_segment<'a, DB: DocOps<'a> + ?Sized>(
     db: &DB,
     oid: OID,
-    txn: &mut TransactionMut,
+    update: &[u8],
+    segment_size: usize,
 ) -> Result<u32, Error>
 where
     Error: From<<DB as KVStore<'a>>::Error>,
 {
-    let mut found = false;
-    {
-        let doc_key = key_doc(oid);
-        if let Some(doc_state) = db.get(&doc_key)? {
-            let update = Update::decode_v1(doc_state.as_ref())?;
-            txn.apply_update(update);
-            found = true;
+    let record_len = segment::record_len(update.len());
+    let last = last_update(db, oid)?;
+    let seq = match &last {
+        Some(e) => segment::last_seq(e.key(), e.value())? + 1,
+        None => 1,
+    };
+    match last {
+        Some(e) if segment::is_segment(e.key()) && e.value().len() + record_len <= segment_size => {
+            let mut value = Vec::with_capacity(e.value().len() + record_len);
+            value.extend_from_slice(e.value());
+            segment::append(&mut value, update);
+            db.upsert(e.key(), &value)?;
         }
-    }
-    let mut update_count = 0;
-    {
-        let update_key_start = key_update(oid, 0);
-        let update_key_end = key_update(oid, u32::MAX);
-        let mut iter = db.iter_range(&update_key_start, &update_key_end)?;
-        while let Some(e) = iter.next() {
-            let value = e.value();
-            let update = Update::decode_v1(value)?;
-            txn.apply_update(update);
-            update_count += 1;
+        _ => {
+            let mut value = Vec::with_capacity(record_len);
+            segment::append(&mut value, update);
+            db.upsert(&key_update_segment(oid, seq), &value)?;
         }
     }
-    if found {
-        update_count |= 1 << 31; // mark hi bit to note that document core state was used
+    Ok(seq)
+}
+
+/// Reads the clock of an update entry from its key. For update segments, it's the sequence number
+/// of their first update.
+/// Malformed keys too short to contain a clock have clock `0`.
+fn update_clock(key: &[u8]) -> u32 {
+    // update key scheme: 01{oid:4}2{clock:4}0
+    match key.len().checked_sub(CLOCK_LEN + 1) {
+        Some(start) => u32::from_be_bytes(key[start..(start + CLOCK_LEN)].try_into().unwrap()),
+        None => 0,
     }
-    Ok(update_count)
+}
+
+/// Removes pending updates, which have been merged into document state, as described by `loaded`,
+/// and updates pending updates counter accordingly.
+fn delete_updates_until<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    loaded: &Loaded,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    // segment keys sort after the key of their first update, so the range is bounded by the key
+    // of the last entry read rather than by the last sequence number
+    db.remove_range(&key_update(oid, 0), &loaded.last_key)?;
+    db.remove(&loaded.last_key)?;
+    let pending = get_pending(db, oid)?;
+    let pending = Pending {
+        updates: pending.updates.saturating_sub(loaded.updates),
+        bytes: pending.bytes.saturating_sub(loaded.update_bytes),
+    };
+    db.upsert(&key_pending(oid), &pending.encode())?;
+    Ok(())
 }
 
 fn delete_updates<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<(), Error>
@@ -438,6 +3669,42 @@ where
     let start = key_update(oid, 0);
     let end = key_update(oid, u32::MAX);
     db.remove_range(&start, &end)?;
+    db.remove(&key_pending(oid))?;
+    Ok(())
+}
+
+/// Returns options persisted by the most recent flush of a document with a given `oid`, used to
+/// flush and materialize it when no options are given explicitly.
+fn doc_options<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<yrs::Options, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let flags = match db.get(&key_meta(oid, META_DOC_OPTIONS))? {
+        Some(value) => value.as_ref().first().copied().unwrap_or(0),
+        None => 0,
+    };
+    Ok(yrs::Options {
+        skip_gc: flags & DOC_OPTION_SKIP_GC != 0,
+        ..yrs::Options::default()
+    })
+}
+
+/// Persists options of a flush, which has just written the state of a document with a given
+/// `oid` (see [doc_options]).
+fn store_doc_options<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    skip_gc: bool,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if skip_gc {
+        db.upsert(&key_meta(oid, META_DOC_OPTIONS), &[DOC_OPTION_SKIP_GC])?;
+    } else {
+        db.upsert(&key_meta(oid, META_GC), &[1])?;
+        db.remove(&key_meta(oid, META_DOC_OPTIONS))?;
+    }
     Ok(())
 }
 
@@ -445,49 +3712,614 @@ fn flush_doc<'a, DB: DocOps<'a> + ?Sized>(
     db: &DB,
     oid: OID,
     options: yrs::Options,
-) -> Result<Option<Doc>, Error>
+) -> Result<Option<FlushOutcome>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    match reconstruct(db, oid, options)? {
+        Some(reconstructed) => write_reconstructed(db, oid, reconstructed).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Document reconstructed from its stored state and pending updates by [reconstruct], ready to be
+/// written back by [write_reconstructed].
+struct Reconstructed {
+    doc: Doc,
+    loaded: Loaded,
+    doc_state: Vec<u8>,
+    state_vec: Vec<u8>,
+    bytes_before: u64,
+    start: Instant,
+}
+
+/// Reconstructs a document with a given `oid` from its stored state and pending updates, without
+/// writing anything. Returns `None` if there are no pending updates to merge.
+fn reconstruct<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    options: yrs::Options,
+) -> Result<Option<Reconstructed>, Error>
 where
     Error: From<<DB as KVStore<'a>>::Error>,
 {
+    let start = Instant::now();
     let doc = Doc::with_options(options);
-    let found = load_doc(db, oid, &mut doc.transact_mut())?;
-    if found & !(1 << 31) != 0 {
-        // loaded doc was generated from updates
-        let txn = doc.transact();
-        let doc_state = txn.encode_state_as_update_v1(&StateVector::default());
-        let state_vec = txn.state_vector().encode_v1();
-        drop(txn);
+    let deadline = Deadline::start(db.config());
+    let loaded = load_doc_until(db, oid, &mut doc.transact_mut(), deadline, &mut Vec::new())?;
+    if loaded.updates == 0 {
+        return Ok(None);
+    }
+    if loaded.complete {
+        // channel updates stay pending, but pending updates may depend on them
+        channel::apply_all(db, oid, &mut doc.transact_mut())?;
+    }
+    // loaded doc was generated from updates
+    let txn = doc.transact();
+    let doc_state = txn.encode_state_as_update_v1(&StateVector::default());
+    let state_vec = txn.state_vector().encode_v1();
+    drop(txn);
+    let codec = &db.config().codec;
+    let doc_state = codec.encode(&doc_state)?.into_owned();
+    let state_vec = codec.encode(&state_vec)?.into_owned();
+
+    let key_sv = key_state_vector(oid);
+    let mut bytes_before = loaded.bytes;
+    if let Some(prev_sv) = db.get(&key_sv)? {
+        bytes_before += (key_sv.len() + prev_sv.as_ref().len()) as u64;
+    }
+    Ok(Some(Reconstructed {
+        doc,
+        loaded,
+        doc_state,
+        state_vec,
+        bytes_before,
+        start,
+    }))
+}
+
+/// Writes the state of a document with a given `oid` reconstructed by [reconstruct] and removes
+/// the pending updates merged into it.
+fn write_reconstructed<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    reconstructed: Reconstructed,
+) -> Result<FlushOutcome, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let Reconstructed {
+        doc,
+        loaded,
+        doc_state,
+        state_vec,
+        bytes_before,
+        start,
+    } = reconstructed;
+    let bytes_after =
+        (key_doc(oid).len() + doc_state.len() + key_state_vector(oid).len() + state_vec.len())
+            as u64;
 
-        insert_inner_v1(db, oid, &doc_state, &state_vec)?;
-        delete_updates(db, oid)?;
-        Ok(Some(doc))
+    let pending_before = if db.config().size_history {
+        Some(get_pending(db, oid)?.bytes)
     } else {
-        Ok(None)
+        None
+    };
+    let skip_gc = doc.options().skip_gc;
+    with_intent(db, oid, &Intent::Flush { skip_gc }, || {
+        insert_inner(db, oid, &doc_state, &state_vec)?;
+        store_doc_options(db, oid, skip_gc)?;
+        match loaded.last_seq {
+            Some(_) if !loaded.complete => delete_updates_until(db, oid, &loaded),
+            _ => delete_updates(db, oid),
+        }
+    })?;
+    if let Some(pending_bytes) = pending_before {
+        size_history::append(db, oid, doc_state.len() as u64, pending_bytes)?;
+    }
+    if let (Some(seq), false) = (loaded.last_seq, loaded.complete) {
+        let remaining = get_pending(db, oid)?.updates;
+        return Err(Error::DeadlineExceeded {
+            progress: Progress::Flush { seq, remaining },
+        });
+    }
+    Ok(FlushOutcome {
+        doc,
+        updates_folded: loaded.updates,
+        bytes_before,
+        bytes_after,
+        duration: start.elapsed(),
+    })
+}
+
+/// Writes a document with a given `oid` reconstructed by [DocOps::prepare_flush], unless it has
+/// changed since then, in which case it's flushed from scratch.
+fn apply_prepared<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    prepared: PreparedFlush,
+) -> Result<Option<FlushOutcome>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    // every write appending updates rewrites the pending counter
+    lock_pending(db, oid)?;
+    if version::doc_version(db, oid)? == prepared.version {
+        write_reconstructed(db, oid, prepared.reconstructed).map(Some)
+    } else {
+        flush_doc(db, oid, doc_options(db, oid)?)
+    }
+}
+
+/// Removes all entries of a document with a given `name` and `oid`. It can be executed again if it
+/// has been interrupted, even if OID index entry has already been removed. Returned report covers
+/// only the entries removed by this call.
+fn clear_doc<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    oid: OID,
+) -> Result<ClearReport, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let mut report = ClearReport::default();
+    db.remove(&key_oid(name))?;
+    // fence outlives the document, so that writes of its previous owners remain refused
+    if let Some(token) = db.get(&key_meta(oid, META_FENCE))? {
+        db.upsert(&key_fence(name), token.as_ref())?;
+    }
+    guid::unindex(db, name, oid)?;
+    let archive_key = key_archive(oid);
+    if let Some(archived) = db.get(&archive_key)? {
+        report.doc_state_removed = true;
+        report.bytes_removed += (archive_key.len() + archived.as_ref().len()) as u64;
+        db.remove(&archive_key)?;
+    }
+    refs::unlink(db, oid)?;
+    sync_cache::remove_frames(db, oid, None)?;
+    // all document related elements are stored within bounds [0,1,..oid,0]..[0,1,..oid,255]
+    let start = key_doc_start(oid);
+    let end = key_doc_end(oid);
+    let deadline = Deadline::start(db.config());
+    // entries are removed only once the range has been read, since removing the entry under
+    // a cursor ends the iteration of some stores (i.e. LMDB)
+    let mut entries = Vec::new();
+    for v in db.iter_range(&start, &end)? {
+        let key: &[u8] = v.key();
+        if key > &end {
+            break; //TODO: for some reason key range doesn't always work
+        }
+        // document entry key scheme: 01{oid:4}{sub:1}..
+        let updates = match key.get(2 + OID_LEN) {
+            Some(&SUB_UPDATE) => segment::records(key, v.value()).count() as u32,
+            Some(&SUB_CHANNEL) => 1,
+            _ => 0,
+        };
+        entries.push((key.to_vec(), v.value().len(), updates));
+    }
+    for (removed, (key, value_len, updates)) in entries.into_iter().enumerate() {
+        if removed != 0 && deadline.exceeded() {
+            return Err(Error::DeadlineExceeded {
+                progress: Progress::Clear { key },
+            });
+        }
+        match key.get(2 + OID_LEN) {
+            Some(&SUB_DOC) => report.doc_state_removed = true,
+            Some(&SUB_META) => report.meta_removed += 1,
+            _ => {}
+        }
+        report.updates_removed += updates;
+        report.bytes_removed += (key.len() + value_len) as u64;
+        db.remove(&key)?;
+    }
+    Ok(report)
+}
+
+/// Clears a document with a given `name` and `oid`. If clearing is interrupted by
+/// [StoreConfig::op_deadline], it's recorded in the intent log, even if [StoreConfig::intent_log]
+/// is disabled, so that it can be completed by [resume_clear_doc] or [DocOps::recover_intents].
+fn clear_doc_recorded<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    oid: OID,
+) -> Result<ClearReport, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let intent = Intent::Clear {
+        name: name.to_vec(),
+    };
+    let cleared = usage::track(db, name, || {
+        with_intent(db, oid, &intent, || clear_doc(db, name, oid))
+    });
+    match cleared {
+        Err(e @ Error::DeadlineExceeded { .. }) => {
+            db.upsert(&key_intent(oid), &intent.encode())?;
+            Err(e)
+        }
+        other => other,
+    }
+}
+
+/// Completes clearing of a document with a given `name`, which OID index entry has already been
+/// removed by an interrupted [clear_doc_recorded] call.
+fn resume_clear_doc<'a, DB: DocOps<'a> + ?Sized>(db: &DB, name: &[u8]) -> Result<ClearReport, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let mut report = ClearReport::default();
+    for (oid, intent) in get_intents(db)? {
+        match intent {
+            Intent::Clear { name: cleared } if cleared.as_slice() == name => {
+                report += clear_doc_recorded(db, name, oid)?;
+                db.remove(&key_intent(oid))?;
+            }
+            _ => {}
+        }
     }
+    Ok(report)
 }
 
-fn insert_inner_v1<'a, DB: DocOps<'a> + ?Sized>(
+/// Returns all records of the intent log together with OIDs of documents they refer to.
+fn get_intents<'a, DB: DocOps<'a> + ?Sized>(db: &DB) -> Result<Vec<(OID, Intent)>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let start = Key::from_const([V1, KEYSPACE_INTENT]);
+    let end = Key::from_const([V1, KEYSPACE_INTENT + 1]);
+    let mut intents = Vec::new();
+    for e in db.iter_range(&start, &end)? {
+        let key = e.key();
+        if key >= end.as_ref() {
+            break;
+        }
+        // intents with keys too short to tell their document cannot be recovered
+        if let Some(oid) = key_oid_part(key) {
+            intents.push((oid, Intent::decode(e.value())?));
+        }
+    }
+    Ok(intents)
+}
+
+/// Executes a multi-key operation `f` on a document with a given `oid`. If
+/// [StoreConfig::intent_log] is enabled, operation is described by `intent` record stored before
+/// `f` is executed and removed once it completes successfully.
+fn with_intent<'a, DB, F, R>(db: &DB, oid: OID, intent: &Intent, f: F) -> Result<R, Error>
+where
+    DB: DocOps<'a> + ?Sized,
+    F: FnOnce() -> Result<R, Error>,
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if db.config().intent_log {
+        let key = key_intent(oid);
+        db.upsert(&key, &intent.encode())?;
+        let result = f()?;
+        db.remove(&key)?;
+        Ok(result)
+    } else {
+        f()
+    }
+}
+
+/// Writes document state together with its state vector, both already encoded using
+/// [StoreConfig::codec].
+///
+/// Stores which don't isolate readers from uncommitted writes could otherwise expose a new
+/// document state paired with the state vector of the previous one. To narrow that, the state
+/// vector is removed first and written back only after the document state: readers which read
+/// the document state before its state vector observe either a consistent pair or no state
+/// vector at all, which makes them fall back to the document state. Readers which read the state
+/// vector first may still pair it with a newer document state.
+fn insert_inner<'a, DB: DocOps<'a> + ?Sized>(
     db: &DB,
     oid: OID,
-    doc_state_v1: &[u8],
-    doc_sv_v1: &[u8],
+    doc_state: &[u8],
+    doc_sv: &[u8],
 ) -> Result<(), Error>
 where
     error::Error: From<<DB as KVStore<'a>>::Error>,
 {
     let key_doc = key_doc(oid);
     let key_sv = key_state_vector(oid);
-    db.upsert(&key_doc, doc_state_v1)?;
-    db.upsert(&key_sv, doc_sv_v1)?;
+    db.remove(&key_sv)?;
+    blob::put_state(db, &key_doc, doc_state)?;
+    db.upsert(&key_sv, doc_sv)?;
+    sync_cache::invalidate(db, oid)?;
+    Ok(())
+}
+
+/// Decodes stored document state. Fails with [Error::DocStateDecode], which name of the document
+/// is filled in by the caller.
+pub(crate) fn decode_doc_state(data: &[u8]) -> Result<Update, Error> {
+    Update::decode_v1(data).map_err(|e| Error::DocStateDecode {
+        doc: Vec::new(),
+        source: Box::new(e),
+    })
+}
+
+/// Decodes stored document state and applies it to a given `txn`. Fails with
+/// [Error::DocStateDecode], which name of the document is filled in by the caller.
+pub(crate) fn apply_doc_state(txn: &mut TransactionMut, data: &[u8]) -> Result<(), Error> {
+    txn.apply_update(decode_doc_state(data)?);
+    Ok(())
+}
+
+/// Checks if all blocks of a given `update` are already integrated into a document with a given
+/// state vector `local`, comparing clocks of every client. Updates without blocks (i.e. carrying
+/// only deletions) are never covered.
+fn covered_by(local: &StateVector, update: &Update) -> bool {
+    if local.is_empty() {
+        return false;
+    }
+    let upper = update.state_vector();
+    !upper.is_empty()
+        && upper
+            .iter()
+            .all(|(client, clock)| local.get(client) >= *clock)
+}
+
+/// Returns an update carrying only the deletions of a given `update`, or `None` if it has none.
+/// State vectors don't account for deletions, so they must be applied even if all blocks of an
+/// update are already known. Delete set of an update is not exposed by yrs, so the update is
+/// applied to a scratch document and its deletions are read back from there: deletions of
+/// integrated blocks from the document snapshot, the remaining ones from its pending delete set.
+fn deletions_of(update: Update) -> Result<Option<Update>, Error> {
+    let doc = Doc::new();
+    let mut txn = doc.transact_mut();
+    txn.apply_update(update);
+    let mut deletions = txn.snapshot().delete_set;
+    if let Some(pending) = txn.store().pending_ds() {
+        deletions.merge(pending.clone());
+    }
+    if deletions.is_empty() {
+        return Ok(None);
+    }
+    let mut encoder = EncoderV1::new();
+    encoder.write_var(0u32); // no blocks
+    deletions.encode(&mut encoder);
+    Ok(Some(Update::decode_v1(&encoder.to_vec())?))
+}
+
+/// Decodes a stored update with a given sequence number. Fails with [Error::UpdateDecode], which
+/// name of the document is filled in by the caller.
+pub(crate) fn decode_update(seq: Option<u32>, data: &[u8]) -> Result<Update, Error> {
+    Update::decode_v1(data).map_err(|e| Error::UpdateDecode {
+        doc: Vec::new(),
+        seq,
+        source: Box::new(e),
+    })
+}
+
+/// Decodes a state vector entry, returning the state vector and the sequence number of the last
+/// pending update it covers, if the entry records it (see [STATE_VEC_SEQ_MARKER]).
+fn decode_state_vector(data: &[u8]) -> Result<(StateVector, Option<u32>), Error> {
+    let mut cursor = Cursor::new(data);
+    let len: u32 = cursor.read_var()?;
+    for _ in 0..len {
+        let _client: u64 = cursor.read_var()?;
+        let _clock: u32 = cursor.read_var()?;
+    }
+    let (sv, trailer) = data.split_at(cursor.next);
+    let covered_seq = match trailer {
+        [] => None,
+        [STATE_VEC_SEQ_MARKER, seq @ ..] if seq.len() == CLOCK_LEN => {
+            Some(u32::from_be_bytes(seq.try_into().unwrap()))
+        }
+        _ => return Err(Error::CorruptedValue),
+    };
+    Ok((StateVector::decode_v1(sv)?, covered_seq))
+}
+
+/// Checks if every client clock of state vector `a` is covered by state vector `b`.
+fn sv_covered_by(a: &StateVector, b: &StateVector) -> bool {
+    a.iter().all(|(client, clock)| b.get(client) >= *clock)
+}
+
+/// Stores the current time as the last modification time of a given document, if
+/// [StoreConfig::timestamps] are enabled, and invalidates its cached sync frames.
+fn touch<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    sync_cache::invalidate(db, oid)?;
+    if db.config().timestamps {
+        let now = encode_timestamp(db.clock().now());
+        db.upsert(&key_meta(oid, META_LAST_MODIFIED), &now)?;
+    }
     Ok(())
 }
 
+fn archive_doc<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    oid: OID,
+    flags: u8,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let doc = Doc::new();
+    load_doc(db, oid, &mut doc.transact_mut())?;
+    let doc_state = doc
+        .transact()
+        .encode_state_as_update_v1(&StateVector::default());
+    let mut compressed = Vec::new();
+    config::compress(&doc_state, ARCHIVE_COMPRESSION_LEVEL, &mut compressed)?;
+
+    db.upsert(&key_archive(oid), &compressed)?;
+    db.upsert(&key_meta(oid, META_GC), &[1])?;
+    db.remove(&key_doc(oid))?;
+    db.remove(&key_state_vector(oid))?;
+    delete_updates(db, oid)?;
+    channel::delete_all(db, oid)?;
+    set_oid_flags(db, name, oid, flags | OID_FLAG_ARCHIVED)
+}
+
+fn unarchive_doc<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    oid: OID,
+    flags: u8,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let archive_key = key_archive(oid);
+    if let Some(compressed) = db.get(&archive_key)? {
+        let mut doc_state = Vec::new();
+        config::decompress(compressed.as_ref(), &mut doc_state)?;
+        let state_vector = decode_doc_state(&doc_state)
+            .map_err(|e| e.in_doc(name))?
+            .state_vector()
+            .encode_v1();
+        let codec = &db.config().codec;
+        insert_inner(
+            db,
+            oid,
+            &codec.encode(&doc_state)?,
+            &codec.encode(&state_vector)?,
+        )?;
+        db.remove(&archive_key)?;
+    }
+    set_oid_flags(db, name, oid, flags & !OID_FLAG_ARCHIVED)
+}
+
+/// Result of appending an update, returned by [DocOps::push_update].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PushReceipt {
+    /// Sequence number of a stored update.
+    pub seq: u32,
+    /// Number of updates, which have not been merged into document state yet, including the
+    /// stored one. It's 0 if the update triggered an automatic flush (see
+    /// [StoreConfig::flush_policy]).
+    pub pending_updates: u32,
+    /// Total size (in bytes) of stored values of updates counted by [Self::pending_updates].
+    pub pending_bytes: u64,
+    /// Set if the document didn't exist before and has been created by this call.
+    pub doc_created: bool,
+    /// Health of the underlying database at the time of the write, if the store reports it (see
+    /// [KVStore::health_hint]).
+    pub health: Option<HealthHint>,
+}
+
+/// Coarse health of a database, carried by [PushReceipt::health]. Variants are ordered from the
+/// healthiest one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HealthHint {
+    /// Writes are served without delays.
+    Ok,
+    /// Database is falling behind, i.e. writes are being slowed down or its resources are close
+    /// to being exhausted. Callers should reduce the rate of writes.
+    Degraded,
+    /// Writes are stopped or about to fail. Callers should stop writing until health recovers.
+    Stalled,
+}
+
+/// Data removed by [DocOps::clear_doc], [DocOps::clear_doc_with] and [DocOps::clear_doc_if].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClearReport {
+    /// Set if document state (either regular or archived) has been removed.
+    pub doc_state_removed: bool,
+    /// Number of removed pending updates.
+    pub updates_removed: u32,
+    /// Number of removed metadata entries, including reserved ones.
+    pub meta_removed: u32,
+    /// Total size (in bytes) of keys and values of removed document entries, including archived
+    /// state.
+    pub bytes_removed: u64,
+}
+
+impl std::ops::AddAssign for ClearReport {
+    fn add_assign(&mut self, other: Self) {
+        self.doc_state_removed |= other.doc_state_removed;
+        self.updates_removed += other.updates_removed;
+        self.meta_removed += other.meta_removed;
+        self.bytes_removed += other.bytes_removed;
+    }
+}
+
+/// Result of merging pending updates into document state, returned by [DocOps::flush_doc] and
+/// [DocOps::flush_doc_with].
+pub struct FlushOutcome {
+    /// Document containing the most recent state.
+    pub doc: Doc,
+    /// Number of pending updates merged into document state.
+    pub updates_folded: u32,
+    /// Total size of keys and values of document state, state vector and pending update entries
+    /// before the flush.
+    pub bytes_before: u64,
+    /// Total size of keys and values of document state and state vector entries written by
+    /// the flush.
+    pub bytes_after: u64,
+    /// Time it took to complete the flush.
+    pub duration: Duration,
+}
+
+/// Document reconstructed from its stored state and pending updates by [DocOps::prepare_flush],
+/// which can be written by [DocOps::apply_flush] in another transaction.
+pub struct PreparedFlush {
+    /// Version of the document the reconstruction started from.
+    version: DocVersion,
+    reconstructed: Reconstructed,
+}
+
+impl PreparedFlush {
+    /// Number of pending updates merged into the reconstructed document.
+    pub fn updates_folded(&self) -> u32 {
+        self.reconstructed.loaded.updates
+    }
+}
+
+/// Outcome of [DocOps::load_doc_checked].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadResult {
+    /// Set if the document has been found in the store.
+    pub found: bool,
+    /// State vector describing the changes which loaded updates depend on, but which have never
+    /// been received, or `None` if all loaded updates have been integrated into the document.
+    pub missing: Option<StateVector>,
+    /// State vector stored for a document without stored document state, if it claims changes
+    /// missing from the loaded document. It's left behind when document state has been removed
+    /// without its state vector, i.e. by a manual cleanup. See [DocOps::repair_drop_state_vector].
+    pub orphaned_sv: Option<StateVector>,
+    /// Set if the stored document state has been skipped, because the transaction already
+    /// covered it. See [DocOps::load_doc].
+    pub state_skipped: bool,
+    /// Number of pending updates applied to the transaction.
+    pub updates_applied: u32,
+    /// Number of pending updates skipped, because the transaction already covered them.
+    pub updates_skipped: u32,
+}
+
+/// Outcome of [DocOps::load_doc_at_seq].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeqLoad {
+    /// Document has been loaded the way it was right after the update with requested sequence
+    /// number has been stored.
+    Exact,
+    /// Requested update has been merged into document state by the last flush, so document has
+    /// been loaded the way it was when it was flushed.
+    ClampedToFlushBase,
+}
+
+/// Reply to the first step of y-sync protocol, returned by [DocOps::handle_sync_step1]. Both
+/// fields are encoded using lib0 v1 encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncStep2 {
+    /// Update containing all changes missing on the remote peer side.
+    pub update: Vec<u8>,
+    /// State vector of the local document.
+    pub state_vector: Vec<u8>,
+}
+
 pub struct DocsNameIter<I, E>
 where
     I: Iterator<Item = E>,
     E: KVEntry,
 {
     cursor: I,
+    end: Vec<u8>,
+    include_archived: bool,
 }
 
 impl<I, E> Iterator for DocsNameIter<I, E>
@@ -498,8 +4330,83 @@ where
     type Item = Box<[u8]>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let e = self.cursor.next()?;
-        Some(doc_oid_name(e.key()).into())
+        loop {
+            let e = self.cursor.next()?;
+            if e.key() >= self.end.as_slice() {
+                return None;
+            }
+            if !self.include_archived {
+                // entries with malformed values are listed, so that they can be cleared
+                let hidden = matches!(
+                    oid_value(e.value()),
+                    Ok((_, flags)) if flags & (OID_FLAG_ARCHIVED | OID_FLAG_DRAFT) != 0
+                );
+                if hidden {
+                    continue;
+                }
+            }
+            return Some(doc_oid_name(e.key()).into());
+        }
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl<I, E> DocsNameIter<I, E>
+where
+    I: Iterator<Item = E>,
+    E: KVEntry,
+{
+    /// Maps returned document names into [doc_id::DocName], decoding names of documents
+    /// identified by [doc_id::DocId] back into their UUIDs.
+    pub fn decoded(self) -> impl Iterator<Item = doc_id::DocName> {
+        self.map(doc_id::DocName::decode)
+    }
+}
+
+/// Cursor over update entries of a document, together with the end of their range, the codec of
+/// their values and the name of the document.
+type UpdatesCursor<I> = (I, Vec<u8>, ValueCodec, Box<[u8]>);
+
+/// Iterator over decoded pending updates of a document, returned by [DocOps::decoded_updates].
+pub struct UpdatesIter<I, E>(
+    Option<UpdatesCursor<I>>,
+    VecDeque<Result<(u32, Update), Error>>,
+)
+where
+    I: Iterator<Item = E>,
+    E: KVEntry;
+
+impl<I, E> Iterator for UpdatesIter<I, E>
+where
+    I: Iterator<Item = E>,
+    E: KVEntry,
+{
+    type Item = Result<(u32, Update), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(update) = self.1.pop_front() {
+                return Some(update);
+            }
+            let (cursor, end, codec, name) = self.0.as_mut()?;
+            let e = cursor.next()?;
+            let key = e.key();
+            if key > end.as_slice() {
+                self.0 = None;
+                return None;
+            }
+            // all updates of a segment are decoded at once
+            for record in segment::records(key, e.value()) {
+                let update = record.and_then(|(seq_nr, update)| {
+                    let update = codec.decode(update)?;
+                    match decode_update(Some(seq_nr), &update) {
+                        Ok(update) => Ok((seq_nr, update)),
+                        Err(e) => Err(e.in_doc(name)),
+                    }
+                });
+                self.1.push_back(update);
+            }
+        }
     }
 }
 
@@ -516,11 +4423,16 @@ where
     type Item = (Box<[u8]>, Box<[u8]>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let (cursor, _, _) = self.0.as_mut()?;
+        let (cursor, _, end) = self.0.as_mut()?;
         let v = cursor.next()?;
         let key = v.key();
+        // key ranges are inclusive, while the end key is the pending updates counter
+        if key >= end.as_slice() {
+            self.0 = None;
+            return None;
+        }
         let value = v.value();
-        let meta_key = &key[7..key.len() - 1];
+        let meta_key = doc_meta_name(key);
         Some((meta_key.into(), value.into()))
     }
 }
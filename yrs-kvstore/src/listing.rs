@@ -0,0 +1,170 @@
+//! Listing documents stored in a store together with their state.
+//!
+//! [DocOps::list_docs](crate::DocOps::list_docs) walks the OID index, which stores every document
+//! name exactly once, so documents are always listed in ascending order of their names and without
+//! duplicates. Which documents are listed and what is returned for each of them is decided by
+//! [ListOptions] in one place, instead of every caller filtering names on its own:
+//!
+//! ```rust,ignore
+//! let options = ListOptions {
+//!     prefix: b"workspace-1/".to_vec(),
+//!     include_archived: true,
+//!     include_drafts: false,
+//!     hints: true,
+//! };
+//! for doc in db_txn.list_docs(options)? {
+//!     let doc = doc?;
+//!     let hints = doc.hints.unwrap();
+//!     println!("{:?} archived: {}, pending: {}", doc.name, doc.archived, hints.has_pending_updates);
+//! }
+//! ```
+//!
+//! The listing is lazy: entries of the OID index are read as the iterator advances, and hints of
+//! a document are read only when that document is returned. Documents stored in a
+//! [NamespacedStore](crate::namespace::NamespacedStore) are listed only through that namespace.
+
+use crate::clock;
+use crate::error::Error;
+use crate::format::{decode_timestamp, META_LAST_MODIFIED, OID_FLAG_ARCHIVED, OID_FLAG_DRAFT};
+use crate::keys::{doc_oid_name, key_meta, OID};
+use crate::{get_pending, oid_value, DocOps, KVEntry, KVStore};
+use std::time::SystemTime;
+
+/// Selects documents listed by [DocOps::list_docs](crate::DocOps::list_docs) and what is returned
+/// for each of them. Default options list all documents, which are neither archived nor drafts,
+/// without hints.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ListOptions {
+    /// Only documents, which names start with a given prefix, are listed. Empty prefix matches
+    /// all documents.
+    pub prefix: Vec<u8>,
+    /// When set, documents archived with [DocOps::archive_doc](crate::DocOps::archive_doc) are
+    /// listed as well.
+    pub include_archived: bool,
+    /// When set, [drafts](crate::draft) which haven't been promoted yet are listed as well.
+    pub include_drafts: bool,
+    /// When set, [DocHints] are read and attached to every listed document.
+    pub hints: bool,
+}
+
+/// Document returned by [DocOps::list_docs](crate::DocOps::list_docs).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedDoc {
+    /// Name of the document.
+    pub name: Box<[u8]>,
+    /// Whether the document has been archived with
+    /// [DocOps::archive_doc](crate::DocOps::archive_doc).
+    pub archived: bool,
+    /// Whether the document is a [draft](crate::draft) which hasn't been promoted yet.
+    pub draft: bool,
+    /// Hints about the document state, present only if requested with [ListOptions::hints].
+    pub hints: Option<DocHints>,
+}
+
+/// Hints about the state of a listed document. Every hint is read from a single entry, without
+/// loading the document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DocHints {
+    /// Whether the document has updates which haven't been merged into its state yet, see
+    /// [DocOps::flush_doc](crate::DocOps::flush_doc).
+    pub has_pending_updates: bool,
+    /// Time of the last write modifying contents of the document, as returned by
+    /// [DocOps::last_modified](crate::DocOps::last_modified). Present only if it has been
+    /// recorded with [StoreConfig::timestamps](crate::config::StoreConfig::timestamps) enabled.
+    pub last_modified: Option<SystemTime>,
+}
+
+/// Lazy iterator over documents selected by [ListOptions], returned by
+/// [DocOps::list_docs](crate::DocOps::list_docs).
+pub struct DocListing<'d, 'a, DB>
+where
+    DB: DocOps<'a>,
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    db: &'d DB,
+    cursor: <DB as KVStore<'a>>::Cursor,
+    end: Vec<u8>,
+    options: ListOptions,
+}
+
+impl<'d, 'a, DB> DocListing<'d, 'a, DB>
+where
+    DB: DocOps<'a>,
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    pub(crate) fn new(
+        db: &'d DB,
+        cursor: <DB as KVStore<'a>>::Cursor,
+        end: Vec<u8>,
+        options: ListOptions,
+    ) -> Self {
+        DocListing {
+            db,
+            cursor,
+            end,
+            options,
+        }
+    }
+
+    fn hints(&self, oid: OID) -> Result<DocHints, Error> {
+        let pending = get_pending(self.db, oid)?;
+        let last_modified = match self.db.get(&key_meta(oid, META_LAST_MODIFIED))? {
+            Some(value) => {
+                let millis = decode_timestamp(value.as_ref()).ok_or(Error::CorruptedValue)?;
+                Some(clock::system_time(millis))
+            }
+            None => None,
+        };
+        Ok(DocHints {
+            has_pending_updates: pending.updates > 0,
+            last_modified,
+        })
+    }
+}
+
+impl<'d, 'a, DB> Iterator for DocListing<'d, 'a, DB>
+where
+    DB: DocOps<'a>,
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    type Item = Result<ListedDoc, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let e = self.cursor.next()?;
+            if e.key() >= self.end.as_slice() {
+                return None;
+            }
+            let name: Box<[u8]> = doc_oid_name(e.key()).into();
+            // entries with malformed values are listed, so that they can be cleared
+            let (oid, archived, draft) = match oid_value(e.value()) {
+                Ok((oid, flags)) => (
+                    Some(oid),
+                    flags & OID_FLAG_ARCHIVED != 0,
+                    flags & OID_FLAG_DRAFT != 0,
+                ),
+                Err(_) => (None, false, false),
+            };
+            if (archived && !self.options.include_archived)
+                || (draft && !self.options.include_drafts)
+            {
+                continue;
+            }
+            let hints = if self.options.hints {
+                match oid.map(|oid| self.hints(oid)) {
+                    Some(Ok(hints)) => Some(hints),
+                    Some(Err(e)) => return Some(Err(e)),
+                    None => return Some(Err(Error::CorruptedValue)),
+                }
+            } else {
+                None
+            };
+            return Some(Ok(ListedDoc {
+                name,
+                archived,
+                draft,
+                hints,
+            }));
+        }
+    }
+}
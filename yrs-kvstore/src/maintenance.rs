@@ -0,0 +1,460 @@
+//! Periodic store maintenance, combining several housekeeping steps behind a single call.
+//!
+//! [DocOps::maintain](crate::DocOps::maintain) walks all documents of the store in the order of
+//! their names and, depending on [MaintenanceOptions]:
+//!
+//! - merges pending updates of documents which accumulated too many of them into document state
+//!   (see [DocOps::flush_doc](crate::DocOps::flush_doc)),
+//! - verifies that stored values of a sample of documents pass checksum verification (see
+//!   [ValueCodec](crate::config::ValueCodec)) and can be decoded,
+//! - once all documents have been visited, removes orphaned document entries, which are no longer
+//!   referenced by any document name (i.e. left over by interrupted writes of stores that cannot
+//!   apply them atomically).
+//!
+//! Orphaned entries may also be left behind by a damaged OID index, which lost entries of documents
+//! still holding their contents. Before running vacuum on such a store,
+//! [DocOps::orphaned_oids](crate::DocOps::orphaned_oids) lists OIDs of all orphaned documents and
+//! [DocOps::adopt_orphan](crate::DocOps::adopt_orphan) makes any of them reachable again under
+//! a new name:
+//!
+//! ```rust,ignore
+//! for (i, oid) in db_txn.orphaned_oids()?.into_iter().enumerate() {
+//!     db_txn.adopt_orphan(oid, &format!("recovered/{}", i))?;
+//! }
+//! ```
+//!
+//! A single call visits a bounded number of documents and stops once its time budget is exceeded.
+//! The [MaintenanceReport] it returns carries a cursor, which should be passed to the next call in
+//! order to continue where the previous one stopped. This way maintenance can be run
+//! incrementally, e.g. from a periodic job, without holding a database transaction for long:
+//!
+//! ```rust,ignore
+//! let mut options = MaintenanceOptions {
+//!     compact_threshold: Some(128),
+//!     verify_percent: 10,
+//!     vacuum: true,
+//!     ..MaintenanceOptions::default()
+//! };
+//! loop {
+//!     let db_txn = RocksDBStore::from(db.transaction());
+//!     let report = db_txn.maintain(options.clone())?;
+//!     db_txn.commit()?;
+//!     for (name, error) in report.corrupted {
+//!         eprintln!("document {:?} is corrupted: {:?}", name, error);
+//!     }
+//!     match report.cursor {
+//!         Some(cursor) => options.cursor = Some(cursor),
+//!         None => break,
+//!     }
+//! }
+//! ```
+
+use crate::error::Error;
+use crate::keys::{
+    doc_oid_name, key_archive, key_doc_end, key_doc_start, key_oid, key_oid_part, key_setting,
+    key_state_vector, Key, KEYSPACE_DOC, KEYSPACE_OID, OID, OID_FLAG_ARCHIVED, SETTING_LAST_OID,
+    V1,
+};
+use crate::{
+    decode_oid, doc_options, flush_doc, get_pending, load_doc, oid_value, usage, DocOps, KVEntry,
+    KVStore,
+};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use yrs::updates::decoder::Decode;
+use yrs::{Doc, StateVector, Transact};
+
+/// Steps executed by [DocOps::maintain](crate::DocOps::maintain) and the limits of a single
+/// call. See [module documentation](self) for details.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaintenanceOptions {
+    /// Documents with at least this many pending updates get them merged into document state.
+    /// `None` disables compaction.
+    pub compact_threshold: Option<u32>,
+    /// Percentage (0-100) of visited documents, which stored values are verified.
+    pub verify_percent: u8,
+    /// When set, orphaned document entries are removed once all documents have been visited.
+    pub vacuum: bool,
+    /// Maximum number of documents (or orphan candidates) visited by a single call.
+    pub max_docs: u32,
+    /// Time after which a single call stops visiting further documents. At least one document
+    /// is always visited, so that every call makes progress. When
+    /// [StoreConfig::op_deadline](crate::config::StoreConfig::op_deadline) is shorter, it's used
+    /// instead.
+    pub max_duration: Option<Duration>,
+    /// Position to continue from, returned by the previous call in [MaintenanceReport::cursor].
+    /// `None` starts from the beginning.
+    pub cursor: Option<MaintenanceCursor>,
+}
+
+impl Default for MaintenanceOptions {
+    fn default() -> Self {
+        MaintenanceOptions {
+            compact_threshold: None,
+            verify_percent: 0,
+            vacuum: false,
+            max_docs: 1000,
+            max_duration: None,
+            cursor: None,
+        }
+    }
+}
+
+/// Opaque position of the maintenance process, used to resume it with the next call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaintenanceCursor {
+    step: Step,
+    /// Accumulator used to verify evenly spread [MaintenanceOptions::verify_percent] of documents.
+    sample: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    /// Visiting documents with names greater than a given one.
+    Docs { after: Option<Vec<u8>> },
+    /// Looking for orphaned entries of documents with OIDs starting from a given one.
+    Vacuum { next_oid: OID },
+}
+
+/// Summary of a single [DocOps::maintain](crate::DocOps::maintain) call.
+#[derive(Debug)]
+pub struct MaintenanceReport {
+    /// Number of visited documents.
+    pub docs_visited: u32,
+    /// Number of documents which had their pending updates merged into document state.
+    pub docs_compacted: u32,
+    /// Total number of pending updates merged into document state.
+    pub updates_folded: u32,
+    /// Number of documents which stored values have been verified.
+    pub docs_verified: u32,
+    /// Names of documents which failed verification, together with the reason.
+    pub corrupted: Vec<(Box<[u8]>, Error)>,
+    /// Number of orphaned documents, which entries have been removed.
+    pub orphans_removed: u32,
+    /// Time it took to complete the call.
+    pub duration: Duration,
+    /// Position to pass to the next call in [MaintenanceOptions::cursor] or `None` if the whole
+    /// store has been processed.
+    pub cursor: Option<MaintenanceCursor>,
+}
+
+pub(crate) fn maintain<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    options: MaintenanceOptions,
+) -> Result<MaintenanceReport, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let start = Instant::now();
+    let mut report = MaintenanceReport {
+        docs_visited: 0,
+        docs_compacted: 0,
+        updates_folded: 0,
+        docs_verified: 0,
+        corrupted: Vec::new(),
+        orphans_removed: 0,
+        duration: Duration::default(),
+        cursor: None,
+    };
+    let mut cursor = options.cursor.clone().unwrap_or(MaintenanceCursor {
+        step: Step::Docs { after: None },
+        sample: 0,
+    });
+    let mut budget = Budget {
+        start,
+        visited: 0,
+        max_docs: options.max_docs.max(1),
+        // operations of a single visit are bound by the store deadline as well
+        max_duration: match (options.max_duration, db.config().op_deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        },
+    };
+    if let Step::Docs { after } = &cursor.step {
+        let after = after.clone();
+        cursor.step = match visit_docs(
+            db,
+            &options,
+            after,
+            &mut cursor.sample,
+            &mut budget,
+            &mut report,
+        )? {
+            Some(last) => Step::Docs { after: Some(last) },
+            None => Step::Vacuum { next_oid: 1 },
+        };
+    }
+    report.cursor = match cursor.step {
+        Step::Docs { .. } => Some(cursor),
+        Step::Vacuum { next_oid } if options.vacuum => {
+            match vacuum(db, next_oid, &mut budget, &mut report)? {
+                Some(next_oid) => Some(MaintenanceCursor {
+                    step: Step::Vacuum { next_oid },
+                    sample: cursor.sample,
+                }),
+                None => None,
+            }
+        }
+        Step::Vacuum { .. } => None,
+    };
+    report.docs_visited = budget.visited;
+    report.duration = start.elapsed();
+    Ok(report)
+}
+
+/// Limits of a single maintenance call.
+struct Budget {
+    start: Instant,
+    visited: u32,
+    max_docs: u32,
+    max_duration: Option<Duration>,
+}
+
+impl Budget {
+    /// Checks if another document can be visited and counts it if so.
+    fn take(&mut self) -> bool {
+        if self.visited >= self.max_docs {
+            return false;
+        }
+        if let Some(max_duration) = self.max_duration {
+            if self.visited > 0 && self.start.elapsed() >= max_duration {
+                return false;
+            }
+        }
+        self.visited += 1;
+        true
+    }
+
+    fn remaining(&self) -> usize {
+        (self.max_docs - self.visited) as usize
+    }
+}
+
+/// Compacts and verifies documents with names greater than `after`. Returns the name of the last
+/// visited document or `None` if there are no more documents to visit.
+fn visit_docs<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    options: &MaintenanceOptions,
+    after: Option<Vec<u8>>,
+    sample: &mut u32,
+    budget: &mut Budget,
+    report: &mut MaintenanceReport,
+) -> Result<Option<Vec<u8>>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let start = match after {
+        Some(name) => key_oid(&name).into(),
+        None => vec![V1, KEYSPACE_OID],
+    };
+    let end = Key::from_const([V1, KEYSPACE_DOC]);
+    // collect entries first, since compaction writes into the store
+    let docs: Vec<(Vec<u8>, OID, u8)> = db
+        .iter_range(&start, &end)?
+        .take_while(|e| e.key() < end.as_ref())
+        .filter(|e| e.key() != start.as_slice())
+        .take(budget.remaining() + 1)
+        .map(|e| {
+            let (oid, flags) = oid_value(e.value())?;
+            Ok((doc_oid_name(e.key()).to_vec(), oid, flags))
+        })
+        .collect::<Result<_, Error>>()?;
+    let mut last = None;
+    for (name, oid, flags) in docs {
+        if !budget.take() {
+            return Ok(last);
+        }
+        if flags & OID_FLAG_ARCHIVED == 0 {
+            let mut verified = true;
+            *sample += options.verify_percent.min(100) as u32;
+            if *sample >= 100 {
+                *sample -= 100;
+                match verify_doc(db, oid) {
+                    Ok(()) => report.docs_verified += 1,
+                    // document is too large to verify within the deadline
+                    Err(Error::DeadlineExceeded { .. }) => verified = false,
+                    Err(e) => {
+                        report.docs_verified += 1;
+                        report.corrupted.push((name.as_slice().into(), e));
+                        verified = false;
+                    }
+                }
+            }
+            if let (Some(threshold), true) = (options.compact_threshold, verified) {
+                if get_pending(db, oid)?.updates >= threshold {
+                    let options = doc_options(db, oid)?;
+                    match usage::track(db, &name, || flush_doc(db, oid, options)) {
+                        Ok(Some(outcome)) => {
+                            report.docs_compacted += 1;
+                            report.updates_folded += outcome.updates_folded;
+                        }
+                        Ok(None) => {}
+                        // partially flushed, next call continues with the same document
+                        Err(Error::DeadlineExceeded { .. }) => return Ok(last),
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+        last = Some(name);
+    }
+    Ok(None)
+}
+
+/// Checks that stored document state, state vector and pending updates of a document with
+/// a given `oid` pass codec verification and can be decoded.
+fn verify_doc<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let doc = Doc::new();
+    load_doc(db, oid, &mut doc.transact_mut())?;
+    if let Some(sv) = db.get(&key_state_vector(oid))? {
+        StateVector::decode_v1(&db.config().codec.decode(sv.as_ref())?)?;
+    }
+    Ok(())
+}
+
+/// Removes entries of documents with OIDs starting from `next_oid`, which are not referenced by
+/// the OID index. Returns the OID to continue from or `None` if all documents have been visited.
+fn vacuum<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    mut next_oid: OID,
+    budget: &mut Budget,
+    report: &mut MaintenanceReport,
+) -> Result<Option<OID>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let live = live_oids(db)?;
+    loop {
+        let oid = match next_doc_oid(db, next_oid)? {
+            Some(oid) => oid,
+            None => return Ok(None),
+        };
+        if !budget.take() {
+            return Ok(Some(oid));
+        }
+        if !live.contains(&oid) {
+            crate::refs::unlink(db, oid)?;
+            db.remove_range(&key_doc_start(oid), &key_doc_end(oid))?;
+            db.remove(&key_archive(oid))?;
+            report.orphans_removed += 1;
+        }
+        match oid.checked_add(1) {
+            Some(oid) => next_oid = oid,
+            None => return Ok(None),
+        }
+    }
+}
+
+/// Returns OIDs of documents, which have entries stored in the document key space, but are not
+/// referenced by the OID index, in ascending order.
+pub(crate) fn orphaned_oids<'a, DB: DocOps<'a> + ?Sized>(db: &DB) -> Result<Vec<OID>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let live = live_oids(db)?;
+    let mut orphans = Vec::new();
+    let mut next_oid = 1;
+    while let Some(oid) = next_doc_oid(db, next_oid)? {
+        if !live.contains(&oid) {
+            orphans.push(oid);
+        }
+        match oid.checked_add(1) {
+            Some(oid) => next_oid = oid,
+            None => break,
+        }
+    }
+    Ok(orphans)
+}
+
+/// Makes entries of an orphaned document with a given `oid` reachable again under a given `name`.
+/// Returns `false` if a document with that name already exists or there are no entries stored
+/// under that OID. Fails with [Error::OidClaimed] if the OID is referenced by another document.
+pub(crate) fn adopt_orphan<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    name: &[u8],
+) -> Result<bool, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if let Some(limit) = db.config().max_name_len {
+        if name.len() > limit {
+            return Err(Error::InvalidDocName {
+                len: name.len(),
+                limit,
+            });
+        }
+    }
+    if db.get_for_update(&key_oid(name))?.is_some() || next_doc_oid(db, oid)? != Some(oid) {
+        return Ok(false);
+    }
+    /*
+       OID counter is read for update and rewritten, so that concurrent transactions adopting
+       the same OID conflict with each other. It's also moved past the adopted OID, in case it
+       has been lost together with OID index entries, so that new documents never reuse it.
+    */
+    let counter_key = key_setting(SETTING_LAST_OID);
+    let mut last_oid = match db.get_for_update(&counter_key)? {
+        Some(value) => decode_oid(value.as_ref())?,
+        None => 0,
+    };
+    let start = Key::from_const([V1, KEYSPACE_OID]);
+    let end = Key::from_const([V1, KEYSPACE_DOC]);
+    for e in db.iter_range(&start, &end)? {
+        if e.key() >= end.as_ref() {
+            break;
+        }
+        let (claimed, _) = oid_value(e.value())?;
+        if claimed == oid {
+            return Err(Error::OidClaimed {
+                owner: doc_oid_name(e.key()).to_vec(),
+            });
+        }
+        last_oid = last_oid.max(claimed);
+    }
+    db.upsert(&counter_key, &last_oid.max(oid).to_be_bytes())?;
+    usage::track(db, name, || {
+        db.upsert(&key_oid(name), &oid.to_be_bytes())?;
+        Ok(true)
+    })
+}
+
+/// Returns OIDs referenced by the OID index.
+fn live_oids<'a, DB: DocOps<'a> + ?Sized>(db: &DB) -> Result<HashSet<OID>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let start = Key::from_const([V1, KEYSPACE_OID]);
+    let end = Key::from_const([V1, KEYSPACE_DOC]);
+    let mut live = HashSet::new();
+    for e in db.iter_range(&start, &end)? {
+        if e.key() >= end.as_ref() {
+            break;
+        }
+        live.insert(oid_value(e.value())?.0);
+    }
+    Ok(live)
+}
+
+/// Returns the lowest OID at or past `from`, which has entries stored in the document key space.
+fn next_doc_oid<'a, DB: DocOps<'a> + ?Sized>(db: &DB, from: OID) -> Result<Option<OID>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    // seek to the first document entry at or past `from`, which may be a large document state, so
+    // its value is not read
+    let end = Key::from_const([V1, KEYSPACE_DOC + 1]);
+    let mut found = None;
+    db.iter_keys_range(&key_doc_start(from), &end, &mut |key, _| {
+        if key >= end.as_ref() {
+            return false;
+        }
+        // keys too short to contain an OID are skipped
+        found = key_oid_part(key);
+        found.is_none()
+    })?;
+    Ok(found)
+}
@@ -0,0 +1,288 @@
+//! Memoization of document lookups repeated within a single store instance.
+//!
+//! Composite [DocOps] methods are built from smaller ones, each resolving the document on its own:
+//! [DocOps::get_diff] resolves the OID of the document, then loads it with
+//! [DocOps::load_doc_checked], which resolves it again, and a sync handshake calling
+//! [DocOps::get_state_vector] first reads the same entries once more. [MemoStore] wraps any
+//! [KVStore] and remembers values of the entries read this way - OID index entries (including
+//! missing ones) and document state vectors - for its own lifetime:
+//!
+//! ```rust,ignore
+//! let db_txn = MemoStore::new(RocksDBStore::from(db.transaction()));
+//! let (sv, _) = db_txn.get_state_vector("my-doc-name")?;
+//! let diff = db_txn.get_diff("my-doc-name", &remote_sv)?; // OID and state vector are memoized
+//! db_txn.into_inner().commit()?;
+//! ```
+//!
+//! Memoized entries are updated by every write performed through the same [MemoStore], so reads
+//! always observe writes made by the current transaction. Since a store instance corresponds to
+//! a single transaction, which is short-lived, entries are not refreshed otherwise: writes made
+//! through the wrapped store directly are not observed and changes committed concurrently by
+//! other transactions are not observed even by stores with [ReadIsolation::BestEffort] reads.
+//! [MemoStore::clear] drops all memoized entries.
+
+use crate::blob::BlobStore;
+use crate::capabilities::Capabilities;
+use crate::clock::Clock;
+use crate::config::{StoreConfig, UpdateValidator};
+use crate::error::Error;
+use crate::keys::{KEYSPACE_DOC, KEYSPACE_OID, SUB_STATE_VEC, V1};
+use crate::usage::TenantExtractor;
+use crate::{DocOps, HealthHint, KVStore, ReadIsolation, ScanMode};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::ops::{Bound, Deref};
+use std::rc::Rc;
+
+/// Memoized value of an entry, `None` if the entry doesn't exist.
+type Memoized = Option<Rc<[u8]>>;
+
+/// Default maximum number of entries memoized by [MemoStore::new].
+pub const DEFAULT_MEMO_CAPACITY: usize = 1024;
+
+/// Wrapper around any [KVStore], which memoizes OID index entries and document state vectors
+/// read through it. See [memo](crate::memo) module for details.
+pub struct MemoStore<S> {
+    store: S,
+    capacity: usize,
+    entries: RefCell<BTreeMap<Vec<u8>, Memoized>>,
+    stats: Cell<MemoStats>,
+}
+
+/// Lookups of memoized entries counted by [MemoStore].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoStats {
+    /// Number of lookups served from memoized entries.
+    pub hits: u64,
+    /// Number of lookups forwarded to the wrapped store.
+    pub misses: u64,
+}
+
+impl<S> MemoStore<S> {
+    /// Wraps a given `store`, memoizing up to [DEFAULT_MEMO_CAPACITY] entries.
+    pub fn new(store: S) -> Self {
+        Self::with_capacity(store, DEFAULT_MEMO_CAPACITY)
+    }
+
+    /// Wraps a given `store`, memoizing up to `capacity` entries. Once the capacity is reached,
+    /// all memoized entries are dropped, so that scans over many documents don't grow the memo
+    /// without bounds.
+    pub fn with_capacity(store: S, capacity: usize) -> Self {
+        MemoStore {
+            store,
+            capacity,
+            entries: RefCell::new(BTreeMap::new()),
+            stats: Cell::new(MemoStats::default()),
+        }
+    }
+
+    /// Returns lookups counted so far.
+    pub fn stats(&self) -> MemoStats {
+        self.stats.get()
+    }
+
+    /// Drops all memoized entries, i.e. after writing to the wrapped store directly.
+    pub fn clear(&self) {
+        self.entries.borrow_mut().clear();
+    }
+
+    /// Unwraps the underlying store, i.e. in order to commit it.
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+
+    fn memoize(&self, key: &[u8], value: Option<&[u8]>) -> Memoized {
+        let value: Memoized = value.map(Rc::from);
+        let mut entries = self.entries.borrow_mut();
+        if entries.len() >= self.capacity && !entries.contains_key(key) {
+            entries.clear();
+        }
+        entries.insert(key.to_vec(), value.clone());
+        value
+    }
+
+    fn record(&self, hit: bool) {
+        let mut stats = self.stats.get();
+        if hit {
+            stats.hits += 1;
+        } else {
+            stats.misses += 1;
+        }
+        self.stats.set(stats);
+    }
+}
+
+/// Checks if an entry under a given `key` is memoized: an OID index entry or a document state
+/// vector.
+fn is_memoized(key: &[u8]) -> bool {
+    matches!(
+        key,
+        [V1, KEYSPACE_OID, ..] | [V1, KEYSPACE_DOC, _, _, _, _, SUB_STATE_VEC]
+    )
+}
+
+/// Gives access to the wrapped store. Writes made through it directly are not observed by
+/// memoized entries, see [MemoStore::clear].
+impl<S> Deref for MemoStore<S> {
+    type Target = S;
+
+    fn deref(&self) -> &Self::Target {
+        &self.store
+    }
+}
+
+/// Value returned by [MemoStore], either read from the wrapped store or memoized.
+pub enum MemoValue<R> {
+    /// Value read from the wrapped store.
+    Store(R),
+    /// Memoized value.
+    Memo(Rc<[u8]>),
+}
+
+impl<R: AsRef<[u8]>> AsRef<[u8]> for MemoValue<R> {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            MemoValue::Store(value) => value.as_ref(),
+            MemoValue::Memo(value) => value,
+        }
+    }
+}
+
+impl<'a, S: KVStore<'a>> KVStore<'a> for MemoStore<S> {
+    type Error = S::Error;
+    type Cursor = S::Cursor;
+    type Entry = S::Entry;
+    type Return = MemoValue<S::Return>;
+
+    const CAPABILITIES: Capabilities = S::CAPABILITIES;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+        if !is_memoized(key) {
+            return Ok(self.store.get(key)?.map(MemoValue::Store));
+        }
+        if let Some(value) = self.entries.borrow().get(key) {
+            self.record(true);
+            return Ok(value.clone().map(MemoValue::Memo));
+        }
+        self.record(false);
+        let value = self.store.get(key)?;
+        let value = self.memoize(key, value.as_ref().map(AsRef::as_ref));
+        Ok(value.map(MemoValue::Memo))
+    }
+
+    fn get_for_update(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+        // always forwarded, so that the key gets locked by the wrapped store
+        let value = self.store.get_for_update(key)?;
+        if !is_memoized(key) {
+            return Ok(value.map(MemoValue::Store));
+        }
+        let value = self.memoize(key, value.as_ref().map(AsRef::as_ref));
+        Ok(value.map(MemoValue::Memo))
+    }
+
+    fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, Self::Error> {
+        self.store.get_many(keys)
+    }
+
+    fn upsert(&self, key: &[u8], value: &[u8]) -> Result<(), Self::Error> {
+        self.store.upsert(key, value)?;
+        if is_memoized(key) {
+            self.memoize(key, Some(value));
+        }
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+        self.store.remove(key)?;
+        if is_memoized(key) {
+            self.memoize(key, None);
+        }
+        Ok(())
+    }
+
+    fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<u32, Self::Error> {
+        let removed = self.store.remove_range(from, to)?;
+        let mut entries = self.entries.borrow_mut();
+        let range = (Bound::Included(from), Bound::Included(to));
+        for (_, value) in entries.range_mut::<[u8], _>(range) {
+            *value = None;
+        }
+        Ok(removed)
+    }
+
+    fn iter_range(&self, from: &[u8], to: &[u8]) -> Result<Self::Cursor, Self::Error> {
+        self.store.iter_range(from, to)
+    }
+
+    fn iter_range_with(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        mode: ScanMode,
+    ) -> Result<Self::Cursor, Self::Error> {
+        self.store.iter_range_with(from, to, mode)
+    }
+
+    fn iter_keys_range(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        f: &mut dyn FnMut(&[u8], usize) -> bool,
+    ) -> Result<(), Self::Error> {
+        self.store.iter_keys_range(from, to, f)
+    }
+
+    fn iter_range_rev(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        f: &mut dyn FnMut(&[u8], &[u8]) -> bool,
+    ) -> Result<(), Self::Error> {
+        self.store.iter_range_rev(from, to, f)
+    }
+
+    fn peek_back(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+        self.store.peek_back(key)
+    }
+
+    fn seek(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+        self.store.seek(key)
+    }
+
+    fn read_isolation(&self) -> ReadIsolation {
+        self.store.read_isolation()
+    }
+
+    fn health_hint(&self) -> Option<HealthHint> {
+        self.store.health_hint()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.store.is_read_only()
+    }
+}
+
+impl<'a, S: DocOps<'a>> DocOps<'a> for MemoStore<S>
+where
+    Error: From<S::Error>,
+{
+    fn config(&self) -> &StoreConfig {
+        self.store.config()
+    }
+
+    fn update_validator(&self) -> Option<&dyn UpdateValidator> {
+        self.store.update_validator()
+    }
+
+    fn tenant_extractor(&self) -> Option<&dyn TenantExtractor> {
+        self.store.tenant_extractor()
+    }
+
+    fn blob_store(&self) -> Option<&dyn BlobStore> {
+        self.store.blob_store()
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.store.clock()
+    }
+}
@@ -0,0 +1,124 @@
+//! Consolidating two stored documents into one.
+//!
+//! [DocOps::merge_docs](crate::DocOps::merge_docs) merges the contents of a source document into
+//! a destination document the same way Yrs merges concurrent edits: the part of source document
+//! state missing from the destination is encoded as an update, which is then pushed to the
+//! destination. Root types present in both documents (i.e. two `text` roots) are merged into one,
+//! interleaving their contents as concurrent inserts would, while roots present in only one of
+//! them are simply carried over.
+//!
+//! The returned update is all the connected replicas of the destination document need to converge
+//! with the merged one:
+//!
+//! ```rust,ignore
+//! let strategy = MergeStrategy {
+//!     meta: MetaMerge::Combine,
+//!     remove_src: true,
+//! };
+//! let update = db_txn.merge_docs("meeting-notes (1)", "meeting-notes", strategy)?;
+//! broadcast("meeting-notes", update);
+//! ```
+//!
+//! Metadata entries are merged according to [MetaMerge]. Reserved entries (with keys starting
+//! with `$`) describe the stored data of each document, so they are never copied.
+
+use crate::error::Error;
+use crate::{DocOps, KVStore};
+use std::collections::HashSet;
+use yrs::{Doc, ReadTxn, Transact};
+
+/// Describes how [DocOps::merge_docs](crate::DocOps::merge_docs) merges two documents, besides
+/// their contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MergeStrategy {
+    /// How metadata entries of both documents are merged.
+    pub meta: MetaMerge,
+    /// When set, the source document is removed once merged, using
+    /// [DocOps::clear_doc](crate::DocOps::clear_doc).
+    pub remove_src: bool,
+}
+
+/// Describes how metadata entries of a source document are merged into a destination document.
+/// Reserved entries (with keys starting with `$`) are never merged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetaMerge {
+    /// Destination document keeps its metadata, entries of the source document are ignored.
+    #[default]
+    KeepDst,
+    /// Metadata of the destination document is replaced with entries of the source document.
+    KeepSrc,
+    /// Destination document receives entries of the source document, which it doesn't have yet.
+    /// Entries present in both documents keep the values of the destination document.
+    Combine,
+}
+
+pub(crate) fn merge_docs<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    src: &[u8],
+    dst: &[u8],
+    strategy: MergeStrategy,
+) -> Result<Vec<u8>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let src_doc = Doc::new();
+    if !db.load_doc(src, &mut src_doc.transact_mut())? {
+        return Err(Error::DocNotFound);
+    }
+    if src == dst {
+        // nothing to merge: the update is empty
+        let txn = src_doc.transact();
+        return Ok(txn.encode_state_as_update_v1(&txn.state_vector()));
+    }
+    let dst_doc = Doc::new();
+    db.load_doc(dst, &mut dst_doc.transact_mut())?;
+    let update = src_doc
+        .transact()
+        .encode_state_as_update_v1(&dst_doc.transact().state_vector());
+    db.push_update(dst, &update)?;
+    db.flush_doc(dst)?;
+    merge_meta(db, src, dst, strategy.meta)?;
+    if strategy.remove_src {
+        db.clear_doc(src)?;
+    }
+    Ok(update)
+}
+
+fn merge_meta<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    src: &[u8],
+    dst: &[u8],
+    strategy: MetaMerge,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if strategy == MetaMerge::KeepDst {
+        return Ok(());
+    }
+    let dst_keys: HashSet<Box<[u8]>> = db
+        .iter_meta(dst)?
+        .map(|(key, _)| key)
+        .filter(|key| !is_reserved(key))
+        .collect();
+    let src_entries: Vec<_> = db
+        .iter_meta(src)?
+        .filter(|(key, _)| !is_reserved(key))
+        .collect();
+    if strategy == MetaMerge::KeepSrc {
+        for key in dst_keys.iter() {
+            db.remove_meta(dst, key)?;
+        }
+    }
+    for (key, value) in src_entries {
+        if strategy == MetaMerge::KeepSrc || !dst_keys.contains(&key) {
+            db.insert_meta(dst, &key, &value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks if a metadata `key` is reserved for yrs-kvstore internal use.
+fn is_reserved(key: &[u8]) -> bool {
+    key.first() == Some(&b'$')
+}
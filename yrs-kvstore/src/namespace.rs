@@ -0,0 +1,276 @@
+//! Independent logical stores sharing a single database.
+//!
+//! [NamespacedStore] wraps any [KVStore] and moves all of its entries into
+//! [KEYSPACE_NAMESPACE](crate::format::KEYSPACE_NAMESPACE), under a given namespace identifier.
+//! Every namespace has its own OID index, settings and documents, so [DocOps] methods called on
+//! one namespace - including [DocOps::iter_docs] - never observe entries of other namespaces or
+//! of the store that isn't namespaced at all. This can be used i.e. to keep a shadow copy of
+//! production documents next to them or to give every plugin its own sandbox:
+//!
+//! ```rust,ignore
+//! let staging = NamespacedStore::new(RocksDBStore::from(db.transaction()), 1);
+//! staging.push_update("my-doc-name", &update)?;
+//! staging.into_inner().commit()?;
+//! ```
+//!
+//! Since namespaces are part of the [format](crate::format), they can be enumerated with
+//! [DocOps::list_namespaces] and removed as a whole with [DocOps::drop_namespace] using a store
+//! that is not namespaced.
+
+use crate::blob::BlobStore;
+use crate::capabilities::Capabilities;
+use crate::clock::Clock;
+use crate::config::{StoreConfig, UpdateValidator};
+use crate::error::Error;
+use crate::format::{KEYSPACE_NAMESPACE, NAMESPACE_LEN, V1};
+use crate::keys::{key_namespace, key_namespace_end, split_namespace, Key};
+use crate::usage::TenantExtractor;
+use crate::{DocOps, HealthHint, KVEntry, KVStore, ReadIsolation, ScanMode};
+use std::ops::Deref;
+
+/// Length of the prefix of all keys of a namespace: [V1], [KEYSPACE_NAMESPACE] and namespace
+/// identifier.
+const PREFIX_LEN: usize = 2 + NAMESPACE_LEN;
+
+/// Wrapper around any [KVStore], which confines all of its entries to a single namespace. See
+/// [namespace](crate::namespace) module for details.
+pub struct NamespacedStore<S> {
+    store: S,
+    namespace: u16,
+    prefix: Key<PREFIX_LEN>,
+}
+
+impl<S> NamespacedStore<S> {
+    /// Wraps a given `store`, making it operate within a namespace with a given identifier.
+    pub fn new(store: S, namespace: u16) -> Self {
+        NamespacedStore {
+            store,
+            namespace,
+            prefix: key_namespace(namespace),
+        }
+    }
+
+    /// Returns identifier of the namespace current store operates in.
+    pub fn namespace(&self) -> u16 {
+        self.namespace
+    }
+
+    /// Unwraps the underlying store, i.e. in order to commit it.
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+
+    fn key(&self, key: &[u8]) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.prefix.len() + key.len());
+        result.extend_from_slice(&self.prefix);
+        result.extend_from_slice(key);
+        result
+    }
+}
+
+impl<S> Deref for NamespacedStore<S> {
+    type Target = S;
+
+    fn deref(&self) -> &Self::Target {
+        &self.store
+    }
+}
+
+impl<'a, S: KVStore<'a>> KVStore<'a> for NamespacedStore<S> {
+    type Error = S::Error;
+    type Cursor = NamespacedCursor<S::Cursor>;
+    type Entry = NamespacedEntry<S::Entry>;
+    type Return = S::Return;
+
+    const CAPABILITIES: Capabilities = S::CAPABILITIES;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+        self.store.get(&self.key(key))
+    }
+
+    fn get_for_update(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+        self.store.get_for_update(&self.key(key))
+    }
+
+    fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, Self::Error> {
+        let keys: Vec<Vec<u8>> = keys.iter().map(|key| self.key(key)).collect();
+        let keys: Vec<&[u8]> = keys.iter().map(Vec::as_slice).collect();
+        self.store.get_many(&keys)
+    }
+
+    fn upsert(&self, key: &[u8], value: &[u8]) -> Result<(), Self::Error> {
+        self.store.upsert(&self.key(key), value)
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+        self.store.remove(&self.key(key))
+    }
+
+    fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<u32, Self::Error> {
+        self.store.remove_range(&self.key(from), &self.key(to))
+    }
+
+    fn iter_range(&self, from: &[u8], to: &[u8]) -> Result<Self::Cursor, Self::Error> {
+        self.iter_range_with(from, to, ScanMode::Interactive)
+    }
+
+    fn iter_range_with(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        mode: ScanMode,
+    ) -> Result<Self::Cursor, Self::Error> {
+        let inner = self
+            .store
+            .iter_range_with(&self.key(from), &self.key(to), mode)?;
+        Ok(NamespacedCursor { inner })
+    }
+
+    fn iter_keys_range(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        f: &mut dyn FnMut(&[u8], usize) -> bool,
+    ) -> Result<(), Self::Error> {
+        self.store
+            .iter_keys_range(&self.key(from), &self.key(to), &mut |key, len| {
+                f(&key[PREFIX_LEN..], len)
+            })
+    }
+
+    fn iter_range_rev(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        f: &mut dyn FnMut(&[u8], &[u8]) -> bool,
+    ) -> Result<(), Self::Error> {
+        self.store
+            .iter_range_rev(&self.key(from), &self.key(to), &mut |key, value| {
+                f(&key[PREFIX_LEN..], value)
+            })
+    }
+
+    fn peek_back(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+        match self.store.peek_back(&self.key(key))? {
+            // last entry may belong to a preceding namespace or key space
+            Some(entry) if entry.key().starts_with(&self.prefix) => {
+                Ok(Some(NamespacedEntry(entry)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn seek(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+        match self.store.seek(&self.key(key))? {
+            // first entry may belong to a following namespace or key space
+            Some(entry) if entry.key().starts_with(&self.prefix) => {
+                Ok(Some(NamespacedEntry(entry)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn read_isolation(&self) -> ReadIsolation {
+        self.store.read_isolation()
+    }
+
+    fn health_hint(&self) -> Option<HealthHint> {
+        self.store.health_hint()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.store.is_read_only()
+    }
+}
+
+impl<'a, S: DocOps<'a>> DocOps<'a> for NamespacedStore<S>
+where
+    Error: From<S::Error>,
+{
+    fn config(&self) -> &StoreConfig {
+        self.store.config()
+    }
+
+    fn update_validator(&self) -> Option<&dyn UpdateValidator> {
+        self.store.update_validator()
+    }
+
+    fn tenant_extractor(&self) -> Option<&dyn TenantExtractor> {
+        self.store.tenant_extractor()
+    }
+
+    fn blob_store(&self) -> Option<&dyn BlobStore> {
+        self.store.blob_store()
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.store.clock()
+    }
+}
+
+/// Cursor returned by [NamespacedStore], which strips namespace prefix from keys of its entries.
+pub struct NamespacedCursor<C> {
+    inner: C,
+}
+
+impl<C: Iterator> Iterator for NamespacedCursor<C>
+where
+    C::Item: KVEntry,
+{
+    type Item = NamespacedEntry<C::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(NamespacedEntry)
+    }
+}
+
+/// Entry returned by [NamespacedStore], which key doesn't include namespace prefix.
+pub struct NamespacedEntry<E>(E);
+
+impl<E: KVEntry> KVEntry for NamespacedEntry<E> {
+    fn key(&self) -> &[u8] {
+        &self.0.key()[PREFIX_LEN..]
+    }
+
+    fn value(&self) -> &[u8] {
+        self.0.value()
+    }
+}
+
+/// Returns identifiers of all namespaces with any entries, in ascending order. Every namespace is
+/// found with a single range lookup.
+pub(crate) fn list<'a, DB: DocOps<'a> + ?Sized>(db: &DB) -> Result<Vec<u16>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let end = Key::from_const([V1, KEYSPACE_NAMESPACE + 1]);
+    let mut start = key_namespace(0);
+    let mut namespaces = Vec::new();
+    loop {
+        let first = match db.iter_range(&start, &end)?.next() {
+            Some(entry) => entry,
+            None => break,
+        };
+        match split_namespace(first.key()) {
+            Some((namespace, _)) if first.key() < end.as_ref() => {
+                namespaces.push(namespace);
+                match namespace.checked_add(1) {
+                    Some(next) => start = key_namespace(next),
+                    None => break,
+                }
+            }
+            _ => break,
+        }
+    }
+    Ok(namespaces)
+}
+
+/// Removes all entries of a given `namespace` with a single range delete. Returns the number of
+/// removed entries.
+pub(crate) fn delete_all<'a, DB: DocOps<'a> + ?Sized>(db: &DB, namespace: u16) -> Result<u32, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let removed = db.remove_range(&key_namespace(namespace), &key_namespace_end(namespace))?;
+    Ok(removed)
+}
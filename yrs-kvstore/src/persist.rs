@@ -0,0 +1,135 @@
+//! Persisting document updates as they happen, without panicking inside the update observer.
+//!
+//! [persist_on_update] subscribes to updates of a [Doc] and passes every one of them to
+//! a user-provided function, which is expected to open a new write transaction, call
+//! [DocOps::push_update](crate::DocOps::push_update) and commit. Store errors are not raised
+//! within Yrs update notifications. Instead, they are forwarded to an error sink together with
+//! the bytes of the update which could not be persisted, so that the application can retry,
+//! buffer or report them:
+//!
+//! ```rust,ignore
+//! let store = RocksDBDocStore::from(db);
+//! let (tx, rx) = std::sync::mpsc::sync_channel(16);
+//! let _sub = persist_on_update_with(
+//!     &doc,
+//!     move |name, update| store.push_update(name, update).map(|_| ()),
+//!     "my-doc-name",
+//!     move |failure| {
+//!         eprintln!("failed to persist update: {}", failure.error);
+//!         if !failure.queued {
+//!             let _ = tx.try_send(failure.update);
+//!         }
+//!     },
+//!     PersistConfig { retry_queue: 64 },
+//! )?;
+//! ```
+//!
+//! With [PersistConfig::retry_queue] set, updates which failed to persist are kept in memory
+//! and pushed again, in the order they were produced, before the next update of the document.
+//! Retry queue lives only as long as the returned subscription.
+
+use crate::error::Error;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use yrs::{Doc, Subscription};
+
+/// Configuration of [persist_on_update_with].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PersistConfig {
+    /// Maximum number of updates which failed to persist, that are kept in memory to be retried
+    /// on subsequent updates of the document. When `0` (default), failed updates are only
+    /// reported.
+    pub retry_queue: usize,
+}
+
+/// Update which could not be persisted, passed to the error sink of [persist_on_update].
+#[derive(Debug)]
+pub struct PersistFailure {
+    /// Error returned by the store. If earlier updates were waiting in the retry queue, this is
+    /// the error which stopped their retry, as updates are always pushed in order.
+    pub error: Error,
+    /// Update, encoded using lib0 v1 encoding, which triggered the failed push.
+    pub update: Vec<u8>,
+    /// Whether the update has been put into the retry queue. When `false`, the update has been
+    /// dropped and is owned solely by the error sink.
+    pub queued: bool,
+}
+
+/// Persists every update of a given `doc` under `doc_name` by calling `store_factory`, and
+/// forwards the ones which failed to `error_sink`. Failed updates are not retried. See
+/// [module documentation](self).
+///
+/// Fails with [Error::Other] if `doc` has a read-write transaction in progress, which prevents
+/// the observer from being registered.
+pub fn persist_on_update<K, F, S>(
+    doc: &Doc,
+    store_factory: F,
+    doc_name: &K,
+    error_sink: S,
+) -> Result<Subscription, Error>
+where
+    K: AsRef<[u8]> + ?Sized,
+    F: Fn(&[u8], &[u8]) -> Result<(), Error> + Send + Sync + 'static,
+    S: Fn(PersistFailure) + Send + Sync + 'static,
+{
+    persist_on_update_with(
+        doc,
+        store_factory,
+        doc_name,
+        error_sink,
+        PersistConfig::default(),
+    )
+}
+
+/// Same as [persist_on_update], but keeps up to [PersistConfig::retry_queue] failed updates
+/// in memory and pushes them again on subsequent updates of the document.
+pub fn persist_on_update_with<K, F, S>(
+    doc: &Doc,
+    store_factory: F,
+    doc_name: &K,
+    error_sink: S,
+    config: PersistConfig,
+) -> Result<Subscription, Error>
+where
+    K: AsRef<[u8]> + ?Sized,
+    F: Fn(&[u8], &[u8]) -> Result<(), Error> + Send + Sync + 'static,
+    S: Fn(PersistFailure) + Send + Sync + 'static,
+{
+    let name: Box<[u8]> = doc_name.as_ref().into();
+    let queue: Mutex<VecDeque<Vec<u8>>> = Mutex::new(VecDeque::new());
+    let sub = doc
+        .observe_update_v1(move |_, e| {
+            // a panic inside of `store_factory` must not make subsequent updates panic as well
+            let mut queue = queue.lock().unwrap_or_else(|e| e.into_inner());
+            queue.push_back(e.update.clone());
+            let mut failed = None;
+            while let Some(update) = queue.front() {
+                match store_factory(&name, update) {
+                    Ok(()) => {
+                        queue.pop_front();
+                    }
+                    Err(error) => {
+                        failed = Some(error);
+                        break;
+                    }
+                }
+            }
+            if let Some(error) = failed {
+                let queued = queue.len() <= config.retry_queue;
+                let update = if queued {
+                    e.update.clone()
+                } else {
+                    queue.pop_back().unwrap()
+                };
+                drop(queue);
+                error_sink(PersistFailure {
+                    error,
+                    update,
+                    queued,
+                });
+            }
+        })
+        // BorrowMutError doesn't implement std::error::Error, so it's wrapped by its message
+        .map_err(|e| Error::other(e.to_string()))?;
+    Ok(sub)
+}
@@ -0,0 +1,39 @@
+//! Items needed by most code working with documents through [DocOps], regardless of the backend
+//! storing them: store traits, the error type, store wrappers and key helpers.
+//!
+//! Every backend crate re-exports this module as its own `prelude`, so code written against it
+//! compiles unchanged after switching to another backend - only the construction of the store
+//! itself needs to change:
+//!
+//! ```rust,ignore
+//! use yrs_lmdb::prelude::*; // or yrs_rocksdb::prelude::*, yrs_file::prelude::*
+//!
+//! fn save<'a, S: DocOps<'a>>(db: &S, name: &str, update: &[u8]) -> Result<u32, Error>
+//! where
+//!     Error: From<<S as KVStore<'a>>::Error>,
+//! {
+//!     let receipt = db.push_update(name, update)?;
+//!     if receipt.pending_updates >= 64 {
+//!         db.flush_doc(name)?;
+//!     }
+//!     Ok(receipt.seq)
+//! }
+//! ```
+//!
+//! Backend specific types (i.e. `LmdbStore` or `RocksDBStore`) are not part of the prelude and
+//! have to be imported from their crates.
+
+#[cfg(feature = "cache")]
+pub use crate::cache::{CachedStore, DocCache};
+pub use crate::config::{ConfiguredStore, CreatePolicy, FlushPolicy, StoreConfig};
+pub use crate::dual::DualStore;
+pub use crate::error::Error;
+pub use crate::io_stats::IoStatsStore;
+pub use crate::keys::{build_key, parse_key, ParsedKey, OID};
+pub use crate::memo::MemoStore;
+pub use crate::namespace::NamespacedStore;
+pub use crate::shard::ShardedStore;
+pub use crate::{
+    ClearReport, DocOps, FlushOutcome, HealthHint, KVEntry, KVStore, LoadResult, PushReceipt,
+    ReadIsolation, WriteDurability,
+};
@@ -0,0 +1,237 @@
+//! References between documents, stored as document reference entries (see
+//! [DocOps::insert_meta_ref](crate::DocOps::insert_meta_ref)).
+//!
+//! A reference entry of a referring document stores the OID of the referenced document, so it
+//! doesn't depend on the name of the referenced document. Every reference is also recorded in
+//! the inbound references index ([KEYSPACE_REF](crate::keys::KEYSPACE_REF)) of the referenced
+//! document, together with the names of both documents. It's used to resolve references back to
+//! document names and to find documents referring to a document, which is about to be cleared
+//! (see [RefPolicy]).
+
+use crate::error::Error;
+use crate::format::OID_LEN;
+use crate::keys::{
+    key_ref, key_ref_end, key_ref_inbound, key_ref_inbound_end, key_ref_inbound_start,
+    key_ref_start, key_ref_target, OID,
+};
+use crate::{
+    clear_doc_recorded, decode_oid, get_oid, get_oid_entry, get_or_create_oid, resume_clear_doc,
+};
+use crate::{ClearReport, DocOps, KVEntry, KVStore};
+use std::convert::TryInto;
+
+/// Decides what happens to documents referring to a document being cleared with
+/// [DocOps::clear_doc_with](crate::DocOps::clear_doc_with).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefPolicy {
+    /// Referring documents are cleared as well, recursively.
+    Cascade,
+    /// References pointing at cleared document are removed from referring documents.
+    Nullify,
+    /// Document is not cleared and [Error::DocReferenced] is returned if any other document
+    /// refers to it. References of a document to itself don't prevent it from being cleared.
+    Refuse,
+}
+
+/// Reference of another document, returned by
+/// [DocOps::get_inbound_refs](crate::DocOps::get_inbound_refs).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InboundRef {
+    /// Name of the referring document.
+    pub source: Vec<u8>,
+    /// Key of the reference entry in the referring document.
+    pub key: Vec<u8>,
+}
+
+pub(crate) fn insert_ref<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    ref_key: &[u8],
+    target: &[u8],
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let target_oid = get_oid(db, target)?.ok_or(Error::DocNotFound)?;
+    let oid = get_or_create_oid(db, name)?;
+    let key = key_ref(oid, ref_key);
+    if let Some(prev) = db.get(&key)? {
+        let prev = decode_oid(prev.as_ref())?;
+        remove_inbound(db, prev, oid, ref_key)?;
+    }
+    db.upsert(&key, &target_oid.to_be_bytes())?;
+    db.upsert(&key_ref_target(target_oid), target)?;
+    db.upsert(&key_ref_inbound(target_oid, oid, ref_key), name)?;
+    Ok(())
+}
+
+pub(crate) fn get_ref<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    ref_key: &[u8],
+) -> Result<Option<DB::Return>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let oid = match get_oid(db, name)? {
+        Some(oid) => oid,
+        None => return Ok(None),
+    };
+    let target = match db.get(&key_ref(oid, ref_key))? {
+        Some(value) => decode_oid(value.as_ref())?,
+        None => return Ok(None),
+    };
+    Ok(db.get(&key_ref_target(target))?)
+}
+
+pub(crate) fn remove_ref<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    ref_key: &[u8],
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if let Some(oid) = get_oid(db, name)? {
+        let key = key_ref(oid, ref_key);
+        if let Some(target) = db.get(&key)? {
+            let target = decode_oid(target.as_ref())?;
+            db.remove(&key)?;
+            remove_inbound(db, target, oid, ref_key)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns references of other documents to a document with a given `oid`.
+pub(crate) fn inbound_refs<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+) -> Result<Vec<InboundRef>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let end = key_ref_inbound_end(oid);
+    let mut result = Vec::new();
+    for e in db.iter_range(&key_ref_inbound_start(oid), &end)? {
+        let key = e.key();
+        if key >= end.as_ref() {
+            break;
+        }
+        let (_, ref_key) = split_inbound_key(key);
+        result.push(InboundRef {
+            source: e.value().to_vec(),
+            key: ref_key.to_vec(),
+        });
+    }
+    Ok(result)
+}
+
+/// Clears a document with a given `name`, handling documents referring to it according to a given
+/// `policy`.
+pub(crate) fn clear_doc<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    policy: RefPolicy,
+) -> Result<ClearReport, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let oid = match get_oid_entry(db, name)? {
+        Some((oid, _)) => oid,
+        None => return resume_clear_doc(db, name),
+    };
+    let mut sources: Vec<Vec<u8>> = inbound_refs(db, oid)?
+        .into_iter()
+        .map(|r| r.source)
+        .filter(|source| source.as_slice() != name)
+        .collect();
+    sources.dedup();
+    match policy {
+        RefPolicy::Refuse if !sources.is_empty() => return Err(Error::DocReferenced),
+        RefPolicy::Cascade => {}
+        _ => sources.clear(),
+    }
+    let mut report = clear_doc_recorded(db, name, oid)?;
+    for source in sources {
+        report += clear_doc(db, &source, RefPolicy::Cascade)?;
+    }
+    Ok(report)
+}
+
+/// Removes all references of a document with a given `oid` to other documents and all references
+/// of other documents to it.
+pub(crate) fn unlink<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let outbound = {
+        let end = key_ref_end(oid);
+        let mut outbound = Vec::new();
+        for e in db.iter_range(&key_ref_start(oid), &end)? {
+            let key = e.key();
+            if key >= end.as_ref() {
+                break;
+            }
+            // reference entry key scheme: 01{oid:4}6{name:M}0
+            let ref_key = key[(3 + OID_LEN)..(key.len() - 1)].to_vec();
+            outbound.push((ref_key, decode_oid(e.value())?));
+        }
+        outbound
+    };
+    for (ref_key, target) in outbound {
+        db.remove(&key_ref(oid, &ref_key))?;
+        remove_inbound(db, target, oid, &ref_key)?;
+    }
+
+    let inbound = {
+        let end = key_ref_inbound_end(oid);
+        let mut inbound = Vec::new();
+        for e in db.iter_range(&key_ref_inbound_start(oid), &end)? {
+            let key = e.key();
+            if key >= end.as_ref() {
+                break;
+            }
+            let (source, ref_key) = split_inbound_key(key);
+            inbound.push((source, ref_key.to_vec()));
+        }
+        inbound
+    };
+    for (source, ref_key) in inbound {
+        db.remove(&key_ref(source, &ref_key))?;
+        db.remove(&key_ref_inbound(oid, source, &ref_key))?;
+    }
+    db.remove(&key_ref_target(oid))?;
+    Ok(())
+}
+
+/// Removes inbound reference entry of `target` document. Name of `target` document is removed
+/// from the index once it's no longer referenced.
+fn remove_inbound<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    target: OID,
+    source: OID,
+    ref_key: &[u8],
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    db.remove(&key_ref_inbound(target, source, ref_key))?;
+    let end = key_ref_inbound_end(target);
+    let referenced = match db.iter_range(&key_ref_inbound_start(target), &end)?.next() {
+        Some(e) => e.key() < end.as_ref(),
+        None => false,
+    };
+    if !referenced {
+        db.remove(&key_ref_target(target))?;
+    }
+    Ok(())
+}
+
+/// Splits inbound reference key into referring document OID and reference entry key.
+fn split_inbound_key(key: &[u8]) -> (OID, &[u8]) {
+    // inbound reference key scheme: 06{target:4}1{source:4}{name:M}0
+    let source = &key[(3 + OID_LEN)..(3 + 2 * OID_LEN)];
+    let source = OID::from_be_bytes(source.try_into().unwrap());
+    (source, &key[(3 + 2 * OID_LEN)..(key.len() - 1)])
+}
@@ -0,0 +1,122 @@
+//! Replacing document contents as a regular update. See
+//! [DocOps::replace_doc](crate::DocOps::replace_doc).
+
+use crate::error::Error;
+use yrs::encoding::write::Write;
+use yrs::updates::decoder::Decode;
+use yrs::{
+    Array, ArrayPrelim, ArrayRef, Doc, GetString, Map, MapPrelim, MapRef, Out, ReadTxn,
+    StateVector, Text, TextPrelim, TextRef, Transact, TransactionMut, Update,
+};
+
+/// Root type of the replaced document, which receives the copy of a root type of the new content.
+enum Root {
+    Text(TextRef, TextRef),
+    Array(ArrayRef, ArrayRef),
+    Map(MapRef, MapRef),
+}
+
+/// Deletes all contents of a given `doc` and copies root types of `content` into it within
+/// a single transaction. Returns a lib0 v1 encoded update, which transforms the previous state of
+/// the `doc` into the new one.
+pub(crate) fn replace_content<T: ReadTxn>(doc: &Doc, content: &T) -> Result<Vec<u8>, Error> {
+    // root types must be obtained before a write transaction is opened
+    let mut roots = Vec::new();
+    for (name, value) in content.root_refs() {
+        roots.push(match value {
+            Out::YText(text) => Root::Text(text, doc.get_or_insert_text(name)),
+            Out::YArray(array) => Root::Array(array, doc.get_or_insert_array(name)),
+            Out::YMap(map) => Root::Map(map, doc.get_or_insert_map(name)),
+            _ => return Err(Error::UnsupportedContent(name.to_string())),
+        });
+    }
+    let before = doc.transact().state_vector();
+    {
+        let mut txn = doc.transact_mut();
+        txn.apply_update(Update::decode_v1(&delete_all(&before))?);
+        for root in roots {
+            match root {
+                Root::Text(src, dst) => dst.push(&mut txn, &src.get_string(content)),
+                Root::Array(src, dst) => copy_array(content, &src, &mut txn, &dst)?,
+                Root::Map(src, dst) => copy_map(content, &src, &mut txn, &dst)?,
+            }
+        }
+    }
+    Ok(doc.transact().encode_diff_v1(&before))
+}
+
+/// Encodes an update, which deletes every block of a document with a given `state_vector`, no
+/// matter the type it belongs to.
+fn delete_all(state_vector: &StateVector) -> Vec<u8> {
+    let clients: Vec<_> = state_vector
+        .iter()
+        .filter(|(_, &clock)| clock != 0)
+        .collect();
+    let mut buf = Vec::new();
+    buf.write_var(0u32); // no blocks
+    buf.write_var(clients.len() as u32);
+    for (&client, &clock) in clients {
+        buf.write_var(client);
+        buf.write_var(1u32); // a single delete range of blocks from 0 up to the clock
+        buf.write_var(0u32);
+        buf.write_var(clock);
+    }
+    buf
+}
+
+fn copy_array<T: ReadTxn>(
+    src_txn: &T,
+    src: &ArrayRef,
+    txn: &mut TransactionMut,
+    dst: &ArrayRef,
+) -> Result<(), Error> {
+    for (i, value) in src.iter(src_txn).enumerate() {
+        let index = dst.len(txn);
+        match value {
+            Out::Any(any) => {
+                dst.insert(txn, index, any);
+            }
+            Out::YText(text) => {
+                dst.insert(txn, index, TextPrelim::new(text.get_string(src_txn)));
+            }
+            Out::YArray(array) => {
+                let copy = dst.insert(txn, index, ArrayPrelim::default());
+                copy_array(src_txn, &array, txn, &copy)?;
+            }
+            Out::YMap(map) => {
+                let copy = dst.insert(txn, index, MapPrelim::default());
+                copy_map(src_txn, &map, txn, &copy)?;
+            }
+            _ => return Err(Error::UnsupportedContent(i.to_string())),
+        }
+    }
+    Ok(())
+}
+
+fn copy_map<T: ReadTxn>(
+    src_txn: &T,
+    src: &MapRef,
+    txn: &mut TransactionMut,
+    dst: &MapRef,
+) -> Result<(), Error> {
+    for (key, value) in src.iter(src_txn) {
+        match value {
+            Out::Any(any) => {
+                dst.insert(txn, key, any);
+            }
+            Out::YText(text) => {
+                dst.insert(txn, key, TextPrelim::new(text.get_string(src_txn)));
+            }
+            Out::YArray(array) => {
+                let copy = dst.insert(txn, key, ArrayPrelim::default());
+                copy_array(src_txn, &array, txn, &copy)?;
+            }
+            Out::YMap(map) => {
+                let copy = dst.insert(txn, key, MapPrelim::default());
+                copy_map(src_txn, &map, txn, &copy)?;
+            }
+            _ => return Err(Error::UnsupportedContent(key.to_string())),
+        }
+    }
+    Ok(())
+}
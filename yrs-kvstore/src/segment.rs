@@ -0,0 +1,101 @@
+//! Reading and writing of update entries stored using either of
+//! [UpdateFraming](crate::config::UpdateFraming) layouts. See [format](crate::format) for the
+//! layout of update segments.
+
+use crate::error::Error;
+use crate::format::UPDATE_SEGMENT;
+use yrs::encoding::read::{Cursor, Read};
+use yrs::encoding::write::Write;
+
+/// Checks if an update entry with a given `key` stores a segment of update records.
+pub(crate) fn is_segment(key: &[u8]) -> bool {
+    key.last() == Some(&UPDATE_SEGMENT)
+}
+
+/// Reads the sequence number of the first update stored within an update entry from its key.
+pub(crate) fn base_seq(key: &[u8]) -> u32 {
+    crate::update_clock(key)
+}
+
+/// Appends a record with a given codec framed `update` to a `segment`.
+pub(crate) fn append(segment: &mut Vec<u8>, update: &[u8]) {
+    segment.write_buf(update);
+}
+
+/// Returns the size of a record storing an update of a given length.
+pub(crate) fn record_len(update_len: usize) -> usize {
+    let mut prefix = 1;
+    let mut len = update_len >> 7;
+    while len != 0 {
+        prefix += 1;
+        len >>= 7;
+    }
+    prefix + update_len
+}
+
+/// Returns an iterator over the updates stored within an update entry, together with their
+/// sequence numbers. Individual update entries yield their value as a single update.
+pub(crate) fn records<'v>(key: &[u8], value: &'v [u8]) -> Records<'v> {
+    Records {
+        seq: base_seq(key),
+        rest: value,
+        segment: is_segment(key),
+        done: false,
+    }
+}
+
+/// Iterator over codec framed updates stored within an update entry, returned by [records].
+pub(crate) struct Records<'v> {
+    seq: u32,
+    rest: &'v [u8],
+    segment: bool,
+    done: bool,
+}
+
+impl<'v> Iterator for Records<'v> {
+    type Item = Result<(u32, &'v [u8]), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if !self.segment {
+            self.done = true;
+            return Some(Ok((self.seq, self.rest)));
+        }
+        if self.rest.is_empty() {
+            self.done = true;
+            return None;
+        }
+        let mut cursor = Cursor::new(self.rest);
+        let len = match cursor.read_buf() {
+            Ok(update) => update.len(),
+            Err(_) => {
+                // records following a malformed one cannot be located
+                self.done = true;
+                return Some(Err(Error::CorruptedValue));
+            }
+        };
+        let end = cursor.next;
+        let update = &self.rest[(end - len)..end];
+        self.rest = &self.rest[end..];
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        Some(Ok((seq, update)))
+    }
+}
+
+/// Returns the sequence number of the last update stored within an update entry.
+pub(crate) fn last_seq(key: &[u8], value: &[u8]) -> Result<u32, Error> {
+    Ok(last_record(key, value)?.0)
+}
+
+/// Returns the last codec framed update stored within an update entry, together with its sequence
+/// number.
+pub(crate) fn last_record<'v>(key: &[u8], value: &'v [u8]) -> Result<(u32, &'v [u8]), Error> {
+    let mut last = None;
+    for record in records(key, value) {
+        last = Some(record?);
+    }
+    last.ok_or(Error::CorruptedValue)
+}
@@ -0,0 +1,484 @@
+//! Spreading documents across multiple stores, i.e. separate database instances, by their names.
+//!
+//! [ShardedStore] holds a number of shards, each identified by a number and represented by a
+//! function opening a store for it. Every document lives in exactly one shard, picked by hashing
+//! its name onto a consistent hash ring. Since documents are routed by name, OIDs and sequence
+//! numbers are allocated by every shard on its own. Stores are opened lazily, the first time a
+//! shard is needed by the current sharded store, and returned by [ShardedStore::into_shards] to
+//! be committed:
+//!
+//! ```rust,ignore
+//! let mut sharded = ShardedStore::new();
+//! for (id, db) in databases.iter().enumerate() {
+//!     sharded.add_shard(id as u32, move || RocksDBStore::from(db.transaction()));
+//! }
+//! sharded.push_update("my-doc-name", &update)?;
+//! for (_, store) in sharded.into_shards() {
+//!     store.commit()?;
+//! }
+//! ```
+//!
+//! Only the most common document operations are exposed by [ShardedStore] directly. Any other
+//! [DocOps] method can be called on the store of the document's shard, returned by
+//! [ShardedStore::route]. Operations spanning multiple documents, which may live in different
+//! shards, go through [archive](crate::archive) records instead of raw keys (see
+//! [ShardedStore::copy_doc]).
+//!
+//! # Adding shards
+//!
+//! Every shard is placed on the ring at [VIRTUAL_NODES] points derived from its identifier, so
+//! routing depends only on the set of shard identifiers - not on the order in which shards have
+//! been added. When a shard is added, only documents whose names fall onto its points are
+//! routed to it, which is roughly `1/N` of all documents for `N` shards. Remaining documents keep
+//! their shard.
+//!
+//! Documents routed to a new shard are not moved automatically: they stay in their previous shard
+//! and are returned by [ShardedStore::misplaced_docs] until [ShardedStore::rebalance] moves them.
+//! In the meantime reads routed to the new shard don't see them, while writes create a new
+//! document there. Rebalancing merges such documents with their previous contents, but stores
+//! should be rebalanced before serving writes whenever possible.
+
+use crate::archive::{export_docs_consistent, ArchiveReader, ConflictMode};
+use crate::config::crc32;
+use crate::error::Error;
+use crate::guard::ClearGuard;
+use crate::listing::{ListOptions, ListedDoc};
+use crate::{get_oid, ClearReport, DocOps, FlushOutcome, KVStore, PushReceipt};
+use std::cell::OnceCell;
+use std::time::Duration;
+use yrs::{Doc, ReadTxn, StateVector, Transact, TransactionMut};
+
+/// Number of points every shard occupies on the hash ring. More points spread documents more
+/// evenly between shards.
+pub const VIRTUAL_NODES: u32 = 64;
+
+/// Wrapper routing documents across multiple stores. See [shard](crate::shard) module for
+/// details.
+pub struct ShardedStore<'f, S> {
+    /// Shards ordered by their identifiers.
+    shards: Vec<Shard<'f, S>>,
+    /// Hash ring as a list of `(point, shard id)` pairs in ascending order.
+    ring: Vec<(u32, u32)>,
+}
+
+struct Shard<'f, S> {
+    id: u32,
+    open: Box<dyn Fn() -> S + 'f>,
+    store: OnceCell<S>,
+}
+
+/// Summary of a single shard returned by [ShardedStore::stats].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShardStats {
+    /// Identifier of the shard.
+    pub shard: u32,
+    /// Number of documents stored in the shard, excluding archived ones.
+    pub docs: u32,
+    /// Number of updates, which have not been merged into document states yet.
+    pub pending_updates: u64,
+    /// Total size (in bytes) of stored values of pending updates.
+    pub pending_bytes: u64,
+}
+
+/// Document stored in another shard than the one it's routed to, returned by
+/// [ShardedStore::misplaced_docs].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MisplacedDoc {
+    /// Name of the document.
+    pub name: Box<[u8]>,
+    /// Identifier of the shard storing the document.
+    pub shard: u32,
+    /// Identifier of the shard the document is routed to.
+    pub target: u32,
+}
+
+impl<'f, S> ShardedStore<'f, S> {
+    /// Creates a new sharded store without any shards. At least one shard must be added with
+    /// [Self::add_shard] before documents can be routed.
+    pub fn new() -> Self {
+        ShardedStore {
+            shards: Vec::new(),
+            ring: Vec::new(),
+        }
+    }
+
+    /// Adds a shard with a given `id`, which store is opened by calling `open`. Documents routed
+    /// to the new shard are not moved into it (see [module documentation](self)).
+    ///
+    /// # Panics
+    ///
+    /// Panics if a shard with the same `id` has been added already.
+    pub fn add_shard<F>(&mut self, id: u32, open: F)
+    where
+        F: Fn() -> S + 'f,
+    {
+        let i = match self.shards.binary_search_by_key(&id, |shard| shard.id) {
+            Ok(_) => panic!("shard {} has been added already", id),
+            Err(i) => i,
+        };
+        self.shards.insert(
+            i,
+            Shard {
+                id,
+                open: Box::new(open),
+                store: OnceCell::new(),
+            },
+        );
+        for replica in 0..VIRTUAL_NODES {
+            let mut point = [0u8; 8];
+            point[..4].copy_from_slice(&id.to_be_bytes());
+            point[4..].copy_from_slice(&replica.to_be_bytes());
+            self.ring.push((crc32(&point), id));
+        }
+        self.ring.sort_unstable();
+    }
+
+    /// Returns identifiers of all shards in ascending order.
+    pub fn shard_ids(&self) -> Vec<u32> {
+        self.shards.iter().map(|shard| shard.id).collect()
+    }
+
+    /// Returns identifier of the shard, which a document with a given `name` is routed to.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no shards have been added.
+    pub fn shard_of<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> u32 {
+        assert!(!self.ring.is_empty(), "sharded store has no shards");
+        let hash = crc32(name.as_ref());
+        let i = self.ring.partition_point(|&(point, _)| point < hash);
+        let (_, id) = self.ring[i % self.ring.len()];
+        id
+    }
+
+    /// Returns store of the shard, which a document with a given `name` is routed to, opening it
+    /// if necessary.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no shards have been added.
+    pub fn route<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> &S {
+        let id = self.shard_of(name);
+        self.shard(id).unwrap()
+    }
+
+    /// Returns store of the shard with a given `id`, opening it if necessary.
+    pub fn shard(&self, id: u32) -> Option<&S> {
+        let i = self
+            .shards
+            .binary_search_by_key(&id, |shard| shard.id)
+            .ok()?;
+        let shard = &self.shards[i];
+        Some(shard.store.get_or_init(|| (shard.open)()))
+    }
+
+    /// Unwraps stores of all shards opened by current sharded store, i.e. in order to commit
+    /// them. Stores are returned together with shard identifiers, in ascending order.
+    pub fn into_shards(self) -> Vec<(u32, S)> {
+        let mut stores = Vec::new();
+        for shard in self.shards {
+            if let Some(store) = shard.store.into_inner() {
+                stores.push((shard.id, store));
+            }
+        }
+        stores
+    }
+
+    fn all_shards(&self) -> impl Iterator<Item = (u32, &S)> {
+        self.shards
+            .iter()
+            .map(|shard| (shard.id, shard.store.get_or_init(|| (shard.open)())))
+    }
+}
+
+impl<'f, S> Default for ShardedStore<'f, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, 'f, S> ShardedStore<'f, S>
+where
+    S: DocOps<'a>,
+    Error: From<<S as KVStore<'a>>::Error>,
+{
+    /// Inserts document state into its shard. See [DocOps::insert_doc].
+    pub fn insert_doc<K: AsRef<[u8]> + ?Sized, T: ReadTxn>(
+        &self,
+        name: &K,
+        txn: &T,
+    ) -> Result<(), Error> {
+        self.route(name).insert_doc(name, txn)
+    }
+
+    /// Appends an update to a document in its shard. See [DocOps::push_update].
+    pub fn push_update<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        update: &[u8],
+    ) -> Result<PushReceipt, Error> {
+        self.route(name).push_update(name, update)
+    }
+
+    /// Merges pending updates of a document in its shard. See [DocOps::flush_doc].
+    pub fn flush_doc<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<FlushOutcome>, Error> {
+        self.route(name).flush_doc(name)
+    }
+
+    /// Removes document from its shard. See [DocOps::clear_doc].
+    pub fn clear_doc<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<ClearReport, Error> {
+        self.route(name).clear_doc(name)
+    }
+
+    /// Removes document meeting a given `guard` from its shard. See [DocOps::clear_doc_if].
+    pub fn clear_doc_if<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        guard: ClearGuard,
+    ) -> Result<ClearReport, Error> {
+        self.route(name).clear_doc_if(name, guard)
+    }
+
+    /// Creates a draft document in its shard. See [DocOps::create_draft].
+    pub fn create_draft<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<bool, Error> {
+        self.route(name).create_draft(name)
+    }
+
+    /// Promotes a draft document in its shard. See [DocOps::promote_draft].
+    pub fn promote_draft<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<bool, Error> {
+        self.route(name).promote_draft(name)
+    }
+
+    /// Loads document from its shard. See [DocOps::load_doc].
+    pub fn load_doc<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        txn: &mut TransactionMut,
+    ) -> Result<bool, Error> {
+        self.route(name).load_doc(name, txn)
+    }
+
+    /// Returns state vector of a document from its shard. See [DocOps::get_state_vector].
+    pub fn get_state_vector<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<(Option<StateVector>, bool), Error> {
+        self.route(name).get_state_vector(name)
+    }
+
+    /// Computes diff of a document from its shard. See [DocOps::get_diff].
+    pub fn get_diff<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        sv: &StateVector,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.route(name).get_diff(name, sv)
+    }
+
+    /// Returns metadata entry of a document from its shard. See [DocOps::get_meta].
+    pub fn get_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        meta_key: &K2,
+    ) -> Result<Option<<S as KVStore<'a>>::Return>, Error> {
+        self.route(name).get_meta(name, meta_key)
+    }
+
+    /// Inserts metadata entry of a document into its shard. See [DocOps::insert_meta].
+    pub fn insert_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        meta_key: &K2,
+        meta: &[u8],
+    ) -> Result<(), Error> {
+        self.route(name).insert_meta(name, meta_key, meta)
+    }
+
+    /// Removes metadata entry of a document from its shard. See [DocOps::remove_meta].
+    pub fn remove_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        meta_key: &K2,
+    ) -> Result<(), Error> {
+        self.route(name).remove_meta(name, meta_key)
+    }
+
+    /// Returns names of documents stored in all shards in ascending order, regardless of the shard
+    /// they're routed to. Archived documents are skipped. Names of documents stored in more than
+    /// one shard (see [Self::misplaced_docs]) are returned once.
+    ///
+    /// Every shard is opened and read, so this method is meant for maintenance jobs rather than
+    /// request paths.
+    pub fn iter_docs(&self) -> Result<std::vec::IntoIter<Box<[u8]>>, Error> {
+        let mut names = Vec::new();
+        for (_, store) in self.all_shards() {
+            names.extend(store.iter_docs()?);
+        }
+        names.sort_unstable();
+        names.dedup();
+        Ok(names.into_iter())
+    }
+
+    /// Returns documents selected by given `options` from all shards, in ascending order of their
+    /// names (see [DocOps::list_docs]). Documents stored in more than one shard (see
+    /// [Self::misplaced_docs]) are returned once, as stored by the shard they're routed to.
+    ///
+    /// Every shard is opened and read, so this method is meant for maintenance jobs rather than
+    /// request paths.
+    pub fn list_docs(&self, options: ListOptions) -> Result<std::vec::IntoIter<ListedDoc>, Error> {
+        let mut docs = Vec::new();
+        for (shard, store) in self.all_shards() {
+            for doc in store.list_docs(options.clone())? {
+                let doc = doc?;
+                let routed = self.shard_of(&doc.name) == shard;
+                docs.push((doc, !routed));
+            }
+        }
+        // routed copies of the same document go first
+        docs.sort_by(|(a, a_misplaced), (b, b_misplaced)| {
+            a.name.cmp(&b.name).then(a_misplaced.cmp(b_misplaced))
+        });
+        docs.dedup_by(|(a, _), (b, _)| a.name == b.name);
+        Ok(docs
+            .into_iter()
+            .map(|(doc, _)| doc)
+            .collect::<Vec<_>>()
+            .into_iter())
+    }
+
+    /// Removes drafts created at least `older_than` ago from all shards. Returns the number of
+    /// removed drafts. See [DocOps::purge_drafts].
+    ///
+    /// Every shard is opened and read, so this method is meant for maintenance jobs rather than
+    /// request paths.
+    pub fn purge_drafts(&self, older_than: Duration) -> Result<u32, Error> {
+        let mut purged = 0;
+        for (_, store) in self.all_shards() {
+            purged += store.purge_drafts(older_than)?;
+        }
+        Ok(purged)
+    }
+
+    /// Returns summary of every shard, ordered by shard identifiers.
+    ///
+    /// Every shard is opened and read, so this method is meant for maintenance jobs rather than
+    /// request paths.
+    pub fn stats(&self) -> Result<Vec<ShardStats>, Error> {
+        let mut stats = Vec::with_capacity(self.shards.len());
+        for (shard, store) in self.all_shards() {
+            let mut s = ShardStats {
+                shard,
+                docs: store.iter_docs()?.count() as u32,
+                ..ShardStats::default()
+            };
+            for candidate in store.flush_candidates()? {
+                s.pending_updates += candidate.pending_updates as u64;
+                s.pending_bytes += candidate.pending_bytes;
+            }
+            stats.push(s);
+        }
+        Ok(stats)
+    }
+
+    /// Copies a document with a given name `from` into a document named `to`, which may be
+    /// routed to a different shard, replacing its current contents. Document state, pending
+    /// updates, metadata and snapshots are copied as [archive](crate::archive) records, so both
+    /// shards may use different backends and value codecs. GUID of the source document is not
+    /// copied. Returns false if the source document doesn't exist.
+    pub fn copy_doc<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        from: &K1,
+        to: &K2,
+    ) -> Result<bool, Error> {
+        let source = self.route(from);
+        let target = self.route(to);
+        transfer(source, target, from.as_ref(), to.as_ref())
+    }
+
+    /// Returns documents stored in another shard than the one they're routed to, i.e. after a
+    /// shard has been added. See [module documentation](self).
+    ///
+    /// Every shard is opened and read, so this method is meant for maintenance jobs rather than
+    /// request paths.
+    pub fn misplaced_docs(&self) -> Result<Vec<MisplacedDoc>, Error> {
+        let mut misplaced = Vec::new();
+        for (shard, store) in self.all_shards() {
+            for name in store.iter_docs_with(true)? {
+                let target = self.shard_of(&name);
+                if target != shard {
+                    misplaced.push(MisplacedDoc {
+                        name,
+                        shard,
+                        target,
+                    });
+                }
+            }
+        }
+        Ok(misplaced)
+    }
+
+    /// Moves every document returned by [Self::misplaced_docs] into the shard it's routed to,
+    /// removing it from its previous shard. Returns the number of moved documents.
+    ///
+    /// If the target shard already has a document with the same name, i.e. because it has been
+    /// written after its shard was added, the previous contents are merged into it as an update,
+    /// while metadata entries missing in the target shard are copied over.
+    pub fn rebalance(&self) -> Result<u32, Error> {
+        let mut moved = 0;
+        for doc in self.misplaced_docs()? {
+            let source = self.shard(doc.shard).unwrap();
+            let target = self.shard(doc.target).unwrap();
+            if get_oid(target, &doc.name)?.is_some() {
+                merge(source, target, &doc.name)?;
+            } else {
+                transfer(source, target, &doc.name, &doc.name)?;
+            }
+            source.clear_doc(&doc.name)?;
+            moved += 1;
+        }
+        Ok(moved)
+    }
+}
+
+/// Copies a document named `from` stored in a `source` store into a document named `to` in
+/// a `target` store through an in-memory archive.
+fn transfer<'a, S>(source: &S, target: &S, from: &[u8], to: &[u8]) -> Result<bool, Error>
+where
+    S: DocOps<'a>,
+    Error: From<<S as KVStore<'a>>::Error>,
+{
+    let mut archive = Vec::new();
+    let progress = export_docs_consistent(&source.read_snapshot(), [from], &mut archive)?;
+    if progress.docs == 0 {
+        return Ok(false);
+    }
+    let mut reader = ArchiveReader::new(archive.as_slice())?;
+    reader.import_next_as(target, to, ConflictMode::Replace)?;
+    Ok(true)
+}
+
+/// Merges contents of a document with a given `name` stored in a `source` store into
+/// a document with the same name in a `target` store.
+fn merge<'a, S>(source: &S, target: &S, name: &[u8]) -> Result<(), Error>
+where
+    S: DocOps<'a>,
+    Error: From<<S as KVStore<'a>>::Error>,
+{
+    let doc = Doc::new();
+    if source.load_doc(name, &mut doc.transact_mut())? {
+        let update = doc
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        target.push_update(name, &update)?;
+    }
+    for (key, value) in source.iter_meta(name)? {
+        // reserved entries are maintained by the target store itself
+        if !key.starts_with(b"$") && target.get_meta(name, &key)?.is_none() {
+            target.insert_meta(name, &key, value.as_ref())?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,131 @@
+//! History of encoded sizes of a document, i.e. to show how close the document is to its size
+//! limit and how its size has changed over time.
+//!
+//! When [StoreConfig::size_history](crate::config::StoreConfig::size_history) is set, every flush
+//! of a document records a [SizeSample]: the time of the flush, the size of the document state it
+//! has written and the size of the pending updates it has merged. Samples are kept in
+//! [SIZE_HISTORY_LEN] slots under the OID of the document, used as a ring buffer: once all of
+//! them are taken, every new sample overwrites the oldest one, so the history never grows past
+//! a fixed number of entries.
+//!
+//! ```rust,ignore
+//! let config = StoreConfig {
+//!     size_history: true,
+//!     ..StoreConfig::DEFAULT
+//! };
+//! let db_txn = ConfiguredStore::new(RocksDBStore::from(db.transaction()), config);
+//! db_txn.flush_doc("my-doc-name")?;
+//!
+//! for sample in db_txn.size_history("my-doc-name")? {
+//!     println!("{:?}: {} bytes", sample.time, sample.state_bytes);
+//! }
+//! ```
+//!
+//! Size history belongs to a single document: it's removed together with the document and it's
+//! not carried over when contents of a document are copied or merged into another one. See
+//! [format](crate::format) for the layout of size history entries.
+
+use crate::clock;
+use crate::error::Error;
+use crate::format::{decode_timestamp, encode_timestamp, SIZE_HISTORY_LEN, SIZE_SAMPLE_LEN};
+use crate::keys::{key_size_history, key_size_history_head, OID};
+use crate::{get_oid, DocOps, KVStore};
+use std::convert::TryInto;
+use std::time::SystemTime;
+
+/// Encoded size of a document recorded by a flush, returned by
+/// [DocOps::size_history](crate::DocOps::size_history).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeSample {
+    /// Time of the flush, with millisecond precision.
+    pub time: SystemTime,
+    /// Size in bytes of the document state written by the flush, as stored (after being encoded
+    /// with [StoreConfig::codec](crate::config::StoreConfig::codec)).
+    pub state_bytes: u64,
+    /// Total size in bytes of the pending updates stored right before the flush.
+    pub pending_bytes: u64,
+}
+
+/// Records a sample of a document with a given `oid` in the next slot of its size history.
+pub(crate) fn append<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    state_bytes: u64,
+    pending_bytes: u64,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let head_key = key_size_history_head(oid);
+    let written = match db.get(&head_key)? {
+        Some(value) => decode_head(value.as_ref())?,
+        None => 0,
+    };
+    let slot = (written % SIZE_HISTORY_LEN as u32) as u8;
+    let mut value = Vec::with_capacity(SIZE_SAMPLE_LEN);
+    value.extend_from_slice(&encode_timestamp(db.clock().now()));
+    value.extend_from_slice(&state_bytes.to_be_bytes());
+    value.extend_from_slice(&pending_bytes.to_be_bytes());
+    db.upsert(&key_size_history(oid, slot), &value)?;
+    // once the history is full, the counter only has to tell the slot of the oldest sample, so
+    // it's kept below twice the number of slots instead of growing without bounds
+    let mut written = written + 1;
+    if written >= 2 * SIZE_HISTORY_LEN as u32 {
+        written -= SIZE_HISTORY_LEN as u32;
+    }
+    db.upsert(&head_key, &written.to_be_bytes())?;
+    Ok(())
+}
+
+/// Returns samples of the size history of a document with a given `name`, starting with the oldest
+/// one.
+pub(crate) fn samples<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+) -> Result<Vec<SizeSample>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let oid = match get_oid(db, name)? {
+        Some(oid) => oid,
+        None => return Ok(Vec::new()),
+    };
+    let written = match db.get(&key_size_history_head(oid))? {
+        Some(value) => decode_head(value.as_ref())?,
+        None => return Ok(Vec::new()),
+    };
+    let len = SIZE_HISTORY_LEN as u32;
+    // until the ring buffer wraps around, the oldest sample is stored in the first slot,
+    // afterwards - in the slot to be overwritten next
+    let (first, count) = if written <= len {
+        (0, written)
+    } else {
+        (written % len, len)
+    };
+    let mut samples = Vec::with_capacity(count as usize);
+    for i in 0..count {
+        let slot = ((first + i) % len) as u8;
+        match db.get(&key_size_history(oid, slot))? {
+            Some(value) => samples.push(decode_sample(value.as_ref())?),
+            None => return Err(Error::CorruptedValue),
+        }
+    }
+    Ok(samples)
+}
+
+fn decode_head(value: &[u8]) -> Result<u32, Error> {
+    let value: [u8; 4] = value.try_into().map_err(|_| Error::CorruptedValue)?;
+    Ok(u32::from_be_bytes(value))
+}
+
+fn decode_sample(value: &[u8]) -> Result<SizeSample, Error> {
+    if value.len() != SIZE_SAMPLE_LEN {
+        return Err(Error::CorruptedValue);
+    }
+    let millis = decode_timestamp(&value[..8]).ok_or(Error::CorruptedValue)?;
+    Ok(SizeSample {
+        time: clock::system_time(millis),
+        state_bytes: u64::from_be_bytes(value[8..16].try_into().unwrap()),
+        pending_bytes: u64::from_be_bytes(value[16..].try_into().unwrap()),
+    })
+}
@@ -0,0 +1,702 @@
+//! Storing root types of a document as separate parts, which can be loaded independently.
+//!
+//! Some documents combine a large root type, which is rarely needed (e.g. an edit history), with
+//! small ones that are read all the time. [SplitPolicy] maps root type names onto named parts of
+//! a document. Every part is stored as a separate document named `{name}#{part}` (see
+//! [SplitPolicy::doc_name]). [DocOps::push_split_update](crate::DocOps::push_split_update) decodes
+//! incoming updates and routes each inserted block into the part of the root type it belongs to,
+//! while [DocOps::load_split_doc](crate::DocOps::load_split_doc) reassembles a single document
+//! from the parts holding requested root types only.
+//!
+//! Every part receives the complete clock range of each update: blocks routed into other parts
+//! are replaced with garbage collected placeholders, while delete sets are copied as they are.
+//! This way all parts remain valid Yrs documents with the same state vector, and a document
+//! loaded from all of its parts is equivalent to the one built from the original updates.
+//!
+//! Blocks inserted next to existing content or into nested types don't carry the name of their
+//! root type. They are routed into the part holding their neighbour or parent block, using ranges
+//! of block IDs recorded by every part in [META_SPLIT_IDS] metadata entry. Because of that,
+//! updates must be pushed in causal order: an update depending on content which has not been
+//! pushed yet is rejected with [Error::UpdateRejected].
+//!
+//! Documents loaded from a subset of parts should be treated as read-only views, since content
+//! of the remaining parts is replaced with garbage collected placeholders. Weak links are not
+//! supported.
+//!
+//! ```rust,ignore
+//! let policy = SplitPolicy::new("content").route("history", "history");
+//! // stored as `my-doc#content` and `my-doc#history`
+//! db_txn.push_split_update("my-doc", &update, &policy)?;
+//!
+//! let doc = Doc::new();
+//! db_txn.load_split_doc("my-doc", &policy, &["content"], &mut doc.transact_mut())?;
+//! ```
+
+use crate::config::RejectReason;
+use crate::error::Error;
+use crate::keys::META_SPLIT_IDS;
+use crate::{DocOps, KVStore};
+use std::collections::{BTreeMap, HashMap};
+use yrs::encoding::read;
+use yrs::updates::decoder::Decode;
+use yrs::{StateVector, TransactionMut, Update};
+
+/// Byte separating document name from the name of its part.
+pub const PART_SEPARATOR: u8 = b'#';
+
+/// Mapping of root type names onto parts of a document, each stored as a separate document. Root
+/// types which are not routed explicitly are stored in the default part. See
+/// [module documentation](self) for details.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitPolicy {
+    /// Names of all parts, starting with the default one.
+    parts: Vec<String>,
+    /// Indexes of parts of explicitly routed root types.
+    routes: HashMap<String, usize>,
+}
+
+impl SplitPolicy {
+    /// Creates a new policy, which stores all root types in a part with a given name.
+    pub fn new<S: Into<String>>(default_part: S) -> Self {
+        SplitPolicy {
+            parts: vec![default_part.into()],
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Routes root type with a given name into a given `part`.
+    pub fn route<R: Into<String>, P: Into<String>>(mut self, root: R, part: P) -> Self {
+        let part = part.into();
+        let index = match self.parts.iter().position(|p| *p == part) {
+            Some(index) => index,
+            None => {
+                self.parts.push(part);
+                self.parts.len() - 1
+            }
+        };
+        self.routes.insert(root.into(), index);
+        self
+    }
+
+    /// Returns names of all parts, starting with the default one.
+    pub fn parts(&self) -> &[String] {
+        &self.parts
+    }
+
+    /// Returns the name of a part storing root type with a given name.
+    pub fn part_of(&self, root: &str) -> &str {
+        &self.parts[self.part_index(root)]
+    }
+
+    /// Returns the name of a document storing a given `part` of the document with a given `name`.
+    pub fn doc_name(name: &[u8], part: &str) -> Vec<u8> {
+        let mut doc_name = Vec::with_capacity(name.len() + part.len() + 1);
+        doc_name.extend_from_slice(name);
+        doc_name.push(PART_SEPARATOR);
+        doc_name.extend_from_slice(part.as_bytes());
+        doc_name
+    }
+
+    fn part_index(&self, root: &str) -> usize {
+        self.routes.get(root).copied().unwrap_or(0)
+    }
+}
+
+pub(crate) fn push_split_update<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    update: &[u8],
+    policy: &SplitPolicy,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let parsed = ParsedUpdate::decode(update)?;
+    let names: Vec<Vec<u8>> = policy
+        .parts
+        .iter()
+        .map(|part| SplitPolicy::doc_name(name, part))
+        .collect();
+    let mut index = Vec::with_capacity(names.len());
+    for name in names.iter() {
+        index.push(match db.get_meta(name, META_SPLIT_IDS)? {
+            Some(value) => IdRanges::decode(value.as_ref())?,
+            None => IdRanges::default(),
+        });
+    }
+    let mut changed = vec![false; names.len()];
+
+    // items referencing other items of the same update may come before them, so keep routing
+    // until no more items can be resolved
+    let mut routes: Vec<Vec<Option<usize>>> = parsed
+        .clients
+        .iter()
+        .map(|(_, blocks)| vec![None; blocks.len()])
+        .collect();
+    let mut unresolved = parsed
+        .clients
+        .iter()
+        .flat_map(|(_, blocks)| blocks.iter())
+        .filter(|block| matches!(block.kind, Kind::Item { .. }))
+        .count();
+    while unresolved > 0 {
+        let mut progress = false;
+        for (i, (client, blocks)) in parsed.clients.iter().enumerate() {
+            for (j, block) in blocks.iter().enumerate() {
+                let parent = match &block.kind {
+                    Kind::Item { parent, .. } if routes[i][j].is_none() => parent,
+                    _ => continue,
+                };
+                let part = match *parent {
+                    Parent::Root(root) => Some(policy.part_index(root)),
+                    Parent::Type(id) => find_part(&index, id),
+                    Parent::Neighbour(origin, right_origin) => origin
+                        .and_then(|id| find_part(&index, id))
+                        .or_else(|| right_origin.and_then(|id| find_part(&index, id))),
+                };
+                if let Some(part) = part {
+                    routes[i][j] = Some(part);
+                    index[part].insert(*client, block.clock, block.clock + block.len);
+                    changed[part] = true;
+                    unresolved -= 1;
+                    progress = true;
+                }
+            }
+        }
+        if !progress {
+            return Err(Error::UpdateRejected(RejectReason::new(
+                "update depends on content missing from split document",
+            )));
+        }
+    }
+
+    for (part, name) in names.iter().enumerate() {
+        let clients = parsed
+            .clients
+            .iter()
+            .zip(routes.iter())
+            .map(|((client, blocks), routes)| {
+                let mut out = Vec::with_capacity(blocks.len());
+                for (block, route) in blocks.iter().zip(routes.iter()) {
+                    match block.kind {
+                        Kind::Item { bytes, .. } if *route == Some(part) => {
+                            out.push(Out::Raw(bytes))
+                        }
+                        Kind::Skip => out.push(Out::Skip(block.len)),
+                        _ => push_gc(&mut out, block.len),
+                    }
+                }
+                (*client, blocks.first().map_or(0, |b| b.clock), out)
+            })
+            .collect();
+        db.push_update(name, &encode_update(clients, parsed.delete_set))?;
+        if changed[part] {
+            let mut value = Vec::new();
+            index[part].encode(&mut value);
+            db.insert_meta(name, META_SPLIT_IDS, &value)?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn load_split_doc<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    name: &[u8],
+    policy: &SplitPolicy,
+    roots: &[&str],
+    txn: &mut TransactionMut,
+) -> Result<bool, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let mut parts: Vec<usize> = roots.iter().map(|root| policy.part_index(root)).collect();
+    parts.sort_unstable();
+    parts.dedup();
+    let mut states = Vec::with_capacity(parts.len());
+    for part in parts {
+        let name = SplitPolicy::doc_name(name, &policy.parts[part]);
+        if let Some(state) = db.get_diff(&name, &StateVector::default())? {
+            states.push(state);
+        }
+    }
+    if states.is_empty() {
+        return Ok(false);
+    }
+    let update = merge_parts(&states)?;
+    txn.apply_update(Update::decode_v1(&update)?);
+    Ok(true)
+}
+
+/// Combines full states of several parts of the same document into a single update, taking
+/// items from the parts holding them and filling the remaining clock ranges with placeholders.
+fn merge_parts(states: &[Vec<u8>]) -> Result<Vec<u8>, Error> {
+    let mut clients: BTreeMap<u64, ClientItems> = BTreeMap::new();
+    let mut deleted = IdRanges::default();
+    for state in states.iter() {
+        let parsed = ParsedUpdate::decode(state)?;
+        let mut held = IdRanges::default();
+        for (client, blocks) in parsed.clients {
+            for block in blocks {
+                let entry = clients.entry(client).or_insert(ClientItems {
+                    start: block.clock,
+                    end: block.clock,
+                    items: BTreeMap::new(),
+                });
+                entry.start = entry.start.min(block.clock);
+                entry.end = entry.end.max(block.clock + block.len);
+                if let Kind::Item { bytes, .. } = block.kind {
+                    entry.items.insert(block.clock, (block.len, bytes));
+                    held.insert(client, block.clock, block.clock + block.len);
+                }
+            }
+        }
+        // placeholders of other parts are reported as deleted once a part is flushed, so only
+        // deletions of items held by the part itself are taken from it
+        for (client, ranges) in IdRanges::decode(parsed.delete_set)?.0 {
+            for (start, end) in ranges {
+                deleted.insert_within(client, start, end, &held);
+            }
+        }
+    }
+    let clients = clients
+        .into_iter()
+        .map(|(client, entry)| {
+            let mut out = Vec::with_capacity(entry.items.len() + 1);
+            let mut clock = entry.start;
+            for (item_clock, (len, bytes)) in entry.items {
+                if item_clock < clock {
+                    continue; // already covered by another part
+                }
+                if item_clock > clock {
+                    push_gc(&mut out, item_clock - clock);
+                }
+                out.push(Out::Raw(bytes));
+                clock = item_clock + len;
+            }
+            if entry.end > clock {
+                push_gc(&mut out, entry.end - clock);
+            }
+            (client, entry.start, out)
+        })
+        .collect();
+    let mut delete_set = Vec::new();
+    deleted.encode(&mut delete_set);
+    Ok(encode_update(clients, &delete_set))
+}
+
+/// Clock range of a single client covered by merged parts, together with items found in them
+/// (length and encoding) by their clocks.
+struct ClientItems<'a> {
+    start: u32,
+    end: u32,
+    items: BTreeMap<u32, (u32, &'a [u8])>,
+}
+
+fn find_part(index: &[IdRanges], id: Id) -> Option<usize> {
+    index.iter().position(|ranges| ranges.contains(id))
+}
+
+/// Block ID: client and clock.
+type Id = (u64, u32);
+
+/// Block info byte of garbage collected content.
+const BLOCK_GC: u8 = 0;
+/// Block info byte of skipped (missing) content.
+const BLOCK_SKIP: u8 = 10;
+
+const HAS_ORIGIN: u8 = 0b1000_0000;
+const HAS_RIGHT_ORIGIN: u8 = 0b0100_0000;
+const HAS_PARENT_SUB: u8 = 0b0010_0000;
+const CONTENT_MASK: u8 = 0b0001_1111;
+
+const CONTENT_DELETED: u8 = 1;
+const CONTENT_JSON: u8 = 2;
+const CONTENT_BINARY: u8 = 3;
+const CONTENT_STRING: u8 = 4;
+const CONTENT_EMBED: u8 = 5;
+const CONTENT_FORMAT: u8 = 6;
+const CONTENT_TYPE: u8 = 7;
+const CONTENT_ANY: u8 = 8;
+const CONTENT_DOC: u8 = 9;
+const CONTENT_MOVE: u8 = 11;
+
+const TYPE_REF_XML_ELEMENT: u64 = 3;
+const TYPE_REF_XML_HOOK: u64 = 5;
+const TYPE_REF_WEAK: u64 = 7;
+
+/// lib0 v1 encoded update, decoded just enough to tell blocks and their parents apart.
+struct ParsedUpdate<'a> {
+    clients: Vec<(u64, Vec<Block<'a>>)>,
+    /// Encoded delete set following the blocks.
+    delete_set: &'a [u8],
+}
+
+struct Block<'a> {
+    clock: u32,
+    len: u32,
+    kind: Kind<'a>,
+}
+
+enum Kind<'a> {
+    /// Inserted item together with its complete encoding.
+    Item {
+        parent: Parent<'a>,
+        bytes: &'a [u8],
+    },
+    Gc,
+    Skip,
+}
+
+/// The way an item refers to its parent.
+#[derive(Clone, Copy)]
+enum Parent<'a> {
+    /// Item inserted directly into a root type with a given name.
+    Root(&'a str),
+    /// Item inserted into a nested type created by an item with a given ID.
+    Type(Id),
+    /// Item sharing the parent with its origin and right origin.
+    Neighbour(Option<Id>, Option<Id>),
+}
+
+impl<'a> ParsedUpdate<'a> {
+    fn decode(update: &'a [u8]) -> Result<Self, read::Error> {
+        let mut r = Reader {
+            buf: update,
+            pos: 0,
+        };
+        let client_count = r.read_var()?;
+        let mut clients = Vec::new();
+        for _ in 0..client_count {
+            let block_count = r.read_var()?;
+            let client = r.read_var()?;
+            let mut clock = r.read_var()? as u32;
+            let mut blocks = Vec::new();
+            for _ in 0..block_count {
+                let start = r.pos;
+                let info = r.read_u8()?;
+                let (len, kind) = match info & CONTENT_MASK {
+                    BLOCK_GC => (r.read_var()? as u32, Kind::Gc),
+                    BLOCK_SKIP => (r.read_var()? as u32, Kind::Skip),
+                    _ => {
+                        let (len, parent) = r.read_item(info)?;
+                        let bytes = &r.buf[start..r.pos];
+                        (len, Kind::Item { parent, bytes })
+                    }
+                };
+                blocks.push(Block { clock, len, kind });
+                clock += len;
+            }
+            clients.push((client, blocks));
+        }
+        Ok(ParsedUpdate {
+            clients,
+            delete_set: &r.buf[r.pos..],
+        })
+    }
+}
+
+enum Out<'a> {
+    Raw(&'a [u8]),
+    Gc(u32),
+    Skip(u32),
+}
+
+/// Appends garbage collected placeholder of a given length, merging it with the preceding one.
+fn push_gc(out: &mut Vec<Out>, len: u32) {
+    match out.last_mut() {
+        Some(Out::Gc(prev)) => *prev += len,
+        _ => out.push(Out::Gc(len)),
+    }
+}
+
+/// Encodes blocks of given clients, each starting at a given clock, followed by an already
+/// encoded `delete_set` into lib0 v1 update.
+fn encode_update(clients: Vec<(u64, u32, Vec<Out>)>, delete_set: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_var(&mut buf, clients.len() as u64);
+    for (client, clock, blocks) in clients {
+        write_var(&mut buf, blocks.len() as u64);
+        write_var(&mut buf, client);
+        write_var(&mut buf, clock as u64);
+        for block in blocks {
+            match block {
+                Out::Raw(bytes) => buf.extend_from_slice(bytes),
+                Out::Gc(len) => {
+                    buf.push(BLOCK_GC);
+                    write_var(&mut buf, len as u64);
+                }
+                Out::Skip(len) => {
+                    buf.push(BLOCK_SKIP);
+                    write_var(&mut buf, len as u64);
+                }
+            }
+        }
+    }
+    buf.extend_from_slice(delete_set);
+    buf
+}
+
+fn write_var(buf: &mut Vec<u8>, mut num: u64) {
+    while num >= 0b1000_0000 {
+        buf.push(num as u8 | 0b1000_0000);
+        num >>= 7;
+    }
+    buf.push(num as u8);
+}
+
+/// Sorted, non-overlapping ranges of block clocks grouped by client, encoded the same way as
+/// lib0 v1 delete sets.
+#[derive(Debug, Default)]
+struct IdRanges(BTreeMap<u64, Vec<(u32, u32)>>);
+
+impl IdRanges {
+    fn insert(&mut self, client: u64, start: u32, end: u32) {
+        let ranges = self.0.entry(client).or_default();
+        let i = ranges.partition_point(|r| r.1 < start);
+        let (mut start, mut end, mut j) = (start, end, i);
+        while j < ranges.len() && ranges[j].0 <= end {
+            start = start.min(ranges[j].0);
+            end = end.max(ranges[j].1);
+            j += 1;
+        }
+        ranges.splice(i..j, std::iter::once((start, end)));
+    }
+
+    /// Inserts parts of a given clock range, which are covered by ranges of a given `mask`.
+    fn insert_within(&mut self, client: u64, start: u32, end: u32, mask: &IdRanges) {
+        if let Some(ranges) = mask.0.get(&client) {
+            for &(s, e) in ranges.iter() {
+                let (s, e) = (s.max(start), e.min(end));
+                if s < e {
+                    self.insert(client, s, e);
+                }
+            }
+        }
+    }
+
+    fn contains(&self, (client, clock): Id) -> bool {
+        match self.0.get(&client) {
+            Some(ranges) => {
+                let i = ranges.partition_point(|r| r.1 <= clock);
+                i < ranges.len() && ranges[i].0 <= clock
+            }
+            None => false,
+        }
+    }
+
+    fn decode(value: &[u8]) -> Result<Self, read::Error> {
+        let mut r = Reader { buf: value, pos: 0 };
+        let mut ranges = IdRanges::default();
+        let client_count = r.read_var()?;
+        for _ in 0..client_count {
+            let client = r.read_var()?;
+            let range_count = r.read_var()?;
+            for _ in 0..range_count {
+                let clock = r.read_var()? as u32;
+                let len = r.read_var()? as u32;
+                ranges.insert(client, clock, clock + len);
+            }
+        }
+        Ok(ranges)
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write_var(buf, self.0.len() as u64);
+        for (client, ranges) in self.0.iter() {
+            write_var(buf, *client);
+            write_var(buf, ranges.len() as u64);
+            for (start, end) in ranges.iter() {
+                write_var(buf, *start as u64);
+                write_var(buf, (end - start) as u64);
+            }
+        }
+    }
+}
+
+/// Reader of lib0 encoded values, which keeps track of their positions in the source buffer.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read_u8(&mut self) -> Result<u8, read::Error> {
+        let byte = *self.buf.get(self.pos).ok_or(read::Error::EndOfBuffer(1))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    /// Reads unsigned variable length integer. Signed ones can be skipped this way as well.
+    fn read_var(&mut self) -> Result<u64, read::Error> {
+        let mut num = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            if shift < 64 {
+                num |= ((byte & 0b0111_1111) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0b1000_0000 == 0 {
+                return Ok(num);
+            }
+        }
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], read::Error> {
+        match self.pos.checked_add(len) {
+            Some(end) if end <= self.buf.len() => {
+                let bytes = &self.buf[self.pos..end];
+                self.pos = end;
+                Ok(bytes)
+            }
+            _ => Err(read::Error::EndOfBuffer(len)),
+        }
+    }
+
+    fn read_buf(&mut self) -> Result<&'a [u8], read::Error> {
+        let len = self.read_var()? as usize;
+        self.read_bytes(len)
+    }
+
+    fn read_string(&mut self) -> Result<&'a str, read::Error> {
+        std::str::from_utf8(self.read_buf()?)
+            .map_err(|_| read::Error::Custom("invalid UTF-8 string".to_string()))
+    }
+
+    fn read_id(&mut self) -> Result<Id, read::Error> {
+        let client = self.read_var()?;
+        let clock = self.read_var()? as u32;
+        Ok((client, clock))
+    }
+
+    /// Skips a value encoded using lib0 `Any` encoding.
+    fn skip_any(&mut self) -> Result<(), read::Error> {
+        match self.read_u8()? {
+            // undefined, null, false, true
+            127 | 126 | 121 | 120 => {}
+            // integer
+            125 => {
+                self.read_var()?;
+            }
+            // float32
+            124 => {
+                self.read_bytes(4)?;
+            }
+            // float64, bigint
+            123 | 122 => {
+                self.read_bytes(8)?;
+            }
+            // string, buffer
+            119 | 116 => {
+                self.read_buf()?;
+            }
+            // object
+            118 => {
+                for _ in 0..self.read_var()? {
+                    self.read_buf()?;
+                    self.skip_any()?;
+                }
+            }
+            // array
+            117 => {
+                for _ in 0..self.read_var()? {
+                    self.skip_any()?;
+                }
+            }
+            tag => {
+                return Err(read::Error::Custom(format!("unknown Any tag: {}", tag)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the rest of an item following its `info` byte. Returns item length together with
+    /// the way it refers to its parent.
+    fn read_item(&mut self, info: u8) -> Result<(u32, Parent<'a>), read::Error> {
+        let origin = if info & HAS_ORIGIN != 0 {
+            Some(self.read_id()?)
+        } else {
+            None
+        };
+        let right_origin = if info & HAS_RIGHT_ORIGIN != 0 {
+            Some(self.read_id()?)
+        } else {
+            None
+        };
+        let parent = if origin.is_none() && right_origin.is_none() {
+            let parent = if self.read_var()? == 1 {
+                Parent::Root(self.read_string()?)
+            } else {
+                Parent::Type(self.read_id()?)
+            };
+            if info & HAS_PARENT_SUB != 0 {
+                self.read_buf()?;
+            }
+            parent
+        } else {
+            Parent::Neighbour(origin, right_origin)
+        };
+        let len = match info & CONTENT_MASK {
+            CONTENT_DELETED => self.read_var()? as u32,
+            CONTENT_JSON => {
+                let count = self.read_var()?;
+                for _ in 0..count {
+                    self.read_buf()?;
+                }
+                count as u32
+            }
+            CONTENT_BINARY | CONTENT_EMBED => {
+                self.read_buf()?;
+                1
+            }
+            // item length of strings is measured in UTF-16 code units
+            CONTENT_STRING => self.read_string()?.encode_utf16().count() as u32,
+            CONTENT_FORMAT => {
+                self.read_buf()?;
+                self.read_buf()?;
+                1
+            }
+            CONTENT_TYPE => {
+                match self.read_var()? {
+                    TYPE_REF_XML_ELEMENT | TYPE_REF_XML_HOOK => {
+                        self.read_buf()?;
+                    }
+                    TYPE_REF_WEAK => {
+                        return Err(read::Error::Custom(
+                            "weak links are not supported".to_string(),
+                        ))
+                    }
+                    _ => {}
+                }
+                1
+            }
+            CONTENT_ANY => {
+                let count = self.read_var()?;
+                for _ in 0..count {
+                    self.skip_any()?;
+                }
+                count as u32
+            }
+            CONTENT_DOC => {
+                self.read_buf()?;
+                self.skip_any()?;
+                1
+            }
+            CONTENT_MOVE => {
+                let flags = self.read_var()?;
+                self.read_id()?;
+                if flags & 1 == 0 {
+                    self.read_id()?;
+                }
+                1
+            }
+            content => {
+                return Err(read::Error::Custom(format!(
+                    "unknown content type: {}",
+                    content
+                )));
+            }
+        };
+        Ok((len, parent))
+    }
+}
@@ -0,0 +1,114 @@
+//! Concurrency test suite shared by all backends. Available with `stress-tests` feature enabled.
+//!
+//! Scenarios run multiple threads writing into the same document at once and verify that no
+//! update is lost on the way, which holds only if a backend satisfies
+//! [isolation](crate::KVStore#isolation) requirements. Backends expose their stores by
+//! implementing [StressStore], which maps every operation onto a separate committed transaction:
+//!
+//! ```rust,ignore
+//! struct Store(RocksDBDocStore<MultiThreaded>);
+//!
+//! impl StressStore for Store {
+//!     fn push_update(&self, name: &str, update: &[u8]) -> Result<PushReceipt, Error> {
+//!         self.0.push_update(name, update)
+//!     }
+//!     // ...
+//! }
+//!
+//! stress::concurrent_pushes(&store, 8, 64);
+//! ```
+
+use crate::error::Error;
+use crate::PushReceipt;
+use std::collections::HashSet;
+use yrs::{Doc, GetString, Text, Transact, TransactionMut};
+
+/// Store operations used by stress scenarios. Every call is expected to run within its own
+/// transaction, which is committed before the call returns. Calls are made from many threads at
+/// the same time.
+pub trait StressStore: Sync {
+    /// See [DocOps::push_update](crate::DocOps::push_update).
+    fn push_update(&self, name: &str, update: &[u8]) -> Result<PushReceipt, Error>;
+
+    /// Returns a number of pending updates stored for a document (see
+    /// [DocOps::decoded_updates](crate::DocOps::decoded_updates)).
+    fn update_count(&self, name: &str) -> Result<usize, Error>;
+
+    /// See [DocOps::load_doc](crate::DocOps::load_doc).
+    fn load_doc(&self, name: &str, txn: &mut TransactionMut) -> Result<bool, Error>;
+}
+
+/// Name of the document written by stress scenarios.
+pub const DOC_NAME: &str = "stress-doc";
+
+/// Runs `threads` threads, each pushing `pushes` updates into the same document concurrently.
+/// Every update appends a unique token to a shared text. Once all threads are done, verifies
+/// that:
+///
+/// - every push has been given a distinct sequence number,
+/// - the document has exactly `threads * pushes` pending updates,
+/// - a document loaded from the store contains every pushed token.
+///
+/// Store is expected to use a configuration which doesn't flush pending updates on its own.
+///
+/// # Panics
+///
+/// Panics if any of the conditions above is not met or a store operation fails.
+pub fn concurrent_pushes<S: StressStore>(store: &S, threads: u32, pushes: u32) {
+    let seqs: Vec<u32> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|t| {
+                s.spawn(move || {
+                    let doc = Doc::with_client_id(t as u64 + 1);
+                    let text = doc.get_or_insert_text("text");
+                    let mut seqs = Vec::with_capacity(pushes as usize);
+                    for i in 0..pushes {
+                        let update = {
+                            let mut txn = doc.transact_mut();
+                            text.push(&mut txn, &token(t, i));
+                            txn.encode_update_v1()
+                        };
+                        let receipt = store.push_update(DOC_NAME, &update).unwrap();
+                        seqs.push(receipt.seq);
+                    }
+                    seqs
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    });
+
+    let expected = (threads * pushes) as usize;
+    let unique: HashSet<u32> = seqs.iter().copied().collect();
+    assert_eq!(seqs.len(), expected);
+    assert_eq!(
+        unique.len(),
+        expected,
+        "duplicate sequence numbers assigned"
+    );
+    assert_eq!(store.update_count(DOC_NAME).unwrap(), expected);
+
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    {
+        let mut txn = doc.transact_mut();
+        assert!(store.load_doc(DOC_NAME, &mut txn).unwrap());
+    }
+    let content = text.get_string(&doc.transact());
+    let mut len = 0;
+    for t in 0..threads {
+        for i in 0..pushes {
+            let token = token(t, i);
+            assert!(content.contains(&token), "missing edit {}", token);
+            len += token.len();
+        }
+    }
+    assert_eq!(content.len(), len);
+}
+
+fn token(thread: u32, i: u32) -> String {
+    format!("[{}:{}]", thread, i)
+}
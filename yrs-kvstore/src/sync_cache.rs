@@ -0,0 +1,197 @@
+//! Cache of pre-encoded sync protocol frames (see
+//! [DocOps::cache_sync_frame](crate::DocOps::cache_sync_frame)).
+//!
+//! Peers joining a document at about the same time usually send the same state vector, so they
+//! need to receive the same diff. Instead of materializing the document for every one of them,
+//! a frame computed for the first peer can be stored in the cache and served to the next ones,
+//! until it expires - according to the [Clock](crate::clock::Clock) of the store - or the
+//! document changes.
+//!
+//! Frames are stored in [KEYSPACE_SYNC](crate::keys::KEYSPACE_SYNC) under the current generation
+//! of the document and a hash of the remote state vector they were computed for. Every write
+//! modifying document contents increments the generation, which makes all frames cached so far
+//! unreachable at once. Frames of past generations are removed by the next
+//! [DocOps::cache_sync_frame](crate::DocOps::cache_sync_frame) call for the same document, and
+//! all frames of a document are removed together with it by
+//! [DocOps::clear_doc](crate::DocOps::clear_doc).
+//!
+//! When [StoreConfig::sync_frame_ttl](crate::config::StoreConfig::sync_frame_ttl) is set,
+//! [DocOps::handle_sync_step1](crate::DocOps::handle_sync_step1) uses the cache on its own,
+//! keying frames by [sv_hash] of the remote state vector:
+//!
+//! ```rust,ignore
+//! let config = StoreConfig {
+//!     sync_frame_ttl: Some(Duration::from_secs(10)),
+//!     ..StoreConfig::DEFAULT
+//! };
+//! let db_txn = ConfiguredStore::new(RocksDBStore::from(db.transaction()), config);
+//! // the first call for a given state vector computes the reply and caches it
+//! let step2 = db_txn.handle_sync_step1("my-doc-name", &remote_sv)?;
+//! db_txn.into_inner().commit()?;
+//! ```
+
+use crate::error::Error;
+use crate::format::{decode_timestamp, encode_timestamp, TIMESTAMP_LEN};
+use crate::keys::{key_sync_frame, key_sync_frame_end, key_sync_frame_start, key_sync_gen, OID};
+use crate::{DocOps, KVEntry, KVStore, SyncStep2};
+use std::convert::TryInto;
+use std::time::Duration;
+use yrs::encoding::read::{Cursor, Read};
+use yrs::encoding::write::Write;
+
+/// Returns a hash of the binary `state_vector`, used by
+/// [DocOps::handle_sync_step1](crate::DocOps::handle_sync_step1) to key the frames it caches.
+/// It's a 64-bit FNV-1a hash, which is stable across processes and releases.
+pub fn sv_hash(state_vector: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in state_vector {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+pub(crate) fn cache_frame<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    hash: u64,
+    frame: &[u8],
+    ttl: Duration,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let generation = match get_generation(db, oid)? {
+        Some(generation) => {
+            remove_frames(db, oid, Some(generation))?;
+            generation
+        }
+        None => {
+            // from now on, writes to this document increment its generation
+            db.upsert(&key_sync_gen(oid), &0u32.to_be_bytes())?;
+            0
+        }
+    };
+    let expires = db.clock().now().saturating_add(ttl.as_millis() as u64);
+    let mut value = Vec::with_capacity(TIMESTAMP_LEN + frame.len());
+    value.extend_from_slice(&encode_timestamp(expires));
+    value.extend_from_slice(frame);
+    db.upsert(&key_sync_frame(oid, generation, hash), &value)?;
+    Ok(())
+}
+
+pub(crate) fn get_frame<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    hash: u64,
+) -> Result<Option<Vec<u8>>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let generation = match get_generation(db, oid)? {
+        Some(generation) => generation,
+        None => return Ok(None),
+    };
+    match db.get(&key_sync_frame(oid, generation, hash))? {
+        Some(value) => {
+            let value = value.as_ref();
+            if value.len() < TIMESTAMP_LEN {
+                return Err(Error::CorruptedValue);
+            }
+            let (expires, frame) = value.split_at(TIMESTAMP_LEN);
+            if db.clock().now() >= decode_timestamp(expires).unwrap() {
+                Ok(None)
+            } else {
+                Ok(Some(frame.to_vec()))
+            }
+        }
+        None => Ok(None),
+    }
+}
+
+/// Makes all sync frames cached for a document with a given `oid` unreachable. Documents which
+/// never had frames cached are left untouched.
+pub(crate) fn invalidate<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    if let Some(generation) = get_generation(db, oid)? {
+        db.upsert(
+            &key_sync_gen(oid),
+            &generation.wrapping_add(1).to_be_bytes(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Removes sync frames cached for a document with a given `oid`, except for the frames of
+/// a given `generation`. Passing `None` removes all of them.
+pub(crate) fn remove_frames<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+    generation: Option<u32>,
+) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let start = key_sync_frame_start(oid, 0);
+    let end = key_sync_frame_end(oid);
+    let keep = generation.map(|generation| key_sync_frame_start(oid, generation));
+    let mut stale = Vec::new();
+    for e in db.iter_range(&start, &end)? {
+        let key = e.key();
+        if key >= end.as_ref() {
+            break;
+        }
+        match &keep {
+            Some(keep) if key.starts_with(keep) => {}
+            _ => stale.push(key.to_vec()),
+        }
+    }
+    for key in stale {
+        db.remove(&key)?;
+    }
+    Ok(())
+}
+
+/// Encodes a reply to the first step of y-sync protocol computed for `remote_sv` into a frame
+/// cached by [DocOps::handle_sync_step1](crate::DocOps::handle_sync_step1). Remote state vector
+/// is stored as well, so that frames of state vectors with colliding hashes are never served.
+pub(crate) fn encode_step2(remote_sv: &[u8], step2: &SyncStep2) -> Vec<u8> {
+    let mut frame = Vec::new();
+    frame.write_buf(remote_sv);
+    frame.write_buf(&step2.update);
+    frame.write_buf(&step2.state_vector);
+    frame
+}
+
+/// Decodes a frame written by [encode_step2]. Returns `None` if the frame has been computed for
+/// a state vector other than `remote_sv`.
+pub(crate) fn decode_step2(remote_sv: &[u8], frame: &[u8]) -> Result<Option<SyncStep2>, Error> {
+    let mut cursor = Cursor::new(frame);
+    if cursor.read_buf()? != remote_sv {
+        return Ok(None);
+    }
+    let update = cursor.read_buf()?.to_vec();
+    let state_vector = cursor.read_buf()?.to_vec();
+    Ok(Some(SyncStep2 {
+        update,
+        state_vector,
+    }))
+}
+
+fn get_generation<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<Option<u32>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    match db.get(&key_sync_gen(oid))? {
+        Some(value) => {
+            let bytes: [u8; 4] = value
+                .as_ref()
+                .try_into()
+                .map_err(|_| Error::CorruptedValue)?;
+            Ok(Some(u32::from_be_bytes(bytes)))
+        }
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,212 @@
+//! Storage usage accounting of documents grouped by tenants, i.e. for billing.
+//!
+//! Tenant of a document is decided by a [TenantExtractor] installed with
+//! [ConfiguredStore::with_tenant_extractor](crate::config::ConfiguredStore::with_tenant_extractor),
+//! which maps document names to tenant identifiers - i.e. the first segment of a collection path
+//! ([first_segment]). Every write changing the size of a document attributed to a tenant applies
+//! the difference to the usage counter of that tenant, so usage can be read without scanning the
+//! documents:
+//!
+//! ```rust,ignore
+//! let db_txn = ConfiguredStore::new(RocksDBStore::from(db.transaction()), StoreConfig::DEFAULT)
+//!     .with_tenant_extractor(Arc::new(usage::first_segment));
+//! db_txn.push_update("acme/my-doc-name", &update)?;
+//!
+//! for (tenant, bytes) in db_txn.usage_by_tenant()? {
+//!     println!("{}: {} bytes", String::from_utf8_lossy(&tenant), bytes);
+//! }
+//! ```
+//!
+//! Document size is computed the same way as for [storage quotas](crate#storage-quotas): it's the
+//! size of its stored state and all of its pending updates. Updates pushed to channels other than
+//! the default one, documents archived with [DocOps::archive_doc] and [drafts](crate::draft) don't
+//! count towards it.
+//!
+//! Counters are updated by [DocOps::insert_doc], [DocOps::push_update],
+//! [DocOps::import_updates], flushes, [DocOps::clear_doc], archiving, [DocOps::promote_draft] and
+//! [archive](crate::archive) imports. Writes made by the same transaction are accounted together with them, but stores which
+//! don't apply writes atomically may leave counters off after a crash, as may operations completed
+//! by [DocOps::recover_intents]. [DocOps::recompute_usage] scans documents of a single tenant and
+//! corrects its counter. It finds them by their names, so identifiers returned by the extractor
+//! must be prefixes of the names they have been extracted from.
+//!
+//! Tracking a write reads the size of the document before and after it, which includes the
+//! length of its stored state.
+
+use crate::blob;
+use crate::collection::find_separator;
+use crate::error::Error;
+use crate::format::OID_FLAG_DRAFT;
+use crate::keys::{key_doc, key_usage, parse_key, Key, ParsedKey, KEYSPACE_USAGE, OID, V1};
+use crate::{decode_u64, get_oid_entry, get_pending, DocOps, KVEntry, KVStore};
+
+/// Maps names of documents to identifiers of tenants owning them. Documents for which it returns
+/// `None` are not attributed to any tenant.
+///
+/// Any `Fn(&[u8]) -> Option<Vec<u8>>` closure can be used as an extractor.
+pub trait TenantExtractor: Send + Sync {
+    /// Returns identifier of a tenant owning a document with a given `doc_name`.
+    fn tenant(&self, doc_name: &[u8]) -> Option<Vec<u8>>;
+}
+
+impl<F> TenantExtractor for F
+where
+    F: Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync,
+{
+    fn tenant(&self, doc_name: &[u8]) -> Option<Vec<u8>> {
+        self(doc_name)
+    }
+}
+
+/// Tenant extractor returning the first segment of a collection path (see
+/// [collection](crate::collection)) of a given `doc_name`, i.e. `acme` for `acme/docs/readme`.
+/// Documents stored outside of any collection are not attributed to any tenant.
+pub fn first_segment(doc_name: &[u8]) -> Option<Vec<u8>> {
+    let end = find_separator(doc_name)?;
+    Some(doc_name[..end].to_vec())
+}
+
+/// Change of the size of a single document made by a write, which is being tracked.
+pub(crate) struct Tracker(Option<(Vec<u8>, Vec<u8>, u64)>);
+
+impl Tracker {
+    /// Starts tracking a write to a document with a given `name`. Nothing is read if the document
+    /// is not attributed to any tenant.
+    pub fn start<'a, DB: DocOps<'a> + ?Sized>(db: &DB, name: &[u8]) -> Result<Self, Error>
+    where
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        let tenant = match db.tenant_extractor().and_then(|e| e.tenant(name)) {
+            Some(tenant) => tenant,
+            None => return Ok(Tracker(None)),
+        };
+        let bytes = doc_bytes(db, name)?;
+        Ok(Tracker(Some((tenant, name.to_vec(), bytes))))
+    }
+
+    /// Applies the change of the document size to the usage counter of its tenant once the write
+    /// has ended with a given `result`. Failed writes are not applied, unless they have stopped at
+    /// the deadline, since their progress is kept.
+    pub fn finish<'a, DB, R>(self, db: &DB, result: Result<R, Error>) -> Result<R, Error>
+    where
+        DB: DocOps<'a> + ?Sized,
+        Error: From<<DB as KVStore<'a>>::Error>,
+    {
+        match (&result, self.0) {
+            (Ok(_), Some((tenant, name, before)))
+            | (Err(Error::DeadlineExceeded { .. }), Some((tenant, name, before))) => {
+                let after = doc_bytes(db, &name)?;
+                if after != before {
+                    let usage = get(db, &tenant)?;
+                    set(db, &tenant, (usage + after).saturating_sub(before))?;
+                }
+                result
+            }
+            _ => result,
+        }
+    }
+}
+
+/// Tracks a write to a document with a given `name`, performed by `op`. See [Tracker].
+pub(crate) fn track<'a, DB, F, R>(db: &DB, name: &[u8], op: F) -> Result<R, Error>
+where
+    DB: DocOps<'a> + ?Sized,
+    Error: From<<DB as KVStore<'a>>::Error>,
+    F: FnOnce() -> Result<R, Error>,
+{
+    let tracker = Tracker::start(db, name)?;
+    tracker.finish(db, op())
+}
+
+/// Returns the size of a document with a given `name`, or 0 if it doesn't exist or it's a draft.
+fn doc_bytes<'a, DB: DocOps<'a> + ?Sized>(db: &DB, name: &[u8]) -> Result<u64, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    match get_oid_entry(db, name)? {
+        Some((oid, flags)) if flags & OID_FLAG_DRAFT == 0 => oid_bytes(db, oid),
+        _ => Ok(0),
+    }
+}
+
+/// Returns the size of a document with a given `oid`: its stored state and pending updates.
+fn oid_bytes<'a, DB: DocOps<'a> + ?Sized>(db: &DB, oid: OID) -> Result<u64, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let state_bytes = match db.get(&key_doc(oid))? {
+        Some(state) => blob::stored_len(state.as_ref()),
+        None => 0,
+    };
+    Ok(state_bytes + get_pending(db, oid)?.bytes)
+}
+
+/// Returns usage counter of a given `tenant`.
+pub(crate) fn get<'a, DB: DocOps<'a> + ?Sized>(db: &DB, tenant: &[u8]) -> Result<u64, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    match db.get(&key_usage(tenant))? {
+        Some(value) => decode_u64(value.as_ref()),
+        None => Ok(0),
+    }
+}
+
+/// Sets usage counter of a given `tenant`. Counters of tenants without any usage are removed.
+fn set<'a, DB: DocOps<'a> + ?Sized>(db: &DB, tenant: &[u8], bytes: u64) -> Result<(), Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let key = key_usage(tenant);
+    if bytes == 0 {
+        db.remove(&key)?;
+    } else {
+        db.upsert(&key, &bytes.to_be_bytes())?;
+    }
+    Ok(())
+}
+
+/// Returns usage counters of all tenants, in the order of their identifiers.
+pub(crate) fn all<'a, DB: DocOps<'a> + ?Sized>(db: &DB) -> Result<Vec<(Vec<u8>, u64)>, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let start = Key::from_const([V1, KEYSPACE_USAGE]);
+    let end = Key::from_const([V1, KEYSPACE_USAGE + 1]);
+    let mut usage = Vec::new();
+    for e in db.iter_range(&start, &end)? {
+        let key = e.key();
+        if key >= end.as_ref() {
+            break;
+        }
+        match parse_key(key) {
+            Some(ParsedKey::Usage { tenant }) => {
+                usage.push((tenant.to_vec(), decode_u64(e.value())?));
+            }
+            _ => return Err(Error::CorruptedValue),
+        }
+    }
+    Ok(usage)
+}
+
+/// Computes usage of a given `tenant` from the sizes of its documents and writes it into its
+/// counter. Documents are found by their names, which start with the tenant identifier.
+pub(crate) fn recompute<'a, DB: DocOps<'a>>(db: &DB, tenant: &[u8]) -> Result<u64, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let extractor = match db.tenant_extractor() {
+        Some(extractor) => extractor,
+        None => return Ok(0),
+    };
+    let mut bytes = 0;
+    for name in db.iter_docs_prefix(tenant)? {
+        // names sharing the prefix may belong to other tenants
+        if extractor.tenant(&name).as_deref() != Some(tenant) {
+            continue;
+        }
+        bytes += doc_bytes(db, &name)?;
+    }
+    set(db, tenant, bytes)?;
+    Ok(bytes)
+}
@@ -0,0 +1,299 @@
+//! Consistency checks between two stores, i.e. after a migration or to verify a replica.
+//!
+//! [compare_stores] walks documents of both stores in the order of their names and compares
+//! their logical contents rather than the stored entries, so stores using different OIDs,
+//! [ValueCodec](crate::config::ValueCodec)s, or holding the same document as a flushed state on
+//! one side and as a log of pending updates on the other one, are still considered equal:
+//!
+//! ```rust,ignore
+//! let report = compare_stores_with(&source_txn, &replica_txn, CompareMode::Fast)?;
+//! for name in report.only_in_a.iter() {
+//!     eprintln!("document {:?} is missing in replica", name);
+//! }
+//! assert!(report.is_equal());
+//! ```
+//!
+//! Reserved metadata entries (with keys starting with `$`) describe how a document is stored
+//! rather than its contents, so they are not compared.
+//!
+//! Stored state vectors of a single store can be checked against the actual contents of their
+//! documents with [DocOps::verify_state_vectors](crate::DocOps::verify_state_vectors), which
+//! reports them as [SvDrift]s.
+
+use crate::config;
+use crate::error::Error;
+use crate::keys::{key_archive, key_state_vector, OID, OID_FLAG_ARCHIVED};
+use crate::{
+    decode_state_vector, doc_state_sv, get_oid_entry, load_doc, sv_covered_by, DocOps, KVStore,
+};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use yrs::updates::decoder::Decode;
+use yrs::{Doc, ReadTxn, StateVector, Transact, Update};
+
+/// Decides how thoroughly [compare_stores_with] compares the contents of documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareMode {
+    /// Only state vectors are compared. Stored state vectors are used whenever they're up to
+    /// date, so most documents don't need to be materialized. Documents which differ only by
+    /// deletions are not detected.
+    Fast,
+    /// Both documents are materialized and their entire states, encoded using lib0 v1 encoding,
+    /// are compared.
+    Thorough,
+}
+
+/// Document which contents differ between compared stores.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentMismatch {
+    /// Name of the document.
+    pub name: Box<[u8]>,
+    /// State vector of the document in the first store.
+    pub state_vector_a: StateVector,
+    /// State vector of the document in the second store.
+    pub state_vector_b: StateVector,
+}
+
+/// Metadata entry which differs between compared stores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetaMismatch {
+    /// Name of the document.
+    pub name: Box<[u8]>,
+    /// Key of the metadata entry.
+    pub key: Box<[u8]>,
+    /// Value of the entry in the first store or `None` if it's missing there.
+    pub value_a: Option<Box<[u8]>>,
+    /// Value of the entry in the second store or `None` if it's missing there.
+    pub value_b: Option<Box<[u8]>>,
+}
+
+/// Document which stored state vector differs from the state vector of its contents, reported by
+/// [DocOps::verify_state_vectors](crate::DocOps::verify_state_vectors).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SvDrift {
+    /// Name of the document.
+    pub name: Box<[u8]>,
+    /// State vector stored for the document.
+    pub stored: StateVector,
+    /// State vector of the document state with all of its pending updates applied.
+    pub computed: StateVector,
+    /// True if the difference is explained by updates pushed after the stored state vector has
+    /// been written: it covers the document state, is covered by the computed state vector and
+    /// doesn't claim to cover the last pending update.
+    pub explained_by_pending: bool,
+    /// True if the document has no stored document state, while the stored state vector claims
+    /// changes missing from its pending updates - i.e. the state has been removed by a manual
+    /// cleanup, leaving the state vector behind. See
+    /// [DocOps::repair_drop_state_vector](crate::DocOps::repair_drop_state_vector).
+    pub doc_state_missing: bool,
+}
+
+/// Differences between two stores found by [compare_stores].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompareReport {
+    /// Number of documents present in both stores, which have been compared.
+    pub docs_compared: u32,
+    /// Names of documents present only in the first store.
+    pub only_in_a: Vec<Box<[u8]>>,
+    /// Names of documents present only in the second store.
+    pub only_in_b: Vec<Box<[u8]>>,
+    /// Documents which contents differ.
+    pub content_mismatches: Vec<ContentMismatch>,
+    /// Metadata entries which differ.
+    pub meta_mismatches: Vec<MetaMismatch>,
+}
+
+impl CompareReport {
+    /// Checks if no differences have been found.
+    pub fn is_equal(&self) -> bool {
+        self.only_in_a.is_empty()
+            && self.only_in_b.is_empty()
+            && self.content_mismatches.is_empty()
+            && self.meta_mismatches.is_empty()
+    }
+}
+
+/// Compares all documents of two stores using [CompareMode::Thorough]. See
+/// [module documentation](self).
+///
+/// This feature requires only the read capabilities from the database transactions.
+pub fn compare_stores<'a, 'b, A, B>(a: &A, b: &B) -> Result<CompareReport, Error>
+where
+    A: DocOps<'a>,
+    B: DocOps<'b>,
+    Error: From<<A as KVStore<'a>>::Error> + From<<B as KVStore<'b>>::Error>,
+{
+    compare_stores_with(a, b, CompareMode::Thorough)
+}
+
+/// Same as [compare_stores], but compares contents of documents according to a given `mode`.
+pub fn compare_stores_with<'a, 'b, A, B>(
+    a: &A,
+    b: &B,
+    mode: CompareMode,
+) -> Result<CompareReport, Error>
+where
+    A: DocOps<'a>,
+    B: DocOps<'b>,
+    Error: From<<A as KVStore<'a>>::Error> + From<<B as KVStore<'b>>::Error>,
+{
+    let mut report = CompareReport::default();
+    let mut names_a = a.iter_docs_with(true)?.peekable();
+    let mut names_b = b.iter_docs_with(true)?.peekable();
+    loop {
+        let order = match (names_a.peek(), names_b.peek()) {
+            (Some(name_a), Some(name_b)) => name_a.cmp(name_b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => break,
+        };
+        match order {
+            Ordering::Less => report.only_in_a.extend(names_a.next()),
+            Ordering::Greater => report.only_in_b.extend(names_b.next()),
+            Ordering::Equal => {
+                let name = names_a.next().unwrap();
+                names_b.next();
+                compare_doc(a, b, name, mode, &mut report)?;
+                report.docs_compared += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+fn compare_doc<'a, 'b, A, B>(
+    a: &A,
+    b: &B,
+    name: Box<[u8]>,
+    mode: CompareMode,
+    report: &mut CompareReport,
+) -> Result<(), Error>
+where
+    A: DocOps<'a>,
+    B: DocOps<'b>,
+    Error: From<<A as KVStore<'a>>::Error> + From<<B as KVStore<'b>>::Error>,
+{
+    let (state_vector_a, state_a) = doc_content(a, &name, mode)?;
+    let (state_vector_b, state_b) = doc_content(b, &name, mode)?;
+    if state_vector_a != state_vector_b || state_a != state_b {
+        report.content_mismatches.push(ContentMismatch {
+            name: name.clone(),
+            state_vector_a,
+            state_vector_b,
+        });
+    }
+
+    let mut meta_a = user_meta(a, &name)?;
+    for (key, value_b) in user_meta(b, &name)? {
+        match meta_a.remove(&key) {
+            Some(value_a) if value_a == value_b => {}
+            value_a => report.meta_mismatches.push(MetaMismatch {
+                name: name.clone(),
+                key,
+                value_a,
+                value_b: Some(value_b),
+            }),
+        }
+    }
+    for (key, value_a) in meta_a {
+        report.meta_mismatches.push(MetaMismatch {
+            name: name.clone(),
+            key,
+            value_a: Some(value_a),
+            value_b: None,
+        });
+    }
+    Ok(())
+}
+
+/// Returns the state vector of a document with a given `name` and, in [CompareMode::Thorough],
+/// its lib0 v1 encoded state.
+fn doc_content<'a, DB>(
+    db: &DB,
+    name: &[u8],
+    mode: CompareMode,
+) -> Result<(StateVector, Option<Vec<u8>>), Error>
+where
+    DB: DocOps<'a>,
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let (oid, flags) = match get_oid_entry(db, name)? {
+        Some(entry) => entry,
+        None => return Ok((StateVector::default(), None)),
+    };
+    let archived = flags & OID_FLAG_ARCHIVED != 0;
+    if mode == CompareMode::Fast && !archived {
+        if let (Some(sv), true) = db.get_state_vector(name)? {
+            return Ok((sv, None));
+        }
+    }
+    // documents are materialized with the same options on both sides, so that the states of
+    // documents flushed with and without garbage collection can be compared
+    let doc = Doc::new();
+    {
+        let mut txn = doc.transact_mut();
+        if archived {
+            if let Some(compressed) = db.get(&key_archive(oid))? {
+                let mut doc_state = Vec::new();
+                config::decompress(compressed.as_ref(), &mut doc_state)?;
+                txn.apply_update(Update::decode_v1(&doc_state)?);
+            }
+        } else {
+            load_doc(db, oid, &mut txn)?;
+        }
+    }
+    let txn = doc.transact();
+    let state = match mode {
+        CompareMode::Fast => None,
+        CompareMode::Thorough => Some(txn.encode_state_as_update_v1(&StateVector::default())),
+    };
+    Ok((txn.state_vector(), state))
+}
+
+/// Metadata entries of a document by their keys.
+type MetaMap = BTreeMap<Box<[u8]>, Box<[u8]>>;
+
+/// Returns metadata entries of a document with a given `name`, excluding reserved ones.
+fn user_meta<'a, DB>(db: &DB, name: &[u8]) -> Result<MetaMap, Error>
+where
+    DB: DocOps<'a>,
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    Ok(db
+        .iter_meta(name)?
+        .filter(|(key, _)| !key.starts_with(b"$"))
+        .collect())
+}
+
+/// Compares the state vector stored for a document with a given `name` and `oid` with the state
+/// vector of its contents. Returns `None` if they are equal or if there's no stored state vector.
+pub(crate) fn sv_drift<'a, DB>(db: &DB, name: &[u8], oid: OID) -> Result<Option<SvDrift>, Error>
+where
+    DB: DocOps<'a>,
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let codec = db.config().codec;
+    let (stored, covered_seq) = match db.get(&key_state_vector(oid))? {
+        Some(data) => decode_state_vector(&codec.decode(data.as_ref())?)?,
+        None => return Ok(None),
+    };
+    let doc = Doc::new();
+    let loaded = load_doc(db, oid, &mut doc.transact_mut())?;
+    let computed = doc.transact().state_vector();
+    if stored == computed {
+        return Ok(None);
+    }
+    let state_sv = doc_state_sv(db, oid)?.unwrap_or_default();
+    let explained_by_pending = (loaded.updates != 0 && covered_seq != loaded.last_seq
+        || loaded.channel_updates != 0)
+        && sv_covered_by(&state_sv, &stored)
+        && sv_covered_by(&stored, &computed);
+    let doc_state_missing = !loaded.doc_state && !sv_covered_by(&stored, &computed);
+    Ok(Some(SvDrift {
+        name: name.into(),
+        stored,
+        computed,
+        explained_by_pending,
+        doc_state_missing,
+    }))
+}
@@ -0,0 +1,127 @@
+//! Version tokens of stored documents, i.e. to be used as HTTP entity tags.
+//!
+//! [DocOps::doc_version](crate::DocOps::doc_version) derives a [DocVersion] from the entries
+//! describing the stored document - its OID, stored state vector and the sequence number of its
+//! last pending update - without reconstructing the document itself. Every write which changes
+//! the contents of a document ([DocOps::push_update](crate::DocOps::push_update),
+//! [DocOps::flush_doc](crate::DocOps::flush_doc), [DocOps::insert_doc](crate::DocOps::insert_doc),
+//! [DocOps::clear_doc](crate::DocOps::clear_doc) etc.) changes its version, while metadata
+//! entries have no effect on it. Version may also change without any change of the contents, i.e.
+//! when the stored state vector is rewritten by
+//! [DocOps::state_vector_force](crate::DocOps::state_vector_force), which costs no more than
+//! a cache miss.
+//!
+//! Versions are formatted as short strings, which can be embedded in HTTP headers and parsed
+//! back:
+//!
+//! ```rust,ignore
+//! let version = db_txn.doc_version("my-doc-name")?;
+//! match (version, request.header("If-None-Match")) {
+//!     (Some(v), Some(tag)) if tag.parse::<DocVersion>() == Ok(v) => not_modified(),
+//!     _ => ok(version, db_txn.load_doc("my-doc-name", &mut doc.transact_mut())?),
+//! }
+//! ```
+
+use crate::config::crc32;
+use crate::error::Error;
+use crate::keys::{key_state_vector, OID};
+use crate::{channel, decode_state_vector, last_seq, DocOps, KVEntry, KVStore};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Version token of a stored document, returned by
+/// [DocOps::doc_version](crate::DocOps::doc_version). See [version](crate::version) module for
+/// details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DocVersion {
+    /// OID of the document, which changes when the document is cleared and created again.
+    oid: OID,
+    /// Sum of all client clocks of the stored state vector.
+    clock: u64,
+    /// Sequence number of the last pending update, 0 if there are none.
+    seq: u32,
+    /// Checksum of the stored state vector and last updates of other channels.
+    checksum: u32,
+}
+
+impl DocVersion {
+    /// Checks if current version has been written after `other` one, as far as it can be told
+    /// from the versions alone: a document created again after being cleared is newer than the
+    /// cleared one, while versions of the same document are ordered by the clocks of its stored
+    /// state vector first and by the sequence number of its last pending update next.
+    ///
+    /// Versions which are neither equal nor newer than one another describe concurrent changes,
+    /// i.e. flushing pending updates which haven't changed the document state resets its sequence
+    /// number without advancing the clocks. Use equality to check if a document has changed.
+    pub fn is_newer_than(&self, other: &DocVersion) -> bool {
+        (self.oid, self.clock, self.seq) > (other.oid, other.clock, other.seq)
+    }
+}
+
+impl Display for DocVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:x}.{:x}.{:x}.{:08x}",
+            self.oid, self.clock, self.seq, self.checksum
+        )
+    }
+}
+
+impl FromStr for DocVersion {
+    type Err = ParseDocVersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('.');
+        let mut next = || parts.next().ok_or(ParseDocVersionError);
+        let version = DocVersion {
+            oid: OID::from_str_radix(next()?, 16).map_err(|_| ParseDocVersionError)?,
+            clock: u64::from_str_radix(next()?, 16).map_err(|_| ParseDocVersionError)?,
+            seq: u32::from_str_radix(next()?, 16).map_err(|_| ParseDocVersionError)?,
+            checksum: u32::from_str_radix(next()?, 16).map_err(|_| ParseDocVersionError)?,
+        };
+        match parts.next() {
+            None => Ok(version),
+            Some(_) => Err(ParseDocVersionError),
+        }
+    }
+}
+
+/// Error returned when a string cannot be parsed as a [DocVersion].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseDocVersionError;
+
+impl Display for ParseDocVersionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid document version")
+    }
+}
+
+impl std::error::Error for ParseDocVersionError {}
+
+/// Returns the version of a document with a given `oid`.
+pub(crate) fn doc_version<'a, DB: DocOps<'a> + ?Sized>(
+    db: &DB,
+    oid: OID,
+) -> Result<DocVersion, Error>
+where
+    Error: From<<DB as KVStore<'a>>::Error>,
+{
+    let mut hashed = Vec::new();
+    let mut clock = 0;
+    if let Some(data) = db.get(&key_state_vector(oid))? {
+        let data = db.config().codec.decode(data.as_ref())?;
+        let (sv, _) = decode_state_vector(&data)?;
+        clock = sv.iter().map(|(_, &clock)| clock as u64).sum();
+        hashed.extend_from_slice(&data);
+    }
+    for e in channel::last_updates(db, oid)? {
+        hashed.extend_from_slice(e.key());
+    }
+    Ok(DocVersion {
+        oid,
+        clock,
+        seq: last_seq(db, oid)?.unwrap_or(0),
+        checksum: crc32(&hashed),
+    })
+}
@@ -0,0 +1,432 @@
+//! Background compaction of documents, moving [DocOps::flush_doc](crate::DocOps::flush_doc) calls
+//! off the hot path.
+//!
+//! [CompactionWorker] runs a pool of threads consuming a queue of document names. Every document
+//! enqueued is flushed once its debounce window elapses, so repeatedly enqueueing the same
+//! document within that window results in a single flush. Since the worker is not tied to any
+//! particular store implementation, the flush itself is performed by a user-provided function,
+//! which is expected to open a new write transaction, call `flush_doc` and commit.
+//!
+//! ```rust,ignore
+//! let worker = CompactionWorker::spawn(
+//!     WorkerConfig::default(),
+//!     move |name| {
+//!         let db_txn = RocksDBStore::from(db.transaction());
+//!         let outcome = db_txn.flush_doc(name)?;
+//!         db_txn.commit()?;
+//!         Ok(outcome)
+//!     },
+//!     |name, result| { /* report outcome */ },
+//! );
+//! let handle = worker.handle();
+//! let seq_nr = db_txn.push_update("my-doc-name", &update)?.seq;
+//! handle.notify_pending("my-doc-name", seq_nr);
+//! ```
+//!
+//! # Flushing on shutdown
+//!
+//! Before a server stops, [shutdown_flush] can merge pending updates of as many documents as
+//! possible within a given time budget, so that they load fast after the next start. Documents
+//! are taken from [DocOps::flush_candidates](crate::DocOps::flush_candidates), starting with the
+//! ones with the most pending bytes, and flushed using the same kind of function as above:
+//!
+//! ```rust,ignore
+//! let candidates = RocksDBStore::from(db.transaction()).flush_candidates()?;
+//! let report = shutdown_flush(candidates, Duration::from_secs(5), 4, |name| {
+//!     let db_txn = RocksDBStore::from(db.transaction());
+//!     let outcome = db_txn.flush_doc(name)?;
+//!     db_txn.commit()?;
+//!     Ok(outcome)
+//! });
+//! for name in report.unflushed {
+//!     eprintln!("document {:?} left with pending updates", name);
+//! }
+//! ```
+//!
+//! Flushes of different documents are independent, but stores allowing a single write transaction
+//! at a time (like LMDB) run them one after another, no matter how many threads are used. For
+//! these stores [shutdown_flush_pipelined] reconstructs documents on worker threads using
+//! [DocOps::prepare_flush](crate::DocOps::prepare_flush), which needs only read-only
+//! transactions, while their states are written one at a time on the calling thread using
+//! [DocOps::apply_flush](crate::DocOps::apply_flush).
+
+use crate::error::Error;
+use crate::{FlushOutcome, PreparedFlush};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Configuration of the [CompactionWorker].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerConfig {
+    /// Time a document waits in the queue before being flushed. Enqueueing the same document
+    /// again within that time has no effect.
+    pub debounce: Duration,
+    /// Number of pending updates after which [CompactionHandle::notify_pending] enqueues
+    /// a document.
+    pub threshold: u32,
+    /// Maximum number of flushes running at the same time.
+    pub max_concurrent: usize,
+    /// Delay before retrying a failed flush. It's doubled on every consecutive failure of the
+    /// same document, up to [WorkerConfig::max_backoff].
+    pub backoff: Duration,
+    /// Maximum delay between retries of a failed flush.
+    pub max_backoff: Duration,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig {
+            debounce: Duration::from_secs(1),
+            threshold: 64,
+            max_concurrent: 1,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// Documents waiting to be flushed with the time they are due.
+    pending: HashMap<Box<[u8]>, Instant>,
+    /// Documents being flushed right now.
+    in_flight: HashSet<Box<[u8]>>,
+    /// Number of consecutive flush failures per document.
+    failures: HashMap<Box<[u8]>, u32>,
+    shutdown: bool,
+}
+
+struct Shared {
+    config: WorkerConfig,
+    state: Mutex<State>,
+    signal: Condvar,
+}
+
+impl Shared {
+    fn enqueue(&self, name: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        if state.shutdown || state.pending.contains_key(name) {
+            return;
+        }
+        let due = Instant::now() + self.config.debounce;
+        state.pending.insert(name.into(), due);
+        self.signal.notify_all();
+    }
+
+    /// Blocks until there's a document ready to be flushed. Returns `None` once worker has been
+    /// shut down and all queued documents have been flushed.
+    fn next(&self) -> Option<Box<[u8]>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let mut earliest: Option<Instant> = None;
+            let mut ready = None;
+            for (name, due) in state.pending.iter() {
+                if state.in_flight.contains(name) {
+                    continue; // never flush the same document concurrently
+                }
+                if state.shutdown || *due <= now {
+                    ready = Some(name.clone());
+                    break;
+                }
+                earliest = Some(earliest.map_or(*due, |e| e.min(*due)));
+            }
+            if let Some(name) = ready {
+                state.pending.remove(&name);
+                state.in_flight.insert(name.clone());
+                return Some(name);
+            }
+            if state.shutdown && state.pending.is_empty() {
+                return None;
+            }
+            state = match earliest {
+                Some(due) => self.signal.wait_timeout(state, due - now).unwrap().0,
+                None => self.signal.wait(state).unwrap(),
+            };
+        }
+    }
+
+    fn complete(&self, name: Box<[u8]>, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight.remove(&name);
+        if success {
+            state.failures.remove(&name);
+        } else if !state.shutdown {
+            let failures = state.failures.entry(name.clone()).or_insert(0);
+            *failures += 1;
+            let backoff = self
+                .config
+                .backoff
+                .checked_mul(1 << (*failures - 1).min(16))
+                .unwrap_or(self.config.max_backoff)
+                .min(self.config.max_backoff);
+            let due = Instant::now() + backoff;
+            let entry = state.pending.entry(name).or_insert(due);
+            *entry = (*entry).max(due);
+        }
+        self.signal.notify_all();
+    }
+}
+
+/// Pool of background threads flushing enqueued documents. See [module documentation](self)
+/// for details.
+///
+/// Dropping the worker shuts it down the same way as [CompactionWorker::shutdown].
+pub struct CompactionWorker {
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl CompactionWorker {
+    /// Spawns a new worker. `flush` is called on a worker thread to flush a document with
+    /// a given name, while `report` is called right after with the result of that flush.
+    pub fn spawn<F, R>(config: WorkerConfig, flush: F, report: R) -> Self
+    where
+        F: Fn(&[u8]) -> Result<Option<FlushOutcome>, Error> + Send + Sync + 'static,
+        R: Fn(&[u8], Result<Option<FlushOutcome>, Error>) + Send + Sync + 'static,
+    {
+        let threads_count = config.max_concurrent.max(1);
+        let shared = Arc::new(Shared {
+            config,
+            state: Mutex::new(State::default()),
+            signal: Condvar::new(),
+        });
+        let flush = Arc::new(flush);
+        let report = Arc::new(report);
+        let threads = (0..threads_count)
+            .map(|_| {
+                let shared = shared.clone();
+                let flush = flush.clone();
+                let report = report.clone();
+                std::thread::spawn(move || {
+                    while let Some(name) = shared.next() {
+                        let result = flush(&name);
+                        let success = result.is_ok();
+                        report(&name, result);
+                        shared.complete(name, success);
+                    }
+                })
+            })
+            .collect();
+        CompactionWorker { shared, threads }
+    }
+
+    /// Returns a handle, which can be used to enqueue documents from other threads.
+    pub fn handle(&self) -> CompactionHandle {
+        CompactionHandle(self.shared.clone())
+    }
+
+    /// Enqueues a document with a given `name` to be flushed once debounce window elapses.
+    pub fn enqueue<K: AsRef<[u8]> + ?Sized>(&self, name: &K) {
+        self.shared.enqueue(name.as_ref())
+    }
+
+    /// Stops accepting new documents, flushes all documents remaining in the queue right away
+    /// (without waiting for their debounce windows) and waits for worker threads to finish.
+    /// Failed flushes are not retried during shutdown.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            state.shutdown = true;
+            self.shared.signal.notify_all();
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for CompactionWorker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Cloneable handle used to enqueue documents into [CompactionWorker].
+#[derive(Clone)]
+pub struct CompactionHandle(Arc<Shared>);
+
+impl CompactionHandle {
+    /// Enqueues a document with a given `name` to be flushed once debounce window elapses.
+    pub fn enqueue<K: AsRef<[u8]> + ?Sized>(&self, name: &K) {
+        self.0.enqueue(name.as_ref())
+    }
+
+    /// Enqueues a document with a given `name` if number of its `pending_updates` (i.e. sequence
+    /// number returned by [DocOps::push_update](crate::DocOps::push_update)) reached
+    /// [WorkerConfig::threshold].
+    pub fn notify_pending<K: AsRef<[u8]> + ?Sized>(&self, name: &K, pending_updates: u32) {
+        if pending_updates >= self.0.config.threshold {
+            self.0.enqueue(name.as_ref())
+        }
+    }
+}
+
+/// Document with pending updates, returned by
+/// [DocOps::flush_candidates](crate::DocOps::flush_candidates).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlushCandidate {
+    /// Name of the document.
+    pub name: Box<[u8]>,
+    /// Number of updates, which have not been merged into document state yet.
+    pub pending_updates: u32,
+    /// Total size (in bytes) of stored values of pending updates.
+    pub pending_bytes: u64,
+}
+
+/// Summary of a [shutdown_flush] call.
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    /// Names of flushed documents, in the order their flushes have completed.
+    pub flushed: Vec<Box<[u8]>>,
+    /// Names of documents which flush has failed, together with the error message. Errors are
+    /// reported as messages, since they are not guaranteed to be sendable between threads.
+    pub failed: Vec<(Box<[u8]>, String)>,
+    /// Names of documents which have not been flushed before the time budget ran out, starting
+    /// with the ones with the most pending bytes.
+    pub unflushed: Vec<Box<[u8]>>,
+    /// Total number of pending updates merged into document state.
+    pub updates_folded: u32,
+    /// Time it took to complete the call.
+    pub duration: Duration,
+}
+
+/// Flushes given `candidates` with up to `parallelism` flushes running at the same time, starting
+/// with documents with the most pending bytes. No flush is started once `max_duration` has
+/// elapsed, but the ones already running are awaited. At least one document is always flushed,
+/// so that every call makes progress. See [module documentation](self) for details.
+///
+/// `flush` is expected to flush a document within its own transaction and commit it, so the call
+/// is safe to interrupt at any point: every document is either flushed or left with all of its
+/// pending updates in place, and can be loaded either way.
+pub fn shutdown_flush<I, F>(
+    candidates: I,
+    max_duration: Duration,
+    parallelism: usize,
+    flush: F,
+) -> ShutdownReport
+where
+    I: IntoIterator<Item = FlushCandidate>,
+    F: Fn(&[u8]) -> Result<Option<FlushOutcome>, Error> + Sync,
+{
+    let start = Instant::now();
+    let queue = FlushQueue::new(candidates);
+    let report = Mutex::new(ShutdownReport::default());
+    std::thread::scope(|scope| {
+        for _ in 0..parallelism.max(1) {
+            scope.spawn(|| {
+                while let Some(name) = queue.next(start, max_duration) {
+                    let result = flush(&name).map_err(|e| e.to_string());
+                    report.lock().unwrap().record(name, result);
+                }
+            });
+        }
+    });
+    queue.finish(report.into_inner().unwrap(), start)
+}
+
+/// Works like [shutdown_flush], but up to `parallelism` worker threads only reconstruct documents
+/// using `prepare`, which is expected to call [DocOps::prepare_flush](crate::DocOps::prepare_flush)
+/// within a read-only transaction. Reconstructed documents are written one at a time on the
+/// calling thread by `apply`, which is expected to call
+/// [DocOps::apply_flush](crate::DocOps::apply_flush) within its own write transaction and commit
+/// it. See [module documentation](self) for details.
+///
+/// Documents, which have been changed after being reconstructed, are flushed from scratch by
+/// `apply`, so the outcome is the same as if every document was flushed with [shutdown_flush].
+pub fn shutdown_flush_pipelined<I, P, A>(
+    candidates: I,
+    max_duration: Duration,
+    parallelism: usize,
+    prepare: P,
+    mut apply: A,
+) -> ShutdownReport
+where
+    I: IntoIterator<Item = FlushCandidate>,
+    P: Fn(&[u8]) -> Result<Option<PreparedFlush>, Error> + Sync,
+    A: FnMut(&[u8], PreparedFlush) -> Result<Option<FlushOutcome>, Error>,
+{
+    let start = Instant::now();
+    let queue = FlushQueue::new(candidates);
+    let mut report = ShutdownReport::default();
+    let parallelism = parallelism.max(1);
+    // bounded, so that reconstructed documents don't pile up faster than they are written
+    let (tx, rx) = mpsc::sync_channel(parallelism);
+    std::thread::scope(|scope| {
+        for _ in 0..parallelism {
+            let tx = tx.clone();
+            let (queue, prepare) = (&queue, &prepare);
+            scope.spawn(move || {
+                while let Some(name) = queue.next(start, max_duration) {
+                    let prepared = prepare(&name).map_err(|e| e.to_string());
+                    if tx.send((name, prepared)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+        for (name, prepared) in rx {
+            let result = match prepared {
+                Ok(Some(prepared)) => apply(&name, prepared).map_err(|e| e.to_string()),
+                Ok(None) => Ok(None),
+                Err(e) => Err(e),
+            };
+            report.record(name, result);
+        }
+    });
+    queue.finish(report, start)
+}
+
+/// Queue of documents to be flushed by [shutdown_flush] and [shutdown_flush_pipelined], shared
+/// by their worker threads.
+struct FlushQueue(Mutex<(VecDeque<FlushCandidate>, usize)>);
+
+impl FlushQueue {
+    fn new<I: IntoIterator<Item = FlushCandidate>>(candidates: I) -> Self {
+        let mut candidates: Vec<FlushCandidate> = candidates.into_iter().collect();
+        candidates.sort_by_key(|c| Reverse(c.pending_bytes));
+        FlushQueue(Mutex::new((candidates.into(), 0)))
+    }
+
+    /// Returns the name of the next document to flush, or `None` if there are no more documents
+    /// or the time budget has run out. At least one document is always returned.
+    fn next(&self, start: Instant, max_duration: Duration) -> Option<Box<[u8]>> {
+        let mut queue = self.0.lock().unwrap();
+        let (docs, started) = &mut *queue;
+        if *started > 0 && start.elapsed() >= max_duration {
+            return None;
+        }
+        let candidate = docs.pop_front()?;
+        *started += 1;
+        Some(candidate.name)
+    }
+
+    fn finish(self, mut report: ShutdownReport, start: Instant) -> ShutdownReport {
+        let (docs, _) = self.0.into_inner().unwrap();
+        report.unflushed = docs.into_iter().map(|candidate| candidate.name).collect();
+        report.duration = start.elapsed();
+        report
+    }
+}
+
+impl ShutdownReport {
+    fn record(&mut self, name: Box<[u8]>, result: Result<Option<FlushOutcome>, String>) {
+        match result {
+            Ok(outcome) => {
+                if let Some(outcome) = outcome {
+                    self.updates_folded += outcome.updates_folded;
+                }
+                self.flushed.push(name);
+            }
+            Err(e) => self.failed.push((name, e)),
+        }
+    }
+}
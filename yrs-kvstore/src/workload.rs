@@ -0,0 +1,163 @@
+//! Comparison of [FlushPolicy] settings, measured by replaying a text editing trace through
+//! [IoStatsStore]. Available with `bench` feature enabled.
+//!
+//! Every policy is replayed against its own document, so the same transaction can be used to
+//! compare all of them. Stores are created by a given function, so that backends can start each
+//! replay with a fresh transaction:
+//!
+//! ```rust,ignore
+//! let updates = trace_updates(&load_trace("editing-trace.bin"));
+//! let runs = compare_flush_policies(
+//!     || RocksDBStore::from(db.transaction()),
+//!     &updates,
+//!     &FLUSH_POLICIES,
+//! )?;
+//! println!("{}", comparison_table(&runs));
+//! ```
+//!
+//! See [bench](crate::bench) for utilities used to obtain editing traces.
+
+use crate::config::{ConfiguredStore, FlushPolicy, StoreConfig};
+use crate::error::Error;
+use crate::io_stats::{IoReport, IoStatsStore};
+use crate::keys::{key_doc_end, key_doc_start};
+use crate::{get_oid, KVStore};
+use std::fmt::Write;
+
+/// Flush policies compared by default: merging every 64 updates, merging once pending updates
+/// reach 16KiB or 256KiB, and never merging them at all.
+pub const FLUSH_POLICIES: [FlushPolicy; 4] = [
+    FlushPolicy::AfterUpdates(64),
+    FlushPolicy::AfterBytes(16 * 1024),
+    FlushPolicy::AfterBytes(256 * 1024),
+    FlushPolicy::Manual,
+];
+
+/// Results of replaying updates under a single [FlushPolicy].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyRun {
+    /// Flush policy used by the replay.
+    pub policy: FlushPolicy,
+    /// Number of replayed updates.
+    pub updates: u32,
+    /// Total size of replayed updates.
+    pub update_bytes: u64,
+    /// Key-value store traffic generated by the replay.
+    pub io: IoReport,
+    /// Highest number of pending updates observed after any update.
+    pub peak_pending_updates: u32,
+    /// Highest total size of pending updates observed after any update.
+    pub peak_pending_bytes: u64,
+    /// Total size of keys and values of the document once the replay has finished.
+    pub store_size: u64,
+}
+
+impl PolicyRun {
+    /// Returns the number of bytes written to the store per byte of replayed updates.
+    pub fn write_amplification(&self) -> f64 {
+        if self.update_bytes == 0 {
+            0.0
+        } else {
+            self.io.total().bytes_written as f64 / self.update_bytes as f64
+        }
+    }
+}
+
+/// Pushes given `updates` into a document with a given `name`, letting a given flush `policy`
+/// decide when they are merged into document state. All other settings of a `store` are reset to
+/// their defaults.
+pub fn replay_with_policy<'a, S>(
+    store: S,
+    name: &str,
+    updates: &[Vec<u8>],
+    policy: FlushPolicy,
+) -> Result<PolicyRun, Error>
+where
+    S: KVStore<'a>,
+    Error: From<S::Error>,
+{
+    let config = StoreConfig {
+        flush_policy: policy,
+        ..StoreConfig::DEFAULT
+    };
+    let db = IoStatsStore::new(ConfiguredStore::new(store, config));
+    let mut run = PolicyRun {
+        policy,
+        updates: 0,
+        update_bytes: 0,
+        io: IoReport::default(),
+        peak_pending_updates: 0,
+        peak_pending_bytes: 0,
+        store_size: 0,
+    };
+    for update in updates.iter() {
+        let receipt = db.push_update(name, update)?;
+        run.updates += 1;
+        run.update_bytes += update.len() as u64;
+        run.peak_pending_updates = run.peak_pending_updates.max(receipt.pending_updates);
+        run.peak_pending_bytes = run.peak_pending_bytes.max(receipt.pending_bytes);
+    }
+    run.io = db.report();
+    if let Some(oid) = get_oid(&db, name.as_bytes())? {
+        let store_size = &mut run.store_size;
+        db.iter_keys_range(&key_doc_start(oid), &key_doc_end(oid), &mut |key, len| {
+            *store_size += (key.len() + len) as u64;
+            true
+        })?;
+    }
+    Ok(run)
+}
+
+/// Replays given `updates` under every flush policy of `policies` (see [replay_with_policy]),
+/// using a new store returned by `open` for each one of them. Returns results in the same order
+/// as `policies`.
+pub fn compare_flush_policies<'a, S, F>(
+    mut open: F,
+    updates: &[Vec<u8>],
+    policies: &[FlushPolicy],
+) -> Result<Vec<PolicyRun>, Error>
+where
+    S: KVStore<'a>,
+    F: FnMut() -> S,
+    Error: From<S::Error>,
+{
+    let mut runs = Vec::with_capacity(policies.len());
+    for (i, policy) in policies.iter().enumerate() {
+        let name = format!("workload-{}", i);
+        runs.push(replay_with_policy(open(), &name, updates, *policy)?);
+    }
+    Ok(runs)
+}
+
+/// Formats results of [compare_flush_policies] as a plain text table, one policy per row.
+pub fn comparison_table(runs: &[PolicyRun]) -> String {
+    let mut table = String::new();
+    writeln!(
+        table,
+        "{:<24} {:>14} {:>14} {:>8} {:>18} {:>16}",
+        "policy", "update bytes", "bytes written", "amp.", "peak pending bytes", "store size"
+    )
+    .unwrap();
+    for run in runs.iter() {
+        writeln!(
+            table,
+            "{:<24} {:>14} {:>14} {:>8.2} {:>18} {:>16}",
+            policy_label(run.policy),
+            run.update_bytes,
+            run.io.total().bytes_written,
+            run.write_amplification(),
+            run.peak_pending_bytes,
+            run.store_size
+        )
+        .unwrap();
+    }
+    table
+}
+
+fn policy_label(policy: FlushPolicy) -> String {
+    match policy {
+        FlushPolicy::Manual => "never".to_string(),
+        FlushPolicy::AfterUpdates(n) => format!("every {} updates", n),
+        FlushPolicy::AfterBytes(n) => format!("every {} bytes", n),
+    }
+}
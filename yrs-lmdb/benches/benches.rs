@@ -3,21 +3,27 @@ use std::sync::Arc;
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use lmdb_rs::core::DbCreate;
 use lmdb_rs::Environment;
-use yrs::encoding::read::{Cursor, Read};
-use yrs::{uuid_v4, Doc, Text, Transact};
+use yrs::{uuid_v4, Doc, StateVector, Text, Transact, TransactionMut};
 
-use yrs_kvstore::DocOps;
-use yrs_lmdb::LmdbStore;
+use yrs_kvstore::bench::{self as shared, apply_ops, load_trace, BenchStore, Cleaner, TextOp};
+use yrs_kvstore::error::Error;
+use yrs_kvstore::{DocOps, WriteDurability};
+use yrs_lmdb::{LmdbDocStore, LmdbEnv, LmdbStore};
 
 fn bench(c: &mut Criterion) {
     insert_doc(c);
     updates(c);
+    updates_durability(c);
+    load_docs(c);
+    shared_scenarios(c);
 }
 
+const TRACE: &str = "editing-trace.bin";
+
 fn insert_doc(c: &mut Criterion) {
     let doc = Doc::new();
-    let ops = read_input("editing-trace.bin");
-    apply_ops(&doc, ops);
+    let ops = load_trace(TRACE);
+    apply_ops(&doc, &ops);
 
     let clean = Cleaner::new("insert-doc-lmdb");
     let env = init_env(clean.dir());
@@ -42,7 +48,7 @@ fn updates(c: &mut Criterion) {
     let doc = Doc::new();
     let text = doc.get_or_insert_text("text");
 
-    let ops = read_input("editing-trace.bin");
+    let ops = load_trace(TRACE);
 
     let clean = Cleaner::new("insert-doc-lmdb");
     let env = Arc::new(init_env(clean.dir()));
@@ -75,28 +81,136 @@ fn updates(c: &mut Criterion) {
     );
 }
 
-struct Cleaner(&'static str);
-
-impl Cleaner {
-    fn new(dir: &'static str) -> Self {
-        Self::cleanup(dir);
-        Cleaner(dir)
+fn updates_durability(c: &mut Criterion) {
+    let ops = load_trace(TRACE);
+    let mut group = c.benchmark_group("updates durability");
+
+    for durability in [
+        WriteDurability::Durable,
+        WriteDurability::Relaxed,
+        WriteDurability::Volatile,
+    ] {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+
+        let clean = Cleaner::new("updates-durability-lmdb");
+        let mut env = LmdbEnv::new(init_env(clean.dir()));
+        env.set_durability(durability).unwrap();
+        let env = Arc::new(env);
+        let handle = Arc::new(env.create_db("yrs", DbCreate).unwrap());
+
+        group.bench_with_input(
+            BenchmarkId::new(format!("{:?}", durability), ops.len()),
+            &(doc, text, &ops, env, handle),
+            |b, (doc, text, ops, env, handle)| {
+                b.iter(|| {
+                    let env = env.clone();
+                    let handle = handle.clone();
+                    let name = uuid_v4().to_string();
+                    let _sub = doc.observe_update_v1(move |_, e| {
+                        env.with_write_txn(&handle, |db| db.push_update(&name, &e.update))
+                            .unwrap();
+                    });
+
+                    for op in ops.iter() {
+                        let mut txn = doc.transact_mut();
+                        match op {
+                            TextOp::Insert(idx, txt) => text.insert(&mut txn, *idx, txt),
+                            TextOp::Delete(idx, len) => text.remove_range(&mut txn, *idx, *len),
+                        }
+                    }
+                });
+            },
+        );
     }
+    group.finish();
+}
 
-    fn dir(&self) -> &str {
-        self.0
-    }
+fn load_docs(c: &mut Criterion) {
+    const DOCS: usize = 500;
+    let mut group = c.benchmark_group("load documents");
 
-    fn cleanup(dir: &str) {
-        if let Err(_) = std::fs::remove_dir_all(dir) {
-            // if dir doesn't exists, ignore
+    let clean = Cleaner::new("load-docs-lmdb");
+    let env = init_env(clean.dir());
+    let handle = env.create_db("yrs", DbCreate).unwrap();
+    let names: Vec<String> = (0..DOCS).map(|i| format!("doc-{}", i)).collect();
+    {
+        let txn = env.new_transaction().unwrap();
+        let db = LmdbStore::from(txn.bind(&handle));
+        for (i, name) in names.iter().enumerate() {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            text.push(&mut doc.transact_mut(), &format!("hello {}", i));
+            db.insert_doc(name, &doc.transact()).unwrap();
         }
+        txn.commit().unwrap();
     }
+
+    group.bench_with_input(
+        BenchmarkId::new("per-doc transactions", DOCS),
+        &(&names, &env, &handle),
+        |b, &(names, env, handle)| {
+            b.iter(|| {
+                for name in names.iter() {
+                    let doc = Doc::new();
+                    let txn = env.get_reader().unwrap();
+                    let db = LmdbStore::from(txn.bind(handle));
+                    db.load_doc(name, &mut doc.transact_mut()).unwrap();
+                }
+            });
+        },
+    );
+    group.bench_with_input(
+        BenchmarkId::new("batched", DOCS),
+        &(&names, &env, &handle),
+        |b, &(names, env, handle)| {
+            b.iter(|| {
+                let txn = env.get_reader().unwrap();
+                let db = LmdbStore::from(txn.bind(handle));
+                let docs = db
+                    .load_docs(names.iter().map(|name| name.as_bytes()))
+                    .unwrap();
+                assert_eq!(docs.len(), names.len());
+            });
+        },
+    );
+    group.finish();
+}
+
+/// Scenarios shared with other backends, see [yrs_kvstore::bench].
+fn shared_scenarios(c: &mut Criterion) {
+    let trace = load_trace(TRACE);
+    let clean = Cleaner::new("shared-scenarios-lmdb");
+    let env = LmdbEnv::new(init_env(clean.dir()));
+    let handle = env.create_db("yrs", DbCreate).unwrap();
+    let store = Store(LmdbDocStore::new(Arc::new(env), Arc::new(handle)));
+
+    shared::load_doc(c, &store, &trace);
+    shared::flush_doc(c, &store, &trace);
+    shared::get_diff(c, &store, &trace);
+    shared::mixed_workload(c, &store, &trace);
 }
 
-impl Drop for Cleaner {
-    fn drop(&mut self) {
-        Self::cleanup(self.dir());
+struct Store(LmdbDocStore);
+
+impl BenchStore for Store {
+    const NAME: &'static str = "lmdb";
+
+    fn push_update(&self, name: &str, update: &[u8]) -> Result<u32, Error> {
+        Ok(self.0.push_update(name, update)?.seq)
+    }
+
+    fn flush_doc(&self, name: &str) -> Result<(), Error> {
+        self.0.flush_doc(name)?;
+        Ok(())
+    }
+
+    fn load_doc(&self, name: &str, txn: &mut TransactionMut) -> Result<bool, Error> {
+        self.0.load_doc(name, txn)
+    }
+
+    fn get_diff(&self, name: &str, sv: &StateVector) -> Result<Option<Vec<u8>>, Error> {
+        self.0.get_diff(name, sv)
     }
 }
 
@@ -109,54 +223,6 @@ fn init_env(dir: &str) -> Environment {
         .unwrap();
     env
 }
-#[derive(Clone)]
-enum TextOp {
-    Insert(u32, String),
-    Delete(u32, u32),
-}
-
-fn apply_ops(doc: &Doc, ops: Vec<TextOp>) {
-    let text = doc.get_or_insert_text("text");
-    for op in ops.iter() {
-        let mut txn = doc.transact_mut();
-        match op {
-            TextOp::Insert(idx, txt) => text.insert(&mut txn, *idx, txt),
-            TextOp::Delete(idx, len) => text.remove_range(&mut txn, *idx, *len),
-        }
-    }
-}
-
-fn read_input(fpath: &str) -> Vec<TextOp> {
-    use std::fs::File;
-    use yrs::updates::decoder::DecoderV1;
-
-    let mut f = File::open(fpath).unwrap();
-    let mut buf = Vec::new();
-    std::io::Read::read_to_end(&mut f, &mut buf).unwrap();
-    let mut decoder = DecoderV1::new(Cursor::new(buf.as_slice()));
-    let len: usize = decoder.read_var().unwrap();
-    let mut result = Vec::with_capacity(len);
-    for _ in 0..len {
-        let op = {
-            match decoder.read_var().unwrap() {
-                1u32 => {
-                    let idx = decoder.read_var().unwrap();
-                    let chunk = decoder.read_string().unwrap();
-                    TextOp::Insert(idx, chunk.to_string())
-                }
-                2u32 => {
-                    let idx = decoder.read_var().unwrap();
-                    let len = decoder.read_var().unwrap();
-                    TextOp::Delete(idx, len)
-                }
-                other => panic!("unrecognized TextOp tag type: {}", other),
-            }
-        };
-        result.push(op);
-    }
-    result
-}
-
 criterion_group! {
     name = benches;
     config = Criterion::default().sample_size(10);
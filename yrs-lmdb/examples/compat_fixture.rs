@@ -0,0 +1,25 @@
+use lmdb_rs::core::DbCreate;
+use lmdb_rs::Environment;
+use yrs_kvstore::bench::Cleaner;
+use yrs_kvstore::compat::{dump, populate};
+use yrs_lmdb::LmdbStore;
+
+fn main() {
+    let cleaner = Cleaner::new("example-compat-fixture");
+    let env = Environment::new()
+        .autocreate_dir(true)
+        .max_dbs(1)
+        .open(cleaner.dir(), 0o777)
+        .unwrap();
+    let h = env.create_db("yrs", DbCreate).unwrap();
+
+    // write the fixture documents into an empty store and print all of its entries, i.e.
+    // `cargo run --example compat_fixture > yrs-kvstore/fixtures/format-v<FORMAT_VERSION>.txt`
+    let txn = env.new_transaction().unwrap();
+    {
+        let db = LmdbStore::from(txn.bind(&h));
+        populate(&db);
+        dump(&db, &mut std::io::stdout()).unwrap();
+    }
+    txn.commit().unwrap();
+}
@@ -2,35 +2,10 @@ use lmdb_rs::core::DbCreate;
 use lmdb_rs::Environment;
 use std::sync::Arc;
 use std::time::Instant;
-use yrs::encoding::read::{Cursor, Read};
-use yrs::{Doc, Text, Transact};
-use yrs_kvstore::DocOps;
-use yrs_lmdb::LmdbStore;
-
-struct Cleaner(&'static str);
-
-impl Cleaner {
-    fn new(dir: &'static str) -> Self {
-        Self::cleanup(dir);
-        Cleaner(dir)
-    }
-
-    fn dir(&self) -> &str {
-        self.0
-    }
-
-    fn cleanup(dir: &str) {
-        if let Err(_) = std::fs::remove_dir_all(dir) {
-            // if dir doesn't exists, ignore
-        }
-    }
-}
-
-impl Drop for Cleaner {
-    fn drop(&mut self) {
-        Self::cleanup(self.dir());
-    }
-}
+use yrs::{Doc, Transact};
+use yrs_kvstore::bench::{load_trace, Cleaner};
+use yrs_kvstore::persist::{persist_on_update_with, PersistConfig};
+use yrs_lmdb::{LmdbDocStore, LmdbEnv};
 
 fn main() {
     let cleaner = Cleaner::new("example-lmdb");
@@ -40,48 +15,48 @@ fn main() {
         .max_dbs(1)
         .open(cleaner.dir(), 0o777)
         .unwrap();
-    let env = Arc::new(env);
-    let handle = Arc::new(env.create_db("test", DbCreate).unwrap());
+    let env = LmdbEnv::new(env);
+    let handle = env.create_db("test", DbCreate).unwrap();
+    let store = LmdbDocStore::new(Arc::new(env), Arc::new(handle));
     let doc_name = "sample-doc";
 
     let doc = Doc::new();
     let text = doc.get_or_insert_text("text");
 
-    // store subsequent updates automatically
-    let _sub = {
-        let env = env.clone();
-        let handle = handle.clone();
-        doc.observe_update_v1(move |_, e| {
-            let txn = env.new_transaction().unwrap();
-            let db = LmdbStore::from(txn.bind(&handle));
-            let i = db.push_update(doc_name, &e.update).unwrap();
-            if i % 128 == 0 {
+    // load document using readonly transaction
+    store.load_doc(doc_name, &mut doc.transact_mut()).unwrap();
+
+    // store subsequent updates automatically, reporting the ones which failed to persist
+    let _sub = persist_on_update_with(
+        &doc,
+        move |name, update| {
+            let i = store.push_update(name, update)?.seq;
+            if i.is_multiple_of(128) {
                 // compact updates into document
-                db.flush_doc(doc_name).unwrap();
+                if let Some(outcome) = store.flush_doc(name)? {
+                    println!(
+                        "flushed {} updates in {}us: {}B -> {}B",
+                        outcome.updates_folded,
+                        outcome.duration.as_micros(),
+                        outcome.bytes_before,
+                        outcome.bytes_after
+                    );
+                }
             }
-            txn.commit().unwrap();
-        })
-        .unwrap()
-    };
-
-    {
-        // load document using readonly transaction
-        let mut txn = doc.transact_mut();
-        let db_txn = env.get_reader().unwrap();
-        let db = LmdbStore::from(db_txn.bind(&handle));
-        db.load_doc(&doc_name, &mut txn).unwrap();
-    }
+            Ok(())
+        },
+        doc_name,
+        |failure| eprintln!("failed to persist update: {}", failure.error),
+        PersistConfig { retry_queue: 64 },
+    )
+    .unwrap();
 
     // execute editing trace
-    let ops = read_input("editing-trace.bin");
+    let ops = load_trace("editing-trace.bin");
     let ops_count = ops.len();
     let now = Instant::now();
     for op in ops.iter() {
-        let mut txn = doc.transact_mut();
-        match op {
-            TextOp::Insert(idx, txt) => text.insert(&mut txn, *idx, txt),
-            TextOp::Delete(idx, len) => text.remove_range(&mut txn, *idx, *len),
-        }
+        op.apply(&text, &mut doc.transact_mut());
     }
     let elapsed = Instant::now().duration_since(now);
     println!(
@@ -90,39 +65,3 @@ fn main() {
         elapsed.as_millis()
     );
 }
-
-enum TextOp {
-    Insert(u32, String),
-    Delete(u32, u32),
-}
-
-fn read_input(fpath: &str) -> Vec<TextOp> {
-    use std::fs::File;
-    use yrs::updates::decoder::DecoderV1;
-
-    let mut f = File::open(fpath).unwrap();
-    let mut buf = Vec::new();
-    std::io::Read::read_to_end(&mut f, &mut buf).unwrap();
-    let mut decoder = DecoderV1::new(Cursor::new(buf.as_slice()));
-    let len: usize = decoder.read_var().unwrap();
-    let mut result = Vec::with_capacity(len);
-    for _ in 0..len {
-        let op = {
-            match decoder.read_var().unwrap() {
-                1u32 => {
-                    let idx = decoder.read_var().unwrap();
-                    let chunk = decoder.read_string().unwrap();
-                    TextOp::Insert(idx, chunk.to_string())
-                }
-                2u32 => {
-                    let idx = decoder.read_var().unwrap();
-                    let len = decoder.read_var().unwrap();
-                    TextOp::Delete(idx, len)
-                }
-                other => panic!("unrecognized TextOp tag type: {}", other),
-            }
-        };
-        result.push(op);
-    }
-    result
-}
@@ -0,0 +1,55 @@
+// Program which has written `data.mdb` and the entries of `yrs-kvstore/fixtures/format-v1.txt`,
+// using the API of yrs-lmdb 0.3.0. It's not built with the development version: to run it again,
+// check out the 0.3.0 release, copy it into `yrs-lmdb/examples` and run
+// `cargo run -p yrs-lmdb --example compat_fixture -- <output directory>`.
+
+use lmdb_rs::core::DbCreate;
+use lmdb_rs::Environment;
+use yrs::{Doc, Options, Text, Transact};
+use yrs_kvstore::{DocOps, KVEntry, KVStore};
+use yrs_lmdb::LmdbStore;
+
+fn doc(client_id: u64) -> Doc {
+    Doc::with_options(Options {
+        client_id,
+        ..Options::default()
+    })
+}
+
+fn main() {
+    let dir = std::env::args().nth(1).expect("missing output directory");
+    let env = Environment::new()
+        .autocreate_dir(true)
+        .max_dbs(4)
+        .open(&dir, 0o777)
+        .unwrap();
+    let h = env.create_db("yrs", DbCreate).unwrap();
+    let txn = env.new_transaction().unwrap();
+    {
+        let db = LmdbStore::from(txn.bind(&h));
+        // compat/pending is written first, so that the last entry of the OID index doesn't hold
+        // the highest OID
+        let d = doc(2);
+        let text = d.get_or_insert_text("text");
+        for chunk in ["abc", "de"] {
+            let mut t = d.transact_mut();
+            text.push(&mut t, chunk);
+            db.push_update("compat/pending", &t.encode_update_v1())
+                .unwrap();
+        }
+        let d = doc(1);
+        let text = d.get_or_insert_text("text");
+        text.push(&mut d.transact_mut(), "hello");
+        db.insert_doc("compat/flushed", &d.transact()).unwrap();
+        db.insert_meta("compat/flushed", "title", b"Flushed")
+            .unwrap();
+    }
+    txn.commit().unwrap();
+
+    let txn = env.new_transaction().unwrap();
+    let db = LmdbStore::from(txn.bind(&h));
+    let hex = |b: &[u8]| b.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    for e in db.iter_range(&[0], &[1, 0xff]).unwrap() {
+        println!("{} {}", hex(e.key()), hex(e.value()));
+    }
+}
@@ -0,0 +1,652 @@
+use crate::{LmdbEnv, LmdbStore};
+use lmdb_rs::DbHandle;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use yrs::block::ClientID;
+use yrs::{Doc, ReadTxn, Snapshot, StateVector, TransactionMut, Update};
+use yrs_kvstore::access::{AccessStats, StaleDoc};
+use yrs_kvstore::audit::AuditRecord;
+use yrs_kvstore::collection::Collection;
+use yrs_kvstore::error::Error;
+use yrs_kvstore::maintenance::{MaintenanceOptions, MaintenanceReport};
+use yrs_kvstore::refs::{InboundRef, RefPolicy};
+use yrs_kvstore::split::SplitPolicy;
+use yrs_kvstore::version::DocVersion;
+use yrs_kvstore::worker::{
+    shutdown_flush, shutdown_flush_pipelined, FlushCandidate, ShutdownReport,
+};
+use yrs_kvstore::{
+    ClearReport, DocOps, FlushOutcome, LoadResult, LoadedDocs, PreparedFlush, PushReceipt,
+    SyncStep2,
+};
+
+/// Metadata entries of a document, as `(key, value)` pairs.
+type MetaEntries = Vec<(Box<[u8]>, Box<[u8]>)>;
+
+/// Convenience wrapper around shared LMDB environment and database handle, exposing [DocOps]
+/// methods directly.
+///
+/// Every method call opens a new transaction - read-only one for read operations and read-write
+/// one for mutations - executes corresponding [DocOps] method and commits it. Write transactions
+/// are executed using [LmdbEnv::with_write_txn], so they are retried whenever memory map has to
+/// grow. This means that **atomicity is guaranteed only within a single method call**: there's no
+/// guarantee that document state read by one call has not been changed by another thread before
+/// the next call. Use [LmdbStore] directly whenever multiple operations must be executed
+/// atomically.
+///
+/// Since results cannot outlive transactions that produced them, all of them are returned as
+/// owned values.
+///
+/// # Example
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use lmdb_rs::core::DbCreate;
+/// use lmdb_rs::Environment;
+/// use yrs::{Doc, ReadTxn, Text, Transact};
+/// use yrs_lmdb::{LmdbDocStore, LmdbEnv};
+///
+/// let env = LmdbEnv::new(Environment::new()
+///     .autocreate_dir(true)
+///     .max_dbs(4)
+///     .open("my-lmdb-dir", 0o777)
+///     .unwrap());
+/// let h = env.create_db("yrs", DbCreate).unwrap();
+/// let store = LmdbDocStore::new(Arc::new(env), Arc::new(h));
+///
+/// let doc = Doc::new();
+/// let text = doc.get_or_insert_text("text");
+/// store.load_doc("my-doc-name", &mut doc.transact_mut()).unwrap();
+///
+/// let sub = doc.observe_update_v1(move |txn, e| {
+///     let seq_nr = store.push_update("my-doc-name", &e.update).unwrap().seq;
+///     store.put_state_vector("my-doc-name", &txn.state_vector()).unwrap();
+///     if seq_nr % 64 == 0 {
+///         store.flush_doc("my-doc-name").unwrap();
+///     }
+/// });
+/// ```
+#[derive(Clone)]
+pub struct LmdbDocStore {
+    env: Arc<LmdbEnv>,
+    db: Arc<DbHandle>,
+}
+
+impl LmdbDocStore {
+    /// Creates a new store operating on a database identified by a given `handle`.
+    pub fn new(env: Arc<LmdbEnv>, handle: Arc<DbHandle>) -> Self {
+        LmdbDocStore { env, db: handle }
+    }
+
+    /// Returns a reference to the underlying environment.
+    pub fn env(&self) -> &Arc<LmdbEnv> {
+        &self.env
+    }
+
+    /// Returns a reference to the database handle used by current store.
+    pub fn handle(&self) -> &Arc<DbHandle> {
+        &self.db
+    }
+
+    fn read<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&LmdbStore) -> Result<R, Error>,
+    {
+        let txn = self.env.get_reader().map_err(Error::other)?;
+        let db = LmdbStore::read_only(txn.bind(&self.db));
+        f(&db)
+    }
+
+    fn write<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnMut(&LmdbStore) -> Result<R, Error>,
+    {
+        self.env.with_write_txn(&self.db, f)
+    }
+
+    /// See [DocOps::insert_doc].
+    pub fn insert_doc<K: AsRef<[u8]> + ?Sized, Txn: ReadTxn>(
+        &self,
+        name: &K,
+        txn: &Txn,
+    ) -> Result<(), Error> {
+        self.write(|db| db.insert_doc(name, txn))
+    }
+
+    /// See [DocOps::insert_doc_raw_v1].
+    pub fn insert_doc_raw_v1(
+        &self,
+        name: &[u8],
+        doc_state_v1: &[u8],
+        doc_sv_v1: &[u8],
+    ) -> Result<(), Error> {
+        self.write(|db| db.insert_doc_raw_v1(name, doc_state_v1, doc_sv_v1))
+    }
+
+    /// See [DocOps::load_doc].
+    pub fn load_doc<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        txn: &mut TransactionMut,
+    ) -> Result<bool, Error> {
+        self.read(|db| db.load_doc(name, txn))
+    }
+
+    /// See [DocOps::load_doc_checked].
+    pub fn load_doc_checked<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        txn: &mut TransactionMut,
+    ) -> Result<LoadResult, Error> {
+        self.read(|db| db.load_doc_checked(name, txn))
+    }
+
+    /// See [DocOps::load_docs].
+    pub fn load_docs<'x, I>(&self, names: I) -> Result<LoadedDocs, Error>
+    where
+        I: IntoIterator<Item = &'x [u8]>,
+    {
+        self.read(|db| db.load_docs(names))
+    }
+
+    /// See [DocOps::with_doc].
+    pub fn with_doc<K, F, R>(&self, name: &K, f: F) -> Result<Option<R>, Error>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        F: FnOnce(&Doc) -> R,
+    {
+        self.read(|db| db.with_doc(name, f))
+    }
+
+    /// See [DocOps::get_root_type_names].
+    pub fn get_root_type_names<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Vec<String>, Error> {
+        self.read(|db| db.get_root_type_names(name))
+    }
+
+    /// See [DocOps::flush_doc].
+    pub fn flush_doc<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<FlushOutcome>, Error> {
+        self.write(|db| db.flush_doc(name))
+    }
+
+    /// See [DocOps::flush_doc_with].
+    pub fn flush_doc_with<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        options: yrs::Options,
+    ) -> Result<Option<FlushOutcome>, Error> {
+        self.write(|db| db.flush_doc_with(name, options.clone()))
+    }
+
+    /// See [DocOps::get_state_vector].
+    pub fn get_state_vector<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<(Option<StateVector>, bool), Error> {
+        self.read(|db| db.get_state_vector(name))
+    }
+
+    /// See [DocOps::doc_version].
+    pub fn doc_version<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<DocVersion>, Error> {
+        self.read(|db| db.doc_version(name))
+    }
+
+    /// See [DocOps::state_vector_force].
+    pub fn state_vector_force<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<StateVector>, Error> {
+        self.write(|db| db.state_vector_force(name))
+    }
+
+    /// See [DocOps::put_state_vector].
+    pub fn put_state_vector<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        sv: &StateVector,
+    ) -> Result<(), Error> {
+        self.write(|db| db.put_state_vector(name, sv))
+    }
+
+    /// See [DocOps::push_update].
+    pub fn push_update<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        update: &[u8],
+    ) -> Result<PushReceipt, Error> {
+        self.write(|db| db.push_update(name, update))
+    }
+
+    /// See [DocOps::push_update_seq].
+    pub fn push_update_seq<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        update: &[u8],
+    ) -> Result<u32, Error> {
+        self.write(|db| db.push_update_seq(name, update))
+    }
+
+    /// See [DocOps::import_updates].
+    pub fn import_updates<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        updates: &[(u32, &[u8])],
+    ) -> Result<(), Error> {
+        self.write(|db| db.import_updates(name, updates))
+    }
+
+    /// See [DocOps::import_updates_with].
+    pub fn import_updates_with<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        updates: &[(u32, &[u8])],
+        flush: Option<yrs::Options>,
+    ) -> Result<Option<FlushOutcome>, Error> {
+        self.write(|db| db.import_updates_with(name, updates, flush.clone()))
+    }
+
+    /// See [DocOps::get_diff].
+    pub fn get_diff<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        sv: &StateVector,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.read(|db| db.get_diff(name, sv))
+    }
+
+    /// See [DocOps::get_diff_with].
+    pub fn get_diff_with<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        sv: &StateVector,
+        options: yrs::Options,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.read(|db| db.get_diff_with(name, sv, options))
+    }
+
+    /// See [DocOps::missing_ranges].
+    pub fn missing_ranges<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Vec<(ClientID, Range<u32>)>, Error> {
+        self.read(|db| db.missing_ranges(name))
+    }
+
+    /// See [DocOps::handle_sync_step1].
+    pub fn handle_sync_step1<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        remote_sv: &[u8],
+    ) -> Result<SyncStep2, Error> {
+        self.read(|db| db.handle_sync_step1(name, remote_sv))
+    }
+
+    /// See [DocOps::cache_sync_frame].
+    pub fn cache_sync_frame<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        remote_sv_hash: u64,
+        frame: &[u8],
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        self.write(|db| db.cache_sync_frame(name, remote_sv_hash, frame, ttl))
+    }
+
+    /// See [DocOps::get_cached_sync_frame].
+    pub fn get_cached_sync_frame<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        remote_sv_hash: u64,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.read(|db| db.get_cached_sync_frame(name, remote_sv_hash))
+    }
+
+    /// See [DocOps::insert_snapshot].
+    pub fn insert_snapshot<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        label: &K2,
+        snapshot: &Snapshot,
+    ) -> Result<(), Error> {
+        self.write(|db| db.insert_snapshot(name, label, snapshot))
+    }
+
+    /// See [DocOps::get_snapshot].
+    pub fn get_snapshot<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        label: &K2,
+    ) -> Result<Option<Snapshot>, Error> {
+        self.read(|db| db.get_snapshot(name, label))
+    }
+
+    /// See [DocOps::remove_snapshot].
+    pub fn remove_snapshot<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        label: &K2,
+    ) -> Result<(), Error> {
+        self.write(|db| db.remove_snapshot(name, label))
+    }
+
+    /// See [DocOps::encode_state_from_snapshot].
+    pub fn encode_state_from_snapshot<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        snapshot: &Snapshot,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.read(|db| db.encode_state_from_snapshot(name, snapshot))
+    }
+
+    /// See [DocOps::clear_doc].
+    pub fn clear_doc<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<ClearReport, Error> {
+        self.write(|db| db.clear_doc(name))
+    }
+
+    /// See [DocOps::clear_doc_with].
+    pub fn clear_doc_with<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        policy: RefPolicy,
+    ) -> Result<ClearReport, Error> {
+        self.write(|db| db.clear_doc_with(name, policy))
+    }
+
+    /// See [DocOps::recover_intents].
+    pub fn recover_intents(&self) -> Result<u32, Error> {
+        self.write(|db| db.recover_intents())
+    }
+
+    /// See [DocOps::maintain].
+    pub fn maintain(&self, options: MaintenanceOptions) -> Result<MaintenanceReport, Error> {
+        self.write(|db| db.maintain(options.clone()))
+    }
+
+    /// See [DocOps::push_split_update].
+    pub fn push_split_update<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        update: &[u8],
+        policy: &SplitPolicy,
+    ) -> Result<(), Error> {
+        self.write(|db| db.push_split_update(name, update, policy))
+    }
+
+    /// See [DocOps::load_split_doc].
+    pub fn load_split_doc<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        policy: &SplitPolicy,
+        roots: &[&str],
+        txn: &mut TransactionMut,
+    ) -> Result<bool, Error> {
+        self.read(|db| db.load_split_doc(name, policy, roots, txn))
+    }
+
+    /// See [DocOps::get_meta].
+    pub fn get_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        meta_key: &K2,
+    ) -> Result<Option<Box<[u8]>>, Error> {
+        self.read(|db| {
+            let value = db.get_meta(name, meta_key)?;
+            Ok(value.map(|v| v.into()))
+        })
+    }
+
+    /// See [DocOps::insert_meta].
+    pub fn insert_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        meta_key: &K2,
+        meta: &[u8],
+    ) -> Result<(), Error> {
+        self.write(|db| db.insert_meta(name, meta_key, meta))
+    }
+
+    /// See [DocOps::remove_meta].
+    pub fn remove_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        meta_key: &K2,
+    ) -> Result<(), Error> {
+        self.write(|db| db.remove_meta(name, meta_key))
+    }
+
+    /// See [DocOps::init_meta_once].
+    pub fn init_meta_once<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        entries: &[(&[u8], &[u8])],
+    ) -> Result<bool, Error> {
+        self.write(|db| db.init_meta_once(name, entries))
+    }
+
+    /// See [DocOps::insert_meta_ref].
+    pub fn insert_meta_ref<K1, K2, K3>(&self, name: &K1, key: &K2, target: &K3) -> Result<(), Error>
+    where
+        K1: AsRef<[u8]> + ?Sized,
+        K2: AsRef<[u8]> + ?Sized,
+        K3: AsRef<[u8]> + ?Sized,
+    {
+        self.write(|db| db.insert_meta_ref(name, key, target))
+    }
+
+    /// See [DocOps::get_meta_ref].
+    pub fn get_meta_ref<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        key: &K2,
+    ) -> Result<Option<Box<[u8]>>, Error> {
+        self.read(|db| {
+            let value = db.get_meta_ref(name, key)?;
+            Ok(value.map(|v| v.as_ref().into()))
+        })
+    }
+
+    /// See [DocOps::remove_meta_ref].
+    pub fn remove_meta_ref<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        key: &K2,
+    ) -> Result<(), Error> {
+        self.write(|db| db.remove_meta_ref(name, key))
+    }
+
+    /// See [DocOps::get_inbound_refs].
+    pub fn get_inbound_refs<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Vec<InboundRef>, Error> {
+        self.read(|db| db.get_inbound_refs(name))
+    }
+
+    /// See [DocOps::iter_docs].
+    pub fn iter_docs(&self) -> Result<Vec<Box<[u8]>>, Error> {
+        self.read(|db| Ok(db.iter_docs()?.collect()))
+    }
+
+    /// See [DocOps::iter_docs_with].
+    pub fn iter_docs_with(&self, include_archived: bool) -> Result<Vec<Box<[u8]>>, Error> {
+        self.read(|db| Ok(db.iter_docs_with(include_archived)?.collect()))
+    }
+
+    /// See [DocOps::iter_docs_prefix].
+    pub fn iter_docs_prefix<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        prefix: &K,
+    ) -> Result<Vec<Box<[u8]>>, Error> {
+        self.read(|db| Ok(db.iter_docs_prefix(prefix)?.collect()))
+    }
+
+    /// See [DocOps::iter_meta].
+    pub fn iter_meta<K: AsRef<[u8]> + ?Sized>(&self, doc_name: &K) -> Result<MetaEntries, Error> {
+        self.read(|db| Ok(db.iter_meta(doc_name)?.collect()))
+    }
+
+    /// See [DocOps::decoded_updates]. All pending updates are decoded before returning.
+    pub fn decoded_updates<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Vec<(u32, Update)>, Error> {
+        self.read(|db| db.decoded_updates(name)?.collect())
+    }
+
+    /// See [DocOps::last_modified].
+    pub fn last_modified<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<SystemTime>, Error> {
+        self.read(|db| db.last_modified(name))
+    }
+
+    /// See [DocOps::iter_audit].
+    pub fn iter_audit<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        time_range: Range<SystemTime>,
+    ) -> Result<Vec<AuditRecord>, Error> {
+        self.read(|db| Ok(db.iter_audit(name, time_range)?.collect()))
+    }
+
+    /// See [DocOps::prune_audit].
+    pub fn prune_audit<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        cutoff: SystemTime,
+    ) -> Result<u32, Error> {
+        self.write(|db| db.prune_audit(name, cutoff))
+    }
+
+    /// See [DocOps::access_stats].
+    pub fn access_stats<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<AccessStats>, Error> {
+        self.read(|db| db.access_stats(name))
+    }
+
+    /// See [DocOps::iter_docs_by_staleness].
+    pub fn iter_docs_by_staleness(&self, limit: usize) -> Result<Vec<StaleDoc>, Error> {
+        self.read(|db| Ok(db.iter_docs_by_staleness(limit)?.collect()))
+    }
+
+    /// See [DocOps::flush_candidates].
+    pub fn flush_candidates(&self) -> Result<Vec<FlushCandidate>, Error> {
+        self.read(|db| Ok(db.flush_candidates()?.collect()))
+    }
+
+    /// See [DocOps::prepare_flush].
+    pub fn prepare_flush<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<PreparedFlush>, Error> {
+        self.read(|db| db.prepare_flush(name))
+    }
+
+    /// See [DocOps::apply_flush].
+    pub fn apply_flush<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        prepared: PreparedFlush,
+    ) -> Result<Option<FlushOutcome>, Error> {
+        let mut prepared = Some(prepared);
+        self.write(|db| match prepared.take() {
+            Some(prepared) => db.apply_flush(name, prepared),
+            // transaction retried after the memory map has grown
+            None => db.flush_doc(name),
+        })
+    }
+
+    /// Flushes documents returned by [Self::flush_candidates] within a given time budget, each
+    /// one in its own transaction. See [shutdown_flush] for details.
+    ///
+    /// LMDB allows a single write transaction at a time, so with `parallelism` greater than 1
+    /// documents are flushed using [shutdown_flush_pipelined]: they are reconstructed by
+    /// `parallelism` threads, while only their writes are serialized.
+    pub fn shutdown_flush(
+        &self,
+        max_duration: Duration,
+        parallelism: usize,
+    ) -> Result<ShutdownReport, Error> {
+        let candidates = self.flush_candidates()?;
+        if parallelism <= 1 {
+            return Ok(shutdown_flush(candidates, max_duration, 1, |name| {
+                self.flush_doc(name)
+            }));
+        }
+        Ok(shutdown_flush_pipelined(
+            candidates,
+            max_duration,
+            parallelism,
+            |name| self.prepare_flush(name),
+            |name, prepared| self.apply_flush(name, prepared),
+        ))
+    }
+
+    /// Flushes all documents returned by [Self::flush_candidates], each one in its own
+    /// transaction, using up to `parallelism` threads. Same as [Self::shutdown_flush] without
+    /// a time budget.
+    pub fn flush_all(&self, parallelism: usize) -> Result<ShutdownReport, Error> {
+        self.shutdown_flush(Duration::MAX, parallelism)
+    }
+
+    /// See [DocOps::create_collection].
+    pub fn create_collection(&self, collection: &Collection) -> Result<bool, Error> {
+        self.write(|db| db.create_collection(collection))
+    }
+
+    /// See [DocOps::collection_exists].
+    pub fn collection_exists(&self, collection: &Collection) -> Result<bool, Error> {
+        self.read(|db| db.collection_exists(collection))
+    }
+
+    /// See [DocOps::iter_collection].
+    pub fn iter_collection(&self, collection: &Collection) -> Result<Vec<Box<[u8]>>, Error> {
+        self.read(|db| db.iter_collection(collection))
+    }
+
+    /// See [DocOps::clear_collection].
+    pub fn clear_collection(&self, collection: &Collection) -> Result<u32, Error> {
+        self.write(|db| db.clear_collection(collection))
+    }
+
+    /// See [DocOps::archive_doc].
+    pub fn archive_doc<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<bool, Error> {
+        self.write(|db| db.archive_doc(name))
+    }
+
+    /// See [DocOps::unarchive_doc].
+    pub fn unarchive_doc<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<bool, Error> {
+        self.write(|db| db.unarchive_doc(name))
+    }
+
+    /// See [DocOps::is_archived].
+    pub fn is_archived<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<bool, Error> {
+        self.read(|db| db.is_archived(name))
+    }
+
+    /// See [DocOps::set_default_max_doc_bytes].
+    pub fn set_default_max_doc_bytes(&self, limit: Option<u64>) -> Result<(), Error> {
+        self.write(|db| db.set_default_max_doc_bytes(limit))
+    }
+
+    /// See [DocOps::set_max_doc_bytes].
+    pub fn set_max_doc_bytes<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        limit: Option<u64>,
+    ) -> Result<(), Error> {
+        self.write(|db| db.set_max_doc_bytes(name, limit))
+    }
+}
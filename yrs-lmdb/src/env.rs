@@ -0,0 +1,278 @@
+use crate::LmdbStore;
+use lmdb_rs::core::{EnvNoMetaSync, EnvNoSync};
+use lmdb_rs::{DbHandle, Environment, MdbError};
+use std::convert::TryFrom;
+use std::ops::Deref;
+use yrs_kvstore::error::Error;
+use yrs_kvstore::{HealthHint, WriteDurability};
+
+/// LMDB error code returned when memory map reached its maximum size.
+const MDB_MAP_FULL: i32 = -30792;
+
+/// Configuration of memory map growth used by [LmdbEnv].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapGrowth {
+    /// Factor by which the current map size is multiplied each time the map becomes full.
+    pub factor: f64,
+    /// Hard cap on the map size in bytes. Once reached, map-full errors are returned to the caller.
+    pub max_size: usize,
+}
+
+impl Default for MapGrowth {
+    fn default() -> Self {
+        MapGrowth {
+            factor: 2.0,
+            max_size: usize::try_from(16u64 << 30).unwrap_or(usize::MAX), // 16GiB
+        }
+    }
+}
+
+/// Wrapper around LMDB [Environment], which automatically grows the memory map when write
+/// transactions fail because the map is full. It also allows to configure [WriteDurability] of
+/// committed transactions.
+///
+/// # Caveats
+///
+/// LMDB allows to resize the memory map only when there are no active transactions in the current
+/// process. [LmdbEnv::with_write_txn] aborts its own transaction before resizing, but it has no
+/// way to know about other transactions (including read-only ones) opened concurrently from other
+/// threads using the same environment. It's up to the caller to make sure these don't overlap with
+/// write transactions executed through this wrapper.
+pub struct LmdbEnv {
+    env: Environment,
+    growth: MapGrowth,
+    durability: WriteDurability,
+}
+
+impl LmdbEnv {
+    /// Wraps a given environment using default [MapGrowth] settings.
+    pub fn new(env: Environment) -> Self {
+        Self::with_growth(env, MapGrowth::default())
+    }
+
+    /// Wraps a given environment using provided [MapGrowth] settings.
+    pub fn with_growth(env: Environment, growth: MapGrowth) -> Self {
+        LmdbEnv {
+            env,
+            growth,
+            durability: WriteDurability::Durable,
+        }
+    }
+
+    /// Returns memory map growth settings used by current environment.
+    pub fn growth(&self) -> &MapGrowth {
+        &self.growth
+    }
+
+    /// Reads current usage of the memory map and reader slots of current environment. Map usage is
+    /// measured against [MapGrowth::max_size], since the map is grown automatically until then.
+    /// [LmdbStore] doesn't report health in push receipts, use [LmdbHealth::hint] to classify it.
+    pub fn health(&self) -> Result<LmdbHealth, Error> {
+        let health = LmdbHealth::read(&self.env)?;
+        Ok(LmdbHealth {
+            max_map_size: self.growth.max_size.max(health.map_size),
+            ..health
+        })
+    }
+
+    /// Returns durability of transactions committed within current environment.
+    pub fn durability(&self) -> WriteDurability {
+        self.durability
+    }
+
+    /// Changes durability of all transactions committed within current environment from now on.
+    /// It's mapped onto LMDB environment flags:
+    ///
+    /// - [WriteDurability::Durable] clears both `MDB_NOSYNC` and `MDB_NOMETASYNC` flags.
+    /// - [WriteDurability::Relaxed] sets `MDB_NOMETASYNC`: meta page is not flushed on commit.
+    /// - [WriteDurability::Volatile] sets `MDB_NOSYNC`: nothing is flushed on commit.
+    ///
+    /// When durability is relaxed, environment is flushed when [LmdbEnv] is dropped.
+    pub fn set_durability(&mut self, durability: WriteDurability) -> Result<(), Error> {
+        match durability {
+            WriteDurability::Durable => {
+                self.env
+                    .set_flags(EnvNoSync | EnvNoMetaSync, false)
+                    .map_err(Error::other)?;
+            }
+            WriteDurability::Relaxed => {
+                self.env.set_flags(EnvNoSync, false).map_err(Error::other)?;
+                self.env
+                    .set_flags(EnvNoMetaSync, true)
+                    .map_err(Error::other)?;
+            }
+            WriteDurability::Volatile => {
+                self.env
+                    .set_flags(EnvNoMetaSync, false)
+                    .map_err(Error::other)?;
+                self.env.set_flags(EnvNoSync, true).map_err(Error::other)?;
+            }
+        }
+        self.durability = durability;
+        Ok(())
+    }
+
+    /// Executes a given function `f` within a new write transaction bound to a database `handle`,
+    /// committing that transaction once function completes successfully.
+    ///
+    /// If either `f` or the commit fails because LMDB memory map is full, the transaction is
+    /// aborted, map is resized according to [MapGrowth] settings and `f` is called again with
+    /// a fresh transaction. Since `f` may be called multiple times, it should not have side
+    /// effects outside of the database transaction.
+    pub fn with_write_txn<F, T>(&self, handle: &DbHandle, mut f: F) -> Result<T, Error>
+    where
+        F: FnMut(&LmdbStore) -> Result<T, Error>,
+    {
+        loop {
+            let txn = self.env.new_transaction().map_err(Error::other)?;
+            let result = {
+                let store = LmdbStore::from(txn.bind(handle));
+                f(&store)
+            };
+            let result = match result {
+                Ok(value) => match txn.commit() {
+                    Ok(()) => Ok(value),
+                    Err(e) => Err(Error::other(e)),
+                },
+                Err(e) => {
+                    txn.abort();
+                    Err(e)
+                }
+            };
+            match result {
+                Err(e) if is_map_full(&e) => {
+                    if !self.grow()? {
+                        return Err(e);
+                    }
+                }
+                other => return other,
+            }
+        }
+    }
+
+    /// Grows the memory map. Returns `false` if map already reached its maximum size.
+    fn grow(&self) -> Result<bool, Error> {
+        let current = self.env.info().map_err(Error::other)?.me_mapsize;
+        if current >= self.growth.max_size {
+            return Ok(false);
+        }
+        let new_size = (current as f64 * self.growth.factor) as usize;
+        let new_size = new_size.max(current + 1).min(self.growth.max_size);
+        self.env.set_mapsize(new_size).map_err(Error::other)?;
+        Ok(true)
+    }
+}
+
+impl From<Environment> for LmdbEnv {
+    #[inline(always)]
+    fn from(env: Environment) -> Self {
+        LmdbEnv::new(env)
+    }
+}
+
+impl Drop for LmdbEnv {
+    fn drop(&mut self) {
+        if self.durability != WriteDurability::Durable {
+            // there's no way to report an error at this point
+            let _ = self.env.sync(true);
+        }
+    }
+}
+
+impl Deref for LmdbEnv {
+    type Target = Environment;
+
+    fn deref(&self) -> &Self::Target {
+        &self.env
+    }
+}
+
+/// Usage of LMDB environment resources, which exhaustion makes transactions fail: space of the
+/// memory map and reader slots. Returned by [LmdbEnv::health].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LmdbHealth {
+    /// Current size of the memory map in bytes.
+    pub map_size: usize,
+    /// Size the memory map is allowed to grow to, in bytes.
+    pub max_map_size: usize,
+    /// Number of bytes of the memory map used so far, up to the last used page.
+    pub map_used: usize,
+    /// Number of reader slots in use.
+    pub readers: u32,
+    /// Total number of reader slots.
+    pub max_readers: u32,
+}
+
+impl LmdbHealth {
+    /// Reads usage of a given environment `env`, which map cannot grow beyond its current size.
+    pub fn read(env: &Environment) -> Result<Self, Error> {
+        let info = env.info().map_err(Error::other)?;
+        let stat = env.stat().map_err(Error::other)?;
+        Ok(LmdbHealth {
+            map_size: info.me_mapsize,
+            max_map_size: info.me_mapsize,
+            map_used: (info.me_last_pgno + 1) * stat.ms_psize as usize,
+            readers: info.me_numreaders,
+            max_readers: info.me_maxreaders,
+        })
+    }
+
+    /// Returns the percentage of [Self::max_map_size] used so far.
+    pub fn map_used_percent(&self) -> f64 {
+        percent(self.map_used as f64, self.max_map_size as f64)
+    }
+
+    /// Returns the percentage of reader slots in use.
+    pub fn reader_saturation(&self) -> f64 {
+        percent(self.readers as f64, self.max_readers as f64)
+    }
+
+    /// Classifies current usage using given `thresholds`. The highest of
+    /// [Self::map_used_percent] and [Self::reader_saturation] is compared against them.
+    pub fn hint(&self, thresholds: &LmdbThresholds) -> HealthHint {
+        let usage = self.map_used_percent().max(self.reader_saturation());
+        if usage >= thresholds.stalled_percent {
+            HealthHint::Stalled
+        } else if usage >= thresholds.degraded_percent {
+            HealthHint::Degraded
+        } else {
+            HealthHint::Ok
+        }
+    }
+}
+
+/// Usage percentages at which [LmdbHealth::hint] reports degraded or stalled health.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LmdbThresholds {
+    /// Usage at which [HealthHint::Degraded] is reported.
+    pub degraded_percent: f64,
+    /// Usage at which [HealthHint::Stalled] is reported.
+    pub stalled_percent: f64,
+}
+
+impl Default for LmdbThresholds {
+    fn default() -> Self {
+        LmdbThresholds {
+            degraded_percent: 80.0,
+            stalled_percent: 95.0,
+        }
+    }
+}
+
+fn percent(used: f64, total: f64) -> f64 {
+    if total == 0.0 {
+        0.0
+    } else {
+        used * 100.0 / total
+    }
+}
+
+fn is_map_full(e: &Error) -> bool {
+    match e {
+        Error::Other(e) => matches!(
+            e.downcast_ref::<MdbError>(),
+            Some(MdbError::Other(MDB_MAP_FULL, _))
+        ),
+        _ => false,
+    }
+}
@@ -8,9 +8,9 @@
 //! use std::sync::Arc;
 //! use lmdb_rs::core::DbCreate;
 //! use lmdb_rs::Environment;
-//! use yrs::{Doc, Text, Transact};
+//! use yrs::{Doc, ReadTxn, Text, Transact};
 //! use yrs_kvstore::DocOps;
-//! use yrs_lmdb::LmdbStore;
+//! use yrs_lmdb::{LmdbReader, LmdbStore};
 //!
 //! let env = Arc::new(Environment::new()
 //!     .autocreate_dir(true)
@@ -25,7 +25,7 @@
 //! // restore document state from DB
 //! {
 //!   let db_txn = env.get_reader().unwrap();
-//!   let db = LmdbStore::from(db_txn.bind(&h));
+//!   let db = LmdbReader::new(&db_txn, &h);
 //!   db.load_doc("my-doc-name", &mut doc.transact_mut()).unwrap();
 //! }
 //!
@@ -44,10 +44,12 @@
 //!   let env = env.clone();
 //!   let h = h.clone();
 //!   let options = doc.options().clone();
-//!   doc.observe_update_v1(move |_,e| {
+//!   doc.observe_update_v1(move |txn,e| {
 //!       let db_txn = env.new_transaction().unwrap();
 //!       let db = LmdbStore::from(db_txn.bind(&h));
-//!       let seq_nr = db.push_update("my-doc-name", &e.update).unwrap();
+//!       let seq_nr = db.push_update("my-doc-name", &e.update).unwrap().seq;
+//!       // keep stored state vector up to date, so that it can be used without loading the doc
+//!       db.put_state_vector("my-doc-name", &txn.state_vector()).unwrap();
 //!       if seq_nr % 64 == 0 {
 //!           // occassinally merge updates into the document state
 //!           db.flush_doc_with("my-doc-name", options.clone()).unwrap();
@@ -60,15 +62,92 @@
 //! text.insert(&mut doc.transact_mut(), 1, "b");
 //! text.insert(&mut doc.transact_mut(), 2, "c");
 //! ```
+//!
+//! Errors can't be returned from within the update observer and unwrapping them there panics
+//! inside of Yrs update notifications. [persist_on_update](yrs_kvstore::persist::persist_on_update)
+//! installs an observer which forwards store errors, together with the updates which failed to
+//! persist, to a callback instead, optionally retrying them on subsequent updates.
+//!
+//! # Growing the memory map
+//!
+//! LMDB memory map has a fixed size, configured when environment is opened. Once the map is full,
+//! every write fails. [LmdbEnv] can be used to wrap the environment and execute write transactions
+//! that automatically grow the map and retry when that happens:
+//!
+//! ```rust
+//! use lmdb_rs::core::DbCreate;
+//! use lmdb_rs::Environment;
+//! use yrs_kvstore::DocOps;
+//! use yrs_lmdb::LmdbEnv;
+//!
+//! let env = LmdbEnv::new(Environment::new()
+//!     .autocreate_dir(true)
+//!     .max_dbs(4)
+//!     .open("my-lmdb-dir", 0o777)
+//!     .unwrap());
+//! let h = env.create_db("yrs", DbCreate).unwrap();
+//! env.with_write_txn(&h, |db| db.push_update("my-doc-name", &[0, 0])).unwrap();
+//! ```
+//!
+//! # Creating documents
+//!
+//! By default [DocOps::push_update] creates documents which don't exist yet, so a typo in
+//! a document name silently creates a new, empty document. This can be prevented by configuring
+//! a different [CreatePolicy](yrs_kvstore::config::CreatePolicy):
+//!
+//! ```rust
+//! use lmdb_rs::core::DbCreate;
+//! use lmdb_rs::Environment;
+//! use yrs_kvstore::config::{ConfiguredStore, CreatePolicy, StoreConfig};
+//! use yrs_kvstore::error::Error;
+//! use yrs_kvstore::DocOps;
+//! use yrs_lmdb::LmdbStore;
+//!
+//! let env = Environment::new()
+//!     .autocreate_dir(true)
+//!     .max_dbs(4)
+//!     .open("my-lmdb-dir", 0o777)
+//!     .unwrap();
+//! let h = env.create_db("yrs", DbCreate).unwrap();
+//! let config = StoreConfig {
+//!     create_policy: CreatePolicy::RequireExisting,
+//!     ..StoreConfig::DEFAULT
+//! };
+//! let db_txn = env.new_transaction().unwrap();
+//! let db = ConfiguredStore::new(LmdbStore::from(db_txn.bind(&h)), config);
+//! // documents must be created explicitly i.e. with `insert_doc`
+//! let res = db.push_update("my-doc-nmae", &[0, 0]);
+//! assert!(matches!(res, Err(Error::DocNotFound)));
+//! ```
+//!
+//! For simple use cases, where every operation is meant to be executed in its own transaction,
+//! [LmdbDocStore] can be used instead.
 
 use lmdb_rs::core::{CursorIterator, MdbResult};
-use lmdb_rs::{CursorKeyRangeIter, Database, MdbError, ReadonlyTransaction};
-use std::ops::Deref;
+use lmdb_rs::{CursorKeyRangeIter, Database, DbHandle, MdbError, ReadonlyTransaction};
+use std::ops::{Deref, Range};
+use yrs::block::ClientID;
+use yrs::{Doc, Snapshot, StateVector, TransactionMut};
+
+mod doc_store;
+mod env;
 
+pub use doc_store::LmdbDocStore;
+pub use env::{LmdbEnv, LmdbHealth, LmdbThresholds, MapGrowth};
 pub use yrs_kvstore as store;
+use yrs_kvstore::access::{AccessStats, StaleDoc};
+use yrs_kvstore::capabilities::Capabilities;
+use yrs_kvstore::collection::Collection;
 use yrs_kvstore::error::Error;
 use yrs_kvstore::keys::Key;
-use yrs_kvstore::{DocOps, KVEntry, KVStore};
+use yrs_kvstore::namespace::NamespacedStore;
+pub use yrs_kvstore::prelude;
+use yrs_kvstore::refs::InboundRef;
+use yrs_kvstore::split::SplitPolicy;
+use yrs_kvstore::{
+    DocOps, DocsNameIter, KVEntry, KVStore, LoadResult, LoadedDocs, MetadataIter, ReadIsolation,
+    SyncStep2,
+};
 
 trait OptionalNotFound {
     type Return;
@@ -79,28 +158,44 @@ trait OptionalNotFound {
 
 impl<T> OptionalNotFound for MdbResult<T> {
     type Return = T;
-    type Error = MdbError;
+    type Error = Error;
 
-    /// Changes [MdbError::NotFound] onto [None] case.
+    /// Changes [MdbError::NotFound] onto [None] case and wraps remaining errors into
+    /// [Error::Other].
     fn optional(self) -> Result<Option<Self::Return>, Self::Error> {
         match self {
             Ok(value) => Ok(Some(value)),
             Err(MdbError::NotFound) => Ok(None),
-            Err(err) => Err(err),
+            Err(err) => Err(Error::other(err)),
         }
     }
 }
 
 /// Type wrapper around LMDB's [Database] struct. Used to extend LMDB transactions with [DocOps]
 /// methods used for convenience when working with Yrs documents.
-#[repr(transparent)]
 #[derive(Debug)]
-pub struct LmdbStore<'db>(Database<'db>);
+pub struct LmdbStore<'db>(Database<'db>, bool); // second field is set for read-only stores
+
+impl<'db> LmdbStore<'db> {
+    /// Wraps a given database `db`, making it operate on a logical store identified by a given
+    /// `namespace`, isolated from other namespaces of the same database. See
+    /// [namespace](yrs_kvstore::namespace) for details.
+    pub fn with_namespace(db: Database<'db>, namespace: u16) -> NamespacedStore<Self> {
+        NamespacedStore::new(LmdbStore(db, false), namespace)
+    }
+
+    /// Wraps a given database `db` bound to a read-only transaction. LMDB rejects all writes made
+    /// through it, so [DocOps] methods which write only opportunistically skip their writes (see
+    /// [KVStore::is_read_only]). Use [LmdbReader] to rule out other writes at compile time.
+    pub fn read_only(db: Database<'db>) -> Self {
+        LmdbStore(db, true)
+    }
+}
 
 impl<'db> From<Database<'db>> for LmdbStore<'db> {
     #[inline(always)]
     fn from(db: Database<'db>) -> Self {
-        LmdbStore(db)
+        LmdbStore(db, false)
     }
 }
 
@@ -122,59 +217,340 @@ impl<'db> Deref for LmdbStore<'db> {
 impl<'db> DocOps<'db> for LmdbStore<'db> {}
 
 impl<'db> KVStore<'db> for LmdbStore<'db> {
-    type Error = MdbError;
+    type Error = Error;
     type Cursor = LmdbRange<'db>;
     type Entry = LmdbEntry<'db>;
     type Return = &'db [u8];
 
+    const CAPABILITIES: Capabilities = Capabilities::BOUNDED_PEEK_BACK
+        .union(Capabilities::REVERSE_ITER)
+        .union(Capabilities::SNAPSHOTS);
+
     fn get(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
         let value = self.0.get(&key).optional()?;
         Ok(value)
     }
 
     fn upsert(&self, key: &[u8], value: &[u8]) -> Result<(), Self::Error> {
-        self.0.set(&key, &value)?;
+        self.0.set(&key, &value).map_err(Error::other)?;
         Ok(())
     }
 
     fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
         let prev: Option<&[u8]> = self.0.get(&key).optional()?;
         if prev.is_some() {
-            self.0.del(&key)?;
+            self.0.del(&key).map_err(Error::other)?;
         }
         Ok(())
     }
 
-    fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<(), Self::Error> {
-        let mut c = self.0.new_cursor()?;
+    fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<u32, Self::Error> {
+        let mut c = self.0.new_cursor().map_err(Error::other)?;
+        let mut removed = 0;
         if c.to_gte_key(&from).optional()?.is_some() {
-            while c.get_key::<&[u8]>()? <= to {
-                c.del()?;
+            while c.get_key::<&[u8]>().map_err(Error::other)? <= to {
+                c.del().map_err(Error::other)?;
+                removed += 1;
                 if c.to_next_key().optional()?.is_none() {
                     break;
                 }
             }
         }
-        Ok(())
+        Ok(removed)
     }
 
     fn iter_range(&self, from: &[u8], to: &[u8]) -> Result<Self::Cursor, Self::Error> {
         let from = from.to_vec();
         let to = to.to_vec();
-        let cursor = unsafe { std::mem::transmute(self.0.keyrange(&from, &to)?) };
+        let cursor =
+            unsafe { std::mem::transmute(self.0.keyrange(&from, &to).map_err(Error::other)?) };
         Ok(LmdbRange { from, to, cursor })
     }
 
+    fn iter_range_rev(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        f: &mut dyn FnMut(&[u8], &[u8]) -> bool,
+    ) -> Result<(), Self::Error> {
+        let mut cursor = self.0.new_cursor().map_err(Error::other)?;
+        let mut positioned = match cursor.to_gte_key(&to).optional()? {
+            // first entry at or after the upper bound: step back unless it's within the range
+            Some(_) if cursor.get_key::<&[u8]>().map_err(Error::other)? == to => Some(()),
+            Some(_) => cursor.to_prev_key().optional()?,
+            None => cursor.to_last().optional()?,
+        };
+        while positioned.is_some() {
+            let key: &[u8] = cursor.get_key().map_err(Error::other)?;
+            if key < from {
+                break;
+            }
+            let value: &[u8] = cursor.get_value().map_err(Error::other)?;
+            if !f(key, value) {
+                break;
+            }
+            positioned = cursor.to_prev_key().optional()?;
+        }
+        Ok(())
+    }
+
     fn peek_back(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
-        let mut cursor = self.0.new_cursor()?;
-        cursor.to_gte_key(&key).optional()?;
-        if cursor.to_prev_key().optional()?.is_none() {
+        let mut cursor = self.0.new_cursor().map_err(Error::other)?;
+        let positioned = match cursor.to_gte_key(&key).optional()? {
+            // first entry at or after the key: step back unless it's an exact match
+            Some(_) if cursor.get_key::<&[u8]>().map_err(Error::other)? == key => Some(()),
+            Some(_) => cursor.to_prev_key().optional()?,
+            // all entries are prior to the key
+            None => cursor.to_last().optional()?,
+        };
+        if positioned.is_none() {
+            return Ok(None);
+        }
+        let key = cursor.get_key().map_err(Error::other)?;
+        let value = cursor.get_value().map_err(Error::other)?;
+        Ok(Some(LmdbEntry::new(key, value)))
+    }
+
+    fn seek(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+        let mut cursor = self.0.new_cursor().map_err(Error::other)?;
+        if cursor.to_gte_key(&key).optional()?.is_none() {
             return Ok(None);
         }
-        let key = cursor.get_key()?;
-        let value = cursor.get_value()?;
+        let key = cursor.get_key().map_err(Error::other)?;
+        let value = cursor.get_value().map_err(Error::other)?;
         Ok(Some(LmdbEntry::new(key, value)))
     }
+
+    fn read_isolation(&self) -> ReadIsolation {
+        // LMDB transactions always read from a snapshot taken when they have begun
+        ReadIsolation::Snapshot
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.1
+    }
+}
+
+/// Read-only counterpart of [LmdbStore], bound to LMDB [ReadonlyTransaction]. It exposes only
+/// the subset of [DocOps] methods, which don't require write capabilities from the database
+/// transaction, so that any attempt to modify the store through a reader is a compile time error:
+///
+/// ```compile_fail
+/// use yrs_lmdb::LmdbReader;
+///
+/// let db_txn = env.get_reader().unwrap();
+/// let db = LmdbReader::new(&db_txn, &h);
+/// db.push_update("my-doc-name", &update).unwrap(); // no such method
+/// ```
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct LmdbReader<'db>(LmdbStore<'db>);
+
+impl<'db> LmdbReader<'db> {
+    /// Binds a new reader to a given database `handle` within read-only transaction `txn`.
+    pub fn new(txn: &'db ReadonlyTransaction<'_>, handle: &'db DbHandle) -> Self {
+        LmdbReader(LmdbStore::read_only(txn.bind(handle)))
+    }
+
+    /// See [DocOps::load_doc].
+    pub fn load_doc<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        txn: &mut TransactionMut,
+    ) -> Result<bool, Error> {
+        self.0.load_doc(name, txn)
+    }
+
+    /// See [DocOps::load_doc_checked].
+    pub fn load_doc_checked<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        txn: &mut TransactionMut,
+    ) -> Result<LoadResult, Error> {
+        self.0.load_doc_checked(name, txn)
+    }
+
+    /// See [DocOps::load_split_doc].
+    pub fn load_split_doc<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        policy: &SplitPolicy,
+        roots: &[&str],
+        txn: &mut TransactionMut,
+    ) -> Result<bool, Error> {
+        self.0.load_split_doc(name, policy, roots, txn)
+    }
+
+    /// See [DocOps::load_docs].
+    pub fn load_docs<'x, I>(&self, names: I) -> Result<LoadedDocs, Error>
+    where
+        I: IntoIterator<Item = &'x [u8]>,
+    {
+        self.0.load_docs(names)
+    }
+
+    /// See [DocOps::with_doc].
+    pub fn with_doc<K, F, R>(&self, name: &K, f: F) -> Result<Option<R>, Error>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        F: FnOnce(&Doc) -> R,
+    {
+        self.0.with_doc(name, f)
+    }
+
+    /// See [DocOps::get_root_type_names].
+    pub fn get_root_type_names<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Vec<String>, Error> {
+        self.0.get_root_type_names(name)
+    }
+
+    /// See [DocOps::get_state_vector].
+    pub fn get_state_vector<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<(Option<StateVector>, bool), Error> {
+        self.0.get_state_vector(name)
+    }
+
+    /// See [DocOps::get_diff].
+    pub fn get_diff<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        sv: &StateVector,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.0.get_diff(name, sv)
+    }
+
+    /// See [DocOps::get_diff_with].
+    pub fn get_diff_with<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        sv: &StateVector,
+        options: yrs::Options,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.0.get_diff_with(name, sv, options)
+    }
+
+    /// See [DocOps::missing_ranges].
+    pub fn missing_ranges<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Vec<(ClientID, Range<u32>)>, Error> {
+        self.0.missing_ranges(name)
+    }
+
+    /// See [DocOps::handle_sync_step1].
+    pub fn handle_sync_step1<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        remote_sv: &[u8],
+    ) -> Result<SyncStep2, Error> {
+        self.0.handle_sync_step1(name, remote_sv)
+    }
+
+    /// See [DocOps::get_cached_sync_frame].
+    pub fn get_cached_sync_frame<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        remote_sv_hash: u64,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.0.get_cached_sync_frame(name, remote_sv_hash)
+    }
+
+    /// See [DocOps::get_snapshot].
+    pub fn get_snapshot<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        label: &K2,
+    ) -> Result<Option<Snapshot>, Error> {
+        self.0.get_snapshot(name, label)
+    }
+
+    /// See [DocOps::encode_state_from_snapshot].
+    pub fn encode_state_from_snapshot<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+        snapshot: &Snapshot,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.0.encode_state_from_snapshot(name, snapshot)
+    }
+
+    /// See [DocOps::get_meta].
+    pub fn get_meta<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        meta_key: &K2,
+    ) -> Result<Option<&'db [u8]>, Error> {
+        self.0.get_meta(name, meta_key)
+    }
+
+    /// See [DocOps::get_meta_ref].
+    pub fn get_meta_ref<K1: AsRef<[u8]> + ?Sized, K2: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K1,
+        key: &K2,
+    ) -> Result<Option<&'db [u8]>, Error> {
+        self.0.get_meta_ref(name, key)
+    }
+
+    /// See [DocOps::get_inbound_refs].
+    pub fn get_inbound_refs<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Vec<InboundRef>, Error> {
+        self.0.get_inbound_refs(name)
+    }
+
+    /// See [DocOps::iter_docs].
+    pub fn iter_docs(&self) -> Result<DocsNameIter<LmdbRange<'db>, LmdbEntry<'db>>, Error> {
+        self.0.iter_docs()
+    }
+
+    /// See [DocOps::iter_docs_with].
+    pub fn iter_docs_with(
+        &self,
+        include_archived: bool,
+    ) -> Result<DocsNameIter<LmdbRange<'db>, LmdbEntry<'db>>, Error> {
+        self.0.iter_docs_with(include_archived)
+    }
+
+    /// See [DocOps::iter_meta].
+    pub fn iter_meta<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        doc_name: &K,
+    ) -> Result<MetadataIter<LmdbRange<'db>, LmdbEntry<'db>>, Error> {
+        self.0.iter_meta(doc_name)
+    }
+
+    /// See [DocOps::collection_exists].
+    pub fn collection_exists(&self, collection: &Collection) -> Result<bool, Error> {
+        self.0.collection_exists(collection)
+    }
+
+    /// See [DocOps::iter_collection].
+    pub fn iter_collection(&self, collection: &Collection) -> Result<Vec<Box<[u8]>>, Error> {
+        self.0.iter_collection(collection)
+    }
+
+    /// See [DocOps::is_archived].
+    pub fn is_archived<K: AsRef<[u8]> + ?Sized>(&self, name: &K) -> Result<bool, Error> {
+        self.0.is_archived(name)
+    }
+
+    /// See [DocOps::access_stats].
+    pub fn access_stats<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &K,
+    ) -> Result<Option<AccessStats>, Error> {
+        self.0.access_stats(name)
+    }
+
+    /// See [DocOps::iter_docs_by_staleness].
+    pub fn iter_docs_by_staleness(&self, limit: usize) -> Result<Vec<StaleDoc>, Error> {
+        Ok(self.0.iter_docs_by_staleness(limit)?.collect())
+    }
 }
 
 pub struct LmdbRange<'a> {
@@ -229,7 +605,8 @@ impl<'a> OwnedCursorRange<'a> {
     ) -> Result<Self, Error> {
         let start = start.into();
         let end = end.into();
-        let cursor = unsafe { std::mem::transmute(db.keyrange(&start, &end)?) };
+        let cursor =
+            unsafe { std::mem::transmute(db.keyrange(&start, &end).map_err(Error::other)?) };
 
         Ok(OwnedCursorRange {
             txn,
@@ -256,13 +633,26 @@ impl<'a> Iterator for OwnedCursorRange<'a> {
 
 #[cfg(test)]
 mod test {
-    use crate::{DocOps, LmdbStore};
+    use crate::{DocOps, LmdbDocStore, LmdbEnv, LmdbReader, LmdbStore, MapGrowth};
     use lmdb_rs::core::DbCreate;
-    use lmdb_rs::Environment;
+    use lmdb_rs::{DbHandle, Environment};
+    use std::io;
     use std::path::Path;
-    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
     use tempdir::TempDir;
-    use yrs::{Doc, GetString, ReadTxn, Text, Transact};
+    use yrs::{Doc, GetString, ReadTxn, StateVector, Text, Transact};
+    use yrs_kvstore::config::{ConfiguredStore, StoreConfig};
+    use yrs_kvstore::conformance;
+    use yrs_kvstore::conformance::allocations;
+    use yrs_kvstore::error::Error;
+    use yrs_kvstore::keys::{
+        key_doc, key_namespace, key_namespace_end, key_state_vector, parse_key, split_namespace,
+        ParsedKey,
+    };
+    use yrs_kvstore::persist::{persist_on_update_with, PersistConfig, PersistFailure};
+    use yrs_kvstore::{KVEntry, KVStore, PushReceipt, WriteDurability};
 
     fn init_env<P: AsRef<Path>>(dir: P) -> Environment {
         let env = Environment::new()
@@ -274,22 +664,214 @@ mod test {
     }
 
     #[test]
-    fn create_get_remove() {
-        let dir = TempDir::new("lmdb-create_get_remove").unwrap();
+    fn reader_read_ops() {
+        const DOC_NAME: &str = "doc";
+        let dir = TempDir::new("lmdb-reader_read_ops").unwrap();
         let env = init_env(&dir);
         let h = env.create_db("yrs", DbCreate).unwrap();
 
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        {
+            let db_txn = env.new_transaction().unwrap();
+            let db = LmdbStore::from(db_txn.bind(&h));
+            text.push(&mut doc.transact_mut(), "hello");
+            db.insert_doc(DOC_NAME, &doc.transact()).unwrap();
+            let sv = doc.transact().state_vector();
+            text.push(&mut doc.transact_mut(), " world");
+            let update = doc.transact().encode_diff_v1(&sv);
+            db.push_update(DOC_NAME, &update).unwrap();
+            db.insert_meta(DOC_NAME, "key", "value".as_bytes()).unwrap();
+            db_txn.commit().unwrap();
+        }
+
+        {
+            let db_txn = env.get_reader().unwrap();
+            let db = LmdbReader::new(&db_txn, &h);
+
+            let loaded = Doc::new();
+            let loaded_text = loaded.get_or_insert_text("text");
+            assert!(db.load_doc(DOC_NAME, &mut loaded.transact_mut()).unwrap());
+            assert_eq!(loaded_text.get_string(&loaded.transact()), "hello world");
+
+            let (sv, completed) = db.get_state_vector(DOC_NAME).unwrap();
+            assert!(sv.is_some());
+            assert!(!completed);
+
+            let diff = db.get_diff(DOC_NAME, &StateVector::default()).unwrap();
+            assert!(diff.is_some());
+
+            assert_eq!(
+                db.get_meta(DOC_NAME, "key").unwrap(),
+                Some("value".as_bytes())
+            );
+            let meta: Vec<_> = db.iter_meta(DOC_NAME).unwrap().collect();
+            assert_eq!(
+                meta,
+                vec![("key".as_bytes().into(), "value".as_bytes().into())]
+            );
+
+            let docs: Vec<_> = db.iter_docs().unwrap().collect();
+            assert_eq!(docs, vec![DOC_NAME.as_bytes().into()]);
+            assert!(!db.is_archived(DOC_NAME).unwrap());
+
+            // reads of unknown documents must not allocate new entries
+            assert_eq!(db.get_meta("unknown", "key").unwrap(), None);
+            assert!(!db
+                .load_doc("unknown", &mut Doc::new().transact_mut())
+                .unwrap());
+            // unknown documents have no pending updates their state vector could miss
+            assert_eq!(db.get_state_vector("unknown").unwrap(), (None, true));
+            assert!(db.iter_meta("unknown").unwrap().next().is_none());
+        }
+
+        let db_txn = env.get_reader().unwrap();
+        let db = LmdbReader::new(&db_txn, &h);
+        let docs: Vec<_> = db.iter_docs().unwrap().collect();
+        assert_eq!(docs, vec![DOC_NAME.as_bytes().into()]);
+    }
+
+    #[test]
+    fn reader_writes_rejected() {
+        const DOC_NAME: &str = "doc";
+        let dir = TempDir::new("lmdb-reader_writes_rejected").unwrap();
+        let env = init_env(&dir);
+        let h = env.create_db("yrs", DbCreate).unwrap();
+
+        // LmdbReader doesn't expose write methods at all, but even when read-only transaction
+        // is wrapped with LmdbStore directly, all writes are rejected by LMDB
+        let db_txn = env.get_reader().unwrap();
+        let db = LmdbStore::from(db_txn.bind(&h));
+        assert!(db.push_update(DOC_NAME, &[0, 0]).is_err());
+        assert!(db.insert_meta(DOC_NAME, "key", "value".as_bytes()).is_err());
+        assert!(db.iter_docs().unwrap().next().is_none());
+    }
+
+    #[test]
+    fn env_map_growth() {
+        const DOC_NAME: &str = "doc";
+        const INITIAL_MAP_SIZE: u64 = 64 * 1024;
+        let dir = TempDir::new("lmdb-env_map_growth").unwrap();
+        let env = Environment::new()
+            .autocreate_dir(true)
+            .max_dbs(4)
+            .map_size(INITIAL_MAP_SIZE)
+            .open(&dir, 0o777)
+            .unwrap();
+        let env = LmdbEnv::with_growth(
+            env,
+            MapGrowth {
+                factor: 2.0,
+                max_size: 64 * 1024 * 1024,
+            },
+        );
+        let h = env.create_db("yrs", DbCreate).unwrap();
+
+        // push updates way bigger than initial map size
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        let chunk = "x".repeat(16 * 1024);
+        for _ in 0..32 {
+            let sv = doc.transact().state_vector();
+            text.push(&mut doc.transact_mut(), &chunk);
+            let update = doc.transact().encode_diff_v1(&sv);
+            env.with_write_txn(&h, |db| db.push_update(DOC_NAME, &update))
+                .unwrap();
+        }
+        assert!(env.info().unwrap().me_mapsize as u64 > INITIAL_MAP_SIZE);
+
+        let db_txn = env.get_reader().unwrap();
+        let db = LmdbReader::new(&db_txn, &h);
+        let loaded = Doc::new();
+        let loaded_text = loaded.get_or_insert_text("text");
+        db.load_doc(DOC_NAME, &mut loaded.transact_mut()).unwrap();
+        assert_eq!(
+            loaded_text.get_string(&loaded.transact()).len(),
+            32 * chunk.len()
+        );
+    }
+
+    #[test]
+    fn env_map_growth_cap() {
+        const MAP_SIZE: u64 = 64 * 1024;
+        let dir = TempDir::new("lmdb-env_map_growth_cap").unwrap();
+        let env = Environment::new()
+            .autocreate_dir(true)
+            .max_dbs(4)
+            .map_size(MAP_SIZE)
+            .open(&dir, 0o777)
+            .unwrap();
+        let env = LmdbEnv::with_growth(
+            env,
+            MapGrowth {
+                factor: 2.0,
+                max_size: MAP_SIZE as usize,
+            },
+        );
+        let h = env.create_db("yrs", DbCreate).unwrap();
+
+        // map is not allowed to grow, so writes must eventually fail
+        let update = vec![0u8; 16 * 1024];
+        let res = (0..32).try_for_each(|_| {
+            env.with_write_txn(&h, |db| db.push_update("doc", &update))
+                .map(|_| ())
+        });
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn write_durability() {
+        const DOC_NAME: &str = "doc";
+        let dir = TempDir::new("lmdb-write_durability").unwrap();
+
+        for durability in [
+            WriteDurability::Durable,
+            WriteDurability::Relaxed,
+            WriteDurability::Volatile,
+        ] {
+            let doc_name = format!("{}-{:?}", DOC_NAME, durability);
+            {
+                let mut env = LmdbEnv::new(init_env(&dir));
+                env.set_durability(durability).unwrap();
+                assert_eq!(env.durability(), durability);
+                let h = env.create_db("yrs", DbCreate).unwrap();
+                let doc = Doc::new();
+                let text = doc.get_or_insert_text("text");
+                text.push(&mut doc.transact_mut(), "hello");
+                env.with_write_txn(&h, |db| db.insert_doc(&doc_name, &doc.transact()))
+                    .unwrap();
+            }
+
+            // data must be readable after clean close
+            let env = init_env(&dir);
+            let h = env.create_db("yrs", DbCreate).unwrap();
+            let db_txn = env.get_reader().unwrap();
+            let db = LmdbReader::new(&db_txn, &h);
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            assert!(db.load_doc(&doc_name, &mut doc.transact_mut()).unwrap());
+            assert_eq!(text.get_string(&doc.transact()), "hello");
+        }
+    }
+
+    fn init_doc_store<P: AsRef<Path>>(dir: P) -> LmdbDocStore {
+        let env = LmdbEnv::new(init_env(dir));
+        let h = env.create_db("yrs", DbCreate).unwrap();
+        LmdbDocStore::new(Arc::new(env), Arc::new(h))
+    }
+
+    #[test]
+    fn doc_store_create_get_remove() {
+        let dir = TempDir::new("lmdb-doc_store_create_get_remove").unwrap();
+        let store = init_doc_store(&dir);
+
         // insert document
         {
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
             let mut txn = doc.transact_mut();
             text.insert(&mut txn, 0, "hello");
-
-            let db_txn = env.new_transaction().unwrap();
-            let db = LmdbStore::from(db_txn.bind(&h));
-            db.insert_doc("doc", &txn).unwrap();
-            db_txn.commit().unwrap();
+            store.insert_doc("doc", &txn).unwrap();
         }
 
         // retrieve document
@@ -297,354 +879,1196 @@ mod test {
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
             let mut txn = doc.transact_mut();
-            let db_txn = env.get_reader().unwrap();
-            let db = LmdbStore::from(db_txn.bind(&h));
-            db.load_doc("doc", &mut txn).unwrap();
+            store.load_doc("doc", &mut txn).unwrap();
 
             assert_eq!(text.get_string(&txn), "hello");
 
-            let (sv, completed) = db.get_state_vector("doc").unwrap();
+            let (sv, completed) = store.get_state_vector("doc").unwrap();
             assert_eq!(sv, Some(txn.state_vector()));
             assert!(completed);
         }
 
         // remove document
         {
-            let db_txn = env.new_transaction().unwrap();
-            let db = LmdbStore::from(db_txn.bind(&h));
-
-            db.clear_doc("doc").unwrap();
+            let report = store.clear_doc("doc").unwrap();
+            assert!(report.doc_state_removed);
+            assert!(report.bytes_removed > 0);
 
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
             let mut txn = doc.transact_mut();
-            db.load_doc("doc", &mut txn).unwrap();
+            store.load_doc("doc", &mut txn).unwrap();
 
             assert_eq!(text.get_string(&txn), "");
 
-            let (sv, completed) = db.get_state_vector("doc").unwrap();
+            let (sv, completed) = store.get_state_vector("doc").unwrap();
             assert!(sv.is_none());
             assert!(completed);
         }
     }
+
     #[test]
-    fn multi_insert() {
-        let dir = TempDir::new("lmdb-multi_insert").unwrap();
-        let env = init_env(&dir);
-        let h = env.create_db("yrs", DbCreate).unwrap();
+    fn doc_store_meta() {
+        const DOC_NAME: &str = "doc";
+        let dir = TempDir::new("lmdb-doc_store_meta").unwrap();
+        let store = init_doc_store(&dir);
 
-        // insert document twice
-        {
+        assert!(store.get_meta(DOC_NAME, "key").unwrap().is_none());
+        store
+            .insert_meta(DOC_NAME, "key", "value1".as_bytes())
+            .unwrap();
+        let value = store.get_meta(DOC_NAME, "key").unwrap();
+        assert_eq!(value.as_deref(), Some("value1".as_bytes()));
+
+        store.insert_meta(DOC_NAME, "key2", [2].as_ref()).unwrap();
+        let meta = store.iter_meta(DOC_NAME).unwrap();
+        assert_eq!(
+            meta,
+            vec![
+                ("key".as_bytes().into(), "value1".as_bytes().into()),
+                ("key2".as_bytes().into(), [2].into()),
+            ]
+        );
+
+        store.remove_meta(DOC_NAME, "key").unwrap();
+        assert!(store.get_meta(DOC_NAME, "key").unwrap().is_none());
+        assert_eq!(store.iter_docs().unwrap(), vec![DOC_NAME.as_bytes().into()]);
+    }
+
+    #[test]
+    fn doc_store_concurrent() {
+        const THREADS: usize = 4;
+        const UPDATES: usize = 50;
+        let dir = TempDir::new("lmdb-doc_store_concurrent").unwrap();
+        let store = init_doc_store(&dir);
+
+        let threads: Vec<_> = (0..THREADS)
+            .map(|i| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    let doc_name = format!("doc-{}", i);
+                    let doc = Doc::new();
+                    let text = doc.get_or_insert_text("text");
+                    for j in 0..UPDATES {
+                        let sv = doc.transact().state_vector();
+                        text.push(&mut doc.transact_mut(), "a");
+                        let update = doc.transact().encode_diff_v1(&sv);
+                        let seq_nr = store.push_update(&doc_name, &update).unwrap().seq;
+                        if seq_nr.is_multiple_of(16) {
+                            store.flush_doc(&doc_name).unwrap();
+                        }
+
+                        // read back state persisted so far
+                        let loaded = Doc::new();
+                        let loaded_text = loaded.get_or_insert_text("text");
+                        let mut txn = loaded.transact_mut();
+                        store.load_doc(&doc_name, &mut txn).unwrap();
+                        assert_eq!(loaded_text.get_string(&txn).len(), j + 1);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut docs = store.iter_docs().unwrap();
+        docs.sort();
+        let expected: Vec<Box<[u8]>> = (0..THREADS)
+            .map(|i| format!("doc-{}", i).into_bytes().into())
+            .collect();
+        assert_eq!(docs, expected);
+        for name in expected.iter() {
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
             let mut txn = doc.transact_mut();
-            text.push(&mut txn, "hello");
+            store.load_doc(name, &mut txn).unwrap();
+            assert_eq!(text.get_string(&txn), "a".repeat(UPDATES));
+        }
+    }
 
+    #[test]
+    fn load_docs() {
+        let dir = TempDir::new("lmdb-load_docs").unwrap();
+        let env = init_env(&dir);
+        let h = env.create_db("yrs", DbCreate).unwrap();
+
+        {
             let db_txn = env.new_transaction().unwrap();
-            let db = LmdbStore::from(db_txn.bind(&h));
+            {
+                let db = LmdbStore::from(db_txn.bind(&h));
+                for name in ["doc-1", "doc-2"] {
+                    let doc = Doc::new();
+                    let text = doc.get_or_insert_text("text");
+                    text.push(&mut doc.transact_mut(), name);
+                    db.insert_doc(name, &doc.transact()).unwrap();
+                }
+                // documents with pending updates only are loaded too
+                let doc = Doc::new();
+                let text = doc.get_or_insert_text("text");
+                text.push(&mut doc.transact_mut(), "doc-3");
+                let update = doc
+                    .transact()
+                    .encode_state_as_update_v1(&StateVector::default());
+                db.push_update("doc-3", &update).unwrap();
+            }
+            db_txn.commit().unwrap();
+        }
 
-            db.insert_doc("doc", &txn).unwrap();
+        let db_txn = env.get_reader().unwrap();
+        let db = LmdbReader::new(&db_txn, &h);
+        let names: [&[u8]; 4] = [b"doc-3", b"missing", b"doc-1", b"doc-2"];
+        let docs = db.load_docs(names.iter().copied()).unwrap();
+        assert_eq!(docs.len(), names.len());
+        for ((name, doc), expected) in docs.into_iter().zip(names.iter()) {
+            assert_eq!(&name, expected);
+            if *expected == b"missing" {
+                assert!(doc.is_none());
+            } else {
+                let doc = doc.unwrap();
+                let text = doc.get_or_insert_text("text");
+                assert_eq!(text.get_string(&doc.transact()).as_bytes(), *expected);
+            }
+        }
+    }
 
-            text.push(&mut txn, " world");
+    #[test]
+    fn push_receipt() {
+        const THREADS: usize = 8;
+        const DOCS: usize = 10;
+        let dir = TempDir::new("lmdb-push_receipt").unwrap();
+        let store = init_doc_store(&dir);
+
+        let receipt = store.push_update("doc", &[0, 0]).unwrap();
+        assert_eq!(
+            receipt,
+            PushReceipt {
+                seq: 1,
+                pending_updates: 1,
+                pending_bytes: 2,
+                doc_created: true,
+                health: None,
+            }
+        );
+        let receipt = store.push_update("doc", &[0, 0]).unwrap();
+        assert_eq!(
+            receipt,
+            PushReceipt {
+                seq: 2,
+                pending_updates: 2,
+                pending_bytes: 4,
+                doc_created: false,
+                health: None,
+            }
+        );
+        assert_eq!(store.push_update_seq("doc", &[0, 0]).unwrap(), 3);
+
+        // every document is created exactly once, no matter how many threads push to it
+        let threads: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    let mut created = Vec::new();
+                    for i in 0..DOCS {
+                        let receipt = store.push_update(&format!("doc-{}", i), &[0, 0]).unwrap();
+                        if receipt.doc_created {
+                            created.push(i);
+                        }
+                    }
+                    created
+                })
+            })
+            .collect();
+        let mut created: Vec<_> = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        created.sort();
+        assert_eq!(created, (0..DOCS).collect::<Vec<_>>());
+        for i in 0..DOCS {
+            let receipt = store.push_update(&format!("doc-{}", i), &[0, 0]).unwrap();
+            assert_eq!(receipt.seq, THREADS as u32 + 1);
+            assert!(!receipt.doc_created);
+        }
+    }
 
-            db.insert_doc("doc", &txn).unwrap();
+    #[test]
+    fn decoded_updates() {
+        let dir = TempDir::new("lmdb-decoded_updates").unwrap();
+        let store = init_doc_store(&dir);
 
-            db_txn.commit().unwrap();
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        for chunk in ["hello", " ", "world", "!"] {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            store.push_update("doc", &txn.encode_update_v1()).unwrap();
         }
+        store.push_update("other", &[0, 0]).unwrap();
+
+        // updates are decoded in order, but not applied anywhere
+        let mut inserted = 0;
+        let mut seen = StateVector::default();
+        let mut seq_nrs = Vec::new();
+        for (seq_nr, update) in store.decoded_updates("doc").unwrap() {
+            for (&client, &clock) in update.state_vector().iter() {
+                inserted += clock - seen.get(&client);
+                seen.set_max(client, clock);
+            }
+            seq_nrs.push(seq_nr);
+        }
+        assert_eq!(seq_nrs, vec![1, 2, 3, 4]);
 
-        // retrieve document
-        {
-            let db_txn = env.get_reader().unwrap();
-            let db = LmdbStore::from(db_txn.bind(&h));
+        let loaded = Doc::new();
+        let text = loaded.get_or_insert_text("text");
+        store.load_doc("doc", &mut loaded.transact_mut()).unwrap();
+        let len = text.get_string(&loaded.transact()).len() as u32;
+        assert_eq!(inserted, len);
+
+        assert!(store.decoded_updates("missing").unwrap().is_empty());
+        store.flush_doc("doc").unwrap();
+        assert!(store.decoded_updates("doc").unwrap().is_empty());
+    }
+
+    #[cfg(feature = "stress-tests")]
+    #[test]
+    fn concurrent_pushes() {
+        use yrs::TransactionMut;
+        use yrs_kvstore::stress::{self, StressStore};
+
+        struct Store(LmdbDocStore);
 
+        impl StressStore for Store {
+            fn push_update(&self, name: &str, update: &[u8]) -> Result<PushReceipt, Error> {
+                self.0.push_update(name, update)
+            }
+
+            fn update_count(&self, name: &str) -> Result<usize, Error> {
+                Ok(self.0.decoded_updates(name)?.len())
+            }
+
+            fn load_doc(&self, name: &str, txn: &mut TransactionMut) -> Result<bool, Error> {
+                self.0.load_doc(name, txn)
+            }
+        }
+
+        // LMDB executes a single write transaction at a time, so concurrent pushes are serialized
+        let dir = TempDir::new("lmdb-concurrent_pushes").unwrap();
+        let store = Store(init_doc_store(&dir));
+        stress::concurrent_pushes(&store, 8, 32);
+    }
+
+    #[test]
+    fn persist_on_update_retry() {
+        const DOC_NAME: &str = "doc";
+        let tmp = TempDir::new("lmdb-persist_on_update_retry").unwrap();
+        let store = init_doc_store(&tmp);
+        // injects a given number of failed pushes
+        let failures = Arc::new(AtomicU32::new(0));
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let doc = Doc::with_client_id(1);
+        let text = doc.get_or_insert_text("text");
+        let _sub = {
+            let store = store.clone();
+            let failures = failures.clone();
+            let reported = reported.clone();
+            persist_on_update_with(
+                &doc,
+                move |name, update| {
+                    if failures.load(Ordering::SeqCst) > 0 {
+                        failures.fetch_sub(1, Ordering::SeqCst);
+                        return Err(io::Error::other("disk full").into());
+                    }
+                    store.push_update(name, update)?;
+                    Ok(())
+                },
+                DOC_NAME,
+                move |failure: PersistFailure| {
+                    let error = failure.error.to_string();
+                    reported
+                        .lock()
+                        .unwrap()
+                        .push((error, failure.update, failure.queued))
+                },
+                PersistConfig { retry_queue: 1 },
+            )
+            .unwrap()
+        };
+        let load = || {
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
             let mut txn = doc.transact_mut();
-            db.load_doc("doc", &mut txn).unwrap();
+            store.load_doc(DOC_NAME, &mut txn).unwrap();
+            text.get_string(&txn)
+        };
 
-            assert_eq!(text.get_string(&txn), "hello world");
+        text.push(&mut doc.transact_mut(), "a");
+        failures.store(1, Ordering::SeqCst);
+        text.push(&mut doc.transact_mut(), "b");
+        {
+            let reported = reported.lock().unwrap();
+            assert_eq!(reported.len(), 1);
+            assert!(reported[0].2);
+            assert_eq!(reported[0].0, "disk full");
         }
+        assert_eq!(load(), "a");
+
+        // failed update is retried before the next one
+        text.push(&mut doc.transact_mut(), "c");
+        assert_eq!(reported.lock().unwrap().len(), 1);
+        assert_eq!(load(), "abc");
+
+        // updates which don't fit into the retry queue are dropped and handed over to the sink
+        failures.store(2, Ordering::SeqCst);
+        text.push(&mut doc.transact_mut(), "d");
+        text.push(&mut doc.transact_mut(), "e");
+        text.push(&mut doc.transact_mut(), "f");
+        let dropped = {
+            let mut reported = reported.lock().unwrap();
+            assert_eq!(reported.len(), 3);
+            assert!(reported[1].2);
+            assert!(!reported[2].2);
+            reported.pop().unwrap().1
+        };
+        // "f" is persisted, but it can't be integrated without "e"
+        assert_eq!(load(), "abcd");
+        store.push_update(DOC_NAME, &dropped).unwrap();
+        assert_eq!(load(), "abcdef");
     }
 
+    #[global_allocator]
+    static ALLOCATOR: conformance::CountingAlloc = conformance::CountingAlloc;
+
     #[test]
-    fn incremental_updates() {
-        const DOC_NAME: &str = "doc";
-        let dir = TempDir::new("lmdb-incremental_updates").unwrap();
-        let env = init_env(&dir);
+    fn conformance() {
+        let dir = TempDir::new("lmdb-conformance").unwrap();
+        let env = LmdbEnv::new(init_env(&dir));
         let h = env.create_db("yrs", DbCreate).unwrap();
-        let env = Arc::new(env);
-        let h = Arc::new(h);
+        let db_txn = env.new_transaction().unwrap();
+        let db = LmdbStore::from(db_txn.bind(&h));
+        conformance::run_all(&db);
+        db_txn.commit().unwrap();
+    }
 
-        // store document updates
-        {
-            let doc = Doc::new();
-            let text = doc.get_or_insert_text("text");
+    /// Database in a temporary directory, which scenarios of the conformance suite run against.
+    #[derive(Clone)]
+    struct LmdbBackend {
+        env: Arc<Environment>,
+        handle: Arc<DbHandle>,
+        _dir: Arc<TempDir>,
+    }
 
-            let env = env.clone();
-            let h = h.clone();
-            let _sub = doc.observe_update_v1(move |_, u| {
-                let db_txn = env.new_transaction().unwrap();
-                let db = LmdbStore::from(db_txn.bind(&h));
-                db.push_update(DOC_NAME, &u.update).unwrap();
-                db_txn.commit().unwrap();
-            });
-            // generate 3 updates
-            text.push(&mut doc.transact_mut(), "a");
-            text.push(&mut doc.transact_mut(), "b");
-            text.push(&mut doc.transact_mut(), "c");
+    impl conformance::Backend for LmdbBackend {
+        type Store<'t> = LmdbStore<'t>;
+
+        fn open(name: &str) -> Self {
+            let dir = TempDir::new(&format!("lmdb-{}", name)).unwrap();
+            // some scenarios write more within a single transaction than fits into the default
+            // map size
+            let env = Environment::new()
+                .autocreate_dir(true)
+                .max_dbs(4)
+                .map_size(64 * 1024 * 1024)
+                .open(&dir, 0o777)
+                .unwrap();
+            let handle = env.create_db("yrs", DbCreate).unwrap();
+            LmdbBackend {
+                env: Arc::new(env),
+                handle: Arc::new(handle),
+                _dir: Arc::new(dir),
+            }
         }
 
-        // load document
-        {
-            let doc = Doc::new();
-            let text = doc.get_or_insert_text("text");
-            let mut txn = doc.transact_mut();
+        fn transaction<R>(
+            &self,
+            f: impl for<'t> conformance::TransactionFn<'t, LmdbStore<'t>, R>,
+        ) -> R {
+            let db_txn = self.env.new_transaction().unwrap();
+            let (_, result) = f(LmdbStore::from(db_txn.bind(&self.handle)));
+            db_txn.commit().unwrap();
+            result
+        }
 
-            let db_txn = env.get_reader().unwrap();
-            let db = LmdbStore::from(db_txn.bind(&h));
-            db.load_doc(DOC_NAME, &mut txn).unwrap();
+        fn read<R>(&self, f: impl for<'t> FnOnce(LmdbStore<'t>) -> R) -> R {
+            let db_txn = self.env.get_reader().unwrap();
+            f(LmdbStore::from(db_txn.bind(&self.handle)))
+        }
 
-            assert_eq!(text.get_string(&txn), "abc");
+        fn abort<R>(&self, f: impl for<'t> FnOnce(&LmdbStore<'t>) -> R) -> R {
+            let db_txn = self.env.new_transaction().unwrap();
+            f(&LmdbStore::from(db_txn.bind(&self.handle)))
         }
 
-        // flush document
-        {
+        fn open_stores<R>(&self, f: impl for<'t> FnOnce(&dyn Fn() -> LmdbStore<'t>) -> R) -> R {
+            let db_txn = self.env.new_transaction().unwrap();
+            f(&|| LmdbStore::from(db_txn.bind(&self.handle)))
+        }
+
+        fn try_write<R, E>(
+            &self,
+            f: impl for<'t> FnOnce(&LmdbStore<'t>) -> Result<R, E>,
+        ) -> Result<R, E> {
+            let db_txn = self.env.new_transaction().unwrap();
+            let result = f(&LmdbStore::from(db_txn.bind(&self.handle)));
+            if result.is_ok() {
+                db_txn.commit().unwrap();
+            }
+            result
+        }
+    }
+
+    yrs_kvstore::conformance_tests!(LmdbBackend);
+
+    #[test]
+    fn compat_fixtures() {
+        use yrs_kvstore::compat;
+
+        for fixture in compat::FIXTURES {
+            let dir = TempDir::new(&format!("lmdb-compat-{}", fixture.format_version)).unwrap();
+            let env = init_env(&dir);
+            let h = env.create_db("yrs", DbCreate).unwrap();
             let db_txn = env.new_transaction().unwrap();
-            let db = LmdbStore::from(db_txn.bind(&h));
-            let doc = db.flush_doc(DOC_NAME).unwrap().unwrap();
+            compat::restore(&LmdbStore::from(db_txn.bind(&h)), fixture);
             db_txn.commit().unwrap();
 
-            let text = doc.get_or_insert_text("text");
-
-            assert_eq!(text.get_string(&doc.transact()), "abc");
+            // fixture is read from the database, not from the transaction which has written it
+            let db_txn = env.new_transaction().unwrap();
+            compat::verify(&LmdbStore::from(db_txn.bind(&h)));
+            db_txn.commit().unwrap();
         }
     }
 
     #[test]
-    fn state_vector_updates_only() {
-        const DOC_NAME: &str = "doc";
-        let dir = TempDir::new("lmdb-state_vector_updates_only").unwrap();
+    fn compat_release_files() {
+        use yrs_kvstore::compat;
+
+        // database file written by yrs-lmdb 0.3.0, see fixtures/v0.3.0/compat_fixture.rs
+        let dir = TempDir::new("lmdb-compat_release_files").unwrap();
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/v0.3.0/data.mdb");
+        std::fs::copy(fixture, dir.path().join("data.mdb")).unwrap();
         let env = init_env(&dir);
         let h = env.create_db("yrs", DbCreate).unwrap();
-        let env = Arc::new(env);
-        let h = Arc::new(h);
+        let db_txn = env.new_transaction().unwrap();
+        compat::verify(&LmdbStore::from(db_txn.bind(&h)));
+        db_txn.commit().unwrap();
+    }
 
-        // store document updates
+    #[test]
+    fn compat_layout() {
+        use yrs_kvstore::compat;
+
+        let dir = TempDir::new("lmdb-compat_layout").unwrap();
+        let env = init_env(&dir);
+        let h = env.create_db("yrs", DbCreate).unwrap();
+        let db_txn = env.new_transaction().unwrap();
+        let db = LmdbStore::from(db_txn.bind(&h));
+        compat::current_layout(&db);
+        db_txn.commit().unwrap();
+    }
+
+    #[test]
+    fn namespaces() {
+        fn write<'a, DB: DocOps<'a>>(db: &DB, name: &str, content: &str)
+        where
+            Error: From<<DB as KVStore<'a>>::Error>,
         {
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
-            let env = env.clone();
-            let h = h.clone();
-            let _sub = doc.observe_update_v1(move |_, u| {
-                let db_txn = env.new_transaction().unwrap();
-                let db = LmdbStore::from(db_txn.bind(&h));
-                db.push_update(DOC_NAME, &u.update).unwrap();
-                db_txn.commit().unwrap();
-            });
-            // generate 3 updates
-            text.push(&mut doc.transact_mut(), "a");
-            text.push(&mut doc.transact_mut(), "b");
-            text.push(&mut doc.transact_mut(), "c");
+            text.push(&mut doc.transact_mut(), content);
+            db.insert_doc(name, &doc.transact()).unwrap();
+        }
 
-            let sv = doc.transact().state_vector();
-            sv
-        };
+        fn read<'a, DB: DocOps<'a>>(db: &DB, name: &str) -> Option<String>
+        where
+            Error: From<<DB as KVStore<'a>>::Error>,
+        {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            if db.load_doc(name, &mut doc.transact_mut()).unwrap() {
+                Some(text.get_string(&doc.transact()))
+            } else {
+                None
+            }
+        }
 
-        let db_txn = env.get_reader().unwrap();
+        /// Returns OIDs allocated for documents within a given namespace, read from raw keys.
+        fn oids<'a, DB: DocOps<'a>>(db: &DB, namespace: u16) -> Vec<Vec<u8>>
+        where
+            Error: From<<DB as KVStore<'a>>::Error>,
+        {
+            let start = key_namespace(namespace);
+            let end = key_namespace_end(namespace);
+            db.iter_range(&start, &end)
+                .unwrap()
+                .filter(|e| e.key() < end.as_ref())
+                .filter_map(|e| {
+                    let (ns, key) = split_namespace(e.key())?;
+                    assert_eq!(ns, namespace);
+                    match parse_key(key)? {
+                        ParsedKey::Oid { .. } => Some(e.value()[..4].to_vec()),
+                        _ => None,
+                    }
+                })
+                .collect()
+        }
+
+        let dir = TempDir::new("lmdb-namespaces").unwrap();
+        let env = LmdbEnv::new(init_env(&dir));
+        let h = env.create_db("yrs", DbCreate).unwrap();
+        let db_txn = env.new_transaction().unwrap();
         let db = LmdbStore::from(db_txn.bind(&h));
-        let (sv, completed) = db.get_state_vector(DOC_NAME).unwrap();
-        assert!(sv.is_none());
-        assert!(!completed); // since it's not completed, we should recalculate state vector from doc state
+        let staging = LmdbStore::with_namespace(db_txn.bind(&h), 1);
+        let sandbox = LmdbStore::with_namespace(db_txn.bind(&h), 2);
+        write(&staging, "doc", "staging");
+        write(&sandbox, "doc", "sandbox");
+        write(&sandbox, "other", "sandbox");
+        assert_eq!(read(&staging, "doc"), Some("staging".to_string()));
+        assert_eq!(read(&sandbox, "doc"), Some("sandbox".to_string()));
+        assert_eq!(staging.iter_docs().unwrap().count(), 1);
+        assert_eq!(sandbox.iter_docs().unwrap().count(), 2);
+        // documents of both namespaces are invisible outside of them
+        assert_eq!(read(&db, "doc"), None);
+        assert_eq!(db.iter_docs().unwrap().count(), 0);
+        assert_eq!(db.list_namespaces().unwrap(), vec![1, 2]);
+        // both namespaces allocated the same OID for their first document
+        let staging_oids = oids(&db, 1);
+        assert_eq!(staging_oids.len(), 1);
+        assert_eq!(staging_oids[0], oids(&db, 2)[0]);
+        assert!(db.drop_namespace(1).unwrap() > 0);
+        assert_eq!(db.list_namespaces().unwrap(), vec![2]);
+        assert_eq!(read(&staging, "doc"), None);
+        assert_eq!(staging.iter_docs().unwrap().count(), 0);
+        assert_eq!(read(&sandbox, "doc"), Some("sandbox".to_string()));
     }
 
     #[test]
-    fn state_diff_from_updates() {
-        const DOC_NAME: &str = "doc";
-        let dir = TempDir::new("lmdb-state_diff_from_updates").unwrap();
-        let env = init_env(&dir);
-        let h = env.create_db("yrs", DbCreate).unwrap();
-        let env = Arc::new(env);
-        let h = Arc::new(h);
+    fn shutdown_flush() {
+        use yrs_kvstore::worker::{shutdown_flush, FlushCandidate};
 
-        let (sv, expected) = {
+        let dir = TempDir::new("lmdb-shutdown_flush").unwrap();
+        let store = init_doc_store(&dir);
+
+        // document "doc-i" receives 4 * (i + 1) updates, so later documents have more pending bytes
+        let mut expected = Vec::new();
+        for i in 0..5 {
+            let name = format!("doc-{}", i);
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
-
-            let env = env.clone();
-            let h = h.clone();
-            let _sub = doc.observe_update_v1(move |_, u| {
-                let db_txn = env.new_transaction().unwrap();
-                let db = LmdbStore::from(db_txn.bind(&h));
-                db.push_update(DOC_NAME, &u.update).unwrap();
-                db_txn.commit().unwrap();
-            });
-
-            // generate 3 updates
-            text.push(&mut doc.transact_mut(), "a");
-            text.push(&mut doc.transact_mut(), "b");
-            let sv = doc.transact().state_vector();
-            text.push(&mut doc.transact_mut(), "c");
-            let update = doc.transact().encode_diff_v1(&sv);
-            (sv, update)
+            for _ in 0..4 * (i + 1) {
+                let mut txn = doc.transact_mut();
+                text.push(&mut txn, "a");
+                store.push_update(&name, &txn.encode_update_v1()).unwrap();
+            }
+            let content = text.get_string(&doc.transact());
+            expected.push((name, content));
+        }
+        // documents without pending updates are not candidates
+        store.push_update("flushed", &[0, 0]).unwrap();
+        store.flush_doc("flushed").unwrap();
+
+        let candidates = store.flush_candidates().unwrap();
+        let names: Vec<&[u8]> = candidates.iter().map(|c| c.name.as_ref()).collect();
+        assert_eq!(
+            names,
+            vec![
+                b"doc-4".as_ref(),
+                b"doc-3".as_ref(),
+                b"doc-2".as_ref(),
+                b"doc-1".as_ref(),
+                b"doc-0".as_ref()
+            ]
+        );
+        assert_eq!(candidates[0].pending_updates, 20);
+
+        // every flush takes more than a half of the time budget
+        let report = shutdown_flush(candidates.clone(), Duration::from_millis(100), 1, |name| {
+            std::thread::sleep(Duration::from_millis(60));
+            store.flush_doc(name)
+        });
+        assert!(report.failed.is_empty());
+        let flushed = report.flushed.len();
+        assert!((1..=2).contains(&flushed));
+        let names = |candidates: &[FlushCandidate]| -> Vec<Box<[u8]>> {
+            candidates.iter().map(|c| c.name.clone()).collect()
         };
+        assert_eq!(report.flushed, names(&candidates[..flushed]));
+        assert_eq!(report.unflushed, names(&candidates[flushed..]));
+        let folded: u32 = candidates[..flushed]
+            .iter()
+            .map(|c| c.pending_updates)
+            .sum();
+        assert_eq!(report.updates_folded, folded);
+
+        // unflushed documents keep all of their pending updates and load in full
+        assert_eq!(store.flush_candidates().unwrap(), &candidates[flushed..]);
+        for (name, content) in expected.iter() {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            assert!(store.load_doc(name, &mut doc.transact_mut()).unwrap());
+            assert_eq!(&text.get_string(&doc.transact()), content);
+        }
 
-        let db_txn = env.get_reader().unwrap();
-        let db = LmdbStore::from(db_txn.bind(&h));
-        let actual = db.get_diff(DOC_NAME, &sv).unwrap();
-        assert_eq!(actual, Some(expected));
+        let report = store.shutdown_flush(Duration::from_secs(60), 2).unwrap();
+        assert_eq!(report.flushed.len(), 5 - flushed);
+        assert!(report.unflushed.is_empty());
+        assert!(store.flush_candidates().unwrap().is_empty());
     }
 
     #[test]
-    fn state_diff_from_doc() {
-        const DOC_NAME: &str = "doc";
-        let dir = TempDir::new("lmdb-state_diff_from_doc").unwrap();
-        let env = init_env(&dir);
-        let h = env.create_db("yrs", DbCreate).unwrap();
+    fn init_meta_once_concurrent() {
+        const THREADS: usize = 8;
+        let dir = TempDir::new("lmdb-init_meta_once_concurrent").unwrap();
+        let store = init_doc_store(&dir);
+        let barrier = Arc::new(std::sync::Barrier::new(THREADS));
+        let threads: Vec<_> = (0..THREADS)
+            .map(|i| {
+                let store = store.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    let owner = format!("client-{}", i);
+                    barrier.wait();
+                    let entries = [(b"owner".as_ref(), owner.as_bytes())];
+                    let won = store.init_meta_once("doc", &entries).unwrap();
+                    if won {
+                        Some(owner)
+                    } else {
+                        None
+                    }
+                })
+            })
+            .collect();
+        let winners: Vec<String> = threads
+            .into_iter()
+            .filter_map(|thread| thread.join().unwrap())
+            .collect();
+        assert_eq!(winners.len(), 1);
+        let owner = store.get_meta("doc", "owner").unwrap().unwrap();
+        assert_eq!(owner.as_ref(), winners[0].as_bytes());
+    }
 
-        let (sv, expected) = {
+    #[test]
+    fn sharded_store() {
+        use yrs_kvstore::shard::ShardedStore;
+
+        fn text<'a, S: DocOps<'a>>(db: &ShardedStore<S>, name: &str) -> String
+        where
+            Error: From<<S as KVStore<'a>>::Error>,
+        {
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
-            // generate 3 updates
-            text.push(&mut doc.transact_mut(), "a");
-            text.push(&mut doc.transact_mut(), "b");
-            let sv = doc.transact().state_vector();
-            text.push(&mut doc.transact_mut(), "c");
-            let update = doc.transact().encode_diff_v1(&sv);
+            db.load_doc(name, &mut doc.transact_mut()).unwrap();
+            let content = text.get_string(&doc.transact());
+            content
+        }
 
-            let db_txn = env.new_transaction().unwrap();
-            let db = LmdbStore::from(db_txn.bind(&h));
-            db.insert_doc(DOC_NAME, &doc.transact()).unwrap();
-            db_txn.commit().unwrap();
+        fn edit(client_id: u64, content: &str) -> Vec<u8> {
+            let doc = Doc::with_client_id(client_id);
+            let text = doc.get_or_insert_text("text");
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, content);
+            txn.encode_update_v1()
+        }
 
-            (sv, update)
+        let names: Vec<String> = (0..100).map(|i| format!("doc-{}", i)).collect();
+        let dir = TempDir::new("lmdb-sharded_store").unwrap();
+        let env = Environment::new()
+            .autocreate_dir(true)
+            .max_dbs(5)
+            .open(&dir, 0o777)
+            .unwrap();
+        let env = LmdbEnv::new(env);
+        let handles: Vec<_> = (0..5)
+            .map(|i| env.create_db(&format!("shard-{}", i), DbCreate).unwrap())
+            .collect();
+        let db_txn = env.new_transaction().unwrap();
+        let open = |id: u32| {
+            let (db_txn, h) = (&db_txn, &handles[id as usize]);
+            move || LmdbStore::from(db_txn.bind(h))
         };
+        let mut db = ShardedStore::new();
+        for id in 0..4 {
+            db.add_shard(id, open(id));
+        }
 
-        let db_txn = env.get_reader().unwrap();
-        let db = LmdbStore::from(db_txn.bind(&h));
-        let actual = db.get_diff(DOC_NAME, &sv).unwrap();
-        assert_eq!(actual, Some(expected));
-    }
+        // routing depends only on the set of shards, not on the order they're added in
+        let mut reversed = ShardedStore::new();
+        for id in (0..4).rev() {
+            reversed.add_shard(id, open(id));
+        }
+        let mut counts = [0u32; 4];
+        for name in names.iter() {
+            let shard = db.shard_of(name);
+            assert_eq!(shard, db.shard_of(name));
+            assert_eq!(shard, reversed.shard_of(name));
+            counts[shard as usize] += 1;
+        }
+        assert!(counts.iter().all(|&n| n > 0), "{:?}", counts);
+        drop(reversed);
 
-    #[test]
-    fn doc_meta() {
-        const DOC_NAME: &str = "doc";
-        let dir = TempDir::new("lmdb-doc_meta").unwrap();
-        let env = init_env(&dir);
-        let h = env.create_db("yrs", DbCreate).unwrap();
+        // documents are stored only in the shard they're routed to
+        for (i, name) in names.iter().enumerate() {
+            db.push_update(name, &edit(i as u64 + 1, name)).unwrap();
+        }
+        let mut stored = 0;
+        for id in db.shard_ids() {
+            for name in db.shard(id).unwrap().iter_docs().unwrap() {
+                assert_eq!(db.shard_of(&name), id);
+                stored += 1;
+            }
+        }
+        assert_eq!(stored, names.len());
+
+        // fan-out iteration merges names of all shards in ascending order
+        let mut expected: Vec<Box<[u8]>> = names.iter().map(|n| n.as_bytes().into()).collect();
+        expected.sort();
+        let docs: Vec<Box<[u8]>> = db.iter_docs().unwrap().collect();
+        assert_eq!(docs, expected);
+        let stats = db.stats().unwrap();
+        let shards: Vec<u32> = stats.iter().map(|s| s.shard).collect();
+        assert_eq!(shards, vec![0, 1, 2, 3]);
+        for s in stats.iter() {
+            assert_eq!(s.docs, counts[s.shard as usize]);
+            assert_eq!(s.pending_updates, s.docs as u64);
+        }
 
-        let db_txn = env.new_transaction().unwrap();
-        let db = LmdbStore::from(db_txn.bind(&h));
-        let value = db.get_meta(DOC_NAME, "key").unwrap();
-        assert!(value.is_none());
-        db.insert_meta(DOC_NAME, "key", "value1".as_bytes())
+        // a new shard takes over some documents, while the rest keeps their shards
+        let before: Vec<u32> = names.iter().map(|n| db.shard_of(n)).collect();
+        db.add_shard(4, open(4));
+        let mut moved = Vec::new();
+        for (name, prev) in names.iter().zip(before) {
+            let shard = db.shard_of(name);
+            if shard != prev {
+                assert_eq!(shard, 4);
+                moved.push(name.clone());
+            }
+        }
+        assert!(!moved.is_empty() && moved.len() < names.len());
+        let mut misplaced: Vec<String> = db
+            .misplaced_docs()
+            .unwrap()
+            .into_iter()
+            .map(|doc| {
+                assert_eq!(doc.target, 4);
+                String::from_utf8(doc.name.into()).unwrap()
+            })
+            .collect();
+        misplaced.sort();
+        moved.sort();
+        assert_eq!(misplaced, moved);
+
+        // moved documents are not visible until rebalanced, writes create them in the new shard
+        let first = &moved[0];
+        assert_eq!(text(&db, first), "");
+        db.push_update(first, &edit(1000, "!")).unwrap();
+        assert_eq!(db.rebalance().unwrap(), moved.len() as u32);
+        assert!(db.misplaced_docs().unwrap().is_empty());
+        for name in moved.iter().skip(1) {
+            assert_eq!(&text(&db, name), name);
+        }
+        let merged = text(&db, first);
+        assert!(merged.contains(first.as_str()) && merged.contains('!'));
+        assert_eq!(db.iter_docs().unwrap().count(), names.len());
+
+        // copying a document across shards
+        let source = &names[0];
+        db.insert_meta(source, "owner", b"alice").unwrap();
+        let copy = (0..)
+            .map(|i| format!("copy-{}", i))
+            .find(|name| db.shard_of(name) != db.shard_of(source))
             .unwrap();
+        assert!(db.copy_doc(source, &copy).unwrap());
+        assert_eq!(text(&db, &copy), text(&db, source));
+        let owner = db.get_meta(&copy, "owner").unwrap();
+        assert_eq!(owner, Some("alice".as_bytes()));
+        assert!(!db.copy_doc("missing", &copy).unwrap());
+        drop(db);
         db_txn.commit().unwrap();
+    }
 
+    #[test]
+    fn peek_back_db_edges() {
+        let dir = TempDir::new("lmdb-peek_back_db_edges").unwrap();
+        let env = LmdbEnv::new(init_env(&dir));
+        let h = env.create_db("yrs", DbCreate).unwrap();
         let db_txn = env.new_transaction().unwrap();
         let db = LmdbStore::from(db_txn.bind(&h));
-        let prev = db.get_meta(DOC_NAME, "key").unwrap().map(Vec::from);
-        db.insert_meta(DOC_NAME, "key", "value2".as_bytes())
-            .unwrap();
+
+        // empty database
+        assert!(db.peek_back(&[5]).unwrap().is_none());
+
+        db.upsert(&[1], &[1]).unwrap();
+        db.upsert(&[3], &[3]).unwrap();
+        // cursor positioned at the first entry cannot step back
+        assert!(db.peek_back(&[0]).unwrap().is_none());
+        assert_eq!(db.peek_back(&[1]).unwrap().unwrap().key(), &[1]);
+        assert_eq!(db.peek_back(&[2]).unwrap().unwrap().key(), &[1]);
+        assert_eq!(db.peek_back(&[3]).unwrap().unwrap().key(), &[3]);
+        // no entry at or after the key: the last entry is returned
+        let last = db.peek_back(&[9]).unwrap().unwrap();
+        assert_eq!((last.key(), last.value()), (&[3][..], &[3][..]));
         db_txn.commit().unwrap();
-        assert_eq!(prev.as_deref(), Some("value1".as_bytes()));
+    }
 
+    #[test]
+    fn iter_keys_range() {
+        let dir = TempDir::new("lmdb-iter_keys_range").unwrap();
+        let env = LmdbEnv::new(init_env(&dir));
+        let h = env.create_db("yrs", DbCreate).unwrap();
         let db_txn = env.new_transaction().unwrap();
         let db = LmdbStore::from(db_txn.bind(&h));
-        let prev = db.get_meta(DOC_NAME, "key").unwrap().map(Vec::from);
-        db.remove_meta(DOC_NAME, "key").unwrap();
-        assert_eq!(prev.as_deref(), Some("value2".as_bytes()));
-        let value = db.get_meta(DOC_NAME, "key").unwrap();
-        assert!(value.is_none());
+        let value = vec![7u8; 16 * 1024];
+        for i in 0..16u8 {
+            db.upsert(&[0x10, i], &value[..(i as usize + 1) * 1024])
+                .unwrap();
+        }
+        let (from, to) = ([0x10, 2], [0x10, 5, 0]);
+
+        // the same keys and value lengths as returned by the cursor
+        let expected: Vec<(Vec<u8>, usize)> = db
+            .iter_range(&from, &to)
+            .unwrap()
+            .map(|e| (e.key().to_vec(), e.value().len()))
+            .collect();
+        let mut visited = Vec::new();
+        db.iter_keys_range(&from, &to, &mut |key, len| {
+            visited.push((key.to_vec(), len));
+            true
+        })
+        .unwrap();
+        assert_eq!(visited, expected);
+        assert_eq!(visited.len(), 4);
+        assert_eq!(visited[0], (vec![0x10, 2], 3 * 1024));
+
+        // iteration stops once the callback returns false
+        let mut visited = 0;
+        db.iter_keys_range(&from, &to, &mut |_, _| {
+            visited += 1;
+            visited < 2
+        })
+        .unwrap();
+        assert_eq!(visited, 2);
+
+        // scanning keys doesn't copy values of visited entries
+        let (from, to) = ([0x10], [0x11]);
+        let mut keys = 0;
+        let before = allocations();
+        db.iter_keys_range(&from, &to, &mut |_, _| {
+            keys += 1;
+            true
+        })
+        .unwrap();
+        let key_scan = allocations() - before;
+        assert_eq!(keys, 16);
+        assert!(key_scan < 16, "{} allocations", key_scan);
+        db_txn.commit().unwrap();
     }
 
     #[test]
-    fn doc_meta_iter() {
-        let dir = TempDir::new("lmdb-doc_meta_iter").unwrap();
-        let env = init_env(&dir);
+    fn health() {
+        use crate::{LmdbHealth, LmdbThresholds};
+        use yrs_kvstore::HealthHint;
+
+        let dir = TempDir::new("lmdb-health").unwrap();
+        let env = LmdbEnv::with_growth(
+            init_env(&dir),
+            MapGrowth {
+                factor: 2.0,
+                max_size: 64 * 1024 * 1024,
+            },
+        );
         let h = env.create_db("yrs", DbCreate).unwrap();
-        let db_txn = env.new_transaction().unwrap();
-        let db = LmdbStore::from(db_txn.bind(&h));
+        env.with_write_txn(&h, |db| db.push_update("doc", &[0, 0]))
+            .unwrap();
 
-        db.insert_meta("A", "key1", [1].as_ref()).unwrap();
-        db.insert_meta("B", "key2", [2].as_ref()).unwrap();
-        db.insert_meta("B", "key3", [3].as_ref()).unwrap();
-        db.insert_meta("C", "key4", [4].as_ref()).unwrap();
+        // usage is read from the environment, measured against the size the map may grow to
+        let raw = LmdbHealth::read(&env).unwrap();
+        assert_eq!(raw.max_map_size, raw.map_size);
+        let health = env.health().unwrap();
+        assert_eq!(health.map_size, raw.map_size);
+        assert_eq!(health.max_map_size, 64 * 1024 * 1024);
+        assert!(health.map_used > 0);
+        assert!(health.map_used <= health.map_size);
+        assert!(health.map_used_percent() < raw.map_used_percent());
+        assert!(health.max_readers > 0);
+        {
+            // open read transactions occupy reader slots
+            let _reader = env.get_reader().unwrap();
+            let busy = env.health().unwrap();
+            assert!(busy.readers >= 1 && busy.readers <= busy.max_readers);
+        }
+        assert_eq!(health.hint(&LmdbThresholds::default()), HealthHint::Ok);
 
-        let mut i = db.iter_meta("B").unwrap();
-        assert_eq!(i.next(), Some(("key2".as_bytes().into(), [2].into())));
-        assert_eq!(i.next(), Some(("key3".as_bytes().into(), [3].into())));
-        assert!(i.next().is_none());
+        // thresholds are applied to the more saturated resource
+        let thresholds = LmdbThresholds {
+            degraded_percent: 50.0,
+            stalled_percent: 90.0,
+        };
+        let usage = |map_used, readers| LmdbHealth {
+            map_size: 100,
+            max_map_size: 200,
+            map_used,
+            readers,
+            max_readers: 10,
+        };
+        assert_eq!(usage(98, 4).map_used_percent(), 49.0);
+        assert_eq!(usage(98, 4).reader_saturation(), 40.0);
+        assert_eq!(usage(98, 4).hint(&thresholds), HealthHint::Ok);
+        assert_eq!(usage(100, 4).hint(&thresholds), HealthHint::Degraded);
+        assert_eq!(usage(0, 5).hint(&thresholds), HealthHint::Degraded);
+        assert_eq!(usage(180, 0).hint(&thresholds), HealthHint::Stalled);
+        assert_eq!(usage(0, 10).hint(&thresholds), HealthHint::Stalled);
+        let empty = LmdbHealth {
+            max_map_size: 0,
+            max_readers: 0,
+            ..usage(0, 0)
+        };
+        assert_eq!(empty.map_used_percent(), 0.0);
+        assert_eq!(empty.hint(&thresholds), HealthHint::Ok);
+
+        // LMDB stores don't attach hints to push receipts
+        let receipt = env
+            .with_write_txn(&h, |db| db.push_update("doc", &[0, 0]))
+            .unwrap();
+        assert_eq!(receipt.health, None);
     }
 
     #[test]
-    fn doc_iter() {
-        let dir = TempDir::new("lmdb-doc_iter").unwrap();
+    fn sv_read_repair() {
+        let dir = TempDir::new("lmdb-sv_read_repair").unwrap();
         let env = init_env(&dir);
         let h = env.create_db("yrs", DbCreate).unwrap();
-        let env = Arc::new(env);
-        let h = Arc::new(h);
-
-        // insert metadata
+        let doc = Doc::with_client_id(1);
+        let text = doc.get_or_insert_text("text");
+        text.push(&mut doc.transact_mut(), "hello");
+        let expected = doc.transact().state_vector();
         {
             let db_txn = env.new_transaction().unwrap();
             let db = LmdbStore::from(db_txn.bind(&h));
-            db.insert_meta("A", "key1", [1].as_ref()).unwrap();
+            // document state written without its state vector, i.e. by an older version
+            db.insert_doc("doc", &doc.transact()).unwrap(); // OID 1
+            db.remove(&key_state_vector(1)).unwrap();
+            assert_eq!(db.get_state_vector("doc").unwrap().0, None);
             db_txn.commit().unwrap();
         }
 
-        // insert full doc state
-        {
-            let doc = Doc::new();
+        let repair = StoreConfig {
+            sv_read_repair: true,
+            ..StoreConfig::DEFAULT
+        };
+        // read-only stores return computed state vector without storing it
+        let mut reader_txn = env.get_reader().unwrap();
+        let reader =
+            ConfiguredStore::new(LmdbStore::read_only(reader_txn.bind(&h)), repair.clone());
+        assert!(reader.is_read_only());
+        assert_eq!(
+            reader.get_state_vector("doc").unwrap(),
+            (Some(expected.clone()), true)
+        );
+        assert!(reader.get(&key_state_vector(1)).unwrap().is_none());
+        drop(reader);
+        reader_txn.abort();
+
+        // writable stores write it back
+        let db_txn = env.new_transaction().unwrap();
+        let db = ConfiguredStore::new(LmdbStore::from(db_txn.bind(&h)), repair);
+        assert_eq!(
+            db.get_state_vector("doc").unwrap(),
+            (Some(expected.clone()), true)
+        );
+        assert!(db.get(&key_state_vector(1)).unwrap().is_some());
+
+        // repaired state vector covers only the document state
+        let remote = Doc::with_client_id(2);
+        let remote_text = remote.get_or_insert_text("text");
+        let update = {
+            let mut txn = remote.transact_mut();
+            remote_text.push(&mut txn, "world");
+            txn.encode_update_v1()
+        };
+        db.push_update("doc", &update).unwrap();
+        assert_eq!(db.get_state_vector("doc").unwrap(), (Some(expected), false));
+
+        // malformed document state is reported and nothing is written
+        db.upsert(&key_doc(1), &[0xff, 0xff, 0xff]).unwrap();
+        db.remove(&key_state_vector(1)).unwrap();
+        assert!(db.get_state_vector("doc").is_err());
+        assert!(db.get(&key_state_vector(1)).unwrap().is_none());
+        drop(db);
+        db_txn.commit().unwrap();
+    }
+
+    #[test]
+    fn flush_all_parallel() {
+        use yrs_kvstore::worker::shutdown_flush_pipelined;
+
+        let serial_dir = TempDir::new("lmdb-flush_all_parallel-serial").unwrap();
+        let parallel_dir = TempDir::new("lmdb-flush_all_parallel").unwrap();
+        let serial = init_doc_store(&serial_dir);
+        let parallel = init_doc_store(&parallel_dir);
+        let update = |client_id: u64, content: &str| {
+            let doc = Doc::with_client_id(client_id);
             let text = doc.get_or_insert_text("text");
             let mut txn = doc.transact_mut();
-            text.push(&mut txn, "hello world");
-            let db_txn = env.new_transaction().unwrap();
-            let db = LmdbStore::from(db_txn.bind(&h));
-            db.insert_doc("B", &txn).unwrap();
-            db_txn.commit().unwrap();
-        }
-
-        // insert update
-        {
+            text.push(&mut txn, content);
+            txn.encode_update_v1()
+        };
+        let load = |store: &LmdbDocStore, name: &[u8]| {
             let doc = Doc::new();
-            let env = env.clone();
-            let h = h.clone();
-            let _sub = doc.observe_update_v1(move |_, u| {
-                let db_txn = env.new_transaction().unwrap();
-                let db = LmdbStore::from(db_txn.bind(&h));
-                db.push_update("C", &u.update).unwrap();
-                db_txn.commit().unwrap();
-            });
             let text = doc.get_or_insert_text("text");
-            let mut txn = doc.transact_mut();
-            text.push(&mut txn, "hello world");
-        }
+            assert!(store.load_doc(name, &mut doc.transact_mut()).unwrap());
+            let txn = doc.transact();
+            (text.get_string(&txn), txn.state_vector())
+        };
 
-        {
-            let db_txn = env.get_reader().unwrap();
-            let db = LmdbStore::from(db_txn.bind(&h));
-            let mut i = db.iter_docs().unwrap();
-            assert_eq!(i.next(), Some("A".as_bytes().into()));
-            assert_eq!(i.next(), Some("B".as_bytes().into()));
-            assert_eq!(i.next(), Some("C".as_bytes().into()));
-            assert!(i.next().is_none());
+        // the same updates are written into both stores
+        for i in 0..40u64 {
+            let name = format!("doc-{}", i);
+            let doc = Doc::with_client_id(i + 1);
+            let text = doc.get_or_insert_text("text");
+            for j in 0..(i % 7 + 1) {
+                let mut txn = doc.transact_mut();
+                text.push(&mut txn, &format!("{}-{};", i, j));
+                let update = txn.encode_update_v1();
+                serial.push_update(&name, &update).unwrap();
+                parallel.push_update(&name, &update).unwrap();
+            }
+        }
+        let candidates = parallel.flush_candidates().unwrap();
+        assert_eq!(candidates.len(), 40);
+
+        // parallel flush has the same outcome as the serial one
+        let expected = serial.flush_all(1).unwrap();
+        let report = parallel.flush_all(4).unwrap();
+        assert!(report.failed.is_empty());
+        assert!(report.unflushed.is_empty());
+        assert_eq!(report.updates_folded, expected.updates_folded);
+        let sorted = |mut names: Vec<Box<[u8]>>| {
+            names.sort();
+            names
+        };
+        assert_eq!(sorted(report.flushed), sorted(expected.flushed));
+        assert!(parallel.flush_candidates().unwrap().is_empty());
+        assert!(serial.flush_candidates().unwrap().is_empty());
+        for candidate in candidates.iter() {
+            assert_eq!(
+                load(&parallel, &candidate.name),
+                load(&serial, &candidate.name)
+            );
         }
 
-        // clear doc
-        {
-            let db_txn = env.new_transaction().unwrap();
-            let db = LmdbStore::from(db_txn.bind(&h));
-            db.clear_doc("B").unwrap();
-            db_txn.commit().unwrap();
+        // documents changed after being reconstructed are flushed from scratch
+        for i in 0..8u64 {
+            let name = format!("doc-{}", i);
+            parallel.push_update(&name, &update(100 + i, "x")).unwrap();
         }
+        let late = update(200, "late");
+        let report = shutdown_flush_pipelined(
+            parallel.flush_candidates().unwrap(),
+            Duration::MAX,
+            4,
+            |name| {
+                let prepared = parallel.prepare_flush(name)?;
+                assert_eq!(prepared.as_ref().map(|p| p.updates_folded()), Some(1));
+                Ok(prepared)
+            },
+            |name, prepared| {
+                if name == b"doc-0" {
+                    parallel.push_update(name, &late).unwrap();
+                }
+                parallel.apply_flush(name, prepared)
+            },
+        );
+        assert!(report.failed.is_empty());
+        assert_eq!(report.flushed.len(), 8);
+        assert_eq!(report.updates_folded, 9);
+        assert!(parallel.flush_candidates().unwrap().is_empty());
+        let (content, _) = load(&parallel, b"doc-0");
+        assert!(content.contains("late"));
+        assert!(content.contains('x'));
+    }
 
-        {
-            let db_txn = env.get_reader().unwrap();
-            let db = LmdbStore::from(db_txn.bind(&h));
-            let mut i = db.iter_docs().unwrap();
-            assert_eq!(i.next(), Some("A".as_bytes().into()));
-            assert_eq!(i.next(), Some("C".as_bytes().into()));
-            assert!(i.next().is_none());
+    #[test]
+    fn capabilities() {
+        use yrs_kvstore::capabilities::Capabilities;
+        use yrs_kvstore::conformance;
+
+        // store declaring no capabilities, whose peek_back must never be called
+        struct Plain<'s, S>(&'s S);
+
+        impl<'a, 's, S: KVStore<'a>> KVStore<'a> for Plain<'s, S> {
+            type Error = S::Error;
+            type Cursor = S::Cursor;
+            type Entry = S::Entry;
+            type Return = S::Return;
+
+            fn get(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+                self.0.get(key)
+            }
+
+            fn upsert(&self, key: &[u8], value: &[u8]) -> Result<(), Self::Error> {
+                self.0.upsert(key, value)
+            }
+
+            fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+                self.0.remove(key)
+            }
+
+            fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<u32, Self::Error> {
+                self.0.remove_range(from, to)
+            }
+
+            fn iter_range(&self, from: &[u8], to: &[u8]) -> Result<Self::Cursor, Self::Error> {
+                self.0.iter_range(from, to)
+            }
+
+            fn peek_back(&self, _key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+                unimplemented!("BOUNDED_PEEK_BACK is not declared")
+            }
+
+            fn seek(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+                self.0.seek(key)
+            }
         }
+
+        impl<'a, 's, S: KVStore<'a>> DocOps<'a> for Plain<'s, S> where Error: From<S::Error> {}
+
+        let dir = TempDir::new("lmdb-capabilities").unwrap();
+        let env = init_env(&dir);
+        let h = env.create_db("yrs", DbCreate).unwrap();
+        let db_txn = env.new_transaction().unwrap();
+        let db = LmdbStore::from(db_txn.bind(&h));
+        assert_eq!(
+            db.capabilities(),
+            Capabilities::BOUNDED_PEEK_BACK | Capabilities::REVERSE_ITER | Capabilities::SNAPSHOTS
+        );
+        conformance::capabilities(&db);
+        let namespaced = LmdbStore::with_namespace(db_txn.bind(&h), 1);
+        conformance::capabilities(&namespaced);
+        drop(namespaced);
+
+        let update = |content: &str| {
+            let doc = Doc::with_client_id(1);
+            let text = doc.get_or_insert_text("text");
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, content);
+            txn.encode_update_v1()
+        };
+
+        // DocOps falls back to scans, so pushes and loads work without peek_back
+        let plain = Plain(&db);
+        assert_eq!(plain.capabilities(), Capabilities::NONE);
+        conformance::capabilities(&plain);
+        assert_eq!(plain.push_update("doc", &update("a")).unwrap().seq, 1);
+        assert_eq!(plain.push_update("doc", &update("b")).unwrap().seq, 2);
+        assert_eq!(plain.last_update_seq("doc").unwrap(), Some(2));
+        assert_eq!(db.last_update_seq("doc").unwrap(), Some(2));
+        plain.flush_doc("doc").unwrap().unwrap();
+        assert_eq!(plain.push_update("doc", &update("c")).unwrap().seq, 1);
+        let loaded = plain
+            .load_docs(vec![b"doc".as_ref(), b"missing".as_ref()])
+            .unwrap();
+        assert!(loaded[0].1.is_some());
+        assert!(loaded[1].1.is_none());
+        db_txn.commit().unwrap();
     }
 }
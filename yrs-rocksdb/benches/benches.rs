@@ -1,22 +1,56 @@
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
 
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
-use rocksdb::TransactionDB;
-use yrs::encoding::read::{Cursor, Read};
-use yrs::{uuid_v4, Doc, Text, Transact};
+use rocksdb::{Options, TransactionDB, TransactionDBOptions};
+use yrs::{uuid_v4, Doc, StateVector, Text, Transact, TransactionMut};
 
-use yrs_kvstore::DocOps;
-use yrs_rocksdb::RocksDBStore;
+use yrs_kvstore::bench::{self as shared, apply_ops, load_trace, BenchStore, Cleaner, TextOp};
+use yrs_kvstore::conformance::{allocated_bytes, allocations, CountingAlloc};
+use yrs_kvstore::error::Error;
+use yrs_kvstore::format::{KEYSPACE_DOC, V1};
+use yrs_kvstore::memo::MemoStore;
+use yrs_kvstore::{DocOps, HealthHint, KVEntry, KVStore, WriteDurability};
+use yrs_rocksdb::coalescer::{CoalescerConfig, WriteCoalescer};
+use yrs_rocksdb::columns::{self, Column, ColumnLayout};
+use yrs_rocksdb::health::HealthThresholds;
+use yrs_rocksdb::options::open_recommended;
+use yrs_rocksdb::{RocksDBDocStore, RocksDBStore};
 
 fn bench(c: &mut Criterion) {
     insert_doc(c);
     updates(c);
+    updates_durability(c);
+    updates_options(c);
+    updates_columns(c);
+    updates_coalesced(c);
+    load_docs(c);
+    scan_keys(c);
+    memo_lookups(c);
+    shared_scenarios(c);
+}
+
+const TRACE: &str = "editing-trace.bin";
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+/// Runs `f` once and prints the number of heap allocations and allocated bytes it made.
+fn report_allocations<F: FnOnce()>(name: &str, f: F) {
+    let (count, bytes) = (allocations(), allocated_bytes());
+    f();
+    println!(
+        "{}: {} allocations, {} bytes allocated",
+        name,
+        allocations() - count,
+        allocated_bytes() - bytes
+    );
 }
 
 fn insert_doc(c: &mut Criterion) {
     let doc = Doc::new();
-    let ops = read_input("editing-trace.bin");
-    apply_ops(&doc, ops);
+    let ops = load_trace(TRACE);
+    apply_ops(&doc, &ops);
 
     let clean = Cleaner::new("insert-doc-rocksdb");
     let db = init_env(clean.dir());
@@ -39,7 +73,7 @@ fn updates(c: &mut Criterion) {
     let doc = Doc::new();
     let text = doc.get_or_insert_text("text");
 
-    let ops = read_input("editing-trace.bin");
+    let ops = load_trace(TRACE);
 
     let clean = Cleaner::new("insert-doc-lmdb");
     let db = Arc::new(init_env(clean.dir()));
@@ -69,82 +103,441 @@ fn updates(c: &mut Criterion) {
     );
 }
 
-struct Cleaner(&'static str);
+fn updates_durability(c: &mut Criterion) {
+    let ops = load_trace(TRACE);
+    let mut group = c.benchmark_group("updates durability");
+
+    for durability in [
+        WriteDurability::Durable,
+        WriteDurability::Relaxed,
+        WriteDurability::Volatile,
+    ] {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+
+        let clean = Cleaner::new("updates-durability-rocksdb");
+        let db = Arc::new(init_env(clean.dir()));
 
-impl Cleaner {
-    fn new(dir: &'static str) -> Self {
-        Self::cleanup(dir);
-        Cleaner(dir)
+        group.bench_with_input(
+            BenchmarkId::new(format!("{:?}", durability), ops.len()),
+            &(doc, text, &ops, db),
+            |b, (doc, text, ops, db)| {
+                b.iter(|| {
+                    let db = db.clone();
+                    let name = uuid_v4().to_string();
+                    let _sub = doc.observe_update_v1(move |_, e| {
+                        let db_txn = RocksDBStore::with_durability(&db, durability);
+                        db_txn.push_update(&name, &e.update).unwrap();
+                        db_txn.commit().unwrap();
+                    });
+
+                    for op in ops.iter() {
+                        let mut txn = doc.transact_mut();
+                        match op {
+                            TextOp::Insert(idx, txt) => text.insert(&mut txn, *idx, txt),
+                            TextOp::Delete(idx, len) => text.remove_range(&mut txn, *idx, *len),
+                        }
+                    }
+                });
+            },
+        );
     }
+    group.finish();
+}
+
+fn updates_options(c: &mut Criterion) {
+    let ops = load_trace(TRACE);
+    let mut group = c.benchmark_group("updates options");
+
+    for preset in ["default", "recommended"] {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
 
-    fn dir(&self) -> &str {
-        self.0
+        let clean = Cleaner::new("updates-options-rocksdb");
+        let db = if preset == "recommended" {
+            open_recommended(clean.dir()).unwrap()
+        } else {
+            init_env(clean.dir())
+        };
+        let db = Arc::new(db);
+
+        group.bench_with_input(
+            BenchmarkId::new(preset, ops.len()),
+            &(doc, text, &ops, db),
+            |b, (doc, text, ops, db)| {
+                b.iter(|| {
+                    let db = db.clone();
+                    let name = uuid_v4().to_string();
+                    let _sub = doc.observe_update_v1(move |_, e| {
+                        let db_txn = RocksDBStore::from(db.transaction());
+                        let seq_nr = db_txn.push_update(&name, &e.update).unwrap().seq;
+                        if seq_nr % 128 == 0 {
+                            // mix in big document state writes
+                            db_txn.flush_doc(&name).unwrap();
+                        }
+                        db_txn.commit().unwrap();
+                    });
+
+                    for op in ops.iter() {
+                        let mut txn = doc.transact_mut();
+                        match op {
+                            TextOp::Insert(idx, txt) => text.insert(&mut txn, *idx, txt),
+                            TextOp::Delete(idx, len) => text.remove_range(&mut txn, *idx, *len),
+                        }
+                    }
+                });
+            },
+        );
     }
+    group.finish();
+}
 
-    fn cleanup(dir: &str) {
-        if let Err(_) = std::fs::remove_dir_all(dir) {
-            // if dir doesn't exists, ignore
+/// Compares a database keeping all entries in a single column family with the one using
+/// [ColumnLayout]. Besides timings, reports how often writes have been slowed down by compaction
+/// and the space amplification of each database at the end of the run.
+fn updates_columns(c: &mut Criterion) {
+    let ops = load_trace(TRACE);
+    let mut group = c.benchmark_group("updates columns");
+
+    for layout in ["single", "columns"] {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+
+        let clean = Cleaner::new("updates-columns-rocksdb");
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        let db = columns::open(&options, &TransactionDBOptions::default(), clean.dir()).unwrap();
+        let db = Arc::new(db);
+        let stalls = Arc::new(AtomicU64::new(0));
+
+        group.bench_with_input(
+            BenchmarkId::new(layout, ops.len()),
+            &(doc, text, &ops, db.clone()),
+            |b, (doc, text, ops, db)| {
+                b.iter(|| {
+                    let db = db.clone();
+                    let stalls = stalls.clone();
+                    let name = uuid_v4().to_string();
+                    let _sub = doc.observe_update_v1(move |_, e| {
+                        let mut db_txn = RocksDBStore::from(db.transaction());
+                        if layout == "columns" {
+                            db_txn = db_txn.with_columns(ColumnLayout::from_db(&db).unwrap());
+                        }
+                        let seq_nr = db_txn.push_update(&name, &e.update).unwrap().seq;
+                        if seq_nr % 128 == 0 {
+                            // flushes delete ranges of updates and rewrite document state
+                            db_txn.flush_doc(&name).unwrap();
+                            let health = RocksDBStore::health(&db).unwrap();
+                            if health.hint(&HealthThresholds::default()) != HealthHint::Ok {
+                                stalls.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                        db_txn.commit().unwrap();
+                    });
+
+                    for op in ops.iter() {
+                        let mut txn = doc.transact_mut();
+                        match op {
+                            TextOp::Insert(idx, txt) => text.insert(&mut txn, *idx, txt),
+                            TextOp::Delete(idx, len) => text.remove_range(&mut txn, *idx, *len),
+                        }
+                    }
+                });
+            },
+        );
+
+        // space amplification: size of all table files relative to the size of live data
+        let (mut sst_bytes, mut live_bytes) = (0, 0);
+        for column in Column::ALL.iter() {
+            let cf = db.cf_handle(column.name()).unwrap();
+            let prop = |name| db.property_int_value_cf(cf, name).unwrap().unwrap_or(0);
+            sst_bytes += prop("rocksdb.total-sst-files-size");
+            live_bytes += prop("rocksdb.estimate-live-data-size");
         }
+        println!(
+            "{}: {} stalled flushes, space amplification {:.2}",
+            layout,
+            stalls.load(Ordering::Relaxed),
+            sst_bytes as f64 / live_bytes.max(1) as f64
+        );
     }
+    group.finish();
 }
 
-impl Drop for Cleaner {
-    fn drop(&mut self) {
-        Self::cleanup(self.dir());
-    }
+fn updates_coalesced(c: &mut Criterion) {
+    const WRITERS: usize = 8;
+    const UPDATES: usize = 200;
+    let mut group = c.benchmark_group("concurrent updates");
+
+    // every writer pushes small updates into its own document
+    let updates: Vec<Vec<u8>> = {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        (0..UPDATES)
+            .map(|_| {
+                let mut txn = doc.transact_mut();
+                text.push(&mut txn, "a");
+                txn.encode_update_v1()
+            })
+            .collect()
+    };
+    let updates = Arc::new(updates);
+
+    let clean = Cleaner::new("updates-per-txn-rocksdb");
+    let db = Arc::new(init_env(clean.dir()));
+    group.bench_with_input(
+        BenchmarkId::new("transaction per update", WRITERS * UPDATES),
+        &(db, updates.clone()),
+        |b, (db, updates)| {
+            b.iter(|| {
+                let writers: Vec<_> = (0..WRITERS)
+                    .map(|_| {
+                        let db = db.clone();
+                        let updates = updates.clone();
+                        std::thread::spawn(move || {
+                            let name = uuid_v4().to_string();
+                            for update in updates.iter() {
+                                let db_txn = RocksDBStore::from(db.transaction());
+                                db_txn.push_update(&name, update).unwrap();
+                                db_txn.commit().unwrap();
+                            }
+                        })
+                    })
+                    .collect();
+                for writer in writers {
+                    writer.join().unwrap();
+                }
+            });
+        },
+    );
+
+    let clean = Cleaner::new("updates-coalesced-rocksdb");
+    let db = Arc::new(init_env(clean.dir()));
+    let coalescer = Arc::new(WriteCoalescer::spawn(db, CoalescerConfig::default()));
+    group.bench_with_input(
+        BenchmarkId::new("coalesced", WRITERS * UPDATES),
+        &(coalescer, updates),
+        |b, (coalescer, updates)| {
+            b.iter(|| {
+                let (tx, rx) = mpsc::channel();
+                let writers: Vec<_> = (0..WRITERS)
+                    .map(|_| {
+                        let coalescer = coalescer.clone();
+                        let updates = updates.clone();
+                        let tx = tx.clone();
+                        std::thread::spawn(move || {
+                            let name = uuid_v4().to_string();
+                            for i in 0..updates.len() {
+                                let (doc_name, updates, tx) =
+                                    (name.clone(), updates.clone(), tx.clone());
+                                coalescer.submit(
+                                    &name,
+                                    move |db| db.push_update(&doc_name, &updates[i]),
+                                    move |result| tx.send(result.is_ok()).unwrap(),
+                                );
+                            }
+                        })
+                    })
+                    .collect();
+                for writer in writers {
+                    writer.join().unwrap();
+                }
+                // wait until all updates are committed
+                for _ in 0..WRITERS * UPDATES {
+                    assert!(rx.recv().unwrap());
+                }
+            });
+        },
+    );
+    group.finish();
 }
 
-fn init_env(dir: &str) -> TransactionDB {
-    let db = TransactionDB::open_default(dir).unwrap();
-    db
+fn load_docs(c: &mut Criterion) {
+    const DOCS: usize = 500;
+    let mut group = c.benchmark_group("load documents");
+
+    let clean = Cleaner::new("load-docs-rocksdb");
+    let db = init_env(clean.dir());
+    let names: Vec<String> = (0..DOCS).map(|i| format!("doc-{}", i)).collect();
+    {
+        let db_txn = RocksDBStore::from(db.transaction());
+        for (i, name) in names.iter().enumerate() {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            text.push(&mut doc.transact_mut(), &format!("hello {}", i));
+            db_txn.insert_doc(name, &doc.transact()).unwrap();
+        }
+        db_txn.commit().unwrap();
+    }
+
+    group.bench_with_input(
+        BenchmarkId::new("per-doc transactions", DOCS),
+        &(&names, &db),
+        |b, (names, db)| {
+            b.iter(|| {
+                for name in names.iter() {
+                    let doc = Doc::new();
+                    let db_txn = RocksDBStore::from(db.transaction());
+                    db_txn.load_doc(name, &mut doc.transact_mut()).unwrap();
+                }
+            });
+        },
+    );
+    group.bench_with_input(
+        BenchmarkId::new("batched", DOCS),
+        &(&names, &db),
+        |b, (names, db)| {
+            b.iter(|| {
+                let db_txn = RocksDBStore::with_snapshot(db);
+                let docs = db_txn
+                    .load_docs(names.iter().map(|name| name.as_bytes()))
+                    .unwrap();
+                assert_eq!(docs.len(), names.len());
+            });
+        },
+    );
+    group.finish();
 }
 
-#[derive(Clone)]
-enum TextOp {
-    Insert(u32, String),
-    Delete(u32, u32),
+/// Scans entries of documents with large states, comparing the cursor, which copies every value,
+/// with [KVStore::iter_keys_range], which only reads keys and value lengths.
+fn scan_keys(c: &mut Criterion) {
+    const DOCS: usize = 200;
+    let mut group = c.benchmark_group("scan keys");
+
+    let clean = Cleaner::new("scan-keys-rocksdb");
+    let db = init_env(clean.dir());
+    {
+        let db_txn = RocksDBStore::from(db.transaction());
+        let content = "lorem ipsum ".repeat(64 * 1024 / 12);
+        for i in 0..DOCS {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            text.push(&mut doc.transact_mut(), &content);
+            db_txn
+                .insert_doc(&format!("doc-{}", i), &doc.transact())
+                .unwrap();
+        }
+        db_txn.commit().unwrap();
+    }
+    let (from, to) = ([V1, KEYSPACE_DOC], [V1, KEYSPACE_DOC + 1]);
+    let cursor = |db: &TransactionDB| {
+        let db_txn = RocksDBStore::from(db.transaction());
+        let mut size = 0;
+        for e in db_txn.iter_range(&from, &to).unwrap() {
+            size += e.key().len() + e.value().len();
+        }
+        assert!(size > DOCS * 64 * 1024);
+    };
+    let keys_only = |db: &TransactionDB| {
+        let db_txn = RocksDBStore::from(db.transaction());
+        let mut size = 0;
+        db_txn
+            .iter_keys_range(&from, &to, &mut |key, len| {
+                size += key.len() + len;
+                true
+            })
+            .unwrap();
+        assert!(size > DOCS * 64 * 1024);
+    };
+    report_allocations("scan keys/cursor", || cursor(&db));
+    report_allocations("scan keys/keys only", || keys_only(&db));
+
+    group.bench_with_input(BenchmarkId::new("cursor", DOCS), &db, |b, db| {
+        b.iter(|| cursor(db));
+    });
+    group.bench_with_input(BenchmarkId::new("keys only", DOCS), &db, |b, db| {
+        b.iter(|| keys_only(db));
+    });
+    group.finish();
 }
 
-fn apply_ops(doc: &Doc, ops: Vec<TextOp>) {
-    let text = doc.get_or_insert_text("text");
-    for op in ops.iter() {
-        let mut txn = doc.transact_mut();
-        match op {
-            TextOp::Insert(idx, txt) => text.insert(&mut txn, *idx, txt),
-            TextOp::Delete(idx, len) => text.remove_range(&mut txn, *idx, *len),
+/// Runs sync handshakes - a state vector lookup followed by a diff - of small documents, comparing
+/// a plain transaction with one wrapped in [MemoStore], which resolves every document only once.
+fn memo_lookups(c: &mut Criterion) {
+    const DOCS: usize = 200;
+    let mut group = c.benchmark_group("memoized lookups");
+
+    let clean = Cleaner::new("memo-lookups-rocksdb");
+    let db = init_env(clean.dir());
+    let names: Vec<String> = (0..DOCS).map(|i| format!("doc-{}", i)).collect();
+    {
+        let db_txn = RocksDBStore::from(db.transaction());
+        for (i, name) in names.iter().enumerate() {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            text.push(&mut doc.transact_mut(), &format!("hello {}", i));
+            db_txn.insert_doc(name, &doc.transact()).unwrap();
+        }
+        db_txn.commit().unwrap();
+    }
+
+    fn exchange<'a, S: DocOps<'a>>(db: &S, names: &[String])
+    where
+        Error: From<S::Error>,
+    {
+        for name in names.iter() {
+            let (sv, _) = db.get_state_vector(name).unwrap();
+            assert!(sv.is_some());
+            db.get_diff(name, &StateVector::default()).unwrap().unwrap();
         }
     }
+
+    group.bench_with_input(
+        BenchmarkId::new("plain", DOCS),
+        &(&names, &db),
+        |b, (names, db)| {
+            b.iter(|| exchange(&RocksDBStore::from(db.transaction()), names));
+        },
+    );
+    group.bench_with_input(
+        BenchmarkId::new("memo", DOCS),
+        &(&names, &db),
+        |b, (names, db)| {
+            b.iter(|| exchange(&MemoStore::new(RocksDBStore::from(db.transaction())), names));
+        },
+    );
+    group.finish();
 }
 
-fn read_input(fpath: &str) -> Vec<TextOp> {
-    use std::fs::File;
-    use yrs::updates::decoder::DecoderV1;
-
-    let mut f = File::open(fpath).unwrap();
-    let mut buf = Vec::new();
-    std::io::Read::read_to_end(&mut f, &mut buf).unwrap();
-    let mut decoder = DecoderV1::new(Cursor::new(buf.as_slice()));
-    let len: usize = decoder.read_var().unwrap();
-    let mut result = Vec::with_capacity(len);
-    for _ in 0..len {
-        let op = {
-            match decoder.read_var().unwrap() {
-                1u32 => {
-                    let idx = decoder.read_var().unwrap();
-                    let chunk = decoder.read_string().unwrap();
-                    TextOp::Insert(idx, chunk.to_string())
-                }
-                2u32 => {
-                    let idx = decoder.read_var().unwrap();
-                    let len = decoder.read_var().unwrap();
-                    TextOp::Delete(idx, len)
-                }
-                other => panic!("unrecognized TextOp tag type: {}", other),
-            }
-        };
-        result.push(op);
+/// Scenarios shared with other backends, see [yrs_kvstore::bench].
+fn shared_scenarios(c: &mut Criterion) {
+    let trace = load_trace(TRACE);
+    let clean = Cleaner::new("shared-scenarios-rocksdb");
+    let store = Store(RocksDBDocStore::from(init_env(clean.dir())));
+
+    shared::load_doc(c, &store, &trace);
+    shared::flush_doc(c, &store, &trace);
+    shared::get_diff(c, &store, &trace);
+    shared::mixed_workload(c, &store, &trace);
+}
+
+struct Store(RocksDBDocStore);
+
+impl BenchStore for Store {
+    const NAME: &'static str = "rocksdb";
+
+    fn push_update(&self, name: &str, update: &[u8]) -> Result<u32, Error> {
+        Ok(self.0.push_update(name, update)?.seq)
+    }
+
+    fn flush_doc(&self, name: &str) -> Result<(), Error> {
+        self.0.flush_doc(name)?;
+        Ok(())
+    }
+
+    fn load_doc(&self, name: &str, txn: &mut TransactionMut) -> Result<bool, Error> {
+        self.0.load_doc(name, txn)
+    }
+
+    fn get_diff(&self, name: &str, sv: &StateVector) -> Result<Option<Vec<u8>>, Error> {
+        self.0.get_diff(name, sv)
     }
-    result
+}
+
+fn init_env(dir: &str) -> TransactionDB {
+    let db = TransactionDB::open_default(dir).unwrap();
+    db
 }
 
 criterion_group! {
@@ -0,0 +1,19 @@
+use rocksdb::TransactionDB;
+use yrs_kvstore::bench::{load_trace, trace_updates, Cleaner};
+use yrs_kvstore::workload::{compare_flush_policies, comparison_table, FLUSH_POLICIES};
+use yrs_rocksdb::RocksDBStore;
+
+fn main() {
+    let cleaner = Cleaner::new("example-flush-policies");
+    let db: TransactionDB = TransactionDB::open_default(cleaner.dir()).unwrap();
+
+    // replay editing trace under every flush policy and compare the amount of written data
+    let updates = trace_updates(&load_trace("editing-trace.bin"));
+    let runs = compare_flush_policies(
+        || RocksDBStore::from(db.transaction()),
+        &updates,
+        &FLUSH_POLICIES,
+    )
+    .unwrap();
+    print!("{}", comparison_table(&runs));
+}
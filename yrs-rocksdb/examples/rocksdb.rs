@@ -1,36 +1,13 @@
 use rocksdb::TransactionDB;
 use std::sync::Arc;
 use std::time::Instant;
-use yrs::encoding::read::{Cursor, Read};
-use yrs::{Doc, Text, Transact};
+use yrs::{Doc, Transact};
+use yrs_kvstore::bench::{load_trace, Cleaner};
+use yrs_kvstore::error::Error;
+use yrs_kvstore::persist::{persist_on_update_with, PersistConfig};
 use yrs_kvstore::DocOps;
 use yrs_rocksdb::RocksDBStore;
 
-struct Cleaner(&'static str);
-
-impl Cleaner {
-    fn new(dir: &'static str) -> Self {
-        Self::cleanup(dir);
-        Cleaner(dir)
-    }
-
-    fn dir(&self) -> &str {
-        self.0
-    }
-
-    fn cleanup(dir: &str) {
-        if let Err(_) = std::fs::remove_dir_all(dir) {
-            // if dir doesn't exists, ignore
-        }
-    }
-}
-
-impl Drop for Cleaner {
-    fn drop(&mut self) {
-        Self::cleanup(self.dir());
-    }
-}
-
 fn main() {
     let cleaner = Cleaner::new("example-rocksdb");
     let db: TransactionDB = TransactionDB::open_default(cleaner.dir()).unwrap();
@@ -41,18 +18,33 @@ fn main() {
     let doc = Doc::new();
     let text = doc.get_or_insert_text("text");
 
-    // store subsequent updates automatically
+    // store subsequent updates automatically, reporting the ones which failed to persist
     let _sub = {
         let db = db.clone();
-        doc.observe_update_v1(move |_, e| {
-            let txn = RocksDBStore::from(db.transaction());
-            let i = txn.push_update(doc_name, &e.update).unwrap();
-            if i % 128 == 0 {
-                // compact updates into document
-                txn.flush_doc(doc_name).unwrap();
-            }
-            txn.commit().unwrap();
-        })
+        persist_on_update_with(
+            &doc,
+            move |name, update| {
+                let txn = RocksDBStore::from(db.transaction());
+                let i = txn.push_update(name, update)?.seq;
+                if i.is_multiple_of(128) {
+                    // compact updates into document
+                    if let Some(outcome) = txn.flush_doc(name)? {
+                        println!(
+                            "flushed {} updates in {}us: {}B -> {}B",
+                            outcome.updates_folded,
+                            outcome.duration.as_micros(),
+                            outcome.bytes_before,
+                            outcome.bytes_after
+                        );
+                    }
+                }
+                txn.commit().map_err(Error::other)?;
+                Ok(())
+            },
+            doc_name,
+            |failure| eprintln!("failed to persist update: {}", failure.error),
+            PersistConfig { retry_queue: 64 },
+        )
         .unwrap()
     };
 
@@ -64,15 +56,11 @@ fn main() {
     }
 
     // execute editing trace
-    let ops = read_input("editing-trace.bin");
+    let ops = load_trace("editing-trace.bin");
     let now = Instant::now();
     let ops_count = ops.len();
     for op in ops.iter() {
-        let mut txn = doc.transact_mut();
-        match op {
-            TextOp::Insert(idx, txt) => text.insert(&mut txn, *idx, txt),
-            TextOp::Delete(idx, len) => text.remove_range(&mut txn, *idx, *len),
-        }
+        op.apply(&text, &mut doc.transact_mut());
     }
     let elapsed = Instant::now().duration_since(now);
     println!(
@@ -81,39 +69,3 @@ fn main() {
         elapsed.as_millis()
     );
 }
-
-enum TextOp {
-    Insert(u32, String),
-    Delete(u32, u32),
-}
-
-fn read_input(fpath: &str) -> Vec<TextOp> {
-    use std::fs::File;
-    use yrs::updates::decoder::DecoderV1;
-
-    let mut f = File::open(fpath).unwrap();
-    let mut buf = Vec::new();
-    std::io::Read::read_to_end(&mut f, &mut buf).unwrap();
-    let mut decoder = DecoderV1::new(Cursor::new(buf.as_slice()));
-    let len: usize = decoder.read_var().unwrap();
-    let mut result = Vec::with_capacity(len);
-    for _ in 0..len {
-        let op = {
-            match decoder.read_var().unwrap() {
-                1u32 => {
-                    let idx = decoder.read_var().unwrap();
-                    let chunk = decoder.read_string().unwrap();
-                    TextOp::Insert(idx, chunk.to_string())
-                }
-                2u32 => {
-                    let idx = decoder.read_var().unwrap();
-                    let len = decoder.read_var().unwrap();
-                    TextOp::Delete(idx, len)
-                }
-                other => panic!("unrecognized TextOp tag type: {}", other),
-            }
-        };
-        result.push(op);
-    }
-    result
-}
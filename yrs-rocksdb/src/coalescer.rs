@@ -0,0 +1,240 @@
+//! Batching of many small write operations into shared transactions.
+//!
+//! Committing a separate transaction for every pushed update is dominated by per-transaction
+//! overhead (lock acquisition, write batch allocation and, with durable writes, syncing of
+//! write-ahead log). [WriteCoalescer] accepts operations from many threads and executes them in
+//! batches, each batch within a single transaction committed once it reaches
+//! [CoalescerConfig::max_batch] operations or once the oldest operation in it waited for
+//! [CoalescerConfig::max_delay].
+//!
+//! Operations are routed to shards by a hash of the document name passed along with them. Every
+//! shard is served by a single thread executing its operations in submission order, so operations
+//! submitted for the same document are applied in the order they were submitted.
+//!
+//! Once a batch is committed, every operation in it is completed by calling its `done` callback on
+//! the shard thread with the result of that operation. If the commit fails, every operation of the
+//! batch fails with the commit error. Since [Error] cannot be sent between threads, callers waiting
+//! for the result on another thread are expected to map it into a type that can:
+//!
+//! ```rust,ignore
+//! let coalescer = WriteCoalescer::spawn(db, CoalescerConfig::default());
+//! let (tx, rx) = std::sync::mpsc::sync_channel(1);
+//! coalescer.submit(
+//!     "my-doc-name",
+//!     move |db| db.push_update("my-doc-name", &update),
+//!     move |result| {
+//!         let _ = tx.send(result.map(|receipt| receipt.seq).map_err(|e| e.to_string()));
+//!     },
+//! );
+//! let seq_nr = rx.recv().unwrap()?;
+//! ```
+//!
+//! Operations of different shards run in concurrent transactions, so they may still conflict on
+//! keys shared between documents (i.e. when creating new documents). Such conflicts are resolved
+//! by RocksDB locking and can fail an operation with a lock timeout error.
+
+use crate::RocksDBStore;
+use rocksdb::{SingleThreaded, ThreadMode, TransactionDB};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use yrs_kvstore::error::Error;
+use yrs_kvstore::WriteDurability;
+
+/// Configuration of the [WriteCoalescer].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoalescerConfig {
+    /// Number of shards, each one served by its own thread and transaction.
+    pub shards: usize,
+    /// Maximum number of operations executed within a single transaction.
+    pub max_batch: usize,
+    /// Maximum time an operation waits for other operations to join its batch.
+    pub max_delay: Duration,
+    /// Durability of batch commits (see [write_options](crate::write_options)).
+    pub durability: WriteDurability,
+}
+
+impl Default for CoalescerConfig {
+    fn default() -> Self {
+        CoalescerConfig {
+            shards: 4,
+            max_batch: 128,
+            max_delay: Duration::from_millis(2),
+            durability: WriteDurability::Relaxed,
+        }
+    }
+}
+
+/// Completes an executed operation once the outcome of its batch commit is known. `None` means
+/// the commit succeeded.
+type Completion = Box<dyn FnOnce(Option<&rocksdb::Error>)>;
+
+/// Operation executed within a batch transaction. Returns its completion and the error of
+/// rolling back the operation's writes, if it failed and that rollback failed as well.
+type Job<T> = Box<
+    dyn for<'a> FnOnce(&RocksDBStore<'a, TransactionDB<T>>) -> (Completion, Option<rocksdb::Error>)
+        + Send,
+>;
+
+struct Queue<T: ThreadMode> {
+    jobs: VecDeque<(Instant, Job<T>)>,
+    shutdown: bool,
+}
+
+struct Shard<T: ThreadMode> {
+    queue: Mutex<Queue<T>>,
+    signal: Condvar,
+}
+
+impl<T: ThreadMode> Shard<T> {
+    /// Blocks until a batch of operations is ready to be executed. Returns `None` once coalescer
+    /// has been shut down and all queued operations have been executed.
+    fn next_batch(&self, config: &CoalescerConfig) -> Option<Vec<Job<T>>> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            match queue.jobs.front() {
+                Some(_) if queue.shutdown || queue.jobs.len() >= config.max_batch => break,
+                Some((submitted, _)) => {
+                    let due = *submitted + config.max_delay;
+                    if due <= now {
+                        break;
+                    }
+                    queue = self.signal.wait_timeout(queue, due - now).unwrap().0;
+                }
+                None if queue.shutdown => return None,
+                None => queue = self.signal.wait(queue).unwrap(),
+            }
+        }
+        let len = queue.jobs.len().min(config.max_batch.max(1));
+        Some(queue.jobs.drain(..len).map(|(_, job)| job).collect())
+    }
+}
+
+/// Pool of shard threads executing submitted operations in batched transactions. See
+/// [module documentation](self) for details.
+///
+/// Dropping the coalescer shuts it down the same way as [WriteCoalescer::shutdown].
+pub struct WriteCoalescer<T: ThreadMode + 'static = SingleThreaded> {
+    shards: Vec<Arc<Shard<T>>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl<T: ThreadMode + 'static> WriteCoalescer<T> {
+    /// Spawns shard threads executing operations over a given `db`.
+    pub fn spawn(db: Arc<TransactionDB<T>>, config: CoalescerConfig) -> Self {
+        let shards: Vec<_> = (0..config.shards.max(1))
+            .map(|_| {
+                Arc::new(Shard {
+                    queue: Mutex::new(Queue {
+                        jobs: VecDeque::new(),
+                        shutdown: false,
+                    }),
+                    signal: Condvar::new(),
+                })
+            })
+            .collect();
+        let threads = shards
+            .iter()
+            .map(|shard| {
+                let shard = shard.clone();
+                let db = db.clone();
+                let config = config.clone();
+                std::thread::spawn(move || {
+                    while let Some(batch) = shard.next_batch(&config) {
+                        execute(&db, &config, batch);
+                    }
+                })
+            })
+            .collect();
+        WriteCoalescer { shards, threads }
+    }
+
+    /// Submits an operation `op` over a document with a given `name`. Operation is executed on
+    /// a shard thread, within a transaction shared with other operations, after all operations
+    /// previously submitted for the same document. Once that transaction is committed, `done` is
+    /// called on the same thread with the result of the operation.
+    ///
+    /// If `op` fails, its writes are rolled back, while other operations of the batch are not
+    /// affected. Operations submitted after [WriteCoalescer::shutdown] has started are executed
+    /// before the shard threads exit.
+    pub fn submit<K, F, D, R>(&self, name: &K, op: F, done: D)
+    where
+        K: AsRef<[u8]> + ?Sized,
+        F: for<'a> FnOnce(&RocksDBStore<'a, TransactionDB<T>>) -> Result<R, Error> + Send + 'static,
+        D: FnOnce(Result<R, Error>) + Send + 'static,
+        R: 'static,
+    {
+        let job: Job<T> = Box::new(move |db| {
+            db.set_savepoint();
+            let (result, rollback_error) = match op(db) {
+                Ok(value) => (Ok(value), None),
+                Err(e) => (Err(e), db.rollback_to_savepoint().err()),
+            };
+            let completion: Completion = Box::new(move |commit_error| {
+                done(match (result, commit_error) {
+                    (Ok(_), Some(e)) => Err(Error::other(e.clone())),
+                    (result, _) => result,
+                })
+            });
+            (completion, rollback_error)
+        });
+        let shard = &self.shards[shard_of(name.as_ref(), self.shards.len())];
+        let mut queue = shard.queue.lock().unwrap();
+        queue.jobs.push_back((Instant::now(), job));
+        shard.signal.notify_all();
+    }
+
+    /// Executes all operations remaining in the queues right away (without waiting for
+    /// [CoalescerConfig::max_delay]) and waits for shard threads to finish.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        for shard in self.shards.iter() {
+            let mut queue = shard.queue.lock().unwrap();
+            queue.shutdown = true;
+            shard.signal.notify_all();
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<T: ThreadMode + 'static> Drop for WriteCoalescer<T> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Executes a batch of operations within a single transaction and completes them with the outcome
+/// of its commit.
+fn execute<T: ThreadMode>(db: &TransactionDB<T>, config: &CoalescerConfig, batch: Vec<Job<T>>) {
+    let txn = RocksDBStore::with_durability(db, config.durability);
+    let mut completions = Vec::with_capacity(batch.len());
+    let mut failure = None;
+    for job in batch {
+        let (completion, rollback_error) = job(&txn);
+        completions.push(completion);
+        // writes of a failed operation could not be undone, so the batch cannot be committed
+        failure = failure.or(rollback_error);
+    }
+    let failure = match failure {
+        Some(e) => Some(e),
+        None => txn.commit().err(),
+    };
+    for completion in completions {
+        completion(failure.as_ref());
+    }
+}
+
+fn shard_of(name: &[u8], shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
@@ -0,0 +1,236 @@
+//! Layout of a RocksDB database, which keeps entries of different kinds in separate column
+//! families.
+//!
+//! By default all entries written by [DocOps](yrs_kvstore::DocOps) live in a single column
+//! family, even though they behave very differently: update entries are small, written often and
+//! deleted soon after (when pending updates are flushed), while document states are large and
+//! rarely rewritten. Mixing them makes compaction rewrite document states over and over, and
+//! leaves ranges of deleted updates as tombstones within the same files. [ColumnLayout] routes
+//! every entry to one of three column families, chosen by the kind of its key (see [Column]), so
+//! each of them can be compacted - and tuned - on its own:
+//!
+//! ```rust,ignore
+//! let db = columns::open(&options, &TransactionDBOptions::default(), "my-db-path")?;
+//! let layout = ColumnLayout::from_db(&db).unwrap();
+//!
+//! let db_txn = RocksDBStore::from(db.transaction()).with_columns(layout);
+//! db_txn.push_update("my-doc-name", &update)?;
+//! db_txn.commit()?;
+//! ```
+//!
+//! Metadata column family is the default one, so databases written without a layout keep all of
+//! their entries there. Once a database has been used with a [ColumnLayout], all transactions
+//! writing to it must use it as well, otherwise entries written by them won't be found.
+//! Existing databases can be moved to the column layout with [ColumnLayout::migrate].
+
+use crate::read_options;
+use rocksdb::{
+    ColumnFamily, ColumnFamilyDescriptor, Options, ThreadMode, TransactionDB, TransactionDBOptions,
+    DEFAULT_COLUMN_FAMILY_NAME,
+};
+use std::path::Path;
+use yrs_kvstore::keys::{
+    split_namespace, KEYSPACE_ARCHIVE, KEYSPACE_DOC, KEYSPACE_NAMESPACE, SUB_CHANNEL, SUB_DOC,
+    SUB_SNAPSHOT, SUB_UPDATE, V1,
+};
+
+/// Name of the column family storing update entries.
+pub const CF_UPDATES: &str = "yrs-updates";
+
+/// Name of the column family storing document states.
+pub const CF_STATES: &str = "yrs-states";
+
+/// Length of the document key prefix preceding its tag byte: version byte, keyspace byte and
+/// 4-byte document OID.
+const DOC_TAG_OFFSET: usize = 6;
+
+/// Column family an entry is stored in, decided by the kind of its key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Column {
+    /// Document name to OID index, store settings, document metadata, state vectors and every
+    /// other small entry. Stored in the default column family.
+    Meta,
+    /// Update entries of all channels ([SUB_UPDATE] and [SUB_CHANNEL]).
+    Updates,
+    /// Document states ([SUB_DOC]), snapshots ([SUB_SNAPSHOT]) and archived documents
+    /// ([KEYSPACE_ARCHIVE]).
+    States,
+}
+
+impl Column {
+    /// All columns, in the order of their column families.
+    pub const ALL: [Column; 3] = [Column::Meta, Column::Updates, Column::States];
+
+    /// Returns the column a given `key` is stored in. Keys of namespaced stores are routed by the
+    /// key within their namespace.
+    pub fn of(key: &[u8]) -> Self {
+        Self::of_prefix(key).unwrap_or(Column::Meta)
+    }
+
+    /// Returns the column storing all keys starting with a given `prefix`, or [None] if they may
+    /// be stored in different columns.
+    pub fn of_prefix(prefix: &[u8]) -> Option<Self> {
+        match prefix {
+            [] | [V1] => None,
+            [V1, KEYSPACE_NAMESPACE, ..] => {
+                let (_, key) = split_namespace(prefix)?;
+                Self::of_prefix(key)
+            }
+            [V1, KEYSPACE_DOC, ..] => match prefix.get(DOC_TAG_OFFSET)? {
+                &SUB_UPDATE | &SUB_CHANNEL => Some(Column::Updates),
+                &SUB_DOC | &SUB_SNAPSHOT => Some(Column::States),
+                _ => Some(Column::Meta),
+            },
+            [V1, KEYSPACE_ARCHIVE, ..] => Some(Column::States),
+            _ => Some(Column::Meta),
+        }
+    }
+
+    /// Returns columns which may store keys within `from`..`to` range.
+    pub fn of_range(from: &[u8], to: &[u8]) -> &'static [Column] {
+        let common = from.iter().zip(to).take_while(|(a, b)| a == b).count();
+        // all keys between `prefix + [b]` and `prefix + [b + 1]` start with `prefix + [b]`
+        let prefix = match (from.get(common), to.get(common)) {
+            (Some(&a), Some(&b)) if to.len() == common + 1 && a.checked_add(1) == Some(b) => {
+                &from[..=common]
+            }
+            _ => &from[..common],
+        };
+        match Self::of_prefix(prefix) {
+            Some(Column::Meta) => &[Column::Meta],
+            Some(Column::Updates) => &[Column::Updates],
+            Some(Column::States) => &[Column::States],
+            None => &Self::ALL,
+        }
+    }
+
+    /// Returns the name of the column family storing this column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Meta => DEFAULT_COLUMN_FAMILY_NAME,
+            Column::Updates => CF_UPDATES,
+            Column::States => CF_STATES,
+        }
+    }
+}
+
+/// Handles of the column families used by [RocksDBStore](crate::RocksDBStore) configured with
+/// [RocksDBStore::with_columns](crate::RocksDBStore::with_columns).
+#[derive(Clone, Copy)]
+pub struct ColumnLayout<'a> {
+    meta: &'a ColumnFamily,
+    updates: &'a ColumnFamily,
+    states: &'a ColumnFamily,
+}
+
+impl<'a> ColumnLayout<'a> {
+    /// Creates a layout from handles of column families storing each [Column].
+    pub fn new(
+        meta: &'a ColumnFamily,
+        updates: &'a ColumnFamily,
+        states: &'a ColumnFamily,
+    ) -> Self {
+        ColumnLayout {
+            meta,
+            updates,
+            states,
+        }
+    }
+
+    /// Looks up column families of a given `db` by their names. Returns [None] if any of them
+    /// doesn't exist, i.e. when database has not been opened with [open].
+    pub fn from_db(db: &'a TransactionDB) -> Option<Self> {
+        Some(ColumnLayout {
+            meta: db.cf_handle(DEFAULT_COLUMN_FAMILY_NAME)?,
+            updates: db.cf_handle(CF_UPDATES)?,
+            states: db.cf_handle(CF_STATES)?,
+        })
+    }
+
+    /// Returns a handle of the column family storing a given `column`.
+    pub fn handle(&self, column: Column) -> &'a ColumnFamily {
+        match column {
+            Column::Meta => self.meta,
+            Column::Updates => self.updates,
+            Column::States => self.states,
+        }
+    }
+
+    /// Returns a handle of the column family storing a given `key`.
+    #[inline]
+    pub fn handle_of(&self, key: &[u8]) -> &'a ColumnFamily {
+        self.handle(Column::of(key))
+    }
+
+    /// Moves entries of a `db` written without a layout from the default column family into
+    /// the column families they belong to. Entries are moved in separate transactions, each one
+    /// examining at most `batch_size` entries, so migration can be resumed after it has been
+    /// interrupted. Returns the number of moved entries.
+    ///
+    /// Transactions writing to the database without this layout must not run concurrently with
+    /// the migration, as entries they write to the default column family may be left behind.
+    pub fn migrate<T: ThreadMode>(
+        &self,
+        db: &TransactionDB<T>,
+        batch_size: usize,
+    ) -> Result<u64, rocksdb::Error> {
+        let batch_size = batch_size.max(1);
+        let mut moved = 0;
+        let mut from = Vec::new();
+        loop {
+            let txn = db.transaction();
+            let mut batch = Vec::new();
+            let next = {
+                let mut raw = txn.raw_iterator_cf_opt(self.meta, read_options(&txn));
+                raw.seek(&from);
+                let mut examined = 0;
+                while let Some((key, value)) = raw.item() {
+                    if examined == batch_size {
+                        break;
+                    }
+                    let column = Column::of(key);
+                    if column != Column::Meta {
+                        batch.push((column, key.to_vec(), value.to_vec()));
+                    }
+                    examined += 1;
+                    raw.next();
+                }
+                raw.status()?;
+                raw.key().map(<[u8]>::to_vec)
+            };
+            for (column, key, value) in batch.iter() {
+                txn.put_cf(self.handle(*column), key, value)?;
+                txn.delete_cf(self.meta, key)?;
+            }
+            txn.commit()?;
+            moved += batch.len() as u64;
+            match next {
+                Some(key) => from = key,
+                None => return Ok(moved),
+            }
+        }
+    }
+}
+
+/// Returns descriptors of all column families used by [ColumnLayout], created with given
+/// `options`. Column families can be tuned separately by building descriptors with
+/// [Column::name] instead.
+pub fn descriptors(options: &Options) -> Vec<ColumnFamilyDescriptor> {
+    Column::ALL
+        .iter()
+        .map(|column| ColumnFamilyDescriptor::new(column.name(), options.clone()))
+        .collect()
+}
+
+/// Opens a database under a given `path` with all column families used by [ColumnLayout],
+/// creating the ones which don't exist yet. Existing single column family databases can be
+/// opened this way before being migrated with [ColumnLayout::migrate].
+pub fn open<P: AsRef<Path>>(
+    options: &Options,
+    txn_options: &TransactionDBOptions,
+    path: P,
+) -> Result<TransactionDB, rocksdb::Error> {
+    let mut options = options.clone();
+    options.create_missing_column_families(true);
+    TransactionDB::open_cf_descriptors(&options, txn_options, path, descriptors(&options))
+}
@@ -0,0 +1,141 @@
+//! Health of a RocksDB database, read from its properties.
+//!
+//! RocksDB protects itself from compaction falling behind by slowing down and eventually stopping
+//! writes (a *write stall*), i.e. when too many files pile up at level 0 or when the estimated
+//! amount of data awaiting compaction grows too large. Without any signal, callers observe it only
+//! as a sudden increase of write latency. [RocksDBHealth] collects the relevant properties and
+//! [RocksDBHealth::hint] turns them into a [HealthHint], which callers can use to slow down
+//! producers of updates:
+//!
+//! ```rust,ignore
+//! let health = RocksDBStore::health(&db)?;
+//! println!("{} files at level 0", health.l0_files);
+//!
+//! // attach hints to push receipts
+//! let db_txn =
+//!     RocksDBStore::from(db.transaction()).with_health_probe(&db, HealthThresholds::default());
+//! let receipt = db_txn.push_update("my-doc-name", &update)?;
+//! if receipt.health >= Some(HealthHint::Degraded) {
+//!     // slow down
+//! }
+//! ```
+
+use rocksdb::{ThreadMode, TransactionDB};
+use yrs_kvstore::HealthHint;
+
+/// Source of RocksDB integer properties read by [RocksDBHealth::read]. It's implemented for
+/// [TransactionDB] and can be implemented by other sources, i.e. to test threshold logic.
+pub trait PropertySource {
+    /// Returns the value of a RocksDB integer property with a given `name`, or [None] if the
+    /// property is not supported.
+    fn property_int(&self, name: &str) -> Result<Option<u64>, rocksdb::Error>;
+}
+
+impl<T: ThreadMode> PropertySource for TransactionDB<T> {
+    #[inline]
+    fn property_int(&self, name: &str) -> Result<Option<u64>, rocksdb::Error> {
+        self.property_int_value(name)
+    }
+}
+
+/// Property set to 1 when writes are stopped.
+pub const PROP_IS_WRITE_STOPPED: &str = "rocksdb.is-write-stopped";
+/// Property reporting delayed write rate in bytes per second, 0 when writes are not delayed.
+pub const PROP_ACTUAL_DELAYED_WRITE_RATE: &str = "rocksdb.actual-delayed-write-rate";
+/// Property reporting the number of files at level 0.
+pub const PROP_NUM_FILES_AT_LEVEL0: &str = "rocksdb.num-files-at-level0";
+/// Property reporting the estimated number of bytes compaction needs to rewrite.
+pub const PROP_ESTIMATE_PENDING_COMPACTION_BYTES: &str =
+    "rocksdb.estimate-pending-compaction-bytes";
+/// Property reporting the size of active, unflushed and pinned memtables.
+pub const PROP_CUR_SIZE_ALL_MEM_TABLES: &str = "rocksdb.cur-size-all-mem-tables";
+
+/// Snapshot of RocksDB properties describing its write stall state. Properties not supported by
+/// the database are reported as 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RocksDBHealth {
+    /// Set if writes are stopped until compaction catches up.
+    pub write_stopped: bool,
+    /// Rate (in bytes per second) to which writes are currently slowed down, 0 if they are not.
+    pub delayed_write_rate: u64,
+    /// Number of files at level 0.
+    pub l0_files: u64,
+    /// Estimated number of bytes compaction needs to rewrite to bring all levels down under their
+    /// target size.
+    pub pending_compaction_bytes: u64,
+    /// Size (in bytes) of all memtables, including the ones waiting to be flushed.
+    pub memtable_bytes: u64,
+}
+
+impl RocksDBHealth {
+    /// Reads current health from a given properties `source`.
+    pub fn read<S: PropertySource + ?Sized>(source: &S) -> Result<Self, rocksdb::Error> {
+        let prop = |name| Ok::<_, rocksdb::Error>(source.property_int(name)?.unwrap_or(0));
+        Ok(RocksDBHealth {
+            write_stopped: prop(PROP_IS_WRITE_STOPPED)? != 0,
+            delayed_write_rate: prop(PROP_ACTUAL_DELAYED_WRITE_RATE)?,
+            l0_files: prop(PROP_NUM_FILES_AT_LEVEL0)?,
+            pending_compaction_bytes: prop(PROP_ESTIMATE_PENDING_COMPACTION_BYTES)?,
+            memtable_bytes: prop(PROP_CUR_SIZE_ALL_MEM_TABLES)?,
+        })
+    }
+
+    /// Classifies current health using given `thresholds`:
+    ///
+    /// - [HealthHint::Stalled] if writes are stopped.
+    /// - [HealthHint::Degraded] if writes are delayed or any of the `thresholds` has been reached.
+    /// - [HealthHint::Ok] otherwise.
+    pub fn hint(&self, thresholds: &HealthThresholds) -> HealthHint {
+        if self.write_stopped {
+            HealthHint::Stalled
+        } else if self.delayed_write_rate > 0
+            || self.l0_files >= thresholds.l0_files
+            || self.pending_compaction_bytes >= thresholds.pending_compaction_bytes
+            || matches!(thresholds.memtable_bytes, Some(max) if self.memtable_bytes >= max)
+        {
+            HealthHint::Degraded
+        } else {
+            HealthHint::Ok
+        }
+    }
+}
+
+/// Limits at which [RocksDBHealth::hint] reports [HealthHint::Degraded] before RocksDB starts to
+/// delay writes on its own. Use [HealthThresholds::default] to get values below RocksDB defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthThresholds {
+    /// Number of files at level 0. RocksDB slows down writes at 20 files by default.
+    pub l0_files: u64,
+    /// Estimated number of bytes pending compaction. RocksDB slows down writes at 64GiB by
+    /// default.
+    pub pending_compaction_bytes: u64,
+    /// Total size of memtables. It depends on memtable configuration, so it's not checked by
+    /// default.
+    pub memtable_bytes: Option<u64>,
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        HealthThresholds {
+            l0_files: 16,
+            pending_compaction_bytes: 48 << 30, // 48GiB
+            memtable_bytes: None,
+        }
+    }
+}
+
+/// Properties source attached to [RocksDBStore](crate::RocksDBStore) by
+/// [RocksDBStore::with_health_probe](crate::RocksDBStore::with_health_probe).
+#[derive(Clone, Copy)]
+pub(crate) struct HealthProbe<'a> {
+    pub source: &'a dyn PropertySource,
+    pub thresholds: HealthThresholds,
+}
+
+impl<'a> HealthProbe<'a> {
+    /// Returns current health hint, or [None] if properties couldn't be read.
+    pub fn hint(&self) -> Option<HealthHint> {
+        let health = RocksDBHealth::read(self.source).ok()?;
+        Some(health.hint(&self.thresholds))
+    }
+}
@@ -6,7 +6,7 @@
 //! ```rust
 //! use std::sync::Arc;
 //! use rocksdb::TransactionDB;
-//! use yrs::{Doc, Text, Transact};
+//! use yrs::{Doc, ReadTxn, Text, Transact};
 //! use yrs_kvstore::DocOps;
 //! use yrs_rocksdb::RocksDBStore;
 //!
@@ -34,9 +34,11 @@
 //! let sub = {
 //!   let db = db.clone();
 //!   let options = doc.options().clone();
-//!   doc.observe_update_v1(move |_,e| {
+//!   doc.observe_update_v1(move |txn,e| {
 //!       let db_txn = RocksDBStore::from(db.transaction());
-//!       let seq_nr = db_txn.push_update("my-doc-name", &e.update).unwrap();
+//!       let seq_nr = db_txn.push_update("my-doc-name", &e.update).unwrap().seq;
+//!       // keep stored state vector up to date, so that it can be used without loading the doc
+//!       db_txn.put_state_vector("my-doc-name", &txn.state_vector()).unwrap();
 //!       if seq_nr % 64 == 0 {
 //!           // occassinally merge updates into the document state
 //!           db_txn.flush_doc_with("my-doc-name", options.clone()).unwrap();
@@ -49,31 +51,263 @@
 //! text.insert(&mut doc.transact_mut(), 1, "b");
 //! text.insert(&mut doc.transact_mut(), 2, "c");
 //! ```
-
+//!
+//! Errors can't be returned from within the update observer and unwrapping them there panics
+//! inside of Yrs update notifications. [persist_on_update](yrs_kvstore::persist::persist_on_update)
+//! installs an observer which forwards store errors, together with the updates which failed to
+//! persist, to a callback instead, optionally retrying them on subsequent updates.
+//!
+//! # Creating documents
+//!
+//! By default [DocOps::push_update] creates documents which don't exist yet, so a typo in
+//! a document name silently creates a new, empty document. This can be prevented by configuring
+//! a different [CreatePolicy](yrs_kvstore::config::CreatePolicy):
+//!
+//! ```rust
+//! use rocksdb::TransactionDB;
+//! use yrs_kvstore::config::{ConfiguredStore, CreatePolicy, StoreConfig};
+//! use yrs_kvstore::error::Error;
+//! use yrs_kvstore::DocOps;
+//! use yrs_rocksdb::RocksDBStore;
+//!
+//! let db = TransactionDB::open_default("my-strict-db-path").unwrap();
+//! let config = StoreConfig {
+//!     create_policy: CreatePolicy::RequireExisting,
+//!     ..StoreConfig::DEFAULT
+//! };
+//! let db_txn = ConfiguredStore::new(RocksDBStore::from(db.transaction()), config);
+//! // documents must be created explicitly i.e. with `insert_doc`
+//! let res = db_txn.push_update("my-doc-nmae", &[0, 0]);
+//! assert!(matches!(res, Err(Error::DocNotFound)));
+//! ```
+//!
+//! For simple use cases, where every operation is meant to be executed in its own transaction,
+//! [RocksDBDocStore] can be used instead.
+//! High rates of small writes coming from many threads can be batched into shared transactions
+//! with [WriteCoalescer](coalescer::WriteCoalescer).
+//! Write stalls of the database can be detected early with [health], which can also attach
+//! a [HealthHint](yrs_kvstore::HealthHint) to every [PushReceipt](yrs_kvstore::PushReceipt).
+//! Update entries, document states and remaining metadata can be kept in separate column families
+//! with [columns].
+//! Transactions failed by conflicts with concurrent writers can be retried with [with_txn_retry].
+
+use crate::columns::{Column, ColumnLayout};
+use crate::health::{HealthProbe, HealthThresholds, PropertySource, RocksDBHealth};
 use rocksdb::{
-    DBIteratorWithThreadMode, DBPinnableSlice, Direction, IteratorMode, ReadOptions, Transaction,
+    DBIteratorWithThreadMode, DBPinnableSlice, Direction, IteratorMode, KVBytes, ReadOptions,
+    ThreadMode, Transaction, TransactionDB, TransactionOptions, WriteOptions,
 };
 use std::ops::Deref;
-use yrs_kvstore::{DocOps, KVEntry, KVStore};
-
+use yrs_kvstore::capabilities::Capabilities;
+use yrs_kvstore::error::Error;
+use yrs_kvstore::namespace::NamespacedStore;
+use yrs_kvstore::{DocOps, HealthHint, KVEntry, KVStore, ReadIsolation, ScanMode, WriteDurability};
+
+pub mod coalescer;
+pub mod columns;
+mod doc_store;
+pub mod health;
+pub mod options;
+pub mod retry;
+
+pub use doc_store::RocksDBDocStore;
+pub use retry::{with_txn_retry, RetryPolicy};
 pub use yrs_kvstore as store;
+pub use yrs_kvstore::prelude;
 
 /// Type wrapper around RocksDB [Transaction] struct. Used to extend it with [DocOps]
 /// methods used for convenience when working with Yrs documents.
-#[repr(transparent)]
-pub struct RocksDBStore<'a, DB>(Transaction<'a, DB>);
+pub struct RocksDBStore<'a, DB>(
+    Transaction<'a, DB>,
+    ReadIsolation,
+    Option<HealthProbe<'a>>,
+    Option<ColumnLayout<'a>>,
+);
 
 impl<'a, DB> RocksDBStore<'a, DB> {
     #[inline(always)]
     pub fn commit(self) -> Result<(), rocksdb::Error> {
         self.0.commit()
     }
+
+    /// Wraps a given transaction `txn`, making it operate on a logical store identified by
+    /// a given `namespace`, isolated from other namespaces of the same database. See
+    /// [namespace](yrs_kvstore::namespace) for details. Use [NamespacedStore::into_inner] to
+    /// commit it.
+    pub fn with_namespace(txn: Transaction<'a, DB>, namespace: u16) -> NamespacedStore<Self> {
+        NamespacedStore::new(RocksDBStore::from(txn), namespace)
+    }
+
+    /// Makes current store report health of the database read from a given properties `source`
+    /// (usually the database the transaction has been started from) in every [PushReceipt]
+    /// returned by [DocOps::push_update], classified using given `thresholds`. Properties are read
+    /// on every push. See [health] for details.
+    ///
+    /// [PushReceipt]: yrs_kvstore::PushReceipt
+    pub fn with_health_probe(
+        mut self,
+        source: &'a dyn PropertySource,
+        thresholds: HealthThresholds,
+    ) -> Self {
+        self.2 = Some(HealthProbe { source, thresholds });
+        self
+    }
+
+    /// Makes current store keep its entries in column families of a given `layout`, chosen by
+    /// the kind of their keys. See [columns] for details.
+    pub fn with_columns(mut self, layout: ColumnLayout<'a>) -> Self {
+        self.3 = Some(layout);
+        self
+    }
+
+    /// Returns an iterator over all entries between `from`..=`to` range of keys, using provided
+    /// read options. Iteration bounds are set on `opt` by this method.
+    ///
+    /// If current store uses a [ColumnLayout], `opt` is used only by ranges stored within
+    /// a single column family. Ranges spanning many of them are read with default read options.
+    pub fn iter_range_opt(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        opt: ReadOptions,
+    ) -> Result<RocksDBIter<'a, DB>, Error> {
+        let single = match &self.3 {
+            None => true,
+            Some(_) => Column::of_range(from, to).len() == 1,
+        };
+        let mut opt = Some(opt).filter(|_| single);
+        self.iter_range_with_options(from, to, || {
+            opt.take().unwrap_or_else(|| read_options(&self.0))
+        })
+    }
+
+    /// Returns an iterator over entries between `from`..`to` range of keys, merged from all
+    /// column families which may store them. Read options of every scanned column family are
+    /// built by `make_opt`.
+    fn iter_range_with_options<F>(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        mut make_opt: F,
+    ) -> Result<RocksDBIter<'a, DB>, Error>
+    where
+        F: FnMut() -> ReadOptions,
+    {
+        let mode = IteratorMode::From(from, Direction::Forward);
+        let mut opt = || {
+            let mut opt = make_opt();
+            opt.set_iterate_lower_bound(from);
+            opt.set_iterate_upper_bound(to);
+            opt
+        };
+        let inner = match &self.3 {
+            None => {
+                let raw = self.0.iterator_opt(mode, opt());
+                Cursor::Single(unsafe { std::mem::transmute(raw) })
+            }
+            Some(layout) => match Column::of_range(from, to) {
+                [column] => {
+                    let raw = self.0.iterator_cf_opt(layout.handle(*column), opt(), mode);
+                    Cursor::Single(unsafe { std::mem::transmute(raw) })
+                }
+                columns => {
+                    let mut iters = Vec::with_capacity(columns.len());
+                    for column in columns {
+                        let raw = self.0.iterator_cf_opt(layout.handle(*column), opt(), mode);
+                        iters.push(unsafe { std::mem::transmute(raw) });
+                    }
+                    Cursor::Merged(Merge::new(iters).map_err(Error::other)?)
+                }
+            },
+        };
+        Ok(RocksDBIter::new(inner, to.to_vec()))
+    }
+
+    /// Returns the first entry at or after a given `key` (or the last one at or before it, if
+    /// `forward` is not set) among all column families of a given `layout`.
+    fn seek_columns(
+        &self,
+        layout: &ColumnLayout<'a>,
+        key: &[u8],
+        forward: bool,
+    ) -> Result<Option<RocksDBEntry>, Error> {
+        let mut found: Option<RocksDBEntry> = None;
+        for column in Column::ALL.iter() {
+            let opt = read_options(&self.0);
+            let mut raw = self.0.raw_iterator_cf_opt(layout.handle(*column), opt);
+            if forward {
+                raw.seek(key);
+            } else {
+                raw.seek_for_prev(key);
+            }
+            if let Some((key, value)) = raw.item() {
+                let closer = match &found {
+                    Some(e) => (key < e.key()) == forward,
+                    None => true,
+                };
+                if closer {
+                    found = Some(RocksDBEntry::new(key.into(), value.into()));
+                }
+            } else {
+                raw.status().map_err(Error::other)?;
+            }
+        }
+        Ok(found)
+    }
+}
+
+impl<'a, T: ThreadMode> RocksDBStore<'a, TransactionDB<T>> {
+    /// Begins a new transaction over a given `db`. Provided write `options` are used when
+    /// the transaction is committed.
+    pub fn with_write_options(db: &'a TransactionDB<T>, options: &WriteOptions) -> Self {
+        RocksDBStore::from(db.transaction_opt(options, &TransactionOptions::default()))
+    }
+
+    /// Begins a new transaction over a given `db`, which will be committed using write options
+    /// matching requested `durability`. See [write_options] for details.
+    pub fn with_durability(db: &'a TransactionDB<T>, durability: WriteDurability) -> Self {
+        Self::with_write_options(db, &write_options(durability))
+    }
+
+    /// Begins a new transaction over a given `db` with a snapshot taken at its start. All reads
+    /// performed within that transaction observe the database state from that moment, ignoring
+    /// changes committed concurrently by other transactions. This is useful when reading many
+    /// documents at once, i.e. with [DocOps::load_docs]. Writes performed within the transaction
+    /// itself remain visible to its reads. Such transaction reports [ReadIsolation::Snapshot]
+    /// from [DocOps::read_snapshot].
+    pub fn with_snapshot(db: &'a TransactionDB<T>) -> Self {
+        let mut options = TransactionOptions::default();
+        options.set_snapshot(true);
+        let txn = db.transaction_opt(&WriteOptions::default(), &options);
+        RocksDBStore(txn, ReadIsolation::Snapshot, None, None)
+    }
+
+    /// Reads current health of a given `db` from its properties. See [health] for details.
+    pub fn health(db: &TransactionDB<T>) -> Result<RocksDBHealth, rocksdb::Error> {
+        RocksDBHealth::read(db)
+    }
+}
+
+/// Returns RocksDB [WriteOptions] matching a given `durability`:
+///
+/// - [WriteDurability::Durable] enables sync of write-ahead log on every commit.
+/// - [WriteDurability::Relaxed] writes to write-ahead log without syncing it (RocksDB default).
+/// - [WriteDurability::Volatile] disables write-ahead log altogether.
+pub fn write_options(durability: WriteDurability) -> WriteOptions {
+    let mut options = WriteOptions::default();
+    match durability {
+        WriteDurability::Durable => options.set_sync(true),
+        WriteDurability::Relaxed => options.set_sync(false),
+        WriteDurability::Volatile => options.disable_wal(true),
+    }
+    options
 }
 
 impl<'a, DB> From<Transaction<'a, DB>> for RocksDBStore<'a, DB> {
     #[inline(always)]
     fn from(txn: Transaction<'a, DB>) -> Self {
-        RocksDBStore(txn)
+        // snapshot can't be detected on a transaction that has been started elsewhere
+        RocksDBStore(txn, ReadIsolation::BestEffort, None, None)
     }
 }
 
@@ -96,58 +330,247 @@ impl<'a, DB> Deref for RocksDBStore<'a, DB> {
 impl<'a, DB> DocOps<'a> for RocksDBStore<'a, DB> {}
 
 impl<'a, DB> KVStore<'a> for RocksDBStore<'a, DB> {
-    type Error = rocksdb::Error;
+    type Error = Error;
     type Cursor = RocksDBIter<'a, DB>;
     type Entry = RocksDBEntry;
     type Return = DBPinnableSlice<'a>;
 
+    const CAPABILITIES: Capabilities = Capabilities::BOUNDED_PEEK_BACK
+        .union(Capabilities::REVERSE_ITER)
+        .union(Capabilities::MULTI_GET)
+        .union(Capabilities::SNAPSHOTS);
+
     fn get(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
-        if let Some(pinned) = self.0.get_pinned(key)? {
+        let opt = read_options(&self.0);
+        let pinned = match &self.3 {
+            None => self.0.get_pinned_opt(key, &opt).map_err(Error::other)?,
+            Some(layout) => self
+                .0
+                .get_pinned_cf_opt(layout.handle_of(key), key, &opt)
+                .map_err(Error::other)?,
+        };
+        if let Some(pinned) = pinned {
+            Ok(Some(unsafe { std::mem::transmute(pinned) }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn get_for_update(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+        let pinned = match &self.3 {
+            None => self
+                .0
+                .get_pinned_for_update(key, true)
+                .map_err(Error::other)?,
+            Some(layout) => self
+                .0
+                .get_pinned_for_update_cf(layout.handle_of(key), key, true)
+                .map_err(Error::other)?,
+        };
+        if let Some(pinned) = pinned {
             Ok(Some(unsafe { std::mem::transmute(pinned) }))
         } else {
             Ok(None)
         }
     }
 
+    fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, Self::Error> {
+        let opt = read_options(&self.0);
+        match &self.3 {
+            None => self
+                .0
+                .multi_get_opt(keys, &opt)
+                .into_iter()
+                .collect::<Result<_, _>>()
+                .map_err(Error::other),
+            Some(layout) => {
+                let keys = keys.iter().map(|key| (layout.handle_of(key), *key));
+                self.0
+                    .multi_get_cf_opt(keys, &opt)
+                    .into_iter()
+                    .collect::<Result<_, _>>()
+                    .map_err(Error::other)
+            }
+        }
+    }
+
     fn upsert(&self, key: &[u8], value: &[u8]) -> Result<(), Self::Error> {
-        self.0.put(key, value)?;
+        match &self.3 {
+            None => self.0.put(key, value).map_err(Error::other)?,
+            Some(layout) => self
+                .0
+                .put_cf(layout.handle_of(key), key, value)
+                .map_err(Error::other)?,
+        }
         Ok(())
     }
 
     fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
-        self.0.delete(key)?;
+        match &self.3 {
+            None => self.0.delete(key).map_err(Error::other)?,
+            Some(layout) => self
+                .0
+                .delete_cf(layout.handle_of(key), key)
+                .map_err(Error::other)?,
+        }
         Ok(())
     }
 
-    fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<(), Self::Error> {
-        let mut opt = ReadOptions::default();
-        opt.set_iterate_lower_bound(from);
-        opt.set_iterate_upper_bound(to);
-        let mut i = self
-            .0
-            .iterator_opt(IteratorMode::From(from, Direction::Forward), opt);
-        while let Some(res) = i.next() {
-            let (key, _) = res?;
-            self.0.delete(key)?;
+    fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<u32, Self::Error> {
+        let i = self.iter_range_with_options(from, to, || read_options(&self.0))?;
+        let mut removed = 0;
+        for res in i.inner {
+            let (key, _) = res.map_err(Error::other)?;
+            self.remove(&key)?;
+            removed += 1;
         }
-        Ok(())
+        Ok(removed)
     }
 
     fn iter_range(&self, from: &[u8], to: &[u8]) -> Result<Self::Cursor, Self::Error> {
-        let mut opt = ReadOptions::default();
-        opt.set_iterate_lower_bound(from);
-        opt.set_iterate_upper_bound(to);
-        let raw = self
-            .0
-            .iterator_opt(IteratorMode::From(from, Direction::Forward), opt);
-        Ok(RocksDBIter::new(
-            unsafe { std::mem::transmute(raw) },
-            to.to_vec(),
-        ))
+        self.iter_range_with(from, to, ScanMode::Interactive)
+    }
+
+    fn iter_range_with(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        mode: ScanMode,
+    ) -> Result<Self::Cursor, Self::Error> {
+        self.iter_range_with_options(from, to, || {
+            let mut opt = read_options(&self.0);
+            if mode == ScanMode::Bulk {
+                opt.fill_cache(false);
+                opt.set_readahead_size(BULK_READAHEAD_SIZE);
+            }
+            opt
+        })
+    }
+
+    fn iter_keys_range(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        f: &mut dyn FnMut(&[u8], usize) -> bool,
+    ) -> Result<(), Self::Error> {
+        // raw iterator exposes keys and values in place, without copying them like RocksDBIter
+        let opt = || {
+            let mut opt = read_options(&self.0);
+            opt.set_iterate_lower_bound(from);
+            opt.set_iterate_upper_bound(to);
+            opt
+        };
+        let layout = match &self.3 {
+            None => {
+                let mut raw = self.0.raw_iterator_opt(opt());
+                raw.seek(from);
+                while let (Some(key), Some(value)) = (raw.key(), raw.value()) {
+                    if !f(key, value.len()) {
+                        return Ok(());
+                    }
+                    raw.next();
+                }
+                return raw.status().map_err(Error::other);
+            }
+            Some(layout) => layout,
+        };
+        let mut raws = Vec::new();
+        for column in Column::of_range(from, to) {
+            let mut raw = self.0.raw_iterator_cf_opt(layout.handle(*column), opt());
+            raw.seek(from);
+            raws.push(raw);
+        }
+        // visit keys of all column families in order, always advancing the one at the lowest key
+        loop {
+            let mut next: Option<usize> = None;
+            for (i, raw) in raws.iter().enumerate() {
+                if let Some(key) = raw.key() {
+                    if next.is_none_or(|n| Some(key) < raws[n].key()) {
+                        next = Some(i);
+                    }
+                }
+            }
+            let raw = match next {
+                Some(i) => &mut raws[i],
+                None => break,
+            };
+            if let (Some(key), Some(value)) = (raw.key(), raw.value()) {
+                if !f(key, value.len()) {
+                    return Ok(());
+                }
+            }
+            raw.next();
+        }
+        raws.iter()
+            .try_for_each(|raw| raw.status())
+            .map_err(Error::other)
+    }
+
+    fn iter_range_rev(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        f: &mut dyn FnMut(&[u8], &[u8]) -> bool,
+    ) -> Result<(), Self::Error> {
+        // upper bound is left unset, since it's exclusive and would skip an entry at `to`
+        let opt = || {
+            let mut opt = read_options(&self.0);
+            opt.set_iterate_lower_bound(from);
+            opt
+        };
+        let layout = match &self.3 {
+            None => {
+                let mut raw = self.0.raw_iterator_opt(opt());
+                raw.seek_for_prev(to);
+                while let Some((key, value)) = raw.item() {
+                    if !f(key, value) {
+                        return Ok(());
+                    }
+                    raw.prev();
+                }
+                return raw.status().map_err(Error::other);
+            }
+            Some(layout) => layout,
+        };
+        let mut raws = Vec::new();
+        for column in Column::of_range(from, to) {
+            let mut raw = self.0.raw_iterator_cf_opt(layout.handle(*column), opt());
+            raw.seek_for_prev(to);
+            raws.push(raw);
+        }
+        // visit entries of all column families in order, always moving back the one at the
+        // highest key
+        loop {
+            let mut next: Option<usize> = None;
+            for (i, raw) in raws.iter().enumerate() {
+                let higher = match next {
+                    Some(n) => raw.key() > raws[n].key(),
+                    None => raw.key().is_some(),
+                };
+                if higher {
+                    next = Some(i);
+                }
+            }
+            let raw = match next {
+                Some(i) => &mut raws[i],
+                None => break,
+            };
+            if let Some((key, value)) = raw.item() {
+                if !f(key, value) {
+                    return Ok(());
+                }
+            }
+            raw.prev();
+        }
+        raws.iter()
+            .try_for_each(|raw| raw.status().map_err(Error::other))
     }
 
     fn peek_back(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
-        let opt = ReadOptions::default();
+        if let Some(layout) = &self.3 {
+            return self.seek_columns(layout, key, false);
+        }
+        let opt = read_options(&self.0);
         let mut raw = self.0.raw_iterator_opt(opt);
         raw.seek_for_prev(key);
         if let Some((key, value)) = raw.item() {
@@ -156,19 +579,121 @@ impl<'a, DB> KVStore<'a> for RocksDBStore<'a, DB> {
             Ok(None)
         }
     }
+
+    fn seek(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+        if let Some(layout) = &self.3 {
+            return self.seek_columns(layout, key, true);
+        }
+        let opt = read_options(&self.0);
+        let mut raw = self.0.raw_iterator_opt(opt);
+        raw.seek(key);
+        if let Some((key, value)) = raw.item() {
+            Ok(Some(RocksDBEntry::new(key.into(), value.into())))
+        } else {
+            // iterator became invalid either at the end of the keyspace or due to an error
+            raw.status().map_err(Error::other)?;
+            Ok(None)
+        }
+    }
+
+    fn read_isolation(&self) -> ReadIsolation {
+        self.1
+    }
+
+    fn health_hint(&self) -> Option<HealthHint> {
+        self.2.as_ref().and_then(HealthProbe::hint)
+    }
+}
+
+/// Readahead size used by [ScanMode::Bulk] scans.
+const BULK_READAHEAD_SIZE: usize = 2 * 1024 * 1024;
+
+/// Read options used by reads within a given transaction `txn`. Ranges scanned by [DocOps] may
+/// span over multiple key prefixes, so total order seek is required in case if database has been
+/// configured with a prefix extractor (see [options::recommended]).
+///
+/// If transaction was started with a snapshot (see [RocksDBStore::with_snapshot]), reads are
+/// performed against it. Otherwise transaction snapshot is null and has no effect.
+fn read_options<DB>(txn: &Transaction<DB>) -> ReadOptions {
+    let mut opt = ReadOptions::default();
+    opt.set_total_order_seek(true);
+    // transaction snapshot is owned by the transaction itself, it's not released on drop
+    opt.set_snapshot(&txn.snapshot());
+    opt
 }
 
 pub struct RocksDBIter<'a, DB> {
-    inner: DBIteratorWithThreadMode<'a, Transaction<'a, DB>>,
+    inner: Cursor<'a, DB>,
     to: Vec<u8>,
 }
 
 impl<'a, DB> RocksDBIter<'a, DB> {
-    fn new(inner: DBIteratorWithThreadMode<'a, Transaction<'a, DB>>, to: Vec<u8>) -> Self {
+    fn new(inner: Cursor<'a, DB>, to: Vec<u8>) -> Self {
         RocksDBIter { inner, to }
     }
 }
 
+type TxnIter<'a, DB> = DBIteratorWithThreadMode<'a, Transaction<'a, DB>>;
+
+/// Iterator over entries of a single column family, or over entries of many column families
+/// merged in key order.
+enum Cursor<'a, DB> {
+    Single(TxnIter<'a, DB>),
+    Merged(Merge<TxnIter<'a, DB>>),
+}
+
+impl<'a, DB> Iterator for Cursor<'a, DB> {
+    type Item = Result<KVBytes, rocksdb::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Cursor::Single(i) => i.next(),
+            Cursor::Merged(i) => i.next(),
+        }
+    }
+}
+
+/// Merges entries of many iterators ordered by their keys into a single ordered sequence.
+struct Merge<I> {
+    iters: Vec<I>,
+    heads: Vec<Option<KVBytes>>,
+}
+
+impl<I> Merge<I>
+where
+    I: Iterator<Item = Result<KVBytes, rocksdb::Error>>,
+{
+    fn new(mut iters: Vec<I>) -> Result<Self, rocksdb::Error> {
+        let heads = iters
+            .iter_mut()
+            .map(|i| i.next().transpose())
+            .collect::<Result<_, _>>()?;
+        Ok(Merge { iters, heads })
+    }
+}
+
+impl<I> Iterator for Merge<I>
+where
+    I: Iterator<Item = Result<KVBytes, rocksdb::Error>>,
+{
+    type Item = Result<KVBytes, rocksdb::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (i, _) = self
+            .heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, head)| Some((i, &head.as_ref()?.0)))
+            .min_by(|(_, a), (_, b)| a.cmp(b))?;
+        let head = self.heads[i].take()?;
+        match self.iters[i].next().transpose() {
+            Ok(next) => self.heads[i] = next,
+            Err(e) => return Some(Err(e)),
+        }
+        Some(Ok(head))
+    }
+}
+
 impl<'a, DB> Iterator for RocksDBIter<'a, DB> {
     type Item = RocksDBEntry;
 
@@ -215,13 +740,35 @@ impl KVEntry for RocksDBEntry {
 
 #[cfg(test)]
 mod test {
-    use crate::RocksDBStore;
-    use rocksdb::TransactionDB;
+    use crate::coalescer::{CoalescerConfig, WriteCoalescer};
+    use crate::options::{open_recommended, Preset};
+    use crate::{RocksDBDocStore, RocksDBStore};
+    use lmdb_rs::core::DbCreate;
+    use rocksdb::{BlockBasedOptions, Cache, Options, TransactionDB, TransactionDBOptions};
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io;
+    use std::io::{BufReader, BufWriter};
     use std::path::Path;
-    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::time::Duration;
     use tempdir::TempDir;
-    use yrs::{Doc, GetString, ReadTxn, Text, Transact};
-    use yrs_kvstore::DocOps;
+    use yrs::updates::encoder::Encode;
+    use yrs::{Doc, GetString, ReadTxn, StateVector, Text, Transact};
+    use yrs_kvstore::archive::{export_store_with, import_store, ArchiveReader, ConflictMode};
+    use yrs_kvstore::config::{ConfiguredStore, StoreConfig};
+    use yrs_kvstore::conformance;
+    use yrs_kvstore::conformance::allocations;
+    use yrs_kvstore::error::Error;
+    use yrs_kvstore::keys::{
+        key_doc, key_namespace, key_namespace_end, key_oid, key_state_vector, parse_key,
+        split_namespace,
+    };
+    use yrs_kvstore::keys::{ParsedKey, KEYSPACE_ARCHIVE, KEYSPACE_DOC, V1};
+    use yrs_kvstore::persist::{persist_on_update_with, PersistConfig, PersistFailure};
+    use yrs_kvstore::{DocOps, KVEntry, KVStore, PushReceipt, ScanMode, WriteDurability};
+    use yrs_lmdb::{LmdbEnv, LmdbStore};
 
     fn init_env<P: AsRef<Path>>(dir: P) -> TransactionDB {
         let db = TransactionDB::open_default(dir).unwrap();
@@ -229,107 +776,184 @@ mod test {
     }
 
     #[test]
-    fn create_get_remove() {
-        let tmp = TempDir::new("rocksdb-create_get_remove").unwrap();
-        let db = init_env(&tmp);
+    fn write_durability() {
+        const DOC_NAME: &str = "doc";
+        let tmp = TempDir::new("rocksdb-write_durability").unwrap();
+
+        for durability in [
+            WriteDurability::Durable,
+            WriteDurability::Relaxed,
+            WriteDurability::Volatile,
+        ] {
+            let doc_name = format!("{}-{:?}", DOC_NAME, durability);
+            {
+                let db = init_env(&tmp);
+                let doc = Doc::new();
+                let text = doc.get_or_insert_text("text");
+                text.push(&mut doc.transact_mut(), "hello");
+                let db_txn = RocksDBStore::with_durability(&db, durability);
+                db_txn.insert_doc(&doc_name, &doc.transact()).unwrap();
+                db_txn.commit().unwrap();
+            }
 
-        // insert document
-        {
+            // data must be readable after clean close
+            let db = init_env(&tmp);
+            let db_txn = RocksDBStore::from(db.transaction());
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
-            let mut txn = doc.transact_mut();
-            text.insert(&mut txn, 0, "hello");
+            assert!(db_txn.load_doc(&doc_name, &mut doc.transact_mut()).unwrap());
+            assert_eq!(text.get_string(&doc.transact()), "hello");
+        }
+    }
 
+    #[test]
+    fn recommended_options() {
+        let tmp = TempDir::new("rocksdb-recommended_options").unwrap();
+        let preset = Preset {
+            min_blob_size: Some(1024),
+            ..Preset::default()
+        };
+        let names = ["a", "doc-1", "doc-2", "very-long-document-name"];
+        {
+            let db = preset.open(&tmp).unwrap();
             let db_txn = RocksDBStore::from(db.transaction());
-            db_txn.insert_doc("doc", &txn).unwrap();
+            for name in names.iter() {
+                let doc = Doc::new();
+                let text = doc.get_or_insert_text("text");
+                // big enough to be moved into blob files
+                text.push(&mut doc.transact_mut(), &name.repeat(1024));
+                db_txn.insert_doc(name, &doc.transact()).unwrap();
+                db_txn.push_update(name, &[0, 0]).unwrap();
+                db_txn.insert_meta(name, "key", name.as_bytes()).unwrap();
+            }
             db_txn.commit().unwrap();
         }
 
-        // retrieve document
-        {
+        // prefix extractor must not affect range scans spanning over multiple prefixes
+        let db = open_recommended(&tmp).unwrap();
+        let db_txn = RocksDBStore::from(db.transaction());
+        let docs: Vec<_> = db_txn.iter_docs().unwrap().collect();
+        let expected: Vec<Box<[u8]>> = names.iter().map(|n| n.as_bytes().into()).collect();
+        assert_eq!(docs, expected);
+        for name in names.iter() {
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
-            let mut txn = doc.transact_mut();
-            let db_txn = RocksDBStore::from(db.transaction());
-            db_txn.load_doc("doc", &mut txn).unwrap();
-
-            assert_eq!(text.get_string(&txn), "hello");
-
-            let (sv, completed) = db_txn.get_state_vector("doc").unwrap();
-            assert_eq!(sv, Some(txn.state_vector()));
-            assert!(completed);
+            assert!(db_txn.load_doc(name, &mut doc.transact_mut()).unwrap());
+            assert_eq!(text.get_string(&doc.transact()), name.repeat(1024));
+            let meta = db_txn.get_meta(name, "key").unwrap();
+            assert_eq!(meta.as_deref(), Some(name.as_bytes()));
         }
+    }
 
-        // remove document
+    #[test]
+    fn bulk_scan_skips_block_cache() {
+        let tmp = TempDir::new("rocksdb-bulk_scan_skips_block_cache").unwrap();
+        let cache = Cache::new_lru_cache(16 * 1024 * 1024);
+        let open = || -> TransactionDB {
+            let mut table = BlockBasedOptions::default();
+            table.set_block_cache(&cache);
+            let mut options = Options::default();
+            options.create_if_missing(true);
+            options.set_block_based_table_factory(&table);
+            TransactionDB::open(&options, &TransactionDBOptions::default(), &tmp).unwrap()
+        };
         {
+            let db = open();
             let db_txn = RocksDBStore::from(db.transaction());
+            for i in 0..100 {
+                let doc = Doc::new();
+                let text = doc.get_or_insert_text("text");
+                text.push(&mut doc.transact_mut(), &"x".repeat(1024));
+                db_txn
+                    .insert_doc(&format!("doc-{:03}", i), &doc.transact())
+                    .unwrap();
+            }
+            db_txn.commit().unwrap();
+        }
 
-            db_txn.clear_doc("doc").unwrap();
+        // data is flushed into table files on reopen, so scans will read data blocks
+        let db = open();
+        let db_txn = RocksDBStore::from(db.transaction());
+        let start = [V1, KEYSPACE_DOC];
+        let end = [V1, KEYSPACE_ARCHIVE];
+
+        let usage = cache.get_usage();
+        let count = db_txn
+            .iter_range_with(&start, &end, ScanMode::Bulk)
+            .unwrap()
+            .count();
+        assert!(count >= 100);
+        assert_eq!(cache.get_usage(), usage);
+
+        let count = db_txn
+            .iter_range_with(&start, &end, ScanMode::Interactive)
+            .unwrap()
+            .count();
+        assert!(count >= 100);
+        assert!(cache.get_usage() > usage);
+    }
+
+    #[test]
+    fn doc_store_create_get_remove() {
+        let tmp = TempDir::new("rocksdb-doc_store_create_get_remove").unwrap();
+        let store = RocksDBDocStore::from(init_env(&tmp));
 
+        // insert document
+        {
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
             let mut txn = doc.transact_mut();
-            db_txn.load_doc("doc", &mut txn).unwrap();
-
-            assert_eq!(text.get_string(&txn), "");
-
-            let (sv, completed) = db_txn.get_state_vector("doc").unwrap();
-            assert!(sv.is_none());
-            assert!(completed);
+            text.insert(&mut txn, 0, "hello");
+            store.insert_doc("doc", &txn).unwrap();
         }
-    }
-    #[test]
-    fn multi_insert() {
-        let tmp = TempDir::new("rocksdb-multi_insert").unwrap();
-        let db = init_env(&tmp);
 
-        // insert document twice
+        // retrieve document
         {
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
             let mut txn = doc.transact_mut();
-            text.push(&mut txn, "hello");
-
-            let db_txn = RocksDBStore::from(db.transaction());
-
-            db_txn.insert_doc("doc", &txn).unwrap();
+            store.load_doc("doc", &mut txn).unwrap();
 
-            text.push(&mut txn, " world");
+            assert_eq!(text.get_string(&txn), "hello");
 
-            db_txn.insert_doc("doc", &txn).unwrap();
-            db_txn.commit().unwrap();
+            let (sv, completed) = store.get_state_vector("doc").unwrap();
+            assert_eq!(sv, Some(txn.state_vector()));
+            assert!(completed);
         }
 
-        // retrieve document
+        // remove document
         {
-            let db_txn = RocksDBStore::from(db.transaction());
+            let report = store.clear_doc("doc").unwrap();
+            assert!(report.doc_state_removed);
+            assert!(report.bytes_removed > 0);
 
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
             let mut txn = doc.transact_mut();
-            db_txn.load_doc("doc", &mut txn).unwrap();
+            store.load_doc("doc", &mut txn).unwrap();
+
+            assert_eq!(text.get_string(&txn), "");
 
-            assert_eq!(text.get_string(&txn), "hello world");
+            let (sv, completed) = store.get_state_vector("doc").unwrap();
+            assert!(sv.is_none());
+            assert!(completed);
         }
     }
 
     #[test]
-    fn incremental_updates() {
+    fn doc_store_incremental_updates() {
         const DOC_NAME: &str = "doc";
-        let tmp = TempDir::new("rocksdb-incremental_updates").unwrap();
-        let db = init_env(&tmp);
-        let db = Arc::new(db);
+        let tmp = TempDir::new("rocksdb-doc_store_incremental_updates").unwrap();
+        let store = RocksDBDocStore::from(init_env(&tmp));
 
         // store document updates
         {
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
 
-            let db = db.clone();
+            let store = store.clone();
             let _sub = doc.observe_update_v1(move |_, u| {
-                let db_txn = RocksDBStore::from(db.transaction());
-                db_txn.push_update(DOC_NAME, &u.update).unwrap();
-                db_txn.commit().unwrap();
+                store.push_update(DOC_NAME, &u.update).unwrap();
             });
             // generate 3 updates
             text.push(&mut doc.transact_mut(), "a");
@@ -342,227 +966,1756 @@ mod test {
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
             let mut txn = doc.transact_mut();
-
-            let db_txn = RocksDBStore::from(db.transaction());
-            db_txn.load_doc(DOC_NAME, &mut txn).unwrap();
+            store.load_doc(DOC_NAME, &mut txn).unwrap();
 
             assert_eq!(text.get_string(&txn), "abc");
         }
 
         // flush document
         {
-            let db_txn = RocksDBStore::from(db.transaction());
-            let doc = db_txn.flush_doc(DOC_NAME).unwrap().unwrap();
-            db_txn.commit().unwrap();
-
-            let text = doc.get_or_insert_text("text");
-
-            assert_eq!(text.get_string(&doc.transact()), "abc");
+            let outcome = store.flush_doc(DOC_NAME).unwrap().unwrap();
+            assert_eq!(outcome.updates_folded, 3);
+            let text = outcome.doc.get_or_insert_text("text");
+            assert_eq!(text.get_string(&outcome.doc.transact()), "abc");
+
+            // flush has been committed
+            assert!(store.flush_doc(DOC_NAME).unwrap().is_none());
+            let (_, completed) = store.get_state_vector(DOC_NAME).unwrap();
+            assert!(completed);
         }
     }
 
     #[test]
-    fn state_vector_updates_only() {
+    fn doc_store_meta() {
         const DOC_NAME: &str = "doc";
-        let tmp = TempDir::new("rocksdb-state_vector_updates_only").unwrap();
-        let db = init_env(&tmp);
-        let db = Arc::new(db);
-
-        // store document updates
-        {
-            let doc = Doc::new();
-            let text = doc.get_or_insert_text("text");
-            let db = db.clone();
-            let _sub = doc.observe_update_v1(move |_, u| {
-                let db_txn = RocksDBStore::from(db.transaction());
-                db_txn.push_update(DOC_NAME, &u.update).unwrap();
-                db_txn.commit().unwrap();
-            });
-            // generate 3 updates
-            text.push(&mut doc.transact_mut(), "a");
-            text.push(&mut doc.transact_mut(), "b");
-            text.push(&mut doc.transact_mut(), "c");
-
-            let sv = doc.transact().state_vector();
-            sv
-        };
+        let tmp = TempDir::new("rocksdb-doc_store_meta").unwrap();
+        let store = RocksDBDocStore::from(init_env(&tmp));
 
-        let db_txn = RocksDBStore::from(db.transaction());
-        let (sv, completed) = db_txn.get_state_vector(DOC_NAME).unwrap();
-        assert!(sv.is_none());
-        assert!(!completed); // since it's not completed, we should recalculate state vector from doc state
+        assert!(store.get_meta(DOC_NAME, "key").unwrap().is_none());
+        store
+            .insert_meta(DOC_NAME, "key", "value1".as_bytes())
+            .unwrap();
+        let value = store.get_meta(DOC_NAME, "key").unwrap();
+        assert_eq!(value.as_deref(), Some("value1".as_bytes()));
+
+        store.insert_meta(DOC_NAME, "key2", [2].as_ref()).unwrap();
+        let meta = store.iter_meta(DOC_NAME).unwrap();
+        assert_eq!(
+            meta,
+            vec![
+                ("key".as_bytes().into(), "value1".as_bytes().into()),
+                ("key2".as_bytes().into(), [2].into()),
+            ]
+        );
+
+        store.remove_meta(DOC_NAME, "key").unwrap();
+        assert!(store.get_meta(DOC_NAME, "key").unwrap().is_none());
+        assert_eq!(store.iter_docs().unwrap(), vec![DOC_NAME.as_bytes().into()]);
     }
 
     #[test]
-    fn state_diff_from_updates() {
-        const DOC_NAME: &str = "doc";
-        let tmp = TempDir::new("rocksdb-state_diff_from_updates").unwrap();
+    fn load_docs() {
+        let tmp = TempDir::new("rocksdb-load_docs").unwrap();
         let db = init_env(&tmp);
-        let db = Arc::new(db);
 
-        let (sv, expected) = {
+        {
+            let db_txn = RocksDBStore::from(db.transaction());
+            for name in ["doc-1", "doc-2"] {
+                let doc = Doc::new();
+                let text = doc.get_or_insert_text("text");
+                text.push(&mut doc.transact_mut(), name);
+                db_txn.insert_doc(name, &doc.transact()).unwrap();
+            }
+            // documents with pending updates only are loaded too
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
+            text.push(&mut doc.transact_mut(), "doc-3");
+            let update = doc
+                .transact()
+                .encode_state_as_update_v1(&StateVector::default());
+            db_txn.push_update("doc-3", &update).unwrap();
+            db_txn.commit().unwrap();
+        }
 
-            let db = db.clone();
-            let _sub = doc.observe_update_v1(move |_, u| {
-                let db_txn = RocksDBStore::from(db.transaction());
-                db_txn.push_update(DOC_NAME, &u.update).unwrap();
-                db_txn.commit().unwrap();
-            });
+        let db_txn = RocksDBStore::with_snapshot(&db);
 
-            // generate 3 updates
-            text.push(&mut doc.transact_mut(), "a");
-            text.push(&mut doc.transact_mut(), "b");
-            let sv = doc.transact().state_vector();
-            text.push(&mut doc.transact_mut(), "c");
-            let update = doc.transact().encode_diff_v1(&sv);
-            (sv, update)
-        };
+        // changes committed after snapshot was taken are not visible
+        {
+            let other = RocksDBStore::from(db.transaction());
+            other.clear_doc("doc-1").unwrap();
+            other.commit().unwrap();
+        }
 
-        let db_txn = RocksDBStore::from(db.transaction());
-        let actual = db_txn.get_diff(DOC_NAME, &sv).unwrap();
-        assert_eq!(actual, Some(expected));
+        let names: [&[u8]; 4] = [b"doc-3", b"missing", b"doc-1", b"doc-2"];
+        let docs = db_txn.load_docs(names.iter().copied()).unwrap();
+        assert_eq!(docs.len(), names.len());
+        for ((name, doc), expected) in docs.into_iter().zip(names.iter()) {
+            assert_eq!(&name, expected);
+            if *expected == b"missing" {
+                assert!(doc.is_none());
+            } else {
+                let doc = doc.unwrap();
+                let text = doc.get_or_insert_text("text");
+                assert_eq!(text.get_string(&doc.transact()).as_bytes(), *expected);
+            }
+        }
+        drop(db_txn);
+
+        let store = RocksDBDocStore::from(db);
+        let docs = store.load_docs(names.iter().copied()).unwrap();
+        assert!(docs[2].1.is_none()); // doc-1 has been removed in the meantime
     }
 
     #[test]
-    fn state_diff_from_doc() {
-        const DOC_NAME: &str = "doc";
-        let tmp = TempDir::new("rocksdb-state_diff_from_doc").unwrap();
-        let db = init_env(&tmp);
-        let db = Arc::new(db);
+    fn push_receipt() {
+        const THREADS: usize = 8;
+        const DOCS: usize = 10;
+        let tmp = TempDir::new("rocksdb-push_receipt").unwrap();
+        let store = RocksDBDocStore::from(init_env(&tmp));
+
+        let receipt = store.push_update("doc", &[0, 0]).unwrap();
+        assert_eq!(
+            receipt,
+            PushReceipt {
+                seq: 1,
+                pending_updates: 1,
+                pending_bytes: 2,
+                doc_created: true,
+                health: None,
+            }
+        );
+        let receipt = store.push_update("doc", &[0, 0]).unwrap();
+        assert_eq!(
+            receipt,
+            PushReceipt {
+                seq: 2,
+                pending_updates: 2,
+                pending_bytes: 4,
+                doc_created: false,
+                health: None,
+            }
+        );
+        assert_eq!(store.push_update_seq("doc", &[0, 0]).unwrap(), 3);
+
+        // every document is created exactly once, no matter how many threads push to it
+        let threads: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    let mut created = Vec::new();
+                    for i in 0..DOCS {
+                        let receipt = store.push_update(&format!("doc-{}", i), &[0, 0]).unwrap();
+                        if receipt.doc_created {
+                            created.push(i);
+                        }
+                    }
+                    created
+                })
+            })
+            .collect();
+        let mut created: Vec<_> = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        created.sort();
+        assert_eq!(created, (0..DOCS).collect::<Vec<_>>());
+        for i in 0..DOCS {
+            let receipt = store.push_update(&format!("doc-{}", i), &[0, 0]).unwrap();
+            assert_eq!(receipt.seq, THREADS as u32 + 1);
+            assert!(!receipt.doc_created);
+        }
+    }
 
-        let (sv, expected) = {
-            let doc = Doc::new();
-            let text = doc.get_or_insert_text("text");
-            // generate 3 updates
-            text.push(&mut doc.transact_mut(), "a");
-            text.push(&mut doc.transact_mut(), "b");
-            let sv = doc.transact().state_vector();
-            text.push(&mut doc.transact_mut(), "c");
-            let update = doc.transact().encode_diff_v1(&sv);
+    #[test]
+    fn decoded_updates() {
+        let tmp = TempDir::new("rocksdb-decoded_updates").unwrap();
+        let store = RocksDBDocStore::from(init_env(&tmp));
 
-            let db_txn = RocksDBStore::from(db.transaction());
-            db_txn.insert_doc(DOC_NAME, &doc.transact()).unwrap();
-            db_txn.commit().unwrap();
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        for chunk in ["hello", " ", "world", "!"] {
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, chunk);
+            store.push_update("doc", &txn.encode_update_v1()).unwrap();
+        }
+        store.push_update("other", &[0, 0]).unwrap();
+
+        // updates are decoded in order, but not applied anywhere
+        let mut inserted = 0;
+        let mut seen = StateVector::default();
+        let mut seq_nrs = Vec::new();
+        for (seq_nr, update) in store.decoded_updates("doc").unwrap() {
+            for (&client, &clock) in update.state_vector().iter() {
+                inserted += clock - seen.get(&client);
+                seen.set_max(client, clock);
+            }
+            seq_nrs.push(seq_nr);
+        }
+        assert_eq!(seq_nrs, vec![1, 2, 3, 4]);
 
-            (sv, update)
-        };
+        let loaded = Doc::new();
+        let text = loaded.get_or_insert_text("text");
+        store.load_doc("doc", &mut loaded.transact_mut()).unwrap();
+        let len = text.get_string(&loaded.transact()).len() as u32;
+        assert_eq!(inserted, len);
 
-        let db_txn = RocksDBStore::from(db.transaction());
-        let actual = db_txn.get_diff(DOC_NAME, &sv).unwrap();
-        assert_eq!(actual, Some(expected));
+        assert!(store.decoded_updates("missing").unwrap().is_empty());
+        store.flush_doc("doc").unwrap();
+        assert!(store.decoded_updates("doc").unwrap().is_empty());
     }
 
+    #[cfg(feature = "stress-tests")]
     #[test]
-    fn doc_meta() {
-        const DOC_NAME: &str = "doc";
-        let tmp = TempDir::new("rocksdb-doc_meta").unwrap();
-        let db = init_env(&tmp);
-        let db = Arc::new(db);
+    fn concurrent_pushes() {
+        use yrs::TransactionMut;
+        use yrs_kvstore::stress::{self, StressStore};
 
-        let db_txn = RocksDBStore::from(db.transaction());
-        let value = db_txn.get_meta(DOC_NAME, "key").unwrap();
-        assert!(value.is_none());
-        db_txn
-            .insert_meta(DOC_NAME, "key", "value1".as_bytes())
-            .unwrap();
-        db_txn.commit().unwrap();
+        struct Store(RocksDBDocStore);
 
-        let db_txn = RocksDBStore::from(db.transaction());
-        let prev = db_txn.get_meta(DOC_NAME, "key").unwrap();
-        db_txn
-            .insert_meta(DOC_NAME, "key", "value2".as_bytes())
-            .unwrap();
-        db_txn.commit().unwrap();
-        assert_eq!(prev.as_deref(), Some("value1".as_bytes()));
+        impl StressStore for Store {
+            fn push_update(&self, name: &str, update: &[u8]) -> Result<PushReceipt, Error> {
+                self.0.push_update(name, update)
+            }
 
-        let db_txn = RocksDBStore::from(db.transaction());
-        let prev = db_txn.get_meta(DOC_NAME, "key").unwrap();
-        db_txn.remove_meta(DOC_NAME, "key").unwrap();
-        assert_eq!(prev.as_deref(), Some("value2".as_bytes()));
-        let value = db_txn.get_meta(DOC_NAME, "key").unwrap();
-        assert!(value.is_none());
+            fn update_count(&self, name: &str) -> Result<usize, Error> {
+                Ok(self.0.decoded_updates(name)?.len())
+            }
+
+            fn load_doc(&self, name: &str, txn: &mut TransactionMut) -> Result<bool, Error> {
+                self.0.load_doc(name, txn)
+            }
+        }
+
+        // concurrent transactions lock pending updates counter of the document with
+        // `get_for_update`, so that they never assign the same sequence number
+        let tmp = TempDir::new("rocksdb-concurrent_pushes").unwrap();
+        let store = Store(RocksDBDocStore::from(init_env(&tmp)));
+        stress::concurrent_pushes(&store, 8, 32);
     }
 
     #[test]
-    fn doc_meta_iter() {
-        let tmp = TempDir::new("rocksdb-doc_meta_iter").unwrap();
-        let db = init_env(&tmp);
-        let db_txn = RocksDBStore::from(db.transaction());
+    fn archive_round_trip_to_lmdb() {
+        const DOCS: u32 = 200;
+        const LOG_LEN: usize = 2000;
+        fn content<'a, DB: DocOps<'a>>(db: &DB, name: &str) -> String
+        where
+            Error: From<<DB as KVStore<'a>>::Error>,
+        {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            db.load_doc(name, &mut doc.transact_mut()).unwrap();
+            let content = text.get_string(&doc.transact());
+            content
+        }
 
-        db_txn.insert_meta("A", "key1", [1].as_ref()).unwrap();
-        db_txn.insert_meta("B", "key2", [2].as_ref()).unwrap();
-        db_txn.insert_meta("B", "key3", [3].as_ref()).unwrap();
-        db_txn.insert_meta("C", "key4", [4].as_ref()).unwrap();
+        let tmp = TempDir::new("rocksdb-archive_round_trip").unwrap();
+        let db_env = init_env(tmp.path().join("source"));
+        {
+            let db = RocksDBStore::from(db_env.transaction());
+            for i in 0..DOCS {
+                let name = format!("doc-{}", i);
+                let doc = Doc::with_client_id(i as u64 + 1);
+                let text = doc.get_or_insert_text("text");
+                text.push(&mut doc.transact_mut(), &name);
+                db.insert_doc(&name, &doc.transact()).unwrap();
+                let mut txn = doc.transact_mut();
+                text.push(&mut txn, "!");
+                db.push_update(&name, &txn.encode_update_v1()).unwrap();
+                db.insert_meta(&name, "index", &i.to_be_bytes()).unwrap();
+            }
+            // document with a long log of pending updates
+            let doc = Doc::with_client_id(1000);
+            let text = doc.get_or_insert_text("text");
+            for _ in 0..LOG_LEN {
+                let mut txn = doc.transact_mut();
+                text.push(&mut txn, "x");
+                db.push_update("log", &txn.encode_update_v1()).unwrap();
+            }
+            db.insert_snapshot("log", "v1", &doc.transact().snapshot())
+                .unwrap();
+            assert!(db.archive_doc("doc-0").unwrap());
+            db.commit().unwrap();
+        }
+
+        let path = tmp.path().join("store.yrs");
+        let exported = {
+            let db = RocksDBStore::from(db_env.transaction());
+            let file = BufWriter::new(File::create(&path).unwrap());
+            let mut reported = 0;
+            let progress = export_store_with(&db, file, |p| reported = p.docs).unwrap();
+            assert_eq!(progress.docs, DOCS + 1);
+            assert_eq!(reported, progress.docs);
+            progress
+        };
+
+        let lmdb_env = LmdbEnv::new(
+            lmdb_rs::Environment::new()
+                .autocreate_dir(true)
+                .max_dbs(4)
+                .open(tmp.path().join("target"), 0o777)
+                .unwrap(),
+        );
+        let h = lmdb_env.create_db("yrs", DbCreate).unwrap();
+
+        // import of a truncated archive fails part way through, committing a document at a time
+        let data = std::fs::read(&path).unwrap();
+        let mut reader = ArchiveReader::new(&data[..data.len() / 2]).unwrap();
+        let res = loop {
+            let txn = lmdb_env.new_transaction().unwrap();
+            let db = LmdbStore::from(txn.bind(&h));
+            match reader.import_next(&db, ConflictMode::Skip) {
+                Ok(Some(_)) => {
+                    drop(db);
+                    txn.commit().unwrap();
+                }
+                other => break other,
+            }
+        };
+        assert!(res.is_err());
+        let committed = reader.progress().docs;
+        assert!(committed > 0);
+
+        // import of the whole archive resumes, skipping documents imported so far
+        {
+            let txn = lmdb_env.new_transaction().unwrap();
+            let db = LmdbStore::from(txn.bind(&h));
+            let file = BufReader::new(File::open(&path).unwrap());
+            let progress = import_store(&db, file, ConflictMode::Skip).unwrap();
+            assert_eq!(progress.docs, exported.docs);
+            assert_eq!(progress.skipped, committed);
+            assert_eq!(progress.bytes, exported.bytes);
+            drop(db);
+            txn.commit().unwrap();
+        }
 
-        let mut i = db_txn.iter_meta("B").unwrap();
-        assert_eq!(i.next(), Some(("key2".as_bytes().into(), [2].into())));
-        assert_eq!(i.next(), Some(("key3".as_bytes().into(), [3].into())));
-        assert!(i.next().is_none());
+        let src = RocksDBStore::from(db_env.transaction());
+        let txn = lmdb_env.new_transaction().unwrap();
+        let dst = LmdbStore::from(txn.bind(&h));
+        for i in 1..DOCS {
+            let name = format!("doc-{}", i);
+            assert_eq!(content(&dst, &name), format!("{}!", name));
+            let index = DocOps::get_meta(&dst, &name, "index").unwrap();
+            let index = index.map(Vec::from);
+            assert_eq!(index, Some(i.to_be_bytes().to_vec()));
+        }
+        assert_eq!(content(&dst, "log"), content(&src, "log"));
+        assert_eq!(dst.decoded_updates("log").unwrap().count(), LOG_LEN);
+        assert_eq!(
+            dst.get_snapshot("log", "v1")
+                .unwrap()
+                .map(|s| s.encode_v1()),
+            src.get_snapshot("log", "v1")
+                .unwrap()
+                .map(|s| s.encode_v1())
+        );
+        // archived documents stay archived
+        assert!(matches!(
+            dst.load_doc("doc-0", &mut Doc::new().transact_mut()),
+            Err(Error::DocArchived)
+        ));
+        assert!(dst.unarchive_doc("doc-0").unwrap());
+        assert_eq!(content(&dst, "doc-0"), "doc-0!");
+        drop(dst);
+        txn.commit().unwrap();
     }
 
     #[test]
-    fn doc_iter() {
-        let tmp = TempDir::new("rocksdb-doc_iter").unwrap();
-        let db = init_env(&tmp);
-        let db = Arc::new(db);
+    fn persist_on_update_retry() {
+        const DOC_NAME: &str = "doc";
+        let tmp = TempDir::new("rocksdb-persist_on_update_retry").unwrap();
+        let store = RocksDBDocStore::from(init_env(&tmp));
+        // injects a given number of failed pushes
+        let failures = Arc::new(AtomicU32::new(0));
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let doc = Doc::with_client_id(1);
+        let text = doc.get_or_insert_text("text");
+        let _sub = {
+            let store = store.clone();
+            let failures = failures.clone();
+            let reported = reported.clone();
+            persist_on_update_with(
+                &doc,
+                move |name, update| {
+                    if failures.load(Ordering::SeqCst) > 0 {
+                        failures.fetch_sub(1, Ordering::SeqCst);
+                        return Err(io::Error::other("disk full").into());
+                    }
+                    store.push_update(name, update)?;
+                    Ok(())
+                },
+                DOC_NAME,
+                move |failure: PersistFailure| {
+                    let error = failure.error.to_string();
+                    reported
+                        .lock()
+                        .unwrap()
+                        .push((error, failure.update, failure.queued))
+                },
+                PersistConfig { retry_queue: 1 },
+            )
+            .unwrap()
+        };
+        let load = || {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            let mut txn = doc.transact_mut();
+            store.load_doc(DOC_NAME, &mut txn).unwrap();
+            text.get_string(&txn)
+        };
 
-        // insert metadata
+        text.push(&mut doc.transact_mut(), "a");
+        failures.store(1, Ordering::SeqCst);
+        text.push(&mut doc.transact_mut(), "b");
         {
-            let db_txn = RocksDBStore::from(db.transaction());
-            db_txn.insert_meta("A", "key1", [1].as_ref()).unwrap();
-            db_txn.commit().unwrap();
+            let reported = reported.lock().unwrap();
+            assert_eq!(reported.len(), 1);
+            assert!(reported[0].2);
+            assert_eq!(reported[0].0, "disk full");
+        }
+        assert_eq!(load(), "a");
+
+        // failed update is retried before the next one
+        text.push(&mut doc.transact_mut(), "c");
+        assert_eq!(reported.lock().unwrap().len(), 1);
+        assert_eq!(load(), "abc");
+
+        // updates which don't fit into the retry queue are dropped and handed over to the sink
+        failures.store(2, Ordering::SeqCst);
+        text.push(&mut doc.transact_mut(), "d");
+        text.push(&mut doc.transact_mut(), "e");
+        text.push(&mut doc.transact_mut(), "f");
+        let dropped = {
+            let mut reported = reported.lock().unwrap();
+            assert_eq!(reported.len(), 3);
+            assert!(reported[1].2);
+            assert!(!reported[2].2);
+            reported.pop().unwrap().1
+        };
+        // "f" is persisted, but it can't be integrated without "e"
+        assert_eq!(load(), "abcd");
+        store.push_update(DOC_NAME, &dropped).unwrap();
+        assert_eq!(load(), "abcdef");
+    }
+
+    #[test]
+    fn write_coalescer() {
+        let tmp = TempDir::new("rocksdb-write_coalescer").unwrap();
+        let db = Arc::new(init_env(&tmp));
+        let config = CoalescerConfig {
+            shards: 2,
+            max_batch: 8,
+            ..CoalescerConfig::default()
+        };
+        let coalescer = Arc::new(WriteCoalescer::spawn(db.clone(), config));
+        let (tx, rx) = mpsc::channel();
+        let writers: Vec<_> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|&name| {
+                let coalescer = coalescer.clone();
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    let doc = Doc::with_client_id(1);
+                    let text = doc.get_or_insert_text("text");
+                    for i in 0..20 {
+                        let update = {
+                            let mut txn = doc.transact_mut();
+                            text.push(&mut txn, &i.to_string());
+                            txn.encode_update_v1()
+                        };
+                        let tx = tx.clone();
+                        coalescer.submit(
+                            name,
+                            move |db| db.push_update(name, &update),
+                            move |result| {
+                                let seq = result.map(|r| r.seq).map_err(|e| e.to_string());
+                                tx.send((name, seq)).unwrap();
+                            },
+                        );
+                    }
+                    let content = text.get_string(&doc.transact());
+                    content
+                })
+            })
+            .collect();
+        let expected: Vec<String> = writers.into_iter().map(|w| w.join().unwrap()).collect();
+
+        // updates of the same document are applied in submission order
+        let mut last_seq: HashMap<&str, u32> = HashMap::new();
+        for _ in 0..80 {
+            let (name, seq) = rx.recv().unwrap();
+            let seq = seq.unwrap();
+            let last = last_seq.entry(name).or_insert(0);
+            assert_eq!(seq, *last + 1);
+            *last = seq;
+        }
+
+        // failed operation is rolled back without affecting other operations of its batch
+        let (tx, rx) = mpsc::channel();
+        for (name, fail) in [("a", false), ("b", true), ("c", false)] {
+            let tx = tx.clone();
+            coalescer.submit(
+                name,
+                move |db| {
+                    db.insert_meta(name, "owner", b"me")?;
+                    if fail {
+                        return Err(Error::DocNotFound);
+                    }
+                    Ok(())
+                },
+                move |result| tx.send((name, result.is_ok())).unwrap(),
+            );
+        }
+        let mut outcomes: Vec<_> = (0..3).map(|_| rx.recv().unwrap()).collect();
+        outcomes.sort();
+        assert_eq!(outcomes, vec![("a", true), ("b", false), ("c", true)]);
+
+        match Arc::try_unwrap(coalescer) {
+            Ok(coalescer) => coalescer.shutdown(),
+            Err(_) => panic!("coalescer is still shared"),
+        }
+        let db = RocksDBStore::from(db.transaction());
+        for (&name, expected) in ["a", "b", "c", "d"].iter().zip(expected) {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            db.load_doc(name, &mut doc.transact_mut()).unwrap();
+            assert_eq!(text.get_string(&doc.transact()), expected);
+            assert_eq!(db.get_meta(name, "owner").unwrap().is_some(), name != "b");
+        }
+    }
+
+    #[cfg(feature = "stress-tests")]
+    #[test]
+    fn concurrent_coalesced_pushes() {
+        use yrs::TransactionMut;
+        use yrs_kvstore::stress::{self, StressStore};
+
+        struct Store(Arc<TransactionDB>, WriteCoalescer);
+
+        impl StressStore for Store {
+            fn push_update(&self, name: &str, update: &[u8]) -> Result<PushReceipt, Error> {
+                let (tx, rx) = mpsc::sync_channel(1);
+                let (owned_name, update) = (name.to_string(), update.to_vec());
+                self.1.submit(
+                    name,
+                    move |db| db.push_update(&owned_name, &update),
+                    move |result| tx.send(result.map_err(|e| e.to_string())).unwrap(),
+                );
+                rx.recv()
+                    .unwrap()
+                    .map_err(|e| Error::Other(io::Error::other(e).into()))
+            }
+
+            fn update_count(&self, name: &str) -> Result<usize, Error> {
+                let db = RocksDBStore::from(self.0.transaction());
+                Ok(db.decoded_updates(name)?.count())
+            }
+
+            fn load_doc(&self, name: &str, txn: &mut TransactionMut) -> Result<bool, Error> {
+                RocksDBStore::from(self.0.transaction()).load_doc(name, txn)
+            }
         }
 
-        // insert full doc state
+        let tmp = TempDir::new("rocksdb-concurrent_coalesced_pushes").unwrap();
+        let db = Arc::new(init_env(&tmp));
+        let coalescer = WriteCoalescer::spawn(db.clone(), CoalescerConfig::default());
+        stress::concurrent_pushes(&Store(db, coalescer), 8, 32);
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: conformance::CountingAlloc = conformance::CountingAlloc;
+
+    #[test]
+    fn conformance() {
+        let tmp = TempDir::new("rocksdb-conformance").unwrap();
+        let db_env = init_env(&tmp);
+        let db = RocksDBStore::from(db_env.transaction());
+        conformance::run_all(&db);
+        db.commit().unwrap();
+    }
+
+    /// Database in a temporary directory, which scenarios of the conformance suite run against.
+    #[derive(Clone)]
+    struct RocksDBBackend {
+        db: Arc<TransactionDB>,
+        _dir: Arc<TempDir>,
+    }
+
+    impl conformance::Backend for RocksDBBackend {
+        type Store<'t> = RocksDBStore<'t, TransactionDB>;
+
+        fn open(name: &str) -> Self {
+            let dir = TempDir::new(&format!("rocksdb-{}", name)).unwrap();
+            RocksDBBackend {
+                db: Arc::new(init_env(&dir)),
+                _dir: Arc::new(dir),
+            }
+        }
+
+        fn transaction<R>(
+            &self,
+            f: impl for<'t> conformance::TransactionFn<'t, RocksDBStore<'t, TransactionDB>, R>,
+        ) -> R {
+            let (db, result) = f(RocksDBStore::from(self.db.transaction()));
+            db.commit().unwrap();
+            result
+        }
+
+        fn read<R>(&self, f: impl for<'t> FnOnce(RocksDBStore<'t, TransactionDB>) -> R) -> R {
+            f(RocksDBStore::with_snapshot(&self.db))
+        }
+
+        fn abort<R>(&self, f: impl for<'t> FnOnce(&RocksDBStore<'t, TransactionDB>) -> R) -> R {
+            f(&RocksDBStore::from(self.db.transaction()))
+        }
+
+        fn open_stores<R>(
+            &self,
+            f: impl for<'t> FnOnce(&dyn Fn() -> RocksDBStore<'t, TransactionDB>) -> R,
+        ) -> R {
+            f(&|| RocksDBStore::from(self.db.transaction()))
+        }
+
+        fn try_write<R, E>(
+            &self,
+            f: impl for<'t> FnOnce(&RocksDBStore<'t, TransactionDB>) -> Result<R, E>,
+        ) -> Result<R, E> {
+            let db = RocksDBStore::from(self.db.transaction());
+            let result = f(&db);
+            if result.is_ok() {
+                db.commit().unwrap();
+            }
+            result
+        }
+    }
+
+    yrs_kvstore::conformance_tests!(RocksDBBackend);
+
+    #[test]
+    fn compat_fixtures() {
+        use yrs_kvstore::compat;
+
+        for fixture in compat::FIXTURES {
+            let tmp = TempDir::new(&format!("rocksdb-compat-{}", fixture.format_version)).unwrap();
+            let db_env = init_env(&tmp);
+            let db = RocksDBStore::from(db_env.transaction());
+            compat::restore(&db, fixture);
+            db.commit().unwrap();
+
+            // fixture is read from the database, not from the transaction which has written it
+            let db = RocksDBStore::from(db_env.transaction());
+            compat::verify(&db);
+            db.commit().unwrap();
+        }
+    }
+
+    #[test]
+    fn compat_layout() {
+        use yrs_kvstore::compat;
+
+        let tmp = TempDir::new("rocksdb-compat_layout").unwrap();
+        let db_env = init_env(&tmp);
+        let db = RocksDBStore::from(db_env.transaction());
+        compat::current_layout(&db);
+        db.commit().unwrap();
+    }
+
+    #[test]
+    fn namespaces() {
+        fn write<'a, DB: DocOps<'a>>(db: &DB, name: &str, content: &str)
+        where
+            Error: From<<DB as KVStore<'a>>::Error>,
+        {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            text.push(&mut doc.transact_mut(), content);
+            db.insert_doc(name, &doc.transact()).unwrap();
+        }
+
+        fn read<'a, DB: DocOps<'a>>(db: &DB, name: &str) -> Option<String>
+        where
+            Error: From<<DB as KVStore<'a>>::Error>,
+        {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            if db.load_doc(name, &mut doc.transact_mut()).unwrap() {
+                Some(text.get_string(&doc.transact()))
+            } else {
+                None
+            }
+        }
+
+        /// Returns OIDs allocated for documents within a given namespace, read from raw keys.
+        fn oids<'a, DB: DocOps<'a>>(db: &DB, namespace: u16) -> Vec<Vec<u8>>
+        where
+            Error: From<<DB as KVStore<'a>>::Error>,
+        {
+            let start = key_namespace(namespace);
+            let end = key_namespace_end(namespace);
+            db.iter_range(&start, &end)
+                .unwrap()
+                .filter(|e| e.key() < end.as_ref())
+                .filter_map(|e| {
+                    let (ns, key) = split_namespace(e.key())?;
+                    assert_eq!(ns, namespace);
+                    match parse_key(key)? {
+                        ParsedKey::Oid { .. } => Some(e.value()[..4].to_vec()),
+                        _ => None,
+                    }
+                })
+                .collect()
+        }
+
+        let tmp = TempDir::new("rocksdb-namespaces").unwrap();
+        let db_env = init_env(&tmp);
+        let staging = RocksDBStore::with_namespace(db_env.transaction(), 1);
+        let sandbox = RocksDBStore::with_namespace(db_env.transaction(), 2);
+        write(&staging, "doc", "staging");
+        write(&sandbox, "doc", "sandbox");
+        write(&sandbox, "other", "sandbox");
+        staging.into_inner().commit().unwrap();
+        sandbox.into_inner().commit().unwrap();
+
+        let db = RocksDBStore::from(db_env.transaction());
+        let staging = RocksDBStore::with_namespace(db_env.transaction(), 1);
+        let sandbox = RocksDBStore::with_namespace(db_env.transaction(), 2);
+        assert_eq!(read(&staging, "doc"), Some("staging".to_string()));
+        assert_eq!(read(&sandbox, "doc"), Some("sandbox".to_string()));
+        assert_eq!(staging.iter_docs().unwrap().count(), 1);
+        assert_eq!(sandbox.iter_docs().unwrap().count(), 2);
+        // documents of both namespaces are invisible outside of them
+        assert_eq!(read(&db, "doc"), None);
+        assert_eq!(db.iter_docs().unwrap().count(), 0);
+        assert_eq!(db.list_namespaces().unwrap(), vec![1, 2]);
+        // both namespaces allocated the same OID for their first document
+        let staging_oids = oids(&db, 1);
+        assert_eq!(staging_oids.len(), 1);
+        assert_eq!(staging_oids[0], oids(&db, 2)[0]);
+        assert!(db.drop_namespace(1).unwrap() > 0);
+        assert_eq!(db.list_namespaces().unwrap(), vec![2]);
+        db.commit().unwrap();
+
+        let staging = RocksDBStore::with_namespace(db_env.transaction(), 1);
+        let sandbox = RocksDBStore::with_namespace(db_env.transaction(), 2);
+        assert_eq!(read(&staging, "doc"), None);
+        assert_eq!(staging.iter_docs().unwrap().count(), 0);
+        assert_eq!(read(&sandbox, "doc"), Some("sandbox".to_string()));
+    }
+
+    #[test]
+    fn shutdown_flush() {
+        use yrs_kvstore::worker::{shutdown_flush, FlushCandidate};
+
+        let tmp = TempDir::new("rocksdb-shutdown_flush").unwrap();
+        let store = RocksDBDocStore::from(init_env(&tmp));
+
+        // document "doc-i" receives 4 * (i + 1) updates, so later documents have more pending bytes
+        let mut expected = Vec::new();
+        for i in 0..5 {
+            let name = format!("doc-{}", i);
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            for _ in 0..4 * (i + 1) {
+                let mut txn = doc.transact_mut();
+                text.push(&mut txn, "a");
+                store.push_update(&name, &txn.encode_update_v1()).unwrap();
+            }
+            let content = text.get_string(&doc.transact());
+            expected.push((name, content));
+        }
+        // documents without pending updates are not candidates
+        store.push_update("flushed", &[0, 0]).unwrap();
+        store.flush_doc("flushed").unwrap();
+
+        let candidates = store.flush_candidates().unwrap();
+        let names: Vec<&[u8]> = candidates.iter().map(|c| c.name.as_ref()).collect();
+        assert_eq!(
+            names,
+            vec![
+                b"doc-4".as_ref(),
+                b"doc-3".as_ref(),
+                b"doc-2".as_ref(),
+                b"doc-1".as_ref(),
+                b"doc-0".as_ref()
+            ]
+        );
+        assert_eq!(candidates[0].pending_updates, 20);
+
+        // every flush takes more than a half of the time budget
+        let report = shutdown_flush(candidates.clone(), Duration::from_millis(100), 1, |name| {
+            std::thread::sleep(Duration::from_millis(60));
+            store.flush_doc(name)
+        });
+        assert!(report.failed.is_empty());
+        let flushed = report.flushed.len();
+        assert!((1..=2).contains(&flushed));
+        let names = |candidates: &[FlushCandidate]| -> Vec<Box<[u8]>> {
+            candidates.iter().map(|c| c.name.clone()).collect()
+        };
+        assert_eq!(report.flushed, names(&candidates[..flushed]));
+        assert_eq!(report.unflushed, names(&candidates[flushed..]));
+        let folded: u32 = candidates[..flushed]
+            .iter()
+            .map(|c| c.pending_updates)
+            .sum();
+        assert_eq!(report.updates_folded, folded);
+
+        // unflushed documents keep all of their pending updates and load in full
+        assert_eq!(store.flush_candidates().unwrap(), &candidates[flushed..]);
+        for (name, content) in expected.iter() {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            assert!(store.load_doc(name, &mut doc.transact_mut()).unwrap());
+            assert_eq!(&text.get_string(&doc.transact()), content);
+        }
+
+        let report = store.shutdown_flush(Duration::from_secs(60), 2).unwrap();
+        assert_eq!(report.flushed.len(), 5 - flushed);
+        assert!(report.unflushed.is_empty());
+        assert!(store.flush_candidates().unwrap().is_empty());
+    }
+
+    #[test]
+    fn init_meta_once_concurrent() {
+        const THREADS: usize = 8;
+        let tmp = TempDir::new("rocksdb-init_meta_once_concurrent").unwrap();
+        let store = RocksDBDocStore::from(init_env(&tmp));
+        let barrier = Arc::new(std::sync::Barrier::new(THREADS));
+        let threads: Vec<_> = (0..THREADS)
+            .map(|i| {
+                let store = store.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    let owner = format!("client-{}", i);
+                    barrier.wait();
+                    let entries = [(b"owner".as_ref(), owner.as_bytes())];
+                    let won = store.init_meta_once("doc", &entries).unwrap();
+                    if won {
+                        Some(owner)
+                    } else {
+                        None
+                    }
+                })
+            })
+            .collect();
+        let winners: Vec<String> = threads
+            .into_iter()
+            .filter_map(|thread| thread.join().unwrap())
+            .collect();
+        assert_eq!(winners.len(), 1);
+        let owner = store.get_meta("doc", "owner").unwrap().unwrap();
+        assert_eq!(owner.as_ref(), winners[0].as_bytes());
+    }
+
+    #[test]
+    fn sharded_store() {
+        use yrs_kvstore::shard::ShardedStore;
+
+        fn text<'a, S: DocOps<'a>>(db: &ShardedStore<S>, name: &str) -> String
+        where
+            Error: From<<S as KVStore<'a>>::Error>,
         {
             let doc = Doc::new();
             let text = doc.get_or_insert_text("text");
+            db.load_doc(name, &mut doc.transact_mut()).unwrap();
+            let content = text.get_string(&doc.transact());
+            content
+        }
+
+        fn edit(client_id: u64, content: &str) -> Vec<u8> {
+            let doc = Doc::with_client_id(client_id);
+            let text = doc.get_or_insert_text("text");
             let mut txn = doc.transact_mut();
-            text.push(&mut txn, "hello world");
+            text.push(&mut txn, content);
+            txn.encode_update_v1()
+        }
 
-            let db_txn = RocksDBStore::from(db.transaction());
-            db_txn.insert_doc("B", &txn).unwrap();
-            db_txn.commit().unwrap();
+        let names: Vec<String> = (0..100).map(|i| format!("doc-{}", i)).collect();
+        let dirs: Vec<TempDir> = (0..5)
+            .map(|i| TempDir::new(&format!("rocksdb-sharded_store-{}", i)).unwrap())
+            .collect();
+        let envs: Vec<TransactionDB> = dirs.iter().map(init_env).collect();
+        let open = |id: u32| {
+            let env = &envs[id as usize];
+            move || RocksDBStore::from(env.transaction())
+        };
+        let mut db = ShardedStore::new();
+        for id in 0..4 {
+            db.add_shard(id, open(id));
+        }
+
+        // routing depends only on the set of shards, not on the order they're added in
+        let mut reversed = ShardedStore::new();
+        for id in (0..4).rev() {
+            reversed.add_shard(id, open(id));
+        }
+        let mut counts = [0u32; 4];
+        for name in names.iter() {
+            let shard = db.shard_of(name);
+            assert_eq!(shard, db.shard_of(name));
+            assert_eq!(shard, reversed.shard_of(name));
+            counts[shard as usize] += 1;
+        }
+        assert!(counts.iter().all(|&n| n > 0), "{:?}", counts);
+        drop(reversed);
+
+        // documents are stored only in the shard they're routed to
+        for (i, name) in names.iter().enumerate() {
+            db.push_update(name, &edit(i as u64 + 1, name)).unwrap();
+        }
+        let mut stored = 0;
+        for id in db.shard_ids() {
+            for name in db.shard(id).unwrap().iter_docs().unwrap() {
+                assert_eq!(db.shard_of(&name), id);
+                stored += 1;
+            }
+        }
+        assert_eq!(stored, names.len());
+
+        // fan-out iteration merges names of all shards in ascending order
+        let mut expected: Vec<Box<[u8]>> = names.iter().map(|n| n.as_bytes().into()).collect();
+        expected.sort();
+        let docs: Vec<Box<[u8]>> = db.iter_docs().unwrap().collect();
+        assert_eq!(docs, expected);
+        let stats = db.stats().unwrap();
+        let shards: Vec<u32> = stats.iter().map(|s| s.shard).collect();
+        assert_eq!(shards, vec![0, 1, 2, 3]);
+        for s in stats.iter() {
+            assert_eq!(s.docs, counts[s.shard as usize]);
+            assert_eq!(s.pending_updates, s.docs as u64);
+        }
+
+        // a new shard takes over some documents, while the rest keeps their shards
+        let before: Vec<u32> = names.iter().map(|n| db.shard_of(n)).collect();
+        db.add_shard(4, open(4));
+        let mut moved = Vec::new();
+        for (name, prev) in names.iter().zip(before) {
+            let shard = db.shard_of(name);
+            if shard != prev {
+                assert_eq!(shard, 4);
+                moved.push(name.clone());
+            }
         }
+        assert!(!moved.is_empty() && moved.len() < names.len());
+        let mut misplaced: Vec<String> = db
+            .misplaced_docs()
+            .unwrap()
+            .into_iter()
+            .map(|doc| {
+                assert_eq!(doc.target, 4);
+                String::from_utf8(doc.name.into()).unwrap()
+            })
+            .collect();
+        misplaced.sort();
+        moved.sort();
+        assert_eq!(misplaced, moved);
+
+        // moved documents are not visible until rebalanced, writes create them in the new shard
+        let first = &moved[0];
+        assert_eq!(text(&db, first), "");
+        db.push_update(first, &edit(1000, "!")).unwrap();
+        assert_eq!(db.rebalance().unwrap(), moved.len() as u32);
+        assert!(db.misplaced_docs().unwrap().is_empty());
+        for name in moved.iter().skip(1) {
+            assert_eq!(&text(&db, name), name);
+        }
+        let merged = text(&db, first);
+        assert!(merged.contains(first.as_str()) && merged.contains('!'));
+        assert_eq!(db.iter_docs().unwrap().count(), names.len());
+
+        // copying a document across shards
+        let source = &names[0];
+        db.insert_meta(source, "owner", b"alice").unwrap();
+        let copy = (0..)
+            .map(|i| format!("copy-{}", i))
+            .find(|name| db.shard_of(name) != db.shard_of(source))
+            .unwrap();
+        assert!(db.copy_doc(source, &copy).unwrap());
+        assert_eq!(text(&db, &copy), text(&db, source));
+        let owner = db.get_meta(&copy, "owner").unwrap();
+        assert_eq!(owner.as_deref(), Some("alice".as_bytes()));
+        assert!(!db.copy_doc("missing", &copy).unwrap());
+        for (_, store) in db.into_shards() {
+            store.commit().unwrap();
+        }
+    }
+
+    #[test]
+    fn iter_keys_range() {
+        let tmp = TempDir::new("rocksdb-iter_keys_range").unwrap();
+        let db_env = init_env(&tmp);
+        let db = RocksDBStore::from(db_env.transaction());
+        let value = vec![7u8; 16 * 1024];
+        for i in 0..16u8 {
+            db.upsert(&[0x10, i], &value[..(i as usize + 1) * 1024])
+                .unwrap();
+        }
+        let (from, to) = ([0x10, 2], [0x10, 5, 0]);
+
+        // the same keys and value lengths as returned by the cursor
+        let expected: Vec<(Vec<u8>, usize)> = db
+            .iter_range(&from, &to)
+            .unwrap()
+            .map(|e| (e.key().to_vec(), e.value().len()))
+            .collect();
+        let mut visited = Vec::new();
+        db.iter_keys_range(&from, &to, &mut |key, len| {
+            visited.push((key.to_vec(), len));
+            true
+        })
+        .unwrap();
+        assert_eq!(visited, expected);
+        assert_eq!(visited.len(), 4);
+        assert_eq!(visited[0], (vec![0x10, 2], 3 * 1024));
+
+        // iteration stops once the callback returns false
+        let mut visited = 0;
+        db.iter_keys_range(&from, &to, &mut |_, _| {
+            visited += 1;
+            visited < 2
+        })
+        .unwrap();
+        assert_eq!(visited, 2);
+
+        // scanning keys doesn't copy values of visited entries, unlike the cursor
+        let (from, to) = ([0x10], [0x11]);
+        let mut keys = 0;
+        let before = allocations();
+        db.iter_keys_range(&from, &to, &mut |_, _| {
+            keys += 1;
+            true
+        })
+        .unwrap();
+        let key_scan = allocations() - before;
+        let before = allocations();
+        let entries = db.iter_range(&from, &to).unwrap().count();
+        let cursor_scan = allocations() - before;
+        assert_eq!((keys, entries), (16, 16));
+        assert!(key_scan < 16, "{} allocations", key_scan);
+        assert!(cursor_scan >= 2 * 16, "{} allocations", cursor_scan);
+        db.commit().unwrap();
+    }
+
+    #[test]
+    fn health() {
+        use crate::health::{
+            HealthThresholds, PropertySource, RocksDBHealth, PROP_ACTUAL_DELAYED_WRITE_RATE,
+            PROP_CUR_SIZE_ALL_MEM_TABLES, PROP_ESTIMATE_PENDING_COMPACTION_BYTES,
+            PROP_IS_WRITE_STOPPED, PROP_NUM_FILES_AT_LEVEL0,
+        };
+        use yrs_kvstore::config::{ConfiguredStore, StoreConfig};
+        use yrs_kvstore::HealthHint;
+
+        struct Props(HashMap<&'static str, u64>);
+
+        impl PropertySource for Props {
+            fn property_int(&self, name: &str) -> Result<Option<u64>, rocksdb::Error> {
+                Ok(self.0.get(name).copied())
+            }
+        }
+
+        struct Unreadable(rocksdb::Error);
+
+        impl PropertySource for Unreadable {
+            fn property_int(&self, _name: &str) -> Result<Option<u64>, rocksdb::Error> {
+                Err(self.0.clone())
+            }
+        }
+
+        let tmp = TempDir::new("rocksdb-health").unwrap();
+        let props = |values: &[(&'static str, u64)]| Props(values.iter().copied().collect());
+        // a database can't be opened in place of a regular file
+        let not_a_dir = tmp.path().join("not-a-dir");
+        File::create(&not_a_dir).unwrap();
+        let opened: Result<TransactionDB, _> = TransactionDB::open_default(&not_a_dir);
+        let unreadable = Unreadable(opened.err().unwrap());
+
+        // properties are mapped onto fields, missing ones are reported as 0
+        assert_eq!(
+            RocksDBHealth::read(&props(&[])).unwrap(),
+            RocksDBHealth::default()
+        );
+        let health = RocksDBHealth::read(&props(&[
+            (PROP_IS_WRITE_STOPPED, 1),
+            (PROP_ACTUAL_DELAYED_WRITE_RATE, 1024),
+            (PROP_NUM_FILES_AT_LEVEL0, 21),
+            (PROP_ESTIMATE_PENDING_COMPACTION_BYTES, 5000),
+            (PROP_CUR_SIZE_ALL_MEM_TABLES, 64 << 20),
+        ]))
+        .unwrap();
+        assert_eq!(
+            health,
+            RocksDBHealth {
+                write_stopped: true,
+                delayed_write_rate: 1024,
+                l0_files: 21,
+                pending_compaction_bytes: 5000,
+                memtable_bytes: 64 << 20,
+            }
+        );
+        assert!(RocksDBHealth::read(&unreadable).is_err());
+
+        // thresholds
+        let thresholds = HealthThresholds {
+            l0_files: 10,
+            pending_compaction_bytes: 1000,
+            memtable_bytes: Some(100),
+        };
+        let healthy = RocksDBHealth {
+            l0_files: 9,
+            pending_compaction_bytes: 999,
+            memtable_bytes: 99,
+            ..RocksDBHealth::default()
+        };
+        assert_eq!(healthy.hint(&thresholds), HealthHint::Ok);
+        let degraded = [
+            RocksDBHealth {
+                delayed_write_rate: 1,
+                ..healthy
+            },
+            RocksDBHealth {
+                l0_files: 10,
+                ..healthy
+            },
+            RocksDBHealth {
+                pending_compaction_bytes: 1000,
+                ..healthy
+            },
+            RocksDBHealth {
+                memtable_bytes: 100,
+                ..healthy
+            },
+        ];
+        for health in degraded.iter() {
+            assert_eq!(
+                health.hint(&thresholds),
+                HealthHint::Degraded,
+                "{:?}",
+                health
+            );
+        }
+        let unchecked_memtables = HealthThresholds {
+            memtable_bytes: None,
+            ..thresholds
+        };
+        assert_eq!(degraded[3].hint(&unchecked_memtables), HealthHint::Ok);
+        let stalled = RocksDBHealth {
+            write_stopped: true,
+            ..healthy
+        };
+        assert_eq!(stalled.hint(&thresholds), HealthHint::Stalled);
+        assert_eq!(
+            RocksDBHealth::default().hint(&HealthThresholds::default()),
+            HealthHint::Ok
+        );
+
+        // push receipts carry hints only when a probe is attached
+        let db_env = init_env(tmp.path().join("db"));
+        let health = RocksDBStore::health(&db_env).unwrap();
+        assert!(!health.write_stopped);
+        assert_eq!(health.hint(&HealthThresholds::default()), HealthHint::Ok);
+        let store = RocksDBDocStore::from(init_env(TempDir::new("rocksdb-health-2").unwrap()));
+        assert_eq!(store.health().unwrap().delayed_write_rate, 0);
+
+        let db = RocksDBStore::from(db_env.transaction());
+        assert_eq!(db.push_update("doc", &[0, 0]).unwrap().health, None);
+        let db = db.with_health_probe(&db_env, HealthThresholds::default());
+        assert_eq!(
+            db.push_update("doc", &[0, 0]).unwrap().health,
+            Some(HealthHint::Ok)
+        );
+        let stalled_props = props(&[(PROP_IS_WRITE_STOPPED, 1)]);
+        let db = db.with_health_probe(&stalled_props, HealthThresholds::default());
+        let receipt = db.push_update("doc", &[0, 0]).unwrap();
+        assert_eq!(receipt.seq, 3);
+        assert_eq!(receipt.health, Some(HealthHint::Stalled));
+        // channel updates and wrapped stores report it as well
+        assert_eq!(
+            db.push_update_channel("doc", 1, &[0, 0]).unwrap().health,
+            Some(HealthHint::Stalled)
+        );
+        let db = ConfiguredStore::new(db, StoreConfig::DEFAULT);
+        assert_eq!(
+            db.push_update("doc", &[0, 0]).unwrap().health,
+            Some(HealthHint::Stalled)
+        );
+        let db = db
+            .into_inner()
+            .with_health_probe(&unreadable, HealthThresholds::default());
+        // unreadable health is unknown, but it doesn't fail the write
+        assert_eq!(db.push_update("doc", &[0, 0]).unwrap().health, None);
+        db.commit().unwrap();
+    }
+
+    #[test]
+    fn sv_read_repair() {
+        /// Store reporting itself as read-only, which refuses all writes.
+        struct ReadOnly<S>(S);
+
+        impl<'a, S: KVStore<'a>> KVStore<'a> for ReadOnly<S> {
+            type Error = S::Error;
+            type Cursor = S::Cursor;
+            type Entry = S::Entry;
+            type Return = S::Return;
+
+            fn get(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+                self.0.get(key)
+            }
+
+            fn upsert(&self, _: &[u8], _: &[u8]) -> Result<(), Self::Error> {
+                panic!("write to read-only store")
+            }
 
-        // insert update
+            fn remove(&self, _: &[u8]) -> Result<(), Self::Error> {
+                panic!("write to read-only store")
+            }
+
+            fn remove_range(&self, _: &[u8], _: &[u8]) -> Result<u32, Self::Error> {
+                panic!("write to read-only store")
+            }
+
+            fn iter_range(&self, from: &[u8], to: &[u8]) -> Result<Self::Cursor, Self::Error> {
+                self.0.iter_range(from, to)
+            }
+
+            fn peek_back(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+                self.0.peek_back(key)
+            }
+
+            fn seek(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+                self.0.seek(key)
+            }
+
+            fn is_read_only(&self) -> bool {
+                true
+            }
+        }
+
+        impl<'a, S: DocOps<'a>> DocOps<'a> for ReadOnly<S>
+        where
+            Error: From<S::Error>,
         {
+            fn config(&self) -> &StoreConfig {
+                self.0.config()
+            }
+        }
+
+        let tmp = TempDir::new("rocksdb-sv_read_repair").unwrap();
+        let db_env = init_env(&tmp);
+        let doc = Doc::with_client_id(1);
+        let text = doc.get_or_insert_text("text");
+        text.push(&mut doc.transact_mut(), "hello");
+        let expected = doc.transact().state_vector();
+        {
+            let db = RocksDBStore::from(db_env.transaction());
+            // document state written without its state vector, i.e. by an older version
+            db.insert_doc("doc", &doc.transact()).unwrap(); // OID 1
+            db.remove(&key_state_vector(1)).unwrap();
+            assert_eq!(db.get_state_vector("doc").unwrap().0, None);
+            db.commit().unwrap();
+        }
+
+        let repair = StoreConfig {
+            sv_read_repair: true,
+            ..StoreConfig::DEFAULT
+        };
+        // read-only stores return computed state vector without storing it
+        let reader = ReadOnly(ConfiguredStore::new(
+            RocksDBStore::from(db_env.transaction()),
+            repair.clone(),
+        ));
+        assert_eq!(
+            reader.get_state_vector("doc").unwrap(),
+            (Some(expected.clone()), true)
+        );
+        assert!(reader.get(&key_state_vector(1)).unwrap().is_none());
+        drop(reader);
+
+        // writable stores write it back
+        let db = ConfiguredStore::new(RocksDBStore::from(db_env.transaction()), repair);
+        assert_eq!(
+            db.get_state_vector("doc").unwrap(),
+            (Some(expected.clone()), true)
+        );
+        assert!(db.get(&key_state_vector(1)).unwrap().is_some());
+
+        // repaired state vector covers only the document state
+        let remote = Doc::with_client_id(2);
+        let remote_text = remote.get_or_insert_text("text");
+        let update = {
+            let mut txn = remote.transact_mut();
+            remote_text.push(&mut txn, "world");
+            txn.encode_update_v1()
+        };
+        db.push_update("doc", &update).unwrap();
+        assert_eq!(db.get_state_vector("doc").unwrap(), (Some(expected), false));
+
+        // malformed document state is reported and nothing is written
+        db.upsert(&key_doc(1), &[0xff, 0xff, 0xff]).unwrap();
+        db.remove(&key_state_vector(1)).unwrap();
+        assert!(db.get_state_vector("doc").is_err());
+        assert!(db.get(&key_state_vector(1)).unwrap().is_none());
+        db.into_inner().commit().unwrap();
+    }
+
+    #[test]
+    fn column_layout() {
+        use crate::columns::{self, Column, ColumnLayout};
+        use yrs_kvstore::keys::{key_doc_end, key_doc_start, key_state_vector, key_update};
+
+        assert_eq!(Column::of(&key_update(1, 7)), Column::Updates);
+        assert_eq!(Column::of(&key_doc(1)), Column::States);
+        assert_eq!(Column::of(&key_state_vector(1)), Column::Meta);
+        assert_eq!(Column::of(&key_oid(b"doc")), Column::Meta);
+        assert_eq!(
+            Column::of_range(&key_update(1, 0), &key_update(1, u32::MAX)),
+            &[Column::Updates]
+        );
+        assert_eq!(
+            Column::of_range(&key_doc_start(1), &key_doc_end(1)),
+            &Column::ALL
+        );
+
+        let tmp = TempDir::new("rocksdb-column_layout").unwrap();
+        let db_env =
+            columns::open(&Options::default(), &TransactionDBOptions::default(), &tmp).unwrap();
+        let update = |content: &str| {
             let doc = Doc::new();
-            let db = db.clone();
-            let _sub = doc.observe_update_v1(move |_, u| {
-                let db_txn = RocksDBStore::from(db.transaction());
-                db_txn.push_update("C", &u.update).unwrap();
-                db_txn.commit().unwrap();
-            });
             let text = doc.get_or_insert_text("text");
             let mut txn = doc.transact_mut();
-            text.push(&mut txn, "hello world");
+            text.push(&mut txn, content);
+            txn.encode_update_v1()
+        };
+        let read = |db: &RocksDBStore<TransactionDB>, name: &str| {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            db.load_doc(name, &mut doc.transact_mut()).unwrap();
+            let str = text.get_string(&doc.transact());
+            str
+        };
+
+        // written without a layout: everything lands in the default column family
+        {
+            let db = RocksDBStore::from(db_env.transaction());
+            db.push_update("a", &update("pending")).unwrap(); // OID 1
+            db.push_update("b", &update("flushed")).unwrap(); // OID 2
+            db.flush_doc("b").unwrap().unwrap();
+            db.commit().unwrap();
         }
 
+        let layout = ColumnLayout::from_db(&db_env).unwrap();
+        assert_eq!(layout.migrate(&db_env, 2).unwrap(), 2);
+        assert_eq!(layout.migrate(&db_env, 2).unwrap(), 0);
         {
-            let db_txn = RocksDBStore::from(db.transaction());
-            let mut i = db_txn.iter_docs().unwrap();
-            assert_eq!(i.next(), Some("A".as_bytes().into()));
-            assert_eq!(i.next(), Some("B".as_bytes().into()));
-            assert_eq!(i.next(), Some("C".as_bytes().into()));
-            assert!(i.next().is_none());
+            let txn = db_env.transaction();
+            assert!(txn.get_pinned(key_update(1, 0)).unwrap().is_none());
+            assert!(txn.get_pinned(key_doc(2)).unwrap().is_none());
+            assert!(txn.get_pinned(key_oid(b"b")).unwrap().is_some());
         }
 
-        // clear doc
+        let db = RocksDBStore::from(db_env.transaction()).with_columns(layout);
+        assert_eq!(read(&db, "a"), "pending");
+        assert_eq!(read(&db, "b"), "flushed");
+
+        db.push_update("b", &update("more")).unwrap();
+        db.flush_doc("b").unwrap().unwrap();
+        assert_eq!(read(&db, "b"), "flushedmore");
+        assert!(db.get_state_vector("b").unwrap().0.is_some());
+
+        // document ranges span all column families
+        let mut keys = Vec::new();
+        db.iter_keys_range(&key_doc_start(2), &key_doc_end(2), &mut |key, _| {
+            keys.push(key.to_vec());
+            true
+        })
+        .unwrap();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+        assert!(keys.contains(&key_doc(2).as_ref().to_vec()));
+        let entries: Vec<_> = db
+            .iter_range(&key_doc_start(2), &key_doc_end(2))
+            .unwrap()
+            .map(|e| e.key().to_vec())
+            .collect();
+        assert_eq!(entries, keys);
+        assert_eq!(
+            db.seek(&key_doc_start(2)).unwrap().unwrap().key(),
+            key_doc(2).as_ref()
+        );
+
+        db.clear_doc("a").unwrap();
+        assert!(db
+            .seek(&key_update(1, 0))
+            .unwrap()
+            .is_none_or(|e| e.key() >= key_doc_end(1).as_ref()));
+        assert_eq!(read(&db, "a"), "");
+        db.commit().unwrap();
+    }
+
+    #[test]
+    fn draft_promote_purge_race() {
+        let tmp = TempDir::new("rocksdb-draft_promote_purge_race").unwrap();
+        let db_env = init_env(&tmp);
         {
-            let db_txn = RocksDBStore::from(db.transaction());
-            db_txn.clear_doc("B").unwrap();
-            db_txn.commit().unwrap();
+            let db = RocksDBStore::from(db_env.transaction());
+            assert!(db.create_draft("a").unwrap());
+            assert!(db.create_draft("b").unwrap());
+            db.commit().unwrap();
         }
+        let docs = || -> Vec<Box<[u8]>> {
+            let db = RocksDBStore::from(db_env.transaction());
+            let docs = db.iter_docs().unwrap().collect();
+            docs
+        };
 
+        // purge cannot remove a draft while it's being promoted
+        let promoting = RocksDBStore::from(db_env.transaction());
+        assert!(promoting.promote_draft("a").unwrap());
         {
-            let db_txn = RocksDBStore::from(db.transaction());
-            let mut i = db_txn.iter_docs().unwrap();
-            assert_eq!(i.next(), Some("A".as_bytes().into()));
-            assert_eq!(i.next(), Some("C".as_bytes().into()));
-            assert!(i.next().is_none());
+            let purging = RocksDBStore::from(db_env.transaction());
+            assert!(purging.purge_drafts(Duration::ZERO).is_err());
+        }
+        promoting.commit().unwrap();
+        assert_eq!(docs(), vec![Box::from(&b"a"[..])]);
+
+        // draft cannot be promoted while it's being purged
+        let purging = RocksDBStore::from(db_env.transaction());
+        assert_eq!(purging.purge_drafts(Duration::ZERO).unwrap(), 1);
+        {
+            let promoting = RocksDBStore::from(db_env.transaction());
+            assert!(promoting.promote_draft("b").is_err());
+        }
+        purging.commit().unwrap();
+        let db = RocksDBStore::from(db_env.transaction());
+        assert!(!db.promote_draft("b").unwrap());
+        assert!(!db.is_draft("a").unwrap());
+        assert_eq!(db.iter_docs_with(true).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn flush_all_parallel() {
+        let serial_dir = TempDir::new("rocksdb-flush_all_parallel-serial").unwrap();
+        let parallel_dir = TempDir::new("rocksdb-flush_all_parallel").unwrap();
+        let serial = RocksDBDocStore::from(init_env(&serial_dir));
+        let parallel = RocksDBDocStore::from(init_env(&parallel_dir));
+        let update = |client_id: u64, content: &str| {
+            let doc = Doc::with_client_id(client_id);
+            let text = doc.get_or_insert_text("text");
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, content);
+            txn.encode_update_v1()
+        };
+        let load = |store: &RocksDBDocStore, name: &[u8]| {
+            let doc = Doc::new();
+            let text = doc.get_or_insert_text("text");
+            assert!(store.load_doc(name, &mut doc.transact_mut()).unwrap());
+            let txn = doc.transact();
+            (text.get_string(&txn), txn.state_vector())
+        };
+
+        // the same updates are written into both stores
+        for i in 0..40u64 {
+            let name = format!("doc-{}", i);
+            let doc = Doc::with_client_id(i + 1);
+            let text = doc.get_or_insert_text("text");
+            for j in 0..(i % 7 + 1) {
+                let mut txn = doc.transact_mut();
+                text.push(&mut txn, &format!("{}-{};", i, j));
+                let update = txn.encode_update_v1();
+                serial.push_update(&name, &update).unwrap();
+                parallel.push_update(&name, &update).unwrap();
+            }
+        }
+        let candidates = parallel.flush_candidates().unwrap();
+        assert_eq!(candidates.len(), 40);
+
+        // parallel flush has the same outcome as the serial one
+        let expected = serial.flush_all(1).unwrap();
+        let report = parallel.flush_all(4).unwrap();
+        assert!(report.failed.is_empty());
+        assert!(report.unflushed.is_empty());
+        assert_eq!(report.updates_folded, expected.updates_folded);
+        let sorted = |mut names: Vec<Box<[u8]>>| {
+            names.sort();
+            names
+        };
+        assert_eq!(sorted(report.flushed), sorted(expected.flushed));
+        assert!(parallel.flush_candidates().unwrap().is_empty());
+        assert!(serial.flush_candidates().unwrap().is_empty());
+        for candidate in candidates.iter() {
+            assert_eq!(
+                load(&parallel, &candidate.name),
+                load(&serial, &candidate.name)
+            );
+        }
+
+        // documents changed after being reconstructed are flushed from scratch
+        parallel.push_update("doc-0", &update(100, "x")).unwrap();
+        let prepared = {
+            let db = RocksDBStore::from(parallel.db().transaction());
+            db.prepare_flush("doc-0").unwrap().unwrap()
+        };
+        assert_eq!(prepared.updates_folded(), 1);
+        parallel.push_update("doc-0", &update(200, "late")).unwrap();
+        let db = RocksDBStore::from(parallel.db().transaction());
+        let outcome = db.apply_flush("doc-0", prepared).unwrap().unwrap();
+        assert_eq!(outcome.updates_folded, 2);
+        db.commit().unwrap();
+        assert!(parallel.flush_candidates().unwrap().is_empty());
+        let (content, _) = load(&parallel, b"doc-0");
+        assert!(content.contains("late"));
+    }
+
+    #[test]
+    fn txn_retry_contention() {
+        use crate::retry::{with_txn_retry, RetryPolicy, RetryStats};
+
+        const WRITERS: u64 = 8;
+        const UPDATES: usize = 25;
+        let tmp = TempDir::new("rocksdb-txn_retry_contention").unwrap();
+        let db = Arc::new(init_env(&tmp));
+        let stats = Arc::new(RetryStats::default());
+        let policy = RetryPolicy {
+            max_attempts: 20,
+            backoff: Duration::from_millis(1),
+            metrics: Some(stats.clone()),
+        };
+
+        // all writers push updates to the same document, contending for its entries
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|i| {
+                let db = db.clone();
+                let policy = policy.clone();
+                std::thread::spawn(move || {
+                    let doc = Doc::with_client_id(i + 1);
+                    let text = doc.get_or_insert_text("text");
+                    for _ in 0..UPDATES {
+                        let update = {
+                            let mut txn = doc.transact_mut();
+                            text.push(&mut txn, "x");
+                            txn.encode_update_v1()
+                        };
+                        with_txn_retry(&db, &policy, |store| store.push_update("doc", &update))
+                            .map_err(|e| e.to_string())
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let report = stats.report();
+        assert_eq!(report.calls, WRITERS * UPDATES as u64);
+        assert_eq!(report.failed, 0);
+        assert!(report.attempts >= report.calls);
+        let db_txn = RocksDBStore::from(db.transaction());
+        assert_eq!(
+            db_txn.push_update("doc", &[0, 0]).unwrap().seq,
+            (WRITERS as u32) * (UPDATES as u32) + 1
+        );
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("text");
+        db_txn.load_doc("doc", &mut doc.transact_mut()).unwrap();
+        assert_eq!(
+            text.get_string(&doc.transact()).len(),
+            WRITERS as usize * UPDATES
+        );
+    }
+
+    #[test]
+    fn txn_retry_policy() {
+        use crate::retry::{is_retryable, with_txn_retry, RetryPolicy, RetryReport, RetryStats};
+
+        let tmp = TempDir::new("rocksdb-txn_retry_policy").unwrap();
+        let db = init_env(&tmp);
+        let stats = Arc::new(RetryStats::default());
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(1),
+            metrics: Some(stats.clone()),
+        };
+        let update = {
+            let doc = Doc::with_client_id(1);
+            let text = doc.get_or_insert_text("text");
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, "hello");
+            txn.encode_update_v1()
+        };
+
+        // errors which are not caused by conflicts are returned right away
+        let mut attempts = 0;
+        let result: Result<(), Error> = with_txn_retry(&db, &policy, |_| {
+            attempts += 1;
+            Err(Error::DocNotFound)
+        });
+        assert!(matches!(result, Err(Error::DocNotFound)));
+        assert!(!is_retryable(&Error::DocNotFound));
+        assert_eq!(attempts, 1);
+
+        // lock timeout caused by a concurrent transaction is retried once it's gone
+        let mut competitor = None;
+        let mut attempts = 0;
+        let receipt = with_txn_retry(&db, &policy, |store| {
+            attempts += 1;
+            if attempts == 1 {
+                let txn = RocksDBStore::from(db.transaction());
+                txn.push_update("doc", &update).unwrap();
+                competitor = Some(txn);
+            } else {
+                // rolls back the competing transaction, releasing its locks
+                competitor = None;
+            }
+            let result = store.push_update("doc", &update);
+            if attempts == 1 {
+                let e = result.as_ref().err().unwrap();
+                assert!(is_retryable(e), "{}", e);
+            }
+            result
+        })
+        .unwrap();
+        assert!(competitor.is_none());
+        assert_eq!(attempts, 2);
+        assert_eq!(receipt.seq, 1);
+
+        // changes of failed attempts are not committed
+        let db_txn = RocksDBStore::from(db.transaction());
+        assert_eq!(db_txn.decoded_updates("doc").unwrap().count(), 1);
+        drop(db_txn);
+        assert_eq!(
+            stats.report(),
+            RetryReport {
+                calls: 2,
+                attempts: 3,
+                retried: 1,
+                failed: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn capabilities() {
+        use crate::columns::{self, ColumnLayout};
+        use yrs_kvstore::capabilities::Capabilities;
+        use yrs_kvstore::conformance;
+        use yrs_kvstore::keys::{key_doc_end, key_doc_start};
+        use yrs_kvstore::ReadIsolation;
+
+        // store declaring no capabilities, whose peek_back must never be called
+        struct Plain<'s, S>(&'s S);
+
+        impl<'a, 's, S: KVStore<'a>> KVStore<'a> for Plain<'s, S> {
+            type Error = S::Error;
+            type Cursor = S::Cursor;
+            type Entry = S::Entry;
+            type Return = S::Return;
+
+            fn get(&self, key: &[u8]) -> Result<Option<Self::Return>, Self::Error> {
+                self.0.get(key)
+            }
+
+            fn upsert(&self, key: &[u8], value: &[u8]) -> Result<(), Self::Error> {
+                self.0.upsert(key, value)
+            }
+
+            fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+                self.0.remove(key)
+            }
+
+            fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<u32, Self::Error> {
+                self.0.remove_range(from, to)
+            }
+
+            fn iter_range(&self, from: &[u8], to: &[u8]) -> Result<Self::Cursor, Self::Error> {
+                self.0.iter_range(from, to)
+            }
+
+            fn peek_back(&self, _key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+                unimplemented!("BOUNDED_PEEK_BACK is not declared")
+            }
+
+            fn seek(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+                self.0.seek(key)
+            }
         }
+
+        impl<'a, 's, S: KVStore<'a>> DocOps<'a> for Plain<'s, S> where Error: From<S::Error> {}
+
+        let tmp = TempDir::new("rocksdb-capabilities").unwrap();
+        let db_env = init_env(&tmp);
+        let declared = Capabilities::BOUNDED_PEEK_BACK
+            | Capabilities::REVERSE_ITER
+            | Capabilities::MULTI_GET
+            | Capabilities::SNAPSHOTS;
+        let snapshot = RocksDBStore::with_snapshot(&db_env);
+        assert_eq!(snapshot.capabilities(), declared);
+        conformance::capabilities(&snapshot);
+        drop(snapshot);
+
+        // snapshots are reported only by transactions reading from one
+        let db = RocksDBStore::from(db_env.transaction());
+        assert_eq!(
+            db.capabilities(),
+            declared.difference(Capabilities::SNAPSHOTS)
+        );
+        assert_eq!(db.read_snapshot().isolation(), ReadIsolation::BestEffort);
+        conformance::capabilities(&db);
+        let namespaced = RocksDBStore::with_namespace(db_env.transaction(), 1);
+        conformance::capabilities(&namespaced);
+        namespaced.into_inner().commit().unwrap();
+
+        let update = |content: &str| {
+            let doc = Doc::with_client_id(1);
+            let text = doc.get_or_insert_text("text");
+            let mut txn = doc.transact_mut();
+            text.push(&mut txn, content);
+            txn.encode_update_v1()
+        };
+
+        // DocOps falls back to scans, so pushes and loads work without peek_back
+        let plain = Plain(&db);
+        assert_eq!(plain.capabilities(), Capabilities::NONE);
+        conformance::capabilities(&plain);
+        assert_eq!(plain.push_update("doc", &update("a")).unwrap().seq, 1);
+        assert_eq!(plain.push_update("doc", &update("b")).unwrap().seq, 2);
+        assert_eq!(plain.last_update_seq("doc").unwrap(), Some(2));
+        assert_eq!(db.last_update_seq("doc").unwrap(), Some(2));
+        plain.flush_doc("doc").unwrap().unwrap();
+        assert_eq!(plain.push_update("doc", &update("c")).unwrap().seq, 1);
+        let loaded = plain
+            .load_docs(vec![b"doc".as_ref(), b"missing".as_ref()])
+            .unwrap();
+        assert!(loaded[0].1.is_some());
+        assert!(loaded[1].1.is_none());
+        db.commit().unwrap();
+
+        // reverse scans merge entries of all column families
+        let tmp = TempDir::new("rocksdb-capabilities-columns").unwrap();
+        let db_env =
+            columns::open(&Options::default(), &TransactionDBOptions::default(), &tmp).unwrap();
+        let layout = ColumnLayout::from_db(&db_env).unwrap();
+        let db = RocksDBStore::from(db_env.transaction()).with_columns(layout);
+        conformance::capabilities(&db);
+        db.push_update("doc", &update("a")).unwrap();
+        db.flush_doc("doc").unwrap().unwrap();
+        db.push_update("doc", &update("b")).unwrap();
+        db.insert_meta("doc", "title", b"Doc").unwrap();
+        let (start, end) = (key_doc_start(1), key_doc_end(1));
+        let forward: Vec<Vec<u8>> = db
+            .iter_range(&start, &end)
+            .unwrap()
+            .map(|e| e.key().to_vec())
+            .collect();
+        let mut backward = Vec::new();
+        db.iter_range_rev(&start, &end, &mut |key, _| {
+            backward.push(key.to_vec());
+            true
+        })
+        .unwrap();
+        backward.reverse();
+        assert!(forward.len() >= 4);
+        assert_eq!(backward, forward);
+        db.commit().unwrap();
     }
 }
@@ -0,0 +1,102 @@
+//! RocksDB options tuned for workloads produced by [DocOps](yrs_kvstore::DocOps): small,
+//! mostly sequential keys prefixed with document OID, a mixture of tiny update values and large
+//! document state blobs.
+//!
+//! # Example
+//!
+//! ```rust
+//! use yrs_rocksdb::options::{open_recommended, Preset};
+//!
+//! // open database using recommended settings
+//! let db = open_recommended("my-db-path").unwrap();
+//!
+//! // ... or adjust them first
+//! let preset = Preset {
+//!     min_blob_size: Some(64 * 1024),
+//!     ..Preset::default()
+//! };
+//! let db = preset.open("my-other-db-path").unwrap();
+//! ```
+
+use rocksdb::{
+    BlockBasedOptions, Cache, DBCompressionType, Options, SliceTransform, TransactionDB,
+    TransactionDBOptions,
+};
+use std::path::Path;
+
+/// Length of the key prefix shared by all entries of the same document: version byte, keyspace
+/// byte and 4-byte document OID.
+const DOC_PREFIX_LEN: usize = 6;
+
+/// Tunable parameters used to build RocksDB options. Use [Preset::default] to get the recommended
+/// values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preset {
+    /// Size of the LRU block cache (in bytes) shared by all table readers.
+    pub block_cache_size: usize,
+    /// Size of a single memtable (in bytes).
+    pub write_buffer_size: usize,
+    /// Values of at least that many bytes will be stored in separate blob files instead of
+    /// the LSM tree. This is usually worth enabling for large document states. `None` disables
+    /// blob files.
+    pub min_blob_size: Option<u64>,
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        Preset {
+            block_cache_size: 64 * 1024 * 1024,
+            write_buffer_size: 64 * 1024 * 1024,
+            min_blob_size: None,
+        }
+    }
+}
+
+impl Preset {
+    /// Builds RocksDB options matching current preset.
+    pub fn options(&self) -> (Options, TransactionDBOptions) {
+        let mut table = BlockBasedOptions::default();
+        table.set_block_size(16 * 1024);
+        table.set_block_cache(&Cache::new_lru_cache(self.block_cache_size));
+        table.set_bloom_filter(10.0, false);
+        table.set_cache_index_and_filter_blocks(true);
+        // OID index keys are shorter than document prefix, keep full key filters for them
+        table.set_whole_key_filtering(true);
+
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.set_max_background_jobs(4);
+        options.set_prefix_extractor(SliceTransform::create_fixed_prefix(DOC_PREFIX_LEN));
+        options.set_memtable_prefix_bloom_ratio(0.1);
+        options.set_block_based_table_factory(&table);
+        options.set_write_buffer_size(self.write_buffer_size);
+        options.set_max_write_buffer_number(3);
+        options.set_level_compaction_dynamic_level_bytes(true);
+        options.set_compression_type(DBCompressionType::Lz4);
+        options.set_bottommost_compression_type(DBCompressionType::Zstd);
+        if let Some(min_blob_size) = self.min_blob_size {
+            options.set_enable_blob_files(true);
+            options.set_min_blob_size(min_blob_size);
+            options.set_blob_compression_type(DBCompressionType::Lz4);
+            options.set_enable_blob_gc(true);
+        }
+
+        (options, TransactionDBOptions::default())
+    }
+
+    /// Opens a [TransactionDB] at a given `path` using options matching current preset.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<TransactionDB, rocksdb::Error> {
+        let (options, txn_db_options) = self.options();
+        TransactionDB::open(&options, &txn_db_options, path)
+    }
+}
+
+/// Returns RocksDB options recommended for storing Yrs documents. See [Preset] for details.
+pub fn recommended() -> (Options, TransactionDBOptions) {
+    Preset::default().options()
+}
+
+/// Opens a [TransactionDB] at a given `path` using [recommended] options.
+pub fn open_recommended<P: AsRef<Path>>(path: P) -> Result<TransactionDB, rocksdb::Error> {
+    Preset::default().open(path)
+}
@@ -0,0 +1,180 @@
+//! Automatic retries of transactions failed by write conflicts.
+//!
+//! [TransactionDB] locks every key written or read with
+//! [KVStore::get_for_update](yrs_kvstore::KVStore::get_for_update), so concurrent
+//! transactions writing to the same document - i.e. pushing updates to it, which both rewrite its
+//! pending updates counter - wait for each other and may fail with a lock timeout, a busy or
+//! a "try again" error once they wait for too long. [with_txn_retry] runs a closure in a fresh
+//! transaction, commits it and, if the closure or the commit fails with one of these
+//! [retryable](is_retryable) errors, drops the transaction and runs the closure again in a new one,
+//! as configured by [RetryPolicy]:
+//!
+//! ```rust,ignore
+//! let policy = RetryPolicy {
+//!     metrics: Some(stats.clone()),
+//!     ..RetryPolicy::default()
+//! };
+//! let receipt = with_txn_retry(&db, &policy, |store| store.push_update("my-doc-name", &update))?;
+//! ```
+//!
+//! # Closure contract
+//!
+//! The closure may be called multiple times, but only the writes of its last call are committed:
+//! writes of failed attempts are rolled back together with their transaction. The closure must
+//! therefore read everything it depends on from the `store` it's given, instead of from values
+//! captured by a previous attempt, and it must not have side effects outside of the store (i.e.
+//! sending messages), which would be repeated by every attempt. [DocOps](yrs_kvstore::DocOps)
+//! methods called with the same arguments meet that contract.
+//!
+//! Errors other than the retryable ones are returned right away, without committing the
+//! transaction.
+
+use crate::RocksDBStore;
+use rocksdb::{ErrorKind, ThreadMode, TransactionDB};
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use yrs_kvstore::error::Error;
+
+/// Configuration of [with_txn_retry].
+#[derive(Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of times the closure is called, including the first call. Values lower
+    /// than 1 are treated as 1.
+    pub max_attempts: u32,
+    /// Delay before the second attempt. It's doubled before every following attempt.
+    pub backoff: Duration,
+    /// Receives the number of attempts of every [with_txn_retry] call, once it completes.
+    pub metrics: Option<Arc<dyn RetryMetrics>>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            backoff: Duration::from_millis(1),
+            metrics: None,
+        }
+    }
+}
+
+impl Debug for RetryPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("backoff", &self.backoff)
+            .field("metrics", &self.metrics.is_some())
+            .finish()
+    }
+}
+
+/// Hook receiving outcomes of [with_txn_retry] calls, i.e. to export them to a metrics system.
+pub trait RetryMetrics: Send + Sync {
+    /// Records a completed [with_txn_retry] call, which called its closure `attempts` times and
+    /// either `succeeded` or returned an error.
+    fn record(&self, attempts: u32, succeeded: bool);
+}
+
+/// [RetryMetrics] counting outcomes in memory.
+#[derive(Debug, Default)]
+pub struct RetryStats {
+    calls: AtomicU64,
+    attempts: AtomicU64,
+    retried: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl RetryStats {
+    /// Returns counters recorded so far.
+    pub fn report(&self) -> RetryReport {
+        RetryReport {
+            calls: self.calls.load(Ordering::Relaxed),
+            attempts: self.attempts.load(Ordering::Relaxed),
+            retried: self.retried.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl RetryMetrics for RetryStats {
+    fn record(&self, attempts: u32, succeeded: bool) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.attempts.fetch_add(attempts as u64, Ordering::Relaxed);
+        if attempts > 1 {
+            self.retried.fetch_add(1, Ordering::Relaxed);
+        }
+        if !succeeded {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Counters recorded by [RetryStats].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetryReport {
+    /// Number of completed [with_txn_retry] calls.
+    pub calls: u64,
+    /// Total number of closure calls made by them.
+    pub attempts: u64,
+    /// Number of calls, which needed more than a single attempt.
+    pub retried: u64,
+    /// Number of calls, which returned an error.
+    pub failed: u64,
+}
+
+/// Runs `f` in a new transaction of a given `db` and commits it, retrying both on retryable
+/// errors as configured by a given `policy`. See [module documentation](self) for the contract
+/// `f` must meet.
+pub fn with_txn_retry<T, F, R>(
+    db: &TransactionDB<T>,
+    policy: &RetryPolicy,
+    mut f: F,
+) -> Result<R, Error>
+where
+    T: ThreadMode,
+    F: FnMut(&RocksDBStore<TransactionDB<T>>) -> Result<R, Error>,
+{
+    let max_attempts = policy.max_attempts.max(1);
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let db_txn = RocksDBStore::from(db.transaction());
+        let result = match f(&db_txn) {
+            Ok(value) => db_txn.commit().map(|_| value).map_err(Error::other),
+            Err(e) => Err(e),
+        };
+        match result {
+            Err(e) if attempts < max_attempts && is_retryable(&e) => {
+                let backoff = policy
+                    .backoff
+                    .checked_mul(1 << (attempts - 1).min(16))
+                    .unwrap_or(policy.backoff);
+                std::thread::sleep(backoff);
+            }
+            result => {
+                if let Some(metrics) = &policy.metrics {
+                    metrics.record(attempts, result.is_ok());
+                }
+                return result;
+            }
+        }
+    }
+}
+
+/// Checks if a given `error` has been caused by a conflict with a concurrent transaction, which
+/// is likely to go away when the transaction is retried: a [busy](ErrorKind::Busy),
+/// [try again](ErrorKind::TryAgain) or [lock timeout](ErrorKind::TimedOut) error returned by
+/// RocksDB.
+pub fn is_retryable(error: &Error) -> bool {
+    match error {
+        Error::Other(e) => match e.downcast_ref::<rocksdb::Error>() {
+            Some(e) => matches!(
+                e.kind(),
+                ErrorKind::Busy | ErrorKind::TryAgain | ErrorKind::TimedOut
+            ),
+            None => false,
+        },
+        _ => false,
+    }
+}